@@ -0,0 +1,114 @@
+extern crate clap;
+extern crate failure;
+
+extern crate riboprof;
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg};
+
+use riboprof::bam_annotate_genes::*;
+use riboprof::cli_utils::*;
+use riboprof::config_file::*;
+use riboprof::logging::*;
+use riboprof::parallel::*;
+
+fn main() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("bam-annotate-genes", start.elapsed(), &result);
+    match result {
+        Err(e) => {
+            io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
+            process::exit(1);
+        }
+        _ => (),
+    };
+}
+
+fn wrapper() -> Result<(), failure::Error> {
+    let cli = get_cli()?;
+    let config = Config::new(&cli)?;
+    run_bam_annotate_genes(config)
+}
+
+fn get_cli() -> Result<CLI, failure::Error> {
+    let matches = App::new("bam-annotate-genes")
+        .version("0.1.0")
+        .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .about("Tags each alignment with its annotated genes, transcripts, and region")
+        .long_about("Writes each alignment with aux tags giving its annotated region (ZR: CDS, UTR5, UTR3, ncRNA, Intron, Antisense, or NoGene), and the comma-separated genes (ZG) and transcripts (ZT) it overlaps. Genes are assigned from same-strand transcripts whose exons overlap the alignment, and the region is that of the 5' end of the read. Alignments that do not pass the read filters are written without tags.")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT.BAM")
+                .help("BAM format output file (- for stdout)")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("bed")
+                .short("b")
+                .long("bed")
+                .value_name("BED")
+                .help("BED-, GTF-, or GFF3-format annotation filename, repeated for several annotations in order of precedence")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .short("s")
+                .long("stats")
+                .value_name("STATS.TXT")
+                .help("Output file with the number of reads in each region")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("io-threads")
+                .long("io-threads")
+                .value_name("N")
+                .help("Additional threads for BAM compression and decompression")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("INPUT.BAM")
+                .help("BAM format input file (- for stdin)")
+                .required(true),
+        )
+        .args(&read_filter_args())
+        .arg(threads_arg())
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    let corrupt = init_logging(&matches)?;
+
+    let cli = CLI {
+        read_filter: read_filter_cli(&matches),
+        output: matches.value_of("output").unwrap().to_string(),
+        bed: matches
+            .values_of("bed")
+            .unwrap()
+            .map(str::to_string)
+            .collect(),
+        stats: matches.value_of("stats").map(str::to_string),
+        threads: matches.value_of("threads").unwrap().to_string(),
+        io_threads: matches.value_of("io-threads").unwrap().to_string(),
+        input: matches.value_of("input").unwrap().to_string(),
+        corrupt: corrupt,
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
+}
@@ -0,0 +1,259 @@
+extern crate clap;
+extern crate failure;
+
+extern crate riboprof;
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg};
+
+use riboprof::cli_utils::*;
+use riboprof::codon_count::*;
+use riboprof::config_file::*;
+use riboprof::logging::*;
+use riboprof::normalization::*;
+use riboprof::output::*;
+use riboprof::umi::*;
+
+fn main() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("bam-codon-count", start.elapsed(), &result);
+    match result {
+        Err(e) => {
+            io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
+            process::exit(1);
+        }
+        _ => (),
+    };
+}
+
+fn wrapper() -> Result<(), failure::Error> {
+    let cli = get_cli()?;
+    let config = Config::new(&cli)?;
+    run_codon_count(config)
+}
+
+fn get_cli() -> Result<CLI, failure::Error> {
+    let matches = App::new("bam-codon-count")
+        .version("0.1.0")
+        .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .about("Counts ribosome footprint A, P, or E sites on each codon of each gene")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTBASE")
+                .help("Base filename for output files")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("bed")
+                .short("b")
+                .long("bed")
+                .value_name("BED")
+                .help("BED-, GTF-, or GFF3-format annotation filename, repeated for several annotations in order of precedence")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("asites")
+                .short("a")
+                .long("asites")
+                .value_name("ASITES.TXT")
+                .help("Tab-delimited table of footprint length, optional 5' frame, and A site offset")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("default-offset")
+                .long("default-offset")
+                .value_name("OFFSET")
+                .help("A site offset for lengths missing from the offset table")
+                .takes_value(true)
+                .conflicts_with("interpolate-offsets"),
+        )
+        .arg(
+            Arg::with_name("interpolate-offsets")
+                .long("interpolate-offsets")
+                .help("Interpolate A site offsets for lengths missing from the offset table"),
+        )
+        .arg(
+            Arg::with_name("three-prime")
+                .long("three-prime")
+                .help("Measure A site offsets back from the footprint 3' end"),
+        )
+        .arg(
+            Arg::with_name("site")
+                .short("s")
+                .long("site")
+                .value_name("SITE")
+                .help("Ribosomal site assigned to codons")
+                .takes_value(true)
+                .possible_values(&["A", "P", "E"])
+                .default_value("A"),
+        )
+        .arg(
+            Arg::with_name("fasta")
+                .short("f")
+                .long("fasta")
+                .value_name("GENOME.FA")
+                .help("Genome sequence, for codon identity dwell scores")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("track")
+                .short("t")
+                .long("track")
+                .value_name("FORMAT")
+                .help("Write strand-specific genome tracks of site counts")
+                .takes_value(true)
+                .possible_values(&["bedgraph", "wig"]),
+        )
+        .arg(
+            Arg::with_name("windows")
+                .long("windows")
+                .value_name("WINDOWS.BED")
+                .help("Report site occupancy at each nucleotide of these transcript-coordinate windows")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dwell-bootstrap")
+                .long("dwell-bootstrap")
+                .value_name("N")
+                .help("Bootstrap replicates for dwell confidence intervals, also writing per-position dwell")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("bootstrap-seed")
+                .long("bootstrap-seed")
+                .value_name("SEED")
+                .help("Random seed for bootstrap resampling")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("parquet")
+                .long("parquet")
+                .help("Write per-transcript codon occupancy vectors in Parquet format"),
+        )
+        .arg(
+            Arg::with_name("lengths")
+                .short("l")
+                .long("lengths")
+                .value_name("MINLEN,MAXLEN")
+                .help("Length range for codon assignment")
+                .takes_value(true)
+                .default_value("26,34"),
+        )
+        .arg(
+            Arg::with_name("exclude-codons")
+                .short("x")
+                .long("exclude-codons")
+                .value_name("START,END")
+                .help("Codons excluded at the start and end of each CDS in quantification, dwell scores, and pause calling")
+                .takes_value(true)
+                .default_value("0,0"),
+        )
+        .arg(
+            Arg::with_name("terminal-codons")
+                .long("terminal-codons")
+                .value_name("POLICY")
+                .help("Treatment of the excluded start and stop codons in gene quantification")
+                .takes_value(true)
+                .possible_values(&["include", "exclude", "separate"])
+                .default_value("exclude"),
+        )
+        .arg(
+            Arg::with_name("pause-zscore")
+                .long("pause-zscore")
+                .value_name("Z")
+                .help("Call pause sites with a z-score of at least Z within their gene")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pause-min-count")
+                .long("pause-min-count")
+                .value_name("COUNT")
+                .help("Minimum footprint count on a pause site")
+                .takes_value(true)
+                .default_value("5"),
+        )
+        .arg(
+            Arg::with_name("count-multi")
+                .short("m")
+                .long("count-multi")
+                .help("Count multi-mapping reads once, at their first occurrence (i.e., HI = 0)"),
+        )
+        .arg(
+            Arg::with_name("umi")
+                .short("u")
+                .long("umi")
+                .help("Collapse footprints with the same UMI (RX tag or read name suffix) and 5' position; requires a coordinate-sorted BAM"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("INPUT.BAM")
+                .help("Input BAM files, each one sample")
+                .multiple(true)
+                .required(true),
+        )
+        .args(&read_filter_args())
+        .args(&umi_args())
+        .args(&normalize_args())
+        .args(&output_args())
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    let corrupt = init_logging(&matches)?;
+
+    let cli = CLI {
+        read_filter: read_filter_cli(&matches),
+        umi_config: umi_cli(&matches),
+        normalize: normalize_cli(&matches),
+        output_config: output_cli(&matches),
+        output: matches.value_of("output").unwrap().to_string(),
+        bed: matches
+            .values_of("bed")
+            .unwrap()
+            .map(str::to_string)
+            .collect(),
+        asites: matches.value_of("asites").unwrap().to_string(),
+        default_offset: matches.value_of("default-offset").map(str::to_string),
+        interpolate_offsets: matches.is_present("interpolate-offsets"),
+        three_prime: matches.is_present("three-prime"),
+        site: matches.value_of("site").unwrap().to_string(),
+        lengths: matches.value_of("lengths").unwrap().to_string(),
+        exclude_codons: matches.value_of("exclude-codons").unwrap().to_string(),
+        terminal_codons: matches.value_of("terminal-codons").unwrap().to_string(),
+        pause_zscore: matches.value_of("pause-zscore").map(str::to_string),
+        pause_min_count: matches.value_of("pause-min-count").unwrap().to_string(),
+        count_multi: matches.is_present("count-multi"),
+        umi: matches.is_present("umi"),
+        fasta: matches.value_of("fasta").map(str::to_string),
+        track: matches.value_of("track").map(str::to_string),
+        windows: matches.value_of("windows").map(str::to_string),
+        dwell_bootstrap: matches.value_of("dwell-bootstrap").map(str::to_string),
+        bootstrap_seed: matches.value_of("bootstrap-seed").unwrap().to_string(),
+        parquet: matches.is_present("parquet"),
+        inputs: matches
+            .values_of("input")
+            .unwrap()
+            .map(str::to_string)
+            .collect(),
+        corrupt: corrupt,
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
+}
@@ -0,0 +1,144 @@
+extern crate clap;
+extern crate failure;
+
+extern crate riboprof;
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg};
+
+use riboprof::cli_utils::*;
+use riboprof::config_file::*;
+use riboprof::gene_count::*;
+use riboprof::logging::*;
+use riboprof::normalization::*;
+use riboprof::output::*;
+use riboprof::umi::*;
+
+fn main() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("bam-gene-count", start.elapsed(), &result);
+    match result {
+        Err(e) => {
+            io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
+            process::exit(1);
+        }
+        _ => (),
+    };
+}
+
+fn wrapper() -> Result<(), failure::Error> {
+    let cli = get_cli()?;
+    let config = Config::new(&cli)?;
+    run_gene_count(config)
+}
+
+fn get_cli() -> Result<CLI, failure::Error> {
+    let matches = App::new("bam-gene-count")
+        .version("0.1.0")
+        .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .about("Counts footprints on each gene, giving a gene by sample count matrix")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTBASE")
+                .help("Base filename for output files")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("bed")
+                .short("b")
+                .long("bed")
+                .value_name("BED")
+                .help("BED-, GTF-, or GFF3-format annotation filename, repeated for several annotations in order of precedence")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("feature")
+                .long("feature")
+                .value_name("FEATURE")
+                .help("Count footprints overlapping the CDS or any exon of each gene")
+                .takes_value(true)
+                .possible_values(&["cds", "transcript"])
+                .default_value("cds"),
+        )
+        .arg(
+            Arg::with_name("multi")
+                .long("multi")
+                .value_name("POLICY")
+                .help("Count multi-mapping reads never, once at their first alignment (HI = 1), or fractionally (1 / NH)")
+                .takes_value(true)
+                .possible_values(&["unique", "primary", "fraction"])
+                .default_value("unique"),
+        )
+        .arg(
+            Arg::with_name("strand")
+                .short("s")
+                .long("strand")
+                .value_name("STRAND")
+                .help("Strand of the gene relative to the read")
+                .takes_value(true)
+                .possible_values(&["forward", "reverse", "none"])
+                .default_value("forward"),
+        )
+        .arg(
+            Arg::with_name("umi")
+                .short("u")
+                .long("umi")
+                .help("Collapse footprints with the same UMI (RX tag or read name suffix) at the same position"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("INPUT.BAM")
+                .help("Input BAM files, each one sample")
+                .multiple(true)
+                .required(true),
+        )
+        .args(&read_filter_args())
+        .args(&umi_args())
+        .args(&normalize_args())
+        .args(&output_args())
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    let corrupt = init_logging(&matches)?;
+
+    let cli = CLI {
+        read_filter: read_filter_cli(&matches),
+        umi_config: umi_cli(&matches),
+        normalize_config: normalize_cli(&matches),
+        output_config: output_cli(&matches),
+        output: matches.value_of("output").unwrap().to_string(),
+        bed: matches
+            .values_of("bed")
+            .unwrap()
+            .map(str::to_string)
+            .collect(),
+        feature: matches.value_of("feature").unwrap().to_string(),
+        multi: matches.value_of("multi").unwrap().to_string(),
+        strand: matches.value_of("strand").unwrap().to_string(),
+        umi: matches.is_present("umi"),
+        inputs: matches
+            .values_of("input")
+            .unwrap()
+            .map(str::to_string)
+            .collect(),
+        corrupt: corrupt,
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
+}
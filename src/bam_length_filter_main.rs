@@ -0,0 +1,108 @@
+extern crate clap;
+extern crate failure;
+
+extern crate riboprof;
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg};
+
+use riboprof::bam_length_filter::*;
+use riboprof::cli_utils::*;
+use riboprof::config_file::*;
+use riboprof::logging::*;
+
+fn main() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("bam-length-filter", start.elapsed(), &result);
+    match result {
+        Err(e) => {
+            io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
+            process::exit(1);
+        }
+        _ => (),
+    };
+}
+
+fn wrapper() -> Result<(), failure::Error> {
+    let cli = get_cli()?;
+    let config = Config::new(&cli)?;
+    run_bam_length_filter(config)
+}
+
+fn get_cli() -> Result<CLI, failure::Error> {
+    let matches = App::new("bam-length-filter")
+        .version("0.1.0")
+        .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .about("Splits alignments into separate BAM files by footprint length range")
+        .long_about("Writes alignments passing the read filters to OUTPUT_NAME.bam for each length range NAME=MINLEN-MAXLEN, judging length by the read sequence, and reports the number of reads in each range in OUTPUT_length_stats.txt. Ranges must not overlap.")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Base filename for output files")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("range")
+                .short("r")
+                .long("range")
+                .value_name("NAME=MINLEN-MAXLEN")
+                .help("Length range written to its own output, e.g., monosome=26-34, repeated for several ranges")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("other")
+                .long("other")
+                .help("Also write reads outside of every length range to OUTPUT_other.bam"),
+        )
+        .arg(
+            Arg::with_name("io-threads")
+                .long("io-threads")
+                .value_name("N")
+                .help("Additional threads for BAM compression and decompression")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("INPUT.BAM")
+                .help("BAM format input file (- for stdin)")
+                .required(true),
+        )
+        .args(&read_filter_args())
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    let corrupt = init_logging(&matches)?;
+
+    let cli = CLI {
+        read_filter: read_filter_cli(&matches),
+        output: matches.value_of("output").unwrap().to_string(),
+        ranges: matches
+            .values_of("range")
+            .unwrap()
+            .map(str::to_string)
+            .collect(),
+        other: matches.is_present("other"),
+        io_threads: matches.value_of("io-threads").unwrap().to_string(),
+        input: matches.value_of("input").unwrap().to_string(),
+        corrupt: corrupt,
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
+}
@@ -0,0 +1,117 @@
+extern crate clap;
+extern crate failure;
+
+extern crate riboprof;
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg, ArgGroup};
+
+use riboprof::bam_subsample::*;
+use riboprof::config_file::*;
+use riboprof::logging::*;
+use riboprof::umi::*;
+
+fn main() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("bam-subsample", start.elapsed(), &result);
+    match result {
+        Err(e) => {
+            io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
+            process::exit(1);
+        }
+        _ => (),
+    };
+}
+
+fn wrapper() -> Result<(), failure::Error> {
+    let cli = get_cli()?;
+    let config = Config::new(&cli)?;
+    run_bam_subsample(config)
+}
+
+fn get_cli() -> Result<CLI, failure::Error> {
+    let matches = App::new("bam-subsample")
+        .version("0.1.0")
+        .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .about("Downsamples alignments to a target number of reads or unique molecules")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT.BAM")
+                .help("BAM format output file (- for stdout)")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("reads")
+                .short("n")
+                .long("reads")
+                .value_name("N")
+                .help("Keep N reads, with all alignments of each read")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("molecules")
+                .short("u")
+                .long("molecules")
+                .value_name("N")
+                .help("Keep N unique molecules, with all duplicates of each molecule (requires sorted input)")
+                .takes_value(true),
+        )
+        .group(
+            ArgGroup::with_name("target")
+                .args(&["reads", "molecules"])
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Random seed for selecting reads or molecules")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("io-threads")
+                .long("io-threads")
+                .value_name("N")
+                .help("Additional threads for BAM compression and decompression")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("INPUT.BAM")
+                .help("BAM format input file, read twice")
+                .required(true),
+        )
+        .args(&umi_args())
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    let corrupt = init_logging(&matches)?;
+
+    let cli = CLI {
+        output: matches.value_of("output").unwrap().to_string(),
+        reads: matches.value_of("reads").map(str::to_string),
+        molecules: matches.value_of("molecules").map(str::to_string),
+        seed: matches.value_of("seed").unwrap().to_string(),
+        io_threads: matches.value_of("io-threads").unwrap().to_string(),
+        umi_config: umi_cli(&matches),
+        input: matches.value_of("input").unwrap().to_string(),
+        corrupt: corrupt,
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
+}
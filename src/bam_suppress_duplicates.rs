@@ -6,13 +6,21 @@ extern crate riboprof;
 use std::io;
 use std::io::Write;
 use std::process;
+use std::time::Instant;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg};
 
 use riboprof::bam_suppress_duplicates::*;
+use riboprof::config_file::*;
+use riboprof::logging::*;
+use riboprof::parallel::*;
+use riboprof::umi::*;
 
 fn main() {
-    match wrapper() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("bam-suppress-duplicates", start.elapsed(), &result);
+    match result {
         Err(e) => {
             io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
             process::exit(1);
@@ -31,6 +39,7 @@ fn get_cli() -> Result<CLI, failure::Error> {
     let matches = App::new("bam-suppress-duplicates")
         .version("0.1.0")
         .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
         .about("Suppress likely PCR duplicates based on UMIs embedded in sequence names")
         .long_about("Identifies and removes likely PCR duplicates. Duplicates are identified based on a nucleotide tag at the end of the read name stored in the BAM file, separated from the rest of the read name by a \"#\". Reads with no tag are not subject to deduplication. When multiple reads aligning to the same position share the same nucleotide tag, one is selected arbitrarily and written as the \"unique\" representative and, if specified, the rest are written to the file of duplicates. Optionally, the unique representative can be tagged with a \"ZD\" tag indicating the total number of duplicate reads (always 2 or more) at that position. Optionally, a table of duplicate suppression statistics can be written as a tab-separated file, tabulating the duplicate status of each distinct mapping site. In this statistics file, the first column is the total number of reads aligned to the site, the second is the number of unique reads, and the third is the count of distinct sites. Thus, \"1  1  234\" would indicate 234 distinct positions with a single unique read, \"2  2  17\" would indicate 17 distinct positions with two unique reads, and \"2  1  5\" would indicate 5 positions with a single duplicated read (2 reads total, 1 unique).")
         .arg(
@@ -69,19 +78,79 @@ fn get_cli() -> Result<CLI, failure::Error> {
                 .help("Output file with duplicate statistics")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("stats-json")
+                .long("stats-json")
+                .value_name("STATS.JSON")
+                .help("Output file with duplicate statistics as JSON, for merging with riboprof merge-dedup")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("annotate")
                 .short("a")
                 .long("annotate")
                 .help("Annotate deduplicated reads"),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("header-stats")
+                .long("header-stats")
+                .help("Record duplicate statistics as @CO lines in the output BAM header, reading the input twice"),
+        )
+        .arg(
+            Arg::with_name("allow-untagged")
+                .long("allow-untagged")
+                .help("Pass alignments through when no UMIs are found, rather than failing"),
+        )
+        .arg(
+            Arg::with_name("transcripts")
+                .long("transcripts")
+                .value_name("ANNOTATION")
+                .help("BED- or GTF-format annotation of the transcripts that the input is aligned to, grouping duplicates by gene-level position across isoforms")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("isoform-map")
+                .long("isoform-map")
+                .value_name("MAP.TXT")
+                .help("Tab-delimited table of transcript and gene names, assigning isoforms to genes in place of the annotation")
+                .takes_value(true)
+                .requires("transcripts"),
+        )
+        .arg(
+            Arg::with_name("io-threads")
+                .long("io-threads")
+                .value_name("N")
+                .help("Additional threads for BAM compression and decompression")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(threads_arg())
+        .args(&umi_args())
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    let corrupt = init_logging(&matches)?;
 
-    Ok(CLI {
+    let cli = CLI {
         bam_input: matches.value_of("bam_input").unwrap().to_string(),
         bam_output: matches.value_of("bam_output").unwrap().to_string(),
         bam_dups: matches.value_of_lossy("bam_dups").map(|a| a.to_string()),
         stats: matches.value_of_lossy("stats").map(|a| a.to_string()),
+        stats_json: matches.value_of("stats-json").map(str::to_string),
         annotate: matches.is_present("annotate"),
-    })
+        header_stats: matches.is_present("header-stats"),
+        allow_untagged: matches.is_present("allow-untagged"),
+        transcripts: matches.value_of("transcripts").map(str::to_string),
+        isoform_map: matches.value_of("isoform-map").map(str::to_string),
+        threads: matches.value_of("threads").unwrap().to_string(),
+        io_threads: matches.value_of("io-threads").unwrap().to_string(),
+        umi_config: umi_cli(&matches),
+        corrupt: corrupt,
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
 }
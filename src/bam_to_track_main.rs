@@ -0,0 +1,142 @@
+extern crate clap;
+extern crate failure;
+
+extern crate riboprof;
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg};
+
+use riboprof::bam_to_track::*;
+use riboprof::cli_utils::*;
+use riboprof::config_file::*;
+use riboprof::logging::*;
+use riboprof::normalization::*;
+use riboprof::output::*;
+
+fn main() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("bam-to-track", start.elapsed(), &result);
+    match result {
+        Err(e) => {
+            io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
+            process::exit(1);
+        }
+        _ => (),
+    };
+}
+
+fn wrapper() -> Result<(), failure::Error> {
+    let cli = get_cli()?;
+    let config = Config::new(&cli)?;
+    run_bam_to_track(config)
+}
+
+fn get_cli() -> Result<CLI, failure::Error> {
+    let matches = App::new("bam-to-track")
+        .version("0.1.0")
+        .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .about("Strand-specific genome tracks of footprint positions or coverage")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTBASE")
+                .help("Base filename for output tracks")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("t")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Track format (bedgraph, wig, or bigwig)")
+                .takes_value(true)
+                .default_value("bedgraph"),
+        )
+        .arg(
+            Arg::with_name("site")
+                .short("s")
+                .long("site")
+                .value_name("SITE")
+                .help("Position tallied for each read (5p, 3p, asite, or coverage)")
+                .takes_value(true)
+                .default_value("5p"),
+        )
+        .arg(
+            Arg::with_name("asites")
+                .short("a")
+                .long("asites")
+                .value_name("ASITES.TXT")
+                .help("Tab-delimited file of A site offsets, required for A site tracks")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("lengths")
+                .short("l")
+                .long("lengths")
+                .value_name("MINLEN,MAXLEN")
+                .help("Footprint lengths tallied")
+                .takes_value(true)
+                .default_value("15,50"),
+        )
+        .arg(
+            Arg::with_name("count-multi")
+                .short("m")
+                .long("count-multi")
+                .help("Tally the first alignment of multi-mapping reads"),
+        )
+        .arg(
+            Arg::with_name("frames")
+                .long("frames")
+                .help("Write separate tracks for each genomic reading frame"),
+        )
+        .arg(
+            Arg::with_name("io-threads")
+                .long("io-threads")
+                .value_name("N")
+                .help("Additional threads for BAM compression and decompression")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("INPUT.BAM")
+                .required(true),
+        )
+        .args(&read_filter_args())
+        .args(&normalize_args())
+        .args(&output_args())
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    let corrupt = init_logging(&matches)?;
+
+    let cli = CLI {
+        read_filter: read_filter_cli(&matches),
+        normalize_config: normalize_cli(&matches),
+        output_config: output_cli(&matches),
+        output: matches.value_of("output").unwrap().to_string(),
+        format: matches.value_of("format").unwrap().to_string(),
+        site: matches.value_of("site").unwrap().to_string(),
+        asites: matches.value_of("asites").map(|s| s.to_string()),
+        lengths: matches.value_of("lengths").unwrap().to_string(),
+        count_multi: matches.is_present("count-multi"),
+        frames: matches.is_present("frames"),
+        io_threads: matches.value_of("io-threads").unwrap().to_string(),
+        input: matches.value_of("input").unwrap().to_string(),
+        corrupt: corrupt,
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
+}
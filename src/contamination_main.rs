@@ -0,0 +1,93 @@
+extern crate clap;
+extern crate failure;
+
+extern crate riboprof;
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg};
+
+use riboprof::cli_utils::*;
+use riboprof::config_file::*;
+use riboprof::contamination::*;
+use riboprof::logging::*;
+use riboprof::output::*;
+
+fn main() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("contamination", start.elapsed(), &result);
+    match result {
+        Err(e) => {
+            io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
+            process::exit(1);
+        }
+        _ => (),
+    };
+}
+
+fn wrapper() -> Result<(), failure::Error> {
+    let cli = get_cli()?;
+    let config = Config::new(&cli)?;
+    run_contamination(config)
+}
+
+fn get_cli() -> Result<CLI, failure::Error> {
+    let matches = App::new("contamination")
+        .version("0.1.0")
+        .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .about("Reports the fraction of reads from rRNA, tRNA, and other contaminant classes")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTBASE")
+                .help("Base filename for output files")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("classes")
+                .short("c")
+                .long("classes")
+                .value_name("CLASSES.BED")
+                .help("BED file of contaminant features, named by class (e.g., rRNA, tRNA, snoRNA, mito)")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("io-threads")
+                .long("io-threads")
+                .value_name("N")
+                .help("Additional threads for BAM compression and decompression")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(Arg::with_name("input").value_name("INPUT.BAM").required(true))
+        .args(&read_filter_args())
+        .args(&output_args())
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    let corrupt = init_logging(&matches)?;
+
+    let cli = CLI {
+        read_filter: read_filter_cli(&matches),
+        output_config: output_cli(&matches),
+        output: matches.value_of("output").unwrap().to_string(),
+        classes: matches.value_of("classes").unwrap().to_string(),
+        io_threads: matches.value_of("io-threads").unwrap().to_string(),
+        input: matches.value_of("input").unwrap().to_string(),
+        corrupt: corrupt,
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
+}
@@ -7,13 +7,21 @@ extern crate riboprof;
 use std::io;
 use std::io::Write;
 use std::process;
+use std::time::Instant;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg};
 
+use riboprof::config_file::*;
 use riboprof::fastx_split::*;
+use riboprof::logging::*;
+use riboprof::parallel::*;
+use riboprof::umi::*;
 
 fn main() {
-    match wrapper() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("fastx-split", start.elapsed(), &result);
+    match result {
         Err(e) => {
             io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
             process::exit(1);
@@ -32,6 +40,7 @@ fn get_cli() -> Result<CLI, failure::Error> {
     let matches = App::new("fastx-split")
         .version("0.1.0")
         .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
         .about("Split FastQ file using index and random nucleotides")
         .arg(
             Arg::with_name("output_dir")
@@ -51,6 +60,28 @@ fn get_cli() -> Result<CLI, failure::Error> {
                 .takes_value(true)
                 .default_value("0"),
         )
+        .arg(
+            Arg::with_name("max_insert")
+                .long("max-insert")
+                .value_name("MAX-INSERT")
+                .help("Maximum insert length, writing reads with longer inserts to toolong.fastq")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trim_tail")
+                .long("trim-tail")
+                .value_name("NT")
+                .help("Trim a homopolymer tail of NT, e.g., A for poly-A, from the end of each insert")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("min_tail")
+                .long("min-tail")
+                .value_name("LENGTH")
+                .help("Minimum length of a homopolymer tail trimmed with --trim-tail")
+                .takes_value(true)
+                .default_value("4"),
+        )
         .arg(
             Arg::with_name("prefix")
                 .short("p")
@@ -74,7 +105,7 @@ fn get_cli() -> Result<CLI, failure::Error> {
                 .short("s")
                 .long("sample-sheet")
                 .value_name("SAMPLESHEET.CSV")
-                .help("File name of CSV-format sample sheet")
+                .help("File name of CSV-format sample sheet, NAME,INDEX[,EXPECTED%[,MIN-READS]], with a dual INDEX written I7+I5, or with a header row naming columns from name, index, expected_percent, min_reads, output, mismatches, and description")
                 .takes_value(true)
                 .required(true),
         )
@@ -86,16 +117,152 @@ fn get_cli() -> Result<CLI, failure::Error> {
                 .takes_value(true)
                 .default_value("0"),
         )
-        .arg(Arg::with_name("input").multiple(true).required(true))
-        .get_matches();
+        .arg(
+            Arg::with_name("min_index_qual")
+                .long("min-index-qual")
+                .value_name("QUAL")
+                .help("Assign reads with any sample index base below Phred quality QUAL to UnknownIndex")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("min_umi_qual")
+                .long("min-umi-qual")
+                .value_name("QUAL")
+                .help("Mask UMI bases below Phred quality QUAL to N")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("min_mean_qual")
+                .long("min-mean-qual")
+                .value_name("QUAL")
+                .help("Write reads with mean Phred quality below QUAL to lowqual.fastq")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("filter_umi_qual")
+                .long("filter-umi-qual")
+                .value_name("QUAL")
+                .help("Write reads with any UMI base below Phred quality QUAL to lowqual.fastq")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tolerance")
+                .long("tolerance")
+                .value_name("PERCENT")
+                .help("Allowed difference, in percentage points, from the expected percentage of reads in the sample sheet")
+                .takes_value(true)
+                .default_value("5"),
+        )
+        .arg(
+            Arg::with_name("strict")
+                .long("strict")
+                .help("Exit with an error when any sample fails the sample sheet expectations"),
+        )
+        .arg(
+            Arg::with_name("per_sample_dirs")
+                .long("per-sample-dirs")
+                .help("Write the FASTQ and statistics for each sample into a subdirectory named for the sample"),
+        )
+        .arg(
+            Arg::with_name("umi_cluster")
+                .long("umi-cluster")
+                .help("Collapse UMIs one mismatch from a more abundant UMI in the same sample by directional clustering, reading the input twice"),
+        )
+        .arg(
+            Arg::with_name("umi_barcode")
+                .long("umi-barcode")
+                .help("Record the sample barcode before the UMI in read names, as for umi_tools, or as a BC:Z: comment with --umi-source tag"),
+        )
+        .arg(
+            Arg::with_name("interleaved")
+                .long("interleaved")
+                .help("Input is interleaved paired-end FASTQ, with the linker on the first mate; mates are written together, interleaved, in each output"),
+        )
+        .arg(
+            Arg::with_name("header_index")
+                .long("header-index")
+                .help("Take the sample index from the Illumina read header, e.g., 1:N:0:ACGTACGT or dual 1:N:0:ACGTACGT+TTGGCCAA, rather than from the linker"),
+        )
+        .arg(
+            Arg::with_name("gzip")
+                .long("gzip")
+                .help("Write gzip-compressed FASTQ output files, including tooshort, toolong, lowqual, and UnknownIndex, with a .fastq.gz suffix"),
+        )
+        .arg(
+            Arg::with_name("ubam")
+                .long("ubam")
+                .help("Write unaligned BAM for each sample, with the UMI in the RX tag and the sample barcode in the BC tag, instead of FASTQ"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("INPUT.FASTQ")
+                .help("Input FASTQ files, optionally gzip or bzip2 compressed, or unaligned .bam or .cram files (\"-\" for standard input)")
+                .multiple(true)
+                .required(true),
+        )
+        .arg(threads_arg())
+        .args(&umi_args())
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    let corrupt = init_logging(&matches)?;
 
-    Ok(CLI {
+    let cli = CLI {
         fastx_inputs: matches.values_of_lossy("input").unwrap(),
         output_dir: matches.value_of("output_dir").unwrap().to_string(),
         min_insert: value_t!(matches.value_of("min_insert"), usize)?,
+        max_insert: if matches.is_present("max_insert") {
+            Some(value_t!(matches.value_of("max_insert"), usize)?)
+        } else {
+            None
+        },
+        trim_tail: if matches.is_present("trim_tail") {
+            Some(value_t!(matches.value_of("trim_tail"), char)?)
+        } else {
+            None
+        },
+        min_tail: value_t!(matches.value_of("min_tail"), usize)?,
         prefix: matches.value_of("prefix").unwrap().to_string(),
         suffix: matches.value_of("suffix").unwrap().to_string(),
         sample_sheet: matches.value_of("sample_sheet").unwrap().to_string(),
         progress: value_t!(matches.value_of("progress"), usize)?,
-    })
+        threads: matches.value_of("threads").unwrap().to_string(),
+        min_index_qual: if matches.is_present("min_index_qual") {
+            Some(value_t!(matches.value_of("min_index_qual"), u8)?)
+        } else {
+            None
+        },
+        min_umi_qual: if matches.is_present("min_umi_qual") {
+            Some(value_t!(matches.value_of("min_umi_qual"), u8)?)
+        } else {
+            None
+        },
+        min_mean_qual: if matches.is_present("min_mean_qual") {
+            Some(value_t!(matches.value_of("min_mean_qual"), u8)?)
+        } else {
+            None
+        },
+        filter_umi_qual: if matches.is_present("filter_umi_qual") {
+            Some(value_t!(matches.value_of("filter_umi_qual"), u8)?)
+        } else {
+            None
+        },
+        tolerance: value_t!(matches.value_of("tolerance"), f64)?,
+        strict: matches.is_present("strict"),
+        per_sample_dirs: matches.is_present("per_sample_dirs"),
+        gzip: matches.is_present("gzip"),
+        ubam: matches.is_present("ubam"),
+        umi_cluster: matches.is_present("umi_cluster"),
+        umi_barcode: matches.is_present("umi_barcode"),
+        interleaved: matches.is_present("interleaved"),
+        header_index: matches.is_present("header_index"),
+        umi_config: umi_cli(&matches),
+        corrupt: corrupt,
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
 }
@@ -6,13 +6,22 @@ extern crate riboprof;
 use std::io;
 use std::io::Write;
 use std::process;
+use std::time::Instant;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg};
 
+use riboprof::cli_utils::*;
+use riboprof::config_file::*;
 use riboprof::fp_framing::*;
+use riboprof::logging::*;
+use riboprof::output::*;
+use riboprof::parallel::*;
 
 fn main() {
-    match wrapper() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("fp-framing", start.elapsed(), &result);
+    match result {
         Err(e) => {
             io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
             process::exit(1);
@@ -31,6 +40,7 @@ fn get_cli() -> Result<CLI, failure::Error> {
     let matches = App::new("fp-framing")
         .version("0.1.0")
         .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
         .about("Calculates ribosome profiling QC information including reading frame bias and start and stop codon meta-genes")
         .arg(
             Arg::with_name("output")
@@ -46,8 +56,10 @@ fn get_cli() -> Result<CLI, failure::Error> {
                 .short("b")
                 .long("bed")
                 .value_name("BED")
-                .help("BED-format annotation filename")
+                .help("BED-, GTF-, or GFF3-format annotation filename, repeated for several annotations in order of precedence")
                 .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
                 .required(true),
         )
         .arg(
@@ -101,12 +113,102 @@ fn get_cli() -> Result<CLI, failure::Error> {
                 .help("Write output BAM file annotated wiht framing information")
                 .takes_value(true)
         )
+        .arg(
+            Arg::with_name("write-asites")
+                .short("A")
+                .long("write-asites")
+                .value_name("ASITES.TXT")
+                .help("Write A site offsets estimated from frame 0 enrichment for each length")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("offsets")
+                .long("offsets")
+                .value_name("MINOFF,MAXOFF")
+                .help("Range of candidate A site offsets for estimation")
+                .takes_value(true)
+                .default_value("12,18"),
+        )
+        .arg(
+            Arg::with_name("asites")
+                .long("asites")
+                .value_name("ASITES.TXT")
+                .help("A site offsets for each length, used to also count footprints at a ribosomal site around the start and stop")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("site")
+                .long("site")
+                .value_name("SITE")
+                .help("Ribosomal site for counts around the start and stop with --asites")
+                .takes_value(true)
+                .possible_values(&["A", "P", "E"])
+                .default_value("P"),
+        )
+        .arg(
+            Arg::with_name("min-offset-count")
+                .long("min-offset-count")
+                .value_name("COUNT")
+                .help("Minimum framed footprints needed to estimate the offset for a length")
+                .takes_value(true)
+                .default_value("100"),
+        )
+        .arg(
+            Arg::with_name("frame-tables")
+                .long("frame-tables")
+                .help("Also write start and end tables by position, length, and frame"),
+        )
+        .arg(
+            Arg::with_name("gene-frames")
+                .long("gene-frames")
+                .help("Also write footprint counts by frame and CDS position for each gene"),
+        )
+        .arg(
+            Arg::with_name("clean-genes")
+                .long("clean-genes")
+                .help("Frame only genes with one isoform and no overlapping neighbors"),
+        )
+        .arg(
+            Arg::with_name("disome-lengths")
+                .long("disome-lengths")
+                .value_name("MINLEN,MAXLEN")
+                .help("Also frame disome footprints in this length range, e.g., 55,70")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("disome-offsets")
+                .long("disome-offsets")
+                .value_name("MINOFF,MAXOFF")
+                .help("Range of candidate A site offsets for disome footprints")
+                .takes_value(true)
+                .default_value("40,52"),
+        )
+        .arg(
+            Arg::with_name("io-threads")
+                .long("io-threads")
+                .value_name("N")
+                .help("Additional threads for BAM compression and decompression")
+                .takes_value(true)
+                .default_value("0"),
+        )
         .arg(Arg::with_name("input").value_name("INPUT.BAM").required(true))
-        .get_matches();
+        .args(&read_filter_args())
+        .arg(threads_arg())
+        .args(&output_args())
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    let corrupt = init_logging(&matches)?;
 
-    Ok(CLI {
+    let cli = CLI {
+        read_filter: read_filter_cli(&matches),
+        output_config: output_cli(&matches),
         output: matches.value_of("output").unwrap().to_string(),
-        bed: matches.value_of("bed").unwrap().to_string(),
+        bed: matches
+            .values_of("bed")
+            .unwrap()
+            .map(str::to_string)
+            .collect(),
         genes: matches
             .values_of_lossy("genes")
             .unwrap_or_else(|| Vec::new()),
@@ -115,6 +217,25 @@ fn get_cli() -> Result<CLI, failure::Error> {
         lengths: matches.value_of("lengths").unwrap().to_string(),
         count_multi: matches.is_present("count-multi"),
         annotate: matches.value_of_lossy("annotate").map(|a| a.to_string()),
+        write_asites: matches.value_of("write-asites").map(str::to_string),
+        offsets: matches.value_of("offsets").unwrap().to_string(),
+        min_offset_count: matches.value_of("min-offset-count").unwrap().to_string(),
+        asites: matches.value_of("asites").map(str::to_string),
+        site: matches.value_of("site").unwrap().to_string(),
+        frame_tables: matches.is_present("frame-tables"),
+        gene_frames: matches.is_present("gene-frames"),
+        clean_genes: matches.is_present("clean-genes"),
+        disome_lengths: matches.value_of("disome-lengths").map(str::to_string),
+        disome_offsets: matches.value_of("disome-offsets").unwrap().to_string(),
+        threads: matches.value_of("threads").unwrap().to_string(),
+        io_threads: matches.value_of("io-threads").unwrap().to_string(),
         input: matches.value_of("input").unwrap().to_string(),
-    })
+        corrupt: corrupt,
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
 }
@@ -0,0 +1,133 @@
+extern crate clap;
+extern crate failure;
+
+extern crate riboprof;
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg};
+
+use riboprof::cli_utils::*;
+use riboprof::config_file::*;
+use riboprof::framing_compare::*;
+use riboprof::logging::*;
+use riboprof::output::*;
+
+fn main() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("framing-compare", start.elapsed(), &result);
+    match result {
+        Err(e) => {
+            io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
+            process::exit(1);
+        }
+        _ => (),
+    };
+}
+
+fn wrapper() -> Result<(), failure::Error> {
+    let cli = get_cli()?;
+    let config = Config::new(&cli)?;
+    run_framing_compare(config)
+}
+
+fn get_cli() -> Result<CLI, failure::Error> {
+    let matches = App::new("framing-compare")
+        .version("0.1.0")
+        .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .about("Finds genes whose reading frame or CDS position distributions differ between two samples")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTBASE")
+                .help("Base filename for output files")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("bed")
+                .short("b")
+                .long("bed")
+                .value_name("BED")
+                .help("BED-, GTF-, or GFF3-format annotation filename, needed for BAM inputs")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cdsbody")
+                .short("c")
+                .long("cdsbody")
+                .value_name("AFTERSTART,BEFOREEND")
+                .help("Offsets from the start and end of the gene for framing BAM inputs")
+                .takes_value(true)
+                .default_value("34,31"),
+        )
+        .arg(
+            Arg::with_name("lengths")
+                .short("l")
+                .long("lengths")
+                .value_name("MINLEN,MAXLEN")
+                .help("Length range for framing BAM inputs")
+                .takes_value(true)
+                .default_value("26,34"),
+        )
+        .arg(
+            Arg::with_name("min-count")
+                .long("min-count")
+                .value_name("COUNT")
+                .help("Minimum framed footprints on a gene in each sample to test it")
+                .takes_value(true)
+                .default_value("50"),
+        )
+        .arg(
+            Arg::with_name("fdr")
+                .long("fdr")
+                .value_name("Q")
+                .help("False discovery rate for calling genes significant")
+                .takes_value(true)
+                .default_value("0.05"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("GENE_FRAMES.TXT|INPUT.BAM")
+                .help("Two fp-framing --gene-frames tables, or BAM files, to compare")
+                .multiple(true)
+                .min_values(2)
+                .max_values(2)
+                .required(true),
+        )
+        .args(&read_filter_args())
+        .args(&output_args())
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    let corrupt = init_logging(&matches)?;
+
+    let cli = CLI {
+        read_filter: read_filter_cli(&matches),
+        output_config: output_cli(&matches),
+        output: matches.value_of("output").unwrap().to_string(),
+        bed: matches.value_of("bed").map(str::to_string),
+        cdsbody: matches.value_of("cdsbody").unwrap().to_string(),
+        lengths: matches.value_of("lengths").unwrap().to_string(),
+        min_count: matches.value_of("min-count").unwrap().to_string(),
+        fdr: matches.value_of("fdr").unwrap().to_string(),
+        inputs: matches
+            .values_of("input")
+            .unwrap()
+            .map(str::to_string)
+            .collect(),
+        corrupt: corrupt,
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
+}
@@ -0,0 +1,285 @@
+extern crate clap;
+extern crate failure;
+
+extern crate riboprof;
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg};
+
+use riboprof::cli_utils::*;
+use riboprof::config_file::*;
+use riboprof::logging::*;
+use riboprof::metagene_count::*;
+use riboprof::normalization::*;
+use riboprof::output::*;
+
+fn main() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("metagene", start.elapsed(), &result);
+    match result {
+        Err(e) => {
+            io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
+            process::exit(1);
+        }
+        _ => (),
+    };
+}
+
+fn wrapper() -> Result<(), failure::Error> {
+    let cli = get_cli()?;
+    let config = Config::new(&cli)?;
+    run_metagene(config)
+}
+
+fn get_cli() -> Result<CLI, failure::Error> {
+    let matches = App::new("metagene")
+        .version("0.1.0")
+        .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .about("Tallies footprints by position and length around anchor positions")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTBASE")
+                .help("Base filename for output files")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("anchors")
+                .short("r")
+                .long("anchors")
+                .value_name("ANCHORS.BED")
+                .help("BED file of features whose 5' ends are anchors")
+                .takes_value(true)
+                .conflicts_with("bed"),
+        )
+        .arg(
+            Arg::with_name("bed")
+                .short("b")
+                .long("bed")
+                .value_name("BED")
+                .help("BED-, GTF-, or GFF3-format annotation filename, for anchors at annotated features")
+                .takes_value(true)
+                .required_unless("anchors"),
+        )
+        .arg(
+            Arg::with_name("feature")
+                .short("F")
+                .long("feature")
+                .value_name("FEATURE")
+                .help("Annotated feature used as anchor")
+                .takes_value(true)
+                .possible_values(&["start", "stop", "tss", "tes"])
+                .default_value("start"),
+        )
+        .arg(
+            Arg::with_name("codons")
+                .short("c")
+                .long("codons")
+                .value_name("MOTIFS")
+                .help("Anchor on in-frame codon motifs, e.g., CCNCCN, rather than a feature")
+                .takes_value(true)
+                .requires("fasta")
+                .conflicts_with_all(&["anchors", "bins"]),
+        )
+        .arg(
+            Arg::with_name("fasta")
+                .long("fasta")
+                .value_name("GENOME.FA")
+                .help("Genome sequence, for codon motif anchors and start codon contexts")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("bins")
+                .short("n")
+                .long("bins")
+                .value_name("N")
+                .help(
+                    "Tally footprints in N equal bins across each CDS, rather than around anchors",
+                )
+                .takes_value(true)
+                .conflicts_with("anchors"),
+        )
+        .arg(
+            Arg::with_name("runoff")
+                .long("runoff")
+                .value_name("CODONS")
+                .help("Tally footprint density in sliding windows of CODONS codons from each start codon, for run-off time courses")
+                .takes_value(true)
+                .requires("bed")
+                .conflicts_with_all(&["anchors", "bins", "codons"]),
+        )
+        .arg(
+            Arg::with_name("runoff-step")
+                .long("runoff-step")
+                .value_name("CODONS")
+                .help("Spacing in codons between run-off window starts")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("asites")
+                .short("a")
+                .long("asites")
+                .value_name("ASITES.TXT")
+                .help("Tally footprint A sites using offsets from this table, rather than 5' ends")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("flanking")
+                .short("f")
+                .long("flanking")
+                .value_name("START,END")
+                .help("Range of positions surrounding each anchor")
+                .takes_value(true)
+                .default_value("-100,100"),
+        )
+        .arg(
+            Arg::with_name("lengths")
+                .short("l")
+                .long("lengths")
+                .value_name("MINLEN,MAXLEN")
+                .help("Range of footprint lengths tallied")
+                .takes_value(true)
+                .default_value("26,34"),
+        )
+        .arg(
+            Arg::with_name("length-bins")
+                .short("L")
+                .long("length-bins")
+                .value_name("BINS")
+                .help("Tally footprint lengths in bins, e.g., 19-24,25-34,50-80, rather than individually")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("count-multi")
+                .short("m")
+                .long("count-multi")
+                .help("Count multi-mapping reads once, at their first occurrence (i.e., HI = 0)"),
+        )
+        .arg(
+            Arg::with_name("deletions")
+                .long("deletions")
+                .value_name("POLICY")
+                .help("Treat deletions in footprint alignments as part of the footprint (match) or as gaps (gap)")
+                .takes_value(true)
+                .possible_values(&["match", "gap"])
+                .default_value("match"),
+        )
+        .arg(
+            Arg::with_name("max-indel")
+                .long("max-indel")
+                .value_name("LEN")
+                .help("Reject footprint alignments with an insertion or deletion longer than LEN (0 rejects any indel)")
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("sparse").long("sparse").help(
+            "Store only positions with footprints, for wide windows (automatic above 1000 nt)",
+        ))
+        .arg(
+            Arg::with_name("normalize")
+                .short("N")
+                .long("normalize")
+                .value_name("MODE,...")
+                .help("Also write metagene tables with these normalizations")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .possible_values(&["raw", "rpm", "fraction", "gene-mean"])
+                .conflicts_with("bins"),
+        )
+        .arg(
+            Arg::with_name("per-gene")
+                .short("g")
+                .long("per-gene")
+                .help("Keep the profile around each anchor, to summarize variation across genes")
+                .conflicts_with_all(&["bins", "runoff"]),
+        )
+        .arg(
+            Arg::with_name("clean-genes")
+                .long("clean-genes")
+                .help("Use only genes with one isoform and no overlapping neighbors")
+                .requires("bed"),
+        )
+        .arg(
+            Arg::with_name("start-context")
+                .long("start-context")
+                .help("Report the -6..+4 start codon context and footprints around each start codon")
+                .requires_all(&["bed", "fasta"])
+                .conflicts_with_all(&["codons", "bins", "runoff"]),
+        )
+        .arg(
+            Arg::with_name("bootstrap")
+                .long("bootstrap")
+                .value_name("N")
+                .help("Estimate confidence intervals across genes from N bootstrap replicates")
+                .takes_value(true)
+                .requires("per-gene"),
+        )
+        .arg(
+            Arg::with_name("bootstrap-seed")
+                .long("bootstrap-seed")
+                .value_name("SEED")
+                .help("Random seed for bootstrap resampling")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("INPUT.BAM")
+                .required(true),
+        )
+        .args(&read_filter_args())
+        .args(&library_size_args())
+        .args(&output_args())
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    let corrupt = init_logging(&matches)?;
+
+    let cli = CLI {
+        read_filter: read_filter_cli(&matches),
+        library_size: library_size_cli(&matches),
+        output_config: output_cli(&matches),
+        input: matches.value_of("input").unwrap().to_string(),
+        output: matches.value_of("output").unwrap().to_string(),
+        anchors: matches.value_of("anchors").map(str::to_string),
+        bed: matches.value_of("bed").map(str::to_string),
+        feature: matches.value_of("feature").unwrap().to_string(),
+        codons: matches.value_of("codons").map(str::to_string),
+        fasta: matches.value_of("fasta").map(str::to_string),
+        asites: matches.value_of("asites").map(str::to_string),
+        flanking: matches.value_of("flanking").unwrap().to_string(),
+        lengths: matches.value_of("lengths").unwrap().to_string(),
+        length_bins: matches.value_of("length-bins").map(str::to_string),
+        count_multi: matches.is_present("count-multi"),
+        sparse: matches.is_present("sparse"),
+        deletions: matches.value_of("deletions").unwrap().to_string(),
+        max_indel: matches.value_of("max-indel").map(str::to_string),
+        bins: matches.value_of("bins").map(str::to_string),
+        runoff: matches.value_of("runoff").map(str::to_string),
+        runoff_step: matches.value_of("runoff-step").unwrap().to_string(),
+        per_gene: matches.is_present("per-gene"),
+        clean_genes: matches.is_present("clean-genes"),
+        start_context: matches.is_present("start-context"),
+        normalize: matches
+            .values_of_lossy("normalize")
+            .unwrap_or_else(|| Vec::new()),
+        bootstrap: matches.value_of("bootstrap").map(str::to_string),
+        bootstrap_seed: matches.value_of("bootstrap-seed").unwrap().to_string(),
+        corrupt: corrupt,
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
+}
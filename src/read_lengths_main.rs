@@ -0,0 +1,111 @@
+extern crate clap;
+extern crate failure;
+
+extern crate riboprof;
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg};
+
+use riboprof::cli_utils::*;
+use riboprof::config_file::*;
+use riboprof::logging::*;
+use riboprof::output::*;
+use riboprof::read_lengths::*;
+
+fn main() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("read-lengths", start.elapsed(), &result);
+    match result {
+        Err(e) => {
+            io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
+            process::exit(1);
+        }
+        _ => (),
+    };
+}
+
+fn wrapper() -> Result<(), failure::Error> {
+    let cli = get_cli()?;
+    let config = Config::new(&cli)?;
+    run_read_lengths(config)
+}
+
+fn get_cli() -> Result<CLI, failure::Error> {
+    let matches = App::new("read-lengths")
+        .version("0.1.0")
+        .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .about("Tallies read length histograms from FASTQ or BAM files, optionally by annotated region")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTBASE")
+                .help("Base filename for output files")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Input file format (default guessed from the file name)")
+                .takes_value(true)
+                .possible_values(&["fastq", "bam"]),
+        )
+        .arg(
+            Arg::with_name("bed")
+                .short("b")
+                .long("bed")
+                .value_name("BED")
+                .help("BED-, GTF-, or GFF3-format annotation filename, to tally aligned reads by region (CDS, UTR, ncRNA)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("lengths")
+                .short("l")
+                .long("lengths")
+                .value_name("MINLEN,MAXLEN")
+                .help("Range of read lengths tallied individually")
+                .takes_value(true)
+                .default_value("15,50"),
+        )
+        .arg(
+            Arg::with_name("io-threads")
+                .long("io-threads")
+                .value_name("N")
+                .help("Additional threads for BAM compression and decompression")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(Arg::with_name("input").value_name("INPUT").required(true))
+        .args(&read_filter_args())
+        .args(&output_args())
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    let corrupt = init_logging(&matches)?;
+
+    let cli = CLI {
+        read_filter: read_filter_cli(&matches),
+        output_config: output_cli(&matches),
+        output: matches.value_of("output").unwrap().to_string(),
+        format: matches.value_of("format").map(str::to_string),
+        bed: matches.value_of("bed").map(str::to_string),
+        lengths: matches.value_of("lengths").unwrap().to_string(),
+        io_threads: matches.value_of("io-threads").unwrap().to_string(),
+        input: matches.value_of("input").unwrap().to_string(),
+        corrupt: corrupt,
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
+}
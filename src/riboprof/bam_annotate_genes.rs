@@ -0,0 +1,407 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use failure;
+
+use bio_types::annot::loc::Loc;
+use bio_types::annot::refids::RefIDSet;
+use bio_types::annot::spliced::Spliced;
+use bio_types::strand::ReqStrand;
+use rust_htslib::bam;
+use rust_htslib::bam::Read as BamRead;
+
+use bam_utils::*;
+use corrupt::*;
+use parallel::*;
+use timing::*;
+use transcript::*;
+
+/// Tag holding the annotated region of a read.
+pub const REGION_TAG: &[u8; 2] = b"ZR";
+
+/// Tag holding the comma-separated genes of a read.
+pub const GENE_TAG: &[u8; 2] = b"ZG";
+
+/// Tag holding the comma-separated transcripts of a read.
+pub const TRANSCRIPT_TAG: &[u8; 2] = b"ZT";
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CLI {
+    #[serde(skip_serializing)]
+    pub input: String,
+    pub output: String,
+    pub bed: Vec<String>,
+    pub stats: Option<String>,
+    pub threads: String,
+    pub io_threads: String,
+    #[serde(flatten)]
+    pub read_filter: ReadFilterCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+pub struct Config {
+    input: String,
+    output: String,
+    trxome: Transcriptome<Arc<String>>,
+    stats: Option<String>,
+    threads: usize,
+    io_threads: usize,
+    read_filter: ReadFilter,
+    corrupt: CorruptInputs,
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        let mut refids = RefIDSet::new();
+
+        Ok(Config {
+            input: cli.input.to_string(),
+            output: cli.output.to_string(),
+            trxome: Transcriptome::new_from_files(&cli.bed, &mut refids)?,
+            stats: cli.stats.clone(),
+            threads: cli.threads.parse()?,
+            io_threads: cli.io_threads.parse()?,
+            read_filter: ReadFilter::new_from_cli(&cli.read_filter)?,
+            corrupt: cli.corrupt.clone(),
+        })
+    }
+}
+
+/// Annotated region of a read. Regions are ordered by priority, so
+/// that a read whose 5' end lies in the CDS of one transcript and the
+/// UTR of another is in the CDS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ReadRegion {
+    Cds,
+    Utr5,
+    Utr3,
+    Noncoding,
+    /// Overlapping same-strand transcripts only in their introns, or
+    /// with the 5' end outside of their exons
+    Intron,
+    /// Overlapping the exons of opposite-strand transcripts only
+    Antisense,
+    NoGene,
+}
+
+impl ReadRegion {
+    /// Every region, in priority order.
+    pub const ALL: [ReadRegion; 7] = [
+        ReadRegion::Cds,
+        ReadRegion::Utr5,
+        ReadRegion::Utr3,
+        ReadRegion::Noncoding,
+        ReadRegion::Intron,
+        ReadRegion::Antisense,
+        ReadRegion::NoGene,
+    ];
+}
+
+impl fmt::Display for ReadRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ReadRegion::Cds => "CDS",
+            ReadRegion::Utr5 => "UTR5",
+            ReadRegion::Utr3 => "UTR3",
+            ReadRegion::Noncoding => "ncRNA",
+            ReadRegion::Intron => "Intron",
+            ReadRegion::Antisense => "Antisense",
+            ReadRegion::NoGene => "NoGene",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Gene annotation of one aligned read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadAnnotation {
+    region: ReadRegion,
+    genes: BTreeSet<String>,
+    transcripts: BTreeSet<String>,
+}
+
+impl ReadAnnotation {
+    /// Annotates a read from the transcripts overlapping its aligned
+    /// location. Same-strand transcripts whose exons overlap the read
+    /// give its genes and transcripts, and the region is taken from the
+    /// position of its 5' end within them. Otherwise, the read is
+    /// intronic when it lies within same-strand transcripts, antisense
+    /// when it overlaps the exons of opposite-strand transcripts, and
+    /// has no gene when it overlaps neither.
+    pub fn new(trxome: &Transcriptome<Arc<String>>, fp: &Spliced<Arc<String>, ReqStrand>) -> Self {
+        let overlapping: Vec<&Transcript<Arc<String>>> = trxome.find_at_loc(fp).collect();
+        let (sense, antisense): (Vec<_>, Vec<_>) = overlapping
+            .into_iter()
+            .partition(|trx| trx.loc().strand() == fp.strand());
+        let (exonic, intronic): (Vec<_>, Vec<_>) = sense
+            .into_iter()
+            .partition(|trx| exon_overlap(trx.loc(), fp) > 0);
+
+        if !exonic.is_empty() {
+            let five_prime = fp.first_pos();
+            let region = exonic
+                .iter()
+                .filter_map(|trx| {
+                    let trxpos = trx.loc().pos_into(&five_prime)?;
+                    let pos = trxpos.pos() as usize;
+                    Some(match *trx.cds_range() {
+                        Some(ref cds) if pos < cds.start => ReadRegion::Utr5,
+                        Some(ref cds) if pos >= cds.end => ReadRegion::Utr3,
+                        Some(_) => ReadRegion::Cds,
+                        None => ReadRegion::Noncoding,
+                    })
+                })
+                .min()
+                .unwrap_or(ReadRegion::Intron);
+            Self::with_transcripts(region, &exonic)
+        } else if !intronic.is_empty() {
+            Self::with_transcripts(ReadRegion::Intron, &intronic)
+        } else {
+            let antisense: Vec<_> = antisense
+                .into_iter()
+                .filter(|trx| exon_overlap(trx.loc(), fp) > 0)
+                .collect();
+            if antisense.is_empty() {
+                Self::with_transcripts(ReadRegion::NoGene, &[])
+            } else {
+                Self::with_transcripts(ReadRegion::Antisense, &antisense)
+            }
+        }
+    }
+
+    fn with_transcripts(region: ReadRegion, trxs: &[&Transcript<Arc<String>>]) -> Self {
+        ReadAnnotation {
+            region: region,
+            genes: trxs.iter().map(|trx| trx.gene().to_string()).collect(),
+            transcripts: trxs.iter().map(|trx| trx.trxname().to_string()).collect(),
+        }
+    }
+
+    pub fn region(&self) -> ReadRegion {
+        self.region
+    }
+
+    /// Returns the genes, in sorted order.
+    pub fn genes(&self) -> Vec<&str> {
+        self.genes.iter().map(String::as_str).collect()
+    }
+
+    /// Returns the transcripts, in sorted order.
+    pub fn transcripts(&self) -> Vec<&str> {
+        self.transcripts.iter().map(String::as_str).collect()
+    }
+
+    /// Sets the region tag on a record, along with the gene and
+    /// transcript tags when the read has any.
+    pub fn set_tags(&self, rec: &mut bam::Record) -> Result<(), failure::Error> {
+        set_aux_string(rec, REGION_TAG, self.region.to_string().as_bytes())?;
+        if !self.genes.is_empty() {
+            set_aux_string(rec, GENE_TAG, self.genes().join(",").as_bytes())?;
+            set_aux_string(rec, TRANSCRIPT_TAG, self.transcripts().join(",").as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of reads annotated in each region.
+#[derive(Debug, Clone, Default)]
+pub struct RegionStats {
+    counts: [usize; 7],
+    unaligned: usize,
+}
+
+impl RegionStats {
+    pub fn new() -> Self {
+        RegionStats::default()
+    }
+
+    pub fn tally(&mut self, region: ReadRegion) {
+        self.counts[region as usize] += 1;
+    }
+
+    pub fn tally_unaligned(&mut self) {
+        self.unaligned += 1;
+    }
+
+    pub fn count(&self, region: ReadRegion) -> usize {
+        self.counts[region as usize]
+    }
+
+    pub fn merge(&mut self, other: &RegionStats) {
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other_count;
+        }
+        self.unaligned += other.unaligned;
+    }
+
+    pub fn table(&self) -> String {
+        let mut table = "region\treads\n".to_string();
+        for region in ReadRegion::ALL.iter() {
+            table += &format!("{}\t{}\n", region, self.count(*region));
+        }
+        table += &format!("Unaligned\t{}\n", self.unaligned);
+        table
+    }
+}
+
+/// Annotated records for one batch of alignments, computed on a worker
+/// thread.
+struct AnnotatedBatch {
+    records: Vec<bam::Record>,
+    stats: RegionStats,
+}
+
+/// Tags each alignment in a batch that passes the read filter. Other
+/// alignments are written unchanged.
+fn annotate_batch(
+    config: &Config,
+    batch: Vec<(bam::Record, Option<Spliced<Arc<String>, ReqStrand>>)>,
+) -> Result<AnnotatedBatch, failure::Error> {
+    let mut annotated = AnnotatedBatch {
+        records: Vec::with_capacity(batch.len()),
+        stats: RegionStats::new(),
+    };
+
+    for (mut rec, fp) in batch {
+        if config.read_filter.rejection(&rec).is_none() {
+            match fp {
+                Some(fp) => {
+                    let annot = ReadAnnotation::new(&config.trxome, &fp);
+                    annot.set_tags(&mut rec)?;
+                    annotated.stats.tally(annot.region());
+                }
+                None => annotated.stats.tally_unaligned(),
+            }
+        }
+        annotated.records.push(rec);
+    }
+
+    Ok(annotated)
+}
+
+pub fn run_bam_annotate_genes(config: Config) -> Result<(), failure::Error> {
+    info!("{}", config.trxome.summary());
+
+    let mut input = open_bam(&config.input, &config.corrupt)?;
+    set_reader_threads(&mut input, config.io_threads)?;
+
+    let tids = {
+        let mut refids: RefIDSet<Arc<String>> = RefIDSet::new();
+        Tids::new(&mut refids, input.header())
+    };
+    tids.warn_missing(&config.trxome);
+
+    let header = output_header(input.header(), "bam-annotate-genes");
+    let mut output = if config.output == "-" {
+        bam::Writer::from_stdout(&header)?
+    } else {
+        bam::Writer::from_path(Path::new(&config.output), &header)?
+    };
+    set_writer_threads(&mut output, config.io_threads)?;
+
+    let mut stats = RegionStats::new();
+    let _timer = StageTimer::new("classification");
+    Parallel::new(config.threads)?.map_batches(
+        spliced_records(&mut input, &tids, &config.corrupt),
+        |batch| annotate_batch(&config, batch),
+        |annotated| {
+            let _timer = StageTimer::new("output");
+            for rec in annotated.records.iter() {
+                output.write(rec)?;
+            }
+            stats.merge(&annotated.stats);
+            Ok(())
+        },
+    )?;
+
+    if let Some(ref stats_file) = config.stats {
+        fs::write(stats_file, stats.table())?;
+    }
+    info!(
+        "Annotated {} reads in CDS, {} intronic, {} antisense, and {} with no gene",
+        stats.count(ReadRegion::Cds),
+        stats.count(ReadRegion::Intron),
+        stats.count(ReadRegion::Antisense),
+        stats.count(ReadRegion::NoGene)
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bio::io::bed;
+
+    fn transcriptome_from_str(bedstr: &str) -> Transcriptome<Arc<String>> {
+        let mut refids = RefIDSet::new();
+        Transcriptome::new_from_bed(bed::Reader::new(bedstr.as_bytes()).records(), &mut refids)
+            .expect("Transcriptome from string")
+    }
+
+    fn annotate(fp_str: &str) -> ReadAnnotation {
+        // Coding exons [100..200) and [300..500), CDS 150..450
+        let trxome = transcriptome_from_str(
+            "chr01\t100\t500\tCoding\t0\t+\t150\t450\t0\t2\t100,200,\t0,200,\n\
+             chr01\t400\t600\tNoncoding\t0\t+\t400\t400\t0\t1\t200,\t0,\n\
+             chr01\t800\t900\tRevCoding\t0\t-\t820\t880\t0\t1\t100,\t0,\n",
+        );
+        let fp: Spliced<Arc<String>, ReqStrand> = fp_str.parse().unwrap();
+        ReadAnnotation::new(&trxome, &fp)
+    }
+
+    #[test]
+    fn annotate_exonic() {
+        let utr5 = annotate("chr01:120-150(+)");
+        assert_eq!(utr5.region(), ReadRegion::Utr5);
+        assert_eq!(utr5.genes(), vec!["Coding"]);
+        assert_eq!(annotate("chr01:160-190(+)").region(), ReadRegion::Cds);
+        assert_eq!(
+            annotate("chr01:180-200;300-310(+)").region(),
+            ReadRegion::Cds
+        );
+
+        let overlap = annotate("chr01:420-450(+)");
+        assert_eq!(overlap.region(), ReadRegion::Cds);
+        assert_eq!(overlap.transcripts(), vec!["Coding", "Noncoding"]);
+        assert_eq!(annotate("chr01:460-490(+)").region(), ReadRegion::Utr3);
+        assert_eq!(annotate("chr01:520-550(+)").region(), ReadRegion::Noncoding);
+    }
+
+    #[test]
+    fn annotate_intron() {
+        let intron = annotate("chr01:220-250(+)");
+        assert_eq!(intron.region(), ReadRegion::Intron);
+        assert_eq!(intron.genes(), vec!["Coding"]);
+        assert_eq!(annotate("chr01:290-320(+)").region(), ReadRegion::Intron);
+    }
+
+    #[test]
+    fn annotate_strand() {
+        let antisense = annotate("chr01:830-860(+)");
+        assert_eq!(antisense.region(), ReadRegion::Antisense);
+        assert_eq!(antisense.genes(), vec!["RevCoding"]);
+        assert_eq!(annotate("chr01:850-880(-)").region(), ReadRegion::Cds);
+        assert_eq!(annotate("chr01:700-730(+)").region(), ReadRegion::NoGene);
+        assert_eq!(annotate("chr02:160-190(+)").genes(), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn region_stats() {
+        let mut stats = RegionStats::new();
+        stats.tally(ReadRegion::Cds);
+        stats.tally(ReadRegion::Intron);
+        stats.tally(ReadRegion::Cds);
+        stats.tally_unaligned();
+        let table = stats.table();
+        assert!(table.starts_with("region\treads\nCDS\t2\n"));
+        assert!(table.contains("Intron\t1\n"));
+        assert!(table.ends_with("NoGene\t0\nUnaligned\t1\n"));
+    }
+}
@@ -0,0 +1,378 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use failure;
+
+use rust_htslib::bam;
+use rust_htslib::bam::Read as BamRead;
+
+use bam_utils::*;
+use cli_utils::*;
+use corrupt::*;
+use timing::*;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CLI {
+    #[serde(skip_serializing)]
+    pub input: String,
+    pub output: String,
+    #[serde(rename = "range")]
+    pub ranges: Vec<String>,
+    pub other: bool,
+    pub io_threads: String,
+    #[serde(flatten)]
+    pub read_filter: ReadFilterCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+pub struct Config {
+    input: String,
+    output: PathBuf,
+    ranges: LengthRanges,
+    other: bool,
+    io_threads: usize,
+    read_filter: ReadFilter,
+    corrupt: CorruptInputs,
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        let ranges = cli
+            .ranges
+            .iter()
+            .map(|range| range.parse())
+            .collect::<Result<Vec<LengthRange>, failure::Error>>()?;
+
+        Ok(Config {
+            input: cli.input.to_string(),
+            output: Path::new(&cli.output).to_path_buf(),
+            ranges: LengthRanges::new(ranges)?,
+            other: cli.other,
+            io_threads: cli.io_threads.parse()?,
+            read_filter: ReadFilter::new_from_cli(&cli.read_filter)?,
+            corrupt: cli.corrupt.clone(),
+        })
+    }
+
+    fn output_filename(&self, suffix: &str) -> PathBuf {
+        output_filename(&self.output, suffix)
+    }
+}
+
+/// Named, inclusive range of footprint lengths, such as
+/// `monosome=26-34`, whose reads are written to their own output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LengthRange {
+    name: String,
+    minlen: usize,
+    maxlen: usize,
+}
+
+impl LengthRange {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn contains(&self, len: usize) -> bool {
+        len >= self.minlen && len <= self.maxlen
+    }
+}
+
+impl FromStr for LengthRange {
+    type Err = failure::Error;
+
+    /// Parses `NAME=MINLEN-MAXLEN`, where a single length is a range
+    /// on its own. The name becomes part of the output filename.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, lengths) = match s.find('=') {
+            Some(delim) => (&s[..delim], &s[(delim + 1)..]),
+            None => bail!("Length range \"{}\" is not NAME=MINLEN-MAXLEN", s),
+        };
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            bail!("Bad length range name \"{}\"", name);
+        }
+
+        let mut bounds = lengths.trim().splitn(2, '-');
+        let minlen: usize = bounds.next().unwrap_or("").parse()?;
+        let maxlen: usize = match bounds.next() {
+            Some(maxlen_str) => maxlen_str.parse()?,
+            None => minlen,
+        };
+        if minlen > maxlen {
+            bail!("Bad length range {}-{} for \"{}\"", minlen, maxlen, name);
+        }
+
+        Ok(LengthRange {
+            name: name.to_string(),
+            minlen: minlen,
+            maxlen: maxlen,
+        })
+    }
+}
+
+/// Non-overlapping length ranges with distinct names, so that each
+/// read belongs to at most one range.
+#[derive(Debug, Clone)]
+pub struct LengthRanges {
+    ranges: Vec<LengthRange>,
+}
+
+impl LengthRanges {
+    pub fn new(ranges: Vec<LengthRange>) -> Result<Self, failure::Error> {
+        if ranges.is_empty() {
+            bail!("No length ranges");
+        }
+
+        for (i, range) in ranges.iter().enumerate() {
+            for other in ranges[..i].iter() {
+                if range.name == other.name {
+                    bail!("Length range name \"{}\" is used twice", range.name);
+                }
+                if range.minlen <= other.maxlen && other.minlen <= range.maxlen {
+                    bail!(
+                        "Length ranges \"{}\" and \"{}\" overlap",
+                        other.name,
+                        range.name
+                    );
+                }
+            }
+        }
+
+        Ok(LengthRanges { ranges: ranges })
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &LengthRange> {
+        self.ranges.iter()
+    }
+
+    /// Returns the index of the range containing a length, if any.
+    pub fn range_index(&self, len: usize) -> Option<usize> {
+        self.ranges.iter().position(|range| range.contains(len))
+    }
+}
+
+/// Assignment of a read to a length range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthAssignment {
+    /// Rejected by the read filter
+    Filtered,
+    /// Within the length range with this index
+    Range(usize),
+    /// Outside of every length range
+    Other,
+}
+
+/// Number of reads written for each length range, along with the
+/// reads outside of every range and those rejected by the read
+/// filter.
+#[derive(Debug, Clone)]
+pub struct LengthStats {
+    filter: ReadFilterStats,
+    counts: Vec<usize>,
+    other: usize,
+}
+
+impl LengthStats {
+    pub fn new(ranges: &LengthRanges) -> Self {
+        LengthStats {
+            filter: ReadFilterStats::default(),
+            counts: vec![0; ranges.len()],
+            other: 0,
+        }
+    }
+
+    /// Tallies a record and returns its assignment, judging its
+    /// length by the length of the read sequence.
+    pub fn tally(
+        &mut self,
+        ranges: &LengthRanges,
+        filter: &ReadFilter,
+        rec: &bam::Record,
+    ) -> LengthAssignment {
+        if !self.filter.tally(filter, rec) {
+            return LengthAssignment::Filtered;
+        }
+        match ranges.range_index(rec.seq().len()) {
+            Some(i) => {
+                self.counts[i] += 1;
+                LengthAssignment::Range(i)
+            }
+            None => {
+                self.other += 1;
+                LengthAssignment::Other
+            }
+        }
+    }
+
+    pub fn filter(&self) -> &ReadFilterStats {
+        &self.filter
+    }
+
+    /// Returns a table with one row for each length range, giving the
+    /// number of reads and their fraction of the reads passing the
+    /// read filter, followed by reads of other lengths.
+    pub fn table(&self, ranges: &LengthRanges) -> String {
+        let passed = self.filter.passed.max(1) as f64;
+        let mut table = "range\tlengths\treads\tfraction\n".to_string();
+        for (range, count) in ranges.iter().zip(self.counts.iter()) {
+            table += &format!(
+                "{}\t{}-{}\t{}\t{:.4}\n",
+                range.name,
+                range.minlen,
+                range.maxlen,
+                count,
+                *count as f64 / passed
+            );
+        }
+        table += &format!(
+            "Other\tNA\t{}\t{:.4}\n",
+            self.other,
+            self.other as f64 / passed
+        );
+        table
+    }
+}
+
+pub fn run_bam_length_filter(config: Config) -> Result<(), failure::Error> {
+    let mut input = open_bam(&config.input, &config.corrupt)?;
+    set_reader_threads(&mut input, config.io_threads)?;
+
+    let header = output_header(input.header(), "bam-length-filter");
+    let mut outputs = Vec::with_capacity(config.ranges.len());
+    for range in config.ranges.iter() {
+        let mut output = bam::Writer::from_path(
+            &config.output_filename(&format!("_{}.bam", range.name())),
+            &header,
+        )?;
+        set_writer_threads(&mut output, config.io_threads)?;
+        outputs.push(output);
+    }
+    let mut other_output = if config.other {
+        let mut output = bam::Writer::from_path(&config.output_filename("_other.bam"), &header)?;
+        set_writer_threads(&mut output, config.io_threads)?;
+        Some(output)
+    } else {
+        None
+    };
+
+    let mut stats = LengthStats::new(&config.ranges);
+    {
+        let _timer = StageTimer::new("classification");
+        for recres in timed_iter("BAM read", input.records()) {
+            let rec = recres?;
+            match stats.tally(&config.ranges, &config.read_filter, &rec) {
+                LengthAssignment::Range(i) => outputs[i].write(&rec)?,
+                LengthAssignment::Other => {
+                    if let Some(ref mut output) = other_output {
+                        output.write(&rec)?;
+                    }
+                }
+                LengthAssignment::Filtered => (),
+            }
+        }
+    }
+
+    let _timer = StageTimer::new("output");
+    fs::write(
+        config.output_filename("_length_stats.txt"),
+        stats.table(&config.ranges),
+    )?;
+    fs::write(
+        config.output_filename("_filter_stats.txt"),
+        stats.filter().table(),
+    )?;
+
+    info!(
+        "Wrote {} of {} reads in {} length ranges",
+        stats.counts.iter().sum::<usize>(),
+        stats.filter().total,
+        config.ranges.len()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    #[test]
+    fn length_ranges() {
+        let range: LengthRange = "monosome=26-34".parse().unwrap();
+        assert_eq!(range.name(), "monosome");
+        assert!(range.contains(26) && range.contains(34));
+        assert!(!range.contains(35));
+        assert!("single=30".parse::<LengthRange>().unwrap().contains(30));
+        assert!("26-34".parse::<LengthRange>().is_err());
+        assert!("bad/name=26-34".parse::<LengthRange>().is_err());
+        assert!("backward=34-26".parse::<LengthRange>().is_err());
+
+        let parse = |specs: &[&str]| {
+            LengthRanges::new(specs.iter().map(|spec| spec.parse().unwrap()).collect())
+        };
+        assert!(parse(&["a=26-34", "b=30-40"]).is_err());
+        assert!(parse(&["a=26-34", "a=55-70"]).is_err());
+        assert!(parse(&[]).is_err());
+
+        let ranges = parse(&["monosome=26-34", "disome=55-70", "short=19-24"]).unwrap();
+        assert_eq!(ranges.range_index(28), Some(0));
+        assert_eq!(ranges.range_index(19), Some(2));
+        assert_eq!(ranges.range_index(40), None);
+
+        let record = |len: usize, flags: u16| {
+            let mut rec = bam::Record::new();
+            rec.set(
+                b"read",
+                &CigarString(vec![Cigar::Match(len as u32)]),
+                &vec![b'A'; len],
+                &vec![30; len],
+            );
+            rec.set_flags(flags);
+            rec
+        };
+        let filter = ReadFilter::new().exclude_flags(0x4);
+        let mut stats = LengthStats::new(&ranges);
+        assert_eq!(
+            stats.tally(&ranges, &filter, &record(30, 0)),
+            LengthAssignment::Range(0)
+        );
+        assert_eq!(
+            stats.tally(&ranges, &filter, &record(60, 0)),
+            LengthAssignment::Range(1)
+        );
+        assert_eq!(
+            stats.tally(&ranges, &filter, &record(40, 0)),
+            LengthAssignment::Other
+        );
+        assert_eq!(
+            stats.tally(&ranges, &filter, &record(30, 0x4)),
+            LengthAssignment::Filtered
+        );
+        assert_eq!(stats.filter().passed, 3);
+        assert_eq!(
+            stats.table(&ranges),
+            "range\tlengths\treads\tfraction\n\
+             monosome\t26-34\t1\t0.3333\n\
+             disome\t55-70\t1\t0.3333\n\
+             short\t19-24\t0\t0.0000\n\
+             Other\tNA\t1\t0.3333\n"
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BinaryHeap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use failure;
+
+use rust_htslib::bam;
+use rust_htslib::bam::Read as BamRead;
+
+use bam_utils::*;
+use corrupt::*;
+use timing::*;
+use umi::*;
+
+/// Second read of a pair, which is kept or dropped along with its
+/// mate but not counted towards the target.
+const READ2_FLAG: u16 = 0x80;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CLI {
+    #[serde(skip_serializing)]
+    pub input: String,
+    pub output: String,
+    pub reads: Option<String>,
+    pub molecules: Option<String>,
+    pub seed: String,
+    pub io_threads: String,
+    #[serde(flatten)]
+    pub umi_config: UmiCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+pub struct Config {
+    input: String,
+    output: String,
+    target: SubsampleTarget,
+    seed: u64,
+    io_threads: usize,
+    umi_config: UmiConfig,
+    corrupt: CorruptInputs,
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        if cli.input == "-" {
+            bail!("Subsampling reads the input twice and cannot read from stdin");
+        }
+
+        let target = match (&cli.reads, &cli.molecules) {
+            (Some(reads), None) => SubsampleTarget::Reads(reads.parse()?),
+            (None, Some(molecules)) => SubsampleTarget::Molecules(molecules.parse()?),
+            _ => bail!("Expecting exactly one of a target read or molecule count"),
+        };
+        if target.count() == 0 {
+            bail!("Target {} count must be positive", target.unit_name());
+        }
+
+        Ok(Config {
+            input: cli.input.to_string(),
+            output: cli.output.to_string(),
+            target: target,
+            seed: cli.seed.parse()?,
+            io_threads: cli.io_threads.parse()?,
+            umi_config: UmiConfig::new_from_cli(&cli.umi_config)?,
+            corrupt: cli.corrupt.clone(),
+        })
+    }
+}
+
+/// Number of reads, or of unique molecules, to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubsampleTarget {
+    /// Reads, keeping every alignment of a selected read
+    Reads(usize),
+    /// Unique molecules, keeping every duplicate of a selected
+    /// molecule
+    Molecules(usize),
+}
+
+impl SubsampleTarget {
+    pub fn count(&self) -> usize {
+        match *self {
+            SubsampleTarget::Reads(n) | SubsampleTarget::Molecules(n) => n,
+        }
+    }
+
+    pub fn unit_name(&self) -> &'static str {
+        match self {
+            SubsampleTarget::Reads(_) => "reads",
+            SubsampleTarget::Molecules(_) => "molecules",
+        }
+    }
+
+    /// Returns the pseudo-random key of the read or molecule that a
+    /// record belongs to, so that all of its records are kept or
+    /// dropped together. A molecule is identified by its UMI along
+    /// with the alignment position, strand, and CIGAR, matching
+    /// `bam-suppress-duplicates`; a record with no UMI, or a UMI that
+    /// cannot be corrected, is a molecule of its own. Keys depend only
+    /// on `seed` and the record, and not on the order of records.
+    pub fn unit_key(&self, seed: u64, umi_config: &UmiConfig, rec: &bam::Record) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        seed.hash(&mut hasher);
+        match (self, umi_config.record_umi(rec)) {
+            (SubsampleTarget::Molecules(_), Some(umi)) => {
+                rec.tid().hash(&mut hasher);
+                rec.pos().hash(&mut hasher);
+                rec.is_reverse().hash(&mut hasher);
+                rec.raw_cigar().hash(&mut hasher);
+                umi.hash(&mut hasher);
+            }
+            _ => rec.qname().hash(&mut hasher),
+        }
+        hasher.finish()
+    }
+}
+
+/// Selection of a fixed number of units, e.g., reads or molecules, as
+/// those with the smallest pseudo-random keys.
+#[derive(Debug, Clone)]
+pub struct KeySelection {
+    target: usize,
+    smallest: BinaryHeap<u64>,
+    total: usize,
+}
+
+impl KeySelection {
+    pub fn new(target: usize) -> Self {
+        KeySelection {
+            target: target,
+            smallest: BinaryHeap::new(),
+            total: 0,
+        }
+    }
+
+    /// Adds the key of one distinct unit.
+    pub fn add(&mut self, key: u64) {
+        self.total += 1;
+        self.smallest.push(key);
+        if self.smallest.len() > self.target {
+            self.smallest.pop();
+        }
+    }
+
+    /// Returns the number of units added.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Returns the number of units selected.
+    pub fn selected(&self) -> usize {
+        self.total.min(self.target)
+    }
+
+    /// Returns the largest key selected, or `None` when every unit is
+    /// selected.
+    pub fn threshold(&self) -> Option<u64> {
+        if self.total <= self.target {
+            None
+        } else {
+            self.smallest.peek().cloned()
+        }
+    }
+}
+
+fn open_input(config: &Config) -> Result<bam::Reader, failure::Error> {
+    let mut input = open_bam(&config.input, &config.corrupt)?;
+    set_reader_threads(&mut input, config.io_threads)?;
+    Ok(input)
+}
+
+/// Finds the keys of the reads or molecules to keep in a first pass
+/// through the input. Molecules are counted once at each location,
+/// which requires a coordinate-sorted input.
+fn select_units(config: &Config) -> Result<KeySelection, failure::Error> {
+    let _timer = StageTimer::new("classification");
+    let mut input = open_input(config)?;
+    let mut selection = KeySelection::new(config.target.count());
+
+    let mut location = (-1, -1);
+    let mut location_keys = HashSet::new();
+
+    while let Some(rec) = timed("BAM read", || read_record(&mut input, &config.corrupt))? {
+        if rec.flags() & (NON_PRIMARY_FLAGS | READ2_FLAG) != 0 {
+            continue;
+        }
+
+        let key = config
+            .target
+            .unit_key(config.seed, &config.umi_config, &rec);
+        match config.target {
+            SubsampleTarget::Reads(_) => selection.add(key),
+            SubsampleTarget::Molecules(_) => {
+                if (rec.tid(), rec.pos()) != location {
+                    if rec.tid() == location.0 && rec.pos() < location.1 {
+                        return Err(UnsortedError(format!(
+                            "Subsampling molecules requires a coordinate-sorted input, but {} is out of order",
+                            String::from_utf8_lossy(rec.qname())
+                        ))
+                        .into());
+                    }
+                    location = (rec.tid(), rec.pos());
+                    location_keys.clear();
+                }
+                if location_keys.insert(key) {
+                    selection.add(key);
+                }
+            }
+        }
+    }
+
+    Ok(selection)
+}
+
+pub fn run_bam_subsample(config: Config) -> Result<(), failure::Error> {
+    let selection = select_units(&config)?;
+    let unit_name = config.target.unit_name();
+
+    if selection.threshold().is_none() {
+        warn!(
+            "Input has {} {}, no more than the target of {}, and all are kept",
+            selection.total(),
+            unit_name,
+            config.target.count()
+        );
+    }
+
+    let _timer = StageTimer::new("output");
+    let mut input = open_input(&config)?;
+    let header = output_header(input.header(), "bam-subsample");
+    let mut output = if config.output == "-" {
+        bam::Writer::from_stdout(&header)?
+    } else {
+        bam::Writer::from_path(Path::new(&config.output), &header)?
+    };
+    set_writer_threads(&mut output, config.io_threads)?;
+
+    let mut n_records = 0;
+    let mut n_kept = 0;
+    while let Some(rec) = timed("BAM read", || read_record(&mut input, &config.corrupt))? {
+        n_records += 1;
+        let keep = match selection.threshold() {
+            Some(threshold) => {
+                config
+                    .target
+                    .unit_key(config.seed, &config.umi_config, &rec)
+                    <= threshold
+            }
+            None => true,
+        };
+        if keep {
+            output.write(&rec)?;
+            n_kept += 1;
+        }
+    }
+
+    info!(
+        "Kept {} of {} {} ({} of {} alignments)",
+        selection.selected(),
+        selection.total(),
+        unit_name,
+        n_kept,
+        n_records
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_htslib::bam::record::{Aux, Cigar, CigarString};
+
+    #[test]
+    fn subsample_keys() {
+        let mut selection = KeySelection::new(3);
+        for key in [7, 2, 9, 4, 1, 8].iter() {
+            selection.add(*key);
+        }
+        assert_eq!(selection.total(), 6);
+        assert_eq!(selection.selected(), 3);
+        assert_eq!(selection.threshold(), Some(4));
+
+        let mut all = KeySelection::new(10);
+        all.add(5);
+        assert_eq!(all.threshold(), None);
+
+        let record = |qname: &[u8], pos: i32| {
+            let mut rec = bam::Record::new();
+            rec.set(
+                qname,
+                &CigarString(vec![Cigar::Match(4)]),
+                b"ACGT",
+                &[30; 4],
+            );
+            rec.set_pos(pos);
+            rec
+        };
+
+        let umis = UmiConfig::new();
+        let molecules = SubsampleTarget::Molecules(10);
+        let reads = SubsampleTarget::Reads(10);
+        let dup_a = record(b"read1#ACGT", 100);
+        let dup_b = record(b"read2#ACGT", 100);
+        let other_umi = record(b"read3#TTTT", 100);
+        let other_pos = record(b"read4#ACGT", 101);
+        assert_eq!(
+            molecules.unit_key(0, &umis, &dup_a),
+            molecules.unit_key(0, &umis, &dup_b)
+        );
+        assert!(molecules.unit_key(0, &umis, &dup_a) != molecules.unit_key(0, &umis, &other_umi));
+        assert!(molecules.unit_key(0, &umis, &dup_a) != molecules.unit_key(0, &umis, &other_pos));
+        assert!(molecules.unit_key(0, &umis, &dup_a) != molecules.unit_key(1, &umis, &dup_a));
+        assert!(reads.unit_key(0, &umis, &dup_a) != reads.unit_key(0, &umis, &dup_b));
+
+        let mut tagged = record(b"read5", 100);
+        tagged.push_aux(b"RX", &Aux::String(b"ACGT")).unwrap();
+        assert_eq!(
+            molecules.unit_key(0, &umis, &tagged),
+            molecules.unit_key(0, &umis, &dup_a)
+        );
+    }
+}
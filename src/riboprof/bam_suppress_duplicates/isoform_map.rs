@@ -0,0 +1,297 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use failure;
+
+use bio_types::annot::pos::Pos;
+use bio_types::annot::spliced::Spliced;
+use bio_types::strand::ReqStrand;
+use rust_htslib::bam;
+
+use bam_utils::*;
+use corrupt::*;
+use timing::*;
+use transcript::*;
+
+/// Site of an alignment for grouping duplicates. Alignments to an
+/// annotated transcript are placed at a gene-level position, so that
+/// the same molecule aligned to different isoforms of one gene falls
+/// on one site. Other alignments stay at their position on the target.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SiteKey {
+    Gene {
+        gene: usize,
+        pos: isize,
+        reverse: bool,
+    },
+    Target {
+        tid: i32,
+        pos: i32,
+        reverse: bool,
+    },
+}
+
+/// Placement of one BAM target sequence, a transcript, within its gene.
+struct IsoformTarget {
+    gene: usize,
+    loc: Spliced<Rc<String>, ReqStrand>,
+}
+
+/// Reads a tab-delimited table of transcript names and their gene
+/// names, skipping blank lines and `#` comments.
+pub fn read_isoform_genes<P: AsRef<Path>>(
+    path: P,
+) -> Result<HashMap<String, String>, failure::Error> {
+    parse_isoform_genes(&fs::read_to_string(path)?)
+}
+
+fn parse_isoform_genes(text: &str) -> Result<HashMap<String, String>, failure::Error> {
+    let mut isoform_genes = HashMap::new();
+    for line in text.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        match (fields.next(), fields.next()) {
+            (Some(trxname), Some(gene)) if !gene.is_empty() => {
+                isoform_genes.insert(trxname.to_string(), gene.trim().to_string());
+            }
+            _ => bail!(
+                "Bad isoform map line \"{}\", expecting TRANSCRIPT<TAB>GENE",
+                line
+            ),
+        }
+    }
+    Ok(isoform_genes)
+}
+
+/// Projection of alignments to transcript sequences onto gene-level
+/// positions, using an annotation of each transcript within the
+/// genome. Positions on isoforms of one gene that are the same
+/// genomic position are the same gene-level position.
+pub struct IsoformMap {
+    targets: Vec<Option<IsoformTarget>>,
+    genes: usize,
+}
+
+impl IsoformMap {
+    /// Matches the target sequences of a BAM header against the
+    /// annotated transcripts by name. The gene of each transcript is
+    /// taken from `isoform_genes`, when it is listed there, and
+    /// otherwise from the annotation. Targets that are not annotated
+    /// transcripts are never projected.
+    pub fn new(
+        trxome: &Transcriptome<Rc<String>>,
+        isoform_genes: &HashMap<String, String>,
+        header: &bam::HeaderView,
+    ) -> Self {
+        let mut gene_indices: HashMap<&str, usize> = HashMap::new();
+        let trxs: HashMap<&str, &Transcript<Rc<String>>> = trxome
+            .transcripts()
+            .map(|trx| (trx.trxname(), trx))
+            .collect();
+
+        let targets: Vec<Option<IsoformTarget>> = header
+            .target_names()
+            .iter()
+            .map(|name| {
+                let trx = trxs.get(String::from_utf8_lossy(name).as_ref())?;
+                let gene_name = isoform_genes
+                    .get(trx.trxname())
+                    .map_or(trx.gene(), String::as_str);
+                let ngenes = gene_indices.len();
+                let gene = *gene_indices.entry(gene_name).or_insert(ngenes);
+                Some(IsoformTarget {
+                    gene: gene,
+                    loc: trx.loc().clone(),
+                })
+            })
+            .collect();
+
+        let nmapped = targets.iter().filter(|target| target.is_some()).count();
+        if nmapped < targets.len() {
+            warn!(
+                "{} of {} target sequences are not annotated transcripts and are not projected onto genes",
+                targets.len() - nmapped,
+                targets.len()
+            );
+        }
+
+        IsoformMap {
+            targets: targets,
+            genes: gene_indices.len(),
+        }
+    }
+
+    /// Returns the number of genes with at least one target sequence.
+    pub fn genes(&self) -> usize {
+        self.genes
+    }
+
+    /// Returns the site of an alignment, projecting its leftmost
+    /// position on the transcript into the genome.
+    pub fn site_key(&self, rec: &bam::Record) -> SiteKey {
+        let target = if rec.tid() >= 0 {
+            self.targets
+                .get(rec.tid() as usize)
+                .and_then(Option::as_ref)
+        } else {
+            None
+        };
+
+        target
+            .and_then(|target| {
+                let trx_pos = Pos::new((), rec.pos() as isize, ReqStrand::Forward);
+                let genome_pos = target.loc.pos_outof(&trx_pos)?;
+                Some(SiteKey::Gene {
+                    gene: target.gene,
+                    pos: genome_pos.pos(),
+                    reverse: rec.is_reverse(),
+                })
+            })
+            .unwrap_or(SiteKey::Target {
+                tid: rec.tid(),
+                pos: rec.pos(),
+                reverse: rec.is_reverse(),
+            })
+    }
+
+    /// Reads every alignment from `input`, which need not be sorted,
+    /// and groups alignments at the same site.
+    pub fn site_groups(
+        &self,
+        input: &mut bam::Reader,
+        corrupt: &CorruptInputs,
+    ) -> Result<Vec<Vec<bam::Record>>, failure::Error> {
+        let _timer = StageTimer::new("grouping");
+        let mut groups: BTreeMap<SiteKey, Vec<bam::Record>> = BTreeMap::new();
+        while let Some(rec) = read_record(input, corrupt)? {
+            groups
+                .entry(self.site_key(&rec))
+                .or_insert_with(Vec::new)
+                .push(rec);
+        }
+        Ok(groups.into_iter().map(|(_, group)| group).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bio::io::bed;
+    use bio_types::annot::refids::RefIDSet;
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    fn transcriptome_from_str(bedstr: &str) -> Transcriptome<Rc<String>> {
+        let mut refids = RefIDSet::new();
+        Transcriptome::new_from_bed(bed::Reader::new(bedstr.as_bytes()).records(), &mut refids)
+            .expect("Transcriptome from string")
+    }
+
+    fn isoform_header() -> bam::HeaderView {
+        let mut header = bam::Header::new();
+        for &(name, len) in [("TrxA", 40), ("TrxB", 40), ("TrxC", 40), ("Spike", 50)].iter() {
+            header.push_record(
+                bam::header::HeaderRecord::new(b"SQ")
+                    .push_tag(b"SN", &name)
+                    .push_tag(b"LN", &len),
+            );
+        }
+        bam::HeaderView::from_header(&header)
+    }
+
+    fn isoform_map(isoform_genes: &HashMap<String, String>) -> IsoformMap {
+        // Isoforms of one gene sharing exon [100..120), with the second
+        // exon at [200..220) or [300..320), and a reverse-strand gene
+        let trxome = transcriptome_from_str(
+            "chr01\t100\t220\tTrxA\t0\t+\t100\t220\t0\t2\t20,20,\t0,100,\n\
+             chr01\t100\t320\tTrxB\t0\t+\t100\t320\t0\t2\t20,20,\t0,200,\n\
+             chr01\t400\t440\tTrxC\t0\t-\t400\t440\t0\t1\t40,\t0,\n",
+        );
+        IsoformMap::new(&trxome, isoform_genes, &isoform_header())
+    }
+
+    fn gene_ab() -> HashMap<String, String> {
+        parse_isoform_genes("# isoforms\nTrxA\tGeneAB\nTrxB\tGeneAB\n").unwrap()
+    }
+
+    fn record(tid: i32, pos: i32, reverse: bool) -> bam::Record {
+        let mut rec = bam::Record::new();
+        rec.set(
+            b"read",
+            &CigarString(vec![Cigar::Match(10)]),
+            &[b'A'; 10],
+            &[30; 10],
+        );
+        rec.set_tid(tid);
+        rec.set_pos(pos);
+        if reverse {
+            rec.set_flags(0x10);
+        }
+        rec
+    }
+
+    #[test]
+    fn parse_isoforms() {
+        assert!(parse_isoform_genes("TrxA\n").is_err());
+        assert_eq!(gene_ab().len(), 2);
+    }
+
+    #[test]
+    fn isoform_gene_count() {
+        assert_eq!(isoform_map(&gene_ab()).genes(), 2);
+        assert_eq!(isoform_map(&HashMap::new()).genes(), 3);
+    }
+
+    #[test]
+    fn shared_exon_sites() {
+        let isoforms = isoform_map(&gene_ab());
+
+        // Shared first exon, and the first nucleotide of the second exon
+        assert_eq!(
+            isoforms.site_key(&record(0, 5, false)),
+            isoforms.site_key(&record(1, 5, false))
+        );
+        assert_ne!(
+            isoforms.site_key(&record(0, 20, false)),
+            isoforms.site_key(&record(1, 20, false))
+        );
+        assert_ne!(
+            isoforms.site_key(&record(0, 5, false)),
+            isoforms.site_key(&record(0, 5, true))
+        );
+    }
+
+    #[test]
+    fn reverse_and_target_sites() {
+        let isoforms = isoform_map(&gene_ab());
+
+        assert_eq!(
+            isoforms.site_key(&record(2, 0, false)),
+            SiteKey::Gene {
+                gene: 1,
+                pos: 439,
+                reverse: false,
+            }
+        );
+        assert_eq!(
+            isoforms.site_key(&record(3, 7, true)),
+            SiteKey::Target {
+                tid: 3,
+                pos: 7,
+                reverse: true,
+            }
+        );
+        assert_eq!(
+            isoforms.site_key(&record(1, 45, false)),
+            SiteKey::Target {
+                tid: 1,
+                pos: 45,
+                reverse: false,
+            }
+        );
+    }
+}
@@ -1,90 +1,279 @@
+use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use failure;
 
 use rust_htslib::bam;
 use rust_htslib::bam::Read as BamRead;
 
+use bio_types::annot::refids::RefIDSet;
+
+use bam_utils::*;
+use corrupt::*;
+use parallel::*;
+use timing::*;
+use transcript::*;
+use umi::*;
+
+mod isoform_map;
 mod record_class;
 mod record_group;
 mod stats;
 
+use bam_suppress_duplicates::isoform_map::*;
 use bam_suppress_duplicates::record_class::*;
 use bam_suppress_duplicates::record_group::*;
-use bam_suppress_duplicates::stats::*;
+pub use bam_suppress_duplicates::stats::*;
 
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct CLI {
+    #[serde(rename = "input")]
     pub bam_input: String,
+    #[serde(rename = "output")]
     pub bam_output: String,
+    #[serde(rename = "dups")]
     pub bam_dups: Option<String>,
     pub stats: Option<String>,
+    pub stats_json: Option<String>,
     pub annotate: bool,
+    pub header_stats: bool,
+    pub allow_untagged: bool,
+    pub transcripts: Option<String>,
+    pub isoform_map: Option<String>,
+    pub threads: String,
+    pub io_threads: String,
+    #[serde(flatten)]
+    pub umi_config: UmiCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+impl Default for CLI {
+    fn default() -> Self {
+        CLI {
+            bam_input: String::new(),
+            bam_output: String::new(),
+            bam_dups: None,
+            stats: None,
+            stats_json: None,
+            annotate: false,
+            header_stats: false,
+            allow_untagged: false,
+            transcripts: None,
+            isoform_map: None,
+            threads: "1".to_string(),
+            io_threads: "0".to_string(),
+            umi_config: UmiCLI::default(),
+            corrupt: CorruptInputs::default(),
+        }
+    }
 }
 
 pub struct Config {
     input: bam::Reader,
-    uniq_output: bam::Writer,
-    dups_output: Option<bam::Writer>,
+    input_name: String,
+    outputs: Option<DedupOutputs>,
+    output_name: String,
+    dups_name: Option<String>,
     stat_file: Option<PathBuf>,
+    stat_json_file: Option<PathBuf>,
     annotate: bool,
+    header_stats: bool,
+    allow_untagged: bool,
+    isoforms: Option<IsoformMap>,
     stats: Stats,
+    threads: usize,
+    io_threads: usize,
+    umi_config: UmiConfig,
+    corrupt: CorruptInputs,
 }
 
 const DEFAULT_NLIM: usize = 100; // ZZZ
 
 impl Config {
     pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
-        let input = if cli.bam_input == "-" {
-            bam::Reader::from_stdin()?
-        } else {
-            bam::Reader::from_path(Path::new(&cli.bam_input))?
-        };
+        let io_threads: usize = cli.io_threads.parse()?;
+
+        if cli.header_stats && cli.bam_input == "-" {
+            bail!("Header statistics require reading the input twice and cannot read from stdin");
+        }
+
+        let input = open_input(&cli.bam_input, io_threads, &cli.corrupt)?;
 
-        let header = bam::Header::from_template(input.header());
-        let uniq_out = if cli.bam_output == "-" {
-            bam::Writer::from_stdout(&header)?
+        // Outputs are opened once the header statistics are known.
+        let outputs = if cli.header_stats {
+            None
         } else {
-            bam::Writer::from_path(Path::new(&cli.bam_output), &header)?
+            let header = output_header(input.header(), "bam-suppress-duplicates");
+            Some(DedupOutputs::new(
+                &cli.bam_output,
+                cli.bam_dups.as_ref(),
+                &header,
+                io_threads,
+            )?)
         };
 
-        let dups_out = match cli.bam_dups {
+        let mut umi_config = UmiConfig::new_from_cli(&cli.umi_config)?;
+        if cli.umi_config.umi_source.is_none() && cli.bam_input != "-" {
+            let source = probe_umi_source(&cli.bam_input, cli.allow_untagged)?;
+            if let Some(source) = source {
+                umi_config = umi_config.source(source);
+            }
+        }
+
+        let isoforms = match cli.transcripts {
+            Some(ref transcripts) => {
+                let mut refids: RefIDSet<Rc<String>> = RefIDSet::new();
+                let trxome = Transcriptome::new_from_file(transcripts, &mut refids)?;
+                let isoform_genes = match cli.isoform_map {
+                    Some(ref isoform_map) => read_isoform_genes(isoform_map)?,
+                    None => HashMap::new(),
+                };
+                let isoforms = IsoformMap::new(&trxome, &isoform_genes, input.header());
+                info!(
+                    "Grouping alignments to transcripts by position within {} genes",
+                    isoforms.genes()
+                );
+                Some(isoforms)
+            }
+            None if cli.isoform_map.is_some() => {
+                bail!("An isoform map requires a transcript annotation")
+            }
             None => None,
-            Some(ref dups_file) => Some(bam::Writer::from_path(Path::new(&dups_file), &header)?),
         };
 
-        let stats = Stats::new(DEFAULT_NLIM);
+        let stats = new_stats(&umi_config);
 
         Ok(Config {
             input: input,
-            uniq_output: uniq_out,
-            dups_output: dups_out,
+            input_name: cli.bam_input.to_string(),
+            outputs: outputs,
+            output_name: cli.bam_output.to_string(),
+            dups_name: cli.bam_dups.clone(),
             stat_file: cli.stats.as_ref().map(|s| Path::new(&s).to_path_buf()),
+            stat_json_file: cli.stats_json.as_ref().map(|s| Path::new(&s).to_path_buf()),
             annotate: cli.annotate,
+            header_stats: cli.header_stats,
+            allow_untagged: cli.allow_untagged,
+            isoforms: isoforms,
             stats: stats,
+            threads: cli.threads.parse()?,
+            io_threads: io_threads,
+            umi_config: umi_config,
+            corrupt: cli.corrupt.clone(),
         })
     }
+
+    /// Returns a description of how duplicates are identified, for the
+    /// header statistics.
+    fn method(&self) -> String {
+        format!("umi-{}-position-strand-cigar", self.umi_config.get_source())
+    }
 }
 
-pub fn read_tag(r1: &bam::Record) -> Option<&[u8]> {
-    if let Some(delim_pos) = r1.qname().iter().position(|&ch| ch == b'#') {
-        Some(r1.qname().split_at(delim_pos + 1).1)
-    } else {
-        None
+/// Returns empty duplicate statistics, which estimate UMI collisions
+/// among the whitelisted UMIs when there is a whitelist.
+fn new_stats(umi_config: &UmiConfig) -> Stats {
+    match umi_config.get_whitelist() {
+        Some(whitelist) => Stats::new(DEFAULT_NLIM).umi_whitelist(whitelist.len()),
+        None => Stats::new(DEFAULT_NLIM),
+    }
+}
+
+/// Detects where UMIs are recorded from the first records of `input`,
+/// returning `None` for an empty input or, when `allow_untagged` is
+/// set, an input with no UMIs.
+///
+/// # Errors
+///
+/// An error variant is returned when no UMIs are found, unless
+/// `allow_untagged` is set, since every alignment would then pass
+/// through as untagged.
+fn probe_umi_source(
+    input: &str,
+    allow_untagged: bool,
+) -> Result<Option<UmiSource>, failure::Error> {
+    let mut reader = bam::Reader::from_path(Path::new(input))?;
+    let probe = UmiProbe::new_from_records(reader.records(), UMI_PROBE_RECORDS)?;
+    info!("{}", probe);
+
+    match probe.source() {
+        Some(source) => {
+            info!("Reading UMIs from the {} source", source);
+            Ok(Some(source))
+        }
+        None if probe.records() == 0 || allow_untagged => {
+            warn!("No UMIs found, and no duplicates will be suppressed");
+            Ok(None)
+        }
+        None => bail!(
+            "No UMIs found in the first {} records of {}, expecting an RX tag or a \"#\" read name suffix; use --allow-untagged to pass every alignment through",
+            probe.records(),
+            input
+        ),
     }
 }
 
-// N.B. No read tag => never a duplicate!
-pub fn same_tag(r0: &bam::Record, r1: &bam::Record) -> bool {
-    if let Some(tag0) = read_tag(r0) {
-        if let Some(tag1) = read_tag(r1) {
-            (tag0 == tag1)
+fn open_input(
+    input: &str,
+    io_threads: usize,
+    corrupt: &CorruptInputs,
+) -> Result<bam::Reader, failure::Error> {
+    let mut input = open_bam(input, corrupt)?;
+    set_reader_threads(&mut input, io_threads)?;
+    Ok(input)
+}
+
+/// BAM files of unique alignments and, optionally, of duplicates.
+struct DedupOutputs {
+    uniq: bam::Writer,
+    dups: Option<bam::Writer>,
+}
+
+impl DedupOutputs {
+    fn new(
+        uniq_name: &str,
+        dups_name: Option<&String>,
+        header: &bam::Header,
+        io_threads: usize,
+    ) -> Result<Self, failure::Error> {
+        let mut uniq = if uniq_name == "-" {
+            bam::Writer::from_stdout(header)?
         } else {
-            false
+            bam::Writer::from_path(Path::new(uniq_name), header)?
+        };
+        set_writer_threads(&mut uniq, io_threads)?;
+
+        let dups = match dups_name {
+            None => None,
+            Some(dups_name) => {
+                let mut dups = bam::Writer::from_path(Path::new(dups_name), header)?;
+                set_writer_threads(&mut dups, io_threads)?;
+                Some(dups)
+            }
+        };
+
+        Ok(DedupOutputs {
+            uniq: uniq,
+            dups: dups,
+        })
+    }
+
+    fn write_batch(&mut self, batch: &DedupBatch) -> Result<(), failure::Error> {
+        let _timer = StageTimer::new("output");
+        for uniq in batch.uniq.iter() {
+            self.uniq.write(uniq)?;
+        }
+        if let Some(ref mut out) = self.dups.as_mut() {
+            for dup in batch.dups.iter() {
+                out.write(dup)?;
+            }
         }
-    } else {
-        false
+        Ok(())
     }
 }
 
@@ -92,69 +281,266 @@ pub fn same_cigar(r0: &bam::Record, r1: &bam::Record) -> bool {
     r0.raw_cigar() == r1.raw_cigar()
 }
 
-pub fn bam_suppress_duplicates(mut config: Config) -> Result<(), failure::Error> {
-    let loc_groups = RecordGroups::new_by_location(&mut config.input)?;
+/// Unique and duplicate alignments, along with duplicate statistics,
+/// for one batch of location groups, computed on a worker thread.
+struct DedupBatch {
+    uniq: Vec<bam::Record>,
+    dups: Vec<bam::Record>,
+    stats: Stats,
+}
+
+/// Suppresses duplicates within each location group of a batch,
+/// keeping the duplicates themselves only when `keep_dups` is set.
+/// Records with no UMI, or with a UMI that cannot be corrected, are
+/// never duplicates.
+fn dedup_batch(
+    loc_groups: Vec<Vec<bam::Record>>,
+    umi_config: &UmiConfig,
+    annotate: bool,
+    keep_dups: bool,
+) -> Result<DedupBatch, failure::Error> {
+    let same_umi = |r0: &bam::Record, r1: &bam::Record| umi_config.same_umi(r0, r1);
 
-    for loc_group_res in loc_groups {
-        let loc_group = loc_group_res?;
+    let mut batch = DedupBatch {
+        uniq: Vec::new(),
+        dups: Vec::new(),
+        stats: new_stats(umi_config),
+    };
+
+    for loc_group in loc_groups {
         let mut cigar_classes = RecordClass::new(&same_cigar);
         cigar_classes.insert_all(loc_group.into_iter());
         for cigar_class in cigar_classes.classes() {
-            let mut tag_classes = RecordClass::new(&same_tag);
+            let mut tag_classes = RecordClass::new(&same_umi);
             tag_classes.insert_all(cigar_class.into_iter());
 
             let mut n_total = 0;
             let mut n_unique = 0;
 
-            for mut tag_class in tag_classes.classes() {
-                if read_tag(tag_class.first().unwrap()).is_none() {
-                    assert!(tag_class.len() == 1);
-                    config.uniq_output.write(tag_class.first().unwrap())?;
-                    config.stats.tally_untagged();
-                } else {
+            for tag_class in tag_classes.classes() {
+                if let Some(umi) = umi_config.record_umi(tag_class.first().unwrap()) {
+                    batch.stats.tally_umi(&umi);
+
                     let tag_class_len = tag_class.len();
                     n_total += tag_class_len;
                     n_unique += 1;
 
-                    let (mut uniq, dups) = tag_class.split_first_mut().unwrap();
+                    let mut tag_class_iter = tag_class.into_iter();
+                    let mut uniq = tag_class_iter.next().unwrap();
 
-                    if config.annotate && tag_class_len > 1 {
-                        uniq.push_aux(b"ZD", &bam::record::Aux::Integer(tag_class_len as i64))?;
+                    if annotate && tag_class_len > 1 {
+                        set_aux_int(&mut uniq, b"ZD", tag_class_len as i64)?;
                     }
 
-                    config.uniq_output.write(uniq)?;
-                    if let Some(ref mut out) = config.dups_output.as_mut() {
-                        for dup in dups {
-                            out.write(dup)?;
-                        }
+                    batch.uniq.push(uniq);
+                    if keep_dups {
+                        batch.dups.extend(tag_class_iter);
                     }
+                } else {
+                    assert!(tag_class.len() == 1);
+                    batch.uniq.extend(tag_class.into_iter());
+                    batch.stats.tally_untagged();
+                }
+            }
+
+            if n_total > 0 {
+                batch.stats.tally(n_total, n_unique);
+            }
+        }
+    }
+
+    Ok(batch)
+}
+
+/// Suppresses duplicates in `input`, writing the unique alignments and
+/// duplicates to `outputs` when given, and tallying duplicate
+/// statistics into `stats`. With `isoforms`, alignments to transcript
+/// sequences are grouped by gene-level position, and outputs are
+/// sorted only once every alignment has been grouped.
+fn dedup_pass(
+    parallel: &Parallel,
+    input: &mut bam::Reader,
+    mut outputs: Option<&mut DedupOutputs>,
+    stats: &mut Stats,
+    umi_config: &UmiConfig,
+    isoforms: Option<&IsoformMap>,
+    annotate: bool,
+    corrupt: &CorruptInputs,
+) -> Result<(), failure::Error> {
+    let keep_dups = outputs.as_ref().map_or(false, |outputs| outputs.dups.is_some());
+
+    if let Some(isoforms) = isoforms {
+        let site_groups = isoforms.site_groups(input, corrupt)?;
+
+        let _timer = StageTimer::new("classification");
+        let mut sorted = DedupBatch {
+            uniq: Vec::new(),
+            dups: Vec::new(),
+            stats: new_stats(umi_config),
+        };
+        parallel.map_batches(
+            site_groups.into_iter().map(Ok::<_, failure::Error>),
+            |site_groups| dedup_batch(site_groups, umi_config, annotate, keep_dups),
+            |batch| {
+                if outputs.is_some() {
+                    sorted.uniq.extend(batch.uniq);
+                    sorted.dups.extend(batch.dups);
                 }
+                stats.merge(&batch.stats)
+            },
+        )?;
+
+        if let Some(outputs) = outputs {
+            sorted.uniq.sort_by(RecordGroups::cmp_location);
+            sorted.dups.sort_by(RecordGroups::cmp_location);
+            outputs.write_batch(&sorted)?;
+        }
+        return Ok(());
+    }
 
-                config.stats.tally(n_total, n_unique);
+    let _timer = StageTimer::new("classification");
+    parallel.map_batches(
+        RecordGroups::new_by_location(input, corrupt)?,
+        |loc_groups| dedup_batch(loc_groups, umi_config, annotate, keep_dups),
+        |batch| {
+            if let Some(ref mut outputs) = outputs {
+                outputs.write_batch(&batch)?;
             }
+            stats.merge(&batch.stats)
+        },
+    )
+}
+
+pub fn bam_suppress_duplicates(mut config: Config) -> Result<(), failure::Error> {
+    let parallel = Parallel::new(config.threads)?;
+
+    if config.header_stats {
+        // A first pass finds the statistics for the output header,
+        // and the second pass writes the outputs.
+        let mut header_stats = new_stats(&config.umi_config);
+        dedup_pass(
+            &parallel,
+            &mut config.input,
+            None,
+            &mut header_stats,
+            &config.umi_config,
+            config.isoforms.as_ref(),
+            false,
+            &config.corrupt,
+        )?;
+
+        config.input = open_input(&config.input_name, config.io_threads, &config.corrupt)?;
+        let mut header = output_header(config.input.header(), "bam-suppress-duplicates");
+        for comment in header_stats.header_comments(&config.method()) {
+            header.push_comment(comment.as_bytes());
         }
+        config.outputs = Some(DedupOutputs::new(
+            &config.output_name,
+            config.dups_name.as_ref(),
+            &header,
+            config.io_threads,
+        )?);
     }
 
+    {
+        let Config {
+            ref mut input,
+            ref mut outputs,
+            ref mut stats,
+            ref umi_config,
+            ref isoforms,
+            annotate,
+            ref corrupt,
+            ..
+        } = config;
+        dedup_pass(
+            &parallel,
+            input,
+            outputs.as_mut(),
+            stats,
+            umi_config,
+            isoforms.as_ref(),
+            annotate,
+            corrupt,
+        )?;
+    }
+
+    let _timer = StageTimer::new("output");
     if let Some(ref stats_file) = config.stat_file {
         let mut stats_out = fs::File::create(stats_file)?;
         stats_out.write_all(config.stats.dedup_table().as_bytes())?;
     }
 
-    eprintln!(
+    if let Some(ref stats_json_file) = config.stat_json_file {
+        fs::write(stats_json_file, config.stats.to_json()?)?;
+    }
+
+    if config.stats.total_reads() == 0
+        && config.stats.untagged_reads() > 0
+        && !config.allow_untagged
+    {
+        bail!(
+            "No alignments had UMIs, and {} untagged alignments were passed through; use --allow-untagged to accept this",
+            config.stats.untagged_reads()
+        );
+    }
+
+    info!(
         "Processed {} tagged alignments at {} distinct sites, plus {} untagged alignments",
         config.stats.total_reads(),
         config.stats.total_sites(),
         config.stats.untagged_reads()
     );
-    eprintln!(
+    info!(
         "Suppressed {} duplicates at {} distinct sites",
         config.stats.dupl_reads(),
         config.stats.dupl_sites()
     );
-    eprintln!(
+    info!(
         "{:>4.1}% unique",
         100.0 * (config.stats.unique_reads() as f64) / (config.stats.total_reads() as f64)
     );
+    match config.stats.corrected_unique_reads() {
+        Some(corrected) => info!(
+            "{:.1} distinct molecules estimated after correcting for UMI collisions",
+            corrected
+        ),
+        None => warn!("UMIs are saturated at some sites, and UMI collisions cannot be corrected"),
+    }
+
+    Ok(())
+}
+
+/// Combines duplicate statistics saved as JSON by separate runs,
+/// writing the merged duplicate table and, optionally, the merged
+/// statistics as JSON.
+///
+/// # Arguments
+///
+/// * `inputs` are the JSON statistics files
+/// * `output` is the merged duplicate table file
+/// * `json_output` is the merged JSON statistics file
+pub fn run_merge_stats(
+    inputs: &[String],
+    output: &str,
+    json_output: Option<&str>,
+) -> Result<(), failure::Error> {
+    let mut merged: Option<Stats> = None;
+
+    for input in inputs.iter() {
+        let stats = Stats::from_json(&fs::read_to_string(input)?)?;
+        match merged {
+            Some(ref mut merged) => merged.merge(&stats)?,
+            None => merged = Some(stats),
+        }
+    }
+
+    let merged = merged.ok_or_else(|| failure::err_msg("No statistics files to merge"))?;
+
+    fs::write(output, merged.dedup_table())?;
+    if let Some(json_output) = json_output {
+        fs::write(json_output, merged.to_json()?)?;
+    }
 
     Ok(())
 }
@@ -3,12 +3,16 @@ use std::cmp::Ordering;
 use failure;
 
 use rust_htslib::bam;
-use rust_htslib::prelude::*;
+
+use bam_utils::*;
+use corrupt::*;
+use timing::*;
 
 /// Groups of records from a sorted BAM file. Record groups must be
 /// sorted in ascending order based on the grouping key.
 pub struct RecordGroups<'a> {
     bam_reader: &'a mut bam::Reader,
+    corrupt: &'a CorruptInputs,
     next_record: Option<bam::Record>,
     group_order: &'a Fn(&bam::Record, &bam::Record) -> Ordering,
 }
@@ -27,6 +31,8 @@ impl<'a> RecordGroups<'a> {
     /// 
     /// * `bam_reader` iterates over individual records.
     ///
+    /// * `corrupt` determines whether corrupt records are skipped.
+    ///
     /// # Errors
     /// 
     /// An error variant is returned when an error arises reading the
@@ -34,9 +40,11 @@ impl<'a> RecordGroups<'a> {
     pub fn new(
         group_order: &'a Fn(&bam::Record, &bam::Record) -> Ordering,
         bam_reader: &'a mut bam::Reader,
+        corrupt: &'a CorruptInputs,
     ) -> Result<Self, failure::Error> {
         let mut rg = RecordGroups {
             bam_reader: bam_reader,
+            corrupt: corrupt,
             next_record: None,
             group_order: group_order,
         };
@@ -52,21 +60,22 @@ impl<'a> RecordGroups<'a> {
     /// 
     /// * `bam_reader` iterates over individual records.
     ///
+    /// * `corrupt` determines whether corrupt records are skipped.
+    ///
     /// # Errors
     ///
     /// An error variant is returned when an error arises reading the
     /// first record from the nested `bam_reader` iterator.
-    pub fn new_by_location(bam_reader: &'a mut bam::Reader) -> Result<Self, failure::Error> {
-        Self::new(&Self::cmp_location, bam_reader)
+    pub fn new_by_location(
+        bam_reader: &'a mut bam::Reader,
+        corrupt: &'a CorruptInputs,
+    ) -> Result<Self, failure::Error> {
+        Self::new(&Self::cmp_location, bam_reader, corrupt)
     }
 
     fn read_next_record(&mut self) -> Result<Option<bam::Record>, failure::Error> {
-        let mut rec = bam::Record::new();
-        match self.bam_reader.read(&mut rec) {
-            Ok(()) => Ok(Some(rec)),
-            Err(bam::ReadError::NoMoreRecord) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
+        let _timer = StageTimer::new("BAM read");
+        read_record(self.bam_reader, self.corrupt)
     }
 
     fn read_group(&mut self, curr: bam::Record) -> Result<Vec<bam::Record>, failure::Error> {
@@ -84,11 +93,11 @@ impl<'a> RecordGroups<'a> {
                     }
                     Ordering::Equal => group.push(rec),
                     Ordering::Greater => {
-                        return Err(format_err!(
+                        return Err(UnsortedError(format!(
                             "Records out of order: {:?} > {:?}",
                             curr_ref,
                             rec
-                        ));
+                        )).into());
                     }
                 }
             } else {
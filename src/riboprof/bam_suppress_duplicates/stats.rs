@@ -1,5 +1,10 @@
-use std::fmt::Write;
+use std::collections::BTreeMap;
+use std::fmt;
 
+use failure;
+use serde_json;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Stats {
     nlim: usize,
     counts: Vec<u64>,
@@ -9,6 +14,13 @@ pub struct Stats {
     total_sites_count: u64,
     dupl_sites_count: u64,
     untagged_count: u64,
+
+    #[serde(default)]
+    site_unique_counts: BTreeMap<usize, u64>,
+    #[serde(default)]
+    umi_len: usize,
+    #[serde(default)]
+    whitelist_len: Option<usize>,
 }
 
 impl Stats {
@@ -21,6 +33,18 @@ impl Stats {
             total_sites_count: 0,
             dupl_sites_count: 0,
             untagged_count: 0,
+            site_unique_counts: BTreeMap::new(),
+            umi_len: 0,
+            whitelist_len: None,
+        }
+    }
+
+    /// Estimates UMI collisions among the `whitelist_len` UMIs of a
+    /// whitelist, rather than among every sequence of the UMI length.
+    pub fn umi_whitelist(self, whitelist_len: usize) -> Self {
+        Stats {
+            whitelist_len: Some(whitelist_len),
+            ..self
         }
     }
 
@@ -55,9 +79,72 @@ impl Stats {
         self.dupl_sites_count
     }
 
+    /// Returns the number of distinct UMIs that molecules can carry:
+    /// the size of the whitelist, when there is one, and otherwise
+    /// every sequence of the longest UMI seen. `None` is returned
+    /// when no UMIs have been seen.
+    pub fn umi_space(&self) -> Option<f64> {
+        match self.whitelist_len {
+            Some(whitelist_len) => Some(whitelist_len as f64),
+            None if self.umi_len > 0 => Some(4.0f64.powi(self.umi_len as i32)),
+            None => None,
+        }
+    }
+
+    /// Estimates the number of distinct molecules at a site with
+    /// `nunique` distinct UMIs, correcting for molecules that share a
+    /// UMI by chance. When `k` molecules draw UMIs uniformly from `M`
+    /// possible UMIs, the expected number of distinct UMIs is
+    /// `M (1 - (1 - 1/M)^k)`, which is solved for `k`.
+    ///
+    /// `None` is returned when there is no UMI space or when every
+    /// possible UMI is seen, as the number of molecules is then
+    /// unbounded.
+    pub fn collision_corrected(&self, nunique: usize) -> Option<f64> {
+        let space = self.umi_space()?;
+        if nunique == 0 {
+            Some(0.0)
+        } else if (nunique as f64) < space {
+            Some((-(nunique as f64) / space).ln_1p() / (-1.0 / space).ln_1p())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the collision-corrected estimate of distinct molecules
+    /// summed across every site, or `None` when the estimate is
+    /// unbounded at any site.
+    pub fn corrected_unique_reads(&self) -> Option<f64> {
+        let mut total = 0.0;
+        for (&nunique, &nsites) in self.site_unique_counts.iter() {
+            total += self.collision_corrected(nunique)? * nsites as f64;
+        }
+        Some(total)
+    }
+
+    /// Returns comment lines summarizing duplicate suppression, for
+    /// `@CO` records in the header of the deduplicated BAM file. The
+    /// totals include untagged alignments, which are never duplicates.
+    pub fn header_comments(&self, method: &str) -> Vec<String> {
+        let total = self.total_reads() + self.untagged_reads();
+        let unique = self.unique_reads() + self.untagged_reads();
+        let dupl_rate = if total > 0 {
+            self.dupl_reads() as f64 / total as f64
+        } else {
+            0.0
+        };
+        vec![
+            format!("bam-suppress-duplicates total={} unique={} duplicates={} untagged={}",
+                    total, unique, self.dupl_reads(), self.untagged_reads()),
+            format!("bam-suppress-duplicates duplication-rate={:.4} method={}",
+                    dupl_rate, method),
+        ]
+    }
+
     pub fn tally(&mut self, ntotal: usize, nunique: usize) {
         let idx = self.index(ntotal, nunique);
         *self.counts.get_mut(idx).unwrap() += 1;
+        *self.site_unique_counts.entry(nunique).or_insert(0) += 1;
         self.total_reads_count += ntotal as u64;
         self.unique_reads_count += nunique as u64;
         self.total_sites_count += 1;
@@ -68,22 +155,118 @@ impl Stats {
         self.untagged_count += 1;
     }
 
+    /// Notes a corrected UMI, whose length sets the number of
+    /// possible UMIs when there is no whitelist.
+    pub fn tally_umi(&mut self, umi: &[u8]) {
+        self.umi_len = self.umi_len.max(umi.len());
+    }
+
+    /// Adds the statistics from another run, e.g., on a separate
+    /// chunk of alignments.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when the two runs used different
+    /// limits on the per-site count table.
+    pub fn merge(&mut self, other: &Stats) -> Result<(), failure::Error> {
+        if self.nlim != other.nlim {
+            bail!("Cannot merge duplicate statistics with different count limits");
+        }
+
+        for (x, y) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *x += y;
+        }
+        self.total_reads_count += other.total_reads_count;
+        self.unique_reads_count += other.unique_reads_count;
+        self.total_sites_count += other.total_sites_count;
+        self.dupl_sites_count += other.dupl_sites_count;
+        self.untagged_count += other.untagged_count;
+        for (&nunique, &nsites) in other.site_unique_counts.iter() {
+            *self.site_unique_counts.entry(nunique).or_insert(0) += nsites;
+        }
+        self.umi_len = self.umi_len.max(other.umi_len);
+        self.whitelist_len = self.whitelist_len.or(other.whitelist_len);
+        Ok(())
+    }
+
+    /// Returns the statistics serialized as JSON.
+    pub fn to_json(&self) -> Result<String, failure::Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Reads statistics serialized as JSON by `to_json()`.
+    pub fn from_json(json: &str) -> Result<Self, failure::Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
     pub fn dedup_table(&self) -> String {
-        let mut table = "ttl\tuniq\tcount\n".to_string();
+        self.to_string()
+    }
+}
+
+/// Writes the table of sites by total and unique read count, with
+/// untagged reads counted as sites with no reads, along with the
+/// collision-corrected estimate of distinct molecules at each site.
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ttl\tuniq\tcount\tmolecules\n")?;
 
         if self.untagged_count > 0 {
-            write!(table, "0\t0\t{}\n", self.untagged_count).unwrap();
+            write!(f, "0\t0\t{}\tNA\n", self.untagged_count)?;
         }
 
         for ttl in 0..(self.nlim - 1) {
             for uniq in 0..(self.nlim - 1) {
                 let ct = self.counts[self.index(ttl, uniq)];
                 if ct > 0 {
-                    write!(table, "{}\t{}\t{}\n", ttl, uniq, ct).unwrap();
+                    let molecules = self
+                        .collision_corrected(uniq)
+                        .map_or("NA".to_string(), |molecules| format!("{:.2}", molecules));
+                    write!(f, "{}\t{}\t{}\t{}\n", ttl, uniq, ct, molecules)?;
                 }
             }
         }
 
-        table
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collision_corrected() {
+        let mut stats = Stats::new(10);
+        assert_eq!(stats.collision_corrected(3), None);
+
+        stats.tally_umi(b"AC");
+        stats.tally(3, 2);
+        stats.tally(1, 1);
+        stats.tally(20, 16);
+        assert_eq!(stats.umi_space(), Some(16.0));
+        assert_eq!(stats.collision_corrected(0), Some(0.0));
+        assert!((stats.collision_corrected(1).unwrap() - 1.0).abs() < 1e-9);
+        // 16 (1 - (15/16)^k) = 8 gives k = ln(1/2) / ln(15/16)
+        assert!((stats.collision_corrected(8).unwrap() - 10.7401).abs() < 1e-4);
+        assert_eq!(stats.collision_corrected(16), None);
+        assert_eq!(stats.corrected_unique_reads(), None);
+
+        let mut whitelisted = Stats::new(10).umi_whitelist(1000);
+        whitelisted.tally_umi(b"AC");
+        whitelisted.tally(3, 2);
+        whitelisted.tally(1, 1);
+        assert_eq!(whitelisted.umi_space(), Some(1000.0));
+        let corrected = whitelisted.corrected_unique_reads().unwrap();
+        assert!(corrected > 3.0 && corrected < 3.01);
+
+        stats.merge(&whitelisted).unwrap();
+        assert_eq!(stats.umi_space(), Some(1000.0));
+        assert_eq!(
+            stats.to_string(),
+            "ttl\tuniq\tcount\tmolecules\n\
+             1\t1\t2\t1.00\n\
+             3\t2\t2\t2.00\n"
+        );
     }
 }
@@ -0,0 +1,245 @@
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use failure;
+
+use bio_types::annot::loc::Loc;
+use bio_types::annot::refids::RefIDSet;
+use bio_types::strand::ReqStrand;
+use rust_htslib::bam;
+use rust_htslib::bam::Read as BamRead;
+
+use bam_utils::*;
+use cli_utils::*;
+use codon_assign::*;
+use corrupt::*;
+use normalization::*;
+use output::*;
+use timing::*;
+use track::*;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CLI {
+    #[serde(skip_serializing)]
+    pub input: String,
+    pub output: String,
+    pub site: String,
+    pub asites: Option<String>,
+    pub format: String,
+    pub lengths: String,
+    pub count_multi: bool,
+    pub frames: bool,
+    pub io_threads: String,
+    #[serde(flatten)]
+    pub read_filter: ReadFilterCLI,
+    #[serde(flatten)]
+    pub normalize_config: NormalizeCLI,
+    #[serde(flatten)]
+    pub output_config: OutputCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+pub struct Config {
+    input: String,
+    output: PathBuf,
+    site: TrackSite,
+    offsets: Option<SiteOffsets>,
+    format: TrackFormat,
+    lengths: Range<usize>,
+    count_multi: bool,
+    normalize: NormalizeConfig,
+    frames: bool,
+    io_threads: usize,
+    read_filter: ReadFilter,
+    output_config: OutputConfig,
+    corrupt: CorruptInputs,
+}
+
+/// Position of each read tallied in a genome track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackSite {
+    FivePrime,
+    ThreePrime,
+    /// A site, using per-length offsets from the 5' end
+    ASite,
+    /// Every aligned position
+    Coverage,
+}
+
+impl FromStr for TrackSite {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "5p" | "five-prime" => Ok(TrackSite::FivePrime),
+            "3p" | "three-prime" => Ok(TrackSite::ThreePrime),
+            "asite" | "a-site" => Ok(TrackSite::ASite),
+            "coverage" => Ok(TrackSite::Coverage),
+            _ => bail!("Unknown track site \"{}\"", s),
+        }
+    }
+}
+
+impl fmt::Display for TrackSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TrackSite::FivePrime => "5p",
+            TrackSite::ThreePrime => "3p",
+            TrackSite::ASite => "asite",
+            TrackSite::Coverage => "coverage",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        let site: TrackSite = cli.site.parse()?;
+        let offsets = match cli.asites {
+            Some(ref asites) => Some(SiteOffsets::new_from_file(asites)?),
+            None if site == TrackSite::ASite => bail!("A site tracks require an offsets file"),
+            None => None,
+        };
+        if cli.frames && site == TrackSite::Coverage {
+            bail!("Frame-stratified tracks require a single site for each read");
+        }
+
+        let normalize = NormalizeConfig::new_from_cli(&cli.normalize_config)?;
+        if normalize.get_units() == CountUnits::Rpkm {
+            bail!("RPKM normalization needs a feature length and is not defined for tracks");
+        }
+
+        Ok(Config {
+            input: cli.input.to_string(),
+            output: Path::new(&cli.output).to_path_buf(),
+            site: site,
+            offsets: offsets,
+            format: cli.format.parse()?,
+            lengths: parse_pair(&cli.lengths)?,
+            count_multi: cli.count_multi,
+            normalize: normalize,
+            frames: cli.frames,
+            io_threads: cli.io_threads.parse()?,
+            read_filter: ReadFilter::new_from_cli(&cli.read_filter)?
+                .exclude_flags(NON_PRIMARY_FLAGS),
+            output_config: OutputConfig::new_from_cli(&cli.output_config)?,
+            corrupt: cli.corrupt.clone(),
+        })
+    }
+
+    fn output_filename(&self, suffix: &str) -> PathBuf {
+        output_filename(&self.output, suffix)
+    }
+}
+
+pub fn run_bam_to_track(config: Config) -> Result<(), failure::Error> {
+    let mut input = open_bam(&config.input, &config.corrupt)?;
+    set_reader_threads(&mut input, config.io_threads)?;
+
+    let tids = {
+        let mut refids: RefIDSet<Rc<String>> = RefIDSet::new();
+        Tids::new(&mut refids, input.header())
+    };
+    let chrom_sizes: Vec<(String, u32)> = (0..tids.len() as u32)
+        .filter_map(|tid| Some((tids.get(tid)?.to_string(), tids.target_len(tid)?)))
+        .collect();
+
+    let classify = StageTimer::new("classification");
+    let mut track = GenomeTrack::new();
+    let mut filter_stats = ReadFilterStats::default();
+    let mut total = 0;
+    let mut mapped = 0;
+
+    for recres in spliced_records(&mut input, &tids, &config.corrupt) {
+        let (rec, fp) = recres?;
+        total += 1;
+
+        if !filter_stats.tally(&config.read_filter, &rec) {
+            continue;
+        }
+        if !rec.is_unmapped() {
+            mapped += 1;
+        }
+        if !(is_single_hit(&rec) || (config.count_multi && is_first_hit(&rec))) {
+            continue;
+        }
+
+        let fp = match fp {
+            Some(fp) => fp,
+            None => continue,
+        };
+
+        let fp_len = fp.exon_total_length();
+        if fp_len < config.lengths.start || fp_len > config.lengths.end {
+            continue;
+        }
+
+        match config.site {
+            TrackSite::FivePrime => track.tally(&fp.first_pos()),
+            TrackSite::ThreePrime => track.tally(&fp.last_pos()),
+            TrackSite::ASite => {
+                let offsets = config.offsets.as_ref().expect("A site offsets");
+                if let Some(pos) = offsets.a_site(fp, None) {
+                    track.tally(&pos);
+                }
+            }
+            TrackSite::Coverage => track.tally_spliced(&fp),
+        }
+    }
+    classify.finish();
+
+    let _timer = StageTimer::new("output");
+    let tallied = track.total();
+    let library = LibraryCounts::new(mapped as f64, tallied as f64);
+    let scale = config.normalize.scale(&library, 0);
+    let base = config.output.file_name().map_or_else(
+        || "track".to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+
+    // Frame-stratified output splits the track into one per genomic
+    // reading frame, named with an _f0, _f1, or _f2 suffix.
+    let tracks: Vec<(String, GenomeTrack)> = if config.frames {
+        (0..3)
+            .map(|frame| (format!("_f{}", frame), track.frame_track(frame)))
+            .collect()
+    } else {
+        vec![(String::new(), track)]
+    };
+
+    let strands = [(ReqStrand::Forward, "fwd"), (ReqStrand::Reverse, "rev")];
+    for (frame_name, track) in tracks.iter() {
+        for &(strand, strand_name) in strands.iter() {
+            let name = format!("{}_{}_{}{}", base, config.site, strand_name, frame_name);
+            let filename = config.output_filename(&format!(
+                "_{}{}.{}",
+                strand_name,
+                frame_name,
+                config.format.suffix()
+            ));
+            track.write_file(
+                filename,
+                &config.output_config,
+                config.format,
+                strand,
+                scale,
+                &name,
+                &chrom_sizes,
+            )?;
+        }
+    }
+
+    config.output_config.write(
+        config.output_filename("_filter_stats.txt"),
+        filter_stats.table(),
+    )?;
+
+    info!("Tallied {} of {} alignments", tallied, total);
+
+    Ok(())
+}
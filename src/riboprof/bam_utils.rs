@@ -1,4 +1,12 @@
-use std::ops::Deref;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::error;
+use std::fmt;
+use std::hash::Hash;
+use std::ops::{AddAssign, Deref, Range};
+use std::os::raw::c_char;
+use std::path::Path;
+use std::str::FromStr;
 
 use failure;
 
@@ -7,23 +15,91 @@ use bio_types::annot::spliced::Spliced;
 use bio_types::strand::ReqStrand;
 use rust_htslib::bam;
 use rust_htslib::bam::{record::Cigar, record::CigarStringView, HeaderView};
+use rust_htslib::htslib;
+
+use cli_utils::*;
+use corrupt::*;
+use timing::*;
+use transcript::*;
+
+/// Largest reference sequence length whose positions can all be
+/// stored in a BAM record, which holds 0-based positions as signed
+/// 32-bit integers.
+pub const MAX_BAM_TARGET_LEN: u32 = 1 << 31;
+
+/// Flags of secondary and supplementary alignments, which repeat a
+/// read already present in its primary alignment.
+pub const NON_PRIMARY_FLAGS: u16 = 0x900;
 
 pub struct Tids<R> {
     tids: Vec<R>,
+    lens: Vec<u32>,
+    tid_by_name: HashMap<String, u32>,
 }
 
 impl<R: Deref<Target = String> + From<String> + Clone> Tids<R> {
     pub fn new(refids: &mut RefIDSet<R>, header: &HeaderView) -> Self {
         let mut tids = Vec::with_capacity(header.target_count() as usize);
+        let mut lens = Vec::with_capacity(header.target_count() as usize);
+        let mut tid_by_name = HashMap::new();
 
         for (tid, target_name) in header.target_names().into_iter().enumerate() {
             let target_string = String::from_utf8_lossy(target_name);
             let target_rc = refids.intern(&target_string);
             assert!(tids.len() == tid);
+            let len = header.target_len(tid as u32).unwrap_or(0);
+            if len > MAX_BAM_TARGET_LEN {
+                warn!(
+                    "Reference sequence {} is {} bp, and alignments beyond position {} cannot be represented in BAM records",
+                    target_string,
+                    len,
+                    MAX_BAM_TARGET_LEN
+                );
+            }
+            tid_by_name.insert(target_string.into_owned(), tid as u32);
             tids.push(target_rc);
+            lens.push(len);
+        }
+
+        Tids {
+            tids: tids,
+            lens: lens,
+            tid_by_name: tid_by_name,
         }
+    }
 
-        Tids { tids: tids }
+    /// Returns the names of reference sequences with annotated
+    /// transcripts that are absent from the BAM header, in sorted
+    /// order. Reads cannot align to transcripts on these sequences,
+    /// which usually indicates a mismatch in chromosome naming
+    /// between the annotation and the alignments.
+    pub fn validate_against(&self, trxome: &Transcriptome<R>) -> Vec<String>
+    where
+        R: Hash + Eq,
+    {
+        let mut missing: Vec<String> = trxome
+            .ref_transcripts_iter()
+            .map(|ref_trxs| String::clone(ref_trxs.refid()))
+            .filter(|name| !self.tid_by_name.contains_key(name))
+            .collect();
+        missing.sort();
+        missing
+    }
+
+    /// Logs a warning when reference sequences with annotated
+    /// transcripts are absent from the BAM header.
+    pub fn warn_missing(&self, trxome: &Transcriptome<R>)
+    where
+        R: Hash + Eq,
+    {
+        let missing = self.validate_against(trxome);
+        if !missing.is_empty() {
+            warn!(
+                "{} annotated reference sequences are absent from the BAM header: {}",
+                missing.len(),
+                missing.join(", ")
+            );
+        }
     }
 }
 
@@ -31,12 +107,299 @@ impl<R> Tids<R> {
     pub fn get(&self, tid: u32) -> Option<&R> {
         self.tids.get(tid as usize)
     }
+
+    /// Returns the target ID of a reference sequence by name.
+    pub fn tid_of(&self, name: &str) -> Option<u32> {
+        self.tid_by_name.get(name).cloned()
+    }
+
+    /// Returns the length of a reference sequence, as given in the
+    /// BAM header.
+    pub fn target_len(&self, tid: u32) -> Option<u32> {
+        self.lens.get(tid as usize).cloned()
+    }
+
+    /// Returns the number of reference sequences.
+    pub fn len(&self) -> usize {
+        self.tids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tids.is_empty()
+    }
+}
+
+/// Opens a BAM file for reading, or standard input when `input` is
+/// `-`, after checking that a file is not truncated.
+///
+/// # Errors
+///
+/// An error variant is returned when the input cannot be opened, or
+/// when a file lacks the BGZF end-of-file marker, `corrupt` treats
+/// this as truncation, and corrupt inputs are not being skipped.
+pub fn open_bam(input: &str, corrupt: &CorruptInputs) -> Result<bam::Reader, failure::Error> {
+    if input == "-" {
+        Ok(bam::Reader::from_stdin()?)
+    } else {
+        corrupt.check_truncation(input)?;
+        Ok(bam::Reader::from_path(Path::new(input))?)
+    }
+}
+
+/// Reads the next record from `reader`, returning `None` at the end of
+/// the input. A record that cannot be read means the input is
+/// truncated or corrupt beyond recovery, and so when `corrupt` allows
+/// it to be skipped, the input ends there.
+pub fn read_record<B: bam::Read>(
+    reader: &mut B,
+    corrupt: &CorruptInputs,
+) -> Result<Option<bam::Record>, failure::Error> {
+    let mut rec = bam::Record::new();
+    match reader.read(&mut rec) {
+        Ok(()) => Ok(Some(rec)),
+        Err(bam::ReadError::NoMoreRecord) => Ok(None),
+        Err(e) => {
+            corrupt.skip(CorruptKind::Truncated, e.into())?;
+            Ok(None)
+        }
+    }
+}
+
+/// Uses `io_threads` additional threads for BGZF decompression when
+/// reading from `reader`. No threads are added when `io_threads` is
+/// 0.
+pub fn set_reader_threads(
+    reader: &mut bam::Reader,
+    io_threads: usize,
+) -> Result<(), failure::Error> {
+    if io_threads > 0 {
+        reader.set_threads(io_threads)?;
+    }
+    Ok(())
+}
+
+/// Uses `io_threads` additional threads for BGZF compression when
+/// writing to `writer`. No threads are added when `io_threads` is 0.
+pub fn set_writer_threads(
+    writer: &mut bam::Writer,
+    io_threads: usize,
+) -> Result<(), failure::Error> {
+    if io_threads > 0 {
+        writer.set_threads(io_threads)?;
+    }
+    Ok(())
+}
+
+/// Returns a header for a BAM output copied from the input `template`,
+/// with an `@PG` record for `program` appended. The record gives the
+/// version and full command line and is chained through its `PP` tag
+/// to the last program in the template, so that the provenance of the
+/// output can be traced.
+pub fn output_header(template: &HeaderView, program: &str) -> bam::Header {
+    let mut header = bam::Header::from_template(template);
+    let (id, previous) = program_chain(template.as_bytes(), program);
+    let command_line = env::args()
+        .collect::<Vec<String>>()
+        .join(" ")
+        .replace(|c: char| c == '\t' || c == '\n', " ");
+
+    let mut record = bam::header::HeaderRecord::new(b"PG");
+    record
+        .push_tag(b"ID", &id)
+        .push_tag(b"PN", &program)
+        .push_tag(b"VN", &env!("CARGO_PKG_VERSION"))
+        .push_tag(b"CL", &command_line);
+    if let Some(previous) = previous {
+        record.push_tag(b"PP", &previous);
+    }
+    header.push_record(&record);
+    header
+}
+
+/// Returns a unique `@PG` ID for `program` in the header text, along
+/// with the ID of the program it follows, i.e., the last `@PG` record
+/// that no other record names as its `PP`.
+fn program_chain(header_text: &[u8], program: &str) -> (String, Option<String>) {
+    let mut ids = Vec::new();
+    let mut previous_ids = HashSet::new();
+
+    for line in String::from_utf8_lossy(header_text).lines() {
+        let mut fields = line.split('\t');
+        if fields.next() != Some("@PG") {
+            continue;
+        }
+        for field in fields {
+            if field.starts_with("ID:") {
+                ids.push(field[3..].to_string());
+            } else if field.starts_with("PP:") {
+                previous_ids.insert(field[3..].to_string());
+            }
+        }
+    }
+
+    let previous = ids
+        .iter()
+        .rev()
+        .find(|id| !previous_ids.contains(*id))
+        .cloned();
+
+    let mut id = program.to_string();
+    let mut n = 0;
+    while ids.contains(&id) {
+        n += 1;
+        id = format!("{}.{}", program, n);
+    }
+
+    (id, previous)
+}
+
+/// Returns `true` unless the record has an `NH` tag indicating more
+/// than one alignment for the read.
+pub fn is_single_hit(rec: &bam::Record) -> bool {
+    if let Some(bam::record::Aux::Integer(nh)) = rec.aux(b"NH") {
+        nh == 1
+    } else {
+        true
+    }
+}
+
+/// Returns `true` when the record is the first of several alignments
+/// for the read, according to its `HI` tag.
+pub fn is_first_hit(rec: &bam::Record) -> bool {
+    rec.aux(b"HI") == Some(bam::record::Aux::Integer(1))
+}
+
+/// Removes an auxiliary tag from a record, returning `true` when the
+/// tag was present.
+pub fn remove_aux(rec: &mut bam::Record, tag: &[u8; 2]) -> bool {
+    // `bam_aux_get` returns a pointer into the data of the record,
+    // which `bam_aux_del` uses to remove the tag in place.
+    unsafe {
+        let aux = htslib::bam_aux_get(rec.inner, tag.as_ptr() as *const c_char);
+        if aux.is_null() {
+            false
+        } else {
+            htslib::bam_aux_del(rec.inner, aux);
+            true
+        }
+    }
+}
+
+/// Sets an auxiliary tag on a record, replacing any existing value.
+/// Unlike `push_aux`, this does not add a second copy of a tag when
+/// re-annotating records that already carry it.
+pub fn set_aux(
+    rec: &mut bam::Record,
+    tag: &[u8; 2],
+    value: &bam::record::Aux,
+) -> Result<(), failure::Error> {
+    remove_aux(rec, tag);
+    rec.push_aux(tag, value)?;
+    Ok(())
+}
+
+/// Sets an integer auxiliary tag, replacing any existing value.
+pub fn set_aux_int(rec: &mut bam::Record, tag: &[u8; 2], value: i64) -> Result<(), failure::Error> {
+    set_aux(rec, tag, &bam::record::Aux::Integer(value))
+}
+
+/// Sets a floating-point auxiliary tag, replacing any existing value.
+pub fn set_aux_float(
+    rec: &mut bam::Record,
+    tag: &[u8; 2],
+    value: f64,
+) -> Result<(), failure::Error> {
+    set_aux(rec, tag, &bam::record::Aux::Float(value))
+}
+
+/// Sets a string auxiliary tag, replacing any existing value.
+pub fn set_aux_string(
+    rec: &mut bam::Record,
+    tag: &[u8; 2],
+    value: &[u8],
+) -> Result<(), failure::Error> {
+    set_aux(rec, tag, &bam::record::Aux::String(value))
+}
+
+/// Returns the number of bases clipped, by soft or hard clipping, at
+/// the start and at the end of an alignment, on the reference
+/// strand.
+pub fn clipped_lengths(rec: &bam::Record) -> (u32, u32) {
+    let cigar = rec.cigar();
+    (clip_length(cigar.iter()), clip_length(cigar.iter().rev()))
+}
+
+/// Returns the leftmost reference position of an alignment, 0-based,
+/// extended to include any bases clipped at its start. This position
+/// lies before the start of the reference sequence when the clipped
+/// bases extend past it.
+pub fn unclipped_start(rec: &bam::Record) -> isize {
+    let (leading, _) = clipped_lengths(rec);
+    rec.pos() as isize - leading as isize
+}
+
+/// Returns the end of an alignment on the reference, 0-based and
+/// exclusive, extended to include any bases clipped at its end.
+pub fn unclipped_end(rec: &bam::Record) -> isize {
+    let (_, trailing) = clipped_lengths(rec);
+    let ref_len: u32 = rec
+        .cigar()
+        .iter()
+        .map(|op| match op {
+            Cigar::Match(len)
+            | Cigar::Del(len)
+            | Cigar::RefSkip(len)
+            | Cigar::Equal(len)
+            | Cigar::Diff(len) => *len,
+            _ => 0,
+        })
+        .sum();
+    rec.pos() as isize + ref_len as isize + trailing as isize
+}
+
+/// Returns the reference position of the 5' end of the read, 0-based,
+/// including any bases clipped at its 5' end, along with the strand
+/// of the alignment. This is the unclipped start of a forward-strand
+/// alignment and the last position before the unclipped end of a
+/// reverse-strand alignment.
+pub fn five_prime_pos(rec: &bam::Record) -> (isize, ReqStrand) {
+    if rec.is_reverse() {
+        (unclipped_end(rec) - 1, ReqStrand::Reverse)
+    } else {
+        (unclipped_start(rec), ReqStrand::Forward)
+    }
+}
+
+/// Returns the total length of the clipping operations at the start
+/// of a series of CIGAR operations.
+fn clip_length<'a, I: Iterator<Item = &'a Cigar>>(ops: I) -> u32 {
+    ops.map(|op| match op {
+        Cigar::SoftClip(len) | Cigar::HardClip(len) => Some(*len),
+        _ => None,
+    })
+    .take_while(Option::is_some)
+    .map(|len| len.unwrap_or(0))
+    .sum()
 }
 
 pub fn bam_to_spliced<R>(
     tids: &Tids<R>,
     record: &bam::Record,
 ) -> Result<Option<Spliced<R, ReqStrand>>, failure::Error>
+where
+    R: Clone,
+{
+    bam_to_spliced_with(tids, record, DeletionPolicy::Match)
+}
+
+/// Converts an alignment to a spliced location, treating deletions
+/// relative to the reference according to `deletions`. Insertions do
+/// not change the aligned reference region.
+pub fn bam_to_spliced_with<R>(
+    tids: &Tids<R>,
+    record: &bam::Record,
+    deletions: DeletionPolicy,
+) -> Result<Option<Spliced<R, ReqStrand>>, failure::Error>
 where
     R: Clone,
 {
@@ -44,12 +407,21 @@ where
         return Ok(None);
     }
 
-    let (lengths, starts) = cigar_to_lengths_starts(&record.cigar());
+    let (lengths, starts) = cigar_to_blocks(&record.cigar(), deletions);
 
     let refid = tids
         .get(record.tid() as u32)
         .ok_or_else(|| failure::err_msg(format!("BAM target ID {} out of range", record.tid())))?;
 
+    if record.pos() < 0 {
+        bail!(
+            "Alignment of {} on target ID {} has negative position {}, possibly wrapped around from beyond the largest BAM position",
+            String::from_utf8_lossy(record.qname()),
+            record.tid(),
+            record.pos()
+        );
+    }
+
     let strand = if record.is_reverse() {
         ReqStrand::Reverse
     } else {
@@ -67,32 +439,120 @@ where
     Ok(Some(spliced))
 }
 
+/// Iterator over the records of a BAM file along with their
+/// alignments as spliced locations, created by `spliced_records`.
+pub struct SplicedRecords<'a, R: 'a, B: 'a> {
+    reader: &'a mut B,
+    tids: &'a Tids<R>,
+    corrupt: &'a CorruptInputs,
+    deletions: DeletionPolicy,
+}
+
+/// Returns an iterator over the records read from `reader`, each
+/// paired with its alignment as a spliced location, or `None` when
+/// the record is unaligned.
+///
+/// # Errors
+///
+/// An error variant is yielded when a record cannot be read or when
+/// its target ID is not present in `tids`, unless `corrupt` allows
+/// corrupt inputs to be skipped. Records that cannot be converted are
+/// then skipped, and iteration ends at a record that cannot be read.
+pub fn spliced_records<'a, R, B>(
+    reader: &'a mut B,
+    tids: &'a Tids<R>,
+    corrupt: &'a CorruptInputs,
+) -> SplicedRecords<'a, R, B>
+where
+    R: Clone,
+    B: bam::Read,
+{
+    SplicedRecords {
+        reader: reader,
+        tids: tids,
+        corrupt: corrupt,
+        deletions: DeletionPolicy::Match,
+    }
+}
+
+impl<'a, R, B> SplicedRecords<'a, R, B> {
+    /// Treats deletions in the alignments according to `deletions`
+    /// rather than as matches.
+    pub fn deletions(self, deletions: DeletionPolicy) -> Self {
+        SplicedRecords {
+            deletions: deletions,
+            ..self
+        }
+    }
+}
+
+impl<'a, R, B> Iterator for SplicedRecords<'a, R, B>
+where
+    R: Clone,
+    B: bam::Read,
+{
+    type Item = Result<(bam::Record, Option<Spliced<R, ReqStrand>>), failure::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let _timer = StageTimer::new("BAM read");
+        loop {
+            let rec = match read_record(self.reader, self.corrupt) {
+                Ok(Some(rec)) => rec,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+            match bam_to_spliced_with(self.tids, &rec, self.deletions) {
+                Ok(spliced) => return Some(Ok((rec, spliced))),
+                Err(e) => {
+                    if let Err(e) = self.corrupt.skip(CorruptKind::BamRecord, e) {
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
 pub fn cigar_to_lengths_starts(cigar_string: &CigarStringView) -> (Vec<usize>, Vec<usize>) {
+    cigar_to_blocks(cigar_string, DeletionPolicy::Match)
+}
+
+/// Returns the lengths and starts, relative to the alignment start,
+/// of the aligned blocks in a series of CIGAR operations. Blocks are
+/// separated by skipped reference regions and, when `deletions` is
+/// `Gap`, by deletions.
+pub fn cigar_to_blocks(cigar: &[Cigar], deletions: DeletionPolicy) -> (Vec<usize>, Vec<usize>) {
     let mut starts = Vec::new();
     let mut lengths = Vec::new();
 
     let mut curr_start = 0;
     let mut curr_end = 0;
 
-    for cigar in cigar_string.iter() {
-        match cigar {
-            Cigar::Match(len) => curr_end += len,
-            Cigar::Ins(_) => (),
-            Cigar::Del(len) => curr_end += len,
-            Cigar::RefSkip(len) => {
-                if curr_end > curr_start {
-                    starts.push(curr_start as usize);
-                    lengths.push((curr_end - curr_start) as usize);
-                }
-                curr_start = curr_end + len;
-                curr_end = curr_start;
+    for op in cigar.iter() {
+        let gap = match op {
+            Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                curr_end += len;
+                None
             }
-            Cigar::SoftClip(_) => (),
-            Cigar::HardClip(_) => (),
-            Cigar::Pad(_) => (),
-            Cigar::Equal(len) => curr_end += len,
-            Cigar::Diff(len) => curr_end += len,
+            Cigar::Del(len) => match deletions {
+                DeletionPolicy::Match => {
+                    curr_end += len;
+                    None
+                }
+                DeletionPolicy::Gap => Some(len),
+            },
+            Cigar::RefSkip(len) => Some(len),
+            Cigar::Ins(_) | Cigar::SoftClip(_) | Cigar::HardClip(_) | Cigar::Pad(_) => None,
         };
+
+        if let Some(len) = gap {
+            if curr_end > curr_start {
+                starts.push(curr_start as usize);
+                lengths.push((curr_end - curr_start) as usize);
+            }
+            curr_start = curr_end + len;
+            curr_end = curr_start;
+        }
     }
 
     if curr_end > curr_start {
@@ -102,3 +562,859 @@ pub fn cigar_to_lengths_starts(cigar_string: &CigarStringView) -> (Vec<usize>, V
 
     (lengths, starts)
 }
+
+/// Treatment of bases deleted from the read relative to the reference
+/// when determining the reference region covered by an alignment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeletionPolicy {
+    /// Deleted bases are part of the aligned block, so that they
+    /// count toward the footprint length.
+    Match,
+    /// Deleted bases are a gap between aligned blocks, like an
+    /// intron.
+    Gap,
+}
+
+impl FromStr for DeletionPolicy {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "match" => Ok(DeletionPolicy::Match),
+            "gap" => Ok(DeletionPolicy::Gap),
+            _ => bail!("Unknown deletion policy \"{}\"", s),
+        }
+    }
+}
+
+/// Handling of alignments with insertions or deletions. The default
+/// policy accepts all alignments and treats deletions as matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndelPolicy {
+    /// Treatment of deletions in accepted alignments
+    pub deletions: DeletionPolicy,
+    /// Longest insertion or deletion tolerated, if any; alignments
+    /// with a longer indel are rejected, and a limit of 0 rejects
+    /// every alignment with an indel.
+    pub max_indel: Option<u32>,
+}
+
+impl Default for IndelPolicy {
+    fn default() -> Self {
+        IndelPolicy {
+            deletions: DeletionPolicy::Match,
+            max_indel: None,
+        }
+    }
+}
+
+impl IndelPolicy {
+    /// Returns `true` unless the alignment has an indel longer than
+    /// the maximum tolerated.
+    pub fn accepts(&self, cigar: &[Cigar]) -> bool {
+        match self.max_indel {
+            Some(max_indel) => cigar.iter().all(|op| match op {
+                Cigar::Ins(len) | Cigar::Del(len) => *len <= max_indel,
+                _ => true,
+            }),
+            None => true,
+        }
+    }
+}
+
+/// Tallies of alignments with insertions or deletions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndelStats {
+    /// Alignments with at least one insertion
+    pub insertion: usize,
+    /// Alignments with at least one deletion
+    pub deletion: usize,
+    /// Alignments rejected by the indel policy
+    pub rejected: usize,
+}
+
+impl IndelStats {
+    /// Tallies an alignment and returns `true` when it is accepted by
+    /// the indel policy.
+    pub fn tally(&mut self, policy: &IndelPolicy, cigar: &[Cigar]) -> bool {
+        if cigar.iter().any(|op| match op {
+            Cigar::Ins(_) => true,
+            _ => false,
+        }) {
+            self.insertion += 1;
+        }
+        if cigar.iter().any(|op| match op {
+            Cigar::Del(_) => true,
+            _ => false,
+        }) {
+            self.deletion += 1;
+        }
+
+        let accepted = policy.accepts(cigar);
+        if !accepted {
+            self.rejected += 1;
+        }
+        accepted
+    }
+
+    /// Returns one row for each tally, beginning with `name` and
+    /// followed by `\t` and the count.
+    pub fn table(&self) -> String {
+        format!(
+            "WithInsertion\t{}\nWithDeletion\t{}\nIndelRejected\t{}\n",
+            self.insertion, self.deletion, self.rejected
+        )
+    }
+}
+
+impl AddAssign for IndelStats {
+    fn add_assign(&mut self, other: IndelStats) {
+        self.insertion += other.insertion;
+        self.deletion += other.deletion;
+        self.rejected += other.rejected;
+    }
+}
+
+/// Reason for a `ReadFilter` to reject an alignment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterRejection {
+    /// Required flags are missing or excluded flags are set
+    Flags,
+    /// Mapping quality is below the minimum
+    Mapq,
+    /// Read length is outside the allowed range
+    Length,
+    /// `NH` tag reports more alignments than allowed
+    Hits,
+    /// A required tag is missing or has the wrong value
+    Tag,
+}
+
+/// Requirement for an auxiliary tag on an alignment, written `XX` to
+/// require the tag `XX` or `XX:VALUE` to require that it have the
+/// value `VALUE`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagPredicate {
+    tag: [u8; 2],
+    value: Option<String>,
+}
+
+impl TagPredicate {
+    /// Returns `true` when the record satisfies the requirement.
+    pub fn matches(&self, rec: &bam::Record) -> bool {
+        let aux = match rec.aux(&self.tag) {
+            Some(aux) => aux,
+            None => return false,
+        };
+        match self.value {
+            None => true,
+            Some(ref value) => match aux {
+                bam::record::Aux::Integer(i) => i.to_string() == *value,
+                bam::record::Aux::String(s) => s == value.as_bytes(),
+                bam::record::Aux::Char(c) => [c] == value.as_bytes(),
+                bam::record::Aux::Float(f) => f.to_string() == *value,
+            },
+        }
+    }
+}
+
+impl FromStr for TagPredicate {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tag_str, value) = match s.find(':') {
+            Some(delim) => (&s[..delim], Some(s[(delim + 1)..].to_string())),
+            None => (s, None),
+        };
+        let tag_bytes = tag_str.as_bytes();
+        if tag_bytes.len() != 2 {
+            bail!("Tag \"{}\" is not two characters", tag_str);
+        }
+        Ok(TagPredicate {
+            tag: [tag_bytes[0], tag_bytes[1]],
+            value: value,
+        })
+    }
+}
+
+/// Command-line arguments for a `ReadFilter`, shared by all tools
+/// that read BAM files.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReadFilterCLI {
+    pub require_flags: Option<String>,
+    pub exclude_flags: Option<String>,
+    pub min_mapq: Option<String>,
+    pub read_lengths: Option<String>,
+    pub max_hits: Option<String>,
+    #[serde(rename = "require-tag")]
+    pub require_tags: Vec<String>,
+}
+
+/// Chain of filters applied to alignments before they are analyzed.
+/// A new filter accepts every alignment, and filters are added by
+/// chaining the builder methods.
+///
+/// ```
+/// # use riboprof::bam_utils::*;
+/// let filter = ReadFilter::new()
+///     .exclude_flags(0x100)
+///     .min_mapq(10)
+///     .read_lengths(20, 40)
+///     .require_tag("XT:U".parse().unwrap());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ReadFilter {
+    require_flags: u16,
+    exclude_flags: u16,
+    min_mapq: u8,
+    read_lengths: Option<(usize, usize)>,
+    max_hits: Option<u32>,
+    tags: Vec<TagPredicate>,
+}
+
+impl ReadFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses filters from the command-line arguments shared by all
+    /// tools, with flags given in decimal or as `0x` hexadecimal.
+    pub fn new_from_cli(cli: &ReadFilterCLI) -> Result<Self, failure::Error> {
+        let mut filter = ReadFilter::new();
+        if let Some(ref flags) = cli.require_flags {
+            filter = filter.require_flags(parse_flags(flags)?);
+        }
+        if let Some(ref flags) = cli.exclude_flags {
+            filter = filter.exclude_flags(parse_flags(flags)?);
+        }
+        if let Some(ref min_mapq) = cli.min_mapq {
+            filter = filter.min_mapq(min_mapq.parse()?);
+        }
+        if let Some(ref read_lengths) = cli.read_lengths {
+            let lengths: Range<usize> = parse_pair(read_lengths)?;
+            filter = filter.read_lengths(lengths.start, lengths.end);
+        }
+        if let Some(ref max_hits) = cli.max_hits {
+            filter = filter.max_hits(max_hits.parse()?);
+        }
+        for tag in cli.require_tags.iter() {
+            filter = filter.require_tag(tag.parse()?);
+        }
+        Ok(filter)
+    }
+
+    /// Rejects alignments lacking any of the flags in `flags`.
+    pub fn require_flags(self, flags: u16) -> Self {
+        ReadFilter {
+            require_flags: self.require_flags | flags,
+            ..self
+        }
+    }
+
+    /// Rejects alignments with any of the flags in `flags`.
+    pub fn exclude_flags(self, flags: u16) -> Self {
+        ReadFilter {
+            exclude_flags: self.exclude_flags | flags,
+            ..self
+        }
+    }
+
+    /// Rejects alignments with mapping quality below `min_mapq`.
+    pub fn min_mapq(self, min_mapq: u8) -> Self {
+        ReadFilter {
+            min_mapq: min_mapq,
+            ..self
+        }
+    }
+
+    /// Rejects reads shorter than `minlen` or longer than `maxlen`.
+    pub fn read_lengths(self, minlen: usize, maxlen: usize) -> Self {
+        ReadFilter {
+            read_lengths: Some((minlen, maxlen)),
+            ..self
+        }
+    }
+
+    /// Rejects alignments whose `NH` tag reports more than
+    /// `max_hits` alignments for the read.
+    pub fn max_hits(self, max_hits: u32) -> Self {
+        ReadFilter {
+            max_hits: Some(max_hits),
+            ..self
+        }
+    }
+
+    /// Rejects alignments not satisfying the tag requirement.
+    pub fn require_tag(mut self, tag: TagPredicate) -> Self {
+        self.tags.push(tag);
+        self
+    }
+
+    /// Returns the first filter that rejects the alignment, or
+    /// `None` when it passes every filter.
+    pub fn rejection(&self, rec: &bam::Record) -> Option<FilterRejection> {
+        let flags = rec.flags();
+        if flags & self.require_flags != self.require_flags || flags & self.exclude_flags != 0 {
+            return Some(FilterRejection::Flags);
+        }
+
+        if rec.mapq() < self.min_mapq {
+            return Some(FilterRejection::Mapq);
+        }
+
+        if let Some((minlen, maxlen)) = self.read_lengths {
+            let len = rec.seq().len();
+            if len < minlen || len > maxlen {
+                return Some(FilterRejection::Length);
+            }
+        }
+
+        if let Some(max_hits) = self.max_hits {
+            if let Some(bam::record::Aux::Integer(nh)) = rec.aux(b"NH") {
+                if nh > max_hits as i64 {
+                    return Some(FilterRejection::Hits);
+                }
+            }
+        }
+
+        if !self.tags.iter().all(|tag| tag.matches(rec)) {
+            return Some(FilterRejection::Tag);
+        }
+
+        None
+    }
+}
+
+fn parse_flags(flags: &str) -> Result<u16, failure::Error> {
+    if flags.starts_with("0x") || flags.starts_with("0X") {
+        Ok(u16::from_str_radix(&flags[2..], 16)?)
+    } else {
+        Ok(flags.parse()?)
+    }
+}
+
+/// Tallies of alignments rejected by each filter in a `ReadFilter`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadFilterStats {
+    pub total: usize,
+    pub flags: usize,
+    pub mapq: usize,
+    pub length: usize,
+    pub hits: usize,
+    pub tag: usize,
+    pub passed: usize,
+}
+
+impl ReadFilterStats {
+    /// Tallies an alignment and returns `true` when it passes the
+    /// filter.
+    pub fn tally(&mut self, filter: &ReadFilter, rec: &bam::Record) -> bool {
+        self.total += 1;
+        let rejection = filter.rejection(rec);
+        match rejection {
+            None => self.passed += 1,
+            Some(FilterRejection::Flags) => self.flags += 1,
+            Some(FilterRejection::Mapq) => self.mapq += 1,
+            Some(FilterRejection::Length) => self.length += 1,
+            Some(FilterRejection::Hits) => self.hits += 1,
+            Some(FilterRejection::Tag) => self.tag += 1,
+        }
+        rejection.is_none()
+    }
+
+    /// Returns a table with one row for each filter, giving the
+    /// number and fraction of alignments rejected.
+    pub fn table(&self) -> String {
+        let rows = [
+            ("Flags", self.flags),
+            ("Mapq", self.mapq),
+            ("Length", self.length),
+            ("Hits", self.hits),
+            ("Tag", self.tag),
+            ("Passed", self.passed),
+        ];
+        let mut table = format!("Total\t{}\t1.0000\n", self.total);
+        for (name, n) in rows.iter() {
+            table += &format!(
+                "{}\t{}\t{:.4}\n",
+                name,
+                n,
+                *n as f64 / self.total.max(1) as f64
+            );
+        }
+        table
+    }
+}
+
+impl AddAssign for ReadFilterStats {
+    fn add_assign(&mut self, other: ReadFilterStats) {
+        self.total += other.total;
+        self.flags += other.flags;
+        self.mapq += other.mapq;
+        self.length += other.length;
+        self.hits += other.hits;
+        self.tag += other.tag;
+        self.passed += other.passed;
+    }
+}
+
+/// One operation of an `MD` tag, describing the reference sequence
+/// of an alignment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MdOp {
+    /// Reference bases matching the read
+    Match(u32),
+    /// Reference base mismatched in the read
+    Mismatch(u8),
+    /// Reference bases deleted from the read
+    Deletion(Vec<u8>),
+}
+
+/// Mismatch between a read and the reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Reference position, 0-based
+    pub ref_pos: isize,
+    /// Position in the read sequence as stored in the record,
+    /// including soft-clipped bases
+    pub read_pos: usize,
+    /// Offset of the mismatch from the 5' end of the read
+    pub five_prime_offset: usize,
+    /// Reference base
+    pub ref_base: u8,
+    /// Read base, on the reference strand
+    pub read_base: u8,
+}
+
+/// Parses the contents of an `MD` tag.
+///
+/// ```
+/// # use riboprof::bam_utils::*;
+/// assert_eq!(
+///     parse_md(b"10A0^AC5").unwrap(),
+///     vec![MdOp::Match(10), MdOp::Mismatch(b'A'), MdOp::Match(0),
+///          MdOp::Deletion(b"AC".to_vec()), MdOp::Match(5)]
+/// );
+/// ```
+///
+/// # Errors
+///
+/// An error variant is returned when the tag is malformed.
+pub fn parse_md(md: &[u8]) -> Result<Vec<MdOp>, failure::Error> {
+    let mut ops = Vec::new();
+    let mut i = 0;
+
+    while i < md.len() {
+        if md[i].is_ascii_digit() {
+            let start = i;
+            while i < md.len() && md[i].is_ascii_digit() {
+                i += 1;
+            }
+            ops.push(MdOp::Match(String::from_utf8_lossy(&md[start..i]).parse()?));
+        } else if md[i] == b'^' {
+            let start = i + 1;
+            i = start;
+            while i < md.len() && md[i].is_ascii_alphabetic() {
+                i += 1;
+            }
+            if i == start {
+                bail!("Empty deletion in MD tag {}", String::from_utf8_lossy(md));
+            }
+            ops.push(MdOp::Deletion(md[start..i].to_ascii_uppercase()));
+        } else if md[i].is_ascii_alphabetic() {
+            ops.push(MdOp::Mismatch(md[i].to_ascii_uppercase()));
+            i += 1;
+        } else {
+            bail!(
+                "Bad character \'{}\' in MD tag {}",
+                md[i] as char,
+                String::from_utf8_lossy(md)
+            );
+        }
+    }
+
+    Ok(ops)
+}
+
+/// Returns the mismatches between a read and the reference, found
+/// from its `MD` tag and CIGAR string, or `None` when the record has
+/// no `MD` tag.
+///
+/// # Errors
+///
+/// An error variant is returned when the `MD` tag is malformed or
+/// inconsistent with the CIGAR string.
+pub fn mismatches(rec: &bam::Record) -> Result<Option<Vec<Mismatch>>, failure::Error> {
+    let md_ops = match rec.aux(b"MD") {
+        Some(bam::record::Aux::String(md)) => parse_md(md)?,
+        _ => return Ok(None),
+    };
+
+    let seq = rec.seq().as_bytes();
+    let cigar = rec.cigar();
+    let mut mms = cigar_md_mismatches(rec.pos() as isize, &cigar, &md_ops, &seq)?;
+
+    if rec.is_reverse() {
+        for mm in mms.iter_mut() {
+            mm.five_prime_offset = seq.len() - 1 - mm.read_pos;
+        }
+    }
+
+    Ok(Some(mms))
+}
+
+/// Returns the edit distance between a read and the reference from
+/// its `NM` tag, if present.
+pub fn edit_distance(rec: &bam::Record) -> Option<u32> {
+    match rec.aux(b"NM") {
+        Some(bam::record::Aux::Integer(nm)) if nm >= 0 => Some(nm as u32),
+        _ => None,
+    }
+}
+
+/// Finds mismatches by walking the CIGAR operations of an alignment
+/// alongside its `MD` operations. Offsets from the 5' end assume a
+/// forward-strand alignment.
+fn cigar_md_mismatches(
+    pos: isize,
+    cigar: &[Cigar],
+    md_ops: &[MdOp],
+    seq: &[u8],
+) -> Result<Vec<Mismatch>, failure::Error> {
+    let mut mms = Vec::new();
+
+    let mut md_iter = md_ops.iter().peekable();
+    let mut md_matches = 0;
+    let mut read_pos = 0;
+    let mut ref_pos = pos;
+
+    for op in cigar.iter() {
+        match op {
+            Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                for _ in 0..*len {
+                    while md_matches == 0 {
+                        match md_iter.next() {
+                            Some(MdOp::Match(n)) => md_matches = *n,
+                            Some(MdOp::Mismatch(ref_base)) => {
+                                mms.push(Mismatch {
+                                    ref_pos: ref_pos,
+                                    read_pos: read_pos,
+                                    five_prime_offset: read_pos,
+                                    ref_base: *ref_base,
+                                    read_base: *seq.get(read_pos).unwrap_or(&b'N'),
+                                });
+                                break;
+                            }
+                            Some(MdOp::Deletion(_)) => bail!("MD deletion at aligned base"),
+                            None => bail!("MD tag shorter than alignment"),
+                        }
+                    }
+                    if md_matches > 0 {
+                        md_matches -= 1;
+                    }
+                    read_pos += 1;
+                    ref_pos += 1;
+                }
+            }
+            Cigar::Del(len) => {
+                while md_matches == 0 && md_iter.peek() == Some(&&MdOp::Match(0)) {
+                    md_iter.next();
+                }
+                let del_len = match md_iter.next() {
+                    Some(MdOp::Deletion(ref bases)) if md_matches == 0 => bases.len(),
+                    _ => 0,
+                };
+                if del_len != *len as usize {
+                    bail!("MD tag does not match deletion in CIGAR");
+                }
+                ref_pos += *len as isize;
+            }
+            Cigar::Ins(len) | Cigar::SoftClip(len) => read_pos += *len as usize,
+            Cigar::RefSkip(len) => ref_pos += *len as isize,
+            Cigar::HardClip(_) | Cigar::Pad(_) => (),
+        }
+    }
+
+    Ok(mms)
+}
+
+/// Error for alignments that are out of the sorted order that a tool
+/// requires, describing the first alignment out of order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsortedError(pub String);
+
+impl fmt::Display for UnsortedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for UnsortedError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use bio::io::bed;
+    use rust_htslib::bam::record::CigarString;
+
+    #[test]
+    fn md_mismatches() {
+        let cigar = vec![
+            Cigar::SoftClip(2),
+            Cigar::Match(5),
+            Cigar::Ins(1),
+            Cigar::Match(3),
+            Cigar::Del(2),
+            Cigar::Match(4),
+        ];
+        let md = parse_md(b"3C4^TT1G2").unwrap();
+        let mms = cigar_md_mismatches(100, &cigar, &md, b"GGACGTACCCATACA").unwrap();
+        assert_eq!(
+            mms,
+            vec![
+                Mismatch {
+                    ref_pos: 103,
+                    read_pos: 5,
+                    five_prime_offset: 5,
+                    ref_base: b'C',
+                    read_base: b'T',
+                },
+                Mismatch {
+                    ref_pos: 111,
+                    read_pos: 12,
+                    five_prime_offset: 12,
+                    ref_base: b'G',
+                    read_base: b'A',
+                },
+            ]
+        );
+
+        let short_md = parse_md(b"3C4^TT1G1").unwrap();
+        assert!(cigar_md_mismatches(100, &cigar, &short_md, b"GGACGTACCCATACA").is_err());
+        let no_del_md = parse_md(b"3C5G2").unwrap();
+        assert!(cigar_md_mismatches(100, &cigar, &no_del_md, b"GGACGTACCCATACA").is_err());
+        assert!(parse_md(b"3^").is_err());
+        assert!(parse_md(b"3-4").is_err());
+    }
+
+    #[test]
+    fn program_records() {
+        let header = b"@HD\tVN:1.6\tSO:coordinate\n\
+                       @PG\tID:STAR\tPN:STAR\tVN:2.7.3a\n\
+                       @PG\tID:fp-framing\tPN:fp-framing\tPP:STAR\n\
+                       @PG\tID:samtools\tPN:samtools\tPP:fp-framing\n";
+        assert_eq!(
+            program_chain(header, "fp-framing"),
+            ("fp-framing.1".to_string(), Some("samtools".to_string()))
+        );
+        assert_eq!(
+            program_chain(b"@HD\tVN:1.6\n", "bam-subsample"),
+            ("bam-subsample".to_string(), None)
+        );
+
+        let mut template = bam::Header::new();
+        template.push_record(
+            bam::header::HeaderRecord::new(b"PG")
+                .push_tag(b"ID", &"STAR")
+                .push_tag(b"PN", &"STAR"),
+        );
+        let output = output_header(&HeaderView::from_header(&template), "bam-subsample");
+        let text = String::from_utf8(output.to_bytes()).unwrap();
+        let pg = text.lines().last().unwrap();
+        assert!(pg.starts_with("@PG\tID:bam-subsample\tPN:bam-subsample\tVN:"));
+        assert!(pg.contains("\tCL:"));
+        assert!(pg.ends_with("\tPP:STAR"));
+    }
+
+    #[test]
+    fn tids_lookup() {
+        let mut header = bam::Header::new();
+        header.push_record(
+            bam::header::HeaderRecord::new(b"SQ")
+                .push_tag(b"SN", &"chr01")
+                .push_tag(b"LN", &1000),
+        );
+        header.push_record(
+            bam::header::HeaderRecord::new(b"SQ")
+                .push_tag(b"SN", &"chr02")
+                .push_tag(b"LN", &2500),
+        );
+        let header_view = HeaderView::from_header(&header);
+
+        let mut refids: RefIDSet<Rc<String>> = RefIDSet::new();
+        let tids = Tids::new(&mut refids, &header_view);
+        assert_eq!(tids.len(), 2);
+        assert_eq!(tids.tid_of("chr02"), Some(1));
+        assert_eq!(tids.tid_of("chrIII"), None);
+        assert_eq!(tids.target_len(1), Some(2500));
+        assert_eq!(tids.get(0).map(|name| name.as_str()), Some("chr01"));
+
+        let bedstr = "chr01\t0\t100\tTrxA\t0\t+\t10\t90\t0\t1\t100,\t0,\n\
+                      chr03\t0\t100\tTrxB\t0\t+\t10\t90\t0\t1\t100,\t0,\n";
+        let trxome =
+            Transcriptome::new_from_bed(bed::Reader::new(bedstr.as_bytes()).records(), &mut refids)
+                .unwrap();
+        assert_eq!(tids.validate_against(&trxome), vec!["chr03".to_string()]);
+    }
+
+    #[test]
+    fn large_targets() {
+        let mut header = bam::Header::new();
+        header.push_record(
+            bam::header::HeaderRecord::new(b"SQ")
+                .push_tag(b"SN", &"chr3B")
+                .push_tag(b"LN", &3000000000u32),
+        );
+        let header_view = HeaderView::from_header(&header);
+
+        let mut refids: RefIDSet<Rc<String>> = RefIDSet::new();
+        let tids = Tids::new(&mut refids, &header_view);
+        assert_eq!(tids.target_len(0), Some(3000000000));
+
+        let mut rec = bam::Record::new();
+        rec.set(
+            b"read",
+            &CigarString(vec![Cigar::Match(4)]),
+            b"ACGT",
+            &[30; 4],
+        );
+        rec.set_tid(0);
+        rec.set_pos(i32::max_value() - 4);
+        assert_eq!(
+            bam_to_spliced(&tids, &rec).unwrap().unwrap().to_string(),
+            "chr3B:2147483643-2147483647(+)"
+        );
+
+        rec.set_pos(i32::min_value() + 10);
+        assert!(bam_to_spliced(&tids, &rec).is_err());
+    }
+
+    #[test]
+    fn replace_aux() {
+        let mut rec = bam::Record::new();
+        rec.set(
+            b"read1",
+            &bam::record::CigarString(vec![Cigar::Match(4)]),
+            b"ACGT",
+            &[30; 4],
+        );
+        set_aux_string(&mut rec, b"ZF", b"BamNoHit").unwrap();
+        set_aux_int(&mut rec, b"ZD", 2).unwrap();
+        set_aux_string(&mut rec, b"ZF", b"FpNoGene").unwrap();
+        set_aux_float(&mut rec, b"ZX", 0.5).unwrap();
+        set_aux_int(&mut rec, b"ZD", 3).unwrap();
+
+        assert_eq!(rec.aux(b"ZF"), Some(bam::record::Aux::String(b"FpNoGene")));
+        assert_eq!(rec.aux(b"ZD"), Some(bam::record::Aux::Integer(3)));
+        assert_eq!(rec.aux(b"ZX"), Some(bam::record::Aux::Float(0.5)));
+
+        assert!(remove_aux(&mut rec, b"ZF"));
+        assert!(!remove_aux(&mut rec, b"ZF"));
+        assert_eq!(rec.aux(b"ZF"), None);
+        assert_eq!(rec.aux(b"ZD"), Some(bam::record::Aux::Integer(3)));
+    }
+
+    #[test]
+    fn read_filter() {
+        let mut rec = bam::Record::new();
+        rec.set(
+            b"read1",
+            &bam::record::CigarString(vec![Cigar::Match(28)]),
+            &[b'A'; 28],
+            &[30; 28],
+        );
+        rec.set_flags(0x10);
+        rec.set_mapq(20);
+        rec.push_aux(b"NH", &bam::record::Aux::Integer(2)).unwrap();
+        rec.push_aux(b"XT", &bam::record::Aux::Char(b'U')).unwrap();
+
+        assert_eq!(ReadFilter::new().rejection(&rec), None);
+        assert_eq!(ReadFilter::new().require_flags(0x10).rejection(&rec), None);
+        assert_eq!(
+            ReadFilter::new().exclude_flags(0x110).rejection(&rec),
+            Some(FilterRejection::Flags)
+        );
+        assert_eq!(
+            ReadFilter::new().min_mapq(30).rejection(&rec),
+            Some(FilterRejection::Mapq)
+        );
+        assert_eq!(
+            ReadFilter::new().read_lengths(20, 27).rejection(&rec),
+            Some(FilterRejection::Length)
+        );
+        assert_eq!(
+            ReadFilter::new().max_hits(1).rejection(&rec),
+            Some(FilterRejection::Hits)
+        );
+        assert_eq!(
+            ReadFilter::new()
+                .require_tag("XT:U".parse().unwrap())
+                .require_tag("NH:2".parse().unwrap())
+                .rejection(&rec),
+            None
+        );
+        assert_eq!(
+            ReadFilter::new()
+                .require_tag("XT:R".parse().unwrap())
+                .rejection(&rec),
+            Some(FilterRejection::Tag)
+        );
+        assert!("XTU".parse::<TagPredicate>().is_err());
+
+        let cli = ReadFilterCLI {
+            exclude_flags: Some("0x4".to_string()),
+            min_mapq: Some("10".to_string()),
+            ..ReadFilterCLI::default()
+        };
+        let filter = ReadFilter::new_from_cli(&cli).unwrap();
+        let mut stats = ReadFilterStats::default();
+        assert!(stats.tally(&filter, &rec));
+        assert!(!stats.tally(&ReadFilter::new().max_hits(1), &rec));
+        assert_eq!(stats.total, 2);
+        assert_eq!(stats.passed, 1);
+        assert_eq!(stats.hits, 1);
+    }
+
+    #[test]
+    fn indel_blocks() {
+        let cigar = vec![
+            Cigar::SoftClip(2),
+            Cigar::Match(5),
+            Cigar::Ins(1),
+            Cigar::Match(3),
+            Cigar::Del(2),
+            Cigar::Match(4),
+            Cigar::RefSkip(100),
+            Cigar::Match(6),
+        ];
+        assert_eq!(
+            cigar_to_blocks(&cigar, DeletionPolicy::Match),
+            (vec![14, 6], vec![0, 114])
+        );
+        assert_eq!(
+            cigar_to_blocks(&cigar, DeletionPolicy::Gap),
+            (vec![8, 4, 6], vec![0, 10, 114])
+        );
+
+        let mut stats = IndelStats::default();
+        let mut policy = IndelPolicy::default();
+        assert!(stats.tally(&policy, &cigar));
+        policy.max_indel = Some(1);
+        assert!(!stats.tally(&policy, &cigar));
+        assert!(stats.tally(&policy, &[Cigar::Match(20)]));
+        assert_eq!(
+            stats,
+            IndelStats {
+                insertion: 2,
+                deletion: 2,
+                rejected: 1,
+            }
+        );
+        policy.max_indel = Some(0);
+        assert!(!policy.accepts(&[Cigar::Match(10), Cigar::Ins(1), Cigar::Match(10)]));
+    }
+}
@@ -0,0 +1,429 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+use failure;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use bio::io::fastq;
+use rust_htslib::bam;
+use rust_htslib::bam::record::{Cigar, CigarString};
+
+use bam_suppress_duplicates;
+use codon_count;
+use fastx_split;
+use fp_framing;
+
+pub struct CLI {
+    pub reads: String,
+    pub genes: String,
+    pub seed: String,
+    pub threads: String,
+    pub output: String,
+    pub keep: Option<String>,
+}
+
+pub struct Config {
+    reads: usize,
+    genes: usize,
+    seed: u64,
+    threads: usize,
+    output: String,
+    keep: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        let reads = cli.reads.parse()?;
+        let genes = cli.genes.parse()?;
+        if reads == 0 || genes == 0 {
+            bail!("Benchmarks require at least one read and one gene");
+        }
+
+        Ok(Config {
+            reads: reads,
+            genes: genes,
+            seed: cli.seed.parse()?,
+            threads: cli.threads.parse()?,
+            output: cli.output.to_string(),
+            keep: cli.keep.as_ref().map(PathBuf::from),
+        })
+    }
+}
+
+/// Name of the one reference sequence holding every synthetic gene.
+const BENCH_CHROM: &str = "chrBench";
+
+/// Start of the first synthetic gene and the spacing between genes.
+const GENE_START: usize = 1000;
+const GENE_SPACING: usize = 2000;
+
+/// Lengths of the UTRs and the number of codons in the CDS of each
+/// synthetic gene.
+const UTR_LENGTH: usize = 150;
+const CDS_CODONS: usize = 400;
+
+/// Footprint lengths, all with the A site at `ASITE_OFFSET`.
+const FP_LENGTHS: (usize, usize) = (28, 32);
+const ASITE_OFFSET: usize = 15;
+
+/// Sample indices of the synthetic reads, and the linker suffix that
+/// carries a UMI and then the index.
+const SAMPLE_INDICES: [&str; 4] = ["ACGT", "CGTA", "GTAC", "TACG"];
+const LINKER_SUFFIX: &str = "NNNNNNIIII";
+const UMI_LENGTH: usize = 6;
+
+/// Throughput of one tool on the synthetic data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    pub tool: &'static str,
+    pub records: usize,
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    pub fn seconds(&self) -> f64 {
+        self.elapsed.as_secs() as f64 + f64::from(self.elapsed.subsec_nanos()) * 1e-9
+    }
+
+    pub fn records_per_second(&self) -> f64 {
+        self.records as f64 / self.seconds().max(1e-9)
+    }
+}
+
+/// Returns a table with one row for each tool, giving the number of
+/// records processed, the elapsed wall-clock time, and the throughput.
+pub fn bench_table(results: &[BenchResult]) -> String {
+    let mut table = "tool\trecords\tseconds\trecords_per_second\n".to_string();
+    for result in results.iter() {
+        table += &format!(
+            "{}\t{}\t{:.3}\t{:.0}\n",
+            result.tool,
+            result.records,
+            result.seconds(),
+            result.records_per_second()
+        );
+    }
+    table
+}
+
+fn gene_start(gene: usize) -> usize {
+    GENE_START + gene * GENE_SPACING
+}
+
+fn gene_length() -> usize {
+    2 * UTR_LENGTH + 3 * CDS_CODONS
+}
+
+/// Returns a BED annotation of `genes` single-exon, forward-strand
+/// genes, spaced evenly along one reference sequence.
+pub fn synthetic_bed(genes: usize) -> String {
+    let mut bed = String::new();
+    for gene in 0..genes {
+        let start = gene_start(gene);
+        let end = start + gene_length();
+        bed += &format!(
+            "{}\t{}\t{}\tBench{:06}\t0\t+\t{}\t{}\t0\t1\t{},\t0,\n",
+            BENCH_CHROM,
+            start,
+            end,
+            gene,
+            start + UTR_LENGTH,
+            end - UTR_LENGTH,
+            gene_length()
+        );
+    }
+    bed
+}
+
+/// Returns an A site offset table for the synthetic footprints.
+pub fn synthetic_asites() -> String {
+    (FP_LENGTHS.0..FP_LENGTHS.1)
+        .map(|len| format!("{}\t{}\n", len, ASITE_OFFSET))
+        .collect()
+}
+
+/// Returns a sample sheet for the sample indices of the synthetic
+/// reads.
+pub fn synthetic_sample_sheet() -> String {
+    SAMPLE_INDICES
+        .iter()
+        .enumerate()
+        .map(|(i, index)| format!("Sample{},{}\n", i, index))
+        .collect()
+}
+
+fn random_seq<R: Rng>(rng: &mut R, len: usize) -> Vec<u8> {
+    (0..len).map(|_| b"ACGT"[rng.gen_range(0, 4)]).collect()
+}
+
+/// Returns `reads` synthetic sequencing reads, each a random insert
+/// followed by a linker with a random UMI and one of the sample
+/// indices. One read in a hundred has an unknown index.
+pub fn synthetic_fastq<R: Rng>(rng: &mut R, reads: usize) -> Vec<fastq::Record> {
+    (0..reads)
+        .map(|i| {
+            let len = rng.gen_range(FP_LENGTHS.0, FP_LENGTHS.1);
+            let mut seq = random_seq(rng, len);
+            seq.extend(random_seq(rng, UMI_LENGTH));
+            if rng.gen_range(0, 100) == 0 {
+                seq.extend(random_seq(rng, 4));
+            } else {
+                seq.extend(SAMPLE_INDICES[rng.gen_range(0, SAMPLE_INDICES.len())].bytes());
+            }
+            let qual = vec![b'I'; seq.len()];
+            fastq::Record::with_attrs(&format!("bench{}", i), None, &seq, &qual)
+        })
+        .collect()
+}
+
+/// Returns a BAM header with the one synthetic reference sequence.
+pub fn synthetic_header(genes: usize) -> bam::Header {
+    let mut header = bam::Header::new();
+    header.push_record(
+        bam::header::HeaderRecord::new(b"HD")
+            .push_tag(b"VN", &"1.6")
+            .push_tag(b"SO", &"coordinate"),
+    );
+    header.push_record(
+        bam::header::HeaderRecord::new(b"SQ")
+            .push_tag(b"SN", &BENCH_CHROM)
+            .push_tag(b"LN", &(gene_start(genes) + GENE_START)),
+    );
+    header
+}
+
+/// Returns `reads` synthetic footprint alignments, sorted by position,
+/// with A sites on random codons of random genes and UMIs drawn from
+/// a small set so that some alignments are duplicates.
+pub fn synthetic_alignments<R: Rng>(rng: &mut R, reads: usize, genes: usize) -> Vec<bam::Record> {
+    let mut footprints: Vec<(usize, usize, Vec<u8>)> = (0..reads)
+        .map(|_| {
+            let cds_start = gene_start(rng.gen_range(0, genes)) + UTR_LENGTH;
+            let asite = cds_start + 3 * rng.gen_range(0, CDS_CODONS);
+            let len = rng.gen_range(FP_LENGTHS.0, FP_LENGTHS.1);
+            let umi = SAMPLE_INDICES[rng.gen_range(0, SAMPLE_INDICES.len())]
+                .bytes()
+                .chain(random_seq(rng, 2))
+                .collect();
+            (asite - ASITE_OFFSET, len, umi)
+        })
+        .collect();
+    footprints.sort();
+
+    footprints
+        .into_iter()
+        .enumerate()
+        .map(|(i, (pos, len, umi))| {
+            let mut rec = bam::Record::new();
+            let qname = format!("bench{}#{}", i, String::from_utf8_lossy(&umi));
+            rec.set(
+                qname.as_bytes(),
+                &CigarString(vec![Cigar::Match(len as u32)]),
+                &random_seq(rng, len),
+                &vec![40; len],
+            );
+            rec.set_tid(0);
+            rec.set_pos(pos as i32);
+            rec.set_mapq(255);
+            rec
+        })
+        .collect()
+}
+
+/// Writes the synthetic annotation, reads, and alignments into
+/// `dir`.
+fn write_synthetic(config: &Config, dir: &Path) -> Result<(), failure::Error> {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+
+    fs::write(dir.join("bench.bed"), synthetic_bed(config.genes))?;
+    fs::write(dir.join("bench_asites.txt"), synthetic_asites())?;
+    fs::write(dir.join("samples.csv"), synthetic_sample_sheet())?;
+
+    let mut fastq_out = fastq::Writer::to_file(dir.join("bench.fastq"))?;
+    for rec in synthetic_fastq(&mut rng, config.reads) {
+        fastq_out.write_record(&rec)?;
+    }
+    fastq_out.flush()?;
+
+    let header = synthetic_header(config.genes);
+    let mut bam_out = bam::Writer::from_path(dir.join("bench.bam"), &header)?;
+    for rec in synthetic_alignments(&mut rng, config.reads, config.genes) {
+        bam_out.write(&rec)?;
+    }
+
+    Ok(())
+}
+
+fn path_string(path: PathBuf) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Runs `tool` and returns its elapsed time.
+fn timed_run<F>(tool: &'static str, records: usize, run: F) -> Result<BenchResult, failure::Error>
+where
+    F: FnOnce() -> Result<(), failure::Error>,
+{
+    info!("Benchmarking {} on {} records", tool, records);
+    let start = Instant::now();
+    run()?;
+    Ok(BenchResult {
+        tool: tool,
+        records: records,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Runs each tool on the synthetic data in `dir`.
+fn run_tools(config: &Config, dir: &Path) -> Result<Vec<BenchResult>, failure::Error> {
+    let threads = config.threads.to_string();
+    let bed = path_string(dir.join("bench.bed"));
+    let bam = path_string(dir.join("bench.bam"));
+    let mut results = Vec::new();
+
+    results.push(timed_run("fastx-split", config.reads, || {
+        let cli = fastx_split::CLI {
+            fastx_inputs: vec![path_string(dir.join("bench.fastq"))],
+            output_dir: path_string(dir.join("split")),
+            suffix: LINKER_SUFFIX.to_string(),
+            sample_sheet: path_string(dir.join("samples.csv")),
+            threads: threads.clone(),
+            ..Default::default()
+        };
+        fastx_split::fastx_split(fastx_split::Config::new(&cli)?)
+    })?);
+
+    results.push(timed_run("bam-suppress-duplicates", config.reads, || {
+        let cli = bam_suppress_duplicates::CLI {
+            bam_input: bam.clone(),
+            bam_output: path_string(dir.join("dedup.bam")),
+            threads: threads.clone(),
+            io_threads: threads.clone(),
+            ..Default::default()
+        };
+        bam_suppress_duplicates::bam_suppress_duplicates(bam_suppress_duplicates::Config::new(
+            &cli,
+        )?)
+    })?);
+
+    results.push(timed_run("fp-framing", config.reads, || {
+        let cli = fp_framing::CLI {
+            input: bam.clone(),
+            output: path_string(dir.join("framing")),
+            bed: vec![bed.clone()],
+            lengths: format!("{},{}", FP_LENGTHS.0, FP_LENGTHS.1),
+            threads: threads.clone(),
+            io_threads: threads.clone(),
+            ..Default::default()
+        };
+        fp_framing::run_fp_framing(fp_framing::Config::new(&cli)?)
+    })?);
+
+    results.push(timed_run("bam-codon-count", config.reads, || {
+        let cli = codon_count::CLI {
+            inputs: vec![bam.clone()],
+            output: path_string(dir.join("codons")),
+            bed: vec![bed.clone()],
+            asites: path_string(dir.join("bench_asites.txt")),
+            lengths: format!("{},{}", FP_LENGTHS.0, FP_LENGTHS.1),
+            ..Default::default()
+        };
+        codon_count::run_codon_count(codon_count::Config::new(&cli)?)
+    })?);
+
+    Ok(results)
+}
+
+pub fn run_bench(config: Config) -> Result<(), failure::Error> {
+    let dir = match config.keep {
+        Some(ref keep) => keep.clone(),
+        None => ::std::env::temp_dir().join(format!("riboprof-bench-{}", process::id())),
+    };
+    fs::create_dir_all(&dir)?;
+
+    let results = write_synthetic(&config, &dir).and_then(|_| run_tools(&config, &dir));
+    if config.keep.is_none() {
+        fs::remove_dir_all(&dir)?;
+    }
+    let results = results?;
+
+    let mut output: Box<Write> = if config.output == "-" {
+        Box::new(io::stdout())
+    } else {
+        Box::new(fs::File::create(&config.output)?)
+    };
+    output.write_all(bench_table(&results).as_bytes())?;
+
+    for result in results.iter() {
+        info!(
+            "{}: {:.0} records per second",
+            result.tool,
+            result.records_per_second()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use bio::io::bed;
+    use bio_types::annot::refids::RefIDSet;
+
+    use transcript::*;
+
+    fn transcriptome_from_str(bedstr: &str) -> Transcriptome<Rc<String>> {
+        let mut refids = RefIDSet::new();
+        Transcriptome::new_from_bed(bed::Reader::new(bedstr.as_bytes()).records(), &mut refids)
+            .expect("Transcriptome from string")
+    }
+
+    #[test]
+    fn synthetic_data() {
+        let bedstr = synthetic_bed(3);
+        let trxome = transcriptome_from_str(&bedstr);
+        assert_eq!(trxome.transcripts().count(), 3);
+        assert!(trxome
+            .transcripts()
+            .all(|trx| trx.cds_range() == &Some(UTR_LENGTH..(UTR_LENGTH + 3 * CDS_CODONS))));
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let reads = synthetic_fastq(&mut rng, 100);
+        assert_eq!(reads.len(), 100);
+        let known = reads
+            .iter()
+            .filter(|rec| {
+                let index = &rec.seq()[rec.seq().len() - 4..];
+                SAMPLE_INDICES
+                    .iter()
+                    .any(|sample| index == sample.as_bytes())
+            })
+            .count();
+        assert!(known >= 90);
+
+        let alignments = synthetic_alignments(&mut rng, 100, 3);
+        assert!(alignments
+            .windows(2)
+            .all(|pair| pair[0].pos() <= pair[1].pos()));
+        assert!(alignments
+            .iter()
+            .all(|rec| (rec.pos() as usize) < gene_start(3)));
+
+        let results = vec![BenchResult {
+            tool: "fastx-split",
+            records: 1000,
+            elapsed: Duration::from_millis(500),
+        }];
+        assert_eq!(
+            bench_table(&results),
+            "tool\trecords\tseconds\trecords_per_second\nfastx-split\t1000\t0.500\t2000\n"
+        );
+    }
+}
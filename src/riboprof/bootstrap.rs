@@ -0,0 +1,47 @@
+use std::cmp::Ordering;
+
+/// Lower and upper quantiles reported for bootstrap confidence
+/// intervals, giving a 95% interval.
+pub const CI_QUANTILES: (f64, f64) = (0.025, 0.975);
+
+/// Returns the 95% confidence interval from bootstrap estimates,
+/// which are sorted in place. The estimates must not be empty.
+///
+/// ```
+/// # use riboprof::bootstrap::*;
+/// let mut ests: Vec<f64> = (0..41).rev().map(|x| x as f64).collect();
+/// assert_eq!(confidence_interval(&mut ests), (1.0, 39.0));
+/// ```
+pub fn confidence_interval(estimates: &mut [f64]) -> (f64, f64) {
+    sort_values(estimates);
+    (
+        quantile(estimates, CI_QUANTILES.0),
+        quantile(estimates, CI_QUANTILES.1),
+    )
+}
+
+/// Returns the median of values, which are sorted in place. The
+/// values must not be empty.
+///
+/// ```
+/// # use riboprof::bootstrap::*;
+/// assert_eq!(median(&mut [3.0, 1.0, 2.0]), 2.0);
+/// assert_eq!(median(&mut [4.0, 1.0, 2.0, 3.0]), 2.5);
+/// ```
+pub fn median(values: &mut [f64]) -> f64 {
+    sort_values(values);
+    quantile(values, 0.5)
+}
+
+/// Returns a quantile of sorted values, interpolating linearly
+/// between the closest ranks. The values must not be empty.
+pub fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let rank = q * (sorted.len() - 1) as f64;
+    let lo = rank.floor() as usize;
+    let hi = rank.ceil() as usize;
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (rank - lo as f64)
+}
+
+fn sort_values(values: &mut [f64]) {
+    values.sort_by(|x, y| x.partial_cmp(y).unwrap_or(Ordering::Equal));
+}
@@ -0,0 +1,332 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use failure;
+
+use bam_suppress_duplicates;
+use fastx_split::SplitFates;
+use fp_framing::FramingStats;
+use output::*;
+use report::*;
+
+pub struct CLI {
+    pub output: String,
+    pub fates: Vec<String>,
+    pub dedup: Vec<String>,
+    pub framing: Vec<String>,
+    pub codon_stats: Vec<String>,
+}
+
+pub struct Config {
+    output: String,
+    fates: Vec<PathBuf>,
+    dedup: Vec<PathBuf>,
+    framing: Vec<PathBuf>,
+    codon_stats: Vec<PathBuf>,
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        let nstages = [
+            !cli.fates.is_empty(),
+            !cli.dedup.is_empty(),
+            !cli.framing.is_empty(),
+            !cli.codon_stats.is_empty(),
+        ]
+        .iter()
+        .filter(|&&present| present)
+        .count();
+        if nstages < 2 {
+            bail!("Checking read counts requires statistics from at least two stages");
+        }
+
+        Ok(Config {
+            output: cli.output.to_string(),
+            fates: cli.fates.iter().map(PathBuf::from).collect(),
+            dedup: cli.dedup.iter().map(PathBuf::from).collect(),
+            framing: cli.framing.iter().map(PathBuf::from).collect(),
+            codon_stats: cli.codon_stats.iter().map(PathBuf::from).collect(),
+        })
+    }
+}
+
+/// Suffix of the codon assignment statistics written by
+/// `bam-codon-count`.
+const CODON_STATS_SUFFIX: &str = "_codon_stats.txt";
+
+/// Read counts for one sample at each pipeline stage, where known.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StageCounts {
+    /// Reads assigned to the sample by `fastx-split`
+    pub split: Option<u64>,
+    /// Alignments into and out of `bam-suppress-duplicates`
+    pub dedup: Option<(u64, u64)>,
+    /// Alignments seen by `fp-framing`, and those that were good
+    pub framing: Option<(u64, u64)>,
+    /// Alignments seen by `bam-codon-count`, and those assigned to a
+    /// codon
+    pub codon: Option<(u64, u64)>,
+}
+
+/// Comparison of the read count entering one stage against the count
+/// from the stage before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StageCheck {
+    pub upstream: &'static str,
+    pub downstream: &'static str,
+    pub upstream_reads: u64,
+    pub downstream_reads: u64,
+}
+
+impl StageCheck {
+    /// Returns `true` when the downstream stage has no more reads than
+    /// the upstream stage.
+    pub fn is_ok(&self) -> bool {
+        self.downstream_reads <= self.upstream_reads
+    }
+
+    pub fn lost(&self) -> i64 {
+        self.upstream_reads as i64 - self.downstream_reads as i64
+    }
+}
+
+impl StageCounts {
+    /// Returns the comparisons between consecutive stages with known
+    /// counts. Framing and codon counting both read the output of
+    /// duplicate suppression, and so each is compared against it.
+    pub fn checks(&self) -> Vec<StageCheck> {
+        let mut checks = Vec::new();
+        let mut check = |upstream: (&'static str, u64), downstream: (&'static str, u64)| {
+            checks.push(StageCheck {
+                upstream: upstream.0,
+                downstream: downstream.0,
+                upstream_reads: upstream.1,
+                downstream_reads: downstream.1,
+            })
+        };
+
+        let mut last = self.split.map(|reads| ("split", reads));
+        if let Some((input, output)) = self.dedup {
+            if let Some(split) = last {
+                check(split, ("dedup_input", input));
+            }
+            check(("dedup_input", input), ("dedup_output", output));
+            last = Some(("dedup_output", output));
+        }
+
+        for &(input_name, output_name, counts) in [
+            ("framing_input", "framing_good", self.framing),
+            ("codon_input", "codon_assigned", self.codon),
+        ]
+        .iter()
+        {
+            if let Some((input, output)) = counts {
+                if let Some(upstream) = last {
+                    check(upstream, (input_name, input));
+                }
+                check((input_name, input), (output_name, output));
+            }
+        }
+
+        checks
+    }
+}
+
+/// Parses the total and the codon-assigned footprint counts from a
+/// codon statistics table written by `bam-codon-count`.
+pub fn parse_codon_stats(text: &str) -> Result<(u64, u64), failure::Error> {
+    let mut total = None;
+    let mut codon = None;
+    for line in text.lines() {
+        let mut fields = line.split('\t');
+        match (fields.next(), fields.next()) {
+            (Some("TOTAL"), Some(count)) => total = Some(count.parse()?),
+            (Some("Codon"), Some(count)) => codon = Some(count.parse()?),
+            _ => (),
+        }
+    }
+    match (total, codon) {
+        (Some(total), Some(codon)) => Ok((total, codon)),
+        _ => bail!("Codon statistics lack TOTAL and Codon counts"),
+    }
+}
+
+/// Returns a table with one row for each comparison between stages,
+/// giving the reads lost between them and the fraction of upstream
+/// reads lost. The status is `OK`, or `GAINED` when the downstream
+/// stage has more reads than the upstream stage.
+pub fn check_table(samples: &BTreeMap<String, StageCounts>) -> String {
+    let mut table =
+        "sample\tupstream\tdownstream\tupstream_reads\tdownstream_reads\tlost\tlost_fraction\tstatus\n"
+            .to_string();
+    for (sample, counts) in samples.iter() {
+        for check in counts.checks() {
+            table += &format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                sample,
+                check.upstream,
+                check.downstream,
+                check.upstream_reads,
+                check.downstream_reads,
+                check.lost(),
+                if check.upstream_reads > 0 {
+                    format!("{:.4}", check.lost() as f64 / check.upstream_reads as f64)
+                } else {
+                    "NA".to_string()
+                },
+                if check.is_ok() { "OK" } else { "GAINED" }
+            );
+        }
+    }
+    table
+}
+
+fn stage_counts<'a>(
+    samples: &'a mut BTreeMap<String, StageCounts>,
+    file: &Path,
+    suffixes: &[&str],
+) -> &'a mut StageCounts {
+    samples
+        .entry(sample_name(file, suffixes))
+        .or_insert_with(StageCounts::default)
+}
+
+/// Collects the read counts for each sample from every statistics
+/// file. Samples are matched by the names in the `fastx-split` fates
+/// and by the filenames of the other statistics.
+pub fn read_stage_counts(config: &Config) -> Result<BTreeMap<String, StageCounts>, failure::Error> {
+    let mut samples: BTreeMap<String, StageCounts> = BTreeMap::new();
+
+    for file in config.fates.iter() {
+        let fates: SplitFates = read_text(file)?.parse()?;
+        for sample in fates.samples() {
+            let counts = samples
+                .entry(sample.name.clone())
+                .or_insert_with(StageCounts::default);
+            counts.split = Some(counts.split.unwrap_or(0) + sample.reads);
+        }
+    }
+
+    for file in config.dedup.iter() {
+        let stats = bam_suppress_duplicates::Stats::from_json(&read_text(file)?)?;
+        stage_counts(&mut samples, file, &DEDUP_SUFFIXES).dedup = Some((
+            stats.total_reads() + stats.untagged_reads(),
+            stats.unique_reads() + stats.untagged_reads(),
+        ));
+    }
+
+    for file in config.framing.iter() {
+        let stats = FramingStats::from_json(&read_text(file)?)?;
+        let align = stats.align_stats();
+        stage_counts(&mut samples, file, &[FRAMING_SUFFIX]).framing =
+            Some((align.total() as u64, align.good_total() as u64));
+    }
+
+    for file in config.codon_stats.iter() {
+        let codon = parse_codon_stats(&read_text(file)?)
+            .map_err(|err| failure::err_msg(format!("Reading {:?}: {}", file, err)))?;
+        stage_counts(&mut samples, file, &[CODON_STATS_SUFFIX]).codon = Some(codon);
+    }
+
+    Ok(samples)
+}
+
+pub fn run_check(config: Config) -> Result<(), failure::Error> {
+    let samples = read_stage_counts(&config)?;
+
+    let mut output: Box<Write> = if config.output == "-" {
+        Box::new(io::stdout())
+    } else {
+        Box::new(fs::File::create(&config.output)?)
+    };
+    output.write_all(check_table(&samples).as_bytes())?;
+
+    let mut gained = Vec::new();
+    for (sample, counts) in samples.iter() {
+        let checks = counts.checks();
+        if checks.is_empty() {
+            warn!("Sample {} has no statistics after sample splitting", sample);
+        }
+        for check in checks.iter() {
+            if check.is_ok() {
+                info!(
+                    "{}: lost {} of {} reads from {} to {}",
+                    sample,
+                    check.lost(),
+                    check.upstream_reads,
+                    check.upstream,
+                    check.downstream
+                );
+            } else {
+                gained.push(format!(
+                    "{} {} ({}) to {} ({})",
+                    sample,
+                    check.upstream,
+                    check.upstream_reads,
+                    check.downstream,
+                    check.downstream_reads
+                ));
+            }
+        }
+    }
+
+    if !gained.is_empty() {
+        bail!(
+            "Read counts increased between stages: {}",
+            gained.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stage_checks() {
+        assert_eq!(
+            parse_codon_stats("TOTAL\t90\nBamNoHit\t10\t0.1111\nCodon\t70\t0.7778\n").unwrap(),
+            (90, 70)
+        );
+        assert!(parse_codon_stats("TOTAL\t90\n").is_err());
+
+        let mut samples = BTreeMap::new();
+        samples.insert(
+            "wt".to_string(),
+            StageCounts {
+                split: Some(1000),
+                dedup: Some((800, 500)),
+                framing: Some((500, 400)),
+                codon: Some((600, 300)),
+            },
+        );
+        samples.insert(
+            "ko".to_string(),
+            StageCounts {
+                split: None,
+                dedup: None,
+                framing: Some((100, 80)),
+                codon: None,
+            },
+        );
+
+        let wt_checks = samples["wt"].checks();
+        assert_eq!(wt_checks.len(), 6);
+        assert_eq!(wt_checks.iter().filter(|check| !check.is_ok()).count(), 1);
+        assert_eq!(
+            check_table(&samples),
+            "sample\tupstream\tdownstream\tupstream_reads\tdownstream_reads\tlost\tlost_fraction\tstatus\n\
+             ko\tframing_input\tframing_good\t100\t80\t20\t0.2000\tOK\n\
+             wt\tsplit\tdedup_input\t1000\t800\t200\t0.2000\tOK\n\
+             wt\tdedup_input\tdedup_output\t800\t500\t300\t0.3750\tOK\n\
+             wt\tdedup_output\tframing_input\t500\t500\t0\t0.0000\tOK\n\
+             wt\tframing_input\tframing_good\t500\t400\t100\t0.2000\tOK\n\
+             wt\tdedup_output\tcodon_input\t500\t600\t-100\t-0.2000\tGAINED\n\
+             wt\tcodon_input\tcodon_assigned\t600\t300\t300\t0.5000\tOK\n"
+        );
+    }
+}
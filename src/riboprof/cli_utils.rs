@@ -0,0 +1,137 @@
+use std::error::Error;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str;
+
+use clap::{Arg, ArgMatches};
+use failure;
+
+use bam_utils::ReadFilterCLI;
+
+/// Parses a comma-separated pair of values, e.g., `"26,34"`, into a
+/// `Range`.
+///
+/// ```
+/// # use riboprof::cli_utils::*;
+/// assert_eq!(parse_pair::<isize>("-100,100").unwrap(), -100..100);
+/// assert!(parse_pair::<usize>("26").is_err());
+/// ```
+///
+/// # Errors
+///
+/// An error variant is returned when the string does not contain
+/// exactly two comma-separated values or when either value cannot be
+/// parsed.
+pub fn parse_pair<I>(pair_str: &str) -> Result<Range<I>, failure::Error>
+where
+    I: str::FromStr,
+    I::Err: Error + Send + Sized + Sync + 'static,
+{
+    let strs: Vec<&str> = pair_str.split(",").collect();
+    if strs.len() == 2 {
+        Ok(Range {
+            start: strs[0].parse()?,
+            end: strs[1].parse()?,
+        })
+    } else {
+        Err(failure::err_msg(format!(
+            "Expecting integer pair \"a,b\" but got \"{}\"",
+            pair_str
+        )))
+    }
+}
+
+/// Constructs an output filename by appending a suffix to the file
+/// name portion of a base path, e.g., `out/sample` and
+/// `_stats.txt` yield `out/sample_stats.txt`.
+pub fn output_filename(output: &Path, suffix: &str) -> PathBuf {
+    let mut name_base = output.file_name().map_or_else(
+        || "".to_string(),
+        |filename| filename.to_string_lossy().to_string(),
+    );
+    name_base += suffix;
+    let mut filepath = output.to_path_buf();
+    filepath.set_file_name(&name_base);
+    filepath
+}
+
+/// Returns a distinct sample name for each input, based on the file
+/// name without its directory or extension.
+pub fn sample_names(inputs: &[String]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+
+    for input in inputs.iter() {
+        let base = if input == "-" {
+            "stdin".to_string()
+        } else {
+            Path::new(input)
+                .file_stem()
+                .map_or(input.to_string(), |stem| {
+                    stem.to_string_lossy().into_owned()
+                })
+        };
+
+        let mut name = base.clone();
+        let mut n = 1;
+        while names.contains(&name) {
+            n += 1;
+            name = format!("{}_{}", base, n);
+        }
+        names.push(name);
+    }
+
+    names
+}
+
+/// Returns the command-line arguments for a `ReadFilter`, shared by
+/// all tools that read BAM files.
+pub fn read_filter_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("require-flags")
+            .long("require-flags")
+            .value_name("FLAGS")
+            .help("Only use alignments with all of these BAM flags set")
+            .takes_value(true),
+        Arg::with_name("exclude-flags")
+            .long("exclude-flags")
+            .value_name("FLAGS")
+            .help("Skip alignments with any of these BAM flags set")
+            .takes_value(true),
+        Arg::with_name("min-mapq")
+            .long("min-mapq")
+            .value_name("MAPQ")
+            .help("Skip alignments with mapping quality below MAPQ")
+            .takes_value(true),
+        Arg::with_name("read-lengths")
+            .long("read-lengths")
+            .value_name("MINLEN,MAXLEN")
+            .help("Skip reads with sequence length outside this range")
+            .takes_value(true),
+        Arg::with_name("max-hits")
+            .long("max-hits")
+            .value_name("N")
+            .help("Skip alignments of reads with more than N alignments (NH tag)")
+            .takes_value(true),
+        Arg::with_name("require-tag")
+            .long("require-tag")
+            .value_name("TAG[:VALUE]")
+            .help("Only use alignments with this tag, optionally having this value")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+    ]
+}
+
+/// Collects the arguments from `read_filter_args` after parsing.
+pub fn read_filter_cli(matches: &ArgMatches) -> ReadFilterCLI {
+    ReadFilterCLI {
+        require_flags: matches.value_of("require-flags").map(str::to_string),
+        exclude_flags: matches.value_of("exclude-flags").map(str::to_string),
+        min_mapq: matches.value_of("min-mapq").map(str::to_string),
+        read_lengths: matches.value_of("read-lengths").map(str::to_string),
+        max_hits: matches.value_of("max-hits").map(str::to_string),
+        require_tags: matches
+            .values_of_lossy("require-tag")
+            .unwrap_or_else(|| Vec::new()),
+    }
+}
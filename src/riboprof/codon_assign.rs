@@ -1,50 +1,146 @@
 use std::error::Error;
 use std::fmt;
-use std::fs;
+use std::io;
 use std::num::ParseIntError;
 use std::path::Path;
 use std::str::FromStr;
 
-use failure;
 use regex::Regex;
 
 use bio_types::annot::loc::*;
 use bio_types::annot::pos::*;
+use bio_types::annot::spliced::*;
 use bio_types::strand::*;
 //use rust_htslib::bam::record::Record;
 
-//use transcript::*;
+use output::*;
+use transcript::*;
 
-/// Mapping of A site positions within a footprint, based on fragment
-/// length.
+/// Ribosomal tRNA binding site within a footprint.
+#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub enum Site {
+    A,
+    P,
+    E,
+}
+
+impl Site {
+    /// Returns the distance in nucleotides from this site back to the
+    /// A site, which lies downstream of the P and E sites.
+    pub fn a_site_distance(&self) -> usize {
+        match self {
+            Site::A => 0,
+            Site::P => 3,
+            Site::E => 6,
+        }
+    }
+}
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Site::A => write!(f, "A"),
+            Site::P => write!(f, "P"),
+            Site::E => write!(f, "E"),
+        }
+    }
+}
+
+impl FromStr for Site {
+    type Err = ASiteParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "A" | "a" => Ok(Site::A),
+            "P" | "p" => Ok(Site::P),
+            "E" | "e" => Ok(Site::E),
+            _ => Err(ASiteParseError::BadSite(s.to_string())),
+        }
+    }
+}
+
+/// Mapping of A, P, and E site positions within a footprint, based on
+/// fragment length and, optionally, the reading frame of its 5'
+/// end. Offsets are specified for the A site, and the P and E sites
+/// lie one and two codons upstream of it.
 #[derive(Debug, Clone)]
-pub struct ASites {
-    a_site_offsets: Vec<Option<usize>>,
+pub struct SiteOffsets {
+    a_site_offsets: Vec<LenOffsets>,
+    missing: MissingOffset,
+    anchor: OffsetAnchor,
 }
 
-impl ASites {
-    /// Construct an `ASites` mapping based on a table of A site
+/// Former name of `SiteOffsets`, from when only A site offsets were
+/// supported.
+#[deprecated(note = "renamed to SiteOffsets")]
+pub type ASites = SiteOffsets;
+
+/// End of the footprint from which offsets in the table are measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetAnchor {
+    /// Offsets count forward from the 5'-most nucleotide
+    FivePrime,
+    /// Offsets count back from the 3'-most nucleotide
+    ThreePrime,
+}
+
+/// Treatment of fragment lengths with no A site offset in the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingOffset {
+    /// No offset, so that the fragment cannot be assigned
+    Drop,
+    /// A fixed default offset
+    Default(usize),
+    /// Linear interpolation between the nearest shorter and longer
+    /// lengths in the table, rounded to the nearest nucleotide
+    Interpolate,
+}
+
+/// A site offsets for one fragment length, either independent of
+/// reading frame or specific to the frame of the 5' end.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct LenOffsets {
+    any_frame: Option<usize>,
+    in_frame: [Option<usize>; 3],
+}
+
+impl SiteOffsets {
+    /// Construct a `SiteOffsets` mapping based on a table of A site
     /// offsets. The file is a tab-delimited table of length / offset
     /// pairs, e.g.,
     ///
     /// `27      14`
     /// `28      15`
     ///
+    /// Lines may also give a length / frame / offset triple, e.g.,
+    ///
+    /// `28      0       15`
+    /// `28      1       14`
+    ///
+    /// in order to specify an offset that applies only to fragments
+    /// whose 5' end lies in that reading frame. Frame-specific
+    /// offsets take precedence over a frame-independent offset for
+    /// the same length.
+    ///
     /// # Arguments
     ///
-    /// `path` specifies the path for the file to read
+    /// `path` specifies the path for the file to read, which may be
+    /// gzip compressed
     ///
     /// # Errors
     ///
-    /// An error variant is returned when an `io::Error` arises
-    /// reading the file or when an `ASiteParseError` arises in
-    /// parsing it.
-    pub fn new_from_file<P: AsRef<Path>>(path: P) -> Result<Self, failure::Error> {
-        Self::from_str(&fs::read_to_string(path)?).map_err(|e| e.into())
+    /// `ASiteParseError::Read` is returned when the file cannot be
+    /// read, and other variants when a line cannot be parsed.
+    pub fn new_from_file<P: AsRef<Path>>(path: P) -> Result<Self, ASiteParseError> {
+        let path = path.as_ref();
+        let table = read_text(path).map_err(|err| {
+            ASiteParseError::Read(path.to_string_lossy().into_owned(), err.kind())
+        })?;
+        Self::from_str(&table)
     }
 
-    /// Returns the A site offset within a footprint fragment as a
-    /// function of its length.
+    /// Returns the frame-independent A site offset within a footprint
+    /// fragment as a function of its length.
     ///
     /// # Arguments
     ///
@@ -54,7 +150,7 @@ impl ASites {
     /// # use riboprof::codon_assign::*;
     /// # use riboprof::codon_assign::ASiteParseError;
     /// # fn try_main() -> Result<(), Box<ASiteParseError>> {
-    /// let asites = "27\t14\n28\t15\n".parse::<ASites>()?;
+    /// let asites = "27\t14\n28\t15\n".parse::<SiteOffsets>()?;
     /// assert_eq!(asites.offset(26), None);
     /// assert_eq!(asites.offset(27), Some(14));
     /// assert_eq!(asites.offset(28), Some(15));
@@ -64,11 +160,205 @@ impl ASites {
     /// # fn main() { try_main().unwrap(); }
     /// ```
     pub fn offset(&self, len: usize) -> Option<usize> {
-        self.a_site_offsets
-            .get(len)
-            .unwrap_or(&None)
-            .as_ref()
-            .map(|o| *o)
+        self.offset_in_frame(len, None)
+    }
+
+    /// Returns the treatment of lengths missing from the table.
+    pub fn missing_offset(&self) -> MissingOffset {
+        self.missing
+    }
+
+    /// Sets the treatment of lengths missing from the table, which
+    /// is `MissingOffset::Drop` by default.
+    ///
+    /// ```
+    /// # use riboprof::codon_assign::*;
+    /// # use riboprof::codon_assign::ASiteParseError;
+    /// # fn try_main() -> Result<(), Box<ASiteParseError>> {
+    /// let mut asites = "27\t14\n30\t17\n33\t16\n".parse::<SiteOffsets>()?;
+    /// asites.set_missing_offset(MissingOffset::Default(15));
+    /// assert_eq!(asites.offset(26), Some(15));
+    /// assert_eq!(asites.offset(27), Some(14));
+    /// asites.set_missing_offset(MissingOffset::Interpolate);
+    /// assert_eq!(asites.offset(26), None);
+    /// assert_eq!(asites.offset(28), Some(15));
+    /// assert_eq!(asites.offset(29), Some(16));
+    /// assert_eq!(asites.offset(31), Some(17));
+    /// assert_eq!(asites.offset(32), Some(16));
+    /// assert_eq!(asites.offset(34), None);
+    /// # Ok(())
+    /// # }
+    /// # fn main() { try_main().unwrap(); }
+    /// ```
+    pub fn set_missing_offset(&mut self, missing: MissingOffset) {
+        self.missing = missing;
+    }
+
+    /// Returns the end of the footprint from which table offsets are
+    /// measured.
+    pub fn anchor(&self) -> OffsetAnchor {
+        self.anchor
+    }
+
+    /// Sets the end of the footprint from which table offsets are
+    /// measured, which is `OffsetAnchor::FivePrime` by default. With
+    /// a 3' anchor, an offset of 0 places the A site on the 3'-most
+    /// nucleotide, and offsets returned by `offset()` and related
+    /// functions are converted to count from the 5' end.
+    ///
+    /// ```
+    /// # use riboprof::codon_assign::*;
+    /// # use riboprof::codon_assign::ASiteParseError;
+    /// # fn try_main() -> Result<(), Box<ASiteParseError>> {
+    /// let mut asites = "27\t12\n28\t12\n".parse::<SiteOffsets>()?;
+    /// asites.set_anchor(OffsetAnchor::ThreePrime);
+    /// assert_eq!(asites.offset(27), Some(14));
+    /// assert_eq!(asites.offset(28), Some(15));
+    /// assert_eq!(asites.site_offset(28, None, Site::P), Some(12));
+    /// # Ok(())
+    /// # }
+    /// # fn main() { try_main().unwrap(); }
+    /// ```
+    pub fn set_anchor(&mut self, anchor: OffsetAnchor) {
+        self.anchor = anchor;
+    }
+
+    /// Returns the A site offset within a footprint fragment as a
+    /// function of its length and the reading frame of its 5'
+    /// end. When no frame-specific offset is given for the length,
+    /// or the frame is unknown, the frame-independent offset is
+    /// returned. When neither is given, the treatment of missing
+    /// lengths determines the offset.
+    ///
+    /// # Arguments
+    ///
+    /// `len` is the fragment length
+    ///
+    /// `frame` is the reading frame (0, 1, or 2) of the 5' end of
+    /// the fragment, if known
+    ///
+    /// ```
+    /// # use riboprof::codon_assign::*;
+    /// # use riboprof::codon_assign::ASiteParseError;
+    /// # fn try_main() -> Result<(), Box<ASiteParseError>> {
+    /// let asites = "27\t14\n28\t15\n28\t1\t14\n".parse::<SiteOffsets>()?;
+    /// assert_eq!(asites.offset_in_frame(27, Some(1)), Some(14));
+    /// assert_eq!(asites.offset_in_frame(28, Some(0)), Some(15));
+    /// assert_eq!(asites.offset_in_frame(28, Some(1)), Some(14));
+    /// assert_eq!(asites.offset_in_frame(28, None), Some(15));
+    /// assert_eq!(asites.offset_in_frame(29, Some(0)), None);
+    /// # Ok(())
+    /// # }
+    /// # fn main() { try_main().unwrap(); }
+    /// ```
+    pub fn offset_in_frame(&self, len: usize, frame: Option<usize>) -> Option<usize> {
+        let table_offset = self.table_offset(len, frame)?;
+        match self.anchor {
+            OffsetAnchor::FivePrime => Some(table_offset),
+            OffsetAnchor::ThreePrime => len.checked_sub(table_offset + 1),
+        }
+    }
+
+    fn table_offset(&self, len: usize, frame: Option<usize>) -> Option<usize> {
+        self.listed_offset(len, frame)
+            .or_else(|| match self.missing {
+                MissingOffset::Drop => None,
+                MissingOffset::Default(offset) => Some(offset),
+                MissingOffset::Interpolate => self.interpolated_offset(len, frame),
+            })
+    }
+
+    fn listed_offset(&self, len: usize, frame: Option<usize>) -> Option<usize> {
+        let len_offsets = self.a_site_offsets.get(len)?;
+        frame
+            .and_then(|fr| len_offsets.in_frame.get(fr).and_then(|off| *off))
+            .or(len_offsets.any_frame)
+    }
+
+    fn interpolated_offset(&self, len: usize, frame: Option<usize>) -> Option<usize> {
+        let (lo_len, lo_off) = (0..len)
+            .rev()
+            .filter_map(|l| self.listed_offset(l, frame).map(|off| (l, off)))
+            .next()?;
+        let (hi_len, hi_off) = ((len + 1)..self.a_site_offsets.len())
+            .filter_map(|l| self.listed_offset(l, frame).map(|off| (l, off)))
+            .next()?;
+
+        let fract = (len - lo_len) as f64 / (hi_len - lo_len) as f64;
+        let offset = lo_off as f64 + fract * (hi_off as f64 - lo_off as f64);
+        Some(offset.round() as usize)
+    }
+
+    /// Returns the offset of a site within a footprint fragment as a
+    /// function of its length and the reading frame of its 5'
+    /// end. `None` is returned when no A site offset is known, or
+    /// when the site would lie upstream of the 5' end of the fragment.
+    ///
+    /// # Arguments
+    ///
+    /// `len` is the fragment length
+    ///
+    /// `frame` is the reading frame of the 5' end of the fragment, if
+    /// known
+    ///
+    /// `site` is the ribosomal site
+    ///
+    /// ```
+    /// # use riboprof::codon_assign::*;
+    /// # use riboprof::codon_assign::ASiteParseError;
+    /// # fn try_main() -> Result<(), Box<ASiteParseError>> {
+    /// let offsets = "27\t14\n28\t5\n".parse::<SiteOffsets>()?;
+    /// assert_eq!(offsets.site_offset(27, None, Site::A), Some(14));
+    /// assert_eq!(offsets.site_offset(27, None, Site::P), Some(11));
+    /// assert_eq!(offsets.site_offset(27, None, Site::E), Some(8));
+    /// assert_eq!(offsets.site_offset(28, None, Site::E), None);
+    /// # Ok(())
+    /// # }
+    /// # fn main() { try_main().unwrap(); }
+    /// ```
+    pub fn site_offset(&self, len: usize, frame: Option<usize>, site: Site) -> Option<usize> {
+        self.offset_in_frame(len, frame)
+            .and_then(|off| off.checked_sub(site.a_site_distance()))
+    }
+
+    /// Returns the position of a site from a footprint location.
+    ///
+    /// # Arguments
+    ///
+    /// `fp` is the location of a footprint fragment
+    ///
+    /// `frame` is the reading frame of the 5' end of the footprint,
+    /// if known, used to select a frame-specific offset
+    ///
+    /// `site` is the ribosomal site
+    /// ```
+    /// # extern crate bio_types;
+    /// # extern crate riboprof;
+    /// # use std::error::Error;
+    /// # use riboprof::codon_assign::*;
+    /// # use bio_types::annot::pos::*;
+    /// # use bio_types::annot::contig::*;
+    /// # use bio_types::strand::*;
+    /// # fn try_main() -> Result<(), Box<Error>> {
+    /// let offsets = "27\t14\n".parse::<SiteOffsets>()?;
+    /// let fp1 = "chr2:300000-300027(+)".parse::<Contig<String,ReqStrand>>()?;
+    /// assert_eq!(offsets.site(fp1.clone(), None, Site::P), Some("chr2:300011(+)".parse::<Pos<String,ReqStrand>>()?));
+    /// let fp2 = "chr3:200000-200027(-)".parse::<Contig<String,ReqStrand>>()?;
+    /// assert_eq!(offsets.site(fp2, None, Site::E), Some("chr3:200018(-)".parse::<Pos<String,ReqStrand>>()?));
+    /// # Ok(())
+    /// # }
+    /// # fn main() { try_main().unwrap(); }
+    /// ```
+    pub fn site<L>(&self, fp: L, frame: Option<usize>, site: Site) -> Option<Pos<L::RefID, ReqStrand>>
+    where
+        L: Loc,
+        L::Strand: Into<ReqStrand> + Copy,
+        L::RefID: Clone,
+    {
+        match self.site_offset(fp.length(), frame, site) {
+            Some(offset) => fp.pos_outof(&Pos::new((), offset as isize, ReqStrand::Forward)),
+            None => None,
+        }
     }
 
     /// Returns the A site position from a footprint location.
@@ -76,6 +366,9 @@ impl ASites {
     /// # Arguments
     ///
     /// `fp` is the location of a footprint fragment
+    ///
+    /// `frame` is the reading frame of the 5' end of the footprint,
+    /// if known, used to select a frame-specific offset
     /// ```
     /// # extern crate bio_types;
     /// # extern crate riboprof;
@@ -86,34 +379,116 @@ impl ASites {
     /// # use bio_types::annot::contig::*;
     /// # use bio_types::strand::*;
     /// # fn try_main() -> Result<(), Box<Error>> {
-    /// let asites = "27\t14\n28\t15\n".parse::<ASites>()?;
+    /// let asites = "27\t14\n28\t15\n27\t2\t13\n".parse::<SiteOffsets>()?;
     /// let fp1 = "chr2:300000-300027(+)".parse::<Contig<String,ReqStrand>>()?;
-    /// assert_eq!(asites.a_site(fp1), Some("chr2:300014(+)".parse::<Pos<String,ReqStrand>>()?));
+    /// assert_eq!(asites.a_site(fp1.clone(), None), Some("chr2:300014(+)".parse::<Pos<String,ReqStrand>>()?));
+    /// assert_eq!(asites.a_site(fp1, Some(2)), Some("chr2:300013(+)".parse::<Pos<String,ReqStrand>>()?));
     /// let fp2 = "chr3:200000-200023(-)".parse::<Contig<String,ReqStrand>>()?;
-    /// assert_eq!(asites.a_site(fp2), None);
+    /// assert_eq!(asites.a_site(fp2, None), None);
     /// # Ok(())
     /// # }
     /// # fn main() { try_main().unwrap(); }
     /// ```
-    pub fn a_site<L>(&self, fp: L) -> Option<Pos<L::RefID, ReqStrand>>
+    pub fn a_site<L>(&self, fp: L, frame: Option<usize>) -> Option<Pos<L::RefID, ReqStrand>>
     where
         L: Loc,
         L::Strand: Into<ReqStrand> + Copy,
         L::RefID: Clone,
     {
-        match self.offset(fp.length()) {
-            Some(offset) => fp.pos_outof(&Pos::new((), offset as isize, ReqStrand::Forward)),
-            None => None,
+        self.site(fp, frame, Site::A)
+    }
+
+    /// Returns the P site position from a footprint location, one
+    /// codon upstream of the A site.
+    pub fn p_site<L>(&self, fp: L, frame: Option<usize>) -> Option<Pos<L::RefID, ReqStrand>>
+    where
+        L: Loc,
+        L::Strand: Into<ReqStrand> + Copy,
+        L::RefID: Clone,
+    {
+        self.site(fp, frame, Site::P)
+    }
+
+    /// Returns the E site position from a footprint location, two
+    /// codons upstream of the A site.
+    pub fn e_site<L>(&self, fp: L, frame: Option<usize>) -> Option<Pos<L::RefID, ReqStrand>>
+    where
+        L: Loc,
+        L::Strand: Into<ReqStrand> + Copy,
+        L::RefID: Clone,
+    {
+        self.site(fp, frame, Site::E)
+    }
+
+    /// Returns the position of a site for a spliced footprint
+    /// aligned to a transcript, both in genomic coordinates and in
+    /// transcript coordinates. The site is projected along the
+    /// transcript, so that it lies in the correct exon when the
+    /// footprint spans a splice junction. The reading frame of the
+    /// footprint 5' end is determined from the transcript CDS, if
+    /// any, to select frame-specific offsets.
+    ///
+    /// `None` is returned when the footprint is not compatible with
+    /// the splicing of the transcript, when there is no offset for
+    /// its length, or when the site lies beyond the transcript.
+    ///
+    /// # Arguments
+    ///
+    /// `trx` is the transcript
+    ///
+    /// `fp` is the location of a footprint fragment
+    ///
+    /// `site` is the ribosomal site
+    pub fn site_positions<'a, R>(
+        &self,
+        trx: &'a Transcript<R>,
+        fp: &Spliced<R, ReqStrand>,
+        site: Site,
+    ) -> Option<SitePositions<'a, R>>
+    where
+        R: Clone + Eq,
+    {
+        if !splice_compatible(trx.loc(), fp) {
+            return None;
         }
+
+        let fp_start = TrxPos::from_genomic_pos(trx, &fp.first_pos())?;
+        let offset = self.site_offset(fp.exon_total_length(), fp_start.cds_frame(), site)?;
+
+        let site_pos = fp_start.pos() + offset;
+        let genomic = trx
+            .loc()
+            .pos_outof(&Pos::new((), site_pos as isize, ReqStrand::Forward))?;
+
+        Some(SitePositions {
+            genomic: genomic,
+            trx_pos: TrxPos::new(trx, site_pos),
+        })
     }
 }
 
-impl FromStr for ASites {
+/// Position of a footprint site in genomic coordinates and in the
+/// coordinates of a transcript.
+pub struct SitePositions<'a, R: 'a> {
+    genomic: Pos<R, ReqStrand>,
+    trx_pos: TrxPos<'a, R>,
+}
+
+impl<'a, R: 'a> SitePositions<'a, R> {
+    pub fn genomic(&self) -> &Pos<R, ReqStrand> {
+        &self.genomic
+    }
+    pub fn trx_pos(&self) -> &TrxPos<'a, R> {
+        &self.trx_pos
+    }
+}
+
+impl FromStr for SiteOffsets {
     type Err = ASiteParseError;
 
     fn from_str(table: &str) -> Result<Self, Self::Err> {
-        let mut offsets = Vec::new();
-        let re = Regex::new("^(\\d+)\t(\\d+)$").unwrap();
+        let mut offsets: Vec<LenOffsets> = Vec::new();
+        let re = Regex::new("^(\\d+)\t(\\d+)(?:\t(\\d+))?$").unwrap();
 
         for line in table.lines().map(str::trim_right) {
             let cap = re
@@ -122,27 +497,49 @@ impl FromStr for ASites {
             let len = cap[1]
                 .parse::<usize>()
                 .map_err(|e| ASiteParseError::BadLength(e, cap[1].to_owned()))?;
-            let off = cap[2]
+
+            let (frame, off_str) = match cap.get(3) {
+                Some(off_match) => {
+                    let frame = cap[2]
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|fr| *fr < 3)
+                        .ok_or_else(|| ASiteParseError::BadFrame(cap[2].to_owned()))?;
+                    (Some(frame), off_match.as_str())
+                }
+                None => (None, &cap[2]),
+            };
+
+            let off = off_str
                 .parse::<usize>()
-                .map_err(|e| ASiteParseError::BadOffset(e, cap[1].to_owned()))?;
+                .map_err(|e| ASiteParseError::BadOffset(e, off_str.to_owned()))?;
 
             while offsets.len() <= len {
-                offsets.push(None);
+                offsets.push(LenOffsets::default());
+            }
+
+            match frame {
+                Some(fr) => offsets[len].in_frame[fr] = Some(off),
+                None => offsets[len].any_frame = Some(off),
             }
-            offsets[len] = Some(off);
         }
 
-        Ok(ASites {
+        Ok(SiteOffsets {
             a_site_offsets: offsets,
+            missing: MissingOffset::Drop,
+            anchor: OffsetAnchor::FivePrime,
         })
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ASiteParseError {
+    Read(String, io::ErrorKind),
     BadLine(String),
     BadLength(ParseIntError, String),
     BadOffset(ParseIntError, String),
+    BadFrame(String),
+    BadSite(String),
 }
 
 impl Error for ASiteParseError {}
@@ -150,6 +547,12 @@ impl Error for ASiteParseError {}
 impl fmt::Display for ASiteParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
+            ASiteParseError::Read(path, kind) => write!(
+                f,
+                "Reading A sites file {}: {}",
+                path,
+                io::Error::from(*kind)
+            ),
             ASiteParseError::BadLine(line) => write!(f, "Bad A sites line: \"{}\"", &line),
             ASiteParseError::BadLength(err, line) => {
                 write!(f, "Error parsing length \"{}\": {}", line, err)
@@ -157,22 +560,20 @@ impl fmt::Display for ASiteParseError {
             ASiteParseError::BadOffset(err, line) => {
                 write!(f, "Error parsing offset \"{}\": {}", line, err)
             }
+            ASiteParseError::BadFrame(line) => write!(f, "Bad frame \"{}\"", line),
+            ASiteParseError::BadSite(site) => write!(f, "Bad site \"{}\", expected A, P, or E", site),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    extern crate csv;
-
     use super::*;
 
-    use std::cell::*;
+    use std::hash::Hash;
     use std::ops::*;
     use std::rc::*;
 
-    use self::csv::Error;
-
     use bio::io::bed;
     use bio_types::annot::refids::*;
 
@@ -323,4 +724,74 @@ chr03	500	1500	EEE	0	+	600	1200	0	2	250,450	0,550
         assert_eq!(trxpos_at_pos(&tome, "chr03:1500(+)"), none);
         assert_eq!(trxpos_at_pos(&tome, "chr03:2000(+)"), none);
     }
+
+    fn transcript_named<'a>(
+        tome: &'a Transcriptome<Rc<String>>,
+        fp: &Spliced<Rc<String>, ReqStrand>,
+        name: &str,
+    ) -> &'a Transcript<Rc<String>> {
+        tome.find_at_loc(fp)
+            .find(|trx| trx.trxname() == name)
+            .expect("Finding transcript")
+    }
+
+    #[test]
+    fn spliced_site_fwd() {
+        let beds = "\
+chr01	1000	1300	AAA	0	+	1050	1250	0	2	100,100,	0,200,
+";
+        let tome = transcriptome_from_str(&beds);
+        let offsets: SiteOffsets = "27\t14\n28\t3\n".parse().unwrap();
+
+        let fp: Spliced<Rc<String>, ReqStrand> = "chr01:1090-1100;1200-1217(+)".parse().unwrap();
+        let trx = transcript_named(&tome, &fp, "AAA");
+
+        let a_site = offsets.a_site(fp.clone(), None).unwrap();
+        assert_eq!(format!("{}", a_site), "chr01:1204(+)");
+
+        let positions = offsets.site_positions(trx, &fp, Site::A).unwrap();
+        assert_eq!(positions.genomic(), &a_site);
+        assert_eq!(positions.trx_pos().pos(), 104);
+        assert_eq!(positions.trx_pos().cds_frame(), Some(0));
+
+        let positions = offsets.site_positions(trx, &fp, Site::E).unwrap();
+        assert_eq!(format!("{}", positions.genomic()), "chr01:1098(+)");
+        assert_eq!(positions.trx_pos().pos(), 98);
+
+        let unspliced: Spliced<Rc<String>, ReqStrand> = "chr01:1090-1117(+)".parse().unwrap();
+        assert!(offsets.site_positions(trx, &unspliced, Site::A).is_none());
+
+        let fp28: Spliced<Rc<String>, ReqStrand> = "chr01:1090-1100;1200-1218(+)".parse().unwrap();
+        assert!(offsets.site_positions(trx, &fp28, Site::A).is_some());
+        assert!(offsets.site_positions(trx, &fp28, Site::P).is_some());
+        assert!(offsets.site_positions(trx, &fp28, Site::E).is_none());
+    }
+
+    #[test]
+    fn spliced_site_rev() {
+        let beds = "\
+chr01	1000	1300	BBB	0	-	1050	1250	0	2	100,100,	0,200,
+";
+        let tome = transcriptome_from_str(&beds);
+        let offsets: SiteOffsets = "27\t14\n28\t20\n".parse().unwrap();
+
+        let fp: Spliced<Rc<String>, ReqStrand> = "chr01:1090-1100;1200-1217(-)".parse().unwrap();
+        let trx = transcript_named(&tome, &fp, "BBB");
+
+        let a_site = offsets.a_site(fp.clone(), None).unwrap();
+        assert_eq!(format!("{}", a_site), "chr01:1202(-)");
+
+        let positions = offsets.site_positions(trx, &fp, Site::A).unwrap();
+        assert_eq!(positions.genomic(), &a_site);
+        assert_eq!(positions.trx_pos().pos(), 97);
+
+        let fp28: Spliced<Rc<String>, ReqStrand> = "chr01:1089-1100;1200-1217(-)".parse().unwrap();
+        let positions = offsets.site_positions(trx, &fp28, Site::A).unwrap();
+        assert_eq!(format!("{}", positions.genomic()), "chr01:1096(-)");
+        assert_eq!(positions.trx_pos().pos(), 103);
+        assert_eq!(
+            offsets.a_site(fp28, None),
+            Some(positions.genomic().clone())
+        );
+    }
 }
@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+use std::ops::Range;
+use std::rc::Rc;
+
+use failure;
+
+use bio_types::annot::loc::Loc;
+use bio_types::annot::pos::Pos;
+use bio_types::annot::spliced::Spliced;
+use bio_types::strand::*;
+use rust_htslib::bam;
+
+use bam_utils::*;
+use codon_assign::*;
+use transcript::*;
+
+/// Assigns the chosen ribosomal site of a BAM record to a codon,
+/// applying the same multi-mapping and length filters as framing
+/// analysis.
+pub fn record_codon(
+    trxome: &Transcriptome<Rc<String>>,
+    rep_trxnames: &HashSet<Rc<String>>,
+    offsets: &SiteOffsets,
+    site: Site,
+    rec: &bam::Record,
+    fp: Option<Spliced<Rc<String>, ReqStrand>>,
+    lengths: &Range<usize>,
+    count_multi: bool,
+) -> Result<BamCodonResult, failure::Error> {
+    if !(is_single_hit(rec) || (count_multi && is_first_hit(rec))) {
+        return Ok(BamCodonResult::MultiHit);
+    }
+
+    if let Some(fp) = fp {
+        let fp_len = fp.exon_total_length();
+
+        if fp_len < lengths.start {
+            return Ok(BamCodonResult::TooShort);
+        } else if fp_len > lengths.end {
+            return Ok(BamCodonResult::TooLong);
+        }
+
+        let site_pos = offsets.site(fp.clone(), None, site);
+
+        Ok(BamCodonResult::Fp(
+            footprint_codon(trxome, rep_trxnames, offsets, site, &fp),
+            site_pos,
+        ))
+    } else {
+        Ok(BamCodonResult::NoHit)
+    }
+}
+
+/// Codon assignment for a BAM record. A footprint passing the
+/// alignment filters also carries the genomic position of its site,
+/// when a frame-independent offset is known for its length, since
+/// the reading frame is only defined relative to a CDS.
+pub enum BamCodonResult {
+    NoHit,
+    MultiHit,
+    TooShort,
+    TooLong,
+    Fp(FpCodonResult, Option<Pos<Rc<String>, ReqStrand>>),
+}
+
+/// Assigns the chosen ribosomal site of a footprint to a codon in the
+/// representative transcript of the (unique) coding gene that it
+/// overlaps.
+///
+/// # Arguments
+///
+/// * `rep_trxnames` is the set of representative transcripts, at
+/// most one per gene, against which codon positions are reported
+pub fn footprint_codon(
+    trxome: &Transcriptome<Rc<String>>,
+    rep_trxnames: &HashSet<Rc<String>>,
+    offsets: &SiteOffsets,
+    site: Site,
+    fp: &Spliced<Rc<String>, ReqStrand>,
+) -> FpCodonResult {
+    let fp_len = fp.exon_total_length();
+    if (0..3).all(|fr| offsets.site_offset(fp_len, Some(fr), site).is_none()) {
+        return FpCodonResult::NoOffset(fp_len);
+    }
+
+    let gene_sets = Transcript::group_by_gene(
+        trxome
+            .find_at_loc(fp)
+            .filter(|trx| trx.loc().strand() == fp.strand()),
+    );
+
+    if gene_sets.is_empty() {
+        return FpCodonResult::NoGene;
+    }
+
+    let mut coding_sets = gene_sets
+        .into_iter()
+        .filter(|(_gene, trxs)| trxs.iter().any(|trx| trx.is_coding()));
+
+    match (coding_sets.next(), coding_sets.next()) {
+        (None, _) => FpCodonResult::NoncodingOnly,
+        (Some(_), Some(_)) => FpCodonResult::MultiCoding,
+        (Some((_gene, trxs)), None) => {
+            match trxs
+                .into_iter()
+                .find(|trx| rep_trxnames.contains(trx.trxname_ref()))
+            {
+                Some(trx) => transcript_codon(trx, fp, offsets, site),
+                None => FpCodonResult::NoCompatible,
+            }
+        }
+    }
+}
+
+/// Assigns the chosen ribosomal site of a footprint to a codon of a
+/// transcript.
+///
+/// # Arguments
+///
+/// * `trx` is the transcript, which must be coding
+/// * `fp` is the footprint location
+/// * `offsets` gives the site offset from the 5' end of the
+/// footprint, which may depend on the reading frame of the 5' end
+/// relative to the CDS
+/// * `site` is the ribosomal site assigned to a codon
+pub fn transcript_codon(
+    trx: &Transcript<Rc<String>>,
+    fp: &Spliced<Rc<String>, ReqStrand>,
+    offsets: &SiteOffsets,
+    site: Site,
+) -> FpCodonResult {
+    if !splice_compatible(trx.loc(), fp) {
+        return FpCodonResult::NoCompatible;
+    }
+
+    let cds = match trx.cds_range() {
+        Some(cds) => cds,
+        None => return FpCodonResult::NoncodingOnly,
+    };
+    let codon_start = cds.start + trx.cds_phase();
+
+    // With a compatible footprint, no site position means either no
+    // offset for its length and frame or a site past the transcript end.
+    let site_pos = match offsets.site_positions(trx, fp, site) {
+        Some(positions) => positions.trx_pos().pos(),
+        None => {
+            let frame = TrxPos::from_genomic_pos(trx, &fp.first_pos()).and_then(|p| p.cds_frame());
+            if offsets
+                .site_offset(fp.exon_total_length(), frame, site)
+                .is_some()
+            {
+                return FpCodonResult::OutsideCds;
+            } else {
+                return FpCodonResult::NoOffset(fp.exon_total_length());
+            }
+        }
+    };
+
+    if site_pos < codon_start || site_pos >= cds.end {
+        FpCodonResult::OutsideCds
+    } else {
+        FpCodonResult::Codon(CodonHit {
+            gene: trx.gene_ref().clone(),
+            codon: (site_pos - codon_start) / 3,
+        })
+    }
+}
+
+pub enum FpCodonResult {
+    Codon(CodonHit),
+    /// No site offset is known for the footprint length
+    NoOffset(usize),
+    NoGene,
+    NoncodingOnly,
+    MultiCoding,
+    NoCompatible,
+    OutsideCds,
+}
+
+/// Codon assignment for a ribosomal site of a footprint, as a codon index
+/// relative to the first complete codon of the gene's representative
+/// transcript.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CodonHit {
+    gene: Rc<String>,
+    codon: usize,
+}
+
+impl CodonHit {
+    pub fn gene(&self) -> &Rc<String> {
+        &self.gene
+    }
+    pub fn codon(&self) -> usize {
+        self.codon
+    }
+}
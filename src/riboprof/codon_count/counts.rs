@@ -0,0 +1,545 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use failure;
+
+use normalization::*;
+use transcript::*;
+
+use codon_count::assign::*;
+
+/// Treatment of footprints whose site falls on the codons at the
+/// start or the end of a CDS in gene-level quantification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalPolicy {
+    /// Count terminal codons along with the rest of the CDS
+    Include,
+    /// Leave terminal codons out of gene-level counts
+    Exclude,
+    /// Leave terminal codons out of gene-level counts, but report
+    /// them separately for each gene
+    Separate,
+}
+
+impl FromStr for TerminalPolicy {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "include" => Ok(TerminalPolicy::Include),
+            "exclude" => Ok(TerminalPolicy::Exclude),
+            "separate" => Ok(TerminalPolicy::Separate),
+            _ => bail!(
+                "Unknown terminal codon policy \"{}\", expecting include, exclude, or separate",
+                s
+            ),
+        }
+    }
+}
+
+/// Windows of codons at the start and the end of each CDS, and their
+/// treatment in gene-level quantification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerminalCodons {
+    /// Number of codons in the start codon window
+    pub start: usize,
+    /// Number of codons in the stop codon window
+    pub end: usize,
+    pub policy: TerminalPolicy,
+}
+
+impl TerminalCodons {
+    /// Returns the number of codons left out of gene-level counts at
+    /// the start and the end of each CDS.
+    pub fn excluded(&self) -> (usize, usize) {
+        match self.policy {
+            TerminalPolicy::Include => (0, 0),
+            TerminalPolicy::Exclude | TerminalPolicy::Separate => (self.start, self.end),
+        }
+    }
+}
+
+/// Per-codon footprint counts for one gene, in the coordinates of
+/// its representative transcript.
+pub struct GeneCodonCounts {
+    gene: Rc<String>,
+    trxname: Rc<String>,
+    codon_start: usize,
+    counts: Vec<usize>,
+}
+
+impl GeneCodonCounts {
+    /// Creates empty codon counts for a coding transcript.
+    pub fn new(trx: &Transcript<Rc<String>>) -> Self {
+        let (codon_start, n_codons) = match trx.cds_range() {
+            Some(cds) => {
+                let codon_start = cds.start + trx.cds_phase();
+                (codon_start, (cds.end - codon_start) / 3)
+            }
+            None => (0, 0),
+        };
+
+        GeneCodonCounts {
+            gene: trx.gene_ref().clone(),
+            trxname: trx.trxname_ref().clone(),
+            codon_start: codon_start,
+            counts: vec![0; n_codons],
+        }
+    }
+
+    pub fn gene(&self) -> &str {
+        &self.gene
+    }
+
+    pub fn trxname(&self) -> &str {
+        &self.trxname
+    }
+
+    /// Returns the transcript position of the first complete codon.
+    pub fn codon_start(&self) -> usize {
+        self.codon_start
+    }
+
+    /// Returns the transcript position of the first base of a codon.
+    pub fn codon_trxpos(&self, codon: usize) -> usize {
+        self.codon_start + 3 * codon
+    }
+
+    pub fn counts(&self) -> &[usize] {
+        &self.counts
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Returns the counts on codons remaining after excluding codons
+    /// at the start and the end of the CDS, which may be empty.
+    ///
+    /// # Arguments
+    ///
+    /// * `exclude_start` is the number of codons excluded at the start
+    /// * `exclude_end` is the number of codons excluded at the end
+    pub fn window_counts(&self, exclude_start: usize, exclude_end: usize) -> &[usize] {
+        let end = self.counts.len().saturating_sub(exclude_end);
+        if exclude_start < end {
+            &self.counts[exclude_start..end]
+        } else {
+            &[]
+        }
+    }
+
+    /// Returns the total counts in the start codon window, the rest
+    /// of the CDS, and the stop codon window. When the windows
+    /// overlap in a short CDS, the start codon window takes
+    /// precedence.
+    pub fn region_totals(&self, terminal: &TerminalCodons) -> (usize, usize, usize) {
+        let len = self.counts.len();
+        let start_end = terminal.start.min(len);
+        let end_start = len.saturating_sub(terminal.end).max(start_end);
+        (
+            self.counts[..start_end].iter().sum(),
+            self.counts[start_end..end_start].iter().sum(),
+            self.counts[end_start..].iter().sum(),
+        )
+    }
+}
+
+/// Per-codon footprint counts for all coding genes.
+pub struct CodonCounts {
+    genes: HashMap<Rc<String>, GeneCodonCounts>,
+}
+
+impl CodonCounts {
+    /// Creates empty codon counts for each representative transcript.
+    pub fn new<'a, I>(rep_trxs: I) -> Self
+    where
+        I: Iterator<Item = &'a Transcript<Rc<String>>>,
+    {
+        CodonCounts {
+            genes: rep_trxs
+                .map(|trx| (trx.gene_ref().clone(), GeneCodonCounts::new(trx)))
+                .collect(),
+        }
+    }
+
+    /// Tallies one codon assignment. Assignments to unknown genes or
+    /// beyond the last codon are ignored.
+    pub fn tally(&mut self, hit: &CodonHit) {
+        if let Some(gene_counts) = self.genes.get_mut(hit.gene()) {
+            if let Some(ct) = gene_counts.counts.get_mut(hit.codon()) {
+                *ct += 1;
+            }
+        }
+    }
+
+    /// Returns the counts for one gene, if it has a representative
+    /// transcript.
+    pub fn gene_counts(&self, gene: &Rc<String>) -> Option<&GeneCodonCounts> {
+        self.genes.get(gene)
+    }
+
+    /// Returns the per-gene counts, sorted by gene name.
+    pub fn genes(&self) -> Vec<&GeneCodonCounts> {
+        let mut genes: Vec<&GeneCodonCounts> = self.genes.values().collect();
+        genes.sort_by(|g1, g2| g1.gene().cmp(g2.gene()));
+        genes
+    }
+
+    /// Returns a long-format table of per-codon counts, listing each
+    /// codon of every gene with at least one footprint.
+    pub fn codon_table(&self) -> String {
+        let mut table = "gene\ttranscript\tcodon\ttrxpos\tcount\n".to_string();
+
+        for gene_counts in self.genes().into_iter().filter(|g| g.total() > 0) {
+            for (codon, ct) in gene_counts.counts().iter().enumerate() {
+                table += &format!(
+                    "{}\t{}\t{}\t{}\t{}\n",
+                    gene_counts.gene(),
+                    gene_counts.trxname(),
+                    codon,
+                    gene_counts.codon_trxpos(codon),
+                    ct
+                );
+            }
+        }
+
+        table
+    }
+
+    /// Returns a table of gene-level expression, with one row for
+    /// each gene giving the number of codons quantified, the raw
+    /// count on those codons, and RPKM and TPM normalized by the
+    /// quantified length of the CDS. With `TerminalPolicy::Separate`,
+    /// the counts in the start and stop codon windows follow.
+    ///
+    /// # Arguments
+    ///
+    /// * `library_size` chooses the library size for RPKM, where the
+    /// classified reads are those on the quantified codons
+    /// * `mapped` is the number of aligned reads in the sample
+    pub fn gene_table(
+        &self,
+        terminal: &TerminalCodons,
+        library_size: &LibrarySize,
+        mapped: usize,
+    ) -> String {
+        let genes = self.genes();
+        let (exclude_start, exclude_end) = terminal.excluded();
+        let separate = terminal.policy == TerminalPolicy::Separate;
+
+        let windows: Vec<&[usize]> = genes
+            .iter()
+            .map(|g| g.window_counts(exclude_start, exclude_end))
+            .collect();
+        let total: usize = windows.iter().map(|w| w.iter().sum::<usize>()).sum();
+        let rpkm = NormalizeConfig::new()
+            .units(CountUnits::Rpkm)
+            .library_size(*library_size);
+        let library = LibraryCounts::new(mapped as f64, total as f64);
+
+        // TPM scales densities to a million per the summed density of
+        // all genes, whatever the library size.
+        let total_density: f64 = windows.iter().map(|w| density(w)).sum();
+        let tpm_scale = if total_density > 0.0 {
+            1.0e6 / total_density
+        } else {
+            1.0
+        };
+
+        let mut table = "gene\ttranscript\tcodons\tcount\trpkm\ttpm".to_string();
+        table += if separate {
+            "\tstart_count\tstop_count\n"
+        } else {
+            "\n"
+        };
+
+        for (gene_counts, window) in genes.iter().zip(windows.iter()) {
+            let count: usize = window.iter().sum();
+            let scale = rpkm.scale(&library, 3 * window.len());
+
+            table += &format!(
+                "{}\t{}\t{}\t{}\t{:.4}\t{:.4}",
+                gene_counts.gene(),
+                gene_counts.trxname(),
+                window.len(),
+                count,
+                count as f64 * scale,
+                density(window) * tpm_scale
+            );
+            if separate {
+                let (start_count, _, stop_count) = gene_counts.region_totals(terminal);
+                table += &format!("\t{}\t{}", start_count, stop_count);
+            }
+            table += "\n";
+        }
+
+        table
+    }
+
+    /// Returns a table of the total count in the start codon window,
+    /// the rest of the CDS, and the stop codon window across all
+    /// genes, along with the fraction of all codon counts in each
+    /// region and whether it is counted in gene-level quantification.
+    pub fn region_table(&self, terminal: &TerminalCodons) -> String {
+        let (mut start, mut body, mut stop) = (0, 0, 0);
+        for gene_counts in self.genes.values() {
+            let (gene_start, gene_body, gene_stop) = gene_counts.region_totals(terminal);
+            start += gene_start;
+            body += gene_body;
+            stop += gene_stop;
+        }
+        let total = (start + body + stop).max(1) as f64;
+        let terminal_status = match terminal.policy {
+            TerminalPolicy::Include => "included",
+            TerminalPolicy::Exclude => "excluded",
+            TerminalPolicy::Separate => "separate",
+        };
+
+        let mut table = "region\tcodons\tcount\tfraction\tquantification\n".to_string();
+        for &(region, codons, count, status) in [
+            ("start", terminal.start, start, terminal_status),
+            ("body", 0, body, "included"),
+            ("stop", terminal.end, stop, terminal_status),
+        ]
+        .iter()
+        {
+            table += &format!(
+                "{}\t{}\t{}\t{:.4}\t{}\n",
+                region,
+                if region == "body" {
+                    "NA".to_string()
+                } else {
+                    codons.to_string()
+                },
+                count,
+                count as f64 / total,
+                status
+            );
+        }
+        table
+    }
+}
+
+/// Header of the long-format table of per-codon counts for several
+/// samples, whose rows are given by `sample_codon_rows()`.
+pub const SAMPLE_CODON_HEADER: &str = "sample\tgene\ttranscript\tcodon\ttrxpos\tcount\n";
+
+/// Returns the rows for one sample of a long-format table of
+/// per-codon counts for several samples, with a leading sample
+/// column, listing each codon of every gene with at least one
+/// footprint in that sample.
+pub fn sample_codon_rows(sample: &str, codon_counts: &CodonCounts) -> String {
+    let mut table = String::new();
+
+    for gene_counts in codon_counts.genes().into_iter().filter(|g| g.total() > 0) {
+        for (codon, ct) in gene_counts.counts().iter().enumerate() {
+            table += &format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\n",
+                sample,
+                gene_counts.gene(),
+                gene_counts.trxname(),
+                codon,
+                gene_counts.codon_trxpos(codon),
+                ct
+            );
+        }
+    }
+
+    table
+}
+
+/// Matrix of gene-level counts with one row per gene and one column
+/// per sample, built up one sample at a time so that only the gene
+/// totals of earlier samples are kept. The samples must share the
+/// same representative transcripts.
+#[derive(Debug, Clone)]
+pub struct GeneMatrix {
+    terminal: TerminalCodons,
+    samples: Vec<String>,
+    genes: Vec<(Rc<String>, Rc<String>)>,
+    counts: Vec<Vec<usize>>,
+}
+
+impl GeneMatrix {
+    /// Creates an empty matrix. The codons given by `terminal` at the
+    /// start and the end of each CDS are left out of the counts, and
+    /// are never reported separately here.
+    pub fn new(terminal: &TerminalCodons) -> Self {
+        GeneMatrix {
+            terminal: *terminal,
+            samples: Vec::new(),
+            genes: Vec::new(),
+            counts: Vec::new(),
+        }
+    }
+
+    /// Adds a column of gene-level counts for `sample`.
+    pub fn add_sample(&mut self, sample: &str, codon_counts: &CodonCounts) {
+        let (exclude_start, exclude_end) = self.terminal.excluded();
+        let genes = codon_counts.genes();
+
+        if self.samples.is_empty() {
+            self.genes = genes
+                .iter()
+                .map(|gene_counts| (gene_counts.gene.clone(), gene_counts.trxname.clone()))
+                .collect();
+            self.counts = vec![Vec::new(); genes.len()];
+        }
+        self.samples.push(sample.to_string());
+
+        for (gene_counts, counts) in genes.iter().zip(self.counts.iter_mut()) {
+            counts.push(
+                gene_counts
+                    .window_counts(exclude_start, exclude_end)
+                    .iter()
+                    .sum(),
+            );
+        }
+    }
+
+    /// Returns the matrix as a table with a header row of sample
+    /// names.
+    pub fn table(&self) -> String {
+        let mut table = "gene\ttranscript".to_string();
+        for sample in self.samples.iter() {
+            table += &format!("\t{}", sample);
+        }
+        table += "\n";
+
+        for ((gene, trxname), counts) in self.genes.iter().zip(self.counts.iter()) {
+            table += &format!("{}\t{}", gene, trxname);
+            for count in counts.iter() {
+                table += &format!("\t{}", count);
+            }
+            table += "\n";
+        }
+
+        table
+    }
+}
+
+/// Footprint density per nucleotide over a window of codons.
+fn density(window: &[usize]) -> f64 {
+    if window.is_empty() {
+        0.0
+    } else {
+        window.iter().sum::<usize>() as f64 / (3.0 * window.len() as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gene_counts(gene: &str, counts: Vec<usize>) -> GeneCodonCounts {
+        GeneCodonCounts {
+            gene: Rc::new(gene.to_string()),
+            trxname: Rc::new(format!("{}.1", gene)),
+            codon_start: 50,
+            counts: counts,
+        }
+    }
+
+    #[test]
+    fn gene_windows() {
+        let gene = gene_counts("AAA", vec![5, 1, 2, 3, 4, 9]);
+        assert_eq!(gene.window_counts(0, 0), &[5, 1, 2, 3, 4, 9]);
+        assert_eq!(gene.window_counts(1, 1), &[1, 2, 3, 4]);
+        assert_eq!(gene.window_counts(3, 3), &[] as &[usize]);
+        assert_eq!(gene.window_counts(4, 4), &[] as &[usize]);
+        assert_eq!(gene.codon_trxpos(2), 56);
+    }
+
+    #[test]
+    fn sample_matrix() {
+        let sample_counts = |cts: Vec<usize>| {
+            let mut genes = HashMap::new();
+            let gene = gene_counts("AAA", cts);
+            genes.insert(gene.gene.clone(), gene);
+            CodonCounts { genes: genes }
+        };
+        let s1 = sample_counts(vec![1, 2, 3]);
+        let s2 = sample_counts(vec![0, 0, 0]);
+
+        let mut matrix = GeneMatrix::new(&TerminalCodons {
+            start: 0,
+            end: 1,
+            policy: TerminalPolicy::Exclude,
+        });
+        matrix.add_sample("s1", &s1);
+        matrix.add_sample("s2", &s2);
+        assert_eq!(
+            matrix.table(),
+            "gene\ttranscript\ts1\ts2\nAAA\tAAA.1\t3\t0\n"
+        );
+        assert_eq!(
+            sample_codon_rows("s1", &s1),
+            "s1\tAAA\tAAA.1\t0\t50\t1\n\
+             s1\tAAA\tAAA.1\t1\t53\t2\n\
+             s1\tAAA\tAAA.1\t2\t56\t3\n"
+        );
+        assert_eq!(sample_codon_rows("s2", &s2), "");
+    }
+
+    #[test]
+    fn gene_quantification() {
+        let mut genes = HashMap::new();
+        for gene in vec![
+            gene_counts("AAA", vec![10, 2, 2, 2, 2, 10]),
+            gene_counts("BBB", vec![0, 4, 4, 0]),
+        ] {
+            genes.insert(gene.gene.clone(), gene);
+        }
+        let counts = CodonCounts { genes: genes };
+
+        let classified = LibrarySize::new(LibrarySource::Classified);
+        let mut terminal = TerminalCodons {
+            start: 1,
+            end: 1,
+            policy: TerminalPolicy::Exclude,
+        };
+        assert_eq!(
+            counts.gene_table(&terminal, &classified, 0),
+            "gene\ttranscript\tcodons\tcount\trpkm\ttpm\n\
+             AAA\tAAA.1\t4\t8\t41666666.6667\t333333.3333\n\
+             BBB\tBBB.1\t2\t8\t83333333.3333\t666666.6667\n"
+        );
+        assert_eq!(
+            counts.gene_table(&terminal, &LibrarySize::new(LibrarySource::Mapped), 32),
+            "gene\ttranscript\tcodons\tcount\trpkm\ttpm\n\
+             AAA\tAAA.1\t4\t8\t20833333.3333\t333333.3333\n\
+             BBB\tBBB.1\t2\t8\t41666666.6667\t666666.6667\n"
+        );
+        assert_eq!(
+            counts.region_table(&terminal),
+            "region\tcodons\tcount\tfraction\tquantification\n\
+             start\t1\t10\t0.2778\texcluded\n\
+             body\tNA\t16\t0.4444\tincluded\n\
+             stop\t1\t10\t0.2778\texcluded\n"
+        );
+
+        terminal.policy = TerminalPolicy::Separate;
+        assert_eq!(
+            counts.gene_table(&terminal, &classified, 0),
+            "gene\ttranscript\tcodons\tcount\trpkm\ttpm\tstart_count\tstop_count\n\
+             AAA\tAAA.1\t4\t8\t41666666.6667\t333333.3333\t10\t10\n\
+             BBB\tBBB.1\t2\t8\t83333333.3333\t666666.6667\t0\t0\n"
+        );
+
+        terminal.policy = TerminalPolicy::Include;
+        assert_eq!(
+            counts.gene_table(&terminal, &classified, 0),
+            "gene\ttranscript\tcodons\tcount\trpkm\ttpm\n\
+             AAA\tAAA.1\t6\t26\t42483660.1307\t684210.5263\n\
+             BBB\tBBB.1\t4\t8\t19607843.1373\t315789.4737\n"
+        );
+        assert!("skip".parse::<TerminalPolicy>().is_err());
+
+        let short = gene_counts("CCC", vec![1, 2, 4]);
+        terminal.start = 2;
+        terminal.end = 2;
+        assert_eq!(short.region_totals(&terminal), (3, 0, 4));
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+
+use failure;
+
+use rust_htslib::bam;
+
+use bam_utils::*;
+
+/// Distance, in nucleotides, that the alignment start must advance
+/// before 5' positions behind it are forgotten. This is far longer
+/// than the clipping on any footprint read, so the 5' end of a later
+/// alignment can never fall among the forgotten positions.
+const PRUNE_MARGIN: isize = 1000;
+
+/// Footprints already counted at each 5' position, strand, and UMI,
+/// for collapsing UMI duplicates in the same way as
+/// `bam-suppress-duplicates`. Alignments must be coordinate-sorted,
+/// and only the positions near the current alignment are kept.
+#[derive(Debug, Clone)]
+pub struct UmiDuplicates {
+    tid: i32,
+    pos: isize,
+    pruned: isize,
+    seen: HashSet<(isize, bool, Vec<u8>)>,
+}
+
+impl UmiDuplicates {
+    pub fn new() -> Self {
+        UmiDuplicates {
+            tid: -1,
+            pos: 0,
+            pruned: 0,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Returns `true` when `rec` is the first alignment seen with its
+    /// 5' position, strand, and `umi`, and `false` when it is a
+    /// duplicate of an earlier alignment.
+    ///
+    /// An `UnsortedError` is returned when `rec` precedes the previous
+    /// alignment in coordinate order.
+    pub fn is_new(&mut self, rec: &bam::Record, umi: &[u8]) -> Result<bool, failure::Error> {
+        self.advance(rec)?;
+        let (fp_pos, _) = five_prime_pos(rec);
+        Ok(self.seen.insert((fp_pos, rec.is_reverse(), umi.to_vec())))
+    }
+
+    fn advance(&mut self, rec: &bam::Record) -> Result<(), failure::Error> {
+        let pos = rec.pos() as isize;
+
+        if rec.tid() < self.tid || (rec.tid() == self.tid && pos < self.pos) {
+            return Err(UnsortedError(format!(
+                "Collapsing UMI duplicates requires a coordinate-sorted input, but {} is out of order",
+                String::from_utf8_lossy(rec.qname())
+            ))
+            .into());
+        }
+
+        if rec.tid() != self.tid {
+            self.tid = rec.tid();
+            self.pruned = pos;
+            self.seen.clear();
+        } else if pos - self.pruned >= PRUNE_MARGIN {
+            let oldest = pos - PRUNE_MARGIN;
+            self.seen.retain(|&(fp_pos, _, _)| fp_pos >= oldest);
+            self.pruned = pos;
+        }
+        self.pos = pos;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    fn record(tid: i32, pos: i32, reverse: bool, cigar: Vec<Cigar>) -> bam::Record {
+        let len = cigar
+            .iter()
+            .map(|op| match op {
+                Cigar::Match(len) | Cigar::SoftClip(len) => *len as usize,
+                _ => 0,
+            })
+            .sum();
+        let mut rec = bam::Record::new();
+        rec.set(
+            b"read",
+            &CigarString(cigar),
+            &vec![b'A'; len],
+            &vec![30; len],
+        );
+        rec.set_tid(tid);
+        rec.set_pos(pos);
+        if reverse {
+            rec.set_flags(0x10);
+        }
+        rec
+    }
+
+    #[test]
+    fn collapse_duplicates() {
+        let mut dups = UmiDuplicates::new();
+
+        let fwd = record(0, 100, false, vec![Cigar::Match(30)]);
+        assert!(dups.is_new(&fwd, b"ACGT").unwrap());
+        assert!(!dups.is_new(&fwd, b"ACGT").unwrap());
+        assert!(dups.is_new(&fwd, b"TGCA").unwrap());
+
+        // Same 5' end once soft clipping is included
+        let clipped = record(0, 102, false, vec![Cigar::SoftClip(2), Cigar::Match(28)]);
+        assert!(!dups.is_new(&clipped, b"ACGT").unwrap());
+
+        // A different 5' end is new
+        let shorter = record(0, 102, false, vec![Cigar::Match(20)]);
+        assert!(dups.is_new(&shorter, b"ACGT").unwrap());
+        assert!(!dups.is_new(&shorter, b"ACGT").unwrap());
+
+        // Reverse reads are keyed on their 5' end, at the alignment end
+        let rev_a = record(0, 110, true, vec![Cigar::Match(30)]);
+        let rev_b = record(0, 120, true, vec![Cigar::Match(20)]);
+        assert!(dups.is_new(&rev_a, b"ACGT").unwrap());
+        assert!(!dups.is_new(&rev_b, b"ACGT").unwrap());
+
+        // Same position on another reference is new
+        let other = record(1, 100, false, vec![Cigar::Match(30)]);
+        assert!(dups.is_new(&other, b"ACGT").unwrap());
+    }
+
+    #[test]
+    fn prune_positions() {
+        let mut dups = UmiDuplicates::new();
+
+        for pos in 0..50 {
+            let rec = record(0, pos, false, vec![Cigar::Match(30)]);
+            assert!(dups.is_new(&rec, b"ACGT").unwrap());
+        }
+        assert_eq!(dups.seen.len(), 50);
+
+        let far = record(0, 2000, false, vec![Cigar::Match(30)]);
+        assert!(dups.is_new(&far, b"ACGT").unwrap());
+        assert_eq!(dups.seen.len(), 1);
+
+        let near = record(0, 2010, true, vec![Cigar::Match(30)]);
+        assert!(dups.is_new(&near, b"ACGT").unwrap());
+        assert_eq!(dups.seen.len(), 2);
+    }
+
+    #[test]
+    fn unsorted_input() {
+        let mut dups = UmiDuplicates::new();
+
+        let rec = record(1, 100, false, vec![Cigar::Match(30)]);
+        assert!(dups.is_new(&rec, b"ACGT").unwrap());
+
+        let before = record(1, 99, false, vec![Cigar::Match(30)]);
+        assert!(dups.is_new(&before, b"ACGT").is_err());
+
+        let prev_ref = record(0, 200, false, vec![Cigar::Match(30)]);
+        assert!(dups.is_new(&prev_ref, b"ACGT").is_err());
+    }
+}
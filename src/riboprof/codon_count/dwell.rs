@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+
+use rand::distributions::Poisson;
+use rand::Rng;
+
+use bootstrap::*;
+use codon_assign::Site;
+
+use codon_count::counts::*;
+
+const SITES: [Site; 3] = [Site::A, Site::P, Site::E];
+
+/// Returns the 61 sense codons in alphabetical order.
+pub fn sense_codons() -> Vec<[u8; 3]> {
+    let nts = [b'A', b'C', b'G', b'T'];
+    let mut codons = Vec::new();
+    for &nt1 in nts.iter() {
+        for &nt2 in nts.iter() {
+            for &nt3 in nts.iter() {
+                let codon = [nt1, nt2, nt3];
+                if !is_stop_codon(&codon) {
+                    codons.push(codon);
+                }
+            }
+        }
+    }
+    codons
+}
+
+fn is_stop_codon(codon: &[u8]) -> bool {
+    codon == b"TAA" || codon == b"TAG" || codon == b"TGA"
+}
+
+/// Returns the sequence of the codon in a site, for a footprint
+/// whose counted site lies on a codon of a coding sequence, or
+/// `None` if that codon lies outside of the sequence.
+///
+/// # Arguments
+///
+/// * `cds_seq` is the coding sequence, beginning with the first
+/// complete codon of the gene
+/// * `codon` is the codon index of the counted site
+/// * `counted_site` is the site assigned to codons
+/// * `site` is the site whose codon is returned
+pub fn site_codon_seq<'a>(
+    cds_seq: &'a [u8],
+    codon: usize,
+    counted_site: Site,
+    site: Site,
+) -> Option<&'a [u8]> {
+    (codon * 3 + counted_site.a_site_distance())
+        .checked_sub(site.a_site_distance())
+        .and_then(|nt| cds_seq.get(nt..(nt + 3)))
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct DwellTally {
+    footprints: usize,
+    positions: usize,
+    normalized: f64,
+}
+
+impl DwellTally {
+    fn add(&mut self, other: &DwellTally) {
+        self.footprints += other.footprints;
+        self.positions += other.positions;
+        self.normalized += other.normalized;
+    }
+
+    fn dwell(&self) -> Option<f64> {
+        if self.positions > 0 {
+            Some(self.normalized / self.positions as f64)
+        } else {
+            None
+        }
+    }
+}
+
+/// Footprint occupancy aggregated by the codon identity at the A, P,
+/// and E sites.
+///
+/// The dwell score for a codon in a site is the average, over every
+/// occurrence of that codon in the site, of the footprint count
+/// normalized by the average count per codon across the gene. Genes
+/// with no footprints are ignored. A codon with no effect on
+/// elongation thus has a dwell score near 1.
+///
+/// The contribution of each gene is retained separately, so that
+/// confidence intervals on dwell scores can be estimated by
+/// resampling genes.
+pub struct CodonDwell {
+    counted_site: Site,
+    tallies: HashMap<(Site, [u8; 3]), DwellTally>,
+    gene_tallies: Vec<HashMap<(Site, [u8; 3]), DwellTally>>,
+    intervals: Option<HashMap<(Site, [u8; 3]), (f64, f64)>>,
+}
+
+impl CodonDwell {
+    /// Creates an empty dwell tally.
+    ///
+    /// # Arguments
+    ///
+    /// * `counted_site` is the ribosomal site that was assigned to
+    /// codons in producing per-codon footprint counts
+    pub fn new(counted_site: Site) -> Self {
+        let mut tallies = HashMap::new();
+        for site in SITES.iter() {
+            for codon in sense_codons() {
+                tallies.insert((*site, codon), DwellTally::default());
+            }
+        }
+
+        CodonDwell {
+            counted_site: counted_site,
+            tallies: tallies,
+            gene_tallies: Vec::new(),
+            intervals: None,
+        }
+    }
+
+    /// Adds the per-codon footprint counts for one gene.
+    ///
+    /// # Arguments
+    ///
+    /// * `counts` is the footprint count on each codon
+    /// * `cds_seq` is the coding sequence, beginning with the first
+    /// complete codon of the gene
+    pub fn add_gene(&mut self, counts: &[usize], cds_seq: &[u8]) {
+        self.add_gene_window(counts, cds_seq, 0, 0)
+    }
+
+    /// Adds the per-codon footprint counts for one gene, excluding
+    /// codons at the start and the end of the CDS. Excluded codons
+    /// are not tallied and do not contribute to the average count
+    /// used for normalization.
+    ///
+    /// # Arguments
+    ///
+    /// * `counts` is the footprint count on each codon
+    /// * `cds_seq` is the coding sequence, beginning with the first
+    /// complete codon of the gene
+    /// * `exclude_start` is the number of codons excluded at the start
+    /// * `exclude_end` is the number of codons excluded at the end
+    pub fn add_gene_window(
+        &mut self,
+        counts: &[usize],
+        cds_seq: &[u8],
+        exclude_start: usize,
+        exclude_end: usize,
+    ) {
+        let window = exclude_start..counts.len().saturating_sub(exclude_end);
+        if window.start >= window.end {
+            return;
+        }
+
+        let total: usize = counts[window.clone()].iter().sum();
+        if total == 0 {
+            return;
+        }
+        let density = total as f64 / window.len() as f64;
+
+        let mut gene_tally: HashMap<(Site, [u8; 3]), DwellTally> = HashMap::new();
+        for codon in window {
+            let ct = counts[codon];
+            for site in SITES.iter() {
+                let key = match site_codon_seq(cds_seq, codon, self.counted_site, *site) {
+                    Some(seq) => (*site, [seq[0], seq[1], seq[2]]),
+                    None => continue,
+                };
+                if !self.tallies.contains_key(&key) {
+                    continue;
+                }
+
+                let tally = gene_tally.entry(key).or_insert_with(DwellTally::default);
+                tally.footprints += ct;
+                tally.positions += 1;
+                tally.normalized += ct as f64 / density;
+            }
+        }
+
+        for (key, tally) in gene_tally.iter() {
+            if let Some(total_tally) = self.tallies.get_mut(key) {
+                total_tally.add(tally);
+            }
+        }
+        self.gene_tallies.push(gene_tally);
+    }
+
+    /// Returns the number of genes with footprints contributing to
+    /// the dwell scores.
+    pub fn genes(&self) -> usize {
+        self.gene_tallies.len()
+    }
+
+    /// Returns the total footprint count with a codon in a site.
+    pub fn footprints(&self, site: Site, codon: &[u8; 3]) -> usize {
+        self.tallies
+            .get(&(site, *codon))
+            .map_or(0, |tally| tally.footprints)
+    }
+
+    /// Returns the dwell score for a codon in a site, or `None` if
+    /// the codon never occurs in that site in a gene with footprints.
+    pub fn dwell(&self, site: Site, codon: &[u8; 3]) -> Option<f64> {
+        self.tallies
+            .get(&(site, *codon))
+            .and_then(|tally| tally.dwell())
+    }
+
+    /// Estimates 95% confidence intervals on every dwell score by
+    /// bootstrap resampling of genes with replacement. The intervals
+    /// are reported in the dwell table.
+    ///
+    /// # Arguments
+    ///
+    /// * `replicates` is the number of bootstrap replicates
+    /// * `rng` is the source of randomness for resampling
+    pub fn bootstrap<R: Rng>(&mut self, replicates: usize, rng: &mut R) {
+        let mut estimates: HashMap<(Site, [u8; 3]), Vec<f64>> = HashMap::new();
+        let n_genes = self.gene_tallies.len();
+
+        if n_genes > 0 {
+            for _ in 0..replicates {
+                let mut resampled: HashMap<(Site, [u8; 3]), DwellTally> = HashMap::new();
+                for _ in 0..n_genes {
+                    let gene_tally = &self.gene_tallies[rng.gen_range(0, n_genes)];
+                    for (key, tally) in gene_tally.iter() {
+                        resampled
+                            .entry(*key)
+                            .or_insert_with(DwellTally::default)
+                            .add(tally);
+                    }
+                }
+
+                for (key, tally) in resampled.iter() {
+                    if let Some(dwell) = tally.dwell() {
+                        estimates.entry(*key).or_insert_with(Vec::new).push(dwell);
+                    }
+                }
+            }
+        }
+
+        self.intervals = Some(
+            estimates
+                .into_iter()
+                .map(|(key, mut ests)| (key, confidence_interval(&mut ests)))
+                .collect(),
+        );
+    }
+
+    /// Returns the bootstrap confidence interval on the dwell score
+    /// for a codon in a site, if intervals have been estimated and
+    /// the codon occurs in the site.
+    pub fn interval(&self, site: Site, codon: &[u8; 3]) -> Option<(f64, f64)> {
+        self.intervals
+            .as_ref()
+            .and_then(|intervals| intervals.get(&(site, *codon)))
+            .cloned()
+    }
+
+    /// Returns a table with one row for each sense codon, giving the
+    /// number of occurrences, footprint counts, and dwell score for
+    /// the codon in each site, along with the lower and upper bounds
+    /// of the confidence interval when intervals have been estimated.
+    pub fn table(&self) -> String {
+        let mut table = "codon".to_string();
+        for site in SITES.iter() {
+            table += &format!("\t{0}_positions\t{0}_count\t{0}_dwell", site);
+            if self.intervals.is_some() {
+                table += &format!("\t{0}_dwell_lo\t{0}_dwell_hi", site);
+            }
+        }
+        table += "\n";
+
+        for codon in sense_codons() {
+            table += &String::from_utf8_lossy(&codon);
+            for site in SITES.iter() {
+                let tally = self.tallies[&(*site, codon)];
+                table += &format!(
+                    "\t{}\t{}\t{}",
+                    tally.positions,
+                    tally.footprints,
+                    self.dwell(*site, &codon)
+                        .map_or("NA".to_string(), |d| format!("{:.4}", d))
+                );
+                if self.intervals.is_some() {
+                    table += &match self.interval(*site, &codon) {
+                        Some((lo, hi)) => format!("\t{:.4}\t{:.4}", lo, hi),
+                        None => "\tNA\tNA".to_string(),
+                    };
+                }
+            }
+            table += "\n";
+        }
+
+        table
+    }
+}
+
+/// Dwell score for one codon of one gene, the footprint count on
+/// the codon normalized by the average count per codon across the
+/// gene.
+#[derive(Debug, Clone)]
+pub struct PositionDwell {
+    gene: String,
+    trxname: String,
+    codon: usize,
+    trxpos: usize,
+    count: usize,
+    dwell: f64,
+    interval: Option<(f64, f64)>,
+}
+
+impl PositionDwell {
+    pub fn codon(&self) -> usize {
+        self.codon
+    }
+    pub fn count(&self) -> usize {
+        self.count
+    }
+    pub fn dwell(&self) -> f64 {
+        self.dwell
+    }
+    pub fn interval(&self) -> Option<(f64, f64)> {
+        self.interval
+    }
+}
+
+/// Computes the dwell score at each codon of a gene, excluding
+/// codons at the start and end of the CDS, with an optional
+/// parametric bootstrap confidence interval. Each bootstrap replicate
+/// draws a Poisson count at every codon with mean equal to the
+/// observed count, and then renormalizes by the replicate gene
+/// average. Genes with no footprints yield no dwell scores.
+///
+/// # Arguments
+///
+/// * `gene_counts` are the per-codon counts for the gene
+/// * `exclude_start` is the number of codons excluded at the start
+/// * `exclude_end` is the number of codons excluded at the end
+/// * `replicates` is the number of bootstrap replicates, or 0 for
+/// no confidence intervals
+/// * `rng` is the source of randomness for resampling
+pub fn position_dwell<R: Rng>(
+    gene_counts: &GeneCodonCounts,
+    exclude_start: usize,
+    exclude_end: usize,
+    replicates: usize,
+    rng: &mut R,
+) -> Vec<PositionDwell> {
+    let window = gene_counts.window_counts(exclude_start, exclude_end);
+    let total: usize = window.iter().sum();
+    if total == 0 {
+        return Vec::new();
+    }
+    let density = total as f64 / window.len() as f64;
+
+    let mut estimates: Vec<Vec<f64>> = vec![Vec::with_capacity(replicates); window.len()];
+    let mut resampled = vec![0.0; window.len()];
+    for _ in 0..replicates {
+        for (&ct, sample) in window.iter().zip(resampled.iter_mut()) {
+            *sample = if ct > 0 {
+                rng.sample(Poisson::new(ct as f64)) as f64
+            } else {
+                0.0
+            };
+        }
+
+        let resampled_density = resampled.iter().sum::<f64>() / window.len() as f64;
+        if resampled_density > 0.0 {
+            for (sample, ests) in resampled.iter().zip(estimates.iter_mut()) {
+                ests.push(sample / resampled_density);
+            }
+        }
+    }
+
+    window
+        .iter()
+        .zip(estimates.iter_mut())
+        .enumerate()
+        .map(|(i, (&ct, ests))| {
+            let codon = exclude_start + i;
+            PositionDwell {
+                gene: gene_counts.gene().to_owned(),
+                trxname: gene_counts.trxname().to_owned(),
+                codon: codon,
+                trxpos: gene_counts.codon_trxpos(codon),
+                count: ct,
+                dwell: ct as f64 / density,
+                interval: if ests.is_empty() {
+                    None
+                } else {
+                    Some(confidence_interval(ests))
+                },
+            }
+        })
+        .collect()
+}
+
+/// Returns a table of per-position dwell scores, with the codon index
+/// and transcript position, the codon count, the dwell score, and the
+/// confidence interval when it was estimated.
+pub fn position_dwell_table(dwells: &[PositionDwell]) -> String {
+    let mut table =
+        "gene\ttranscript\tcodon\ttrxpos\tcount\tdwell\tdwell_lo\tdwell_hi\n".to_string();
+
+    for dwell in dwells.iter() {
+        let interval = match dwell.interval {
+            Some((lo, hi)) => format!("{:.4}\t{:.4}", lo, hi),
+            None => "NA\tNA".to_string(),
+        };
+
+        table += &format!(
+            "{}\t{}\t{}\t{}\t{}\t{:.4}\t{}\n",
+            dwell.gene,
+            dwell.trxname,
+            dwell.codon,
+            dwell.trxpos,
+            dwell.count,
+            dwell.dwell,
+            interval
+        );
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn sense_codon_count() {
+        let codons = sense_codons();
+        assert_eq!(codons.len(), 61);
+        assert_eq!(codons[0], *b"AAA");
+        assert_eq!(codons[60], *b"TTT");
+        assert!(!codons.contains(b"TGA"));
+    }
+
+    #[test]
+    fn dwell_a_site() {
+        let mut dwell = CodonDwell::new(Site::A);
+        //                 ATG    AAA    CCC    AAA    TAA
+        dwell.add_gene(&[0, 6, 2, 2, 0], b"ATGAAACCCAAATAA");
+
+        assert_eq!(dwell.footprints(Site::A, b"AAA"), 8);
+        assert_eq!(dwell.footprints(Site::A, b"CCC"), 2);
+        assert_eq!(dwell.dwell(Site::A, b"AAA"), Some(2.0));
+        assert_eq!(dwell.dwell(Site::A, b"CCC"), Some(1.0));
+        assert_eq!(dwell.dwell(Site::A, b"GGG"), None);
+
+        assert_eq!(dwell.footprints(Site::P, b"ATG"), 6);
+        assert_eq!(dwell.footprints(Site::P, b"AAA"), 2);
+        assert_eq!(dwell.dwell(Site::P, b"AAA"), Some(0.5));
+        assert_eq!(dwell.footprints(Site::E, b"ATG"), 2);
+    }
+
+    #[test]
+    fn dwell_window() {
+        let mut dwell = CodonDwell::new(Site::A);
+        dwell.add_gene_window(&[0, 6, 2, 2, 0], b"ATGAAACCCAAATAA", 2, 1);
+
+        assert_eq!(dwell.footprints(Site::A, b"AAA"), 2);
+        assert_eq!(dwell.dwell(Site::A, b"AAA"), Some(1.0));
+        assert_eq!(dwell.footprints(Site::P, b"AAA"), 2);
+        assert_eq!(dwell.footprints(Site::E, b"ATG"), 2);
+        assert_eq!(dwell.dwell(Site::P, b"ATG"), None);
+    }
+
+    #[test]
+    fn dwell_p_site() {
+        let mut dwell = CodonDwell::new(Site::P);
+        dwell.add_gene(&[0, 6, 2, 2, 0], b"ATGAAACCCAAATAA");
+
+        assert_eq!(dwell.footprints(Site::P, b"AAA"), 8);
+        assert_eq!(dwell.footprints(Site::A, b"CCC"), 6);
+        assert_eq!(dwell.footprints(Site::E, b"ATG"), 6);
+    }
+
+    #[test]
+    fn dwell_bootstrap() {
+        let mut dwell = CodonDwell::new(Site::A);
+        dwell.add_gene(&[0, 6, 2, 2, 0], b"ATGAAACCCAAATAA");
+        dwell.add_gene(&[0, 0, 0, 0], b"ATGCCCAAATAA");
+        assert_eq!(dwell.genes(), 1);
+        assert_eq!(dwell.interval(Site::A, b"AAA"), None);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        dwell.bootstrap(20, &mut rng);
+
+        // With one gene, every replicate reproduces the estimate
+        assert_eq!(dwell.interval(Site::A, b"AAA"), Some((2.0, 2.0)));
+        assert_eq!(dwell.interval(Site::A, b"GGG"), None);
+        assert!(dwell.table().contains("A_dwell_lo\tA_dwell_hi"));
+    }
+}
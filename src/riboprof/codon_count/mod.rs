@@ -0,0 +1,625 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use failure;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use bio_types::annot::loc::Loc;
+use bio_types::annot::refids::RefIDSet;
+use bio_types::strand::ReqStrand;
+use rust_htslib::bam;
+use rust_htslib::bam::Read as BamRead;
+
+use bam_utils::*;
+use cli_utils::*;
+use codon_assign::*;
+use corrupt::*;
+use genome::*;
+use normalization::*;
+use output::*;
+use timing::*;
+use track::*;
+use transcript::*;
+use umi::*;
+
+mod assign;
+mod counts;
+mod duplicates;
+mod dwell;
+#[cfg(feature = "parquet")]
+mod occupancy;
+mod pauses;
+mod stats;
+mod windows;
+
+use codon_count::assign::*;
+use codon_count::counts::*;
+use codon_count::duplicates::*;
+use codon_count::dwell::*;
+#[cfg(feature = "parquet")]
+use codon_count::occupancy::*;
+use codon_count::pauses::*;
+use codon_count::stats::*;
+use codon_count::windows::*;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CLI {
+    #[serde(skip_serializing)]
+    pub inputs: Vec<String>,
+    pub output: String,
+    pub bed: Vec<String>,
+    pub asites: String,
+    pub default_offset: Option<String>,
+    pub interpolate_offsets: bool,
+    pub three_prime: bool,
+    pub site: String,
+    pub lengths: String,
+    pub exclude_codons: String,
+    pub terminal_codons: String,
+    pub pause_zscore: Option<String>,
+    pub pause_min_count: String,
+    pub dwell_bootstrap: Option<String>,
+    pub bootstrap_seed: String,
+    pub count_multi: bool,
+    pub umi: bool,
+    pub fasta: Option<String>,
+    pub track: Option<String>,
+    pub windows: Option<String>,
+    pub parquet: bool,
+    #[serde(flatten)]
+    pub read_filter: ReadFilterCLI,
+    #[serde(flatten)]
+    pub umi_config: UmiCLI,
+    #[serde(flatten)]
+    pub normalize: NormalizeCLI,
+    #[serde(flatten)]
+    pub output_config: OutputCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+impl Default for CLI {
+    fn default() -> Self {
+        CLI {
+            inputs: Vec::new(),
+            output: String::new(),
+            bed: Vec::new(),
+            asites: String::new(),
+            default_offset: None,
+            interpolate_offsets: false,
+            three_prime: false,
+            site: "A".to_string(),
+            lengths: "26,34".to_string(),
+            exclude_codons: "0,0".to_string(),
+            terminal_codons: "exclude".to_string(),
+            pause_zscore: None,
+            pause_min_count: "5".to_string(),
+            dwell_bootstrap: None,
+            bootstrap_seed: "1".to_string(),
+            count_multi: false,
+            umi: false,
+            fasta: None,
+            track: None,
+            windows: None,
+            parquet: false,
+            read_filter: ReadFilterCLI::default(),
+            umi_config: UmiCLI::default(),
+            normalize: NormalizeCLI::default(),
+            output_config: OutputCLI::default(),
+            corrupt: CorruptInputs::default(),
+        }
+    }
+}
+
+pub struct Config {
+    inputs: Vec<String>,
+    output: PathBuf,
+    trxome: Transcriptome<Rc<String>>,
+    offsets: SiteOffsets,
+    site: Site,
+    lengths: Range<usize>,
+    exclude_codons: Range<usize>,
+    terminal_codons: TerminalCodons,
+    pause_criteria: Option<PauseCriteria>,
+    dwell_bootstrap: Option<usize>,
+    bootstrap_seed: u64,
+    count_multi: bool,
+    umi: Option<UmiConfig>,
+    genome: Option<Genome>,
+    track_format: Option<TrackFormat>,
+    windows: Option<WindowCounts>,
+    normalize: NormalizeConfig,
+    parquet: bool,
+    read_filter: ReadFilter,
+    output_config: OutputConfig,
+    corrupt: CorruptInputs,
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        if cli.parquet && !cfg!(feature = "parquet") {
+            bail!("Parquet output requires building with the \"parquet\" feature");
+        }
+
+        let mut refids = RefIDSet::new();
+        let trxome = Transcriptome::new_from_files(&cli.bed, &mut refids)?;
+
+        let mut offsets = SiteOffsets::new_from_file(&cli.asites)?;
+        if let Some(ref default_offset) = cli.default_offset {
+            offsets.set_missing_offset(MissingOffset::Default(default_offset.parse()?));
+        } else if cli.interpolate_offsets {
+            offsets.set_missing_offset(MissingOffset::Interpolate);
+        }
+        if cli.three_prime {
+            offsets.set_anchor(OffsetAnchor::ThreePrime);
+        }
+
+        let exclude_codons: Range<usize> = parse_pair(&cli.exclude_codons)?;
+
+        let windows = match cli.windows {
+            Some(ref windows) => Some(WindowCounts::new_from_bed(windows, &trxome)?),
+            None => None,
+        };
+
+        let pause_criteria = match cli.pause_zscore {
+            Some(ref zscore) => Some(PauseCriteria {
+                min_zscore: zscore.parse()?,
+                min_count: cli.pause_min_count.parse()?,
+                exclude_start: exclude_codons.start,
+                exclude_end: exclude_codons.end,
+            }),
+            None => None,
+        };
+
+        let normalize = NormalizeConfig::new_from_cli(&cli.normalize)?;
+        if normalize.get_units() == CountUnits::Rpkm {
+            bail!("RPKM normalization needs a feature length and is not defined for tracks");
+        }
+
+        Ok(Config {
+            inputs: cli.inputs.clone(),
+            output: Path::new(&cli.output).to_path_buf(),
+            trxome: trxome,
+            offsets: offsets,
+            site: cli.site.parse()?,
+            lengths: parse_pair(&cli.lengths)?,
+            terminal_codons: TerminalCodons {
+                start: exclude_codons.start,
+                end: exclude_codons.end,
+                policy: cli.terminal_codons.parse()?,
+            },
+            exclude_codons: exclude_codons,
+            pause_criteria: pause_criteria,
+            dwell_bootstrap: match cli.dwell_bootstrap {
+                Some(ref replicates) => Some(replicates.parse()?),
+                None => None,
+            },
+            bootstrap_seed: cli.bootstrap_seed.parse()?,
+            count_multi: cli.count_multi,
+            umi: if cli.umi {
+                Some(UmiConfig::new_from_cli(&cli.umi_config)?)
+            } else {
+                None
+            },
+            genome: match cli.fasta {
+                Some(ref fasta) => Some(Genome::new_from_fasta(fasta)?),
+                None => None,
+            },
+            track_format: match cli.track {
+                Some(ref track) => match track.parse()? {
+                    TrackFormat::BigWig => bail!("Use bam-to-track for bigWig site tracks"),
+                    format => Some(format),
+                },
+                None => None,
+            },
+            windows: windows,
+            normalize: normalize,
+            parquet: cli.parquet,
+            read_filter: ReadFilter::new_from_cli(&cli.read_filter)?,
+            output_config: OutputConfig::new_from_cli(&cli.output_config)?,
+            corrupt: cli.corrupt.clone(),
+        })
+    }
+
+    fn output_filename(&self, suffix: &str) -> PathBuf {
+        output_filename(&self.output, suffix)
+    }
+}
+
+/// Footprint counts and statistics for one input BAM file.
+struct SampleCounts {
+    name: String,
+    output: PathBuf,
+    codon_counts: CodonCounts,
+    codon_stats: CodonStats,
+    filter_stats: ReadFilterStats,
+    track: GenomeTrack,
+    windows: Option<WindowCounts>,
+}
+
+impl SampleCounts {
+    fn output_filename(&self, suffix: &str) -> PathBuf {
+        output_filename(&self.output, suffix)
+    }
+}
+
+pub fn run_codon_count(config: Config) -> Result<(), failure::Error> {
+    info!("{}", config.trxome.summary());
+
+    let rep_trxs = config.trxome.longest_cds_transcripts();
+    let rep_trxnames: HashSet<Rc<String>> = rep_trxs
+        .iter()
+        .map(|trx| trx.trxname_ref().clone())
+        .collect();
+
+    // With several inputs, per-sample outputs are named by sample
+    // and combined tables are written to the base output name.
+    let names = sample_names(&config.inputs);
+    let outputs: Vec<PathBuf> = if config.inputs.len() > 1 {
+        names
+            .iter()
+            .map(|name| config.output_filename(&format!("_{}", name)))
+            .collect()
+    } else {
+        vec![config.output.clone()]
+    };
+
+    // Open (empty) stats output files early to detect errors before processing data.
+    for output in outputs.iter() {
+        config
+            .output_config
+            .create(output_filename(output, "_codon_stats.txt"))?;
+    }
+
+    // Each sample is written and dropped once it is counted, adding
+    // its rows to the combined tables, so that only one sample is
+    // held at a time.
+    let mut combined = if config.inputs.len() > 1 {
+        let mut codons = config
+            .output_config
+            .create(config.output_filename("_codons.txt"))?;
+        codons.write_all(SAMPLE_CODON_HEADER.as_bytes())?;
+        Some((codons, GeneMatrix::new(&config.terminal_codons)))
+    } else {
+        None
+    };
+
+    for ((input, name), output) in config.inputs.iter().zip(names).zip(outputs) {
+        let sample = count_sample(&config, input, name, output, &rep_trxs, &rep_trxnames)?;
+        write_sample(&config, &sample, &rep_trxs)?;
+
+        if let Some((ref mut codons, ref mut matrix)) = combined {
+            let _timer = StageTimer::new("output");
+            codons.write_all(sample_codon_rows(&sample.name, &sample.codon_counts).as_bytes())?;
+            matrix.add_sample(&sample.name, &sample.codon_counts);
+        }
+    }
+
+    if let Some((codons, matrix)) = combined {
+        let _timer = StageTimer::new("output");
+        codons.finish()?;
+        config
+            .output_config
+            .write(config.output_filename("_gene_matrix.txt"), matrix.table())?;
+    }
+
+    Ok(())
+}
+
+fn count_sample(
+    config: &Config,
+    input_name: &str,
+    name: String,
+    output: PathBuf,
+    rep_trxs: &[&Transcript<Rc<String>>],
+    rep_trxnames: &HashSet<Rc<String>>,
+) -> Result<SampleCounts, failure::Error> {
+    let mut input = open_bam(input_name, &config.corrupt)?;
+
+    let tids = {
+        let mut refids: RefIDSet<Rc<String>> = RefIDSet::new();
+        Tids::new(&mut refids, input.header())
+    };
+    tids.warn_missing(&config.trxome);
+
+    let _timer = StageTimer::new("classification");
+    let mut codon_counts = CodonCounts::new(rep_trxs.iter().cloned());
+    let mut codon_stats = CodonStats::new();
+    let mut filter_stats = ReadFilterStats::default();
+    let mut track = GenomeTrack::new();
+    let mut windows = config.windows.as_ref().map(WindowCounts::empty_copy);
+
+    let mut umi_duplicates = UmiDuplicates::new();
+
+    for recres in spliced_records(&mut input, &tids, &config.corrupt) {
+        let (rec, fp) = recres?;
+
+        if !filter_stats.tally(&config.read_filter, &rec) {
+            continue;
+        }
+
+        let res = record_codon(
+            &config.trxome,
+            rep_trxnames,
+            &config.offsets,
+            config.site,
+            &rec,
+            fp,
+            &config.lengths,
+            config.count_multi,
+        )?;
+
+        let umi = config
+            .umi
+            .as_ref()
+            .and_then(|umi_config| umi_config.record_umi(&rec));
+
+        if let BamCodonResult::Fp(ref fp_res, _) = res {
+            if let Some(ref umi) = umi {
+                if !umi_duplicates.is_new(&rec, umi)? {
+                    if let FpCodonResult::Codon(_) = fp_res {
+                        codon_stats.tally_umi_duplicate();
+                    }
+                    continue;
+                }
+            }
+        }
+
+        // Collapsed UMI duplicates are left out of the codon statistics,
+        // and so of the library size for normalization.
+        codon_stats.tally_bam_codon(&res);
+
+        if let BamCodonResult::Fp(ref fp_res, ref site_pos) = res {
+            if let FpCodonResult::Codon(ref hit) = fp_res {
+                codon_counts.tally(hit);
+            }
+            if let Some(ref pos) = site_pos {
+                track.tally(pos);
+                if let Some(ref mut windows) = windows {
+                    windows.tally(pos);
+                }
+            }
+        }
+    }
+
+    Ok(SampleCounts {
+        name: name,
+        output: output,
+        codon_counts: codon_counts,
+        codon_stats: codon_stats,
+        filter_stats: filter_stats,
+        track: track,
+        windows: windows,
+    })
+}
+
+/// Writes the per-sample output files.
+fn write_sample(
+    config: &Config,
+    sample: &SampleCounts,
+    rep_trxs: &[&Transcript<Rc<String>>],
+) -> Result<(), failure::Error> {
+    let _timer = StageTimer::new("output");
+    let codon_counts = &sample.codon_counts;
+    let codon_stats = &sample.codon_stats;
+
+    config.output_config.write(
+        sample.output_filename("_codon_stats.txt"),
+        codon_stats.table(),
+    )?;
+    config.output_config.write(
+        sample.output_filename("_filter_stats.txt"),
+        sample.filter_stats.table(),
+    )?;
+    config.output_config.write(
+        sample.output_filename("_codons.txt"),
+        codon_counts.codon_table(),
+    )?;
+    config.output_config.write(
+        sample.output_filename("_genes.txt"),
+        codon_counts.gene_table(
+            &config.terminal_codons,
+            config.normalize.get_library_size(),
+            codon_stats.mapped(),
+        ),
+    )?;
+    config.output_config.write(
+        sample.output_filename("_cds_regions.txt"),
+        codon_counts.region_table(&config.terminal_codons),
+    )?;
+
+    if config.parquet {
+        write_parquet(sample)?;
+    }
+
+    if let Some(format) = config.track_format {
+        write_tracks(config, sample, format)?;
+    }
+
+    if let Some(ref windows) = sample.windows {
+        config
+            .output_config
+            .write(sample.output_filename("_windows.txt"), windows.table())?;
+    }
+
+    // Each sample is resampled from the same seed, so that results
+    // do not depend on the other samples in the run.
+    let mut rng = StdRng::seed_from_u64(config.bootstrap_seed);
+
+    if let Some(ref genome) = config.genome {
+        let mut dwell = codon_dwell(
+            genome,
+            rep_trxs,
+            codon_counts,
+            config.site,
+            &config.exclude_codons,
+        );
+        if let Some(replicates) = config.dwell_bootstrap {
+            dwell.bootstrap(replicates, &mut rng);
+        }
+        config
+            .output_config
+            .write(sample.output_filename("_codon_dwell.txt"), dwell.table())?;
+    }
+
+    if let Some(replicates) = config.dwell_bootstrap {
+        let mut dwells = Vec::new();
+        for gene_counts in codon_counts.genes() {
+            dwells.extend(position_dwell(
+                gene_counts,
+                config.exclude_codons.start,
+                config.exclude_codons.end,
+                replicates,
+                &mut rng,
+            ));
+        }
+        config.output_config.write(
+            sample.output_filename("_position_dwell.txt"),
+            position_dwell_table(&dwells),
+        )?;
+    }
+
+    if let Some(ref criteria) = config.pause_criteria {
+        let pauses = find_pauses(config, rep_trxs, codon_counts, criteria);
+        config
+            .output_config
+            .write(sample.output_filename("_pauses.txt"), pause_table(&pauses))?;
+        info!("{}: found {} pause sites", sample.name, pauses.len());
+    }
+
+    if let Some(report) = codon_stats.no_offset_report() {
+        warn!("{}: {}", sample.name, report);
+    }
+
+    info!(
+        "{}: assigned {} site of {} of {} alignments to codons",
+        sample.name,
+        config.site,
+        codon_stats.codon(),
+        codon_stats.total()
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(sample: &SampleCounts) -> Result<(), failure::Error> {
+    write_occupancy_parquet(
+        sample.output_filename("_codons.parquet"),
+        &sample.codon_counts,
+    )
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(_sample: &SampleCounts) -> Result<(), failure::Error> {
+    bail!("Parquet output requires building with the \"parquet\" feature")
+}
+
+/// Writes strand-specific tracks of site density across the genome.
+fn write_tracks(
+    config: &Config,
+    sample: &SampleCounts,
+    format: TrackFormat,
+) -> Result<(), failure::Error> {
+    let track = &sample.track;
+    let library = LibraryCounts::new(sample.codon_stats.mapped() as f64, track.total() as f64);
+    let scale = config.normalize.scale(&library, 0);
+
+    let strands = [(ReqStrand::Forward, "fwd"), (ReqStrand::Reverse, "rev")];
+    for &(strand, strand_name) in strands.iter() {
+        let name = format!("{}_{}_{}", sample.name, config.site, strand_name);
+        let filename = sample.output_filename(&format!("_{}.{}", strand_name, format.suffix()));
+        track.write_file(
+            filename,
+            &config.output_config,
+            format,
+            strand,
+            scale,
+            &name,
+            &[],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Aggregates per-codon counts by the codon identity at each site,
+/// using the coding sequence of each representative transcript and
+/// excluding codons at the start and end of each CDS.
+fn codon_dwell(
+    genome: &Genome,
+    rep_trxs: &[&Transcript<Rc<String>>],
+    codon_counts: &CodonCounts,
+    site: Site,
+    exclude_codons: &Range<usize>,
+) -> CodonDwell {
+    let mut dwell = CodonDwell::new(site);
+
+    for trx in rep_trxs.iter() {
+        let gene_counts = match codon_counts.gene_counts(trx.gene_ref()) {
+            Some(gene_counts) => gene_counts,
+            None => continue,
+        };
+
+        match cds_seq(genome, trx, gene_counts) {
+            Some(cds_seq) => dwell.add_gene_window(
+                gene_counts.counts(),
+                &cds_seq,
+                exclude_codons.start,
+                exclude_codons.end,
+            ),
+            None => warn!("No genome sequence for {}", trx.trxname()),
+        }
+    }
+
+    dwell
+}
+
+/// Calls pause sites in each gene with footprints, reporting the
+/// codon context of each pause when the genome sequence is known.
+fn find_pauses(
+    config: &Config,
+    rep_trxs: &[&Transcript<Rc<String>>],
+    codon_counts: &CodonCounts,
+    criteria: &PauseCriteria,
+) -> Vec<PauseSite> {
+    let mut pauses = Vec::new();
+
+    for trx in rep_trxs.iter() {
+        let gene_counts = match codon_counts.gene_counts(trx.gene_ref()) {
+            Some(gene_counts) if gene_counts.total() > 0 => gene_counts,
+            _ => continue,
+        };
+
+        let seq = config
+            .genome
+            .as_ref()
+            .and_then(|genome| cds_seq(genome, trx, gene_counts));
+
+        pauses.extend(gene_pauses(
+            gene_counts,
+            seq.as_ref().map(|s| s.as_slice()),
+            config.site,
+            criteria,
+        ));
+    }
+
+    pauses
+}
+
+/// Returns the coding sequence of a representative transcript,
+/// beginning with its first complete codon.
+fn cds_seq(
+    genome: &Genome,
+    trx: &Transcript<Rc<String>>,
+    gene_counts: &GeneCodonCounts,
+) -> Option<Vec<u8>> {
+    genome
+        .spliced_seq(trx.loc())
+        .map(|trx_seq| trx_seq[gene_counts.codon_start()..].to_vec())
+}
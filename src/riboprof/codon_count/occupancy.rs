@@ -0,0 +1,131 @@
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use failure;
+
+use parquet::column::writer::ColumnWriter;
+use parquet::data_type::ByteArray;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{FileWriter, RowGroupWriter, SerializedFileWriter};
+use parquet::schema::parser::parse_message_type;
+
+use codon_count::counts::*;
+
+const OCCUPANCY_SCHEMA: &str = "
+message codon_occupancy {
+    REQUIRED BYTE_ARRAY gene (UTF8);
+    REQUIRED BYTE_ARRAY transcript (UTF8);
+    REQUIRED INT64 codon_start;
+    REQUIRED INT64 length;
+    REPEATED INT64 counts;
+}
+";
+
+/// Writes per-codon occupancy vectors for every gene into a Parquet
+/// file, with one row per representative transcript holding the
+/// gene and transcript names, the transcript position of the first
+/// codon, the number of codons, and the array of codon counts.
+pub fn write_occupancy_parquet<P: AsRef<Path>>(
+    path: P,
+    codon_counts: &CodonCounts,
+) -> Result<(), failure::Error> {
+    let schema = Rc::new(parse_message_type(OCCUPANCY_SCHEMA)?);
+    let props = Rc::new(WriterProperties::builder().build());
+    let mut writer = SerializedFileWriter::new(fs::File::create(path)?, schema, props)?;
+
+    let genes = codon_counts.genes();
+
+    let mut row_group = writer.next_row_group()?;
+    write_byte_array_column(
+        &mut *row_group,
+        genes.iter().map(|g| ByteArray::from(g.gene())).collect(),
+    )?;
+    write_byte_array_column(
+        &mut *row_group,
+        genes.iter().map(|g| ByteArray::from(g.trxname())).collect(),
+    )?;
+    write_int64_column(
+        &mut *row_group,
+        genes.iter().map(|g| g.codon_start() as i64).collect(),
+    )?;
+    write_int64_column(
+        &mut *row_group,
+        genes.iter().map(|g| g.counts().len() as i64).collect(),
+    )?;
+    write_counts_column(&mut *row_group, &genes)?;
+    writer.close_row_group(row_group)?;
+
+    writer.close()?;
+    Ok(())
+}
+
+fn write_byte_array_column(
+    row_group: &mut RowGroupWriter,
+    values: Vec<ByteArray>,
+) -> Result<(), failure::Error> {
+    let mut col_writer = next_column(row_group)?;
+    match col_writer {
+        ColumnWriter::ByteArrayColumnWriter(ref mut typed) => {
+            typed.write_batch(&values, None, None)?;
+        }
+        _ => bail!("Unexpected column type writing Parquet string column"),
+    }
+    row_group.close_column(col_writer)?;
+    Ok(())
+}
+
+fn write_int64_column(
+    row_group: &mut RowGroupWriter,
+    values: Vec<i64>,
+) -> Result<(), failure::Error> {
+    let mut col_writer = next_column(row_group)?;
+    match col_writer {
+        ColumnWriter::Int64ColumnWriter(ref mut typed) => {
+            typed.write_batch(&values, None, None)?;
+        }
+        _ => bail!("Unexpected column type writing Parquet integer column"),
+    }
+    row_group.close_column(col_writer)?;
+    Ok(())
+}
+
+/// Writes the repeated counts column. Each gene is one record, and
+/// a gene with no codons is written as an empty list.
+fn write_counts_column(
+    row_group: &mut RowGroupWriter,
+    genes: &[&GeneCodonCounts],
+) -> Result<(), failure::Error> {
+    let mut values = Vec::new();
+    let mut def_levels = Vec::new();
+    let mut rep_levels = Vec::new();
+
+    for gene_counts in genes.iter() {
+        if gene_counts.counts().is_empty() {
+            def_levels.push(0);
+            rep_levels.push(0);
+        }
+
+        for (i, ct) in gene_counts.counts().iter().enumerate() {
+            values.push(*ct as i64);
+            def_levels.push(1);
+            rep_levels.push(if i == 0 { 0 } else { 1 });
+        }
+    }
+
+    let mut col_writer = next_column(row_group)?;
+    match col_writer {
+        ColumnWriter::Int64ColumnWriter(ref mut typed) => {
+            typed.write_batch(&values, Some(&def_levels), Some(&rep_levels))?;
+        }
+        _ => bail!("Unexpected column type writing Parquet counts column"),
+    }
+    row_group.close_column(col_writer)?;
+    Ok(())
+}
+
+fn next_column(row_group: &mut RowGroupWriter) -> Result<ColumnWriter, failure::Error> {
+    row_group
+        .next_column()?
+        .ok_or_else(|| format_err!("Missing column writing Parquet occupancy file"))
+}
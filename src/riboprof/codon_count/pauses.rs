@@ -0,0 +1,147 @@
+use codon_assign::Site;
+
+use codon_count::counts::*;
+use codon_count::dwell::*;
+
+/// Criteria for calling pause sites.
+#[derive(Debug, Clone, Copy)]
+pub struct PauseCriteria {
+    /// Minimum z-score of the codon count relative to the gene
+    pub min_zscore: f64,
+    /// Minimum footprint count on the codon
+    pub min_count: usize,
+    /// Number of codons excluded at the start of each CDS
+    pub exclude_start: usize,
+    /// Number of codons excluded at the end of each CDS
+    pub exclude_end: usize,
+}
+
+/// Codon with footprint occupancy significantly above the rest of its
+/// gene.
+#[derive(Debug, Clone)]
+pub struct PauseSite {
+    gene: String,
+    trxname: String,
+    codon: usize,
+    trxpos: usize,
+    count: usize,
+    mean: f64,
+    zscore: f64,
+    context: Option<[Vec<u8>; 3]>,
+}
+
+impl PauseSite {
+    pub fn gene(&self) -> &str {
+        &self.gene
+    }
+    pub fn codon(&self) -> usize {
+        self.codon
+    }
+    pub fn count(&self) -> usize {
+        self.count
+    }
+    pub fn zscore(&self) -> f64 {
+        self.zscore
+    }
+}
+
+/// Calls pause sites within one gene, comparing the count on each
+/// codon against the mean and standard deviation of counts across the
+/// codons of the gene, after excluding codons at the start and end.
+///
+/// # Arguments
+///
+/// * `gene_counts` are the per-codon counts for the gene
+/// * `cds_seq` is the coding sequence beginning with the first
+/// complete codon, if known, used to report the E, P, and A site
+/// codons of each pause
+/// * `counted_site` is the site assigned to codons
+/// * `criteria` gives the thresholds for calling pauses
+pub fn gene_pauses(
+    gene_counts: &GeneCodonCounts,
+    cds_seq: Option<&[u8]>,
+    counted_site: Site,
+    criteria: &PauseCriteria,
+) -> Vec<PauseSite> {
+    let window = gene_counts.window_counts(criteria.exclude_start, criteria.exclude_end);
+    if window.len() < 2 {
+        return Vec::new();
+    }
+
+    let n = window.len() as f64;
+    let mean = window.iter().sum::<usize>() as f64 / n;
+    let var = window
+        .iter()
+        .map(|&ct| (ct as f64 - mean).powi(2))
+        .sum::<f64>()
+        / n;
+    let sd = var.sqrt();
+    if sd <= 0.0 {
+        return Vec::new();
+    }
+
+    let mut pauses = Vec::new();
+
+    for (i, &ct) in window.iter().enumerate() {
+        let zscore = (ct as f64 - mean) / sd;
+        if ct < criteria.min_count || zscore < criteria.min_zscore {
+            continue;
+        }
+
+        let codon = criteria.exclude_start + i;
+        let context = cds_seq.and_then(|seq| {
+            let site_seq =
+                |site| site_codon_seq(seq, codon, counted_site, site).map(|s| s.to_vec());
+            match (site_seq(Site::E), site_seq(Site::P), site_seq(Site::A)) {
+                (Some(e), Some(p), Some(a)) => Some([e, p, a]),
+                _ => None,
+            }
+        });
+
+        pauses.push(PauseSite {
+            gene: gene_counts.gene().to_owned(),
+            trxname: gene_counts.trxname().to_owned(),
+            codon: codon,
+            trxpos: gene_counts.codon_trxpos(codon),
+            count: ct,
+            mean: mean,
+            zscore: zscore,
+            context: context,
+        });
+    }
+
+    pauses
+}
+
+/// Returns a table of pause sites, with the codon index and
+/// transcript position, the codon count and gene mean, the z-score,
+/// and the E, P, and A site codons when the sequence is known.
+pub fn pause_table(pauses: &[PauseSite]) -> String {
+    let mut table = "gene\ttranscript\tcodon\ttrxpos\tcount\tmean\tzscore\tE\tP\tA\n".to_string();
+
+    for pause in pauses.iter() {
+        let context = match pause.context {
+            Some([ref e, ref p, ref a]) => format!(
+                "{}\t{}\t{}",
+                String::from_utf8_lossy(e),
+                String::from_utf8_lossy(p),
+                String::from_utf8_lossy(a)
+            ),
+            None => "NA\tNA\tNA".to_string(),
+        };
+
+        table += &format!(
+            "{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{}\n",
+            pause.gene,
+            pause.trxname,
+            pause.codon,
+            pause.trxpos,
+            pause.count,
+            pause.mean,
+            pause.zscore,
+            context
+        );
+    }
+
+    table
+}
@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+
+use codon_count::assign::*;
+
+/// Tally of the fates of BAM records in codon assignment.
+pub struct CodonStats {
+    unmapped: usize,
+    multi_hit: usize,
+    short: usize,
+    long: usize,
+    no_offset: usize,
+    no_offset_lengths: BTreeMap<usize, usize>,
+    no_gene: usize,
+    noncoding: usize,
+    multi_coding: usize,
+    incompatible: usize,
+    outside_cds: usize,
+    codon: usize,
+    umi_duplicate: usize,
+}
+
+impl CodonStats {
+    pub fn new() -> Self {
+        CodonStats {
+            unmapped: 0,
+            multi_hit: 0,
+            short: 0,
+            long: 0,
+            no_offset: 0,
+            no_offset_lengths: BTreeMap::new(),
+            no_gene: 0,
+            noncoding: 0,
+            multi_coding: 0,
+            incompatible: 0,
+            outside_cds: 0,
+            codon: 0,
+            umi_duplicate: 0,
+        }
+    }
+
+    pub fn tally_bam_codon(&mut self, bam_codon: &BamCodonResult) {
+        match bam_codon {
+            BamCodonResult::NoHit => self.unmapped += 1,
+            BamCodonResult::MultiHit => self.multi_hit += 1,
+            BamCodonResult::TooShort => self.short += 1,
+            BamCodonResult::TooLong => self.long += 1,
+            BamCodonResult::Fp(FpCodonResult::NoOffset(len), _) => {
+                self.no_offset += 1;
+                *self.no_offset_lengths.entry(*len).or_insert(0) += 1;
+            }
+            BamCodonResult::Fp(FpCodonResult::NoGene, _) => self.no_gene += 1,
+            BamCodonResult::Fp(FpCodonResult::NoncodingOnly, _) => self.noncoding += 1,
+            BamCodonResult::Fp(FpCodonResult::MultiCoding, _) => self.multi_coding += 1,
+            BamCodonResult::Fp(FpCodonResult::NoCompatible, _) => self.incompatible += 1,
+            BamCodonResult::Fp(FpCodonResult::OutsideCds, _) => self.outside_cds += 1,
+            BamCodonResult::Fp(FpCodonResult::Codon(_), _) => self.codon += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.unmapped
+            + self.multi_hit
+            + self.short
+            + self.long
+            + self.no_offset
+            + self.no_gene
+            + self.noncoding
+            + self.multi_coding
+            + self.incompatible
+            + self.outside_cds
+            + self.codon
+    }
+
+    /// Returns the number of alignments tallied that were aligned to
+    /// the genome.
+    pub fn mapped(&self) -> usize {
+        self.total() - self.unmapped
+    }
+
+    pub fn codon(&self) -> usize {
+        self.codon
+    }
+
+    /// Tallies a footprint assigned to a codon that was collapsed as
+    /// a UMI duplicate of an earlier footprint. Collapsed footprints
+    /// are not otherwise tallied, and are not part of `total()`.
+    pub fn tally_umi_duplicate(&mut self) {
+        self.umi_duplicate += 1;
+    }
+
+    pub fn umi_duplicate(&self) -> usize {
+        self.umi_duplicate
+    }
+
+    pub fn no_offset(&self) -> usize {
+        self.no_offset
+    }
+
+    /// Returns a description of the footprints dropped for lacking a
+    /// site offset, broken down by length, or `None` if none were.
+    pub fn no_offset_report(&self) -> Option<String> {
+        if self.no_offset == 0 {
+            return None;
+        }
+
+        let by_length: Vec<String> = self
+            .no_offset_lengths
+            .iter()
+            .map(|(len, ct)| format!("{} nt: {}", len, ct))
+            .collect();
+        Some(format!(
+            "Dropped {} footprints with no site offset ({})",
+            self.no_offset,
+            by_length.join(", ")
+        ))
+    }
+
+    pub fn table(&self) -> String {
+        let ttl = self.total() as f64;
+        let mut tbl = format!("TOTAL\t{}\n", self.total());
+
+        for (name, ct) in [
+            ("BamNoHit", self.unmapped),
+            ("BamMultiHit", self.multi_hit),
+            ("BamTooShort", self.short),
+            ("BamTooLong", self.long),
+            ("NoOffset", self.no_offset),
+            ("NoGene", self.no_gene),
+            ("NoncodingOnly", self.noncoding),
+            ("MultiCoding", self.multi_coding),
+            ("NoCompatible", self.incompatible),
+            ("OutsideCds", self.outside_cds),
+            ("Codon", self.codon),
+        ].iter()
+        {
+            tbl += &format!("{}\t{}\t{:.04}\n", name, ct, *ct as f64 / ttl);
+        }
+
+        if self.umi_duplicate > 0 {
+            tbl += &format!(
+                "CodonUmiDuplicate\t{}\t{:.04}\n",
+                self.umi_duplicate,
+                self.umi_duplicate as f64 / ttl
+            );
+        }
+
+        for (len, ct) in self.no_offset_lengths.iter() {
+            tbl += &format!("NoOffset{}\t{}\t{:.04}\n", len, ct, *ct as f64 / ttl);
+        }
+
+        tbl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn umi_duplicate_row() {
+        let mut stats = CodonStats::new();
+        stats.tally_bam_codon(&BamCodonResult::NoHit);
+        stats.tally_bam_codon(&BamCodonResult::MultiHit);
+        assert!(!stats.table().contains("CodonUmiDuplicate"));
+
+        stats.tally_umi_duplicate();
+        assert_eq!(stats.umi_duplicate(), 1);
+        assert_eq!(stats.total(), 2);
+        assert!(stats.table().contains("CodonUmiDuplicate\t1\t0.5000\n"));
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use failure;
+
+use bio::io::bed;
+use bio_types::annot::loc::Loc;
+use bio_types::annot::pos::Pos;
+use bio_types::annot::spliced::Spliced;
+use bio_types::strand::ReqStrand;
+
+use transcript::*;
+
+/// Window in the coordinates of one transcript, such as a reporter
+/// cassette, where site occupancy is reported at each nucleotide.
+#[derive(Debug, Clone)]
+pub struct TrxWindow {
+    name: String,
+    trxname: String,
+    loc: Spliced<Rc<String>, ReqStrand>,
+    start: usize,
+    end: usize,
+}
+
+impl TrxWindow {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end == self.start
+    }
+
+    /// Returns the offset of a genomic position within the window,
+    /// when it lies on the same strand as the transcript.
+    pub fn offset(&self, pos: &Pos<Rc<String>, ReqStrand>) -> Option<usize> {
+        let trxpos = self.loc.pos_into(pos)?;
+        if trxpos.strand() != ReqStrand::Forward || trxpos.pos() < 0 {
+            return None;
+        }
+        let trxpos = trxpos.pos() as usize;
+        if trxpos >= self.start && trxpos < self.end {
+            Some(trxpos - self.start)
+        } else {
+            None
+        }
+    }
+}
+
+/// Transcript-coordinate windows with per-nucleotide site counts.
+pub struct WindowCounts {
+    windows: Vec<TrxWindow>,
+    counts: Vec<Vec<usize>>,
+}
+
+impl WindowCounts {
+    /// Reads windows from a BED-format file whose "chromosome" is a
+    /// transcript name, with start and end in transcript coordinates.
+    /// Windows are named by the BED name column, when present, and
+    /// otherwise by their transcript and coordinates.
+    pub fn new_from_bed<P: AsRef<Path>>(
+        path: P,
+        trxome: &Transcriptome<Rc<String>>,
+    ) -> Result<Self, failure::Error> {
+        Self::new_from_bed_records(bed::Reader::from_file(path)?.records(), trxome)
+    }
+
+    pub fn new_from_bed_records<B: io::Read>(
+        records: bed::Records<B>,
+        trxome: &Transcriptome<Rc<String>>,
+    ) -> Result<Self, failure::Error> {
+        let trxs: HashMap<&str, &Transcript<Rc<String>>> = trxome
+            .transcripts()
+            .map(|trx| (trx.trxname(), trx))
+            .collect();
+
+        let mut windows = Vec::new();
+        for recres in records {
+            let rec = recres?;
+            let trx = match trxs.get(rec.chrom()) {
+                Some(trx) => trx,
+                None => bail!("Window transcript \"{}\" is not annotated", rec.chrom()),
+            };
+            let (start, end) = (rec.start() as usize, rec.end() as usize);
+            if start >= end || end > trx.loc().exon_total_length() {
+                bail!(
+                    "Window {}:{}-{} is empty or extends past the end of the transcript",
+                    rec.chrom(),
+                    start,
+                    end
+                );
+            }
+            windows.push(TrxWindow {
+                name: match rec.name() {
+                    Some(name) if !name.is_empty() => name.to_string(),
+                    _ => format!("{}:{}-{}", rec.chrom(), start, end),
+                },
+                trxname: rec.chrom().to_string(),
+                loc: trx.loc().clone(),
+                start: start,
+                end: end,
+            });
+        }
+
+        let counts = windows.iter().map(|window| vec![0; window.len()]).collect();
+        Ok(WindowCounts {
+            windows: windows,
+            counts: counts,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.windows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    /// Returns a copy of the windows with all counts set to zero, for
+    /// counting another sample.
+    pub fn empty_copy(&self) -> Self {
+        WindowCounts {
+            windows: self.windows.clone(),
+            counts: self
+                .windows
+                .iter()
+                .map(|window| vec![0; window.len()])
+                .collect(),
+        }
+    }
+
+    /// Tallies a site in every window containing it.
+    pub fn tally(&mut self, pos: &Pos<Rc<String>, ReqStrand>) {
+        for (window, counts) in self.windows.iter().zip(self.counts.iter_mut()) {
+            if window.loc.refid() != pos.refid() {
+                continue;
+            }
+            if let Some(offset) = window.offset(pos) {
+                counts[offset] += 1;
+            }
+        }
+    }
+
+    /// Returns the counts at each nucleotide of a window.
+    pub fn counts(&self, window: usize) -> &[usize] {
+        &self.counts[window]
+    }
+
+    /// Returns a long-format table with one row per nucleotide of each
+    /// window, giving its transcript position, its offset within the
+    /// window, its count, and the count normalized to the mean count
+    /// across the window, which is `NA` for a window with no counts.
+    pub fn table(&self) -> String {
+        let mut table = "window\ttranscript\ttrxpos\toffset\tcount\tnorm\n".to_string();
+        for (window, counts) in self.windows.iter().zip(self.counts.iter()) {
+            let mean = counts.iter().sum::<usize>() as f64 / window.len() as f64;
+            for (offset, count) in counts.iter().enumerate() {
+                table += &format!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\n",
+                    window.name,
+                    window.trxname,
+                    window.start + offset,
+                    offset,
+                    count,
+                    if mean > 0.0 {
+                        format!("{:.4}", *count as f64 / mean)
+                    } else {
+                        "NA".to_string()
+                    }
+                );
+            }
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bio_types::annot::refids::RefIDSet;
+
+    fn transcriptome_from_str(bedstr: &str) -> Transcriptome<Rc<String>> {
+        let mut refids = RefIDSet::new();
+        Transcriptome::new_from_bed(bed::Reader::new(bedstr.as_bytes()).records(), &mut refids)
+            .expect("Transcriptome from string")
+    }
+
+    fn reporter_trxome() -> Transcriptome<Rc<String>> {
+        // Reporter exons [100..120) and [200..220), reverse control
+        // transcript on [300..340)
+        transcriptome_from_str(
+            "chr01\t100\t220\tReporter\t0\t+\t105\t215\t0\t2\t20,20,\t0,100,\n\
+             chr01\t300\t340\tControl\t0\t-\t300\t340\t0\t1\t40,\t0,\n",
+        )
+    }
+
+    fn windows_from_str(windowstr: &str) -> Result<WindowCounts, failure::Error> {
+        WindowCounts::new_from_bed_records(
+            bed::Reader::new(windowstr.as_bytes()).records(),
+            &reporter_trxome(),
+        )
+    }
+
+    fn tallied_windows() -> WindowCounts {
+        let mut windows = windows_from_str("Reporter\t18\t22\tstall\nControl\t0\t2\n").unwrap();
+        let pos = |pos_str: &str| -> Pos<Rc<String>, ReqStrand> { pos_str.parse().unwrap() };
+        windows.tally(&pos("chr01:118(+)"));
+        windows.tally(&pos("chr01:201(+)"));
+        windows.tally(&pos("chr01:201(+)"));
+        windows.tally(&pos("chr01:201(-)"));
+        windows.tally(&pos("chr01:150(+)"));
+        windows.tally(&pos("chr01:338(-)"));
+        windows
+    }
+
+    #[test]
+    fn window_counts() {
+        let windows = tallied_windows();
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows.counts(0), &[1, 0, 0, 2]);
+        assert_eq!(windows.counts(1), &[0, 1]);
+        assert_eq!(windows.empty_copy().counts(0), &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn window_table() {
+        assert_eq!(
+            tallied_windows().table(),
+            "window\ttranscript\ttrxpos\toffset\tcount\tnorm\n\
+             stall\tReporter\t18\t0\t1\t1.3333\n\
+             stall\tReporter\t19\t1\t0\t0.0000\n\
+             stall\tReporter\t20\t2\t0\t0.0000\n\
+             stall\tReporter\t21\t3\t2\t2.6667\n\
+             Control:0-2\tControl\t0\t0\t0\t0.0000\n\
+             Control:0-2\tControl\t1\t1\t1\t2.0000\n"
+        );
+    }
+
+    #[test]
+    fn bad_windows() {
+        assert!(windows_from_str("Reporter\t30\t50\n").is_err());
+        assert!(windows_from_str("Missing\t0\t10\n").is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+
+use clap::{Arg, ArgMatches};
+use failure;
+use serde::Serialize;
+use serde_json;
+use serde_yaml;
+use toml;
+
+/// Returns the `--config` and `--print-config` arguments shared by all
+/// tools. The configuration file itself is read by `args_with_config`.
+pub fn config_file_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("config")
+            .long("config")
+            .value_name("CONFIG.TOML")
+            .help("Read options from a TOML (or .yaml) file of long option names and values")
+            .takes_value(true),
+        Arg::with_name("print-config")
+            .long("print-config")
+            .help("Print the effective options as a configuration file and exit"),
+    ]
+}
+
+/// A value in a configuration file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum ConfigValue {
+    Flag(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+    List(Vec<ConfigValue>),
+}
+
+impl ConfigValue {
+    fn option_value(&self) -> Result<String, failure::Error> {
+        match self {
+            ConfigValue::Integer(i) => Ok(i.to_string()),
+            ConfigValue::Float(x) => Ok(x.to_string()),
+            ConfigValue::Text(s) => Ok(s.to_string()),
+            _ => bail!("Expecting a number or string but got {:?}", self),
+        }
+    }
+}
+
+/// Returns the command-line arguments of this process, with options
+/// from a `--config` file inserted ahead of those given explicitly.
+///
+/// A configuration file is a TOML table, or for files ending in
+/// `.yaml` or `.yml` a YAML mapping, whose keys are long option names
+/// (without the leading `--`):
+///
+/// ```toml
+/// bed = "genes.bed"
+/// lengths = "26,34"
+/// min-offset-count = 50
+/// count-multi = true
+/// genes = ["genes.txt", "suppressed.txt"]
+/// ```
+///
+/// Numbers and strings give option values, `true` sets a flag and
+/// `false` leaves it unset, and a list repeats the option once per
+/// value. Positional arguments, such as input filenames, are always
+/// given on the command line.
+///
+/// Tools parse these arguments with `AppSettings::AllArgsOverrideSelf`
+/// so that an option given on the command line overrides the one in
+/// the file. Values for options that can be repeated are combined.
+pub fn args_with_config() -> Result<Vec<OsString>, failure::Error> {
+    let args: Vec<OsString> = env::args_os().collect();
+
+    let config_file = match find_config_file(&args) {
+        Some(config_file) => config_file,
+        None => return Ok(args),
+    };
+    let config_args = read_config_file(&config_file)?;
+
+    let mut merged = Vec::new();
+    merged.extend(args.iter().take(1).cloned());
+    merged.extend(config_args.into_iter().map(OsString::from));
+    merged.extend(args.into_iter().skip(1));
+    Ok(merged)
+}
+
+fn find_config_file(args: &[OsString]) -> Option<OsString> {
+    let mut config_file = None;
+    let mut arg_iter = args.iter().skip(1);
+    while let Some(arg) = arg_iter.next() {
+        let arg = arg.to_string_lossy();
+        if arg == "--" {
+            break;
+        } else if arg == "--config" {
+            config_file = arg_iter.next().cloned();
+        } else if arg.starts_with("--config=") {
+            config_file = Some(OsString::from(&arg["--config=".len()..]));
+        }
+    }
+    config_file
+}
+
+/// Reads a configuration file and returns the equivalent command-line
+/// arguments. See `args_with_config` for the file format.
+pub fn read_config_file<P: AsRef<Path>>(path: P) -> Result<Vec<String>, failure::Error> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .map_err(|err| format_err!("Reading configuration file {:?}: {}", path, err))?;
+    let yaml = path
+        .extension()
+        .map_or(false, |ext| ext == "yaml" || ext == "yml");
+    config_args(&text, yaml).map_err(|err| format_err!("Configuration file {:?}: {}", path, err))
+}
+
+/// Parses configuration file text, in TOML or YAML format, into the
+/// equivalent command-line arguments.
+///
+/// ```
+/// # use riboprof::config_file::*;
+/// let args = config_args("lengths = \"26,34\"\ncount-multi = true\n", false).unwrap();
+/// assert_eq!(args, vec!["--count-multi", "--lengths", "26,34"]);
+/// ```
+pub fn config_args(text: &str, yaml: bool) -> Result<Vec<String>, failure::Error> {
+    let options: BTreeMap<String, ConfigValue> = if yaml {
+        serde_yaml::from_str(text)?
+    } else {
+        toml::from_str(text)?
+    };
+
+    let mut args = Vec::new();
+    for (name, value) in options.iter() {
+        if name == "config" || name == "print-config" {
+            bail!("Option \"{}\" not allowed in a configuration file", name);
+        }
+
+        let option = format!("--{}", name);
+        match value {
+            ConfigValue::Flag(true) => args.push(option),
+            ConfigValue::Flag(false) => (),
+            ConfigValue::List(values) => {
+                for value in values.iter() {
+                    args.push(option.clone());
+                    args.push(value.option_value()?);
+                }
+            }
+            _ => {
+                args.push(option);
+                args.push(value.option_value()?);
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// Prints the effective options in `cli` as a TOML configuration file
+/// when `--print-config` was given, returning `true` if it was printed.
+///
+/// Fields of `cli` are serialized under their long option names, with
+/// positional arguments skipped, so the output can be read back with
+/// `--config`.
+pub fn print_config<T: Serialize>(matches: &ArgMatches, cli: &T) -> Result<bool, failure::Error> {
+    if matches.is_present("print-config") {
+        print!("{}", config_toml(cli)?);
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+/// Formats the options in `cli` as a TOML configuration file. Unset
+/// options are omitted.
+pub fn config_toml<T: Serialize>(cli: &T) -> Result<String, failure::Error> {
+    let options = match serde_json::to_value(cli)? {
+        serde_json::Value::Object(options) => options,
+        value => bail!("Cannot write {} as a configuration", value),
+    };
+
+    let mut config = String::new();
+    for (name, value) in options.iter() {
+        match value {
+            serde_json::Value::Null => (),
+            serde_json::Value::Array(values) => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                config += &format!("{} = [{}]\n", name, values.join(", "));
+            }
+            _ => config += &format!("{} = {}\n", name, value),
+        }
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "kebab-case")]
+    struct TestCLI {
+        #[serde(skip_serializing)]
+        input: String,
+        output: String,
+        min_offset_count: String,
+        genes: Vec<String>,
+        annotate: Option<String>,
+        count_multi: bool,
+        frame_tables: bool,
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let cli = TestCLI {
+            input: "in.bam".to_string(),
+            output: "out/wt \"1\"".to_string(),
+            min_offset_count: "50".to_string(),
+            genes: vec!["a.txt".to_string(), "b.txt".to_string()],
+            annotate: None,
+            count_multi: true,
+            frame_tables: false,
+        };
+
+        let toml = config_toml(&cli).unwrap();
+        assert_eq!(
+            toml,
+            "count-multi = true\n\
+             frame-tables = false\n\
+             genes = [\"a.txt\", \"b.txt\"]\n\
+             min-offset-count = \"50\"\n\
+             output = \"out/wt \\\"1\\\"\"\n"
+        );
+
+        let args = vec![
+            "--count-multi",
+            "--genes",
+            "a.txt",
+            "--genes",
+            "b.txt",
+            "--min-offset-count",
+            "50",
+            "--output",
+            "out/wt \"1\"",
+        ];
+        assert_eq!(config_args(&toml, false).unwrap(), args);
+
+        let yaml = "output: out/wt \"1\"\nmin-offset-count: 50\ncount-multi: true\n\
+                    frame-tables: false\ngenes:\n  - a.txt\n  - b.txt\n";
+        assert_eq!(config_args(yaml, true).unwrap(), args);
+
+        assert!(config_args("config = \"other.toml\"\n", false).is_err());
+        assert!(config_args("[fp-framing]\nbed = \"genes.bed\"\n", false).is_err());
+    }
+}
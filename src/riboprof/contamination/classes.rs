@@ -0,0 +1,140 @@
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use failure;
+
+use bio::data_structures::annot_map::AnnotMap;
+use bio::io::bed;
+use bio_types::annot::contig::Contig;
+use bio_types::annot::loc::Loc;
+use bio_types::annot::spliced::Spliced;
+use bio_types::strand::ReqStrand;
+
+use timing::*;
+
+/// Features belonging to classes of contaminating RNAs, such as rRNA
+/// or tRNA genes, indexed by location.
+pub struct ContaminantClasses {
+    names: Vec<String>,
+    features: AnnotMap<Rc<String>, ClassFeature>,
+}
+
+/// Feature of a contaminant class, along with its strand when the
+/// feature is stranded.
+struct ClassFeature {
+    class: usize,
+    strand: Option<ReqStrand>,
+}
+
+impl ContaminantClasses {
+    /// Reads contaminant features from a BED file. The name of each
+    /// feature gives its class, e.g., `rRNA` or `tRNA`, and features
+    /// with no strand match reads on either strand.
+    pub fn new_from_bed<P: AsRef<Path>>(path: P) -> Result<Self, failure::Error> {
+        let _timer = StageTimer::new("annotation load");
+        Self::new_from_bed_records(bed::Reader::from_file(path)?.records())
+    }
+
+    pub fn new_from_bed_records<B: io::Read>(
+        records: bed::Records<B>,
+    ) -> Result<Self, failure::Error> {
+        const STRAND_COL: usize = 5;
+
+        let mut names: Vec<String> = Vec::new();
+        let mut features = AnnotMap::new();
+
+        for recres in records {
+            let rec = recres?;
+
+            let name = match rec.name() {
+                Some(name) => name.to_owned(),
+                None => bail!(
+                    "Contaminant feature at {}:{} has no class name",
+                    rec.chrom(),
+                    rec.start()
+                ),
+            };
+            let class = match names.iter().position(|known| *known == name) {
+                Some(class) => class,
+                None => {
+                    names.push(name);
+                    names.len() - 1
+                }
+            };
+
+            let strand = match rec.aux(STRAND_COL) {
+                Some("+") => Some(ReqStrand::Forward),
+                Some("-") => Some(ReqStrand::Reverse),
+                _ => None,
+            };
+
+            let contig = Contig::new(
+                Rc::new(rec.chrom().to_owned()),
+                rec.start() as isize,
+                (rec.end() - rec.start()) as usize,
+                strand.unwrap_or(ReqStrand::Forward),
+            );
+            features.insert_at(
+                ClassFeature {
+                    class: class,
+                    strand: strand,
+                },
+                &contig,
+            );
+        }
+
+        Ok(ContaminantClasses {
+            names: names,
+            features: features,
+        })
+    }
+
+    /// Returns the class names, in the order of their first
+    /// appearance in the BED file.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns the class of an alignment, or `None` when it overlaps
+    /// no contaminant feature on the same strand. When features of
+    /// several classes overlap, the class appearing first in the BED
+    /// file takes priority.
+    pub fn classify(&self, loc: &Spliced<Rc<String>, ReqStrand>) -> Option<usize> {
+        self.features
+            .find(loc)
+            .map(|ent| ent.data())
+            .filter(|feature| feature.strand.map_or(true, |strand| strand == loc.strand()))
+            .map(|feature| feature.class)
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_reads() {
+        let bedstr = "chr01\t100\t200\trRNA\t0\t+\n\
+                      chr01\t150\t160\ttRNA\t0\t-\n\
+                      chr01\t300\t400\ttRNA\n\
+                      chrM\t0\t1000\tmito\t0\t+\n";
+        let classes =
+            ContaminantClasses::new_from_bed_records(bed::Reader::new(bedstr.as_bytes()).records())
+                .unwrap();
+        assert_eq!(classes.names(), &["rRNA", "tRNA", "mito"]);
+
+        let classify = |loc_str: &str| {
+            let loc: Spliced<Rc<String>, ReqStrand> = loc_str.parse().unwrap();
+            classes.classify(&loc)
+        };
+        assert_eq!(classify("chr01:110-138(+)"), Some(0));
+        assert_eq!(classify("chr01:110-138(-)"), None);
+        assert_eq!(classify("chr01:145-173(-)"), Some(1));
+        assert_eq!(classify("chr01:145-173(+)"), Some(0));
+        assert_eq!(classify("chr01:380-408(-)"), Some(1));
+        assert_eq!(classify("chrM:500-528(+)"), Some(2));
+        assert_eq!(classify("chr02:500-528(+)"), None);
+    }
+}
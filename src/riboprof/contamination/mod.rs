@@ -0,0 +1,121 @@
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use failure;
+
+use bio_types::annot::refids::RefIDSet;
+use rust_htslib::bam;
+use rust_htslib::bam::Read as BamRead;
+
+use bam_utils::*;
+use cli_utils::*;
+use corrupt::*;
+use output::*;
+use timing::*;
+
+mod classes;
+mod stats;
+
+pub use contamination::classes::*;
+pub use contamination::stats::*;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CLI {
+    #[serde(skip_serializing)]
+    pub input: String,
+    pub output: String,
+    pub classes: String,
+    pub io_threads: String,
+    #[serde(flatten)]
+    pub read_filter: ReadFilterCLI,
+    #[serde(flatten)]
+    pub output_config: OutputCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+pub struct Config {
+    input: String,
+    output: PathBuf,
+    classes: ContaminantClasses,
+    io_threads: usize,
+    read_filter: ReadFilter,
+    output_config: OutputConfig,
+    corrupt: CorruptInputs,
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        Ok(Config {
+            input: cli.input.to_string(),
+            output: Path::new(&cli.output).to_path_buf(),
+            classes: ContaminantClasses::new_from_bed(&cli.classes)?,
+            io_threads: cli.io_threads.parse()?,
+            read_filter: ReadFilter::new_from_cli(&cli.read_filter)?
+                .exclude_flags(NON_PRIMARY_FLAGS),
+            output_config: OutputConfig::new_from_cli(&cli.output_config)?,
+            corrupt: cli.corrupt.clone(),
+        })
+    }
+
+    fn output_filename(&self, suffix: &str) -> PathBuf {
+        output_filename(&self.output, suffix)
+    }
+}
+
+pub fn run_contamination(config: Config) -> Result<(), failure::Error> {
+    let mut input = open_bam(&config.input, &config.corrupt)?;
+    set_reader_threads(&mut input, config.io_threads)?;
+
+    let tids = {
+        let mut refids: RefIDSet<Rc<String>> = RefIDSet::new();
+        Tids::new(&mut refids, input.header())
+    };
+
+    // Open (empty) output file early to detect errors before processing data.
+    config
+        .output_config
+        .create(config.output_filename("_contamination.txt"))?;
+
+    let classify = StageTimer::new("classification");
+    let mut stats = ContaminationStats::new(config.classes.names());
+    let mut filter_stats = ReadFilterStats::default();
+
+    for recres in spliced_records(&mut input, &tids, &config.corrupt) {
+        let (rec, fp) = recres?;
+
+        if !filter_stats.tally(&config.read_filter, &rec) {
+            continue;
+        }
+
+        match fp {
+            Some(ref fp) if !rec.is_unmapped() => {
+                stats.tally(config.classes.classify(fp), rec.seq().len())
+            }
+            _ => stats.tally_unaligned(),
+        }
+    }
+    classify.finish();
+
+    let _timer = StageTimer::new("output");
+    config
+        .output_config
+        .write(config.output_filename("_contamination.txt"), stats.table())?;
+    config.output_config.write(
+        config.output_filename("_contamination_lengths.txt"),
+        stats.length_table(),
+    )?;
+    config.output_config.write(
+        config.output_filename("_filter_stats.txt"),
+        filter_stats.table(),
+    )?;
+
+    info!(
+        "Residual fraction {:.4} of {} aligned reads",
+        stats.residual_fraction(),
+        stats.aligned()
+    );
+
+    Ok(())
+}
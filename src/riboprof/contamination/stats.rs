@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+
+/// Tallies of reads in each contaminant class, overall and by read
+/// length. Aligned reads outside every contaminant class are tallied
+/// as residual reads, which are largely mRNA footprints.
+pub struct ContaminationStats {
+    names: Vec<String>,
+    unaligned: usize,
+    counts: Vec<usize>,
+    len_counts: BTreeMap<usize, Vec<usize>>,
+}
+
+impl ContaminationStats {
+    /// Creates empty tallies for the named contaminant classes.
+    pub fn new(names: &[String]) -> Self {
+        ContaminationStats {
+            names: names.to_vec(),
+            unaligned: 0,
+            counts: vec![0; names.len() + 1],
+            len_counts: BTreeMap::new(),
+        }
+    }
+
+    /// Tallies an unaligned read.
+    pub fn tally_unaligned(&mut self) {
+        self.unaligned += 1;
+    }
+
+    /// Tallies an aligned read of length `len` in contaminant class
+    /// `class`, or as a residual read when `class` is `None`.
+    pub fn tally(&mut self, class: Option<usize>, len: usize) {
+        let idx = class.unwrap_or(self.names.len());
+        self.counts[idx] += 1;
+
+        let nclasses = self.counts.len();
+        self.len_counts
+            .entry(len)
+            .or_insert_with(|| vec![0; nclasses])[idx] += 1;
+    }
+
+    /// Returns the number of aligned reads.
+    pub fn aligned(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Returns the fraction of aligned reads that are residual reads.
+    pub fn residual_fraction(&self) -> f64 {
+        self.counts[self.names.len()] as f64 / self.aligned().max(1) as f64
+    }
+
+    fn class_names(&self) -> impl Iterator<Item = &str> {
+        self.names
+            .iter()
+            .map(|name| name.as_str())
+            .chain(Some("Residual"))
+    }
+
+    /// Returns a table giving the number of reads in each class and
+    /// their fraction of all aligned reads, followed by the total
+    /// number of aligned and unaligned reads and their fraction of all
+    /// reads.
+    pub fn table(&self) -> String {
+        let aligned = self.aligned();
+        let total = aligned + self.unaligned;
+
+        let mut table = "class\treads\tfraction\n".to_string();
+        for (name, ct) in self.class_names().zip(self.counts.iter()) {
+            table += &format!(
+                "{}\t{}\t{:.4}\n",
+                name,
+                ct,
+                *ct as f64 / aligned.max(1) as f64
+            );
+        }
+        table += &format!(
+            "Aligned\t{}\t{:.4}\n",
+            aligned,
+            aligned as f64 / total.max(1) as f64
+        );
+        table += &format!(
+            "Unaligned\t{}\t{:.4}\n",
+            self.unaligned,
+            self.unaligned as f64 / total.max(1) as f64
+        );
+        table
+    }
+
+    /// Returns a table with one row for each read length and class,
+    /// giving the number of aligned reads and their fraction of all
+    /// aligned reads with that length.
+    pub fn length_table(&self) -> String {
+        let mut table = "length\tclass\treads\tfraction\n".to_string();
+        for (len, counts) in self.len_counts.iter() {
+            let len_total: usize = counts.iter().sum();
+            for (name, ct) in self.class_names().zip(counts.iter()) {
+                table += &format!(
+                    "{}\t{}\t{}\t{:.4}\n",
+                    len,
+                    name,
+                    ct,
+                    *ct as f64 / len_total.max(1) as f64
+                );
+            }
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contamination_tables() {
+        let mut stats = ContaminationStats::new(&["rRNA".to_string(), "tRNA".to_string()]);
+        stats.tally(Some(0), 30);
+        stats.tally(Some(0), 30);
+        stats.tally(Some(1), 30);
+        stats.tally(None, 30);
+        stats.tally(None, 28);
+        stats.tally_unaligned();
+        assert_eq!(stats.aligned(), 5);
+        assert_eq!(stats.residual_fraction(), 0.4);
+
+        assert_eq!(
+            stats.table(),
+            "class\treads\tfraction\n\
+             rRNA\t2\t0.4000\n\
+             tRNA\t1\t0.2000\n\
+             Residual\t2\t0.4000\n\
+             Aligned\t5\t0.8333\n\
+             Unaligned\t1\t0.1667\n"
+        );
+        assert_eq!(
+            stats.length_table(),
+            "length\tclass\treads\tfraction\n\
+             28\trRNA\t0\t0.0000\n\
+             28\ttRNA\t0\t0.0000\n\
+             28\tResidual\t1\t1.0000\n\
+             30\trRNA\t2\t0.5000\n\
+             30\ttRNA\t1\t0.2500\n\
+             30\tResidual\t1\t0.2500\n"
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use failure;
+
+use bio::io::fastq;
+
+/// Number of skipped inputs logged individually as warnings, after
+/// which they are logged only at the debugging level.
+const MAX_WARNINGS: usize = 10;
+
+/// The empty BGZF block that ends every complete BAM file.
+const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Kind of corrupt input that can be skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CorruptKind {
+    /// Malformed FASTQ record
+    Fastq,
+    /// BAM record that could not be interpreted, e.g., with an
+    /// unknown target or a negative position
+    BamRecord,
+    /// BAM file that ends early, either in a damaged BGZF block or,
+    /// with `strict_eof`, without the BGZF end-of-file marker
+    Truncated,
+}
+
+impl CorruptKind {
+    pub fn all() -> [CorruptKind; 3] {
+        [
+            CorruptKind::Fastq,
+            CorruptKind::BamRecord,
+            CorruptKind::Truncated,
+        ]
+    }
+
+    fn index(self) -> usize {
+        match self {
+            CorruptKind::Fastq => 0,
+            CorruptKind::BamRecord => 1,
+            CorruptKind::Truncated => 2,
+        }
+    }
+}
+
+impl fmt::Display for CorruptKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CorruptKind::Fastq => write!(f, "fastq_record"),
+            CorruptKind::BamRecord => write!(f, "bam_record"),
+            CorruptKind::Truncated => write!(f, "truncated_bam"),
+        }
+    }
+}
+
+/// Handling of corrupt inputs in one run, from `--max-errors`,
+/// `--skip-corrupt`, and `--strict-eof`, along with the count of
+/// corrupt inputs skipped so far. Clones share their counts, so that
+/// each reader in a run can hold its own copy. By default, the first
+/// corrupt input is an error and a missing BGZF end-of-file marker is
+/// only a warning.
+#[derive(Debug, Clone, Default)]
+pub struct CorruptInputs {
+    max_errors: usize,
+    strict_eof: bool,
+    quiet: bool,
+    skipped: Arc<[AtomicUsize; 3]>,
+}
+
+impl CorruptInputs {
+    /// Allows up to `max_errors` corrupt inputs to be skipped.
+    pub fn new(max_errors: usize) -> Self {
+        CorruptInputs {
+            max_errors: max_errors,
+            ..CorruptInputs::default()
+        }
+    }
+
+    /// Treats a BAM file without the BGZF end-of-file marker as
+    /// truncated, rather than warning and reading it anyway.
+    pub fn strict_eof(self, strict_eof: bool) -> Self {
+        CorruptInputs {
+            strict_eof: strict_eof,
+            ..self
+        }
+    }
+
+    /// Returns a handle with the same limits that counts its skipped
+    /// inputs separately and logs them only at the debugging level,
+    /// for a preliminary pass over inputs that are read again later.
+    /// Each corrupt input is then counted and reported once, in the
+    /// later pass.
+    pub fn preliminary(&self) -> Self {
+        CorruptInputs {
+            max_errors: self.max_errors,
+            strict_eof: self.strict_eof,
+            quiet: true,
+            skipped: Arc::default(),
+        }
+    }
+
+    pub fn max_errors(&self) -> usize {
+        self.max_errors
+    }
+
+    /// Returns the number of corrupt inputs of `kind` skipped so far.
+    pub fn skipped(&self, kind: CorruptKind) -> usize {
+        self.skipped[kind.index()].load(Ordering::Relaxed)
+    }
+
+    /// Returns the total number of corrupt inputs skipped so far.
+    pub fn total(&self) -> usize {
+        CorruptKind::all()
+            .iter()
+            .map(|&kind| self.skipped(kind))
+            .sum()
+    }
+
+    /// Counts and logs a corrupt input of `kind`, which the caller
+    /// then skips.
+    ///
+    /// # Errors
+    ///
+    /// `err` is returned when skipping it would exceed the number of
+    /// corrupt inputs allowed. When none are allowed, `err` is
+    /// returned unchanged, just as it would be without `--max-errors`.
+    pub fn skip(&self, kind: CorruptKind, err: failure::Error) -> Result<(), failure::Error> {
+        if self.max_errors == 0 {
+            return Err(err);
+        }
+
+        let total = self.total() + 1;
+        if total > self.max_errors {
+            return Err(err
+                .context(format!(
+                    "Too many corrupt inputs: more than {} skipped",
+                    self.max_errors
+                ))
+                .into());
+        }
+
+        self.skipped[kind.index()].fetch_add(1, Ordering::Relaxed);
+        if self.quiet {
+            debug!("Skipping corrupt {}: {}", kind, err);
+        } else if total <= MAX_WARNINGS {
+            warn!("Skipping corrupt {}: {}", kind, err);
+            if total == MAX_WARNINGS {
+                warn!("Further corrupt inputs are logged only with --verbose");
+            }
+        } else {
+            debug!("Skipping corrupt {}: {}", kind, err);
+        }
+        Ok(())
+    }
+
+    /// Returns a table with the number of corrupt inputs of each kind
+    /// skipped during the run.
+    pub fn table(&self) -> String {
+        let mut table = "kind\tskipped\n".to_string();
+        for &kind in CorruptKind::all().iter() {
+            table += &format!("{}\t{}\n", kind, self.skipped(kind));
+        }
+        table
+    }
+
+    /// Returns an iterator over the records from `records`, skipping
+    /// those that are errors as long as corrupt inputs may be skipped,
+    /// and otherwise yielding the error.
+    pub fn records<I, T, E>(&self, kind: CorruptKind, records: I) -> SkipCorrupt<I::IntoIter>
+    where
+        I: IntoIterator<Item = Result<T, E>>,
+        E: Into<failure::Error>,
+    {
+        SkipCorrupt {
+            kind: kind,
+            corrupt: self.clone(),
+            records: records.into_iter(),
+        }
+    }
+
+    /// Returns the records read from `reader` that are well-formed,
+    /// with sequence and quality strings of the same length, and skips
+    /// malformed records as long as corrupt inputs may be skipped.
+    pub fn fastq_records<R: io::Read>(
+        &self,
+        reader: fastq::Reader<R>,
+    ) -> SkipCorrupt<impl Iterator<Item = Result<fastq::Record, failure::Error>>> {
+        self.records(
+            CorruptKind::Fastq,
+            reader
+                .records()
+                .map(|recres| -> Result<fastq::Record, failure::Error> {
+                    let rec = recres?;
+                    if let Err(problem) = rec.check() {
+                        bail!("Malformed FASTQ record {}: {}", rec.id(), problem);
+                    }
+                    Ok(rec)
+                }),
+        )
+    }
+
+    /// Checks that the BAM file at `path` is complete, ending with the
+    /// BGZF end-of-file marker, and warns when it is not. Files that
+    /// cannot be read from the end, such as pipes, are not checked.
+    ///
+    /// # Errors
+    ///
+    /// With `strict_eof`, an error variant is returned for a file
+    /// without the marker unless corrupt inputs may be skipped.
+    pub fn check_truncation<P: AsRef<Path>>(&self, path: P) -> Result<(), failure::Error> {
+        let path = path.as_ref();
+        let mut file = fs::File::open(path)?;
+        if !file.metadata()?.is_file() || has_bgzf_eof(&mut file)? {
+            return Ok(());
+        }
+
+        let problem = format!(
+            "{} may be truncated: the BGZF end-of-file marker is missing",
+            path.display()
+        );
+        if self.strict_eof {
+            self.skip(CorruptKind::Truncated, failure::err_msg(problem))?;
+        } else {
+            warn!("{}", problem);
+        }
+        Ok(())
+    }
+}
+
+/// Iterator that skips corrupt records, created by
+/// `CorruptInputs::records`.
+pub struct SkipCorrupt<I> {
+    kind: CorruptKind,
+    corrupt: CorruptInputs,
+    records: I,
+}
+
+impl<I, T, E> Iterator for SkipCorrupt<I>
+where
+    I: Iterator<Item = Result<T, E>>,
+    E: Into<failure::Error>,
+{
+    type Item = Result<T, failure::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.records.next()? {
+                Ok(rec) => return Some(Ok(rec)),
+                Err(err) => {
+                    if let Err(err) = self.corrupt.skip(self.kind, err.into()) {
+                        return Some(Err(err));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns `true` when `reader` ends with the BGZF end-of-file marker.
+pub fn has_bgzf_eof<R: Read + Seek>(reader: &mut R) -> io::Result<bool> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    if len < BGZF_EOF.len() as u64 {
+        return Ok(false);
+    }
+    reader.seek(SeekFrom::End(-(BGZF_EOF.len() as i64)))?;
+    let mut tail = [0; 28];
+    reader.read_exact(&mut tail)?;
+    Ok(tail == BGZF_EOF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::io::Cursor;
+    use std::process;
+
+    #[test]
+    fn fastq_check() {
+        let fastq = "@good\nACGT\n+\nIIII\n@short_qual\nACGT\n+\nIII\n";
+        let mut records =
+            CorruptInputs::default().fastq_records(fastq::Reader::new(fastq.as_bytes()));
+        assert_eq!(records.next().unwrap().unwrap().id(), "good");
+        let err = records.next().unwrap().err().unwrap();
+        assert!(err.to_string().contains("short_qual"));
+    }
+
+    #[test]
+    fn skip_limit() {
+        let corrupt = CorruptInputs::new(2);
+        let records = vec![
+            Ok(1),
+            Err(format_err!("bad")),
+            Ok(2),
+            Err(format_err!("bad")),
+        ];
+        let kept: Vec<usize> = corrupt
+            .records(CorruptKind::BamRecord, records)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(kept, vec![1, 2]);
+
+        let shared = corrupt.clone();
+        assert_eq!(shared.skipped(CorruptKind::BamRecord), 2);
+        assert!(shared.skip(CorruptKind::Fastq, format_err!("bad")).is_err());
+        assert_eq!(corrupt.total(), 2);
+        assert_eq!(
+            corrupt.table(),
+            "kind\tskipped\nfastq_record\t0\nbam_record\t2\ntruncated_bam\t0\n"
+        );
+    }
+
+    #[test]
+    fn bgzf_eof() {
+        let mut complete = vec![0x1f, 0x8b, 0x08, 0x04];
+        complete.extend(BGZF_EOF.iter());
+        assert!(has_bgzf_eof(&mut Cursor::new(complete.clone())).unwrap());
+
+        complete.pop();
+        assert!(!has_bgzf_eof(&mut Cursor::new(complete.clone())).unwrap());
+        assert!(!has_bgzf_eof(&mut Cursor::new(vec![0x1f, 0x8b])).unwrap());
+
+        let path = env::temp_dir().join(format!("riboprof-corrupt-{}.bam", process::id()));
+        fs::write(&path, &complete).unwrap();
+        assert!(CorruptInputs::default().check_truncation(&path).is_ok());
+        assert!(CorruptInputs::default()
+            .strict_eof(true)
+            .check_truncation(&path)
+            .is_err());
+        let lenient = CorruptInputs::new(1).strict_eof(true);
+        assert!(lenient.check_truncation(&path).is_ok());
+        assert_eq!(lenient.skipped(CorruptKind::Truncated), 1);
+
+        let preliminary = lenient.preliminary();
+        assert!(preliminary.check_truncation(&path).is_ok());
+        assert_eq!(preliminary.skipped(CorruptKind::Truncated), 1);
+        assert_eq!(lenient.skipped(CorruptKind::Truncated), 1);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(CorruptKind::Truncated.to_string(), "truncated_bam");
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use failure;
+use rust_htslib::bam;
+use serde_json;
+
+use bam_utils::UnsortedError;
+use fastx_split::SampleError;
+use transcript::TrxError;
+
+/// Category of the error that ended a run, so that workflow engines
+/// can branch on the type of failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Transcript annotation could not be parsed or was inconsistent
+    BadAnnotation,
+    /// Alignments were not in the sorted order required
+    UnsortedBam,
+    /// Two samples in a sample sheet share an index
+    BarcodeClash,
+    /// A file could not be opened, read, or written
+    Io,
+    /// Any other error
+    Other,
+}
+
+impl ErrorCode {
+    /// Classifies an error by the first error in its chain of causes
+    /// that has a known type.
+    pub fn new_from_error(err: &failure::Error) -> Self {
+        err.iter_chain()
+            .filter_map(|fail| Self::new_from_fail(fail))
+            .next()
+            .unwrap_or(ErrorCode::Other)
+    }
+
+    fn new_from_fail(fail: &failure::Fail) -> Option<Self> {
+        if let Some(trx_err) = fail.downcast_ref::<TrxError>() {
+            return match trx_err {
+                TrxError::Open(..) => Some(ErrorCode::Io),
+                _ => Some(ErrorCode::BadAnnotation),
+            };
+        }
+        if fail.downcast_ref::<UnsortedError>().is_some() {
+            return Some(ErrorCode::UnsortedBam);
+        }
+        if let Some(SampleError::IndexClash(_)) = fail.downcast_ref::<SampleError>() {
+            return Some(ErrorCode::BarcodeClash);
+        }
+        if fail.downcast_ref::<io::Error>().is_some()
+            || fail.downcast_ref::<bam::ReaderPathError>().is_some()
+            || fail.downcast_ref::<bam::ReadError>().is_some()
+            || fail.downcast_ref::<bam::WriterPathError>().is_some()
+            || fail.downcast_ref::<bam::WriteError>().is_some()
+        {
+            return Some(ErrorCode::Io);
+        }
+        None
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorCode::BadAnnotation => write!(f, "bad_annotation"),
+            ErrorCode::UnsortedBam => write!(f, "unsorted_bam"),
+            ErrorCode::BarcodeClash => write!(f, "barcode_clash"),
+            ErrorCode::Io => write!(f, "io"),
+            ErrorCode::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// Machine-readable description of the error that ended a run,
+/// written as JSON with `--error-json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorReport {
+    pub tool: String,
+    pub code: ErrorCode,
+    pub message: String,
+    pub causes: Vec<String>,
+}
+
+impl ErrorReport {
+    pub fn new(tool: &str, err: &failure::Error) -> Self {
+        ErrorReport {
+            tool: tool.to_string(),
+            code: ErrorCode::new_from_error(err),
+            message: err.to_string(),
+            causes: err.iter_causes().map(|cause| cause.to_string()).collect(),
+        }
+    }
+
+    /// Writes the report as JSON to `path`.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), failure::Error> {
+        let mut json = serde_json::to_string_pretty(self)?;
+        json.push('\n');
+        fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use bio::io::bed;
+    use bio_types::annot::refids::RefIDSet;
+
+    use transcript::Transcriptome;
+
+    #[test]
+    fn error_codes() {
+        let bedstr = "chr01\t100\t50\tBackward\n";
+        let mut refids: RefIDSet<Rc<String>> = RefIDSet::new();
+        let trx_err =
+            Transcriptome::new_from_bed(bed::Reader::new(bedstr.as_bytes()).records(), &mut refids)
+                .err()
+                .unwrap();
+        let err: failure::Error = trx_err.into();
+        assert_eq!(ErrorCode::new_from_error(&err), ErrorCode::BadAnnotation);
+
+        let err: failure::Error = UnsortedError("Records out of order".to_string()).into();
+        let report = ErrorReport::new("bam-suppress-duplicates", &err);
+        assert_eq!(report.code, ErrorCode::UnsortedBam);
+        assert_eq!(report.message, "Records out of order");
+        assert_eq!(
+            serde_json::to_value(&report).unwrap()["code"],
+            serde_json::Value::String("unsorted_bam".to_string())
+        );
+
+        let err: failure::Error = SampleError::IndexClash(b"ACGT".to_vec()).into();
+        assert_eq!(ErrorCode::new_from_error(&err), ErrorCode::BarcodeClash);
+
+        let err: failure::Error = io::Error::new(io::ErrorKind::NotFound, "missing").into();
+        let err: failure::Error = err.context("Reading sample sheet").into();
+        assert_eq!(ErrorCode::new_from_error(&err), ErrorCode::Io);
+
+        let err = failure::err_msg("Bad input");
+        assert_eq!(ErrorCode::new_from_error(&err), ErrorCode::Other);
+        assert_eq!(ErrorCode::Other.to_string(), "other");
+    }
+}
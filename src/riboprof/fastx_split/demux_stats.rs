@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use failure;
+use serde_json;
+
+use fastx_split::QualityStats;
+
+/// Demultiplexing statistics for one sample.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SampleDemuxStats {
+    pub name: String,
+    pub index: String,
+    pub reads: u64,
+    /// Reads with each UMI, for UMIs with at least one read
+    pub umis: BTreeMap<String, u64>,
+    /// Reads with each insert length after linker trimming
+    pub lengths: BTreeMap<usize, u64>,
+    /// Reads with a homopolymer tail trimmed from the insert
+    #[serde(default)]
+    pub tail_reads: u64,
+    /// Total bases trimmed in homopolymer tails
+    #[serde(default)]
+    pub tail_bases: u64,
+}
+
+/// Statistics from `fastx-split` in one structured report, written as
+/// `demux_stats.json` alongside `fates.txt` and the per-sample tables
+/// so that it can be read without parsing them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DemuxStats {
+    /// All reads, including those too short to split
+    pub total: u64,
+    /// Reads too short to split
+    pub tooshort: u64,
+    /// Reads with inserts too long to keep
+    #[serde(default)]
+    pub toolong: u64,
+    /// Reads set aside for low base qualities
+    #[serde(default)]
+    pub lowqual: u64,
+    pub samples: Vec<SampleDemuxStats>,
+    /// Reads affected by the linker quality thresholds, when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityStats>,
+}
+
+impl DemuxStats {
+    pub fn new() -> Self {
+        DemuxStats::default()
+    }
+
+    pub fn add_sample(&mut self, sample: SampleDemuxStats) {
+        self.total += sample.reads;
+        self.samples.push(sample);
+    }
+
+    pub fn add_short(&mut self, reads: u64) {
+        self.total += reads;
+        self.tooshort += reads;
+    }
+
+    pub fn add_long(&mut self, reads: u64) {
+        self.total += reads;
+        self.toolong += reads;
+    }
+
+    pub fn add_lowqual(&mut self, reads: u64) {
+        self.total += reads;
+        self.lowqual += reads;
+    }
+
+    /// Returns the statistics serialized as JSON.
+    pub fn to_json(&self) -> Result<String, failure::Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Reads statistics serialized as JSON by `to_json()`.
+    pub fn from_json(json: &str) -> Result<Self, failure::Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demux_json() {
+        let mut stats = DemuxStats::new();
+        stats.add_sample(SampleDemuxStats {
+            name: "wt".to_string(),
+            index: "ACGT".to_string(),
+            reads: 3,
+            umis: vec![("AA".to_string(), 2), ("GT".to_string(), 1)]
+                .into_iter()
+                .collect(),
+            lengths: vec![(28, 1), (30, 2)].into_iter().collect(),
+            tail_reads: 1,
+            tail_bases: 6,
+        });
+        stats.add_short(2);
+        stats.add_long(1);
+        stats.add_lowqual(1);
+        assert_eq!(
+            (stats.total, stats.tooshort, stats.toolong, stats.lowqual),
+            (7, 2, 1, 1)
+        );
+
+        let json = stats.to_json().unwrap();
+        assert!(!json.contains("quality"));
+        assert!(json.contains("\"30\": 2"));
+        assert_eq!(DemuxStats::from_json(&json).unwrap(), stats);
+
+        stats.quality = Some(QualityStats {
+            low_quality_index: 1,
+            masked_umi_reads: 0,
+            masked_umi_bases: 0,
+        });
+        let json = stats.to_json().unwrap();
+        assert_eq!(DemuxStats::from_json(&json).unwrap(), stats);
+    }
+}
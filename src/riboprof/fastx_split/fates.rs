@@ -0,0 +1,257 @@
+use std::fmt;
+use std::str::FromStr;
+
+use failure;
+use serde_json;
+
+/// Index shown for reads that were too short to split.
+const SHORT_INDEX: &str = "N/A";
+
+/// Name given to reads that were too short to split.
+const SHORT_NAME: &str = "short";
+
+/// Name given to reads with inserts too long to keep, with
+/// `--max-insert`.
+const LONG_NAME: &str = "long";
+
+/// Name given to reads set aside for low base qualities, with
+/// `--min-mean-qual` or `--filter-umi-qual`.
+const LOWQUAL_NAME: &str = "lowqual";
+
+/// Column header row of the `fates.txt` table.
+const HEADER: &str = "sample\tindex\treads\tpercent";
+
+/// Number of reads assigned to one sample.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SampleFate {
+    pub name: String,
+    pub index: String,
+    pub reads: u64,
+}
+
+/// Fates of all reads split by `fastx-split`, the number assigned to
+/// each sample along with the number too short to split and, when
+/// these filters are used, the number too long or of low quality, as
+/// written in `fates.txt`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitFates {
+    samples: Vec<SampleFate>,
+    short: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    long: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lowqual: Option<u64>,
+}
+
+impl SplitFates {
+    pub fn new() -> Self {
+        SplitFates::default()
+    }
+
+    pub fn samples(&self) -> &[SampleFate] {
+        &self.samples
+    }
+
+    pub fn short(&self) -> u64 {
+        self.short
+    }
+
+    pub fn long(&self) -> Option<u64> {
+        self.long
+    }
+
+    pub fn lowqual(&self) -> Option<u64> {
+        self.lowqual
+    }
+
+    pub fn total(&self) -> u64 {
+        self.samples.iter().map(|sample| sample.reads).sum::<u64>()
+            + self.short
+            + self.long.unwrap_or(0)
+            + self.lowqual.unwrap_or(0)
+    }
+
+    pub fn add_sample(&mut self, name: &str, index: &str, reads: u64) {
+        self.samples.push(SampleFate {
+            name: name.to_string(),
+            index: index.to_string(),
+            reads: reads,
+        });
+    }
+
+    pub fn add_short(&mut self, reads: u64) {
+        self.short += reads;
+    }
+
+    pub fn add_long(&mut self, reads: u64) {
+        self.long = Some(self.long.unwrap_or(0) + reads);
+    }
+
+    pub fn add_lowqual(&mut self, reads: u64) {
+        self.lowqual = Some(self.lowqual.unwrap_or(0) + reads);
+    }
+
+    /// Adds the fates from another run, e.g., on a separate sequencing
+    /// lane with the same sample sheet.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when the two runs have different
+    /// samples or indices.
+    pub fn merge(&mut self, other: &SplitFates) -> Result<(), failure::Error> {
+        if self.samples.len() != other.samples.len() {
+            bail!("Cannot merge sample fates with different samples");
+        }
+
+        for (x, y) in self.samples.iter_mut().zip(other.samples.iter()) {
+            if x.name != y.name || x.index != y.index {
+                bail!(
+                    "Cannot merge sample fates for {} ({}) and {} ({})",
+                    x.name,
+                    x.index,
+                    y.name,
+                    y.index
+                );
+            }
+            x.reads += y.reads;
+        }
+        self.short += other.short;
+        if let Some(long) = other.long {
+            self.add_long(long);
+        }
+        if let Some(lowqual) = other.lowqual {
+            self.add_lowqual(lowqual);
+        }
+        Ok(())
+    }
+
+    /// Returns the fates serialized as JSON.
+    pub fn to_json(&self) -> Result<String, failure::Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Reads fates serialized as JSON by `to_json()`.
+    pub fn from_json(json: &str) -> Result<Self, failure::Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// Writes the `fates.txt` table, with a header row followed by the
+/// name, index, read count, and percentage of reads for each sample,
+/// ending with the reads too short to split and any too long or of
+/// low quality.
+impl fmt::Display for SplitFates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total() as f64;
+
+        write!(f, "{}\n", HEADER)?;
+
+        for sample in self.samples.iter() {
+            write!(
+                f,
+                "{}\t{}\t{}\t{:.2}%\n",
+                sample.name,
+                sample.index,
+                sample.reads,
+                100.0 * (sample.reads as f64) / total
+            )?;
+        }
+
+        write!(
+            f,
+            "{}\t{}\t{}\t{:.2}%\n",
+            SHORT_NAME,
+            SHORT_INDEX,
+            self.short,
+            100.0 * (self.short as f64) / total
+        )?;
+
+        for &(name, reads) in [(LONG_NAME, self.long), (LOWQUAL_NAME, self.lowqual)].iter() {
+            if let Some(reads) = reads {
+                write!(
+                    f,
+                    "{}\t{}\t{}\t{:.2}%\n",
+                    name,
+                    SHORT_INDEX,
+                    reads,
+                    100.0 * (reads as f64) / total
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses the `fates.txt` table written by `Display`. The header row
+/// is optional, for tables written by older versions.
+impl FromStr for SplitFates {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fates = SplitFates::new();
+
+        for line in s.lines().filter(|line| !line.is_empty() && *line != HEADER) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 3 {
+                bail!("Bad fates line \"{}\"", line);
+            }
+            let reads = fields[2].parse()?;
+            if fields[0] == SHORT_NAME && fields[1] == SHORT_INDEX {
+                fates.add_short(reads);
+            } else if fields[0] == LONG_NAME && fields[1] == SHORT_INDEX {
+                fates.add_long(reads);
+            } else if fields[0] == LOWQUAL_NAME && fields[1] == SHORT_INDEX {
+                fates.add_lowqual(reads);
+            } else {
+                fates.add_sample(fields[0], fields[1], reads);
+            }
+        }
+
+        Ok(fates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fates_round_trip() {
+        let table = "sample\tindex\treads\tpercent\n\
+                     wt\tACGT\t750\t75.00%\nko\tTGCA\t200\t20.00%\nshort\tN/A\t50\t5.00%\n";
+        let fates: SplitFates = table.parse().unwrap();
+        assert_eq!(fates.samples().len(), 2);
+        assert_eq!(fates.samples()[1].name, "ko");
+        assert_eq!((fates.short(), fates.total()), (50, 1000));
+        assert_eq!(fates.to_string(), table);
+        assert_eq!(
+            table[(HEADER.len() + 1)..].parse::<SplitFates>().unwrap(),
+            fates
+        );
+        assert_eq!(
+            SplitFates::from_json(&fates.to_json().unwrap()).unwrap(),
+            fates
+        );
+
+        let mut merged = fates.clone();
+        merged.merge(&fates).unwrap();
+        assert_eq!(merged.samples()[0].reads, 1500);
+        assert_eq!(merged.total(), 2000);
+
+        let mut other = SplitFates::new();
+        other.add_sample("wt", "ACGT", 1);
+        assert!(merged.merge(&other).is_err());
+        assert!("wt\t750\n".parse::<SplitFates>().is_err());
+
+        let table = "sample\tindex\treads\tpercent\n\
+                     wt\tACGT\t800\t80.00%\nshort\tN/A\t50\t5.00%\nlong\tN/A\t100\t10.00%\n\
+                     lowqual\tN/A\t50\t5.00%\n";
+        let fates: SplitFates = table.parse().unwrap();
+        assert_eq!(
+            (fates.long(), fates.lowqual(), fates.total()),
+            (Some(100), Some(50), 1000)
+        );
+        assert_eq!(fates.to_string(), table);
+    }
+}
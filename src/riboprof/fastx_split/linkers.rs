@@ -1,10 +1,11 @@
 use std::error;
 use std::fmt;
 
-use failure;
-
 use bio::io::fastq;
 
+/// Offset of Phred quality scores in FastQ quality strings.
+const PHRED_OFFSET: u8 = 33;
+
 /// Nucleotide type in the linker, either a unique molecule identifier
 /// (UMI) base or a part of the sample index.
 #[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
@@ -23,11 +24,11 @@ impl LinkerNtSpec {
     ///
     /// # Errors
     /// An error variant is returned for any other character.
-    pub fn new(ch: char) -> Result<Self, failure::Error> {
+    pub fn new(ch: char) -> Result<Self, LinkerError> {
         match ch {
             'N' => Ok(LinkerNtSpec::UMI),
             'I' => Ok(LinkerNtSpec::SampleIndex),
-            _ => Err(LinkerError::BadSpecChar(ch).into()),
+            _ => Err(LinkerError::BadSpecChar(ch)),
         }
     }
 }
@@ -66,14 +67,15 @@ impl LinkerSpec {
     /// # Errors
     /// An error variant is returned when any of the characters in the
     /// specification strings cannot be parsed.
-    pub fn new(prefix_str: &str, suffix_str: &str) -> Result<Self, failure::Error> {
-        let prefix_res: Result<Vec<LinkerNtSpec>, failure::Error> =
-            prefix_str.chars().map(LinkerNtSpec::new).collect();
-        let suffix_res: Result<Vec<LinkerNtSpec>, failure::Error> =
-            suffix_str.chars().map(LinkerNtSpec::new).collect();
-
-        let prefix = prefix_res?;
-        let suffix = suffix_res?;
+    pub fn new(prefix_str: &str, suffix_str: &str) -> Result<Self, LinkerError> {
+        let prefix: Vec<LinkerNtSpec> = prefix_str
+            .chars()
+            .map(LinkerNtSpec::new)
+            .collect::<Result<_, _>>()?;
+        let suffix: Vec<LinkerNtSpec> = suffix_str
+            .chars()
+            .map(LinkerNtSpec::new)
+            .collect::<Result<_, _>>()?;
 
         let sample_index_length = prefix
             .iter()
@@ -139,28 +141,46 @@ impl LinkerSpec {
     pub fn split_record<'a>(&self, fq: &'a fastq::Record) -> Option<LinkerSplit<'a>> {
         let sequence = fq.seq();
 
+        let quality = fq.qual();
+
         if sequence.len() >= self.prefix.len() + self.suffix.len() {
             let mut umi = Vec::new();
+            let mut umi_quality = Vec::new();
             let mut sample_index = Vec::new();
+            let mut index_quality = Vec::new();
 
             for i in 0..self.prefix.len() {
                 match self.prefix[i] {
-                    LinkerNtSpec::UMI => umi.push(sequence[i]),
-                    LinkerNtSpec::SampleIndex => sample_index.push(sequence[i]),
+                    LinkerNtSpec::UMI => {
+                        umi.push(sequence[i]);
+                        umi_quality.push(quality[i]);
+                    }
+                    LinkerNtSpec::SampleIndex => {
+                        sample_index.push(sequence[i]);
+                        index_quality.push(quality[i]);
+                    }
                 };
             }
 
             let suffix_start = sequence.len() - self.suffix.len();
             for i in 0..self.suffix.len() {
                 match self.suffix[i] {
-                    LinkerNtSpec::UMI => umi.push(sequence[suffix_start + i]),
-                    LinkerNtSpec::SampleIndex => sample_index.push(sequence[suffix_start + i]),
+                    LinkerNtSpec::UMI => {
+                        umi.push(sequence[suffix_start + i]);
+                        umi_quality.push(quality[suffix_start + i]);
+                    }
+                    LinkerNtSpec::SampleIndex => {
+                        sample_index.push(sequence[suffix_start + i]);
+                        index_quality.push(quality[suffix_start + i]);
+                    }
                 };
             }
 
             Some(LinkerSplit {
                 umi: umi,
+                umi_quality: umi_quality,
                 sample_index: sample_index,
+                index_quality: index_quality,
                 sequence: &sequence[self.prefix.len()..suffix_start],
                 quality: &fq.qual()[self.prefix.len()..suffix_start],
             })
@@ -189,7 +209,9 @@ impl fmt::Display for LinkerSpec {
 #[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq, Hash)]
 pub struct LinkerSplit<'a> {
     umi: Vec<u8>,
+    umi_quality: Vec<u8>,
     sample_index: Vec<u8>,
+    index_quality: Vec<u8>,
     sequence: &'a [u8],
     quality: &'a [u8],
 }
@@ -205,6 +227,53 @@ impl<'a> LinkerSplit<'a> {
         &self.sample_index
     }
 
+    /// Replaces the sample index from the linker with `index`, e.g.,
+    /// one from the read header by `header_sample_index`, which has no base
+    /// qualities to check.
+    pub fn set_sample_index(&mut self, index: Vec<u8>) {
+        self.sample_index = index;
+        self.index_quality.clear();
+    }
+
+    /// Returns `true` when every sample index base has a Phred
+    /// quality of at least `min_qual`.
+    pub fn index_passes(&self, min_qual: u8) -> bool {
+        self.index_quality
+            .iter()
+            .all(|&qual| qual >= min_qual.saturating_add(PHRED_OFFSET))
+    }
+
+    /// Returns `true` when every UMI base has a Phred quality of at
+    /// least `min_qual`.
+    pub fn umi_passes(&self, min_qual: u8) -> bool {
+        self.umi_quality
+            .iter()
+            .all(|&qual| qual >= min_qual.saturating_add(PHRED_OFFSET))
+    }
+
+    /// Replaces each UMI base with a Phred quality below `min_qual` by
+    /// `N`, returning the number of bases masked.
+    pub fn mask_umi(&mut self, min_qual: u8) -> usize {
+        let mut masked = 0;
+        for (nt, &qual) in self.umi.iter_mut().zip(self.umi_quality.iter()) {
+            if qual < min_qual.saturating_add(PHRED_OFFSET) {
+                *nt = b'N';
+                masked += 1;
+            }
+        }
+        masked
+    }
+
+    /// Trims a homopolymer `tail` from the 3' end of the non-linker
+    /// sequence, returning the number of bases trimmed.
+    pub fn trim_tail(&mut self, tail: &TailTrim) -> usize {
+        let tail_length = tail.tail_length(self.sequence);
+        let insert_length = self.sequence.len() - tail_length;
+        self.sequence = &self.sequence[..insert_length];
+        self.quality = &self.quality[..insert_length];
+        tail_length
+    }
+
     /// Returns the non-linker portion of the raw input sequence
     pub fn sequence(&self) -> &'a [u8] {
         self.sequence
@@ -216,15 +285,100 @@ impl<'a> LinkerSplit<'a> {
     }
 }
 
+/// Homopolymer tail, such as a poly-A tail, left at the 3' end of the
+/// insert by some library preparations and trimmed after the linker is
+/// removed.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum LinkerError {
+pub struct TailTrim {
+    nt: u8,
+    min_length: usize,
+}
+
+impl TailTrim {
+    /// Trims tails of `nt` that are at least `min_length` bases long.
+    pub fn new(nt: char, min_length: usize) -> Result<Self, LinkerError> {
+        match nt.to_ascii_uppercase() {
+            'A' | 'C' | 'G' | 'T' => Ok(TailTrim {
+                nt: nt.to_ascii_uppercase() as u8,
+                min_length: min_length.max(1),
+            }),
+            _ => Err(LinkerError::BadTailChar(nt)),
+        }
+    }
+
+    /// Returns the length of the tail at the end of `sequence`, or 0
+    /// when it is shorter than the minimum length.
+    pub fn tail_length(&self, sequence: &[u8]) -> usize {
+        let run = sequence
+            .iter()
+            .rev()
+            .take_while(|&&nt| nt == self.nt)
+            .count();
+        if run >= self.min_length {
+            run
+        } else {
+            0
+        }
+    }
+}
+
+/// Returns the mean Phred quality of the bases with qualities `qual`,
+/// or 0 when there are none.
+pub fn mean_quality(qual: &[u8]) -> f64 {
+    if qual.is_empty() {
+        return 0.0;
+    }
+    let total: u64 = qual
+        .iter()
+        .map(|&q| u64::from(q.saturating_sub(PHRED_OFFSET)))
+        .sum();
+    total as f64 / qual.len() as f64
+}
+
+/// Returns the bases of a sample index, joining the i7 and i5 indices
+/// of a dual index written as `ACGTACGT+TTGGCCAA`, or as
+/// `ACGTACGT-TTGGCCAA` in a SAM `BC` tag.
+pub fn index_bases(index: &str) -> Vec<u8> {
+    index
+        .bytes()
+        .filter(|&nt| nt != b'+' && nt != b'-')
+        .collect()
+}
+
+/// Returns the sample index from the description of a FastQ record
+/// with an Illumina read header, e.g., `1:N:0:ACGTACGT` or, with dual
+/// indices, `1:N:0:ACGTACGT+TTGGCCAA`, joined as by `index_bases`. A
+/// `BC:Z:` comment, e.g., from the `BC` tag of an unaligned BAM
+/// record, is used instead when present.
+pub fn header_sample_index(desc: Option<&str>) -> Option<Vec<u8>> {
+    let desc = desc?;
+    if let Some(barcode) = desc
+        .split_whitespace()
+        .find(|field| field.starts_with("BC:Z:"))
+    {
+        return Some(index_bases(&barcode[5..]));
+    }
+    let comment = desc.split_whitespace().next()?;
+    let index = comment.split(':').nth(3)?;
+    if index.is_empty() {
+        None
+    } else {
+        Some(index_bases(index))
+    }
+}
+
+/// Errors parsing a linker specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkerError {
     BadSpecChar(char),
+    BadTailChar(char),
 }
 
 impl fmt::Display for LinkerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             LinkerError::BadSpecChar(ch) => write!(f, "Bad linker spec char \'{}\'", ch),
+            LinkerError::BadTailChar(ch) => write!(f, "Bad tail trimming nucleotide \'{}\'", ch),
         }
     }
 }
@@ -361,6 +515,48 @@ mod tests {
         assert!(spec.umi_length() == 4);
     }
 
+    #[test]
+    fn linker_quality() {
+        let spec = LinkerSpec::new("NNII", "NN").unwrap();
+        let rec = fastq::Record::with_attrs("test_record", None, b"ACGTAAAATG", b"#5I5IIIII#");
+
+        let mut split = spec.split_record(&rec).unwrap();
+        assert!(split.index_passes(20));
+        assert!(!split.index_passes(30));
+        assert!(split.umi_passes(2));
+        assert!(!split.umi_passes(20));
+        assert_eq!(split.mask_umi(20), 2);
+        assert_eq!(split.umi(), b"NCTN");
+        assert_eq!(split.sample_index(), b"GT");
+        assert!((mean_quality(rec.qual()) - 28.4).abs() < 1e-9);
+        assert_eq!(mean_quality(b""), 0.0);
+    }
+
+    #[test]
+    fn tail_trimming() {
+        let spec = LinkerSpec::new("", "NNIII").unwrap();
+        let rec = fastq::Record::with_attrs(
+            "test_record",
+            None,
+            b"GATTACAAAAAGTCAC",
+            b"IIIIII5555555555",
+        );
+
+        let mut split = spec.split_record(&rec).unwrap();
+        assert_eq!(split.trim_tail(&TailTrim::new('a', 6).unwrap()), 0);
+        assert_eq!(split.sequence(), b"GATTACAAAAA");
+        assert_eq!(split.trim_tail(&TailTrim::new('A', 4).unwrap()), 5);
+        assert_eq!(split.sequence(), b"GATTAC");
+        assert_eq!(split.quality(), b"IIIIII");
+        assert_eq!(
+            (split.umi(), split.sample_index()),
+            (&b"GT"[..], &b"CAC"[..])
+        );
+
+        assert_eq!(TailTrim::new('T', 0).unwrap().tail_length(b"ACT"), 1);
+        assert_eq!(TailTrim::new('N', 4), Err(LinkerError::BadTailChar('N')));
+    }
+
     const SEQ10: &[u8] = b"ACACAGTGTG";
     const SEQ11: &[u8] = b"TGCATGCATGC";
     const SEQ12: &[u8] = b"CCCTTTGGGAAA";
@@ -387,4 +583,31 @@ mod tests {
         assert!(split12.as_ref().map(LinkerSplit::sample_index) == Some(b"CCCTT"));
         assert!(split12.as_ref().map(LinkerSplit::sequence) == Some(b"T"));
     }
+
+    #[test]
+    fn header_indices() {
+        assert_eq!(
+            header_sample_index(Some("1:N:0:ACGTACGT")),
+            Some(b"ACGTACGT".to_vec())
+        );
+        assert_eq!(
+            header_sample_index(Some("1:N:0:ACGT+TTGG extra")),
+            Some(b"ACGTTTGG".to_vec())
+        );
+        assert_eq!(header_sample_index(Some("1:N:0:")), None);
+        assert_eq!(header_sample_index(Some("1:N")), None);
+        assert_eq!(header_sample_index(None), None);
+        assert_eq!(
+            header_sample_index(Some("BC:Z:ACGT-TTGG")),
+            Some(b"ACGTTTGG".to_vec())
+        );
+
+        let spec = LinkerSpec::new("", "NNNN").unwrap();
+        let rec =
+            fastq::Record::with_attrs("test_record", Some("1:N:0:TTAG"), SEQ1, b"IIIIIIII####IIII");
+        let mut split = spec.split_record(&rec).unwrap();
+        split.set_sample_index(header_sample_index(rec.desc()).unwrap());
+        assert_eq!(split.sample_index(), b"TTAG");
+        assert!(split.index_passes(30));
+    }
 }
@@ -0,0 +1,140 @@
+use std::fs;
+use std::path::Path;
+
+use failure;
+use serde_json;
+
+use output::*;
+
+/// One file written by `fastx-split`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Path relative to the output directory
+    pub path: String,
+    /// Sample the file belongs to, for per-sample files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample: Option<String>,
+    /// Contents of the file, e.g., `fastq` or `umi_stats`
+    pub kind: String,
+    /// Number of reads, for FASTQ files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reads: Option<u64>,
+    pub bytes: u64,
+    /// CRC32 checksum of the file, as 8 hexadecimal digits
+    pub crc32: String,
+}
+
+/// Listing of every file written by `fastx-split`, written as
+/// `manifest.json` so that downstream pipelines can find their inputs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Per-sample files are in a subdirectory named for the sample
+    pub per_sample_dirs: bool,
+    pub files: Vec<ManifestFile>,
+}
+
+impl Manifest {
+    pub fn new(per_sample_dirs: bool) -> Self {
+        Manifest {
+            per_sample_dirs: per_sample_dirs,
+            files: Vec::new(),
+        }
+    }
+
+    /// Lists a file whose contents were tallied by `checksum`.
+    pub fn add(
+        &mut self,
+        path: &Path,
+        sample: Option<&str>,
+        kind: &str,
+        reads: Option<u64>,
+        checksum: &Checksum,
+    ) {
+        self.files.push(ManifestFile {
+            path: path.to_string_lossy().into_owned(),
+            sample: sample.map(str::to_string),
+            kind: kind.to_string(),
+            reads: reads,
+            bytes: checksum.bytes(),
+            crc32: format!("{:08x}", checksum.crc32()),
+        });
+    }
+
+    /// Writes `contents` to `path`, relative to `output_dir`, and
+    /// lists the file.
+    pub fn write(
+        &mut self,
+        output_dir: &Path,
+        path: &Path,
+        sample: Option<&str>,
+        kind: &str,
+        contents: &str,
+    ) -> Result<(), failure::Error> {
+        fs::write(output_dir.join(path), contents)?;
+        self.add(
+            path,
+            sample,
+            kind,
+            None,
+            &Checksum::new_from_bytes(contents.as_bytes()),
+        );
+        Ok(())
+    }
+
+    /// Returns the manifest serialized as JSON.
+    pub fn to_json(&self) -> Result<String, failure::Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Reads a manifest serialized as JSON by `to_json()`.
+    pub fn from_json(json: &str) -> Result<Self, failure::Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn manifest_files() {
+        let dir = env::temp_dir().join(format!("riboprof-manifest-{}", process::id()));
+        fs::create_dir_all(dir.join("wt")).unwrap();
+
+        let mut manifest = Manifest::new(true);
+        let fastq = Checksum::new_from_bytes(b"@read#ACGT\nACGT\n+\nIIII\n");
+        manifest.add(
+            Path::new("wt/wt.fastq"),
+            Some("wt"),
+            "fastq",
+            Some(1),
+            &fastq,
+        );
+        manifest
+            .write(
+                &dir,
+                Path::new("fates.txt"),
+                None,
+                "fates",
+                "sample\tindex\treads\tpercent\n",
+            )
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("fates.txt")).unwrap(),
+            "sample\tindex\treads\tpercent\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(manifest.files.len(), 2);
+        assert_eq!(manifest.files[0].bytes, 23);
+        assert_eq!(manifest.files[0].crc32.len(), 8);
+        assert_eq!(manifest.files[1].sample, None);
+
+        let json = manifest.to_json().unwrap();
+        assert!(!json.contains("\"reads\": null"));
+        assert_eq!(Manifest::from_json(&json).unwrap(), manifest);
+    }
+}
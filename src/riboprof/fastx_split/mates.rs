@@ -0,0 +1,110 @@
+use failure;
+
+use bio::io::fastq;
+
+/// A read carrying the linker, along with its mate from paired-end
+/// sequencing, if any.
+pub type ReadMates = (fastq::Record, Option<fastq::Record>);
+
+/// Returns the name shared by both mates of a read pair, without a
+/// trailing `/1` or `/2`.
+pub fn mate_name(id: &str) -> &str {
+    if id.ends_with("/1") || id.ends_with("/2") {
+        &id[..(id.len() - 2)]
+    } else {
+        id
+    }
+}
+
+/// Iterator that pairs consecutive mates from interleaved paired-end
+/// reads, created by `interleaved_mates`.
+pub struct Interleaved<I> {
+    records: I,
+}
+
+/// Returns an iterator over the read pairs in `records`, which
+/// alternate between the first mate and the second mate of each pair,
+/// as written by `bcl2fastq --interleaved` or `bwa mem -p`.
+pub fn interleaved_mates<I>(records: I) -> Interleaved<I::IntoIter>
+where
+    I: IntoIterator<Item = Result<fastq::Record, failure::Error>>,
+{
+    Interleaved {
+        records: records.into_iter(),
+    }
+}
+
+impl<I> Iterator for Interleaved<I>
+where
+    I: Iterator<Item = Result<fastq::Record, failure::Error>>,
+{
+    type Item = Result<ReadMates, failure::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = match self.records.next()? {
+            Ok(first) => first,
+            Err(err) => return Some(Err(err)),
+        };
+        let second = match self.records.next() {
+            Some(Ok(second)) => second,
+            Some(Err(err)) => return Some(Err(err)),
+            None => {
+                return Some(Err(format_err!(
+                    "Interleaved read {} has no mate",
+                    first.id()
+                )))
+            }
+        };
+        if mate_name(first.id()) != mate_name(second.id()) {
+            return Some(Err(format_err!(
+                "Interleaved reads {} and {} are not mates",
+                first.id(),
+                second.id()
+            )));
+        }
+        Some(Ok((first, Some(second))))
+    }
+}
+
+/// Returns an iterator over single-end `records` without mates.
+pub fn single_reads<I>(records: I) -> impl Iterator<Item = Result<ReadMates, failure::Error>>
+where
+    I: IntoIterator<Item = Result<fastq::Record, failure::Error>>,
+{
+    records
+        .into_iter()
+        .map(|recres| recres.map(|rec| (rec, None)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interleaved_pairs() {
+        assert_eq!(mate_name("read/1"), "read");
+        assert_eq!(mate_name("read/3"), "read/3");
+
+        let fastq = "@a/1\nACGT\n+\nIIII\n@a/2\nTTTT\n+\nIIII\n\
+                     @b\nACGT\n+\nIIII\n@b\nGGGG\n+\nIIII\n\
+                     @c\nACGT\n+\nIIII\n";
+        let records = fastq::Reader::new(fastq.as_bytes())
+            .records()
+            .map(|recres| recres.map_err(failure::Error::from));
+        let mut pairs = interleaved_mates(records);
+
+        let (first, second) = pairs.next().unwrap().unwrap();
+        assert_eq!((first.id(), second.unwrap().seq()), ("a/1", &b"TTTT"[..]));
+        let (first, second) = pairs.next().unwrap().unwrap();
+        assert_eq!((first.id(), second.unwrap().id()), ("b", "b"));
+        let err = pairs.next().unwrap().err().unwrap();
+        assert_eq!(err.to_string(), "Interleaved read c has no mate");
+        assert!(pairs.next().is_none());
+
+        let fastq = "@a\nACGT\n+\nIIII\n@b\nACGT\n+\nIIII\n";
+        let records = fastq::Reader::new(fastq.as_bytes())
+            .records()
+            .map(|recres| recres.map_err(failure::Error::from));
+        assert!(interleaved_mates(records).next().unwrap().is_err());
+    }
+}
@@ -1,5 +1,5 @@
+use std::collections::HashMap;
 use std::fs;
-use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use std::str;
 
@@ -7,179 +7,1108 @@ use failure;
 
 use bio::io::fastq;
 
+use corrupt::*;
+use output::*;
+use parallel::*;
+use timing::*;
+use umi::*;
+
+mod demux_stats;
+mod fates;
 mod linkers;
+mod manifest;
+mod mates;
+mod qc;
 mod sample;
 mod sample_sheet;
+mod ubam;
 
 use fastx_split::linkers::*;
+use fastx_split::mates::*;
+use fastx_split::qc::*;
 use fastx_split::sample::*;
 use fastx_split::sample_sheet::*;
+use fastx_split::ubam::*;
+
+pub use fastx_split::demux_stats::{DemuxStats, SampleDemuxStats};
+pub use fastx_split::fates::*;
+pub use fastx_split::linkers::LinkerError;
+pub use fastx_split::manifest::{Manifest, ManifestFile};
+pub use fastx_split::qc::{QcProblem, QcReport, SampleExpectation};
+pub use fastx_split::sample_sheet::SampleError;
 
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct CLI {
+    #[serde(skip_serializing)]
     pub fastx_inputs: Vec<String>,
     pub output_dir: String,
     pub min_insert: usize,
+    pub max_insert: Option<usize>,
+    pub trim_tail: Option<char>,
+    pub min_tail: usize,
     pub prefix: String,
     pub suffix: String,
     pub sample_sheet: String,
     pub progress: usize,
+    pub threads: String,
+    pub min_index_qual: Option<u8>,
+    pub min_umi_qual: Option<u8>,
+    pub min_mean_qual: Option<u8>,
+    pub filter_umi_qual: Option<u8>,
+    pub tolerance: f64,
+    pub strict: bool,
+    pub per_sample_dirs: bool,
+    pub gzip: bool,
+    pub ubam: bool,
+    pub umi_cluster: bool,
+    pub umi_barcode: bool,
+    pub interleaved: bool,
+    pub header_index: bool,
+    #[serde(flatten)]
+    pub umi_config: UmiCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+impl Default for CLI {
+    fn default() -> Self {
+        CLI {
+            fastx_inputs: Vec::new(),
+            output_dir: String::new(),
+            min_insert: 0,
+            max_insert: None,
+            trim_tail: None,
+            min_tail: 4,
+            prefix: String::new(),
+            suffix: String::new(),
+            sample_sheet: String::new(),
+            progress: 0,
+            threads: "1".to_string(),
+            min_index_qual: None,
+            min_umi_qual: None,
+            min_mean_qual: None,
+            filter_umi_qual: None,
+            tolerance: 5.0,
+            strict: false,
+            per_sample_dirs: false,
+            gzip: false,
+            ubam: false,
+            umi_cluster: false,
+            umi_barcode: false,
+            interleaved: false,
+            header_index: false,
+            umi_config: UmiCLI::default(),
+            corrupt: CorruptInputs::default(),
+        }
+    }
 }
 
 pub struct Config {
     fastx_inputs: Vec<PathBuf>,
     output_dir: PathBuf,
-    min_insert: usize,
+    insert_spec: InsertSpec,
     linker_spec: LinkerSpec,
     sample_map: SampleMap<Sample>,
-    short_file: fastq::Writer<fs::File>,
+    short_file: fastq::Writer<FastqStream>,
+    long_file: Option<fastq::Writer<FastqStream>>,
+    lowqual_file: Option<fastq::Writer<FastqStream>>,
+    per_sample_dirs: bool,
+    gzip: bool,
+    read_output: ReadOutput,
+    fastq_outputs: HashMap<String, FastqOutput>,
+    output_names: HashMap<String, String>,
+    short_output: FastqOutput,
+    long_output: Option<FastqOutput>,
+    lowqual_output: Option<FastqOutput>,
+    manifest: Manifest,
     progress: Option<usize>,
+    threads: usize,
+    quality: LinkerQuality,
+    quality_stats: QualityStats,
+    expectations: HashMap<String, SampleExpectation>,
+    tolerance: f64,
+    strict: bool,
+    umi_cluster: bool,
+    interleaved: bool,
+    header_index: bool,
+    umi_config: UmiConfig,
+    corrupt: CorruptInputs,
 }
 
 impl Config {
     pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
         let linker_spec = LinkerSpec::new(&cli.prefix, &cli.suffix)?;
-        let index_length = linker_spec.sample_index_length();
+        if let Some(max_insert) = cli.max_insert {
+            if max_insert < cli.min_insert {
+                bail!(
+                    "Maximum insert length {} is less than the minimum {}",
+                    max_insert,
+                    cli.min_insert
+                );
+            }
+        }
+        if cli.umi_cluster && cli.fastx_inputs.iter().any(|input| input == "-") {
+            bail!("Clustering UMIs reads the input twice and cannot read standard input");
+        }
+
+        let sample_sheet_txt = fs::read_to_string(&cli.sample_sheet)?;
+        let sheet_samples = parse_sample_sheet(&sample_sheet_txt)?;
+
+        let index_length = if cli.header_index {
+            if linker_spec.sample_index_length() > 0 {
+                bail!("Sample index bases in the linker cannot be used with --header-index");
+            }
+            if cli.min_index_qual.is_some() {
+                bail!("Read header sample indices have no base qualities for --min-index-qual");
+            }
+            sheet_samples
+                .first()
+                .map_or(0, |sample| index_bases(&sample.index).len())
+        } else {
+            linker_spec.sample_index_length()
+        };
 
         let output_dir = Path::new(&cli.output_dir).to_path_buf();
         fs::DirBuilder::new()
             .recursive(true)
             .create(output_dir.as_path())?;
 
-        let unknown_sample = Sample::new(
-            "UnknownIndex".to_string(),
+        let per_sample_dirs = cli.per_sample_dirs;
+        let gzip = cli.gzip;
+        let read_output = if cli.ubam {
+            if gzip {
+                bail!(
+                    "Unaligned BAM output is always compressed, and --gzip applies only to FASTQ"
+                );
+            }
+            ReadOutput::Ubam {
+                paired: cli.interleaved,
+            }
+        } else {
+            ReadOutput::Fastq { gzip: gzip }
+        };
+        let mut fastq_outputs = HashMap::new();
+
+        let unknown_sample = Config::create_sample(
+            &output_dir,
+            per_sample_dirs,
+            read_output,
+            "UnknownIndex",
+            "UnknownIndex",
             vec![b'N'; index_length],
-            Config::create_writer(&output_dir, "UnknownIndex")?,
-        );
+            &mut fastq_outputs,
+        )?;
 
         let mut sample_map = SampleMap::new(index_length, unknown_sample);
 
-        let sample_sheet_txt = fs::read_to_string(&cli.sample_sheet)?;
-        for (name, index) in parse_sample_sheet(&sample_sheet_txt)?.into_iter() {
-            let output_file = Config::create_writer(&output_dir, &name)?;
-            let sample = Sample::new(
-                name.to_string(),
-                index.to_string().into_bytes(),
-                output_file,
-            );
-            sample_map.insert(index.into_bytes(), true, sample)?;
+        let mut expectations = HashMap::new();
+        let mut output_names = HashMap::new();
+        for sheet_sample in sheet_samples.into_iter() {
+            let sample = Config::create_sample(
+                &output_dir,
+                per_sample_dirs,
+                read_output,
+                &sheet_sample.name,
+                sheet_sample.output_name(),
+                index_bases(&sheet_sample.index),
+                &mut fastq_outputs,
+            )?;
+            sample_map.insert(
+                index_bases(&sheet_sample.index),
+                sheet_sample.mismatches > 0,
+                sample,
+            )?;
+            if let Some(ref output) = sheet_sample.output {
+                output_names.insert(sheet_sample.name.clone(), output.clone());
+            }
+            if !sheet_sample.expectation.is_empty() {
+                expectations.insert(sheet_sample.name, sheet_sample.expectation);
+            }
         }
+        if cli.strict && expectations.is_empty() {
+            warn!("No sample sheet expectations to check with --strict");
+        }
+
+        let short_output = FastqOutput::create(&output_dir.join(short_path(gzip)), gzip)?;
+        let short_file = fastq::Writer::new(short_output.stream.clone());
+        let long_output = match cli.max_insert {
+            Some(_) => Some(FastqOutput::create(
+                &output_dir.join(long_path(gzip)),
+                gzip,
+            )?),
+            None => None,
+        };
+        let long_file = long_output
+            .as_ref()
+            .map(|output| fastq::Writer::new(output.stream.clone()));
 
-        let short_file = fastq::Writer::new(Config::create_writer(&output_dir, "tooshort")?);
+        let quality = LinkerQuality {
+            min_index_qual: cli.min_index_qual,
+            min_umi_qual: cli.min_umi_qual,
+            min_mean_qual: cli.min_mean_qual,
+            filter_umi_qual: cli.filter_umi_qual,
+        };
+        let lowqual_output = if quality.filters_reads() {
+            Some(FastqOutput::create(
+                &output_dir.join(lowqual_path(gzip)),
+                gzip,
+            )?)
+        } else {
+            None
+        };
+        let lowqual_file = lowqual_output
+            .as_ref()
+            .map(|output| fastq::Writer::new(output.stream.clone()));
 
-        let mut mapping_file = output_dir.clone();
-        mapping_file.push("mapping.txt");
-        fs::write(&mapping_file, sample_map.mapping_table())?;
+        let mut manifest = Manifest::new(per_sample_dirs);
+        manifest.write(
+            &output_dir,
+            Path::new("mapping.txt"),
+            None,
+            "mapping",
+            &sample_map.mapping_table(),
+        )?;
 
         Ok(Config {
             fastx_inputs: cli.fastx_inputs.iter().map(PathBuf::from).collect(),
             output_dir: output_dir,
-            min_insert: cli.min_insert,
+            insert_spec: InsertSpec {
+                min: cli.min_insert,
+                max: cli.max_insert,
+                tail: match cli.trim_tail {
+                    Some(nt) => Some(TailTrim::new(nt, cli.min_tail)?),
+                    None => None,
+                },
+            },
             linker_spec: linker_spec,
             sample_map: sample_map,
             short_file: short_file,
+            long_file: long_file,
+            lowqual_file: lowqual_file,
+            per_sample_dirs: per_sample_dirs,
+            gzip: gzip,
+            read_output: read_output,
+            fastq_outputs: fastq_outputs,
+            output_names: output_names,
+            short_output: short_output,
+            long_output: long_output,
+            lowqual_output: lowqual_output,
+            manifest: manifest,
             progress: if cli.progress > 0 {
                 Some(cli.progress)
             } else {
                 None
             },
+            threads: cli.threads.parse()?,
+            quality: quality,
+            quality_stats: QualityStats::default(),
+            expectations: expectations,
+            tolerance: cli.tolerance,
+            strict: cli.strict,
+            umi_cluster: cli.umi_cluster,
+            interleaved: cli.interleaved,
+            header_index: cli.header_index,
+            umi_config: UmiConfig::new_from_cli(&cli.umi_config)?.name_barcode(cli.umi_barcode),
+            corrupt: cli.corrupt.clone(),
         })
     }
 
-    fn create_writer(output_dir: &Path, name: &str) -> Result<fs::File, failure::Error> {
-        let mut output_path = output_dir.to_path_buf();
-        output_path.push(Path::new(name));
-        output_path.set_extension("fastq");
-        fs::File::create(output_path.as_path()).map_err(::std::convert::Into::into)
+    /// Creates a sample along with its output file, named for
+    /// `output_name` in the format set by `read_output`, and the
+    /// directory for the sample when `per_sample_dirs` is set. A FASTQ
+    /// output file is added to `outputs`.
+    fn create_sample(
+        output_dir: &Path,
+        per_sample_dirs: bool,
+        read_output: ReadOutput,
+        name: &str,
+        output_name: &str,
+        index: Vec<u8>,
+        outputs: &mut HashMap<String, FastqOutput>,
+    ) -> Result<Sample, failure::Error> {
+        let output_path = output_dir.join(sample_path(
+            per_sample_dirs,
+            output_name,
+            read_output.suffix(),
+        ));
+        if let Some(sample_dir) = output_path.parent() {
+            fs::create_dir_all(sample_dir)?;
+        }
+        match read_output {
+            ReadOutput::Fastq { gzip } => {
+                let output = FastqOutput::create(&output_path, gzip)?;
+                let sample = Sample::new(name.to_string(), index, output.stream.clone());
+                outputs.insert(name.to_string(), output);
+                Ok(sample)
+            }
+            ReadOutput::Ubam { paired } => {
+                let writer = UbamWriter::create(&output_path, name, &index, paired)?;
+                Ok(Sample::new_ubam(name.to_string(), index, writer))
+            }
+        }
+    }
+}
+
+/// Format of the per-sample read outputs. Reads too short to split
+/// are always written as FASTQ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReadOutput {
+    /// FASTQ, gzip compressed when `gzip` is set, with the UMI
+    /// recorded as set by the `UmiConfig`
+    Fastq { gzip: bool },
+    /// Unaligned BAM with the UMI and barcode in tags, and mates
+    /// flagged when `paired` is set
+    Ubam { paired: bool },
+}
+
+impl ReadOutput {
+    fn suffix(&self) -> &'static str {
+        match *self {
+            ReadOutput::Fastq { gzip } => fastq_suffix(gzip),
+            ReadOutput::Ubam { .. } => UBAM_SUFFIX,
+        }
+    }
+
+    fn is_ubam(&self) -> bool {
+        match *self {
+            ReadOutput::Fastq { .. } => false,
+            ReadOutput::Ubam { .. } => true,
+        }
+    }
+
+    /// Returns the kind of file listed in the manifest.
+    fn kind(&self) -> &'static str {
+        match *self {
+            ReadOutput::Fastq { .. } => "fastq",
+            ReadOutput::Ubam { .. } => "ubam",
+        }
+    }
+}
+
+/// FASTQ output stream, gzip compressed with `--gzip`, tallying the
+/// bytes written to the file.
+type FastqStream = MaybeGzWriter<ChecksumWriter<fs::File>>;
+
+/// FASTQ output file, kept so that its stream can be finished and its
+/// checksum listed in the manifest once splitting is done.
+struct FastqOutput {
+    checksum: Checksum,
+    stream: FastqStream,
+}
+
+impl FastqOutput {
+    fn create(path: &Path, gzip: bool) -> Result<Self, failure::Error> {
+        let checksum = Checksum::new();
+        let stream = MaybeGzWriter::new(checksum.writer(fs::File::create(path)?), gzip);
+        Ok(FastqOutput {
+            checksum: checksum,
+            stream: stream,
+        })
+    }
+}
+
+/// Returns the suffix of FASTQ output files.
+fn fastq_suffix(gzip: bool) -> &'static str {
+    if gzip {
+        ".fastq.gz"
+    } else {
+        ".fastq"
+    }
+}
+
+/// Returns the name of the FASTQ file of reads too short to split.
+fn short_path(gzip: bool) -> PathBuf {
+    PathBuf::from(format!("tooshort{}", fastq_suffix(gzip)))
+}
+
+/// Returns the name of the FASTQ file of reads with inserts too long
+/// to keep.
+fn long_path(gzip: bool) -> PathBuf {
+    PathBuf::from(format!("toolong{}", fastq_suffix(gzip)))
+}
+
+/// Returns the name of the FASTQ file of reads set aside for low base
+/// qualities.
+fn lowqual_path(gzip: bool) -> PathBuf {
+    PathBuf::from(format!("lowqual{}", fastq_suffix(gzip)))
+}
+
+/// Returns the path of a per-sample output file, relative to the
+/// output directory, within a subdirectory named for the sample when
+/// `per_sample_dirs` is set.
+fn sample_path(per_sample_dirs: bool, name: &str, suffix: &str) -> PathBuf {
+    let filename = format!("{}{}", name, suffix);
+    if per_sample_dirs {
+        Path::new(name).join(filename)
+    } else {
+        PathBuf::from(filename)
+    }
+}
+
+/// Trimming of the insert left after the linker is removed, and the
+/// insert lengths kept. A homopolymer `tail`, when set, is trimmed
+/// first. Reads whose inserts are then shorter than `min` are too
+/// short to split, and those longer than `max`, when set, are too long
+/// to keep. Long inserts usually come from adapters that were not
+/// trimmed.
+#[derive(Debug, Clone, Copy)]
+struct InsertSpec {
+    min: usize,
+    max: Option<usize>,
+    tail: Option<TailTrim>,
+}
+
+/// Numbers of reads set aside rather than split into samples.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UnsplitReads {
+    /// Reads too short to split
+    pub tooshort: usize,
+    /// Reads with inserts too long to keep
+    pub toolong: usize,
+    /// Reads set aside for low base qualities
+    pub lowqual: usize,
+}
+
+impl UnsplitReads {
+    pub fn add(&mut self, other: &UnsplitReads) {
+        self.tooshort += other.tooshort;
+        self.toolong += other.toolong;
+        self.lowqual += other.lowqual;
+    }
+}
+
+/// Minimum Phred qualities required of the sample index and UMI bases
+/// in the linker. Reads with a low-quality index base are assigned to
+/// the unknown index, and low-quality UMI bases are masked to `N`.
+///
+/// Reads whose mean quality is below `min_mean_qual`, or with a UMI
+/// base below `filter_umi_qual`, are set aside as low quality instead,
+/// so that bad sequencing cycles do not add reads to the unknown index
+/// or create spurious UMIs.
+#[derive(Debug, Clone, Copy, Default)]
+struct LinkerQuality {
+    min_index_qual: Option<u8>,
+    min_umi_qual: Option<u8>,
+    min_mean_qual: Option<u8>,
+    filter_umi_qual: Option<u8>,
+}
+
+impl LinkerQuality {
+    fn is_active(&self) -> bool {
+        self.min_index_qual.is_some() || self.min_umi_qual.is_some()
+    }
+
+    /// Returns `true` when low-quality reads are set aside.
+    fn filters_reads(&self) -> bool {
+        self.min_mean_qual.is_some() || self.filter_umi_qual.is_some()
+    }
+
+    /// Returns `true` unless read `fq`, with linker `split`, is set
+    /// aside as low quality.
+    fn read_passes(&self, fq: &fastq::Record, split: &LinkerSplit) -> bool {
+        self.min_mean_qual.map_or(true, |min_qual| {
+            mean_quality(fq.qual()) >= f64::from(min_qual)
+        }) && self
+            .filter_umi_qual
+            .map_or(true, |min_qual| split.umi_passes(min_qual))
+    }
+}
+
+/// Number of reads affected by the linker quality thresholds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QualityStats {
+    /// Reads assigned to the unknown index because of a low-quality
+    /// index base
+    pub low_quality_index: usize,
+    /// Reads with at least one UMI base masked
+    pub masked_umi_reads: usize,
+    /// Total UMI bases masked
+    pub masked_umi_bases: usize,
+}
+
+impl QualityStats {
+    pub fn table(&self) -> String {
+        format!(
+            "LowQualityIndex\t{}\nMaskedUmiReads\t{}\nMaskedUmiBases\t{}\n",
+            self.low_quality_index, self.masked_umi_reads, self.masked_umi_bases
+        )
+    }
+}
+
+/// Returns the reads from `input_name`, paired with their mates when
+/// `interleaved` is set. Inputs named `.bam` or `.cram` are read as
+/// unaligned records, and all others as FASTQ.
+fn read_mates<P: AsRef<Path>>(
+    input_name: P,
+    interleaved: bool,
+    corrupt: &CorruptInputs,
+) -> Result<Box<Iterator<Item = Result<ReadMates, failure::Error>>>, failure::Error> {
+    let input_name = input_name.as_ref();
+    let records: Box<Iterator<Item = Result<fastq::Record, failure::Error>>> =
+        if is_ubam_path(input_name) {
+            Box::new(ubam_records(input_name, corrupt)?)
+        } else {
+            Box::new(corrupt.fastq_records(fastq::Reader::new(open_input(input_name)?)))
+        };
+    if interleaved {
+        Ok(Box::new(interleaved_mates(records)))
+    } else {
+        Ok(Box::new(single_reads(records)))
     }
 }
 
 pub fn split_file<P: AsRef<Path>>(
     config: &mut Config,
     input_name: P,
-) -> Result<(usize, usize), failure::Error> {
+) -> Result<(usize, UnsplitReads), failure::Error> {
     let mut total = 0;
-    let mut tooshort = 0;
+    let mut unsplit = UnsplitReads::default();
 
-    let input_reader: Box<Read> = if input_name.as_ref() == Path::new("-") {
-        Box::new(io::stdin())
-    } else {
-        Box::new(fs::File::open(&input_name)?)
-    };
+    let reads = read_mates(&input_name, config.interleaved, &config.corrupt)?;
+
+    let Config {
+        ref linker_spec,
+        insert_spec,
+        ref mut sample_map,
+        ref mut short_file,
+        ref mut long_file,
+        ref mut lowqual_file,
+        progress,
+        threads,
+        ref quality,
+        ref mut quality_stats,
+        umi_cluster,
+        header_index,
+        ref umi_config,
+        read_output,
+        ..
+    } = *config;
+    // Reads are labeled on the worker threads unless the UMI or the
+    // barcode depends on the sample they are assigned to, or they are
+    // written as unaligned BAM
+    let label = !umi_cluster && !umi_config.get_name_barcode() && !read_output.is_ubam();
+
+    let _timer = StageTimer::new("classification");
+    Parallel::new(threads)?.map_batches(
+        timed_iter("FASTQ read", reads),
+        |batch| {
+            split_batch(
+                linker_spec,
+                insert_spec,
+                quality,
+                header_index,
+                umi_config,
+                label,
+                batch,
+            )
+        },
+        |batch| {
+            let _timer = StageTimer::new("output");
+            for read in batch {
+                total += 1;
+
+                match read {
+                    ReadSplit::TooShort(fq, mate) => {
+                        short_file.write_record(&fq)?;
+                        if let Some(mate) = mate {
+                            short_file.write_record(&mate)?;
+                        }
+                        unsplit.tooshort += 1;
+                    }
+                    ReadSplit::TooLong(fq, mate) => {
+                        if let Some(long_file) = long_file.as_mut() {
+                            long_file.write_record(&fq)?;
+                            if let Some(mate) = mate {
+                                long_file.write_record(&mate)?;
+                            }
+                        }
+                        unsplit.toolong += 1;
+                    }
+                    ReadSplit::LowQuality(fq, mate) => {
+                        if let Some(lowqual_file) = lowqual_file.as_mut() {
+                            lowqual_file.write_record(&fq)?;
+                            if let Some(mate) = mate {
+                                lowqual_file.write_record(&mate)?;
+                            }
+                        }
+                        unsplit.lowqual += 1;
+                    }
+                    ReadSplit::Split {
+                        sample_index,
+                        umi,
+                        masked_umi_bases,
+                        tail_bases,
+                        record,
+                        mate,
+                    } => {
+                        if masked_umi_bases > 0 {
+                            quality_stats.masked_umi_reads += 1;
+                            quality_stats.masked_umi_bases += masked_umi_bases;
+                        }
+                        let mut sample = match sample_index {
+                            Some(ref sample_index) => sample_map.get_mut(sample_index)?,
+                            None => {
+                                quality_stats.low_quality_index += 1;
+                                sample_map.unknown_mut()?
+                            }
+                        };
+                        sample.count_tail(tail_bases);
+                        if label {
+                            sample.handle_split_fastq(&umi, &record)?;
+                            if let Some(mate) = mate {
+                                sample.write_mate(&mate)?;
+                            }
+                        } else {
+                            sample.handle_trimmed_fastq(&umi, &record, umi_config)?;
+                            if let Some(mate) = mate {
+                                sample.handle_trimmed_mate(&umi, &mate, umi_config)?;
+                            }
+                        }
+                    }
+                }
+
+                if progress.map_or(false, |nprog| total % nprog == 0) {
+                    info!(
+                        "{:7} reads from {}",
+                        total,
+                        input_name.as_ref().to_str().unwrap_or("???")
+                    );
+                }
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok((total, unsplit))
+}
+
+/// Counts the UMIs of each sample in a first pass through one input,
+/// for clustering before the reads are split. Corrupt inputs skipped
+/// here are counted only when `split_file` reads them again.
+pub fn tally_umis<P: AsRef<Path>>(
+    config: &mut Config,
+    input_name: P,
+) -> Result<(), failure::Error> {
+    let reads = read_mates(
+        &input_name,
+        config.interleaved,
+        &config.corrupt.preliminary(),
+    )?;
+
+    let Config {
+        ref linker_spec,
+        insert_spec,
+        ref mut sample_map,
+        threads,
+        ref quality,
+        header_index,
+        ref umi_config,
+        ..
+    } = *config;
+
+    let _timer = StageTimer::new("UMI tally");
+    Parallel::new(threads)?.map_batches(
+        timed_iter("FASTQ read", reads),
+        |batch| {
+            split_batch(
+                linker_spec,
+                insert_spec,
+                quality,
+                header_index,
+                umi_config,
+                false,
+                batch,
+            )
+        },
+        |batch| {
+            for read in batch {
+                if let ReadSplit::Split {
+                    sample_index, umi, ..
+                } = read
+                {
+                    let mut sample = match sample_index {
+                        Some(ref sample_index) => sample_map.get_mut(sample_index)?,
+                        None => sample_map.unknown_mut()?,
+                    };
+                    sample.tally_umi(&umi);
+                }
+            }
+            Ok(())
+        },
+    )
+}
+
+/// Clusters the UMIs counted by `tally_umis` for each sample.
+fn cluster_umis(config: &mut Config) -> Result<(), failure::Error> {
+    for sample_rc in config.sample_map.things() {
+        let mut sample = sample_rc.try_borrow_mut()?;
+        let (clusters, merged) = {
+            let clusters = sample.cluster_umis();
+            (clusters.clusters(), clusters.merged())
+        };
+        info!(
+            "Sample {}: merged {} UMIs into {} clusters",
+            sample.name(),
+            merged,
+            clusters
+        );
+    }
+    Ok(())
+}
 
-    for fqres in fastq::Reader::new(input_reader).records() {
-        let fq = fqres?;
+/// Outcome of linker trimming for one read, computed on a worker
+/// thread and written out in order by `split_file`. The sample index
+/// is `None` when it has a low-quality base. The record carries its
+/// UMI, except when UMIs are clustered or the sample barcode is
+/// recorded, and it is labeled on output. The mate of a paired-end
+/// read is kept with it and labeled in the same way.
+enum ReadSplit {
+    TooShort(fastq::Record, Option<fastq::Record>),
+    TooLong(fastq::Record, Option<fastq::Record>),
+    LowQuality(fastq::Record, Option<fastq::Record>),
+    Split {
+        sample_index: Option<Vec<u8>>,
+        umi: Vec<u8>,
+        masked_umi_bases: usize,
+        tail_bases: usize,
+        record: fastq::Record,
+        mate: Option<fastq::Record>,
+    },
+}
 
-        total += 1;
+fn split_batch(
+    linker_spec: &LinkerSpec,
+    insert_spec: InsertSpec,
+    quality: &LinkerQuality,
+    header_index: bool,
+    umi_config: &UmiConfig,
+    label: bool,
+    batch: Vec<ReadMates>,
+) -> Result<Vec<ReadSplit>, failure::Error> {
+    let mut reads = Vec::with_capacity(batch.len());
 
-        if fq.seq().len() < config.linker_spec.linker_length() + config.min_insert {
-            config.short_file.write_record(&fq)?;
-            tooshort += 1;
+    for (fq, mate) in batch {
+        if fq.seq().len() < linker_spec.linker_length() + insert_spec.min {
+            reads.push(ReadSplit::TooShort(fq, mate));
         } else {
-            let split = config.linker_spec.split_record(&fq).ok_or_else(|| {
+            let mut split = linker_spec.split_record(&fq).ok_or_else(|| {
                 failure::err_msg(format!(
                     "Split failed on \"{}\"",
                     str::from_utf8(fq.seq()).unwrap_or("???")
                 ))
             })?;
-            let mut sample = config.sample_map.get_mut(split.sample_index())?;
-            sample.handle_split_read(&fq, &split)?;
-        }
-
-        if config.progress.map_or(false, |nprog| total % nprog == 0) {
-            print!(
-                "{:7} reads from {}\n",
-                total,
-                input_name.as_ref().to_str().unwrap_or("???")
-            );
+            let tail_bases = insert_spec.tail.map_or(0, |tail| split.trim_tail(&tail));
+            if split.sequence().len() < insert_spec.min {
+                reads.push(ReadSplit::TooShort(fq, mate));
+                continue;
+            }
+            if insert_spec
+                .max
+                .map_or(false, |max| split.sequence().len() > max)
+            {
+                reads.push(ReadSplit::TooLong(fq, mate));
+                continue;
+            }
+            if !quality.read_passes(&fq, &split) {
+                reads.push(ReadSplit::LowQuality(fq, mate));
+                continue;
+            }
+            if header_index {
+                split.set_sample_index(header_sample_index(fq.desc()).ok_or_else(|| {
+                    format_err!("Read {} has no sample index in its header", fq.id())
+                })?);
+            }
+            let index_passes = quality
+                .min_index_qual
+                .map_or(true, |min_qual| split.index_passes(min_qual));
+            let masked_umi_bases = quality
+                .min_umi_qual
+                .map_or(0, |min_qual| split.mask_umi(min_qual));
+            let umi = split_umi(&split, umi_config);
+            let mate = match mate {
+                Some(ref mate) if label => {
+                    Some(label_fastq(mate, split.sample_index(), &umi, umi_config)?)
+                }
+                _ => mate,
+            };
+            reads.push(ReadSplit::Split {
+                sample_index: if index_passes {
+                    Some(split.sample_index().to_vec())
+                } else {
+                    None
+                },
+                masked_umi_bases: masked_umi_bases,
+                tail_bases: tail_bases,
+                record: if label {
+                    split_fastq(&fq, &split, umi_config)?
+                } else {
+                    trimmed_fastq(&fq, &split)
+                },
+                umi: umi,
+                mate: mate,
+            });
         }
     }
 
-    Ok((total, tooshort))
+    Ok(reads)
 }
 
-pub fn write_stats(config: &Config, total: usize, tooshort: usize) -> Result<(), failure::Error> {
-    let mut fates_path = config.output_dir.clone();
-    fates_path.push("fates.txt");
-    let mut fates = fs::File::create(&fates_path)?;
+/// Writes per-sample UMI counts and insert lengths, the fates of all
+/// reads, any tail trimming, quality, and sample sheet QC reports, all
+/// of these statistics together as JSON, and a manifest of every
+/// output file, returning the QC report.
+pub fn write_stats(
+    config: &mut Config,
+    unsplit: &UnsplitReads,
+) -> Result<QcReport, failure::Error> {
+    let _timer = StageTimer::new("output");
+    let mut fates = SplitFates::new();
+    let mut demux_stats = DemuxStats::new();
+    let mut tail_table = "sample\ttail_reads\ttail_bases\n".to_string();
 
-    for sample_rc in config.sample_map.things() {
-        let sample = sample_rc.try_borrow()?;
-        let mut stats_path = config.output_dir.clone();
-        stats_path.push(format!("{}_stats.txt", sample.name()));
-        fs::write(&stats_path, sample.stats_table())?;
-
-        let fract = 100.0 * (sample.total() as f64) / (total as f64);
-        write!(
-            fates,
-            "{}\t{}\t{}\t{:.2}%\n",
+    config.short_file.flush()?;
+    config.short_output.stream.finish()?;
+    if let Some(long_file) = config.long_file.as_mut() {
+        long_file.flush()?;
+    }
+    if let Some(lowqual_file) = config.lowqual_file.as_mut() {
+        lowqual_file.flush()?;
+    }
+    let mut manifest = config.manifest.clone();
+    let output_dir = config.output_dir.as_path();
+    let per_sample_dirs = config.per_sample_dirs;
+
+    let sample_rcs = config.sample_map.things();
+    let mut samples = sample_rcs
+        .iter()
+        .map(|sample_rc| sample_rc.try_borrow_mut())
+        .collect::<Result<Vec<_>, _>>()?;
+    samples.sort_by(|x, y| (x.name(), x.index()).cmp(&(y.name(), y.index())));
+
+    for mut sample in samples {
+        sample.finish()?;
+        let name = sample.name();
+        let output_name = config.output_names.get(name).map_or(name, String::as_str);
+        let read_path = sample_path(per_sample_dirs, output_name, config.read_output.suffix());
+        let checksum = match config.fastq_outputs.get(name) {
+            Some(output) => {
+                output.stream.finish()?;
+                output.checksum.clone()
+            }
+            None => Checksum::new_from_file(output_dir.join(&read_path))?,
+        };
+        manifest.add(
+            &read_path,
+            Some(name),
+            config.read_output.kind(),
+            Some(sample.total() as u64),
+            &checksum,
+        );
+        manifest.write(
+            output_dir,
+            &sample_path(per_sample_dirs, output_name, "_stats.txt"),
+            Some(name),
+            "umi_stats",
+            &sample.stats_table(config.insert_spec.tail.is_some()),
+        )?;
+        manifest.write(
+            output_dir,
+            &sample_path(per_sample_dirs, output_name, "_lengths.txt"),
+            Some(name),
+            "lengths",
+            &sample.length_table(),
+        )?;
+
+        fates.add_sample(
             sample.name(),
             str::from_utf8(sample.index())?,
-            sample.total(),
-            fract
+            sample.total() as u64,
+        );
+        demux_stats.add_sample(sample.demux_stats()?);
+        let (tail_reads, tail_bases) = sample.tail_counts();
+        tail_table.push_str(&format!("{}\t{}\t{}\n", name, tail_reads, tail_bases));
+    }
+    fates.add_short(unsplit.tooshort as u64);
+    demux_stats.add_short(unsplit.tooshort as u64);
+    manifest.add(
+        &short_path(config.gzip),
+        None,
+        "fastq",
+        Some(unsplit.tooshort as u64),
+        &config.short_output.checksum,
+    );
+    if let Some(ref long_output) = config.long_output {
+        long_output.stream.finish()?;
+        fates.add_long(unsplit.toolong as u64);
+        demux_stats.add_long(unsplit.toolong as u64);
+        manifest.add(
+            &long_path(config.gzip),
+            None,
+            "fastq",
+            Some(unsplit.toolong as u64),
+            &long_output.checksum,
+        );
+    }
+    if let Some(ref lowqual_output) = config.lowqual_output {
+        lowqual_output.stream.finish()?;
+        fates.add_lowqual(unsplit.lowqual as u64);
+        demux_stats.add_lowqual(unsplit.lowqual as u64);
+        manifest.add(
+            &lowqual_path(config.gzip),
+            None,
+            "fastq",
+            Some(unsplit.lowqual as u64),
+            &lowqual_output.checksum,
+        );
+    }
+
+    manifest.write(
+        output_dir,
+        Path::new("fates.txt"),
+        None,
+        "fates",
+        &fates.to_string(),
+    )?;
+
+    if config.quality.is_active() {
+        manifest.write(
+            output_dir,
+            Path::new("quality_stats.txt"),
+            None,
+            "quality_stats",
+            &config.quality_stats.table(),
+        )?;
+        info!(
+            "{} reads with a low-quality index, {} with masked UMI bases",
+            config.quality_stats.low_quality_index, config.quality_stats.masked_umi_reads
+        );
+    }
+
+    if config.insert_spec.tail.is_some() {
+        manifest.write(
+            output_dir,
+            Path::new("tail_stats.txt"),
+            None,
+            "tail_stats",
+            &tail_table,
         )?;
     }
 
-    write!(
-        fates,
-        "short\tN/A\t{}\t{:.2}%\n",
-        tooshort,
-        100.0 * (tooshort as f64) / (total as f64)
+    if config.quality.is_active() {
+        demux_stats.quality = Some(config.quality_stats);
+    }
+    manifest.write(
+        output_dir,
+        Path::new("demux_stats.json"),
+        None,
+        "demux_stats",
+        &(demux_stats.to_json()? + "\n"),
     )?;
 
-    Ok(())
+    let qc_report = QcReport::new(&fates, &config.expectations, config.tolerance);
+    if !qc_report.is_empty() {
+        manifest.write(
+            output_dir,
+            Path::new("qc.txt"),
+            None,
+            "qc",
+            &qc_report.table(),
+        )?;
+        for (name, problems) in qc_report.failures() {
+            warn!(
+                "Sample {} failed QC: {}",
+                name,
+                problems
+                    .iter()
+                    .map(QcProblem::to_string)
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+        }
+    }
+
+    fs::write(output_dir.join("manifest.json"), manifest.to_json()? + "\n")?;
+
+    Ok(qc_report)
 }
 
 pub fn fastx_split(mut config: Config) -> Result<(), failure::Error> {
-    let mut total = 0;
-    let mut tooshort = 0;
+    let mut unsplit = UnsplitReads::default();
+
+    if config.umi_cluster {
+        for input_name in config.fastx_inputs.to_vec() {
+            tally_umis(&mut config, input_name)?;
+        }
+        cluster_umis(&mut config)?;
+    }
 
     for input_name in config.fastx_inputs.to_vec() {
-        let (file_total, file_tooshort) = split_file(&mut config, input_name)?;
-        total += file_total;
-        tooshort += file_tooshort;
+        let (_, file_unsplit) = split_file(&mut config, input_name)?;
+        unsplit.add(&file_unsplit);
     }
 
-    write_stats(&config, total, tooshort)?;
+    let qc_report = write_stats(&mut config, &unsplit)?;
+
+    let failures = qc_report.failures();
+    if config.strict && !failures.is_empty() {
+        bail!(
+            "{} samples failed demultiplexing QC: {}",
+            failures.len(),
+            failures
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<&str>>()
+                .join(", ")
+        );
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn umi_cluster_skips_corrupt_once() {
+        let dir = env::temp_dir().join(format!("riboprof-fastx-split-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let read = |name: &str, umi: &str| {
+            let seq = format!("ACGTTGCAACGTTGCAACGTTGCA{}ACGT", umi);
+            format!("@{}\n{}\n+\n{}\n", name, seq, "I".repeat(seq.len()))
+        };
+        let fastq = format!(
+            "{}@bad\nACGT\n+\nII\n{}{}",
+            read("r1", "AAAAAA"),
+            read("r2", "AAAAAT"),
+            read("r3", "CCCCCC")
+        );
+        let fastq_path = dir.join("reads.fastq");
+        fs::write(&fastq_path, fastq).unwrap();
+        let sheet_path = dir.join("samples.csv");
+        fs::write(&sheet_path, "wt,ACGT\n").unwrap();
+
+        // One corrupt read is allowed, and it is read once when tallying
+        // UMIs and again when splitting.
+        let corrupt = CorruptInputs::new(1);
+        let cli = CLI {
+            fastx_inputs: vec![fastq_path.to_string_lossy().into_owned()],
+            output_dir: dir.join("split").to_string_lossy().into_owned(),
+            suffix: "NNNNNNIIII".to_string(),
+            sample_sheet: sheet_path.to_string_lossy().into_owned(),
+            umi_cluster: true,
+            corrupt: corrupt.clone(),
+            ..Default::default()
+        };
+        fastx_split(Config::new(&cli).unwrap()).unwrap();
+        assert_eq!(corrupt.skipped(CorruptKind::Fastq), 1);
+        assert_eq!(corrupt.total(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use fastx_split::fates::*;
+
+/// Expected read share and minimum read count for one sample, given
+/// by optional sample sheet columns.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SampleExpectation {
+    /// Expected percentage of all reads
+    pub expected_percent: Option<f64>,
+    /// Minimum acceptable number of reads
+    pub min_reads: Option<u64>,
+}
+
+impl SampleExpectation {
+    pub fn is_empty(&self) -> bool {
+        self.expected_percent.is_none() && self.min_reads.is_none()
+    }
+
+    /// Returns the problems with a sample having `reads` out of
+    /// `total` reads, where the percentage of reads may differ from the
+    /// expected percentage by up to `tolerance` percentage points.
+    pub fn problems(&self, reads: u64, total: u64, tolerance: f64) -> Vec<QcProblem> {
+        let mut problems = Vec::new();
+
+        if let Some(expected) = self.expected_percent {
+            let percent = 100.0 * reads as f64 / total.max(1) as f64;
+            if percent < expected - tolerance {
+                problems.push(QcProblem::FractionLow);
+            } else if percent > expected + tolerance {
+                problems.push(QcProblem::FractionHigh);
+            }
+        }
+
+        if let Some(min_reads) = self.min_reads {
+            if reads < min_reads {
+                problems.push(QcProblem::TooFewReads);
+            }
+        }
+
+        problems
+    }
+}
+
+/// Reason for a sample to fail demultiplexing QC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QcProblem {
+    /// Percentage of reads below the expected percentage
+    FractionLow,
+    /// Percentage of reads above the expected percentage
+    FractionHigh,
+    /// Fewer reads than the minimum
+    TooFewReads,
+}
+
+impl fmt::Display for QcProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QcProblem::FractionLow => write!(f, "FractionLow"),
+            QcProblem::FractionHigh => write!(f, "FractionHigh"),
+            QcProblem::TooFewReads => write!(f, "TooFewReads"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct QcRow {
+    name: String,
+    index: String,
+    reads: u64,
+    percent: f64,
+    expectation: SampleExpectation,
+    problems: Vec<QcProblem>,
+}
+
+/// Comparison of the reads assigned to each sample against its
+/// expectations, for samples having any expectations.
+#[derive(Debug, Clone)]
+pub struct QcReport {
+    rows: Vec<QcRow>,
+}
+
+impl QcReport {
+    pub fn new(
+        fates: &SplitFates,
+        expectations: &HashMap<String, SampleExpectation>,
+        tolerance: f64,
+    ) -> Self {
+        let total = fates.total();
+        let rows = fates
+            .samples()
+            .iter()
+            .filter_map(|sample| {
+                let expectation = expectations.get(&sample.name)?;
+                if expectation.is_empty() {
+                    return None;
+                }
+                Some(QcRow {
+                    name: sample.name.clone(),
+                    index: sample.index.clone(),
+                    reads: sample.reads,
+                    percent: 100.0 * sample.reads as f64 / total.max(1) as f64,
+                    expectation: expectation.clone(),
+                    problems: expectation.problems(sample.reads, total, tolerance),
+                })
+            })
+            .collect();
+        QcReport { rows: rows }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Returns the names of samples failing QC, along with their
+    /// problems.
+    pub fn failures(&self) -> Vec<(&str, &[QcProblem])> {
+        self.rows
+            .iter()
+            .filter(|row| !row.problems.is_empty())
+            .map(|row| (row.name.as_str(), row.problems.as_slice()))
+            .collect()
+    }
+
+    /// Returns a table with one row for each sample having
+    /// expectations, giving its reads and percentage of all reads
+    /// along with its expectations and its status, either `PASS` or a
+    /// comma-separated list of problems.
+    pub fn table(&self) -> String {
+        let mut table = "sample\tindex\treads\tpercent\texpected\tmin_reads\tstatus\n".to_string();
+        for row in self.rows.iter() {
+            let status = if row.problems.is_empty() {
+                "PASS".to_string()
+            } else {
+                row.problems
+                    .iter()
+                    .map(QcProblem::to_string)
+                    .collect::<Vec<String>>()
+                    .join(",")
+            };
+            table += &format!(
+                "{}\t{}\t{}\t{:.2}\t{}\t{}\t{}\n",
+                row.name,
+                row.index,
+                row.reads,
+                row.percent,
+                row.expectation
+                    .expected_percent
+                    .map_or("NA".to_string(), |expected| format!("{:.2}", expected)),
+                row.expectation
+                    .min_reads
+                    .map_or("NA".to_string(), |min_reads| min_reads.to_string()),
+                status
+            );
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_qc() {
+        let mut fates = SplitFates::new();
+        fates.add_sample("UnknownIndex", "NNNN", 50);
+        fates.add_sample("wt", "ACGT", 600);
+        fates.add_sample("ko", "TGCA", 300);
+        fates.add_sample("extra", "GGGG", 50);
+
+        let mut expectations = HashMap::new();
+        expectations.insert(
+            "wt".to_string(),
+            SampleExpectation {
+                expected_percent: Some(50.0),
+                min_reads: Some(100),
+            },
+        );
+        expectations.insert(
+            "ko".to_string(),
+            SampleExpectation {
+                expected_percent: Some(28.0),
+                min_reads: Some(500),
+            },
+        );
+        expectations.insert("extra".to_string(), SampleExpectation::default());
+
+        let report = QcReport::new(&fates, &expectations, 5.0);
+        assert_eq!(
+            report.failures(),
+            vec![
+                ("wt", &[QcProblem::FractionHigh][..]),
+                ("ko", &[QcProblem::TooFewReads][..])
+            ]
+        );
+        assert_eq!(
+            report.table(),
+            "sample\tindex\treads\tpercent\texpected\tmin_reads\tstatus\n\
+             wt\tACGT\t600\t60.00\t50.00\t100\tFractionHigh\n\
+             ko\tTGCA\t300\t30.00\t28.00\t500\tTooFewReads\n"
+        );
+
+        let lenient = QcReport::new(&fates, &expectations, 10.0);
+        assert_eq!(lenient.failures().len(), 1);
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::io;
 use std::str;
@@ -7,15 +7,88 @@ use failure;
 
 use bio::io::fastq;
 
+use fastx_split::demux_stats::SampleDemuxStats;
 use fastx_split::linkers::*;
+use fastx_split::ubam::UbamWriter;
+use umi::*;
+
+/// Returns the UMI of a read after linker trimming, corrected against
+/// the UMI whitelist if any. A UMI that cannot be corrected is kept
+/// as-is.
+pub fn split_umi(split: &LinkerSplit, umi_config: &UmiConfig) -> Vec<u8> {
+    umi_config
+        .correct(split.umi())
+        .unwrap_or_else(|| split.umi().to_vec())
+}
+
+/// Builds the trimmed fastq record for a read after linker trimming,
+/// using the trimmed sequence and quality and recording the UMI as
+/// set by `umi_config`, by default appended to the record `id` after
+/// a `#` character. The barcode, when recorded, is the sample index
+/// of the read itself.
+///
+/// # Errors
+///
+/// An error variant is returned when the UMI is not valid UTF-8.
+pub fn split_fastq(
+    fq: &fastq::Record,
+    split: &LinkerSplit,
+    umi_config: &UmiConfig,
+) -> Result<fastq::Record, failure::Error> {
+    label_fastq(
+        &trimmed_fastq(fq, split),
+        split.sample_index(),
+        &split_umi(split, umi_config),
+        umi_config,
+    )
+}
+
+/// Builds the fastq record for a read after linker trimming, keeping
+/// the original `id` and description without the UMI.
+pub fn trimmed_fastq(fq: &fastq::Record, split: &LinkerSplit) -> fastq::Record {
+    fastq::Record::with_attrs(fq.id(), fq.desc(), split.sequence(), split.quality())
+}
+
+/// Records `umi`, and the sample `barcode` if requested, in a trimmed
+/// fastq record from `trimmed_fastq` as set by `umi_config`.
+///
+/// # Errors
+///
+/// An error variant is returned when the UMI is not valid UTF-8.
+pub fn label_fastq(
+    trimmed: &fastq::Record,
+    barcode: &[u8],
+    umi: &[u8],
+    umi_config: &UmiConfig,
+) -> Result<fastq::Record, failure::Error> {
+    let (id, desc) = umi_config.fastq_id(trimmed.id(), trimmed.desc(), barcode, umi)?;
+    Ok(fastq::Record::with_attrs(
+        &id,
+        desc.as_ref().map(String::as_str),
+        trimmed.seq(),
+        trimmed.qual(),
+    ))
+}
+
+/// Output for the reads of one sample, either FastQ with the UMI
+/// recorded as set by the `UmiConfig`, or unaligned BAM with the UMI
+/// in a tag.
+enum SampleOutput {
+    Fastq(fastq::Writer<Box<io::Write>>),
+    Ubam(UbamWriter),
+}
 
 /// Collected information about one particular sample
 pub struct Sample {
     name: String,
     index: Vec<u8>,
-    dest: fastq::Writer<Box<io::Write>>,
+    dest: SampleOutput,
     total: usize,
     umi_count: HashMap<Vec<u8>, usize>,
+    lengths: BTreeMap<usize, usize>,
+    tail_reads: usize,
+    tail_bases: usize,
+    clusters: Option<UmiClusters>,
 }
 
 impl Sample {
@@ -29,24 +102,45 @@ impl Sample {
     ///
     /// * `dest` is the output writer for processed fastq records for this sample
     pub fn new<W: io::Write + 'static>(name: String, index: Vec<u8>, dest: W) -> Self {
+        Self::new_output(
+            name,
+            index,
+            SampleOutput::Fastq(fastq::Writer::new(Box::new(dest))),
+        )
+    }
+
+    /// Creates new sample information for a sample written as
+    /// unaligned BAM to `dest`. Reads for the sample must be handled
+    /// by `handle_trimmed_fastq`, rather than labeled in advance.
+    pub fn new_ubam(name: String, index: Vec<u8>, dest: UbamWriter) -> Self {
+        Self::new_output(name, index, SampleOutput::Ubam(dest))
+    }
+
+    fn new_output(name: String, index: Vec<u8>, dest: SampleOutput) -> Self {
         Sample {
             name: name,
             index: index,
-            dest: fastq::Writer::new(Box::new(dest)),
+            dest: dest,
             total: 0,
             umi_count: HashMap::new(),
+            lengths: BTreeMap::new(),
+            tail_reads: 0,
+            tail_bases: 0,
+            clusters: None,
         }
     }
 
     /// Handle a fastq record after linker trimming. This function
     /// will write a new fastq record to the sample output writer,
     /// using the trimmed sequence and quality. The UMI will be
-    /// appended to the record `id`, after a `#` character. This
-    /// function does not check the sample index in the `LinkerSplit`
-    /// result.
+    /// appended to the record `id`, by default after a `#` character,
+    /// along with the index of the sample if requested by
+    /// `umi_config`. This function does not check the sample index in
+    /// the `LinkerSplit` result.
     ///
     /// The `Sample` also collects statistics on the total number of
-    /// reads, and the number of reads per UMI.
+    /// reads, the number of reads per UMI, and the number of reads of
+    /// each insert length.
     ///
     /// # Arguments
     ///
@@ -54,6 +148,8 @@ impl Sample {
     ///
     /// * `split` contains the results of linker trimming and processing
     ///
+    /// * `umi_config` sets UMI correction and how the UMI is recorded
+    ///
     /// # Errors
     ///
     /// An error variant is returned when problems arise in writing
@@ -62,22 +158,158 @@ impl Sample {
         &mut self,
         fq: &fastq::Record,
         split: &LinkerSplit,
+        umi_config: &UmiConfig,
     ) -> Result<(), failure::Error> {
-        let umi_id = format!("{}#{}", fq.id(), str::from_utf8(split.umi())?);
-        let splitfq = fastq::Record::with_attrs(
-            umi_id.as_str(),
-            fq.desc(),
-            split.sequence(),
-            split.quality(),
-        );
+        self.handle_trimmed_fastq(
+            &split_umi(split, umi_config),
+            &trimmed_fastq(fq, split),
+            umi_config,
+        )
+    }
 
+    /// Handle a fastq record already built by `split_fastq`, writing
+    /// it to the sample output writer and counting its UMI and insert
+    /// length.
+    ///
+    /// # Arguments
+    ///
+    /// * `umi` is the UMI sequence from linker trimming
+    ///
+    /// * `splitfq` is the trimmed fastq record
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when problems arise in writing
+    /// the processed fastq record to the output file, or when the
+    /// sample is written as unaligned BAM.
+    pub fn handle_split_fastq(
+        &mut self,
+        umi: &[u8],
+        splitfq: &fastq::Record,
+    ) -> Result<(), failure::Error> {
+        self.count_read(umi, splitfq);
+        self.write_fastq(splitfq)
+    }
+
+    fn count_read(&mut self, umi: &[u8], splitfq: &fastq::Record) {
         self.total += 1;
-        *self.umi_count.entry(split.umi().to_vec()).or_insert(0) += 1;
+        *self.umi_count.entry(umi.to_vec()).or_insert(0) += 1;
+        *self.lengths.entry(splitfq.seq().len()).or_insert(0) += 1;
+    }
+
+    fn write_fastq(&mut self, fq: &fastq::Record) -> Result<(), failure::Error> {
+        match self.dest {
+            SampleOutput::Fastq(ref mut writer) => writer.write_record(fq)?,
+            SampleOutput::Ubam(_) => bail!(
+                "Labeled read {} cannot be written to unaligned BAM for {}",
+                fq.id(),
+                self.name
+            ),
+        }
+        Ok(())
+    }
+
+    /// Counts the bases of a homopolymer tail trimmed from a read of
+    /// the sample, when any were trimmed.
+    pub fn count_tail(&mut self, bases: usize) {
+        if bases > 0 {
+            self.tail_reads += 1;
+            self.tail_bases += bases;
+        }
+    }
+
+    /// Returns the number of reads with a homopolymer tail trimmed
+    /// and the total number of tail bases trimmed.
+    pub fn tail_counts(&self) -> (usize, usize) {
+        (self.tail_reads, self.tail_bases)
+    }
 
-        self.dest.write_record(&splitfq)?;
+    /// Counts a UMI in a first pass through the reads, before any are
+    /// written, for clustering by `cluster_umis`.
+    pub fn tally_umi(&mut self, umi: &[u8]) {
+        *self.umi_count.entry(umi.to_vec()).or_insert(0) += 1;
+    }
+
+    /// Clusters the UMIs counted by `tally_umi` so that reads handled
+    /// afterwards by `handle_trimmed_fastq` carry the UMI at the center
+    /// of their cluster, and clears the counts.
+    pub fn cluster_umis(&mut self) -> &UmiClusters {
+        let clusters = UmiClusters::new_directional(&self.umi_count);
+        self.umi_count.clear();
+        self.clusters.get_or_insert(clusters)
+    }
+
+    /// Handle a fastq record built by `trimmed_fastq`, correcting its
+    /// UMI by the clusters from `cluster_umis`, if any, and then
+    /// recording the UMI and sample index as set by `umi_config` and
+    /// writing the record as by `handle_split_fastq`. Unaligned BAM
+    /// output always records them in the `RX` and `BC` tags.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when problems arise in writing
+    /// the processed fastq record to the output file.
+    pub fn handle_trimmed_fastq(
+        &mut self,
+        umi: &[u8],
+        trimmed: &fastq::Record,
+        umi_config: &UmiConfig,
+    ) -> Result<(), failure::Error> {
+        let umi = self.clustered_umi(umi);
+        if let SampleOutput::Ubam(ref mut writer) = self.dest {
+            writer.write(trimmed, &self.index, &umi, false)?;
+        } else {
+            let splitfq = label_fastq(trimmed, &self.index, &umi, umi_config)?;
+            self.write_fastq(&splitfq)?;
+        }
+        self.count_read(&umi, trimmed);
         Ok(())
     }
 
+    /// Writes the mate of the read last handled by
+    /// `handle_split_fastq`, already labeled with the same UMI, e.g.,
+    /// by `label_fastq`. Mates are not counted as separate reads.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when problems arise in writing
+    /// the mate to the output file.
+    pub fn write_mate(&mut self, mate: &fastq::Record) -> Result<(), failure::Error> {
+        self.write_fastq(mate)
+    }
+
+    /// Labels the mate of the read last handled by
+    /// `handle_trimmed_fastq` with the same UMI and writes it as by
+    /// `write_mate`.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when problems arise in writing
+    /// the mate to the output file.
+    pub fn handle_trimmed_mate(
+        &mut self,
+        umi: &[u8],
+        mate: &fastq::Record,
+        umi_config: &UmiConfig,
+    ) -> Result<(), failure::Error> {
+        let umi = self.clustered_umi(umi);
+        if let SampleOutput::Ubam(ref mut writer) = self.dest {
+            writer.write(mate, &self.index, &umi, true)
+        } else {
+            let matefq = label_fastq(mate, &self.index, &umi, umi_config)?;
+            self.write_mate(&matefq)
+        }
+    }
+
+    /// Returns the UMI at the center of the cluster holding `umi`,
+    /// once UMIs are clustered, and otherwise `umi` itself.
+    fn clustered_umi(&self, umi: &[u8]) -> Vec<u8> {
+        match self.clusters {
+            Some(ref clusters) => clusters.correct(umi).to_vec(),
+            None => umi.to_vec(),
+        }
+    }
+
     /// Returns the name of the sample
     pub fn name(&self) -> &str {
         &self.name
@@ -93,10 +325,14 @@ impl Sample {
         self.total
     }
 
-    /// Returns a table of the number of reads per UMI
-    pub fn stats_table(&self) -> String {
+    /// Returns a table of the number of reads per UMI, listing every
+    /// possible UMI in a fixed order. With `tail_trimmed`, rows for
+    /// the number of reads with a homopolymer tail trimmed and the
+    /// total tail bases trimmed follow, as `tail_reads` and
+    /// `tail_bases`.
+    pub fn stats_table(&self, tail_trimmed: bool) -> String {
         let umi_length = self.umi_count.keys().next().map_or(0, |umi| umi.len());
-        let mut table = String::new();
+        let mut table = "umi\treads\n".to_string();
 
         for umi in Self::all_umis(umi_length) {
             table.push_str(&format!(
@@ -106,9 +342,70 @@ impl Sample {
             ));
         }
 
+        if tail_trimmed {
+            table.push_str(&format!(
+                "tail_reads\t{}\ntail_bases\t{}\n",
+                self.tail_reads, self.tail_bases
+            ));
+        }
+
+        table
+    }
+
+    /// Returns a table of the number of reads with each insert length
+    /// after linker trimming, for lengths with at least one read
+    pub fn length_table(&self) -> String {
+        let mut table = "length\treads\n".to_string();
+        for (len, reads) in self.lengths.iter() {
+            table.push_str(&format!("{}\t{}\n", len, reads));
+        }
         table
     }
 
+    /// Returns the read count, UMI counts, insert lengths, and tail
+    /// trimming counts for the sample, for the JSON statistics report.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when the index or a UMI is not
+    /// valid UTF-8.
+    pub fn demux_stats(&self) -> Result<SampleDemuxStats, failure::Error> {
+        let mut umis = BTreeMap::new();
+        for (umi, &reads) in self.umi_count.iter() {
+            umis.insert(str::from_utf8(umi)?.to_string(), reads as u64);
+        }
+        Ok(SampleDemuxStats {
+            name: self.name.clone(),
+            index: str::from_utf8(&self.index)?.to_string(),
+            reads: self.total as u64,
+            umis: umis,
+            lengths: self
+                .lengths
+                .iter()
+                .map(|(&len, &reads)| (len, reads as u64))
+                .collect(),
+            tail_reads: self.tail_reads as u64,
+            tail_bases: self.tail_bases as u64,
+        })
+    }
+
+    /// Flushes the sample output writer
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.dest {
+            SampleOutput::Fastq(ref mut writer) => writer.flush(),
+            SampleOutput::Ubam(_) => Ok(()),
+        }
+    }
+
+    /// Flushes FastQ output, or closes unaligned BAM output so that
+    /// the file is complete.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let SampleOutput::Ubam(ref mut writer) = self.dest {
+            writer.finish();
+        }
+        self.flush()
+    }
+
     fn all_umis(len: usize) -> Vec<Vec<u8>> {
         let mut umis = vec![b"".to_vec()];
         for _ in 0..len {
@@ -175,13 +472,17 @@ mod tests {
             let rec1 =
                 fastq::Record::with_attrs("test_record", None, b"ACGTACGTACGTACGT", &vec![40; 16]);
             let spl1 = linker_spec.split_record(&rec1).unwrap();
-            sample.handle_split_read(&rec1, &spl1).unwrap();
+            sample
+                .handle_split_read(&rec1, &spl1, &UmiConfig::new())
+                .unwrap();
             assert!(sample.total() == 1);
 
             let rec2 =
                 fastq::Record::with_attrs("another", None, b"TGTGCGAGCTAGTCACTC", &vec![37; 18]);
             let spl2 = linker_spec.split_record(&rec2).unwrap();
-            sample.handle_split_read(&rec2, &spl2).unwrap();
+            sample
+                .handle_split_read(&rec2, &spl2, &UmiConfig::new())
+                .unwrap();
             assert!(sample.total() == 2);
         }
 
@@ -205,16 +506,78 @@ mod tests {
                 seq.push(*nt2);
                 let rec = fastq::Record::with_attrs("test", None, &seq, &vec![40; seq.len()]);
                 let spl = linker_spec.split_record(&rec).unwrap();
-                sample.handle_split_read(&rec, &spl).unwrap();
+                sample
+                    .handle_split_read(&rec, &spl, &UmiConfig::new())
+                    .unwrap();
             }
         }
 
-        let mut exp = "AA\t4\nAC\t3\nAG\t2\nAT\t1\nAN\t0\n".to_string();
+        let mut exp = "umi\treads\nAA\t4\nAC\t3\nAG\t2\nAT\t1\nAN\t0\n".to_string();
         exp.push_str("CA\t8\nCC\t6\nCG\t4\nCT\t2\nCN\t0\n");
         exp.push_str("GA\t12\nGC\t9\nGG\t6\nGT\t3\nGN\t0\n");
         exp.push_str("TA\t16\nTC\t12\nTG\t8\nTT\t4\nTN\t0\n");
         exp.push_str("NA\t0\nNC\t0\nNG\t0\nNT\t0\nNN\t0\n");
 
-        assert!(sample.stats_table() == exp);
+        assert!(sample.stats_table(false) == exp);
+        assert_eq!(sample.length_table(), "length\treads\n12\t100\n");
+
+        let stats = sample.demux_stats().unwrap();
+        assert_eq!((stats.name.as_str(), stats.reads), ("Two", 100));
+        assert_eq!(stats.umis.len(), 16);
+        assert_eq!(stats.umis["GC"], 9);
+        assert_eq!(stats.lengths[&12], 100);
+        assert_eq!((stats.tail_reads, stats.tail_bases), (0, 0));
+
+        sample.count_tail(0);
+        sample.count_tail(5);
+        sample.count_tail(7);
+        assert_eq!(sample.tail_counts(), (2, 12));
+        assert!(sample
+            .stats_table(true)
+            .ends_with("tail_reads\t2\ntail_bases\t12\n"));
+        assert!(!sample.stats_table(false).contains("tail_reads"));
+    }
+
+    #[test]
+    fn sample_umi_clusters() {
+        let outbuf = Rc::new(RefCell::new(Vec::new()));
+        let writer = TestWriter {
+            dest: outbuf.clone(),
+        };
+        let mut sample = Sample::new("Three".to_string(), Vec::new(), writer);
+        let linker_spec = LinkerSpec::new("", "NN").unwrap();
+
+        let reads: Vec<fastq::Record> = ["ACGTAA", "ACGTAA", "ACGTAA", "ACGTAC", "ACGTGG"]
+            .iter()
+            .enumerate()
+            .map(|(i, seq)| {
+                fastq::Record::with_attrs(&format!("read{}", i), None, seq.as_bytes(), &[40; 6])
+            })
+            .collect();
+
+        for rec in reads.iter() {
+            let spl = linker_spec.split_record(rec).unwrap();
+            sample.tally_umi(&split_umi(&spl, &UmiConfig::new()));
+        }
+        assert_eq!(sample.cluster_umis().merged(), 1);
+
+        for rec in reads.iter() {
+            let spl = linker_spec.split_record(rec).unwrap();
+            sample
+                .handle_trimmed_fastq(
+                    &split_umi(&spl, &UmiConfig::new()),
+                    &trimmed_fastq(rec, &spl),
+                    &UmiConfig::new(),
+                )
+                .unwrap();
+        }
+        sample.flush().unwrap();
+
+        let output = String::from_utf8(outbuf.borrow().clone()).unwrap();
+        assert!(output.starts_with("@read0#AA\nACGT\n"));
+        assert!(output.contains("@read3#AA\n"));
+        assert!(output.contains("@read4#GG\n"));
+        assert!(sample.stats_table(false).contains("AA\t4\n"));
+        assert!(sample.stats_table(false).contains("AC\t0\n"));
     }
 }
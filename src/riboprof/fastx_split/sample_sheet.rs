@@ -6,7 +6,7 @@ use std::fmt;
 use std::rc::Rc;
 use std::str;
 
-use failure;
+use fastx_split::qc::*;
 
 #[derive(Debug, Clone)]
 pub struct SampleMap<T> {
@@ -55,9 +55,9 @@ impl<T> SampleMap<T> {
         index: Vec<u8>,
         allow_mismatch: bool,
         thing: T,
-    ) -> Result<Rc<RefCell<T>>, failure::Error> {
+    ) -> Result<Rc<RefCell<T>>, SampleError> {
         if index.len() != self.index_length {
-            return Err(SampleError::IndexBadLength(self.index_length, index).into());
+            return Err(SampleError::IndexBadLength(self.index_length, index));
         }
 
         let rcrc = Rc::new(RefCell::new(thing));
@@ -81,37 +81,53 @@ impl<T> SampleMap<T> {
         Ok(rcrc)
     }
 
-    fn insert_index(
-        &mut self,
-        index: Vec<u8>,
-        entry: SampleEntry<T>,
-    ) -> Result<(), failure::Error> {
+    fn insert_index(&mut self, index: Vec<u8>, entry: SampleEntry<T>) -> Result<(), SampleError> {
         match self.index_map.entry(index) {
             Entry::Occupied(occ) => Err(SampleError::IndexClash(occ.key().to_vec())),
-            Entry::Vacant(vac) => Ok(vac.insert(entry)),
-        }?;
-        Ok(())
+            Entry::Vacant(vac) => {
+                vac.insert(entry);
+                Ok(())
+            }
+        }
     }
 
     #[allow(dead_code)]
-    pub fn get(&self, index: &[u8]) -> Result<Ref<T>, failure::Error> {
+    pub fn get(&self, index: &[u8]) -> Result<Ref<T>, SampleError> {
         if index.len() != self.index_length {
-            return Err(SampleError::IndexBadLength(self.index_length, index.to_vec()).into());
+            return Err(SampleError::IndexBadLength(
+                self.index_length,
+                index.to_vec(),
+            ));
         }
 
         let entry = self.index_map.get(index).unwrap_or(&self.unknown);
-        let thing = entry.thing.try_borrow()?;
-        Ok(thing)
+        entry
+            .thing
+            .try_borrow()
+            .map_err(|_| SampleError::InUse(index.to_vec()))
     }
 
-    pub fn get_mut(&mut self, index: &[u8]) -> Result<RefMut<T>, failure::Error> {
+    pub fn get_mut(&mut self, index: &[u8]) -> Result<RefMut<T>, SampleError> {
         if index.len() != self.index_length {
-            return Err(SampleError::IndexBadLength(self.index_length, index.to_vec()).into());
+            return Err(SampleError::IndexBadLength(
+                self.index_length,
+                index.to_vec(),
+            ));
         }
 
         let entry = self.index_map.get(index).unwrap_or(&self.unknown);
-        let thing = entry.thing.try_borrow_mut()?;
-        Ok(thing)
+        entry
+            .thing
+            .try_borrow_mut()
+            .map_err(|_| SampleError::InUse(index.to_vec()))
+    }
+
+    /// Returns the sample for reads whose index is unknown.
+    pub fn unknown_mut(&mut self) -> Result<RefMut<T>, SampleError> {
+        self.unknown
+            .thing
+            .try_borrow_mut()
+            .map_err(|_| SampleError::InUse(self.unknown.true_index.to_vec()))
     }
 
     pub fn things(&self) -> Vec<Rc<RefCell<T>>> {
@@ -124,46 +140,288 @@ impl<T> SampleMap<T> {
 }
 
 impl<T: fmt::Display> SampleMap<T> {
+    /// Returns a table with one row for each index sequence that is
+    /// assigned to a sample, giving the sample and its true index.
+    /// Rows are sorted by sample and then by index sequence.
     pub fn mapping_table(&self) -> String {
-        let mut table = String::new();
-        for (index, entry) in self.index_map.iter() {
+        let mut rows: Vec<(String, &[u8], &[u8])> = self
+            .index_map
+            .iter()
+            .map(|(index, entry)| {
+                (
+                    entry.to_string(),
+                    index.as_slice(),
+                    entry.true_index.as_slice(),
+                )
+            })
+            .collect();
+        rows.sort();
+
+        let mut table = "index\tsample\tsample_index\n".to_string();
+        for (sample, index, true_index) in rows {
             table.push_str(&format!(
                 "{}\t{}\t{}\n",
                 str::from_utf8(index).unwrap(),
-                entry,
-                str::from_utf8(entry.true_index.as_slice()).unwrap()
+                sample,
+                str::from_utf8(true_index).unwrap()
             ));
         }
         table
     }
 }
 
-pub fn parse_sample_sheet(sheet: &str) -> Result<Vec<(String, String)>, failure::Error> {
-    sheet.lines().map(parse_sample_line).collect()
+/// One sample from a sample sheet line. Only the name and index are
+/// required; the remaining columns may be absent or left empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SheetSample {
+    pub name: String,
+    pub index: String,
+    pub expectation: SampleExpectation,
+    /// Base name of the sample output files, in place of the sample
+    /// name
+    pub output: Option<String>,
+    /// Mismatches allowed in the index, either 0 or 1
+    pub mismatches: usize,
+    pub description: Option<String>,
+}
+
+impl SheetSample {
+    /// Returns the base name of the sample output files.
+    pub fn output_name(&self) -> &str {
+        self.output.as_ref().unwrap_or(&self.name)
+    }
+}
+
+/// Mismatches allowed in the index of a sample without a `mismatches`
+/// column.
+const DEFAULT_MISMATCHES: usize = 1;
+
+/// Columns of a sample sheet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SheetColumn {
+    Name,
+    Index,
+    ExpectedPercent,
+    MinReads,
+    Output,
+    Mismatches,
+    Description,
+}
+
+/// Columns of a sample sheet without a header row, in order.
+const DEFAULT_COLUMNS: [SheetColumn; 4] = [
+    SheetColumn::Name,
+    SheetColumn::Index,
+    SheetColumn::ExpectedPercent,
+    SheetColumn::MinReads,
+];
+
+impl SheetColumn {
+    fn from_header(header: &str) -> Option<Self> {
+        match header.to_lowercase().as_str() {
+            "name" => Some(SheetColumn::Name),
+            "index" => Some(SheetColumn::Index),
+            "expected_percent" => Some(SheetColumn::ExpectedPercent),
+            "min_reads" => Some(SheetColumn::MinReads),
+            "output" => Some(SheetColumn::Output),
+            "mismatches" => Some(SheetColumn::Mismatches),
+            "description" => Some(SheetColumn::Description),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a CSV-format sample sheet. The sheet may begin with a header
+/// row, whose first column is `name`, naming its columns in any order
+/// from `name`, `index`, `expected_percent`, `min_reads`, `output`,
+/// `mismatches`, and `description`. Without a header, the columns are
+/// `NAME,INDEX[,EXPECTED%[,MIN-READS]]`. Blank lines and lines
+/// starting with `#` are skipped.
+///
+/// # Errors
+///
+/// A `BadSheetLine` error with the line number is returned for a
+/// malformed line, or when a sample name or output name is repeated.
+pub fn parse_sample_sheet(sheet: &str) -> Result<Vec<SheetSample>, SampleError> {
+    let mut columns: Option<Vec<SheetColumn>> = None;
+    let mut has_header = false;
+    let mut names = HashMap::new();
+    let mut outputs = HashMap::new();
+    let mut samples = Vec::new();
+
+    for (line_no, line) in (1..).zip(sheet.lines()) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields: Vec<&str> = line.split(',').map(str::trim).collect();
+
+        if columns.is_none() {
+            if fields[0].eq_ignore_ascii_case("name") {
+                columns = Some(parse_sheet_header(line_no, &fields)?);
+                has_header = true;
+                continue;
+            }
+            columns = Some(DEFAULT_COLUMNS.to_vec());
+        }
+
+        // Sheets without a header have always been allowed trailing
+        // fields beyond the ones that are read.
+        if !has_header && fields.len() > DEFAULT_COLUMNS.len() {
+            warn!(
+                "Ignoring {} extra fields on sample sheet line {}",
+                fields.len() - DEFAULT_COLUMNS.len(),
+                line_no
+            );
+            fields.truncate(DEFAULT_COLUMNS.len());
+        }
+
+        let sample = parse_sample_line(line_no, columns.as_ref().unwrap(), &fields)?;
+        if let Some(first) = names.insert(sample.name.clone(), line_no) {
+            return Err(SampleError::BadSheetLine(
+                line_no,
+                format!("sample {} already named on line {}", sample.name, first),
+            ));
+        }
+        if let Some(first) = outputs.insert(sample.output_name().to_string(), line_no) {
+            return Err(SampleError::BadSheetLine(
+                line_no,
+                format!(
+                    "output {} already used on line {}",
+                    sample.output_name(),
+                    first
+                ),
+            ));
+        }
+        samples.push(sample);
+    }
+
+    Ok(samples)
+}
+
+fn parse_sheet_header(line_no: usize, fields: &[&str]) -> Result<Vec<SheetColumn>, SampleError> {
+    let bad_line = |problem: String| SampleError::BadSheetLine(line_no, problem);
+
+    let mut columns = Vec::new();
+    for field in fields.iter() {
+        let column = SheetColumn::from_header(field)
+            .ok_or_else(|| bad_line(format!("unknown column \"{}\"", field)))?;
+        if columns.contains(&column) {
+            return Err(bad_line(format!("repeated column \"{}\"", field)));
+        }
+        columns.push(column);
+    }
+    if !columns.contains(&SheetColumn::Index) {
+        return Err(bad_line("no index column".to_string()));
+    }
+    Ok(columns)
+}
+
+fn parse_sample_line(
+    line_no: usize,
+    columns: &[SheetColumn],
+    fields: &[&str],
+) -> Result<SheetSample, SampleError> {
+    let bad_line = |problem: String| SampleError::BadSheetLine(line_no, problem);
+
+    if fields.len() > columns.len() {
+        return Err(bad_line(format!(
+            "{} fields but only {} columns",
+            fields.len(),
+            columns.len()
+        )));
+    }
+
+    let mut sample = SheetSample {
+        name: String::new(),
+        index: String::new(),
+        expectation: SampleExpectation::default(),
+        output: None,
+        mismatches: DEFAULT_MISMATCHES,
+        description: None,
+    };
+
+    for (&column, &field) in columns.iter().zip(fields.iter()) {
+        if field.is_empty() {
+            continue;
+        }
+        match column {
+            SheetColumn::Name => sample.name = file_name(field).map_err(&bad_line)?,
+            SheetColumn::Index => {
+                let index = field.to_ascii_uppercase();
+                if !index.bytes().all(|b| b"ACGTN+-".contains(&b)) {
+                    return Err(bad_line(format!(
+                        "index \"{}\" is not a DNA sequence",
+                        field
+                    )));
+                }
+                sample.index = index;
+            }
+            SheetColumn::ExpectedPercent => {
+                let pct: f64 = field.trim_end_matches('%').parse().map_err(|_| {
+                    bad_line(format!("expected percent \"{}\" is not a number", field))
+                })?;
+                if pct < 0.0 || pct > 100.0 {
+                    return Err(bad_line(format!(
+                        "expected percent {} is not between 0 and 100",
+                        pct
+                    )));
+                }
+                sample.expectation.expected_percent = Some(pct);
+            }
+            SheetColumn::MinReads => {
+                sample.expectation.min_reads =
+                    Some(field.parse().map_err(|_| {
+                        bad_line(format!("minimum reads \"{}\" is not a count", field))
+                    })?)
+            }
+            SheetColumn::Output => sample.output = Some(file_name(field).map_err(&bad_line)?),
+            SheetColumn::Mismatches => {
+                sample.mismatches = match field {
+                    "0" => 0,
+                    "1" => 1,
+                    _ => return Err(bad_line(format!("mismatches \"{}\" must be 0 or 1", field))),
+                }
+            }
+            SheetColumn::Description => sample.description = Some(field.to_string()),
+        }
+    }
+
+    if sample.name.is_empty() {
+        return Err(bad_line("no sample name".to_string()));
+    }
+    if sample.index.is_empty() {
+        return Err(bad_line(format!("no index for sample {}", sample.name)));
+    }
+    Ok(sample)
 }
 
-fn parse_sample_line(line: &str) -> Result<(String, String), failure::Error> {
-    let mut field_iter = line.split(',');
-    let name = field_iter
-        .next()
-        .ok_or_else(|| SampleError::BadSheetLine(line.to_string()))?;
-    let idx = field_iter
-        .next()
-        .ok_or_else(|| SampleError::BadSheetLine(line.to_string()))?;
-    Ok((name.to_string(), idx.to_string()))
+/// Checks that a sample or output name can be used in output file
+/// names.
+fn file_name(name: &str) -> Result<String, String> {
+    if name == "." || name == ".." || name.contains('/') || name.contains('\\') {
+        Err(format!("\"{}\" cannot be used as a file name", name))
+    } else {
+        Ok(name.to_string())
+    }
 }
 
+/// Errors reading a sample sheet and assigning reads to samples.
 #[derive(Debug, Clone, PartialEq, Eq)]
-enum SampleError {
-    BadSheetLine(String),
+pub enum SampleError {
+    /// Problem on a sample sheet line, by line number
+    BadSheetLine(usize, String),
     IndexBadLength(usize, Vec<u8>),
     IndexClash(Vec<u8>),
+    InUse(Vec<u8>),
 }
 
 impl fmt::Display for SampleError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            SampleError::BadSheetLine(line) => write!(f, "Bad sample sheet line: \"{}\"", line),
+            SampleError::BadSheetLine(line_no, problem) => {
+                write!(f, "Bad sample sheet line {}: {}", line_no, problem)
+            }
             SampleError::IndexBadLength(ilen, idx) => write!(
                 f,
                 "Index length wrong: index \"{}\" but length {}",
@@ -175,8 +433,136 @@ impl fmt::Display for SampleError {
                 "Index clash: index {}",
                 str::from_utf8(idx).unwrap_or("???")
             ),
+            SampleError::InUse(idx) => write!(
+                f,
+                "Sample already in use: index {}",
+                str::from_utf8(idx).unwrap_or("???")
+            ),
         }
     }
 }
 
 impl error::Error for SampleError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_errors() {
+        assert_eq!(
+            parse_sample_sheet("wt,ACGT\nko\n"),
+            Err(SampleError::BadSheetLine(
+                2,
+                "no index for sample ko".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_sample_sheet("wt,ACGT,12.5%,1000\nko,TGCA,,500\nctl,GGGG\n").unwrap(),
+            vec![
+                SheetSample {
+                    name: "wt".to_string(),
+                    index: "ACGT".to_string(),
+                    expectation: SampleExpectation {
+                        expected_percent: Some(12.5),
+                        min_reads: Some(1000),
+                    },
+                    output: None,
+                    mismatches: 1,
+                    description: None,
+                },
+                SheetSample {
+                    name: "ko".to_string(),
+                    index: "TGCA".to_string(),
+                    expectation: SampleExpectation {
+                        expected_percent: None,
+                        min_reads: Some(500),
+                    },
+                    output: None,
+                    mismatches: 1,
+                    description: None,
+                },
+                SheetSample {
+                    name: "ctl".to_string(),
+                    index: "GGGG".to_string(),
+                    expectation: SampleExpectation::default(),
+                    output: None,
+                    mismatches: 1,
+                    description: None,
+                },
+            ]
+        );
+        assert!(parse_sample_sheet("wt,ACGT,120\n").is_err());
+        assert!(parse_sample_sheet("wt,ACGT,10,lots\n").is_err());
+
+        let samples = parse_sample_sheet("wt,acgt,10,500,lane1,extra\n").unwrap();
+        assert_eq!(samples[0].index, "ACGT");
+        assert_eq!(samples[0].expectation.min_reads, Some(500));
+
+        let sheet = "# Run 12\n\
+                     name,index,output,mismatches,description\n\
+                     wt,ACGT,wt_rep1,0,Wild type\n\
+                     \n\
+                     ko,TGCA\n";
+        let samples = parse_sample_sheet(sheet).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(
+            (samples[0].output_name(), samples[0].mismatches),
+            ("wt_rep1", 0)
+        );
+        assert_eq!(samples[0].description, Some("Wild type".to_string()));
+        assert_eq!((samples[1].output_name(), samples[1].mismatches), ("ko", 1));
+
+        let error = |sheet: &str| parse_sample_sheet(sheet).err().unwrap().to_string();
+        assert_eq!(
+            error("Name,Index,Lane\nwt,ACGT,1\n"),
+            "Bad sample sheet line 1: unknown column \"Lane\""
+        );
+        assert_eq!(
+            error("name,index\nwt,ACGT\nko,TGCA,12\n"),
+            "Bad sample sheet line 3: 3 fields but only 2 columns"
+        );
+        assert_eq!(
+            error("name,index,mismatches\nwt,ACGT,2\n"),
+            "Bad sample sheet line 2: mismatches \"2\" must be 0 or 1"
+        );
+        assert_eq!(
+            error("wt,ACGT\nwt,TGCA\n"),
+            "Bad sample sheet line 2: sample wt already named on line 1"
+        );
+        assert_eq!(
+            error("name,index,output\nwt,ACGT,rep1\nko,TGCA,rep1\n"),
+            "Bad sample sheet line 3: output rep1 already used on line 2"
+        );
+        assert_eq!(
+            error("wt,ACGU\n"),
+            "Bad sample sheet line 1: index \"ACGU\" is not a DNA sequence"
+        );
+        assert!(parse_sample_sheet("name,index,output\nwt,ACGT,../wt\n").is_err());
+
+        let mut sample_map = SampleMap::new(4, "unknown");
+        sample_map.insert(b"ACGT".to_vec(), true, "wt").unwrap();
+        assert_eq!(
+            sample_map.insert(b"ACG".to_vec(), false, "ko").err(),
+            Some(SampleError::IndexBadLength(4, b"ACG".to_vec()))
+        );
+        assert_eq!(
+            sample_map.insert(b"ACGA".to_vec(), false, "ko").err(),
+            Some(SampleError::IndexClash(b"ACGA".to_vec()))
+        );
+        assert_eq!(*sample_map.get(b"ACGC").unwrap(), "wt");
+
+        let table = sample_map.mapping_table();
+        assert!(table.starts_with("index\tsample\tsample_index\nAAGT\twt\tACGT\n"));
+        assert!(table.ends_with("TCGT\twt\tACGT\n"));
+        assert_eq!(table.lines().count(), 1 + 13);
+
+        let unknown = sample_map.things()[0].clone();
+        let held = unknown.borrow_mut();
+        assert_eq!(
+            sample_map.get(b"TTTT").err(),
+            Some(SampleError::InUse(b"TTTT".to_vec()))
+        );
+        drop(held);
+    }
+}
@@ -0,0 +1,281 @@
+use std::env;
+use std::path::Path;
+use std::str;
+
+use failure;
+
+use bio::io::fastq;
+use rust_htslib::bam;
+use rust_htslib::bam::record::{Aux, CigarString};
+
+use bam_utils::*;
+use corrupt::*;
+use genome::reverse_complement;
+use umi::{BARCODE_TAG, UMI_TAG};
+
+/// Quality written for bases whose quality is missing from a record.
+const MISSING_QUAL: u8 = b'!';
+
+/// Flags of an unpaired, unaligned read.
+const UNPAIRED_FLAGS: u16 = 0x4;
+
+/// Flags of the first and second reads of an unaligned pair.
+const FIRST_MATE_FLAGS: u16 = 0x1 | 0x4 | 0x8 | 0x40;
+const SECOND_MATE_FLAGS: u16 = 0x1 | 0x4 | 0x8 | 0x80;
+
+/// Suffix of per-sample unaligned BAM output files.
+pub const UBAM_SUFFIX: &str = ".bam";
+
+/// Returns `true` for an input read as unaligned BAM or CRAM records
+/// rather than FASTQ, by its `.bam` or `.cram` extension.
+pub fn is_ubam_path(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case("bam") || ext.eq_ignore_ascii_case("cram"),
+        None => false,
+    }
+}
+
+/// Converts an unaligned BAM record into a FASTQ record, restoring
+/// the sequencing orientation of a reversed record. A `BC` tag with
+/// the sample barcode becomes a `BC:Z:` comment in the description.
+///
+/// # Errors
+///
+/// An error variant is returned when the read name or barcode is not
+/// valid UTF-8.
+pub fn ubam_fastq(rec: &bam::Record) -> Result<fastq::Record, failure::Error> {
+    let mut seq = rec.seq().as_bytes();
+    let mut qual: Vec<u8> = rec
+        .qual()
+        .iter()
+        .map(|&q| if q == 0xff { MISSING_QUAL } else { q + 33 })
+        .collect();
+    if rec.is_reverse() {
+        seq = reverse_complement(&seq);
+        qual.reverse();
+    }
+
+    let desc = match rec.aux(BARCODE_TAG) {
+        Some(Aux::String(barcode)) => Some(format!("BC:Z:{}", str::from_utf8(barcode)?)),
+        _ => None,
+    };
+
+    Ok(fastq::Record::with_attrs(
+        str::from_utf8(rec.qname())?,
+        desc.as_ref().map(String::as_str),
+        &seq,
+        &qual,
+    ))
+}
+
+/// Iterator over the reads in an unaligned BAM or CRAM file as FASTQ
+/// records, created by `ubam_records`.
+pub struct UbamRecords {
+    reader: bam::Reader,
+    corrupt: CorruptInputs,
+}
+
+/// Opens an unaligned BAM or CRAM file and returns an iterator over
+/// the FASTQ records converted from its primary records.
+///
+/// # Errors
+///
+/// An error variant is returned when the file cannot be opened, or
+/// when a BAM file is truncated and `corrupt` does not allow corrupt
+/// inputs to be skipped.
+pub fn ubam_records(path: &Path, corrupt: &CorruptInputs) -> Result<UbamRecords, failure::Error> {
+    let reader = match path.to_str() {
+        Some(input) if !input.to_lowercase().ends_with(".cram") => open_bam(input, corrupt)?,
+        _ => bam::Reader::from_path(path)?,
+    };
+    Ok(UbamRecords {
+        reader: reader,
+        corrupt: corrupt.clone(),
+    })
+}
+
+impl Iterator for UbamRecords {
+    type Item = Result<fastq::Record, failure::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let rec = match read_record(&mut self.reader, &self.corrupt) {
+                Ok(Some(rec)) => rec,
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err)),
+            };
+            if rec.flags() & NON_PRIMARY_FLAGS == 0 {
+                return Some(ubam_fastq(&rec));
+            }
+        }
+    }
+}
+
+/// Returns the header for the unaligned BAM output of one sample,
+/// with a read group named for the sample that records its barcode.
+pub fn ubam_header(name: &str, index: &[u8]) -> bam::Header {
+    let mut header = bam::Header::new();
+    header.push_record(
+        bam::header::HeaderRecord::new(b"HD")
+            .push_tag(b"VN", &"1.6")
+            .push_tag(b"SO", &"unsorted"),
+    );
+
+    let mut read_group = bam::header::HeaderRecord::new(b"RG");
+    read_group.push_tag(b"ID", &name).push_tag(b"SM", &name);
+    if !index.is_empty() {
+        read_group.push_tag(b"BC", &String::from_utf8_lossy(index));
+    }
+    header.push_record(&read_group);
+
+    let command_line = env::args()
+        .collect::<Vec<String>>()
+        .join(" ")
+        .replace(|c: char| c == '\t' || c == '\n', " ");
+    header.push_record(
+        bam::header::HeaderRecord::new(b"PG")
+            .push_tag(b"ID", &"fastx-split")
+            .push_tag(b"PN", &"fastx-split")
+            .push_tag(b"VN", &env!("CARGO_PKG_VERSION"))
+            .push_tag(b"CL", &command_line),
+    );
+    header
+}
+
+/// Converts a trimmed FASTQ record into an unaligned BAM record with
+/// the UMI in the `RX` tag, the sample barcode in the `BC` tag, and
+/// the read group of the sample.
+///
+/// # Errors
+///
+/// An error variant is returned when a tag cannot be added.
+pub fn fastq_ubam(
+    fq: &fastq::Record,
+    flags: u16,
+    read_group: &[u8],
+    barcode: &[u8],
+    umi: &[u8],
+) -> Result<bam::Record, failure::Error> {
+    let qual: Vec<u8> = fq.qual().iter().map(|&q| q.saturating_sub(33)).collect();
+    let mut rec = bam::Record::new();
+    rec.set(fq.id().as_bytes(), &CigarString(vec![]), fq.seq(), &qual);
+    rec.set_flags(flags);
+    rec.set_tid(-1);
+    rec.set_pos(-1);
+    rec.set_mtid(-1);
+    rec.set_mpos(-1);
+    rec.push_aux(UMI_TAG, &Aux::String(umi))?;
+    if !barcode.is_empty() {
+        rec.push_aux(BARCODE_TAG, &Aux::String(barcode))?;
+    }
+    rec.push_aux(b"RG", &Aux::String(read_group))?;
+    Ok(rec)
+}
+
+/// Unaligned BAM output for the reads of one sample. The file is
+/// complete once the writer is finished.
+pub struct UbamWriter {
+    writer: Option<bam::Writer>,
+    read_group: Vec<u8>,
+    paired: bool,
+}
+
+impl UbamWriter {
+    /// Creates the output at `path` for sample `name` with `index`.
+    /// Reads written are the first mate of a pair when `paired` is
+    /// set.
+    pub fn create(
+        path: &Path,
+        name: &str,
+        index: &[u8],
+        paired: bool,
+    ) -> Result<Self, failure::Error> {
+        Ok(UbamWriter {
+            writer: Some(bam::Writer::from_path(path, &ubam_header(name, index))?),
+            read_group: name.as_bytes().to_vec(),
+            paired: paired,
+        })
+    }
+
+    /// Writes a trimmed read, or the second mate of the read last
+    /// written when `mate` is set, with its `umi` and `barcode`.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when the record cannot be written
+    /// or the writer is already finished.
+    pub fn write(
+        &mut self,
+        fq: &fastq::Record,
+        barcode: &[u8],
+        umi: &[u8],
+        mate: bool,
+    ) -> Result<(), failure::Error> {
+        let flags = if mate {
+            SECOND_MATE_FLAGS
+        } else if self.paired {
+            FIRST_MATE_FLAGS
+        } else {
+            UNPAIRED_FLAGS
+        };
+        let rec = fastq_ubam(fq, flags, &self.read_group, barcode, umi)?;
+        match self.writer {
+            Some(ref mut writer) => writer.write(&rec)?,
+            None => bail!("Unaligned BAM output for {} is already finished", fq.id()),
+        }
+        Ok(())
+    }
+
+    /// Closes the output file, writing the BGZF end-of-file marker.
+    pub fn finish(&mut self) {
+        self.writer.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ubam_conversion() {
+        assert!(is_ubam_path(Path::new("run/lane1.bam")));
+        assert!(is_ubam_path(Path::new("lane1.CRAM")));
+        assert!(!is_ubam_path(Path::new("lane1.fastq.gz")));
+
+        let mut rec = bam::Record::new();
+        rec.set(
+            b"read1",
+            &CigarString(vec![]),
+            b"AACGT",
+            &[40, 40, 30, 20, 10],
+        );
+        rec.set_flags(0x4);
+        let fq = ubam_fastq(&rec).unwrap();
+        assert_eq!(
+            (fq.id(), fq.desc(), fq.seq(), fq.qual()),
+            ("read1", None, &b"AACGT"[..], &b"II?5+"[..])
+        );
+
+        rec.set_flags(0x4 | 0x10);
+        rec.push_aux(BARCODE_TAG, &Aux::String(b"ACGT-TTGG"))
+            .unwrap();
+        let fq = ubam_fastq(&rec).unwrap();
+        assert_eq!(
+            (fq.desc(), fq.seq(), fq.qual()),
+            (Some("BC:Z:ACGT-TTGG"), &b"ACGTT"[..], &b"+5?II"[..])
+        );
+
+        let fq = fastq::Record::with_attrs("read2", None, b"ACGT", b"II?5");
+        let rec = fastq_ubam(&fq, FIRST_MATE_FLAGS, b"wt", b"TTAG", b"GGCA").unwrap();
+        assert_eq!(rec.qname(), b"read2");
+        assert_eq!(rec.qual(), &[40, 40, 30, 20]);
+        assert_eq!((rec.flags(), rec.tid(), rec.pos()), (0x4d, -1, -1));
+        assert_eq!(rec.aux(UMI_TAG), Some(Aux::String(b"GGCA")));
+        assert_eq!(rec.aux(BARCODE_TAG), Some(Aux::String(b"TTAG")));
+        assert_eq!(rec.aux(b"RG"), Some(Aux::String(b"wt")));
+        assert_eq!(ubam_fastq(&rec).unwrap().seq(), b"ACGT");
+
+        let header = String::from_utf8(ubam_header("wt", b"TTAG").to_bytes()).unwrap();
+        assert!(header.contains("@RG\tID:wt\tSM:wt\tBC:TTAG"));
+    }
+}
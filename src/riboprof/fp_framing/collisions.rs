@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use transcript::*;
+
+use fp_framing::framing::*;
+
+/// Monosome and disome footprint counts in the CDS body of each gene,
+/// used to compare ribosome collision density across genes.
+#[derive(Debug, Clone, Default)]
+pub struct GeneCollisions {
+    counts: HashMap<Arc<String>, (usize, usize)>,
+}
+
+impl GeneCollisions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the gene for a footprint framed within the CDS body.
+    fn body_gene(bam_frame: &BamFrameResult) -> Option<&Arc<String>> {
+        match bam_frame {
+            BamFrameResult::Fp(FpFrameResult::Gene(GeneFrameResult::Good(gene_frame)))
+                if gene_frame.frame().is_some() =>
+            {
+                Some(gene_frame.gene())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn tally_monosome(&mut self, bam_frame: &BamFrameResult) {
+        if let Some(gene) = Self::body_gene(bam_frame) {
+            self.counts.entry(gene.clone()).or_insert((0, 0)).0 += 1;
+        }
+    }
+
+    pub fn tally_disome(&mut self, bam_frame: &BamFrameResult) {
+        if let Some(gene) = Self::body_gene(bam_frame) {
+            self.counts.entry(gene.clone()).or_insert((0, 0)).1 += 1;
+        }
+    }
+
+    /// Adds the counts from another analysis, e.g., of a separate
+    /// chunk of alignments.
+    pub fn merge(&mut self, other: &GeneCollisions) {
+        for (gene, (mono, di)) in other.counts.iter() {
+            let counts = self.counts.entry(gene.clone()).or_insert((0, 0));
+            counts.0 += mono;
+            counts.1 += di;
+        }
+    }
+
+    /// Returns the total monosome and disome footprints counted.
+    pub fn totals(&self) -> (usize, usize) {
+        self.counts.values().fold((0, 0), |(mono, di), counts| {
+            (mono + counts.0, di + counts.1)
+        })
+    }
+
+    /// Returns a table with one line per gene, sorted by name, of
+    /// monosome and disome counts, their density per kilobase of the
+    /// longest CDS of the gene, and the ratio of disome to monosome
+    /// density. The ratio is `NA` for genes without monosomes.
+    pub fn table(&self, trxome: &Transcriptome<Arc<String>>) -> String {
+        let mut genes: Vec<&Arc<String>> = self.counts.keys().collect();
+        genes.sort();
+
+        let mut table = "gene\tcds_length\tmonosome\tdisome\tmonosome_density\tdisome_density\tcollision_ratio\n".to_string();
+        for gene in genes {
+            let (mono, di) = self.counts[gene];
+            let cds_length = trxome
+                .gene_transcripts(gene)
+                .iter()
+                .filter_map(|trx| trx.cds_range().as_ref().map(|cds| cds.len()))
+                .max()
+                .unwrap_or(0);
+            let density = |count: usize| {
+                if cds_length > 0 {
+                    format!("{:.3}", 1000.0 * count as f64 / cds_length as f64)
+                } else {
+                    "NA".to_string()
+                }
+            };
+            let ratio = if mono > 0 {
+                format!("{:.4}", di as f64 / mono as f64)
+            } else {
+                "NA".to_string()
+            };
+            table += &format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                gene,
+                cds_length,
+                mono,
+                di,
+                density(mono),
+                density(di),
+                ratio
+            );
+        }
+
+        table
+    }
+}
@@ -1,5 +1,5 @@
 use std::ops::Range;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use failure;
 
@@ -14,9 +14,9 @@ use bam_utils::*;
 use transcript::*;
 
 pub fn record_framing(
-    trxome: &Transcriptome<Rc<String>>,
-    tids: &Tids<Rc<String>>,
+    trxome: &Transcriptome<Arc<String>>,
     rec: &bam::Record,
+    fp: Option<Spliced<Arc<String>, ReqStrand>>,
     lengths: &Range<usize>,
     cdsbody: &(isize, isize),
     count_multi: bool,
@@ -25,7 +25,7 @@ pub fn record_framing(
         return Ok(BamFrameResult::MultiHit);
     }
 
-    if let Some(fp) = bam_to_spliced(tids, &rec)? {
+    if let Some(fp) = fp {
         let fp_len = fp.exon_total_length();
 
         if fp_len < lengths.start {
@@ -41,18 +41,6 @@ pub fn record_framing(
     }
 }
 
-pub fn is_single_hit(rec: &bam::Record) -> bool {
-    if let Some(bam::record::Aux::Integer(nh)) = rec.aux(b"NH") {
-        nh == 1
-    } else {
-        true
-    }
-}
-
-pub fn is_first_hit(rec: &bam::Record) -> bool {
-    rec.aux(b"HI") == Some(bam::record::Aux::Integer(1))
-}
-
 pub enum BamFrameResult {
     NoHit,
     MultiHit,
@@ -74,17 +62,38 @@ impl BamFrameResult {
 }
 
 pub fn footprint_framing(
-    trxome: &Transcriptome<Rc<String>>,
-    fp: &Spliced<Rc<String>, ReqStrand>,
+    trxome: &Transcriptome<Arc<String>>,
+    fp: &Spliced<Arc<String>, ReqStrand>,
     cdsbody: &(isize, isize),
 ) -> FpFrameResult {
-    let gene_sets = Transcript::group_by_gene(
-        trxome
-            .find_at_loc(fp)
-            .filter(|trx| trx.loc().strand() == fp.strand()),
-    );
+    let gene_sets: Vec<(&Arc<String>, Vec<&Transcript<Arc<String>>>)> = trxome
+        .genes_at_loc(fp, fp.strand())
+        .into_iter()
+        .map(|(gene, trxs)| {
+            let exonic = trxs
+                .into_iter()
+                .filter(|trx| exon_overlap(trx.loc(), fp) > 0)
+                .collect::<Vec<_>>();
+            (gene, exonic)
+        })
+        .filter(|(_gene, trxs)| !trxs.is_empty())
+        .collect();
 
-    if gene_sets.len() > 1 {
+    if gene_sets.is_empty() {
+        if trxome
+            .find_at_loc(fp)
+            .any(|trx| trx.loc().strand() == fp.strand())
+        {
+            FpFrameResult::Intronic
+        } else if trxome
+            .find_at_loc(fp)
+            .any(|trx| trx.loc().strand() != fp.strand() && exon_overlap(trx.loc(), fp) > 0)
+        {
+            FpFrameResult::Antisense
+        } else {
+            FpFrameResult::NoGene
+        }
+    } else if gene_sets.len() > 1 {
         let is_coding: Vec<bool> = gene_sets
             .iter()
             .map(|(_gene, trxs)| trxs.iter().any(|trx| trx.is_coding()))
@@ -97,8 +106,9 @@ pub fn footprint_framing(
         } else {
             FpFrameResult::NoncodingOnly
         }
-    } else if let Some((_gene, trxs)) = gene_sets.into_iter().next() {
-        let coding_trxs: Vec<&Transcript<Rc<String>>> =
+    } else {
+        let (_gene, trxs) = gene_sets.into_iter().next().unwrap();
+        let coding_trxs: Vec<&Transcript<Arc<String>>> =
             trxs.into_iter().filter(|trx| trx.is_coding()).collect();
 
         if coding_trxs.is_empty() {
@@ -106,15 +116,19 @@ pub fn footprint_framing(
         } else {
             FpFrameResult::Gene(gene_framing(cdsbody, coding_trxs.as_slice(), fp))
         }
-    } else {
-        // gene_sets is empty
-        FpFrameResult::NoGene
     }
 }
 
+/// Annotation of a footprint. Only transcripts whose exons overlap the
+/// footprint on the same strand assign it to a gene; a footprint lying
+/// entirely within the introns of same-strand transcripts is
+/// `Intronic`, and one overlapping only the exons of opposite-strand
+/// transcripts is `Antisense`.
 pub enum FpFrameResult {
     Gene(GeneFrameResult),
     NoGene,
+    Antisense,
+    Intronic,
     NoncodingOnly,
     NoncodingOverlap,
     MultiCoding,
@@ -125,6 +139,8 @@ impl FpFrameResult {
         match self {
             FpFrameResult::Gene(gfr) => gfr.aux(),
             FpFrameResult::NoGene => "FpNoGene".to_string().into_bytes(),
+            FpFrameResult::Antisense => "FpAntisense".to_string().into_bytes(),
+            FpFrameResult::Intronic => "FpIntronic".to_string().into_bytes(),
             FpFrameResult::NoncodingOnly => "FpNoncodingOnly".to_string().into_bytes(),
             FpFrameResult::NoncodingOverlap => "FpNoncodingOverlap".to_string().into_bytes(),
             FpFrameResult::MultiCoding => "FpMultiCoding".to_string().into_bytes(),
@@ -155,7 +171,7 @@ impl GeneFrameResult {
 /// as well as the reading frame position.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct GeneFraming {
-    gene: Rc<String>,
+    gene: Arc<String>,
     vs_cds_start: Option<isize>,
     vs_cds_end: Option<isize>,
     frame: Option<usize>,
@@ -175,6 +191,9 @@ impl GeneFraming {
         opt.map_or_else(|| "/*".to_string(), |x| format!("/{}", x))
     }
 
+    pub fn gene(&self) -> &Arc<String> {
+        &self.gene
+    }
     pub fn vs_cds_start(&self) -> Option<isize> {
         self.vs_cds_start
     }
@@ -193,8 +212,8 @@ impl GeneFraming {
 /// defined by one or more transcripts. Framing in
 pub fn gene_framing<'a>(
     cdsbody: &(isize, isize),
-    trxs: &[&'a Transcript<Rc<String>>],
-    fp: &Spliced<Rc<String>, ReqStrand>,
+    trxs: &[&'a Transcript<Arc<String>>],
+    fp: &Spliced<Arc<String>, ReqStrand>,
 ) -> GeneFrameResult {
     let gene = if trxs.len() == 0 {
         return GeneFrameResult::NoCompatible;
@@ -204,7 +223,7 @@ pub fn gene_framing<'a>(
 
     let fp_length = fp.exon_total_length();
 
-    let termini: Vec<TrxPos<'a, Rc<String>>> = trxs
+    let termini: Vec<TrxPos<'a, Arc<String>>> = trxs
         .into_iter()
         .filter_map(move |trx| fp_into_transcript(fp, trx))
         .collect();
@@ -280,9 +299,9 @@ pub fn body_frame<'a, S>(cdsbody: &(isize, isize), trxpos: &TrxPos<'a, S>) -> Op
 /// * `fp` is the location of the footprint
 /// * `trx` is the transcript annotation
 pub fn fp_into_transcript<'a>(
-    fp: &Spliced<Rc<String>, ReqStrand>,
-    trx: &'a Transcript<Rc<String>>,
-) -> Option<TrxPos<'a, Rc<String>>> {
+    fp: &Spliced<Arc<String>, ReqStrand>,
+    trx: &'a Transcript<Arc<String>>,
+) -> Option<TrxPos<'a, Arc<String>>> {
     if splice_compatible(&trx.loc(), fp) {
         let pos = trx
             .loc()
@@ -306,11 +325,11 @@ mod tests {
     use bio_types::annot::refids::RefIDSet;
     use bio_types::annot::spliced::*;
 
-    fn fp(fp_str: &str) -> Spliced<Rc<String>, ReqStrand> {
+    fn fp(fp_str: &str) -> Spliced<Arc<String>, ReqStrand> {
         fp_str.parse().unwrap()
     }
 
-    fn pos(pos_str: &str) -> Pos<Rc<String>, ReqStrand> {
+    fn pos(pos_str: &str) -> Pos<Arc<String>, ReqStrand> {
         pos_str.parse().unwrap()
     }
 
@@ -322,13 +341,13 @@ mod tests {
             .expect("No record read")
     }
 
-    fn transcript_from_str(recstr: &str) -> Transcript<Rc<String>> {
+    fn transcript_from_str(recstr: &str) -> Transcript<Arc<String>> {
         let rec = record_from_str(recstr);
-        let mut refids: RefIDSet<Rc<String>> = RefIDSet::new();
+        let mut refids: RefIDSet<Arc<String>> = RefIDSet::new();
         Transcript::from_bed12(&rec, &mut refids).expect("Converting to transcript")
     }
 
-    fn transcriptome_from_str(bedstr: &str) -> Transcriptome<Rc<String>> {
+    fn transcriptome_from_str(bedstr: &str) -> Transcriptome<Arc<String>> {
         let mut refids = RefIDSet::new();
         Transcriptome::new_from_bed(bed::Reader::new(bedstr.as_bytes()).records(), &mut refids)
             .expect("Transcriptome from string")
@@ -347,8 +366,8 @@ mod tests {
         let rev_trx = transcript_from_str(&rev_str);
 
         fn into(
-            fp: &Spliced<Rc<String>, ReqStrand>,
-            trx: &Transcript<Rc<String>>,
+            fp: &Spliced<Arc<String>, ReqStrand>,
+            trx: &Transcript<Arc<String>>,
         ) -> Option<(String, usize)> {
             fp_into_transcript(fp, trx)
                 .map(|trxpos| (trxpos.transcript().trxname().to_string(), trxpos.pos()))
@@ -483,8 +502,8 @@ mod tests {
         assert_eq!(fwd_trx.cds_range(), &Some(24..378));
 
         // CDS body is (15, -15)
-        fn frame(fp_str: &str, trx: &Transcript<Rc<String>>) -> String {
-            let fp: Spliced<Rc<String>, ReqStrand> = fp_str.parse().expect("Error parsing fp");
+        fn frame(fp_str: &str, trx: &Transcript<Arc<String>>) -> String {
+            let fp: Spliced<Arc<String>, ReqStrand> = fp_str.parse().expect("Error parsing fp");
             let gfr = gene_framing(&(15, -15), &vec![trx], &fp);
             String::from_utf8(gfr.aux()).expect("Bad UTF8")
         }
@@ -560,7 +579,27 @@ mod tests {
         validate_framing(&rev_trx, 28, (15, -15));
     }
 
-    fn validate_framing(trx: &Transcript<Rc<String>>, fplen: isize, cdsbody: (isize, isize)) {
+    #[test]
+    fn footprint_classes() {
+        // Exons [87261..87387) and [87500..87822), CDS 87285..87752
+        let trxome = transcriptome_from_str(
+            "chr01\t87261\t87822\tYAL030W\t0\t+\t87285\t87752\t0\t2\t126,322,\t0,239,\n",
+        );
+
+        let class = |fp_str: &str| {
+            let ffr = footprint_framing(&trxome, &fp(fp_str), &(15, -15));
+            String::from_utf8(ffr.aux()).expect("Bad UTF8")
+        };
+
+        assert_eq!(class("chr01:87300-87328(+)"), "YAL030W/+15/-339/+0");
+        assert_eq!(class("chr01:87361-87388(+)"), "NoCompatible");
+        assert_eq!(class("chr01:87400-87428(+)"), "FpIntronic");
+        assert_eq!(class("chr01:87300-87328(-)"), "FpAntisense");
+        assert_eq!(class("chr01:87400-87428(-)"), "FpNoGene");
+        assert_eq!(class("chr01:90000-90028(+)"), "FpNoGene");
+    }
+
+    fn validate_framing(trx: &Transcript<Arc<String>>, fplen: isize, cdsbody: (isize, isize)) {
         for i in 0..(trx.loc().exon_total_length() as isize - fplen) {
             let trx_first = Pos::new(trx.trxname().clone(), i, ReqStrand::Forward);
             let chr_first = trx
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use failure;
+
+use fp_framing::framing::*;
+
+/// Number of equal-width bins along the CDS in the per-gene position
+/// profile.
+pub const CDS_BINS: usize = 5;
+
+/// Footprint counts for one gene, by reading frame and by position
+/// along the CDS.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeneFrameCounts {
+    pub frames: [usize; 3],
+    pub bins: [usize; CDS_BINS],
+}
+
+impl GeneFrameCounts {
+    /// Returns the total footprints counted on the gene.
+    pub fn total(&self) -> usize {
+        self.frames.iter().sum()
+    }
+}
+
+/// Reading frame and CDS position counts of footprints in the CDS body
+/// of each gene, for comparing framing between samples.
+#[derive(Debug, Clone, Default)]
+pub struct GeneFrames {
+    genes: HashMap<Arc<String>, GeneFrameCounts>,
+}
+
+impl GeneFrames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, gene: &str) -> Option<&GeneFrameCounts> {
+        self.genes.get(&gene.to_string())
+    }
+
+    /// Returns the gene names, sorted.
+    pub fn gene_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.genes.keys().map(|gene| gene.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    /// Counts a footprint framed within the CDS body of a gene. The CDS
+    /// position bin is found from the offsets to the start and stop
+    /// codons, so footprints whose offsets differ between isoforms are
+    /// not counted.
+    pub fn tally(&mut self, bam_frame: &BamFrameResult) {
+        if let BamFrameResult::Fp(FpFrameResult::Gene(GeneFrameResult::Good(gene_frame))) =
+            bam_frame
+        {
+            if let (Some(frame), Some(vs_start), Some(vs_end)) = (
+                gene_frame.frame(),
+                gene_frame.vs_cds_start(),
+                gene_frame.vs_cds_end(),
+            ) {
+                let cds_len = vs_start - vs_end;
+                if cds_len <= 0 {
+                    return;
+                }
+                let bin = (CDS_BINS as isize * vs_start / cds_len).max(0) as usize;
+
+                let counts = self
+                    .genes
+                    .entry(gene_frame.gene().clone())
+                    .or_insert_with(GeneFrameCounts::default);
+                counts.frames[frame % 3] += 1;
+                counts.bins[bin.min(CDS_BINS - 1)] += 1;
+            }
+        }
+    }
+
+    /// Adds the counts from another analysis, e.g., of a separate
+    /// chunk of alignments.
+    pub fn merge(&mut self, other: &GeneFrames) {
+        for (gene, other_counts) in other.genes.iter() {
+            let counts = self
+                .genes
+                .entry(gene.clone())
+                .or_insert_with(GeneFrameCounts::default);
+            for (ct, other_ct) in counts.frames.iter_mut().zip(other_counts.frames.iter()) {
+                *ct += other_ct;
+            }
+            for (ct, other_ct) in counts.bins.iter_mut().zip(other_counts.bins.iter()) {
+                *ct += other_ct;
+            }
+        }
+    }
+
+    /// Returns a table with one line per gene, sorted by name, of
+    /// footprint counts in each frame and each CDS position bin.
+    pub fn table(&self) -> String {
+        let mut table = "gene\tframe0\tframe1\tframe2".to_string();
+        for bin in 0..CDS_BINS {
+            table += &format!("\tcds_bin{}", bin + 1);
+        }
+        table += "\n";
+
+        let mut genes: Vec<(&Arc<String>, &GeneFrameCounts)> = self.genes.iter().collect();
+        genes.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        for (gene, counts) in genes {
+            table += gene;
+            for ct in counts.frames.iter().chain(counts.bins.iter()) {
+                table += &format!("\t{}", ct);
+            }
+            table += "\n";
+        }
+
+        table
+    }
+
+    /// Parses a table written by `table()`.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when the header or a line has the
+    /// wrong number of columns, or a count is not an integer.
+    pub fn from_table(table: &str) -> Result<Self, failure::Error> {
+        let ncols = 1 + 3 + CDS_BINS;
+        let mut lines = table.lines();
+
+        match lines.next() {
+            Some(header) if header.split('\t').count() == ncols => (),
+            _ => bail!("Expecting a gene framing table with {} columns", ncols),
+        }
+
+        let mut gene_frames = GeneFrames::new();
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != ncols {
+                bail!("Expecting {} columns in \"{}\"", ncols, line);
+            }
+
+            let mut counts = GeneFrameCounts::default();
+            for (i, field) in fields[1..].iter().enumerate() {
+                let ct = field
+                    .parse()
+                    .map_err(|err| format_err!("Bad count \"{}\": {}", field, err))?;
+                if i < 3 {
+                    counts.frames[i] = ct;
+                } else {
+                    counts.bins[i - 3] = ct;
+                }
+            }
+            gene_frames
+                .genes
+                .insert(Arc::new(fields[0].to_string()), counts);
+        }
+
+        Ok(gene_frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gene_frames_table() {
+        let table =
+            "gene\tframe0\tframe1\tframe2\tcds_bin1\tcds_bin2\tcds_bin3\tcds_bin4\tcds_bin5\n\
+                     GENE1\t10\t2\t3\t5\t4\t3\t2\t1\n\
+                     GENE2\t0\t1\t0\t0\t0\t1\t0\t0\n";
+        let gene_frames = GeneFrames::from_table(table).unwrap();
+        assert_eq!(gene_frames.gene_names(), vec!["GENE1", "GENE2"]);
+        assert_eq!(gene_frames.get("GENE1").unwrap().total(), 15);
+        assert_eq!(gene_frames.get("GENE2").unwrap().bins, [0, 0, 1, 0, 0]);
+        assert_eq!(gene_frames.table(), table);
+
+        let mut merged = gene_frames.clone();
+        merged.merge(&gene_frames);
+        assert_eq!(merged.get("GENE1").unwrap().frames, [20, 4, 6]);
+
+        assert!(GeneFrames::from_table("gene\tframe0\n").is_err());
+        assert!(GeneFrames::from_table(&table.replace("\t10\t", "\tten\t")).is_err());
+    }
+}
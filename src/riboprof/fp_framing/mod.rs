@@ -4,177 +4,581 @@ use std::fs;
 use std::io::Write;
 use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
-use std::str;
+use std::sync::Arc;
 
 use failure;
 
-use bio::io::bed;
 use bio_types::annot::refids::RefIDSet;
+use bio_types::annot::spliced::Spliced;
+use bio_types::strand::ReqStrand;
 use rust_htslib::bam;
 use rust_htslib::bam::Read as BamRead;
 
 use bam_utils::*;
+use cli_utils::*;
+use codon_assign::*;
+use corrupt::*;
+use output::*;
+use parallel::*;
+use timing::*;
 use transcript::*;
 
+mod collisions;
 mod framing;
+mod gene_frames;
+mod offsets;
 mod stats;
 
+use fp_framing::collisions::*;
 use fp_framing::framing::*;
-use fp_framing::stats::*;
+pub use fp_framing::gene_frames::*;
+use fp_framing::offsets::*;
+pub use fp_framing::stats::*;
 
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct CLI {
+    #[serde(skip_serializing)]
     pub input: String,
     pub output: String,
-    pub bed: String,
+    pub bed: Vec<String>,
     pub genes: Vec<String>,
     pub flanking: String,
     pub cdsbody: String,
     pub lengths: String,
     pub count_multi: bool,
     pub annotate: Option<String>,
+    pub write_asites: Option<String>,
+    pub offsets: String,
+    pub min_offset_count: String,
+    pub asites: Option<String>,
+    pub site: String,
+    pub frame_tables: bool,
+    pub gene_frames: bool,
+    pub clean_genes: bool,
+    pub disome_lengths: Option<String>,
+    pub disome_offsets: String,
+    pub threads: String,
+    pub io_threads: String,
+    #[serde(flatten)]
+    pub read_filter: ReadFilterCLI,
+    #[serde(flatten)]
+    pub output_config: OutputCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+impl Default for CLI {
+    fn default() -> Self {
+        CLI {
+            input: String::new(),
+            output: String::new(),
+            bed: Vec::new(),
+            genes: Vec::new(),
+            flanking: "-100,100".to_string(),
+            cdsbody: "34,31".to_string(),
+            lengths: "26,34".to_string(),
+            count_multi: false,
+            annotate: None,
+            write_asites: None,
+            offsets: "12,18".to_string(),
+            min_offset_count: "100".to_string(),
+            asites: None,
+            site: "P".to_string(),
+            frame_tables: false,
+            gene_frames: false,
+            clean_genes: false,
+            disome_lengths: None,
+            disome_offsets: "40,52".to_string(),
+            threads: "1".to_string(),
+            io_threads: "0".to_string(),
+            read_filter: ReadFilterCLI::default(),
+            output_config: OutputCLI::default(),
+            corrupt: CorruptInputs::default(),
+        }
+    }
 }
 
 pub struct Config {
     input: String,
     output: PathBuf,
-    trxome: Transcriptome<Rc<String>>,
+    trxome: Transcriptome<Arc<String>>,
     flanking: Range<isize>,
     cdsbody: (isize, isize),
     lengths: Range<usize>,
     count_multi: bool,
     annotate: Option<PathBuf>,
+    write_asites: Option<PathBuf>,
+    offsets: Range<usize>,
+    min_offset_count: usize,
+    site_offsets: Option<SiteOffsets>,
+    site: Site,
+    frame_tables: bool,
+    gene_frames: bool,
+    disome: Option<DisomeWindow>,
+    threads: usize,
+    io_threads: usize,
+    read_filter: ReadFilter,
+    output_config: OutputConfig,
+    corrupt: CorruptInputs,
 }
 
 impl Config {
     pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
         let trxome = Self::read_transcriptome(&cli)?;
 
-        let cdsbody_range = Self::parse_pair(&cli.cdsbody)?;
+        let cdsbody_range = parse_pair(&cli.cdsbody)?;
 
         Ok(Config {
             input: cli.input.to_string(),
             output: Path::new(&cli.output).to_path_buf(),
             trxome: trxome,
-            flanking: Self::parse_pair(&cli.flanking)?,
+            flanking: parse_pair(&cli.flanking)?,
             cdsbody: (cdsbody_range.start, cdsbody_range.end),
-            lengths: Self::parse_pair(&cli.lengths)?,
+            lengths: parse_pair(&cli.lengths)?,
             count_multi: cli.count_multi,
             annotate: cli
                 .annotate
                 .as_ref()
                 .map(|ann| Path::new(&ann).to_path_buf()),
+            write_asites: cli
+                .write_asites
+                .as_ref()
+                .map(|asites| Path::new(&asites).to_path_buf()),
+            offsets: parse_pair(&cli.offsets)?,
+            min_offset_count: cli.min_offset_count.parse()?,
+            site_offsets: match cli.asites {
+                Some(ref asites) => Some(SiteOffsets::new_from_file(asites)?),
+                None => None,
+            },
+            site: cli.site.parse()?,
+            frame_tables: cli.frame_tables,
+            gene_frames: cli.gene_frames,
+            disome: match cli.disome_lengths {
+                Some(ref lengths) => Some(DisomeWindow {
+                    lengths: parse_pair(lengths)?,
+                    offsets: parse_pair(&cli.disome_offsets)?,
+                }),
+                None => None,
+            },
+            threads: cli.threads.parse()?,
+            io_threads: cli.io_threads.parse()?,
+            read_filter: ReadFilter::new_from_cli(&cli.read_filter)?,
+            output_config: OutputConfig::new_from_cli(&cli.output_config)?,
+            corrupt: cli.corrupt.clone(),
         })
     }
 
     fn output_filename(&self, suffix: &str) -> PathBuf {
-        let mut name_base = self.output.file_name().map_or_else(
-            || "".to_string(),
-            |filename| filename.to_string_lossy().to_string(),
-        );
-        name_base += suffix;
-        let mut filepath = self.output.clone();
-        filepath.set_file_name(&name_base);
-        filepath
+        output_filename(&self.output, suffix)
     }
 
-    fn read_transcriptome(cli: &CLI) -> Result<Transcriptome<Rc<String>>, failure::Error> {
-        // ZZZ Handle Trx->Gene mappings
-        let mut refids = RefIDSet::new();
-        let mut trxome = Transcriptome::new();
-
-        for recres in bed::Reader::from_file(&cli.bed)?.records() {
-            let rec = recres?;
-            let trx = Transcript::from_bed12(&rec, &mut refids)?;
-            trxome.insert(trx)?;
-        }
+    fn new_site_metagenes(&self) -> Option<SiteMetagenes> {
+        self.site_offsets
+            .as_ref()
+            .map(|_| SiteMetagenes::new(self.site, &self.lengths, &self.flanking))
+    }
 
-        Ok(trxome)
+    fn disome_output(&self) -> PathBuf {
+        self.output_filename("_disome")
     }
 
-    fn parse_pair<I>(pair_str: &str) -> Result<Range<I>, failure::Error>
-    where
-        I: str::FromStr,
-        I::Err: Error + Send + Sized + Sync + 'static,
-    {
-        let strs: Vec<&str> = pair_str.split(",").collect();
-        if strs.len() == 2 {
-            Ok(Range {
-                start: strs[0].parse()?,
-                end: strs[1].parse()?,
-            })
+    fn read_transcriptome(cli: &CLI) -> Result<Transcriptome<Arc<String>>, failure::Error> {
+        // ZZZ Handle Trx->Gene mappings
+        let mut refids = RefIDSet::new();
+        let trxome = Transcriptome::new_from_files(&cli.bed, &mut refids)?;
+        Ok(if cli.clean_genes {
+            trxome.clean_genes()
         } else {
-            Err(failure::err_msg(format!(
-                "Expecting integer pair \"a,b\" but got \"{}\"",
-                pair_str
-            )))
-        }
+            trxome
+        })
     }
 }
 
+/// Length window for disome footprints, from two collided ribosomes,
+/// framed separately from monosome footprints.
+struct DisomeWindow {
+    lengths: Range<usize>,
+    offsets: Range<usize>,
+}
+
 pub fn run_fp_framing(config: Config) -> Result<(), failure::Error> {
-    let mut input = if config.input == "-" {
-        bam::Reader::from_stdin()?
-    } else {
-        bam::Reader::from_path(Path::new(&config.input))?
-    };
+    info!("{}", config.trxome.summary());
+
+    let mut input = open_bam(&config.input, &config.corrupt)?;
+    set_reader_threads(&mut input, config.io_threads)?;
 
     let tids = {
-        let mut refids: RefIDSet<Rc<String>> = RefIDSet::new();
+        let mut refids: RefIDSet<Arc<String>> = RefIDSet::new();
         Tids::new(&mut refids, input.header())
     };
+    tids.warn_missing(&config.trxome);
 
     // Open (empty) stats output file early to detect errors before processing data.
-    let mut stats_file = fs::File::create(&config.output_filename("_framing_stats.txt"))?;
+    let mut stats_file = config
+        .output_config
+        .create(&config.output_filename("_framing_stats.txt"))?;
 
     let mut annotate = match config.annotate {
         None => None,
         Some(ref annot_file) => {
-            let header = bam::Header::from_template(input.header());
-            Some(bam::Writer::from_path(Path::new(&annot_file), &header)?)
+            let header = output_header(input.header(), "fp-framing");
+            let mut writer = bam::Writer::from_path(Path::new(&annot_file), &header)?;
+            set_writer_threads(&mut writer, config.io_threads)?;
+            Some(writer)
         }
     };
 
     let mut framing_stats = FramingStats::new(&config.lengths, &config.flanking);
+    let mut disome_stats = config
+        .disome
+        .as_ref()
+        .map(|disome| FramingStats::new(&disome.lengths, &config.flanking));
+    let mut site_metagenes = config.new_site_metagenes();
+    let mut collisions = GeneCollisions::new();
+    let mut gene_frames = GeneFrames::new();
+    let mut filter_stats = ReadFilterStats::default();
+    let annotating = annotate.is_some();
+
+    let classify = StageTimer::new("classification");
+    Parallel::new(config.threads)?.map_batches(
+        spliced_records(&mut input, &tids, &config.corrupt),
+        |batch| frame_batch(&config, batch, annotating),
+        |framed| {
+            framing_stats.merge(&framed.framing)?;
+            filter_stats += framed.filter;
+            if let (Some(ref mut disome_stats), Some(ref disome)) =
+                (&mut disome_stats, &framed.disome)
+            {
+                disome_stats.merge(disome)?;
+            }
+            if let (Some(ref mut site_metagenes), Some(ref sites)) =
+                (&mut site_metagenes, &framed.sites)
+            {
+                site_metagenes.merge(sites);
+            }
+            collisions.merge(&framed.collisions);
+            gene_frames.merge(&framed.gene_frames);
+            if let Some(ref mut ann_writer) = &mut annotate {
+                for rec in framed.annotated.iter() {
+                    ann_writer.write(rec)?;
+                }
+            }
+            Ok(())
+        },
+    )?;
+    classify.finish();
+
+    let _timer = StageTimer::new("output");
+    write!(stats_file, "{}", framing_stats.align_stats().table())?;
+    stats_file.finish()?;
+    config.output_config.write(
+        config.output_filename("_filter_stats.txt"),
+        filter_stats.table(),
+    )?;
+    write_framing_tables(
+        &config.output,
+        &config.output_config,
+        &framing_stats,
+        config.frame_tables,
+    )?;
+    if let Some(ref site_metagenes) = site_metagenes {
+        write_site_tables(&config, site_metagenes)?;
+    }
+    if config.gene_frames {
+        config.output_config.write(
+            config.output_filename("_gene_frames.txt"),
+            gene_frames.table(),
+        )?;
+    }
+
+    if let Some(ref asites_file) = config.write_asites {
+        let estimates = estimate_offsets(
+            framing_stats.frame_length(),
+            framing_stats.around_start(),
+            &config.lengths,
+            &config.offsets,
+            config.min_offset_count,
+        );
+        config.output_config.write(
+            config.output_filename("_offsets.txt"),
+            offsets_table(&estimates),
+        )?;
+        fs::write(asites_file, offsets_file(&estimates))?;
+    }
+
+    if let (Some(disome), Some(disome_stats)) = (config.disome.as_ref(), disome_stats) {
+        write_disome(&config, disome, &disome_stats, &collisions)?;
+    }
 
-    for recres in input.records() {
-        let mut rec = recres?;
+    Ok(())
+}
+
+/// Writes the around-start and around-end tables with footprints
+/// counted at their ribosomal site, e.g., `_around_start_psite.txt`.
+fn write_site_tables(
+    config: &Config,
+    site_metagenes: &SiteMetagenes,
+) -> Result<(), failure::Error> {
+    let site_name = site_metagenes.site().to_string().to_lowercase();
+    config.output_config.write(
+        config.output_filename(&format!("_around_start_{}site.txt", site_name)),
+        site_metagenes.around_start_table(),
+    )?;
+    config.output_config.write(
+        config.output_filename(&format!("_around_end_{}site.txt", site_name)),
+        site_metagenes.around_end_table(),
+    )?;
+    Ok(())
+}
+
+/// Writes the framing tables and offset estimates for disome
+/// footprints, along with the per-gene comparison of disome and
+/// monosome density.
+fn write_disome(
+    config: &Config,
+    disome: &DisomeWindow,
+    disome_stats: &FramingStats,
+    collisions: &GeneCollisions,
+) -> Result<(), failure::Error> {
+    let disome_output = config.disome_output();
+    config.output_config.write(
+        output_filename(&disome_output, "_framing_stats.txt"),
+        disome_stats.align_stats().table(),
+    )?;
+    write_framing_tables(
+        &disome_output,
+        &config.output_config,
+        disome_stats,
+        config.frame_tables,
+    )?;
+
+    if config.write_asites.is_some() {
+        let estimates = estimate_offsets(
+            disome_stats.frame_length(),
+            disome_stats.around_start(),
+            &disome.lengths,
+            &disome.offsets,
+            config.min_offset_count,
+        );
+        config.output_config.write(
+            output_filename(&disome_output, "_offsets.txt"),
+            offsets_table(&estimates),
+        )?;
+        fs::write(
+            output_filename(&disome_output, "_asites.txt"),
+            offsets_file(&estimates),
+        )?;
+    }
+
+    config.output_config.write(
+        config.output_filename("_collisions.txt"),
+        collisions.table(&config.trxome),
+    )?;
+
+    let (mono, di) = collisions.totals();
+    info!(
+        "{} disome and {} monosome footprints in CDS bodies, ratio {:.4}",
+        di,
+        mono,
+        di as f64 / mono.max(1) as f64
+    );
+
+    Ok(())
+}
+
+/// Framing results for one batch of alignments, computed on a worker
+/// thread.
+struct FramedBatch {
+    framing: FramingStats,
+    disome: Option<FramingStats>,
+    sites: Option<SiteMetagenes>,
+    collisions: GeneCollisions,
+    gene_frames: GeneFrames,
+    filter: ReadFilterStats,
+    annotated: Vec<bam::Record>,
+}
+
+/// Filters and frames a batch of alignments, keeping the alignments
+/// with their framing annotation when `annotate` is set. With a disome
+/// window, alignments are also framed as disome footprints.
+fn frame_batch(
+    config: &Config,
+    batch: Vec<(bam::Record, Option<Spliced<Arc<String>, ReqStrand>>)>,
+    annotate: bool,
+) -> Result<FramedBatch, failure::Error> {
+    let mut framed = FramedBatch {
+        framing: FramingStats::new(&config.lengths, &config.flanking),
+        disome: config
+            .disome
+            .as_ref()
+            .map(|disome| FramingStats::new(&disome.lengths, &config.flanking)),
+        sites: config.new_site_metagenes(),
+        collisions: GeneCollisions::new(),
+        gene_frames: GeneFrames::new(),
+        filter: ReadFilterStats::default(),
+        annotated: Vec::new(),
+    };
+
+    for (mut rec, fp) in batch {
+        if !framed.filter.tally(&config.read_filter, &rec) {
+            if annotate {
+                set_aux_string(&mut rec, b"ZF", b"BamFiltered")?;
+                framed.annotated.push(rec);
+            }
+            continue;
+        }
+
+        if let (Some(disome), Some(disome_stats)) = (config.disome.as_ref(), framed.disome.as_mut())
+        {
+            let disome_res = record_framing(
+                &config.trxome,
+                &rec,
+                fp.clone(),
+                &disome.lengths,
+                &config.cdsbody,
+                config.count_multi,
+            )?;
+            disome_stats.tally_bam_frame(&disome_res);
+            framed.collisions.tally_disome(&disome_res);
+        }
 
         let res = record_framing(
             &config.trxome,
-            &tids,
             &rec,
+            fp,
             &config.lengths,
             &config.cdsbody,
             config.count_multi,
         )?;
 
-        framing_stats.tally_bam_frame(&res);
+        framed.framing.tally_bam_frame(&res);
+        if let (Some(offsets), Some(sites)) = (config.site_offsets.as_ref(), framed.sites.as_mut())
+        {
+            sites.tally_bam_frame(&res, offsets);
+        }
+        if config.disome.is_some() {
+            framed.collisions.tally_monosome(&res);
+        }
+        if config.gene_frames {
+            framed.gene_frames.tally(&res);
+        }
 
-        if let Some(ref mut ann_writer) = &mut annotate {
-            rec.push_aux(b"ZF", &bam::record::Aux::String(&res.aux()))?;
-            ann_writer.write(&rec)?;
+        if annotate {
+            set_aux_string(&mut rec, b"ZF", &res.aux())?;
+            framed.annotated.push(rec);
         }
     }
 
-    write!(stats_file, "{}", framing_stats.align_stats().table())?;
+    Ok(framed)
+}
 
-    fs::write(
-        config.output_filename("_frame_length.txt"),
+/// Counts footprints in each reading frame and CDS position bin of
+/// each gene, as written by `fp-framing --gene-frames`, directly from a
+/// BAM file.
+pub fn bam_gene_frames(
+    input: &str,
+    trxome: &Transcriptome<Arc<String>>,
+    lengths: &Range<usize>,
+    cdsbody: &(isize, isize),
+    read_filter: &ReadFilter,
+    corrupt: &CorruptInputs,
+) -> Result<GeneFrames, failure::Error> {
+    let mut reader = open_bam(input, corrupt)?;
+    let tids = {
+        let mut refids: RefIDSet<Arc<String>> = RefIDSet::new();
+        Tids::new(&mut refids, reader.header())
+    };
+    tids.warn_missing(trxome);
+
+    let _timer = StageTimer::new("classification");
+    let mut gene_frames = GeneFrames::new();
+    for recres in spliced_records(&mut reader, &tids, corrupt) {
+        let (rec, fp) = recres?;
+        if read_filter.rejection(&rec).is_none() {
+            let res = record_framing(trxome, &rec, fp, lengths, cdsbody, false)?;
+            gene_frames.tally(&res);
+        }
+    }
+
+    Ok(gene_frames)
+}
+
+/// Writes the framing tables, along with the statistics as JSON so
+/// they can be merged with those from other runs. The JSON is never
+/// compressed by `output_config`, so that its name stays fixed. When `frame_tables`
+/// is set, the around-start and around-end tables are also written in
+/// long format stratified by reading frame.
+fn write_framing_tables(
+    output: &Path,
+    output_config: &OutputConfig,
+    framing_stats: &FramingStats,
+    frame_tables: bool,
+) -> Result<(), failure::Error> {
+    output_config.write(
+        output_filename(output, "_frame_length.txt"),
         framing_stats.frame_length_table(),
     )?;
-    fs::write(
-        config.output_filename("_around_start.txt"),
+    output_config.write(
+        output_filename(output, "_around_start.txt"),
         framing_stats.around_start_table(),
     )?;
-    fs::write(
-        config.output_filename("_around_end.txt"),
+    output_config.write(
+        output_filename(output, "_around_end.txt"),
         framing_stats.around_end_table(),
     )?;
-
+    if frame_tables {
+        output_config.write(
+            output_filename(output, "_around_start_frame.txt"),
+            framing_stats.around_start_frame_table(),
+        )?;
+        output_config.write(
+            output_filename(output, "_around_end_frame.txt"),
+            framing_stats.around_end_frame_table(),
+        )?;
+    }
+    fs::write(
+        output_filename(output, "_framing_stats.json"),
+        framing_stats.to_json()?,
+    )?;
     Ok(())
 }
 
+/// Combines framing statistics saved as JSON by separate runs of
+/// `fp-framing`, e.g., on chunks of one BAM file or on several
+/// samples, and writes the merged statistics and framing tables.
+///
+/// # Arguments
+///
+/// * `inputs` are the `_framing_stats.json` files
+/// * `output` is the base filename for output files
+/// * `frame_tables` adds tables stratified by reading frame
+pub fn run_merge_framing(
+    inputs: &[String],
+    output: &str,
+    frame_tables: bool,
+) -> Result<(), failure::Error> {
+    let mut merged: Option<FramingStats> = None;
+
+    for input in inputs.iter() {
+        let stats = FramingStats::from_json(&read_text(input)?)?;
+        match merged {
+            Some(ref mut merged) => merged.merge(&stats)?,
+            None => merged = Some(stats),
+        }
+    }
+
+    let merged = merged.ok_or_else(|| failure::err_msg("No framing statistics to merge"))?;
+
+    let output = Path::new(output);
+    let output_config = OutputConfig::new();
+    output_config.write(
+        output_filename(output, "_framing_stats.txt"),
+        merged.align_stats().table(),
+    )?;
+    write_framing_tables(output, &output_config, &merged, frame_tables)
+}
+
 #[derive(Debug)]
 pub enum FpFramingError {
     BadArgument(String),
@@ -0,0 +1,188 @@
+use std::ops::Range;
+
+use metagene::*;
+
+/// Position of the A site relative to the start codon, for an
+/// initiating ribosome with the start codon in its P site.
+const INITIATION_A_SITE: isize = 3;
+
+/// A site offset chosen for one footprint length.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OffsetEstimate {
+    length: usize,
+    footprints: usize,
+    offset: Option<usize>,
+    frame0: f64,
+    initiation: usize,
+}
+
+impl OffsetEstimate {
+    pub fn length(&self) -> usize {
+        self.length
+    }
+    pub fn footprints(&self) -> usize {
+        self.footprints
+    }
+    /// Returns the chosen offset, or `None` when too few footprints
+    /// of this length were framed.
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
+    }
+    /// Returns the fraction of footprints whose A site lies in frame
+    /// 0 with the chosen offset.
+    pub fn frame0(&self) -> f64 {
+        self.frame0
+    }
+}
+
+/// Estimates the A site offset for each footprint length from
+/// framing statistics.
+///
+/// Each candidate offset places the A site of a footprint in frame 0
+/// when the 5' end of the footprint lies in the complementary
+/// frame. The candidate maximizing the fraction of footprints with
+/// their A site in frame 0 is chosen. Candidates differing by whole
+/// codons give the same framing, and these ties are broken by the
+/// number of footprints whose A site would lie on the codon following
+/// the start codon, i.e., initiating ribosomes.
+///
+/// # Arguments
+///
+/// * `frame_length` is the reading frame of footprint 5' ends by length
+/// * `around_start` is the position of footprint 5' ends relative to
+/// the start codon, by length
+/// * `lengths` is the inclusive range of lengths estimated
+/// * `candidates` is the inclusive range of candidate offsets
+/// * `min_count` is the minimum number of framed footprints needed
+/// to estimate an offset
+pub fn estimate_offsets(
+    frame_length: &LenProfile<Frame<usize>>,
+    around_start: &Metagene<LenProfile<usize>>,
+    lengths: &Range<usize>,
+    candidates: &Range<usize>,
+    min_count: usize,
+) -> Vec<OffsetEstimate> {
+    (lengths.start..(lengths.end + 1))
+        .map(|len| {
+            let frames = frame_length.get(len);
+            let footprints: usize = frames.iter().sum();
+
+            let best = if footprints > 0 && footprints >= min_count {
+                (candidates.start..(candidates.end + 1))
+                    .filter(|&offset| offset < len)
+                    .map(|offset| {
+                        let in_frame = *frames.get(-(offset as isize));
+                        let initiation = around_start
+                            .get(INITIATION_A_SITE - offset as isize)
+                            .map_or(0, |profile| *profile.get(len));
+                        (offset, in_frame, initiation)
+                    })
+                    .max_by(|(o1, f1, i1), (o2, f2, i2)| {
+                        (f1, i1).cmp(&(f2, i2)).then_with(|| o2.cmp(o1))
+                    })
+            } else {
+                None
+            };
+
+            match best {
+                Some((offset, in_frame, initiation)) => OffsetEstimate {
+                    length: len,
+                    footprints: footprints,
+                    offset: Some(offset),
+                    frame0: in_frame as f64 / footprints as f64,
+                    initiation: initiation,
+                },
+                None => OffsetEstimate {
+                    length: len,
+                    footprints: footprints,
+                    offset: None,
+                    frame0: 0.0,
+                    initiation: 0,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Returns the contents of an A site offsets file giving the chosen
+/// offset for each length with an estimate, in the format read by
+/// `SiteOffsets`.
+pub fn offsets_file(estimates: &[OffsetEstimate]) -> String {
+    let mut file = String::new();
+    for estimate in estimates.iter() {
+        if let Some(offset) = estimate.offset {
+            file += &format!("{}\t{}\n", estimate.length, offset);
+        }
+    }
+    file
+}
+
+/// Returns a table describing the offset estimate for each length,
+/// with the number of framed footprints, the chosen offset, the
+/// fraction of A sites in frame 0, and the number of footprints
+/// placing the A site on the codon after the start codon.
+pub fn offsets_table(estimates: &[OffsetEstimate]) -> String {
+    let mut table = "length\tcount\toffset\tframe0\tinitiation\n".to_string();
+    for estimate in estimates.iter() {
+        table += &match estimate.offset {
+            Some(offset) => format!(
+                "{}\t{}\t{}\t{:.04}\t{}\n",
+                estimate.length, estimate.footprints, offset, estimate.frame0, estimate.initiation
+            ),
+            None => format!("{}\t{}\tNA\tNA\tNA\n", estimate.length, estimate.footprints),
+        };
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use codon_assign::SiteOffsets;
+
+    fn framing() -> (LenProfile<Frame<usize>>, Metagene<LenProfile<usize>>) {
+        let mut frame_length = LenProfile::new(27, 29, Frame::new(0));
+        // 28mers mostly begin in frame 0, 29mers are too few
+        *frame_length.get_mut(28).get_mut(0_isize) = 85;
+        *frame_length.get_mut(28).get_mut(1_isize) = 10;
+        *frame_length.get_mut(28).get_mut(2_isize) = 5;
+        *frame_length.get_mut(29).get_mut(2_isize) = 3;
+
+        let mut around_start = Metagene::new(-20, 40, LenProfile::new(27, 29, 0));
+        // Initiating 28mers begin 12 nt upstream of the start codon
+        *around_start.get_mut(-12).unwrap().get_mut(28) = 20;
+        *around_start.get_mut(-9).unwrap().get_mut(28) = 5;
+
+        (frame_length, around_start)
+    }
+
+    #[test]
+    fn estimate_28mer() {
+        let (frame_length, around_start) = framing();
+        let estimates = estimate_offsets(&frame_length, &around_start, &(27..29), &(12..18), 10);
+        assert_eq!(estimates.len(), 3);
+
+        assert_eq!(estimates[0].offset(), None);
+        assert_eq!(estimates[1].length(), 28);
+        assert_eq!(estimates[1].footprints(), 100);
+        assert_eq!(estimates[1].offset(), Some(15));
+        assert_eq!(estimates[1].frame0(), 0.85);
+        assert_eq!(estimates[2].offset(), None);
+
+        let file = offsets_file(&estimates);
+        assert_eq!(file, "28\t15\n");
+        let offsets: SiteOffsets = file.parse().unwrap();
+        assert_eq!(offsets.offset(28), Some(15));
+    }
+
+    #[test]
+    fn estimate_without_initiation() {
+        let (frame_length, _) = framing();
+        let around_start = Metagene::new(-20, 40, LenProfile::new(27, 29, 0));
+        let estimates = estimate_offsets(&frame_length, &around_start, &(28..28), &(12..18), 10);
+
+        // Ties between whole-codon shifts favor the shortest offset
+        assert_eq!(estimates[0].offset(), Some(12));
+    }
+}
@@ -1,10 +1,16 @@
 use std::cmp::min;
-use std::ops::Range;
+use std::fmt;
+use std::ops::{AddAssign, Range};
 
+use failure;
+use serde_json;
+
+use codon_assign::*;
 use metagene::*;
 
 use fp_framing::framing::*;
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FramingStats {
     frame_length: LenProfile<Frame<usize>>,
     around_start: Metagene<LenProfile<usize>>,
@@ -45,6 +51,40 @@ impl FramingStats {
         &mut self.align_stats
     }
 
+    /// Adds the statistics from another analysis, e.g., of a separate
+    /// chunk of alignments or a separate sample.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when the two analyses used
+    /// different length ranges or flanking regions.
+    pub fn merge(&mut self, other: &FramingStats) -> Result<(), failure::Error> {
+        if self.frame_length.bins() != other.frame_length.bins() {
+            bail!("Cannot merge framing statistics with different length ranges");
+        }
+        if self.around_start.start() != other.around_start.start()
+            || self.around_start.len() != other.around_start.len()
+        {
+            bail!("Cannot merge framing statistics with different flanking regions");
+        }
+
+        self.frame_length += &other.frame_length;
+        self.around_start += &other.around_start;
+        self.around_end += &other.around_end;
+        self.align_stats += &other.align_stats;
+        Ok(())
+    }
+
+    /// Returns the statistics serialized as JSON.
+    pub fn to_json(&self) -> Result<String, failure::Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Reads statistics serialized as JSON by `to_json()`.
+    pub fn from_json(json: &str) -> Result<Self, failure::Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
     pub fn tally_frame_length(&mut self, frame: isize, fp_length: usize) {
         *self.frame_length.get_mut(fp_length).get_mut(frame) += 1
     }
@@ -81,11 +121,23 @@ impl FramingStats {
     }
 
     pub fn around_start_table(&self) -> String {
-        Self::metagene_table(&self.around_start)
+        len_metagene_table(&self.around_start)
     }
 
     pub fn around_end_table(&self) -> String {
-        Self::metagene_table(&self.around_end)
+        len_metagene_table(&self.around_end)
+    }
+
+    /// Returns the counts around the start codon as a long-format
+    /// table stratified by reading frame as well as length.
+    pub fn around_start_frame_table(&self) -> String {
+        len_frame_metagene_table(&self.around_start)
+    }
+
+    /// Returns the counts around the stop codon as a long-format
+    /// table stratified by reading frame as well as length.
+    pub fn around_end_frame_table(&self) -> String {
+        len_frame_metagene_table(&self.around_end)
     }
 
     pub fn frame_length_table(&self) -> String {
@@ -125,36 +177,78 @@ impl FramingStats {
 
         table
     }
+}
 
-    fn metagene_table(table: &Metagene<LenProfile<usize>>) -> String {
-        let mut pos_iter = table.pos_iter().peekable();
+/// Footprint counts around the start and stop codons by length, with
+/// each footprint counted at a ribosomal site, rather than its 5' end,
+/// using length-specific offsets. The profiles for different lengths
+/// then line up with one another on the site positions.
+#[derive(Debug, Clone)]
+pub struct SiteMetagenes {
+    site: Site,
+    around_start: Metagene<LenProfile<usize>>,
+    around_end: Metagene<LenProfile<usize>>,
+}
 
-        let mut table = "pos\tttl".to_string();
+impl SiteMetagenes {
+    pub fn new(site: Site, lengths: &Range<usize>, flanking: &Range<isize>) -> Self {
+        let framing = FramingStats::new(lengths, flanking);
+        SiteMetagenes {
+            site: site,
+            around_start: framing.around_start,
+            around_end: framing.around_end,
+        }
+    }
 
-        if let Some((_, len_profile)) = pos_iter.peek() {
-            for (len_str, _) in len_profile.named_iter() {
-                table += &format!("\t{}", len_str);
+    pub fn site(&self) -> Site {
+        self.site
+    }
+
+    /// Counts a framed footprint at its site, skipping footprints
+    /// whose length has no offset.
+    pub fn tally_bam_frame(&mut self, bam_frame: &BamFrameResult, offsets: &SiteOffsets) {
+        if let BamFrameResult::Fp(FpFrameResult::Gene(GeneFrameResult::Good(gene_frame))) =
+            bam_frame
+        {
+            let fp_length = gene_frame.fp_length();
+            let offset = match offsets.site_offset(fp_length, gene_frame.frame(), self.site) {
+                Some(offset) => offset as isize,
+                None => return,
+            };
+            if let Some(start_offset) = gene_frame.vs_cds_start() {
+                self.around_start
+                    .get_mut(start_offset + offset)
+                    .map(|vs_start| *vs_start.get_mut(fp_length) += 1);
+            }
+            if let Some(end_offset) = gene_frame.vs_cds_end() {
+                self.around_end
+                    .get_mut(end_offset + offset)
+                    .map(|vs_end| *vs_end.get_mut(fp_length) += 1);
             }
         }
+    }
 
-        table += "\n";
-
-        for (pos, len_profile) in pos_iter {
-            let pos_ttl = len_profile.iter().sum::<usize>();
+    pub fn merge(&mut self, other: &SiteMetagenes) {
+        self.around_start += &other.around_start;
+        self.around_end += &other.around_end;
+    }
 
-            table += &format!("{}\t{}", pos, pos_ttl);
-            for n_len in len_profile {
-                table += &format!("\t{}", n_len);
-            }
-            table += "\n";
-        }
+    pub fn around_start_table(&self) -> String {
+        len_metagene_table(&self.around_start)
+    }
 
-        table
+    pub fn around_end_table(&self) -> String {
+        len_metagene_table(&self.around_end)
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AnnotStats {
     no_gene: usize,
+    #[serde(default)]
+    antisense: usize,
+    #[serde(default)]
+    intronic: usize,
     noncoding: usize,
     noncoding_overlap: usize,
     multi_coding: usize,
@@ -168,6 +262,8 @@ impl AnnotStats {
     pub fn new() -> Self {
         AnnotStats {
             no_gene: 0,
+            antisense: 0,
+            intronic: 0,
             noncoding: 0,
             noncoding_overlap: 0,
             multi_coding: 0,
@@ -180,6 +276,12 @@ impl AnnotStats {
     pub fn no_gene(&self) -> usize {
         self.no_gene
     }
+    pub fn antisense(&self) -> usize {
+        self.antisense
+    }
+    pub fn intronic(&self) -> usize {
+        self.intronic
+    }
     pub fn noncoding(&self) -> usize {
         self.noncoding
     }
@@ -202,6 +304,8 @@ impl AnnotStats {
     pub fn tally_fp_frame(&mut self, fp_frame: &FpFrameResult) {
         match fp_frame {
             FpFrameResult::NoGene => self.no_gene += 1,
+            FpFrameResult::Antisense => self.antisense += 1,
+            FpFrameResult::Intronic => self.intronic += 1,
             FpFrameResult::NoncodingOnly => self.noncoding += 1,
             FpFrameResult::NoncodingOverlap => self.noncoding_overlap += 1,
             FpFrameResult::MultiCoding => self.multi_coding += 1,
@@ -213,6 +317,8 @@ impl AnnotStats {
 
     pub fn bad_total(&self) -> usize {
         self.no_gene
+            + self.antisense
+            + self.intronic
             + self.noncoding
             + self.noncoding_overlap
             + self.multi_coding
@@ -235,6 +341,18 @@ impl AnnotStats {
             self.no_gene() as f64 / align_ttl,
             self.no_gene() as f64 / ttl
         );
+        tbl += &format!(
+            "\tAntisense\t{}\t{:.4}\t{:.4}\n",
+            self.antisense(),
+            self.antisense() as f64 / align_ttl,
+            self.antisense() as f64 / ttl
+        );
+        tbl += &format!(
+            "\tIntronic\t{}\t{:.4}\t{:.4}\n",
+            self.intronic(),
+            self.intronic() as f64 / align_ttl,
+            self.intronic() as f64 / ttl
+        );
         tbl += &format!(
             "\tNoncodingOnly\t{}\t{:.4}\t{:.4}\n",
             self.noncoding(),
@@ -282,6 +400,21 @@ impl AnnotStats {
     }
 }
 
+impl<'a> AddAssign<&'a AnnotStats> for AnnotStats {
+    fn add_assign(&mut self, other: &'a AnnotStats) {
+        self.no_gene += other.no_gene;
+        self.antisense += other.antisense;
+        self.intronic += other.intronic;
+        self.noncoding += other.noncoding;
+        self.noncoding_overlap += other.noncoding_overlap;
+        self.multi_coding += other.multi_coding;
+        self.incompatible += other.incompatible;
+        self.ambig += other.ambig;
+        self.good += other.good;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AlignStats {
     unmapped: usize,
     short: usize,
@@ -337,6 +470,15 @@ impl AlignStats {
     }
 
     pub fn table(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// Writes the table of alignment fates, with the count and fraction of
+/// all alignments for each fate, followed by the annotation fates of
+/// the good alignments.
+impl fmt::Display for AlignStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut tbl = String::new();
 
         let ttl = self.total() as f64;
@@ -378,6 +520,16 @@ impl AlignStats {
         // Body = # counted in body framing analysis
         // Not mutually exclusive
 
-        tbl
+        write!(f, "{}", tbl)
+    }
+}
+
+impl<'a> AddAssign<&'a AlignStats> for AlignStats {
+    fn add_assign(&mut self, other: &'a AlignStats) {
+        self.unmapped += other.unmapped;
+        self.short += other.short;
+        self.long += other.long;
+        self.multi_hit += other.multi_hit;
+        self.annot_stats += &other.annot_stats;
     }
 }
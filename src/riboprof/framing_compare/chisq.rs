@@ -0,0 +1,170 @@
+use std::f64;
+
+/// Returns the chi-square statistic and degrees of freedom for a test
+/// of homogeneity between two samples of counts over the same
+/// categories, e.g., footprints in each reading frame. Categories with
+/// no counts in either sample are dropped, and `None` is returned when
+/// either sample is empty or fewer than two categories remain.
+///
+/// ```
+/// # use riboprof::framing_compare::*;
+/// let (chisq, df) = chi_square_homogeneity(&[30, 10, 10], &[10, 20, 20]).unwrap();
+/// assert!((chisq - 16.66667).abs() < 1e-5);
+/// assert_eq!(df, 2);
+/// ```
+pub fn chi_square_homogeneity(a: &[usize], b: &[usize]) -> Option<(f64, usize)> {
+    let total_a: usize = a.iter().sum();
+    let total_b: usize = b.iter().sum();
+    if total_a == 0 || total_b == 0 {
+        return None;
+    }
+    let total = (total_a + total_b) as f64;
+
+    let mut chisq = 0.0;
+    let mut categories = 0;
+    for (&ct_a, &ct_b) in a.iter().zip(b.iter()) {
+        let category = (ct_a + ct_b) as f64;
+        if category == 0.0 {
+            continue;
+        }
+        categories += 1;
+
+        for &(ct, sample_total) in [(ct_a, total_a), (ct_b, total_b)].iter() {
+            let expected = category * sample_total as f64 / total;
+            chisq += (ct as f64 - expected).powi(2) / expected;
+        }
+    }
+
+    if categories < 2 {
+        None
+    } else {
+        Some((chisq, categories - 1))
+    }
+}
+
+/// Returns the probability that a chi-square random variable with `df`
+/// degrees of freedom is at least `x`.
+pub fn chi_square_upper_tail(x: f64, df: usize) -> f64 {
+    upper_gamma_regularized(df as f64 / 2.0, x / 2.0)
+}
+
+/// Returns the Benjamini-Hochberg adjusted P-values (q-values), in the
+/// same order as `pvalues`.
+pub fn benjamini_hochberg(pvalues: &[f64]) -> Vec<f64> {
+    let n = pvalues.len();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| {
+        pvalues[j]
+            .partial_cmp(&pvalues[i])
+            .unwrap_or(::std::cmp::Ordering::Equal)
+    });
+
+    let mut qvalues = vec![1.0; n];
+    let mut running_min: f64 = 1.0;
+    for (rank_from_end, &i) in order.iter().enumerate() {
+        let rank = n - rank_from_end;
+        running_min = running_min.min(pvalues[i] * n as f64 / rank as f64);
+        qvalues[i] = running_min;
+    }
+    qvalues
+}
+
+/// Natural log of the gamma function, by the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut ser = 1.000000000190015;
+    let mut y = x;
+    for coeff in COEFFS.iter() {
+        y += 1.0;
+        ser += coeff / y;
+    }
+    -tmp + (2.5066282746310005 * ser / x).ln()
+}
+
+/// Regularized upper incomplete gamma function Q(a, x), from its
+/// series expansion for small `x` and its continued fraction
+/// otherwise.
+fn upper_gamma_regularized(a: f64, x: f64) -> f64 {
+    const MAX_ITER: usize = 1000;
+    const EPS: f64 = 1e-14;
+
+    if x <= 0.0 {
+        return 1.0;
+    }
+
+    let ln_prefactor = -x + a * x.ln() - ln_gamma(a);
+
+    if x < a + 1.0 {
+        let mut ap = a;
+        let mut del = 1.0 / a;
+        let mut sum = del;
+        for _ in 0..MAX_ITER {
+            ap += 1.0;
+            del *= x / ap;
+            sum += del;
+            if del.abs() < sum.abs() * EPS {
+                break;
+            }
+        }
+        (1.0 - sum * ln_prefactor.exp()).max(0.0)
+    } else {
+        let tiny = f64::MIN_POSITIVE / EPS;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..MAX_ITER {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let del = d * c;
+            h *= del;
+            if (del - 1.0).abs() < EPS {
+                break;
+            }
+        }
+        (ln_prefactor.exp() * h).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chi_square() {
+        assert!((chi_square_upper_tail(3.841459, 1) - 0.05).abs() < 1e-6);
+        assert!((chi_square_upper_tail(5.991465, 2) - 0.05).abs() < 1e-6);
+        assert!((chi_square_upper_tail(1.0, 4) - 0.9097960).abs() < 1e-6);
+        assert_eq!(chi_square_upper_tail(0.0, 2), 1.0);
+
+        assert_eq!(chi_square_homogeneity(&[5, 0, 0], &[0, 0, 0]), None);
+        assert_eq!(chi_square_homogeneity(&[5, 0, 0], &[3, 0, 0]), None);
+        let (chisq, df) = chi_square_homogeneity(&[10, 10, 0], &[10, 10, 0]).unwrap();
+        assert_eq!((chisq, df), (0.0, 1));
+
+        let qvalues = benjamini_hochberg(&[0.01, 0.04, 0.03, 0.5]);
+        let expected = [0.04, 0.04 * 4.0 / 3.0, 0.04 * 4.0 / 3.0, 0.5];
+        for (q, exp) in qvalues.iter().zip(expected.iter()) {
+            assert!((q - exp).abs() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use failure;
+
+use bio_types::annot::refids::RefIDSet;
+
+use bam_utils::*;
+use cli_utils::*;
+use corrupt::*;
+use fp_framing::*;
+use output::*;
+use timing::*;
+use transcript::*;
+
+mod chisq;
+
+pub use framing_compare::chisq::*;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CLI {
+    #[serde(skip_serializing)]
+    pub inputs: Vec<String>,
+    pub output: String,
+    pub bed: Option<String>,
+    pub cdsbody: String,
+    pub lengths: String,
+    pub min_count: String,
+    pub fdr: String,
+    #[serde(flatten)]
+    pub read_filter: ReadFilterCLI,
+    #[serde(flatten)]
+    pub output_config: OutputCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+pub struct Config {
+    inputs: Vec<String>,
+    output: PathBuf,
+    trxome: Option<Transcriptome<Arc<String>>>,
+    cdsbody: (isize, isize),
+    lengths: Range<usize>,
+    min_count: usize,
+    fdr: f64,
+    read_filter: ReadFilter,
+    output_config: OutputConfig,
+    corrupt: CorruptInputs,
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        if cli.inputs.len() != 2 {
+            bail!("Expecting exactly two inputs to compare");
+        }
+
+        let trxome = match cli.bed {
+            Some(ref bed) => {
+                let mut refids = RefIDSet::new();
+                Some(Transcriptome::new_from_file(bed, &mut refids)?)
+            }
+            None => None,
+        };
+
+        let cdsbody_range = parse_pair(&cli.cdsbody)?;
+
+        Ok(Config {
+            inputs: cli.inputs.clone(),
+            output: Path::new(&cli.output).to_path_buf(),
+            trxome: trxome,
+            cdsbody: (cdsbody_range.start, cdsbody_range.end),
+            lengths: parse_pair(&cli.lengths)?,
+            min_count: cli.min_count.parse()?,
+            fdr: cli.fdr.parse()?,
+            read_filter: ReadFilter::new_from_cli(&cli.read_filter)?,
+            output_config: OutputConfig::new_from_cli(&cli.output_config)?,
+            corrupt: cli.corrupt.clone(),
+        })
+    }
+}
+
+/// Comparison of the framing of one gene between two samples.
+#[derive(Debug, Clone)]
+pub struct GeneComparison {
+    gene: String,
+    counts: (GeneFrameCounts, GeneFrameCounts),
+    frame_test: Option<(f64, f64)>,
+    shape_test: Option<(f64, f64)>,
+    frame_qvalue: f64,
+    shape_qvalue: f64,
+}
+
+impl GeneComparison {
+    /// Tests for differences in the reading frame distribution and in
+    /// the distribution along the CDS between two samples. Each test
+    /// gives the chi-square statistic and its P-value, and is `None`
+    /// when the counts do not allow a test.
+    pub fn new(gene: &str, a: &GeneFrameCounts, b: &GeneFrameCounts) -> Self {
+        let test = |a: &[usize], b: &[usize]| {
+            chi_square_homogeneity(a, b)
+                .map(|(chisq, df)| (chisq, chi_square_upper_tail(chisq, df)))
+        };
+
+        GeneComparison {
+            gene: gene.to_string(),
+            counts: (a.clone(), b.clone()),
+            frame_test: test(&a.frames, &b.frames),
+            shape_test: test(&a.bins, &b.bins),
+            frame_qvalue: 1.0,
+            shape_qvalue: 1.0,
+        }
+    }
+
+    pub fn frame_pvalue(&self) -> f64 {
+        self.frame_test.map_or(1.0, |(_, pvalue)| pvalue)
+    }
+
+    pub fn shape_pvalue(&self) -> f64 {
+        self.shape_test.map_or(1.0, |(_, pvalue)| pvalue)
+    }
+
+    fn in_frame(counts: &GeneFrameCounts) -> f64 {
+        counts.frames[0] as f64 / counts.total() as f64
+    }
+}
+
+/// Compares every gene with at least `min_count` footprints in both
+/// samples, adding q-values for each test across genes.
+pub fn compare_genes(a: &GeneFrames, b: &GeneFrames, min_count: usize) -> Vec<GeneComparison> {
+    let genes: BTreeSet<&str> = a
+        .gene_names()
+        .into_iter()
+        .chain(b.gene_names().into_iter())
+        .collect();
+
+    let mut comparisons: Vec<GeneComparison> = genes
+        .into_iter()
+        .filter_map(|gene| match (a.get(gene), b.get(gene)) {
+            (Some(ct_a), Some(ct_b)) if ct_a.total() >= min_count && ct_b.total() >= min_count => {
+                Some(GeneComparison::new(gene, ct_a, ct_b))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let frame_pvalues: Vec<f64> = comparisons.iter().map(|c| c.frame_pvalue()).collect();
+    let shape_pvalues: Vec<f64> = comparisons.iter().map(|c| c.shape_pvalue()).collect();
+    let frame_qvalues = benjamini_hochberg(&frame_pvalues);
+    let shape_qvalues = benjamini_hochberg(&shape_pvalues);
+    for (i, comparison) in comparisons.iter_mut().enumerate() {
+        comparison.frame_qvalue = frame_qvalues[i];
+        comparison.shape_qvalue = shape_qvalues[i];
+    }
+
+    comparisons
+}
+
+/// Returns a table of gene comparisons, one gene per line, with
+/// columns labeled by the two sample names.
+pub fn comparison_table(comparisons: &[GeneComparison], names: (&str, &str), fdr: f64) -> String {
+    let (name_a, name_b) = names;
+    let mut table = format!(
+        "gene\tcount_{a}\tcount_{b}\tin_frame_{a}\tin_frame_{b}\t\
+         frame_chisq\tframe_pvalue\tframe_qvalue\t\
+         shape_chisq\tshape_pvalue\tshape_qvalue\tsignificant\n",
+        a = name_a,
+        b = name_b
+    );
+
+    let chisq =
+        |test: Option<(f64, f64)>| test.map_or("NA".to_string(), |(x, _)| format!("{:.3}", x));
+
+    for c in comparisons.iter() {
+        let significant = c.frame_qvalue <= fdr || c.shape_qvalue <= fdr;
+        table += &format!(
+            "{}\t{}\t{}\t{:.4}\t{:.4}\t{}\t{:.4e}\t{:.4e}\t{}\t{:.4e}\t{:.4e}\t{}\n",
+            c.gene,
+            c.counts.0.total(),
+            c.counts.1.total(),
+            GeneComparison::in_frame(&c.counts.0),
+            GeneComparison::in_frame(&c.counts.1),
+            chisq(c.frame_test),
+            c.frame_pvalue(),
+            c.frame_qvalue,
+            chisq(c.shape_test),
+            c.shape_pvalue(),
+            c.shape_qvalue,
+            if significant { "yes" } else { "no" }
+        );
+    }
+
+    table
+}
+
+/// Reads per-gene framing counts from a table written by
+/// `fp-framing --gene-frames` or, for a `.bam` file, by framing the
+/// alignments directly.
+fn read_gene_frames(config: &Config, input: &str) -> Result<GeneFrames, failure::Error> {
+    if input.ends_with(".bam") {
+        let trxome = config
+            .trxome
+            .as_ref()
+            .ok_or_else(|| format_err!("Annotation (--bed) needed to frame BAM file {}", input))?;
+        bam_gene_frames(
+            input,
+            trxome,
+            &config.lengths,
+            &config.cdsbody,
+            &config.read_filter,
+            &config.corrupt,
+        )
+    } else {
+        GeneFrames::from_table(&read_text(input)?)
+            .map_err(|err| format_err!("Reading gene framing table {}: {}", input, err))
+    }
+}
+
+pub fn run_framing_compare(config: Config) -> Result<(), failure::Error> {
+    let names = sample_names(&config.inputs);
+    let a = read_gene_frames(&config, &config.inputs[0])?;
+    let b = read_gene_frames(&config, &config.inputs[1])?;
+
+    let comparisons = timed("comparison", || compare_genes(&a, &b, config.min_count));
+    let significant = comparisons
+        .iter()
+        .filter(|c| c.frame_qvalue <= config.fdr || c.shape_qvalue <= config.fdr)
+        .count();
+    info!(
+        "{} of {} genes with at least {} footprints differ between {} and {}",
+        significant,
+        comparisons.len(),
+        config.min_count,
+        names[0],
+        names[1]
+    );
+
+    let _timer = StageTimer::new("output");
+    config.output_config.write(
+        output_filename(&config.output, "_framing_compare.txt"),
+        comparison_table(&comparisons, (&names[0], &names[1]), config.fdr),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_framing() {
+        let header =
+            "gene\tframe0\tframe1\tframe2\tcds_bin1\tcds_bin2\tcds_bin3\tcds_bin4\tcds_bin5\n";
+        let a = GeneFrames::from_table(&format!(
+            "{}SAME\t60\t20\t20\t20\t20\t20\t20\t20\n\
+             SHIFT\t80\t10\t10\t20\t20\t20\t20\t20\n\
+             FEW\t5\t0\t0\t1\t1\t1\t1\t1\n",
+            header
+        ))
+        .unwrap();
+        let b = GeneFrames::from_table(&format!(
+            "{}SAME\t60\t20\t20\t20\t20\t20\t20\t20\n\
+             SHIFT\t20\t70\t10\t20\t20\t20\t20\t20\n\
+             FEW\t0\t5\t0\t1\t1\t1\t1\t1\n",
+            header
+        ))
+        .unwrap();
+
+        let comparisons = compare_genes(&a, &b, 20);
+        assert_eq!(comparisons.len(), 2);
+        assert_eq!(comparisons[0].gene, "SAME");
+        assert_eq!(comparisons[0].frame_pvalue(), 1.0);
+        assert_eq!(comparisons[1].gene, "SHIFT");
+        assert!(comparisons[1].frame_qvalue < 1e-10);
+        assert_eq!(comparisons[1].shape_pvalue(), 1.0);
+
+        let table = comparison_table(&comparisons, ("wt", "mut"), 0.05);
+        assert!(table.starts_with("gene\tcount_wt\tcount_mut\t"));
+        assert!(table.ends_with("\tyes\n"));
+    }
+}
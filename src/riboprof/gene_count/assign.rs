@@ -0,0 +1,254 @@
+use std::hash::Hash;
+use std::str::FromStr;
+
+use failure;
+
+use bio_types::annot::loc::Loc;
+use bio_types::annot::pos::Pos;
+use bio_types::annot::spliced::Spliced;
+use bio_types::strand::ReqStrand;
+use rust_htslib::bam;
+
+use bam_utils::*;
+use transcript::*;
+
+/// Part of each gene that a footprint must overlap to be counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Coding sequence of a coding transcript
+    Cds,
+    /// Any exon of any transcript
+    Transcript,
+}
+
+impl Feature {
+    /// Returns `true` when the footprint overlaps this feature of the
+    /// transcript, on either strand.
+    pub fn overlaps<R: Clone + Eq>(&self, trx: &Transcript<R>, fp: &Spliced<R, ReqStrand>) -> bool {
+        match self {
+            Feature::Transcript => exon_overlap(trx.loc(), fp) > 0,
+            Feature::Cds => match *trx.cds_range() {
+                Some(ref cds) => fp.exon_contigs().iter().any(|contig| {
+                    (0..contig.length()).any(|offset| {
+                        let pos = Pos::new(
+                            contig.refid().clone(),
+                            contig.start() + offset as isize,
+                            ReqStrand::Forward,
+                        );
+                        trx.loc().pos_into(&pos).map_or(false, |trxpos| {
+                            trxpos.pos() >= cds.start as isize && trxpos.pos() < cds.end as isize
+                        })
+                    })
+                }),
+                None => false,
+            },
+        }
+    }
+
+    /// Returns the length of this feature of the transcript, which is
+    /// 0 for the CDS of a non-coding transcript.
+    pub fn length<R>(&self, trx: &Transcript<R>) -> usize {
+        match self {
+            Feature::Transcript => trx.loc().exon_total_length(),
+            Feature::Cds => trx
+                .cds_range()
+                .as_ref()
+                .map_or(0, |cds| cds.end - cds.start),
+        }
+    }
+}
+
+impl FromStr for Feature {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cds" => Ok(Feature::Cds),
+            "transcript" => Ok(Feature::Transcript),
+            _ => bail!("Unknown feature \"{}\", expecting cds or transcript", s),
+        }
+    }
+}
+
+/// Strand of the genes that a footprint is counted against, relative
+/// to the strand of its alignment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strandedness {
+    Forward,
+    Reverse,
+    Unstranded,
+}
+
+impl Strandedness {
+    /// Returns the gene strands that match an alignment on `strand`.
+    pub fn gene_strands(&self, strand: ReqStrand) -> Vec<ReqStrand> {
+        match self {
+            Strandedness::Forward => vec![strand],
+            Strandedness::Reverse => vec![-strand],
+            Strandedness::Unstranded => vec![ReqStrand::Forward, ReqStrand::Reverse],
+        }
+    }
+}
+
+impl FromStr for Strandedness {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "forward" => Ok(Strandedness::Forward),
+            "reverse" => Ok(Strandedness::Reverse),
+            "none" => Ok(Strandedness::Unstranded),
+            _ => bail!(
+                "Unknown strandedness \"{}\", expecting forward, reverse, or none",
+                s
+            ),
+        }
+    }
+}
+
+/// Treatment of reads aligned to more than one location.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiPolicy {
+    /// Count only reads with a single alignment
+    Unique,
+    /// Count each read once, at its first alignment (i.e., HI = 1)
+    Primary,
+    /// Count every alignment of a read with weight 1 / NH
+    Fractional,
+}
+
+impl MultiPolicy {
+    /// Returns the weight of one alignment, or `None` when it is not
+    /// counted.
+    pub fn weight(&self, rec: &bam::Record) -> Option<f64> {
+        if is_single_hit(rec) {
+            return Some(1.0);
+        }
+
+        match self {
+            MultiPolicy::Unique => None,
+            MultiPolicy::Primary => {
+                if is_first_hit(rec) {
+                    Some(1.0)
+                } else {
+                    None
+                }
+            }
+            MultiPolicy::Fractional => match rec.aux(b"NH") {
+                Some(bam::record::Aux::Integer(nh)) if nh > 0 => Some(1.0 / nh as f64),
+                _ => Some(1.0),
+            },
+        }
+    }
+}
+
+impl FromStr for MultiPolicy {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unique" => Ok(MultiPolicy::Unique),
+            "primary" => Ok(MultiPolicy::Primary),
+            "fraction" => Ok(MultiPolicy::Fractional),
+            _ => bail!(
+                "Unknown multi-mapping policy \"{}\", expecting unique, primary, or fraction",
+                s
+            ),
+        }
+    }
+}
+
+/// Gene assigned to a footprint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GeneAssignment<R> {
+    Gene(R),
+    NoFeature,
+    Ambiguous,
+}
+
+/// Assigns a footprint to the single gene whose `feature` it
+/// overlaps. Candidate genes are found with `genes_at_loc`, as in
+/// `fp-framing`, and footprints overlapping more than one gene are
+/// ambiguous.
+pub fn assign_gene<R>(
+    trxome: &Transcriptome<R>,
+    fp: &Spliced<R, ReqStrand>,
+    feature: Feature,
+    strandedness: Strandedness,
+) -> GeneAssignment<R>
+where
+    R: Clone + Eq + Hash,
+{
+    let mut genes = Vec::new();
+
+    for strand in strandedness.gene_strands(fp.strand()) {
+        for (gene, trxs) in trxome.genes_at_loc(fp, strand) {
+            if trxs.iter().any(|trx| feature.overlaps(trx, fp)) {
+                genes.push(gene.clone());
+            }
+        }
+    }
+
+    match genes.len() {
+        0 => GeneAssignment::NoFeature,
+        1 => GeneAssignment::Gene(genes.pop().unwrap()),
+        _ => GeneAssignment::Ambiguous,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use test_fixtures::coding_noncoding_trxome;
+
+    fn assign(loc_str: &str, feature: Feature, strandedness: Strandedness) -> String {
+        let trxome = coding_noncoding_trxome();
+        let fp: Spliced<Rc<String>, ReqStrand> = loc_str.parse().unwrap();
+        match assign_gene(&trxome, &fp, feature, strandedness) {
+            GeneAssignment::Gene(gene) => gene.to_string(),
+            GeneAssignment::NoFeature => "NoFeature".to_string(),
+            GeneAssignment::Ambiguous => "Ambiguous".to_string(),
+        }
+    }
+
+    #[test]
+    fn assign_cds() {
+        let fwd = Strandedness::Forward;
+        assert_eq!(assign("chr01:200-229(+)", Feature::Cds, fwd), "Coding");
+        assert_eq!(assign("chr01:110-139(+)", Feature::Cds, fwd), "NoFeature");
+        assert_eq!(assign("chr01:330-359(+)", Feature::Cds, fwd), "Coding");
+        assert_eq!(assign("chr01:700-729(-)", Feature::Cds, fwd), "RevCoding");
+    }
+
+    #[test]
+    fn assign_transcript() {
+        let fwd = Strandedness::Forward;
+        assert_eq!(
+            assign("chr01:110-139(+)", Feature::Transcript, fwd),
+            "Coding"
+        );
+        assert_eq!(
+            assign("chr01:330-359(+)", Feature::Transcript, fwd),
+            "Ambiguous"
+        );
+    }
+
+    #[test]
+    fn assign_strandedness() {
+        assert_eq!(
+            assign("chr01:200-229(-)", Feature::Cds, Strandedness::Forward),
+            "NoFeature"
+        );
+        assert_eq!(
+            assign("chr01:200-229(-)", Feature::Cds, Strandedness::Reverse),
+            "Coding"
+        );
+        assert_eq!(
+            assign("chr01:700-729(+)", Feature::Cds, Strandedness::Unstranded),
+            "RevCoding"
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use failure;
+
+use bio_types::annot::refids::RefIDSet;
+use bio_types::strand::ReqStrand;
+use rust_htslib::bam;
+use rust_htslib::bam::Read as BamRead;
+
+use bam_utils::*;
+use cli_utils::*;
+use corrupt::*;
+use normalization::*;
+use output::*;
+use timing::*;
+use transcript::*;
+use umi::*;
+
+mod assign;
+mod stats;
+
+use gene_count::assign::*;
+use gene_count::stats::*;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CLI {
+    #[serde(skip_serializing)]
+    pub inputs: Vec<String>,
+    pub output: String,
+    pub bed: Vec<String>,
+    pub feature: String,
+    pub multi: String,
+    pub strand: String,
+    pub umi: bool,
+    #[serde(flatten)]
+    pub read_filter: ReadFilterCLI,
+    #[serde(flatten)]
+    pub umi_config: UmiCLI,
+    #[serde(flatten)]
+    pub normalize_config: NormalizeCLI,
+    #[serde(flatten)]
+    pub output_config: OutputCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+pub struct Config {
+    inputs: Vec<String>,
+    output: PathBuf,
+    trxome: Transcriptome<Rc<String>>,
+    feature: Feature,
+    multi: MultiPolicy,
+    strandedness: Strandedness,
+    umi: Option<UmiConfig>,
+    read_filter: ReadFilter,
+    normalize: NormalizeConfig,
+    output_config: OutputConfig,
+    corrupt: CorruptInputs,
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        let mut refids = RefIDSet::new();
+        let trxome = Transcriptome::new_from_files(&cli.bed, &mut refids)?;
+
+        Ok(Config {
+            inputs: cli.inputs.clone(),
+            output: Path::new(&cli.output).to_path_buf(),
+            trxome: trxome,
+            feature: cli.feature.parse()?,
+            multi: cli.multi.parse()?,
+            strandedness: cli.strand.parse()?,
+            umi: if cli.umi {
+                Some(UmiConfig::new_from_cli(&cli.umi_config)?)
+            } else {
+                None
+            },
+            read_filter: ReadFilter::new_from_cli(&cli.read_filter)?,
+            normalize: NormalizeConfig::new_from_cli(&cli.normalize_config)?,
+            output_config: OutputConfig::new_from_cli(&cli.output_config)?,
+            corrupt: cli.corrupt.clone(),
+        })
+    }
+
+    fn output_filename(&self, suffix: &str) -> PathBuf {
+        output_filename(&self.output, suffix)
+    }
+}
+
+/// Gene counts and statistics for one input BAM file.
+struct SampleCounts {
+    name: String,
+    counts: HashMap<Rc<String>, f64>,
+    stats: GeneCountStats,
+    filter_stats: ReadFilterStats,
+}
+
+pub fn run_gene_count(config: Config) -> Result<(), failure::Error> {
+    info!("{}", config.trxome.summary());
+
+    let genes: BTreeSet<Rc<String>> = config
+        .trxome
+        .transcripts()
+        .map(|trx| trx.gene_ref().clone())
+        .collect();
+
+    let names = sample_names(&config.inputs);
+
+    let mut samples = Vec::new();
+    for (input, name) in config.inputs.iter().zip(names) {
+        let sample = count_sample(&config, input, name)?;
+        info!(
+            "{}: {} of {} aligned reads assigned to genes",
+            sample.name,
+            sample.stats.assigned,
+            sample.stats.assigned + sample.stats.no_feature + sample.stats.ambiguous
+        );
+
+        let filter_suffix = if config.inputs.len() > 1 {
+            format!("_{}_filter_stats.txt", sample.name)
+        } else {
+            "_filter_stats.txt".to_string()
+        };
+        config.output_config.write(
+            config.output_filename(&filter_suffix),
+            sample.filter_stats.table(),
+        )?;
+
+        samples.push(sample);
+    }
+
+    let _timer = StageTimer::new("output");
+    config.output_config.write(
+        config.output_filename("_gene_counts.txt"),
+        count_matrix(&genes, &samples),
+    )?;
+
+    if !config.normalize.is_raw() {
+        let lengths = gene_lengths(&config.trxome, config.feature);
+        config.output_config.write(
+            config.output_filename(&format!(
+                "_gene_counts{}.txt",
+                config.normalize.get_units().suffix()
+            )),
+            normalized_matrix(&genes, &samples, &config.normalize, &lengths),
+        )?;
+    }
+
+    let sample_stats: Vec<(&str, &GeneCountStats)> = samples
+        .iter()
+        .map(|sample| (sample.name.as_str(), &sample.stats))
+        .collect();
+    config.output_config.write(
+        config.output_filename("_gene_counts_summary.txt"),
+        stats_table(&sample_stats),
+    )?;
+
+    Ok(())
+}
+
+fn count_sample(
+    config: &Config,
+    input_name: &str,
+    name: String,
+) -> Result<SampleCounts, failure::Error> {
+    let mut input = open_bam(input_name, &config.corrupt)?;
+
+    let tids = {
+        let mut refids: RefIDSet<Rc<String>> = RefIDSet::new();
+        Tids::new(&mut refids, input.header())
+    };
+    tids.warn_missing(&config.trxome);
+
+    let _timer = StageTimer::new("classification");
+    let mut counts = HashMap::new();
+    let mut stats = GeneCountStats::new();
+    let mut filter_stats = ReadFilterStats::default();
+
+    // Genes and 5' end positions already counted for each UMI, when
+    // collapsing duplicates.
+    let mut umi_positions: HashSet<(Rc<String>, i32, isize, bool, Vec<u8>)> = HashSet::new();
+
+    for recres in spliced_records(&mut input, &tids, &config.corrupt) {
+        let (rec, fp) = recres?;
+
+        if !filter_stats.tally(&config.read_filter, &rec) {
+            continue;
+        }
+
+        let fp = match fp {
+            Some(fp) => fp,
+            None => {
+                stats.unaligned += 1;
+                continue;
+            }
+        };
+
+        let weight = match config.multi.weight(&rec) {
+            Some(weight) => weight,
+            None => {
+                stats.multi_hit += 1;
+                continue;
+            }
+        };
+
+        match assign_gene(&config.trxome, &fp, config.feature, config.strandedness) {
+            GeneAssignment::Gene(gene) => {
+                let umi = config
+                    .umi
+                    .as_ref()
+                    .and_then(|umi_config| umi_config.record_umi(&rec));
+                let is_new = match umi {
+                    Some(umi) => {
+                        let (pos, strand) = five_prime_pos(&rec);
+                        umi_positions.insert((
+                            gene.clone(),
+                            rec.tid(),
+                            pos,
+                            strand == ReqStrand::Forward,
+                            umi,
+                        ))
+                    }
+                    None => true,
+                };
+
+                if is_new {
+                    *counts.entry(gene).or_insert(0.0) += weight;
+                    stats.assigned += weight;
+                } else {
+                    stats.umi_duplicate += weight;
+                }
+            }
+            GeneAssignment::NoFeature => stats.no_feature += weight,
+            GeneAssignment::Ambiguous => stats.ambiguous += weight,
+        }
+    }
+
+    Ok(SampleCounts {
+        name: name,
+        counts: counts,
+        stats: stats,
+        filter_stats: filter_stats,
+    })
+}
+
+/// Returns a table of counts with one row per gene, including genes
+/// with no counts, and one column per sample.
+fn count_matrix(genes: &BTreeSet<Rc<String>>, samples: &[SampleCounts]) -> String {
+    let mut table = "gene".to_string();
+    for sample in samples.iter() {
+        table += &format!("\t{}", sample.name);
+    }
+    table += "\n";
+
+    for gene in genes.iter() {
+        table += gene;
+        for sample in samples.iter() {
+            table += &format!("\t{}", sample.counts.get(gene).unwrap_or(&0.0));
+        }
+        table += "\n";
+    }
+
+    table
+}
+
+/// Returns the length of each gene for RPKM normalization, as the
+/// longest `feature` among its transcripts.
+fn gene_lengths(
+    trxome: &Transcriptome<Rc<String>>,
+    feature: Feature,
+) -> HashMap<Rc<String>, usize> {
+    let mut lengths = HashMap::new();
+    for trx in trxome.transcripts() {
+        let length = lengths.entry(trx.gene_ref().clone()).or_insert(0);
+        *length = feature.length(trx).max(*length);
+    }
+    lengths
+}
+
+/// Returns a table of normalized counts with the layout of
+/// `count_matrix`, scaling the counts of each sample by its own
+/// library size.
+fn normalized_matrix(
+    genes: &BTreeSet<Rc<String>>,
+    samples: &[SampleCounts],
+    normalize: &NormalizeConfig,
+    lengths: &HashMap<Rc<String>, usize>,
+) -> String {
+    let mut table = "gene".to_string();
+    for sample in samples.iter() {
+        table += &format!("\t{}", sample.name);
+    }
+    table += "\n";
+
+    for gene in genes.iter() {
+        table += gene;
+        let length = lengths.get(gene).cloned().unwrap_or(0);
+        for sample in samples.iter() {
+            let count = sample.counts.get(gene).unwrap_or(&0.0);
+            let scale = normalize.scale(&sample.stats.library(), length);
+            table += &format!("\t{:.4}", count * scale);
+        }
+        table += "\n";
+    }
+
+    table
+}
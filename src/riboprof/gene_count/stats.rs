@@ -0,0 +1,65 @@
+use normalization::*;
+
+/// Fates of the alignments in one sample, in the style of the
+/// `featureCounts` summary. Fates of multi-mapping reads counted
+/// fractionally are weighted, so some counts are not integers.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GeneCountStats {
+    pub assigned: f64,
+    pub no_feature: f64,
+    pub ambiguous: f64,
+    pub umi_duplicate: f64,
+    pub multi_hit: usize,
+    pub unaligned: usize,
+}
+
+impl GeneCountStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the library size for normalization. Mapped reads are
+    /// those aligned and counted under the multi-mapping policy,
+    /// whether or not they were assigned to a gene.
+    pub fn library(&self) -> LibraryCounts {
+        LibraryCounts::new(
+            self.assigned + self.no_feature + self.ambiguous + self.umi_duplicate,
+            self.assigned,
+        )
+    }
+
+    /// Returns the label and value of each alignment fate.
+    pub fn fates(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Assigned", self.assigned.to_string()),
+            ("Unassigned_NoFeature", self.no_feature.to_string()),
+            ("Unassigned_Ambiguity", self.ambiguous.to_string()),
+            ("Unassigned_UMIDuplicate", self.umi_duplicate.to_string()),
+            ("Unassigned_MultiMapping", self.multi_hit.to_string()),
+            ("Unassigned_Unmapped", self.unaligned.to_string()),
+        ]
+    }
+}
+
+/// Returns a table of alignment fates, one per line, with one column
+/// per sample.
+pub fn stats_table(samples: &[(&str, &GeneCountStats)]) -> String {
+    let mut table = "Status".to_string();
+    for (name, _) in samples.iter() {
+        table += &format!("\t{}", name);
+    }
+    table += "\n";
+
+    let sample_fates: Vec<Vec<(&str, String)>> =
+        samples.iter().map(|(_, stats)| stats.fates()).collect();
+
+    for (i, (label, _)) in GeneCountStats::new().fates().iter().enumerate() {
+        table += label;
+        for fates in sample_fates.iter() {
+            table += &format!("\t{}", fates[i].1);
+        }
+        table += "\n";
+    }
+
+    table
+}
@@ -0,0 +1,71 @@
+use std::fs;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use failure;
+
+use bio_types::annot::refids::RefIDSet;
+use bio_types::strand::ReqStrand;
+
+use transcript::*;
+
+pub struct CLI {
+    pub bed: String,
+    pub output: String,
+}
+
+pub struct Config {
+    trxome: Transcriptome<Rc<String>>,
+    output: String,
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        let mut refids = RefIDSet::new();
+        let trxome = Transcriptome::new_from_file(&cli.bed, &mut refids)?;
+
+        Ok(Config {
+            trxome: trxome,
+            output: cli.output.to_string(),
+        })
+    }
+}
+
+/// Returns a table of overlapping gene pairs, one pair per line, with
+/// the two gene names, the strand, and the exonic overlap length.
+pub fn overlap_table(overlaps: &[GeneOverlap<Rc<String>>]) -> String {
+    let mut table = "gene_a\tgene_b\tstrand\toverlap\n".to_string();
+
+    for ov in overlaps {
+        table += &format!(
+            "{}\t{}\t{}\t{}\n",
+            ov.gene_a(),
+            ov.gene_b(),
+            match ov.strand() {
+                ReqStrand::Forward => "+",
+                ReqStrand::Reverse => "-",
+            },
+            ov.overlap()
+        );
+    }
+
+    table
+}
+
+pub fn run_gene_overlaps(config: Config) -> Result<(), failure::Error> {
+    info!("{}", config.trxome.summary());
+
+    let overlaps = config.trxome.overlapping_genes();
+
+    let mut output: Box<Write> = if config.output == "-" {
+        Box::new(io::stdout())
+    } else {
+        Box::new(fs::File::create(&config.output)?)
+    };
+
+    output.write_all(overlap_table(&overlaps).as_bytes())?;
+
+    info!("{} overlapping gene pairs", overlaps.len());
+
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+use failure;
+
+use bio::io::fasta;
+use bio_types::annot::loc::Loc;
+use bio_types::annot::spliced::Spliced;
+use bio_types::strand::*;
+
+use timing::*;
+
+/// Genome sequence held in memory, indexed by reference sequence name.
+pub struct Genome {
+    seqs: HashMap<String, Vec<u8>>,
+}
+
+impl Genome {
+    /// Reads a genome sequence from a FastA format file.
+    ///
+    /// # Arguments
+    ///
+    /// `path` specifies the path for the file to read
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when an `io::Error` arises
+    /// reading the file.
+    pub fn new_from_fasta<P: AsRef<Path>>(path: P) -> Result<Self, failure::Error> {
+        let _timer = StageTimer::new("genome load");
+        Self::new_from_records(fasta::Reader::from_file(path)?.records())
+    }
+
+    /// Collects a genome sequence from FastA records. Sequences are
+    /// converted to upper case.
+    pub fn new_from_records<I>(records: I) -> Result<Self, failure::Error>
+    where
+        I: Iterator<Item = io::Result<fasta::Record>>,
+    {
+        let mut seqs = HashMap::new();
+
+        for recres in records {
+            let rec = recres?;
+            seqs.insert(rec.id().to_owned(), rec.seq().to_ascii_uppercase());
+        }
+
+        Ok(Genome { seqs: seqs })
+    }
+
+    /// Returns the number of reference sequences
+    pub fn len(&self) -> usize {
+        self.seqs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seqs.is_empty()
+    }
+
+    /// Returns the sequence of a spliced location, reading 5' to 3'
+    /// on the strand of the location, or `None` if the reference
+    /// sequence is unknown or the location extends beyond its end.
+    pub fn spliced_seq<R>(&self, loc: &Spliced<R, ReqStrand>) -> Option<Vec<u8>>
+    where
+        R: Deref<Target = String> + Clone,
+    {
+        let refseq = self.seqs.get(loc.refid().deref())?;
+
+        let mut exons = loc.exon_contigs();
+        exons.sort_by_key(|exon| exon.start());
+
+        let mut seq = Vec::with_capacity(loc.exon_total_length());
+        for exon in exons {
+            if exon.start() < 0 {
+                return None;
+            }
+            let start = exon.start() as usize;
+            seq.extend_from_slice(refseq.get(start..(start + exon.length()))?);
+        }
+
+        if loc.strand() == ReqStrand::Reverse {
+            Some(reverse_complement(&seq))
+        } else {
+            Some(seq)
+        }
+    }
+}
+
+/// Returns the reverse complement of a nucleotide sequence. Bases
+/// other than `ACGT` are replaced by `N`.
+///
+/// ```
+/// # use riboprof::genome::reverse_complement;
+/// assert_eq!(reverse_complement(b"AACGTN"), b"NACGTT".to_vec());
+/// ```
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|nt| match nt {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' => b'A',
+            _ => b'N',
+        })
+        .collect()
+}
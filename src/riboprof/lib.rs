@@ -1,16 +1,77 @@
+//! Tools for ribosome profiling analysis.
+//!
+//! Parsers for annotation and sample description inputs return typed
+//! errors that callers can match on: `transcript::TrxError` for BED
+//! and GTF annotations, `codon_assign::ASiteParseError` for A site
+//! offsets, and `fastx_split::LinkerError` and
+//! `fastx_split::SampleError` for linkers and sample sheets. Tool
+//! configuration and the entry point of each tool, which combine
+//! these with I/O and BAM errors, return `failure::Error`.
+
+extern crate bzip2;
+extern crate clap;
+extern crate csv;
 #[macro_use]
 extern crate failure;
+extern crate flate2;
 extern crate itertools;
+#[macro_use]
+extern crate log;
+extern crate rand;
+extern crate rayon;
 extern crate regex;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate toml;
 
 extern crate bio;
 extern crate bio_types;
 extern crate rust_htslib;
 
+#[cfg(feature = "parquet")]
+extern crate parquet;
+
+#[cfg(test)]
+mod test_fixtures;
+
+pub mod bam_annotate_genes;
+pub mod bam_length_filter;
+pub mod bam_subsample;
 pub mod bam_suppress_duplicates;
+pub mod bam_to_track;
 pub mod bam_utils;
+pub mod bench;
+pub mod bootstrap;
+pub mod check;
+pub mod cli_utils;
 pub mod codon_assign;
+pub mod codon_count;
+pub mod config_file;
+pub mod contamination;
+pub mod corrupt;
+pub mod diagnostics;
 pub mod fastx_split;
 pub mod fp_framing;
+pub mod framing_compare;
+pub mod gene_count;
+pub mod gene_overlaps;
+pub mod genome;
+pub mod logging;
 pub mod metagene;
+pub mod metagene_count;
+pub mod normalization;
+pub mod orfs;
+pub mod output;
+pub mod parallel;
+pub mod pipeline;
+pub mod read_lengths;
+pub mod report;
+pub mod timing;
+pub mod track;
 pub mod transcript;
+pub mod transcriptome_seq;
+pub mod umi;
+pub mod uorfs;
@@ -0,0 +1,270 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use clap::{Arg, ArgMatches};
+use failure;
+use log::{self, Level, LevelFilter, Log, Metadata, Record};
+use serde_json;
+
+use corrupt::*;
+use diagnostics::*;
+use timing::*;
+
+/// Count of warnings and errors logged during the run, reported in the
+/// run summary.
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// File for the machine-readable error report, from `--error-json`.
+    static ERROR_JSON: RefCell<Option<PathBuf>> = RefCell::new(None);
+
+    /// File for the table of skipped corrupt inputs, from
+    /// `--error-summary`.
+    static ERROR_SUMMARY: RefCell<Option<PathBuf>> = RefCell::new(None);
+
+    /// Corrupt input handling given to the tool, whose count of
+    /// skipped inputs is reported in the run summary.
+    static RUN_CORRUPT: RefCell<CorruptInputs> = RefCell::new(CorruptInputs::default());
+}
+
+/// Returns the `--verbose`, `--quiet`, `--timing`, and `--error-json`
+/// arguments shared by all tools, along with `--max-errors`,
+/// `--skip-corrupt`, `--strict-eof`, and `--error-summary` for
+/// handling corrupt inputs.
+pub fn logging_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("verbose")
+            .short("v")
+            .long("verbose")
+            .help("Log more detail (repeat for even more)")
+            .multiple(true)
+            .conflicts_with("quiet"),
+        Arg::with_name("quiet")
+            .short("q")
+            .long("quiet")
+            .help("Log only warnings and errors"),
+        Arg::with_name("timing")
+            .long("timing")
+            .help("Report the time spent in each stage of the run at exit"),
+        Arg::with_name("error-json")
+            .long("error-json")
+            .value_name("ERROR.JSON")
+            .help("On failure, write the error and its category as JSON to this file")
+            .takes_value(true),
+        Arg::with_name("max-errors")
+            .long("max-errors")
+            .value_name("N")
+            .help("Skip up to N corrupt records or truncated inputs before failing")
+            .takes_value(true)
+            .conflicts_with("skip-corrupt"),
+        Arg::with_name("skip-corrupt")
+            .long("skip-corrupt")
+            .help("Skip every corrupt record or truncated input"),
+        Arg::with_name("strict-eof")
+            .long("strict-eof")
+            .help("Treat BAM files lacking the BGZF end-of-file marker as truncated"),
+        Arg::with_name("error-summary")
+            .long("error-summary")
+            .value_name("ERRORS.TXT")
+            .help("Write the number of corrupt inputs skipped to this file")
+            .takes_value(true),
+    ]
+}
+
+/// Returns the logging level selected by `logging_args`: progress
+/// messages by default, only warnings with `--quiet`, and debugging or
+/// trace messages with one or more `--verbose`.
+pub fn log_level(matches: &ArgMatches) -> LevelFilter {
+    if matches.is_present("quiet") {
+        LevelFilter::Warn
+    } else {
+        match matches.occurrences_of("verbose") {
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+}
+
+/// Logs to standard error, stamping each message with the time elapsed
+/// since logging started.
+struct Logger {
+    level: LevelFilter,
+    start: Instant,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= Level::Warn {
+            WARNINGS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if self.enabled(record.metadata()) {
+            let message = format!("{}", record.args());
+            let _ = writeln!(
+                io::stderr(),
+                "[{:>9.3}s] {:<5} {}",
+                seconds(self.start.elapsed()),
+                record.level(),
+                message.trim_end()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Starts logging at the level selected by the `logging_args`, and
+/// stage timing and error reports when requested. Returns the
+/// handling of corrupt inputs selected by the `logging_args`, to be
+/// passed to the readers of the tool.
+pub fn init_logging(matches: &ArgMatches) -> Result<CorruptInputs, failure::Error> {
+    if matches.is_present("timing") {
+        enable_timing();
+    }
+    if let Some(error_json) = matches.value_of("error-json") {
+        ERROR_JSON.with(|path| *path.borrow_mut() = Some(PathBuf::from(error_json)));
+    }
+    let max_errors = if matches.is_present("skip-corrupt") {
+        usize::max_value()
+    } else if let Some(max_errors) = matches.value_of("max-errors") {
+        max_errors.parse()?
+    } else {
+        0
+    };
+    let corrupt = CorruptInputs::new(max_errors).strict_eof(matches.is_present("strict-eof"));
+    RUN_CORRUPT.with(|run_corrupt| *run_corrupt.borrow_mut() = corrupt.clone());
+    if let Some(error_summary) = matches.value_of("error-summary") {
+        ERROR_SUMMARY.with(|path| *path.borrow_mut() = Some(PathBuf::from(error_summary)));
+    }
+
+    let level = log_level(matches);
+    log::set_boxed_logger(Box::new(Logger {
+        level: level,
+        start: Instant::now(),
+    }))
+    .map_err(|err| format_err!("Starting logging: {}", err))?;
+    log::set_max_level(LevelFilter::Trace);
+    Ok(corrupt)
+}
+
+/// Summary of one run of a tool, logged as a single line of JSON so
+/// that pipelines can parse the outcome of each step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub tool: String,
+    pub version: String,
+    pub status: String,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub error_code: Option<ErrorCode>,
+    pub elapsed_secs: f64,
+    pub warnings: usize,
+    #[serde(default)]
+    pub corrupt_skipped: usize,
+}
+
+impl RunSummary {
+    pub fn new(tool: &str, elapsed: Duration, result: &Result<(), failure::Error>) -> Self {
+        RunSummary {
+            tool: tool.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            status: if result.is_ok() { "ok" } else { "error" }.to_string(),
+            error: result.as_ref().err().map(|err| err.to_string()),
+            error_code: result.as_ref().err().map(ErrorCode::new_from_error),
+            elapsed_secs: seconds(elapsed),
+            warnings: WARNINGS.load(Ordering::Relaxed),
+            corrupt_skipped: RUN_CORRUPT.with(|corrupt| corrupt.borrow().total()),
+        }
+    }
+}
+
+/// Logs the run summary, prefixed by `summary`, at the end of a run,
+/// and writes the table of stage times to standard error when timing
+/// was requested. A failed run also writes its error report when
+/// `--error-json` was given. The count of corrupt inputs skipped is
+/// written when `--error-summary` was given. Nothing is logged when
+/// the run failed before logging started.
+pub fn log_summary(tool: &str, elapsed: Duration, result: &Result<(), failure::Error>) {
+    let summary = RunSummary::new(tool, elapsed, result);
+    if summary.corrupt_skipped > 0 {
+        warn!("Skipped {} corrupt inputs", summary.corrupt_skipped);
+    }
+    if let Some(error_summary) = ERROR_SUMMARY.with(|path| path.borrow().clone()) {
+        let table = RUN_CORRUPT.with(|corrupt| corrupt.borrow().table());
+        if let Err(write_err) = fs::write(&error_summary, table) {
+            error!(
+                "Writing error summary {}: {}",
+                error_summary.display(),
+                write_err
+            );
+        }
+    }
+
+    match serde_json::to_string(&summary) {
+        Ok(json) => info!("summary {}", json),
+        Err(err) => error!("Writing run summary: {}", err),
+    }
+
+    if let Err(ref err) = result {
+        if let Some(error_json) = ERROR_JSON.with(|path| path.borrow().clone()) {
+            if let Err(write_err) = ErrorReport::new(tool, err).write(&error_json) {
+                error!(
+                    "Writing error report {}: {}",
+                    error_json.display(),
+                    write_err
+                );
+            }
+        }
+    }
+
+    if let Some(table) = timing_table(elapsed) {
+        let _ = write!(io::stderr(), "{}", table);
+    }
+}
+
+fn seconds(elapsed: Duration) -> f64 {
+    elapsed.as_secs() as f64 + 1e-9 * elapsed.subsec_nanos() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::App;
+
+    fn level(args: &[&str]) -> LevelFilter {
+        let matches = App::new("test")
+            .args(&logging_args())
+            .get_matches_from(args);
+        log_level(&matches)
+    }
+
+    #[test]
+    fn verbosity() {
+        assert_eq!(level(&["test"]), LevelFilter::Info);
+        assert_eq!(level(&["test", "-q"]), LevelFilter::Warn);
+        assert_eq!(level(&["test", "-v"]), LevelFilter::Debug);
+        assert_eq!(level(&["test", "-vv"]), LevelFilter::Trace);
+
+        let summary = RunSummary::new(
+            "test",
+            Duration::from_millis(1500),
+            &Err(failure::err_msg("Bad input")),
+        );
+        assert_eq!(summary.status, "error");
+        assert_eq!(summary.error, Some("Bad input".to_string()));
+        assert_eq!(summary.error_code, Some(ErrorCode::Other));
+        assert_eq!(summary.elapsed_secs, 1.5);
+    }
+}
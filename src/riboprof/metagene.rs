@@ -1,47 +1,178 @@
+use std::collections::BTreeMap;
 use std::default::Default;
+use std::ops::AddAssign;
+use std::str::FromStr;
 //use std::error::Error;
-//use std::fmt;
+use std::fmt;
 use std::iter;
 use std::slice;
 use std::vec;
 
-//use failure;
+use failure;
 
-#[derive(Clone, Debug)]
+/// Grouping of footprint lengths into contiguous bins, such as one
+/// bin for each length or broader bins for monosome and disome
+/// footprints.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LenBins {
+    minlen: usize,
+    maxlen: usize,
+    starts: Vec<usize>,
+}
+
+impl LenBins {
+    /// Creates bins holding one length each, from `minlen` through
+    /// `maxlen` inclusive. There are no bins when `minlen` exceeds
+    /// `maxlen`.
+    pub fn new(minlen: usize, maxlen: usize) -> Self {
+        LenBins {
+            minlen: minlen,
+            maxlen: if minlen > maxlen { minlen - 1 } else { maxlen },
+            starts: (minlen..(maxlen + 1)).collect(),
+        }
+    }
+
+    /// Creates bins from inclusive length ranges, which must not
+    /// overlap. Lengths lying between two ranges are grouped into an
+    /// additional bin so that the bins cover every length from the
+    /// shortest through the longest.
+    ///
+    /// ```
+    /// # use riboprof::metagene::*;
+    /// let bins = LenBins::new_from_ranges(&[(25, 34), (19, 24), (50, 80)]).unwrap();
+    /// assert_eq!(bins.nbins(), 4);
+    /// assert_eq!(bins.bin(40), Some(2));
+    /// assert_eq!(bins.label(2), "35-49");
+    /// assert_eq!(bins.bin(81), None);
+    /// ```
+    pub fn new_from_ranges(ranges: &[(usize, usize)]) -> Result<Self, failure::Error> {
+        let mut ranges = ranges.to_vec();
+        ranges.sort();
+
+        let mut starts = Vec::new();
+        let mut next_len: Option<usize> = None;
+        for &(lo, hi) in ranges.iter() {
+            if lo > hi {
+                bail!("Bad length bin {}-{}", lo, hi);
+            }
+            match next_len {
+                Some(next) if lo < next => bail!("Length bin {}-{} overlaps another bin", lo, hi),
+                Some(next) if lo > next => starts.push(next),
+                _ => (),
+            }
+            starts.push(lo);
+            next_len = Some(hi + 1);
+        }
+
+        match (starts.first(), next_len) {
+            (Some(&minlen), Some(next)) => Ok(LenBins {
+                minlen: minlen,
+                maxlen: next - 1,
+                starts: starts,
+            }),
+            _ => bail!("No length bins"),
+        }
+    }
+
+    /// Returns the shortest length in a bin.
+    pub fn minlen(&self) -> usize {
+        self.minlen
+    }
+
+    /// Returns the longest length in a bin.
+    pub fn maxlen(&self) -> usize {
+        self.maxlen
+    }
+
+    pub fn nbins(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// Returns the bin holding a length, or `None` if the length lies
+    /// outside of every bin.
+    pub fn bin(&self, len: usize) -> Option<usize> {
+        if len < self.minlen || len > self.maxlen || self.starts.is_empty() {
+            None
+        } else {
+            match self.starts.binary_search(&len) {
+                Ok(i) => Some(i),
+                Err(i) => Some(i - 1),
+            }
+        }
+    }
+
+    /// Returns the inclusive range of lengths in a bin.
+    pub fn bin_range(&self, bin: usize) -> (usize, usize) {
+        let end = self
+            .starts
+            .get(bin + 1)
+            .map_or(self.maxlen, |next| next - 1);
+        (self.starts[bin], end)
+    }
+
+    /// Returns the label of a bin, either its single length or its
+    /// inclusive range of lengths, e.g., `19-24`.
+    pub fn label(&self, bin: usize) -> String {
+        match self.bin_range(bin) {
+            (lo, hi) if lo == hi => format!("{}", lo),
+            (lo, hi) => format!("{}-{}", lo, hi),
+        }
+    }
+}
+
+impl FromStr for LenBins {
+    type Err = failure::Error;
+
+    /// Parses comma-separated inclusive length ranges, such as
+    /// `19-24,25-34,50-80`, where a single length is a bin on its own.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ranges = s
+            .split(',')
+            .map(|range_str| -> Result<(usize, usize), failure::Error> {
+                let mut bounds = range_str.trim().splitn(2, '-');
+                let lo: usize = bounds.next().unwrap_or("").parse()?;
+                let hi: usize = match bounds.next() {
+                    Some(hi_str) => hi_str.parse()?,
+                    None => lo,
+                };
+                Ok((lo, hi))
+            })
+            .collect::<Result<Vec<(usize, usize)>, failure::Error>>()?;
+        Self::new_from_ranges(&ranges)
+    }
+}
+
+/// Values for each bin of footprint lengths, along with values for
+/// lengths shorter and longer than all bins.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LenProfile<T> {
     short: T,
     len_vec: Vec<T>,
     long: T,
-    minlen: usize,
+    bins: LenBins,
 }
 
 impl<T: Clone> LenProfile<T> {
     pub fn new(minlen: usize, maxlen: usize, initial: T) -> Self {
-        let nlen = if minlen > maxlen {
-            0
-        } else {
-            1 + maxlen - minlen
-        };
+        Self::new_binned(&LenBins::new(minlen, maxlen), initial)
+    }
 
+    pub fn new_binned(bins: &LenBins, initial: T) -> Self {
         LenProfile {
             short: initial.clone(),
-            len_vec: vec![initial.clone(); nlen],
+            len_vec: vec![initial.clone(); bins.nbins()],
             long: initial.clone(),
-            minlen: minlen,
+            bins: bins.clone(),
         }
     }
 }
 
 impl<T: Default> LenProfile<T> {
     pub fn new_with_default(minlen: usize, maxlen: usize) -> Self {
-        let nlen = if minlen > maxlen {
-            0
-        } else {
-            1 + maxlen - minlen
-        };
+        let bins = LenBins::new(minlen, maxlen);
 
         let mut len_vec = Vec::new();
-        for _i in 0..nlen {
+        for _i in 0..bins.nbins() {
             len_vec.push(Default::default());
         }
 
@@ -49,44 +180,60 @@ impl<T: Default> LenProfile<T> {
             short: Default::default(),
             len_vec: len_vec,
             long: Default::default(),
-            minlen: minlen,
+            bins: bins,
         }
     }
 }
 
 impl<T> LenProfile<T> {
+    /// Returns the shortest length tallied in a bin.
+    pub fn minlen(&self) -> usize {
+        self.bins.minlen()
+    }
+
+    /// Returns the longest length tallied in a bin, which is less
+    /// than `minlen()` when there are no bins.
+    pub fn maxlen(&self) -> isize {
+        self.bins.maxlen() as isize
+    }
+
+    pub fn bins(&self) -> &LenBins {
+        &self.bins
+    }
+
     pub fn get(&self, len: usize) -> &T {
-        if len < self.minlen {
+        if len < self.bins.minlen() {
             &self.short
         } else {
-            self.len_vec.get(len - self.minlen).unwrap_or(&self.long)
+            match self.bins.bin(len) {
+                Some(bin) => &self.len_vec[bin],
+                None => &self.long,
+            }
         }
     }
 
     pub fn get_mut(&mut self, len: usize) -> &mut T {
-        if len < self.minlen {
+        if len < self.bins.minlen() {
             &mut self.short
         } else {
-            self.len_vec
-                .get_mut(len - self.minlen)
-                .unwrap_or(&mut self.long)
+            match self.bins.bin(len) {
+                Some(bin) => &mut self.len_vec[bin],
+                None => &mut self.long,
+            }
         }
     }
 
     pub fn named_iter(&self) -> impl Iterator<Item = (String, &T)> {
-        let minlen = self.minlen;
+        let bins = &self.bins;
 
-        iter::once((format!("<{}", self.minlen), &self.short))
+        iter::once((format!("<{}", bins.minlen()), &self.short))
             .chain(
                 self.len_vec
                     .iter()
                     .enumerate()
-                    .map(move |(i, x)| (format!("{}", i + minlen), x)),
+                    .map(move |(i, x)| (bins.label(i), x)),
             )
-            .chain(iter::once((
-                format!("≥{}", self.minlen + self.len_vec.len()),
-                &self.long,
-            )))
+            .chain(iter::once((format!("≥{}", bins.maxlen() + 1), &self.long)))
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &T> {
@@ -94,6 +241,28 @@ impl<T> LenProfile<T> {
     }
 }
 
+/// Adds the tallies of another `LenProfile` into this one.
+///
+/// # Panics
+///
+/// Panics when the two have different length bins.
+impl<'a, T> AddAssign<&'a LenProfile<T>> for LenProfile<T>
+where
+    T: AddAssign<&'a T>,
+{
+    fn add_assign(&mut self, other: &'a LenProfile<T>) {
+        assert!(
+            self.bins == other.bins,
+            "Merging length profiles with different length bins"
+        );
+        self.short += &other.short;
+        for (x, y) in self.len_vec.iter_mut().zip(other.len_vec.iter()) {
+            *x += y;
+        }
+        self.long += &other.long;
+    }
+}
+
 impl<'a, T> IntoIterator for &'a LenProfile<T> {
     type Item = &'a T;
     type IntoIter =
@@ -117,41 +286,94 @@ impl<T> IntoIterator for LenProfile<T> {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct Frame<T> {
+/// Period of the reading frame, in nucleotides.
+pub const CODON_PERIOD: usize = 3;
+
+/// Values for each phase of a periodic position, such as the reading
+/// frame (period 3), dicodon phase (period 6), or helical phase
+/// (period 10). Positions are reduced modulo the period, so negative
+/// positions are handled consistently.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Periodic<T> {
     frames: Vec<T>,
 }
 
-impl<T: Clone> Frame<T> {
+/// Values for each reading frame, i.e., a period-3 `Periodic`.
+pub type Frame<T> = Periodic<T>;
+
+impl<T: Clone> Periodic<T> {
+    /// Creates a period-3 `Frame` with every frame holding a copy of
+    /// `initial`.
     pub fn new(initial: T) -> Self {
-        Frame {
-            frames: vec![initial.clone(), initial.clone(), initial],
+        Self::new_with_period(CODON_PERIOD, initial)
+    }
+
+    /// # Panics
+    ///
+    /// Panics when `period` is 0.
+    pub fn new_with_period(period: usize, initial: T) -> Self {
+        assert!(period > 0, "Periodic profile with period 0");
+        Periodic {
+            frames: vec![initial; period],
         }
     }
 }
 
-impl<T: Default> Frame<T> {
+impl<T: Default> Periodic<T> {
+    /// Creates a period-3 `Frame` with default values.
     pub fn new_with_default() -> Self {
-        Frame {
-            frames: vec![Default::default(), Default::default(), Default::default()],
+        Self::new_with_period_default(CODON_PERIOD)
+    }
+
+    /// # Panics
+    ///
+    /// Panics when `period` is 0.
+    pub fn new_with_period_default(period: usize) -> Self {
+        assert!(period > 0, "Periodic profile with period 0");
+        let mut frames = Vec::new();
+        for _ in 0..period {
+            frames.push(Default::default());
         }
+        Periodic { frames: frames }
     }
 }
 
-impl<T> Frame<T> {
+impl<T> Periodic<T> {
+    /// Returns the reading frame of a position, i.e., its phase with
+    /// period 3.
     pub fn to_frame<I>(i: I) -> usize
     where
         I: Into<isize>,
     {
-        (((i.into() % 3) + 3) % 3) as usize
+        Self::to_phase(i, CODON_PERIOD)
+    }
+
+    /// Returns the phase of a position with an arbitrary period.
+    ///
+    /// ```
+    /// # use riboprof::metagene::*;
+    /// assert_eq!(Periodic::<usize>::to_phase(13, 10), 3);
+    /// assert_eq!(Periodic::<usize>::to_phase(-1, 6), 5);
+    /// ```
+    pub fn to_phase<I>(i: I, period: usize) -> usize
+    where
+        I: Into<isize>,
+    {
+        let period = period as isize;
+        (((i.into() % period) + period) % period) as usize
+    }
+
+    pub fn period(&self) -> usize {
+        self.frames.len()
     }
 
     pub fn get<I: Into<isize>>(&self, pos: I) -> &T {
-        &self.frames[Self::to_frame(pos)]
+        &self.frames[Self::to_phase(pos, self.frames.len())]
     }
 
     pub fn get_mut<I: Into<isize>>(&mut self, pos: I) -> &mut T {
-        &mut self.frames[Self::to_frame(pos)]
+        let period = self.frames.len();
+        &mut self.frames[Self::to_phase(pos, period)]
     }
 
     pub fn frame_iter(&self) -> impl Iterator<Item = (isize, &T)> {
@@ -163,7 +385,27 @@ impl<T> Frame<T> {
     }
 }
 
-impl<'a, T> IntoIterator for &'a Frame<T> {
+/// Adds the tallies of another `Periodic` into this one.
+///
+/// # Panics
+///
+/// Panics when the two have different periods.
+impl<'a, T> AddAssign<&'a Periodic<T>> for Periodic<T>
+where
+    T: AddAssign<&'a T>,
+{
+    fn add_assign(&mut self, other: &'a Periodic<T>) {
+        assert!(
+            self.frames.len() == other.frames.len(),
+            "Merging periodic profiles with different periods"
+        );
+        for (x, y) in self.frames.iter_mut().zip(other.frames.iter()) {
+            *x += y;
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Periodic<T> {
     type Item = &'a T;
     type IntoIter = slice::Iter<'a, T>;
 
@@ -172,7 +414,7 @@ impl<'a, T> IntoIterator for &'a Frame<T> {
     }
 }
 
-impl<T> IntoIterator for Frame<T> {
+impl<T> IntoIterator for Periodic<T> {
     type Item = T;
     type IntoIter = vec::IntoIter<T>;
 
@@ -181,19 +423,76 @@ impl<T> IntoIterator for Frame<T> {
     }
 }
 
-#[derive(Clone, Debug)]
+/// Window lengths above which `Metagene::new()` uses sparse storage.
+pub const SPARSE_MIN_LEN: usize = 1000;
+
+/// Values at each position in a window surrounding an anchor.
+///
+/// Values are stored either densely, in a vector holding every
+/// position, or sparsely, holding only positions that have been
+/// modified through `get_mut()` and sharing the initial value for all
+/// others. The two behave identically apart from memory use, which
+/// makes sparse storage useful for wide windows where most positions
+/// are never tallied.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Metagene<T> {
-    pos_vec: Vec<T>,
+    store: PosStore<T>,
     start: isize,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum PosStore<T> {
+    Dense(Vec<T>),
+    Sparse {
+        len: usize,
+        initial: T,
+        entries: BTreeMap<usize, T>,
+    },
+}
+
 impl<T: Clone> Metagene<T> {
+    /// Creates a metagene with every position holding a copy of
+    /// `initial`, using sparse storage for windows longer than
+    /// `SPARSE_MIN_LEN`.
     pub fn new(start: isize, len: usize, initial: T) -> Self {
+        if len > SPARSE_MIN_LEN {
+            Self::new_sparse(start, len, initial)
+        } else {
+            Self::new_dense(start, len, initial)
+        }
+    }
+
+    pub fn new_dense(start: isize, len: usize, initial: T) -> Self {
+        Metagene {
+            store: PosStore::Dense(vec![initial; len]),
+            start: start,
+        }
+    }
+
+    pub fn new_sparse(start: isize, len: usize, initial: T) -> Self {
         Metagene {
-            pos_vec: vec![initial; len],
+            store: PosStore::Sparse {
+                len: len,
+                initial: initial,
+                entries: BTreeMap::new(),
+            },
             start: start,
         }
     }
+
+    /// Returns a mutable reference to the value at a position, or
+    /// `None` if the position lies outside the metagene.
+    pub fn get_mut(&mut self, pos: isize) -> Option<&mut T> {
+        let idx = self.index(pos)?;
+        match self.store {
+            PosStore::Dense(ref mut pos_vec) => pos_vec.get_mut(idx),
+            PosStore::Sparse {
+                ref initial,
+                ref mut entries,
+                ..
+            } => Some(entries.entry(idx).or_insert_with(|| initial.clone())),
+        }
+    }
 }
 
 impl<T: Default> Metagene<T> {
@@ -203,55 +502,650 @@ impl<T: Default> Metagene<T> {
             pos_vec.push(Default::default());
         }
         Metagene {
-            pos_vec: pos_vec,
+            store: PosStore::Dense(pos_vec),
             start: start,
         }
     }
 }
 
 impl<T> Metagene<T> {
+    /// Returns the first position in the metagene.
+    pub fn start(&self) -> isize {
+        self.start
+    }
+
+    /// Returns the number of positions in the metagene.
+    pub fn len(&self) -> usize {
+        match self.store {
+            PosStore::Dense(ref pos_vec) => pos_vec.len(),
+            PosStore::Sparse { len, .. } => len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true when the metagene uses sparse storage.
+    pub fn is_sparse(&self) -> bool {
+        match self.store {
+            PosStore::Dense(_) => false,
+            PosStore::Sparse { .. } => true,
+        }
+    }
+
     pub fn get(&self, pos: isize) -> Option<&T> {
-        if pos < self.start {
+        self.index(pos).map(|idx| self.get_index(idx))
+    }
+
+    fn index(&self, pos: isize) -> Option<usize> {
+        if pos < self.start || pos - self.start >= self.len() as isize {
             None
         } else {
-            self.pos_vec.get((pos - self.start) as usize)
+            Some((pos - self.start) as usize)
         }
     }
 
-    pub fn get_mut(&mut self, pos: isize) -> Option<&mut T> {
-        if pos < self.start {
-            None
-        } else {
-            self.pos_vec.get_mut((pos - self.start) as usize)
+    fn get_index(&self, idx: usize) -> &T {
+        match self.store {
+            PosStore::Dense(ref pos_vec) => &pos_vec[idx],
+            PosStore::Sparse {
+                ref initial,
+                ref entries,
+                ..
+            } => entries.get(&idx).unwrap_or(initial),
         }
     }
 
     pub fn pos_iter(&self) -> impl Iterator<Item = (isize, &T)> {
-        self.pos_vec
-            .iter()
+        self.iter()
             .enumerate()
             .map(move |(i, x)| (i as isize + self.start, x))
     }
 
+    pub fn iter(&self) -> Iter<T> {
+        Iter {
+            metagene: self,
+            idx: 0,
+        }
+    }
+}
+
+/// Adds the tallies of another `Metagene` into this one.
+///
+/// # Panics
+///
+/// Panics when the two have different position ranges.
+impl<'a, T> AddAssign<&'a Metagene<T>> for Metagene<T>
+where
+    T: Clone + AddAssign<&'a T>,
+{
+    fn add_assign(&mut self, other: &'a Metagene<T>) {
+        assert!(
+            self.start == other.start && self.len() == other.len(),
+            "Merging metagenes with different position ranges"
+        );
+        match self.store {
+            PosStore::Dense(ref mut pos_vec) => {
+                for (x, y) in pos_vec.iter_mut().zip(other.iter()) {
+                    *x += y;
+                }
+            }
+            PosStore::Sparse {
+                ref mut initial,
+                ref mut entries,
+                ..
+            } => {
+                // Positions stored by either metagene are stored in the
+                // sum, and the initial value accounts for all others
+                match other.store {
+                    PosStore::Dense(_) => {
+                        for idx in 0..other.len() {
+                            entries.entry(idx).or_insert_with(|| initial.clone());
+                        }
+                    }
+                    PosStore::Sparse {
+                        entries: ref other_entries,
+                        ..
+                    } => {
+                        for idx in other_entries.keys() {
+                            entries.entry(*idx).or_insert_with(|| initial.clone());
+                        }
+                    }
+                }
+                for (idx, x) in entries.iter_mut() {
+                    *x += other.get_index(*idx);
+                }
+                if let PosStore::Sparse {
+                    initial: ref other_initial,
+                    ..
+                } = other.store
+                {
+                    *initial += other_initial;
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the values at each position of a `Metagene`, in
+/// order, including positions not stored by a sparse metagene.
+pub struct Iter<'a, T: 'a> {
+    metagene: &'a Metagene<T>,
+    idx: usize,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.idx < self.metagene.len() {
+            self.idx += 1;
+            Some(self.metagene.get_index(self.idx - 1))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Metagene<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T: Clone> IntoIterator for Metagene<T> {
+    type Item = T;
+    type IntoIter = vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        match self.store {
+            PosStore::Dense(pos_vec) => pos_vec.into_iter(),
+            PosStore::Sparse {
+                len,
+                initial,
+                mut entries,
+            } => {
+                let pos_vec: Vec<T> = (0..len)
+                    .map(|idx| entries.remove(&idx).unwrap_or_else(|| initial.clone()))
+                    .collect();
+                pos_vec.into_iter()
+            }
+        }
+    }
+}
+
+/// Profile over a region of variable length, such as a CDS, divided
+/// into a fixed number of equal bins so that regions of different
+/// lengths can be combined on a common, scaled coordinate.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BinnedProfile<T> {
+    bin_vec: Vec<T>,
+}
+
+impl<T: Clone> BinnedProfile<T> {
+    pub fn new(nbins: usize, initial: T) -> Self {
+        BinnedProfile {
+            bin_vec: vec![initial; nbins],
+        }
+    }
+}
+
+impl<T: Default> BinnedProfile<T> {
+    pub fn new_with_default(nbins: usize) -> Self {
+        let mut bin_vec = Vec::new();
+        for _ in 0..nbins {
+            bin_vec.push(Default::default());
+        }
+        BinnedProfile { bin_vec: bin_vec }
+    }
+}
+
+impl<T> BinnedProfile<T> {
+    pub fn nbins(&self) -> usize {
+        self.bin_vec.len()
+    }
+
+    /// Returns the bin holding a position within a region, or `None`
+    /// if the position lies outside of the region.
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` is the offset of the position from the region start
+    /// * `len` is the length of the region
+    ///
+    /// ```
+    /// # use riboprof::metagene::*;
+    /// let profile: BinnedProfile<usize> = BinnedProfile::new_with_default(4);
+    /// assert_eq!(profile.bin(0, 10), Some(0));
+    /// assert_eq!(profile.bin(5, 10), Some(2));
+    /// assert_eq!(profile.bin(9, 10), Some(3));
+    /// assert_eq!(profile.bin(10, 10), None);
+    /// ```
+    pub fn bin(&self, pos: usize, len: usize) -> Option<usize> {
+        if pos < len {
+            Some(pos * self.bin_vec.len() / len)
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, bin: usize) -> Option<&T> {
+        self.bin_vec.get(bin)
+    }
+
+    pub fn get_mut(&mut self, bin: usize) -> Option<&mut T> {
+        self.bin_vec.get_mut(bin)
+    }
+
+    /// Returns the bin holding a position within a region, as with
+    /// `bin()`, or `None` if the position lies outside the region.
+    pub fn get_scaled_mut(&mut self, pos: usize, len: usize) -> Option<&mut T> {
+        match self.bin(pos, len) {
+            Some(bin) => self.bin_vec.get_mut(bin),
+            None => None,
+        }
+    }
+
+    pub fn bin_iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.bin_vec.iter().enumerate()
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &T> {
         self.into_iter()
     }
 }
 
-impl<'a, T> IntoIterator for &'a Metagene<T> {
+/// Adds the tallies of another `BinnedProfile` into this one.
+///
+/// # Panics
+///
+/// Panics when the two have different bin counts.
+impl<'a, T> AddAssign<&'a BinnedProfile<T>> for BinnedProfile<T>
+where
+    T: AddAssign<&'a T>,
+{
+    fn add_assign(&mut self, other: &'a BinnedProfile<T>) {
+        assert!(
+            self.bin_vec.len() == other.bin_vec.len(),
+            "Merging binned profiles with different bin counts"
+        );
+        for (x, y) in self.bin_vec.iter_mut().zip(other.bin_vec.iter()) {
+            *x += y;
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a BinnedProfile<T> {
     type Item = &'a T;
     type IntoIter = slice::Iter<'a, T>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.pos_vec.iter()
+        self.bin_vec.iter()
     }
 }
 
-impl<T> IntoIterator for Metagene<T> {
+impl<T> IntoIterator for BinnedProfile<T> {
     type Item = T;
     type IntoIter = vec::IntoIter<T>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.pos_vec.into_iter()
+        self.bin_vec.into_iter()
+    }
+}
+
+/// Returns a table of counts by position and footprint length, with
+/// one row for each position giving the total and the count for each
+/// length.
+pub fn len_metagene_table(table: &Metagene<LenProfile<usize>>) -> String {
+    let mut pos_iter = table.pos_iter().peekable();
+
+    let mut table = "pos\tttl".to_string();
+
+    if let Some((_, len_profile)) = pos_iter.peek() {
+        for (len_str, _) in len_profile.named_iter() {
+            table += &format!("\t{}", len_str);
+        }
+    }
+
+    table += "\n";
+
+    for (pos, len_profile) in pos_iter {
+        let pos_ttl = len_profile.iter().sum::<usize>();
+
+        table += &format!("{}\t{}", pos, pos_ttl);
+        for n_len in len_profile {
+            table += &format!("\t{}", n_len);
+        }
+        table += "\n";
+    }
+
+    table
+}
+
+/// Writes the table of counts by position and footprint length from
+/// `len_metagene_table()`.
+impl fmt::Display for Metagene<LenProfile<usize>> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", len_metagene_table(self))
+    }
+}
+
+/// Parses a table of counts by position and footprint length with
+/// `parse_len_metagene_table()`.
+impl FromStr for Metagene<LenProfile<usize>> {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_len_metagene_table(s)
+    }
+}
+
+/// Returns a table with the same layout as `len_metagene_table()`,
+/// with each count multiplied by a scaling factor, e.g., to give
+/// reads per million.
+pub fn scaled_len_metagene_table(table: &Metagene<LenProfile<usize>>, scale: f64) -> String {
+    let mut pos_iter = table.pos_iter().peekable();
+
+    let mut table = "pos\tttl".to_string();
+
+    if let Some((_, len_profile)) = pos_iter.peek() {
+        for (len_str, _) in len_profile.named_iter() {
+            table += &format!("\t{}", len_str);
+        }
+    }
+
+    table += "\n";
+
+    for (pos, len_profile) in pos_iter {
+        let pos_ttl = len_profile.iter().sum::<usize>();
+
+        table += &format!("{}\t{:.6}", pos, pos_ttl as f64 * scale);
+        for n_len in len_profile {
+            table += &format!("\t{:.6}", *n_len as f64 * scale);
+        }
+        table += "\n";
+    }
+
+    table
+}
+
+/// Returns a long-format table of counts by position, footprint
+/// length, and reading frame, with one row for each position and
+/// length. The frame is that of the footprint 5' end relative to the
+/// anchor, so that a footprint at position 0 or any whole number of
+/// codons away is in frame 0.
+pub fn len_frame_metagene_table(table: &Metagene<LenProfile<usize>>) -> String {
+    let mut long_table = "pos\tlength\tframe\tcount\n".to_string();
+
+    for (pos, len_profile) in table.pos_iter() {
+        let frame = Frame::<usize>::to_frame(pos);
+        for (len_str, n_len) in len_profile.named_iter() {
+            long_table += &format!("{}\t{}\t{}\t{}\n", pos, len_str, frame, n_len);
+        }
+    }
+
+    long_table
+}
+
+/// Parses a table of counts by position and footprint length, in
+/// the format written by `len_metagene_table()`.
+///
+/// # Errors
+///
+/// An error variant is returned when the table is empty, when the
+/// length columns are not a contiguous range, or when a row is
+/// malformed or its position is out of order.
+pub fn parse_len_metagene_table(
+    table: &str,
+) -> Result<Metagene<LenProfile<usize>>, failure::Error> {
+    let mut lines = table.lines();
+
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or_else(|| format_err!("Empty metagene table"))?
+        .split('\t')
+        .collect();
+    if header.len() < 4 || header[0] != "pos" || header[1] != "ttl" {
+        bail!("Bad metagene table header {:?}", header);
+    }
+    let minlen: usize = header[2].trim_left_matches('<').parse()?;
+    let long_len: usize = header[header.len() - 1].trim_left_matches('≥').parse()?;
+    let bins: LenBins = header[3..(header.len() - 1)].join(",").parse()?;
+    if bins.minlen() != minlen || bins.maxlen() + 1 != long_len {
+        bail!("Length bins do not match short and long lengths in metagene table");
+    }
+    for (bin, label) in header[3..(header.len() - 1)].iter().enumerate() {
+        if bins.label(bin) != *label {
+            bail!("Non-contiguous length {} in metagene table", label);
+        }
+    }
+
+    let mut start = None;
+    let mut pos_vec = Vec::new();
+
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != header.len() {
+            bail!("Bad metagene table line \"{}\"", line);
+        }
+
+        let pos: isize = fields[0].parse()?;
+        let expected = *start.get_or_insert(pos) + pos_vec.len() as isize;
+        if pos != expected {
+            bail!("Position {} out of order in metagene table", pos);
+        }
+
+        let mut len_profile = LenProfile::new_binned(&bins, 0);
+        len_profile.short = fields[2].parse()?;
+        for (ct, field) in len_profile.len_vec.iter_mut().zip(fields[3..].iter()) {
+            *ct = field.parse()?;
+        }
+        len_profile.long = fields[fields.len() - 1].parse()?;
+        pos_vec.push(len_profile);
+    }
+
+    Ok(Metagene {
+        store: PosStore::Dense(pos_vec),
+        start: start.unwrap_or(0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json;
+
+    fn metagene(cts: &[(isize, usize)]) -> Metagene<LenProfile<usize>> {
+        let mut metagene = Metagene::new(-2, 4, LenProfile::new(27, 29, 0));
+        for &(pos, len) in cts.iter() {
+            *metagene.get_mut(pos).unwrap().get_mut(len) += 1;
+        }
+        metagene
+    }
+
+    #[test]
+    fn merge_metagenes() {
+        let mut a = metagene(&[(-2, 27), (0, 28), (0, 28)]);
+        let b = metagene(&[(0, 28), (1, 35), (1, 20)]);
+        a += &b;
+
+        assert_eq!(*a.get(-2).unwrap().get(27), 1);
+        assert_eq!(*a.get(0).unwrap().get(28), 3);
+        assert_eq!(*a.get(1).unwrap().get(35), 1);
+        assert_eq!(*a.get(1).unwrap().get(20), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn merge_mismatched() {
+        let mut a = metagene(&[]);
+        a += &Metagene::new(-1, 4, LenProfile::new(27, 29, 0));
+    }
+
+    #[test]
+    fn merge_frames() {
+        let mut a = LenProfile::new(27, 29, Frame::new(0));
+        *a.get_mut(28).get_mut(1_isize) += 2;
+        let mut b = LenProfile::new(27, 29, Frame::new(0));
+        *b.get_mut(28).get_mut(1_isize) += 3;
+        *b.get_mut(28).get_mut(2_isize) += 1;
+        a += &b;
+
+        assert_eq!(*a.get(28).get(1_isize), 5);
+        assert_eq!(*a.get(28).get(2_isize), 1);
+        assert_eq!(a.minlen(), 27);
+        assert_eq!(a.maxlen(), 29);
+    }
+
+    #[test]
+    fn periodic_phases() {
+        let mut dicodon: Periodic<usize> = Periodic::new_with_period(6, 0);
+        for pos in [-7_isize, -1, 0, 5, 11, 12].iter() {
+            *dicodon.get_mut(*pos) += 1;
+        }
+        assert_eq!(dicodon.period(), 6);
+        assert_eq!(
+            dicodon.iter().cloned().collect::<Vec<usize>>(),
+            vec![2, 0, 0, 0, 0, 4]
+        );
+
+        let frame: Frame<usize> = Frame::new_with_default();
+        assert_eq!(frame.period(), 3);
+        assert_eq!(Frame::<usize>::to_frame(-4_isize), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn merge_mismatched_periods() {
+        let mut a: Periodic<usize> = Periodic::new_with_period(10, 0);
+        let b: Periodic<usize> = Periodic::new(0);
+        a += &b;
+    }
+
+    #[test]
+    fn metagene_table_round_trip() {
+        let a = metagene(&[(-2, 27), (0, 28), (0, 28), (1, 35), (1, 20)]);
+        let table = len_metagene_table(&a);
+        let b = parse_len_metagene_table(&table).unwrap();
+        assert_eq!(b.start(), -2);
+        assert_eq!(b.len(), 4);
+        assert_eq!(len_metagene_table(&b), table);
+        assert_eq!(a.to_string(), table);
+        let c: Metagene<LenProfile<usize>> = table.parse().unwrap();
+        assert_eq!(c.to_string(), table);
+    }
+
+    #[test]
+    fn len_bins() {
+        let bins: LenBins = "25-34, 19-24,50-80".parse().unwrap();
+        assert_eq!(bins.minlen(), 19);
+        assert_eq!(bins.maxlen(), 80);
+        assert_eq!(bins.nbins(), 4);
+        assert_eq!(bins.bin(18), None);
+        assert_eq!(bins.bin(24), Some(0));
+        assert_eq!(bins.bin(25), Some(1));
+        assert_eq!(bins.bin(49), Some(2));
+        assert_eq!(bins.bin(80), Some(3));
+        assert_eq!(bins.bin(81), None);
+        assert_eq!(bins.label(1), "25-34");
+
+        assert_eq!("28,29,30".parse::<LenBins>().unwrap(), LenBins::new(28, 30));
+        assert!("19-24,24-30".parse::<LenBins>().is_err());
+        assert!("24-19".parse::<LenBins>().is_err());
+        assert!("".parse::<LenBins>().is_err());
+    }
+
+    #[test]
+    fn binned_len_profile() {
+        let bins: LenBins = "19-24,25-34,50-80".parse().unwrap();
+        let mut profile = LenProfile::new_binned(&bins, 0);
+        for len in [15, 20, 28, 30, 40, 60, 90].iter() {
+            *profile.get_mut(*len) += 1;
+        }
+        assert_eq!(*profile.get(26), 2);
+        let named: Vec<(String, usize)> = profile
+            .named_iter()
+            .map(|(label, ct)| (label, *ct))
+            .collect();
+        assert_eq!(
+            named,
+            vec![
+                ("<19".to_string(), 1),
+                ("19-24".to_string(), 1),
+                ("25-34".to_string(), 2),
+                ("35-49".to_string(), 1),
+                ("50-80".to_string(), 1),
+                ("≥81".to_string(), 1),
+            ]
+        );
+
+        let mut a = Metagene::new(-1, 2, LenProfile::new_binned(&bins, 0));
+        *a.get_mut(0).unwrap().get_mut(30) += 3;
+        let table = len_metagene_table(&a);
+        assert_eq!(
+            table.lines().next(),
+            Some("pos\tttl\t<19\t19-24\t25-34\t35-49\t50-80\t≥81")
+        );
+        let b = parse_len_metagene_table(&table).unwrap();
+        assert_eq!(len_metagene_table(&b), table);
+    }
+
+    #[test]
+    fn sparse_metagenes() {
+        let cts = [(-2, 27), (0, 28), (0, 28), (1, 35), (1, 20)];
+        let dense = metagene(&cts);
+        let mut sparse = Metagene::new_sparse(-2, 4, LenProfile::new(27, 29, 0));
+        for &(pos, len) in cts.iter() {
+            *sparse.get_mut(pos).unwrap().get_mut(len) += 1;
+        }
+        assert!(sparse.is_sparse() && !dense.is_sparse());
+        assert!(sparse.get_mut(2).is_none());
+        assert_eq!(sparse.get(-1).map(|p| *p.get(27)), Some(0));
+        assert_eq!(len_metagene_table(&sparse), len_metagene_table(&dense));
+
+        let json = serde_json::to_string(&sparse).unwrap();
+        let parsed: Metagene<LenProfile<usize>> = serde_json::from_str(&json).unwrap();
+        assert!(parsed.is_sparse());
+        assert_eq!(len_metagene_table(&parsed), len_metagene_table(&dense));
+
+        let mut sparse_sum = sparse.clone();
+        sparse_sum += &sparse;
+        sparse_sum += &dense;
+        let mut dense_sum = dense.clone();
+        dense_sum += &sparse;
+        dense_sum += &dense;
+        assert_eq!(
+            len_metagene_table(&sparse_sum),
+            len_metagene_table(&dense_sum)
+        );
+        assert_eq!(sparse_sum.get(0).map(|p| *p.get(28)), Some(6));
+
+        assert!(Metagene::new(-1000, 2000, 0_usize).is_sparse());
+        assert!(!Metagene::new(-100, 200, 0_usize).is_sparse());
+    }
+
+    #[test]
+    fn metagene_frame_table() {
+        let a = metagene(&[(-2, 27), (0, 28), (0, 28), (1, 35)]);
+        let table = len_frame_metagene_table(&a);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 1 + 4 * 5);
+        assert_eq!(lines[0], "pos\tlength\tframe\tcount");
+        assert_eq!(lines[2], "-2\t27\t1\t1");
+        assert_eq!(lines[13], "0\t28\t0\t2");
+        assert_eq!(lines[20], "1\t≥30\t1\t1");
+    }
+
+    #[test]
+    fn metagene_json_round_trip() {
+        let a = metagene(&[(-2, 27), (0, 28), (1, 35)]);
+        let json = serde_json::to_string(&a).unwrap();
+        let b: Metagene<LenProfile<usize>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(len_metagene_table(&b), len_metagene_table(&a));
     }
 }
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
+use std::ops::{Deref, Range};
+use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use failure;
+
+use bio::io::bed;
+use bio_types::annot::loc::Loc;
+use bio_types::annot::pos::Pos;
+use bio_types::strand::*;
+
+use genome::*;
+use metagene_count::metacodon::*;
+use timing::*;
+use transcript::*;
+
+/// Annotated feature used as a metagene anchor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// First nucleotide of the start codon
+    Start,
+    /// First nucleotide of the stop codon
+    Stop,
+    /// First nucleotide of the transcript
+    Tss,
+    /// Last nucleotide of the transcript
+    Tes,
+}
+
+impl FromStr for Feature {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "start" => Ok(Feature::Start),
+            "stop" => Ok(Feature::Stop),
+            "tss" => Ok(Feature::Tss),
+            "tes" => Ok(Feature::Tes),
+            _ => Err(format_err!("Unknown anchor feature \"{}\"", s)),
+        }
+    }
+}
+
+/// Strand-specific single-nucleotide anchor positions, indexed for
+/// finding the anchors near a position. Each distinct anchor is
+/// numbered, from 0 up to the number of anchors, so that footprints
+/// can be attributed to individual anchors.
+pub struct Anchors {
+    fwd: HashMap<String, Vec<(isize, usize)>>,
+    rev: HashMap<String, Vec<(isize, usize)>>,
+}
+
+impl Anchors {
+    /// Collects anchors from genomic positions. Duplicate positions
+    /// are included only once. Anchors are numbered in order by
+    /// strand, reference, and position.
+    pub fn new<'a, R, I>(positions: I) -> Self
+    where
+        R: 'a + Deref<Target = String>,
+        I: Iterator<Item = &'a Pos<R, ReqStrand>>,
+    {
+        let mut fwd: HashMap<String, Vec<isize>> = HashMap::new();
+        let mut rev: HashMap<String, Vec<isize>> = HashMap::new();
+
+        for pos in positions {
+            let strand_anchors = match pos.strand() {
+                ReqStrand::Forward => &mut fwd,
+                ReqStrand::Reverse => &mut rev,
+            };
+            strand_anchors
+                .entry(pos.refid().deref().to_owned())
+                .or_insert_with(Vec::new)
+                .push(pos.pos());
+        }
+
+        let mut next_id = 0;
+        let mut number_anchors =
+            |strand_anchors: HashMap<String, Vec<isize>>| -> HashMap<String, Vec<(isize, usize)>> {
+                let mut refs: Vec<(String, Vec<isize>)> = strand_anchors.into_iter().collect();
+                refs.sort_by(|(r1, _), (r2, _)| r1.cmp(r2));
+
+                refs.into_iter()
+                    .map(|(refid, mut ref_anchors)| {
+                        ref_anchors.sort();
+                        ref_anchors.dedup();
+                        let numbered: Vec<(isize, usize)> = ref_anchors
+                            .into_iter()
+                            .map(|anchor| {
+                                next_id += 1;
+                                (anchor, next_id - 1)
+                            })
+                            .collect();
+                        (refid, numbered)
+                    })
+                    .collect()
+            };
+
+        let fwd = number_anchors(fwd);
+        let rev = number_anchors(rev);
+        Anchors { fwd: fwd, rev: rev }
+    }
+
+    /// Reads anchors from a BED file. The anchor is the first
+    /// nucleotide of each feature on its strand, i.e., the start of
+    /// a forward-strand feature and the last nucleotide of a
+    /// reverse-strand feature. Features with no strand give anchors
+    /// on both strands.
+    pub fn new_from_bed<P: AsRef<Path>>(path: P) -> Result<Self, failure::Error> {
+        let _timer = StageTimer::new("annotation load");
+        Self::new_from_bed_records(bed::Reader::from_file(path)?.records())
+    }
+
+    pub fn new_from_bed_records<B: io::Read>(
+        records: bed::Records<B>,
+    ) -> Result<Self, failure::Error> {
+        const STRAND_COL: usize = 5;
+
+        let mut positions: Vec<Pos<Rc<String>, ReqStrand>> = Vec::new();
+
+        for recres in records {
+            let rec = recres?;
+            let chrom = Rc::new(rec.chrom().to_owned());
+            let (start, last) = match (annot_pos(rec.start()), annot_pos(rec.end())) {
+                (Some(start), Some(end)) => (start, end - 1),
+                _ => bail!(
+                    "Anchor feature {}:{}-{} is beyond the largest supported position",
+                    rec.chrom(),
+                    rec.start(),
+                    rec.end()
+                ),
+            };
+
+            match rec.aux(STRAND_COL) {
+                Some("+") => positions.push(Pos::new(chrom.clone(), start, ReqStrand::Forward)),
+                Some("-") => positions.push(Pos::new(chrom.clone(), last, ReqStrand::Reverse)),
+                _ => {
+                    positions.push(Pos::new(chrom.clone(), start, ReqStrand::Forward));
+                    positions.push(Pos::new(chrom.clone(), last, ReqStrand::Reverse));
+                }
+            }
+        }
+
+        Ok(Self::new(positions.iter()))
+    }
+
+    /// Collects anchors at an annotated feature. Start and stop codon
+    /// anchors are taken from the longest-CDS transcript of each
+    /// coding gene, while transcript start and end anchors are taken
+    /// from every transcript.
+    pub fn new_from_transcriptome<R>(trxome: &Transcriptome<R>, feature: Feature) -> Self
+    where
+        R: Deref<Target = String> + Clone + Hash + Eq,
+    {
+        let trxs: Vec<&Transcript<R>> = match feature {
+            Feature::Start | Feature::Stop => trxome.longest_cds_transcripts(),
+            Feature::Tss | Feature::Tes => trxome.transcripts().collect(),
+        };
+
+        let positions: Vec<Pos<R, ReqStrand>> = trxs
+            .into_iter()
+            .filter_map(|trx| feature_pos(trx, feature))
+            .collect();
+
+        Self::new(positions.iter())
+    }
+
+    /// Collects anchors at the first nucleotide of every in-frame
+    /// occurrence of a codon motif in the longest-CDS transcript of
+    /// each coding gene, for metacodon profiles.
+    pub fn new_from_codons<R>(
+        trxome: &Transcriptome<R>,
+        genome: &Genome,
+        motifs: &CodonMotifs,
+    ) -> Self
+    where
+        R: Deref<Target = String> + Clone + Hash + Eq,
+    {
+        Self::new(motifs.positions(trxome, genome).iter())
+    }
+
+    /// Returns the total number of anchors.
+    pub fn len(&self) -> usize {
+        self.fwd
+            .values()
+            .chain(self.rev.values())
+            .map(|ref_anchors| ref_anchors.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of the anchor at a position, if any.
+    pub fn anchor_number<R>(&self, pos: &Pos<R, ReqStrand>) -> Option<usize>
+    where
+        R: Deref<Target = String>,
+    {
+        let strand_anchors = match pos.strand() {
+            ReqStrand::Forward => &self.fwd,
+            ReqStrand::Reverse => &self.rev,
+        };
+        let ref_anchors = strand_anchors.get(pos.refid().deref())?;
+        let i = ref_anchors
+            .binary_search_by_key(&pos.pos(), |&(anchor, _id)| anchor)
+            .ok()?;
+        Some(ref_anchors[i].1)
+    }
+
+    /// Returns the offset of a position relative to each anchor on
+    /// the same strand, for anchors where the offset lies within a
+    /// range. Offsets are measured along the strand, in genomic
+    /// coordinates, and are positive downstream of the anchor.
+    pub fn offsets<R>(&self, pos: &Pos<R, ReqStrand>, range: &Range<isize>) -> Vec<isize>
+    where
+        R: Deref<Target = String>,
+    {
+        self.anchor_offsets(pos, range)
+            .into_iter()
+            .map(|(_anchor, offset)| offset)
+            .collect()
+    }
+
+    /// Returns the number of each anchor near a position along with
+    /// the offset of the position relative to the anchor, as in
+    /// `offsets()`.
+    pub fn anchor_offsets<R>(
+        &self,
+        pos: &Pos<R, ReqStrand>,
+        range: &Range<isize>,
+    ) -> Vec<(usize, isize)>
+    where
+        R: Deref<Target = String>,
+    {
+        let (strand_anchors, lo, hi) = match pos.strand() {
+            ReqStrand::Forward => (
+                &self.fwd,
+                pos.pos() - range.end + 1,
+                pos.pos() - range.start + 1,
+            ),
+            ReqStrand::Reverse => (&self.rev, pos.pos() + range.start, pos.pos() + range.end),
+        };
+
+        let ref_anchors = match strand_anchors.get(pos.refid().deref()) {
+            Some(ref_anchors) => ref_anchors,
+            None => return Vec::new(),
+        };
+
+        let first = match ref_anchors.binary_search_by_key(&lo, |&(anchor, _id)| anchor) {
+            Ok(i) | Err(i) => i,
+        };
+
+        ref_anchors[first..]
+            .iter()
+            .take_while(|&&(anchor, _id)| anchor < hi)
+            .map(|&(anchor, id)| match pos.strand() {
+                ReqStrand::Forward => (id, pos.pos() - anchor),
+                ReqStrand::Reverse => (id, anchor - pos.pos()),
+            })
+            .collect()
+    }
+}
+
+fn feature_pos<R>(trx: &Transcript<R>, feature: Feature) -> Option<Pos<R, ReqStrand>>
+where
+    R: Clone,
+{
+    let trx_pos = match feature {
+        Feature::Start => trx.cds_range().as_ref()?.start,
+        Feature::Stop => trx.cds_range().as_ref()?.end.checked_sub(3)?,
+        Feature::Tss => 0,
+        Feature::Tes => trx.loc().length().checked_sub(1)?,
+    };
+
+    trx.loc()
+        .pos_outof(&Pos::new((), trx_pos as isize, ReqStrand::Forward))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bio_types::annot::refids::RefIDSet;
+
+    fn transcriptome_from_str(bedstr: &str) -> Transcriptome<Rc<String>> {
+        let mut refids = RefIDSet::new();
+        Transcriptome::new_from_bed(bed::Reader::new(bedstr.as_bytes()).records(), &mut refids)
+            .expect("Transcriptome from string")
+    }
+
+    fn pos(pos_str: &str) -> Pos<Rc<String>, ReqStrand> {
+        pos_str.parse().unwrap()
+    }
+
+    #[test]
+    fn anchor_offsets() {
+        let anchors = Anchors::new(
+            [
+                pos("chr1:1000(+)"),
+                pos("chr1:1050(+)"),
+                pos("chr1:2000(-)"),
+                pos("chr1:1000(+)"),
+            ]
+            .iter(),
+        );
+        assert_eq!(anchors.len(), 3);
+
+        let range = -20..60;
+        assert_eq!(anchors.offsets(&pos("chr1:990(+)"), &range), vec![-10]);
+        assert_eq!(anchors.offsets(&pos("chr1:1040(+)"), &range), vec![40, -10]);
+        assert_eq!(anchors.offsets(&pos("chr1:1059(+)"), &range), vec![59, 9]);
+        assert!(anchors.offsets(&pos("chr1:1000(-)"), &range).is_empty());
+        assert!(anchors.offsets(&pos("chr2:1000(+)"), &range).is_empty());
+
+        assert_eq!(anchors.offsets(&pos("chr1:1990(-)"), &range), vec![10]);
+        assert_eq!(anchors.offsets(&pos("chr1:2019(-)"), &range), vec![-19]);
+        assert!(anchors.offsets(&pos("chr1:2021(-)"), &range).is_empty());
+        assert!(anchors.offsets(&pos("chr1:1940(-)"), &range).is_empty());
+
+        assert_eq!(
+            anchors.anchor_offsets(&pos("chr1:1040(+)"), &range),
+            vec![(0, 40), (1, -10)]
+        );
+        assert_eq!(
+            anchors.anchor_offsets(&pos("chr1:1990(-)"), &range),
+            vec![(2, 10)]
+        );
+
+        assert_eq!(anchors.anchor_number(&pos("chr1:1050(+)")), Some(1));
+        assert_eq!(anchors.anchor_number(&pos("chr1:2000(-)")), Some(2));
+        assert_eq!(anchors.anchor_number(&pos("chr1:2000(+)")), None);
+    }
+
+    #[test]
+    fn bed_anchors() {
+        let bedstr =
+            "chr1\t100\t200\tfwd\t0\t+\nchr1\t300\t400\trev\t0\t-\nchr2\t50\t60\tboth\t0\t.\n";
+        let anchors =
+            Anchors::new_from_bed_records(bed::Reader::new(bedstr.as_bytes()).records()).unwrap();
+        assert_eq!(anchors.len(), 4);
+        assert_eq!(anchors.offsets(&pos("chr1:105(+)"), &(0..10)), vec![5]);
+        assert_eq!(anchors.offsets(&pos("chr1:395(-)"), &(0..10)), vec![4]);
+        assert_eq!(anchors.offsets(&pos("chr2:59(-)"), &(0..10)), vec![0]);
+    }
+
+    #[test]
+    fn feature_anchors() {
+        let bedstr = "chr01\t1000\t1300\tFwd\t0\t+\t1050\t1250\t0\t2\t100,100,\t0,200,\n\
+                      chr01\t2000\t2300\tRev\t0\t-\t2050\t2250\t0\t1\t300,\t0,\n";
+        let trxome = transcriptome_from_str(bedstr);
+
+        let starts = Anchors::new_from_transcriptome(&trxome, Feature::Start);
+        assert_eq!(starts.offsets(&pos("chr01:1040(+)"), &(-20..20)), vec![-10]);
+        assert_eq!(starts.offsets(&pos("chr01:2239(-)"), &(-20..20)), vec![10]);
+
+        let stops = Anchors::new_from_transcriptome(&trxome, Feature::Stop);
+        assert_eq!(stops.offsets(&pos("chr01:1247(+)"), &(-20..20)), vec![0]);
+        assert_eq!(stops.offsets(&pos("chr01:2052(-)"), &(-20..20)), vec![0]);
+
+        let tes = Anchors::new_from_transcriptome(&trxome, Feature::Tes);
+        assert_eq!(tes.offsets(&pos("chr01:1289(+)"), &(-20..20)), vec![-10]);
+    }
+}
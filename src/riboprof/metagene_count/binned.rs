@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use metagene::*;
+
+/// Profile of footprints by their position within the CDS of each gene.
+pub trait CdsProfile {
+    /// Returns the number of genes with at least one footprint.
+    fn genes(&self) -> usize;
+
+    /// Tallies a footprint in a gene.
+    ///
+    /// # Arguments
+    ///
+    /// * `gene` is the gene name
+    /// * `pos` is the offset of the footprint from the CDS start
+    /// * `cds_len` is the length of the CDS
+    fn tally(&mut self, gene: &Rc<String>, pos: usize, cds_len: usize);
+}
+
+/// Scaled gene-body profile, with the CDS of each gene divided into
+/// the same number of equal bins.
+pub struct GeneBodyProfile {
+    nbins: usize,
+    genes: HashMap<Rc<String>, BinnedProfile<usize>>,
+}
+
+impl GeneBodyProfile {
+    pub fn new(nbins: usize) -> Self {
+        GeneBodyProfile {
+            nbins: nbins,
+            genes: HashMap::new(),
+        }
+    }
+
+    /// Returns the total count in each bin, summed over all genes.
+    pub fn totals(&self) -> BinnedProfile<usize> {
+        let mut totals = BinnedProfile::new(self.nbins, 0);
+        for profile in self.genes.values() {
+            for (bin, ct) in profile.bin_iter() {
+                if let Some(total) = totals.get_mut(bin) {
+                    *total += ct;
+                }
+            }
+        }
+        totals
+    }
+
+    /// Returns the average density in each bin over all genes with
+    /// footprints, after normalizing the profile of each gene to an
+    /// average of 1 per bin so that every gene contributes equally.
+    pub fn normalized(&self) -> BinnedProfile<f64> {
+        let mut normalized = BinnedProfile::new(self.nbins, 0.0);
+
+        for profile in self.genes.values() {
+            let gene_total: usize = profile.iter().sum();
+            if gene_total == 0 {
+                continue;
+            }
+            let gene_mean = gene_total as f64 / self.nbins as f64;
+
+            for (bin, ct) in profile.bin_iter() {
+                if let Some(density) = normalized.get_mut(bin) {
+                    *density += *ct as f64 / gene_mean;
+                }
+            }
+        }
+
+        let ngenes = self.genes.len();
+        if ngenes > 0 {
+            for bin in 0..self.nbins {
+                if let Some(density) = normalized.get_mut(bin) {
+                    *density /= ngenes as f64;
+                }
+            }
+        }
+
+        normalized
+    }
+
+    /// Returns a table with one row for each bin, giving the
+    /// fractional position of the bin within the CDS, the total count,
+    /// and the average normalized density.
+    pub fn table(&self) -> String {
+        let mut table = "bin\tstart\tend\tcount\tnormalized\n".to_string();
+
+        let totals = self.totals();
+        let normalized = self.normalized();
+
+        for ((bin, ct), density) in totals.bin_iter().zip(normalized.iter()) {
+            table += &format!(
+                "{}\t{:.4}\t{:.4}\t{}\t{:.4}\n",
+                bin,
+                bin as f64 / self.nbins as f64,
+                (bin + 1) as f64 / self.nbins as f64,
+                ct,
+                density
+            );
+        }
+
+        table
+    }
+}
+
+impl CdsProfile for GeneBodyProfile {
+    fn genes(&self) -> usize {
+        self.genes.len()
+    }
+
+    fn tally(&mut self, gene: &Rc<String>, pos: usize, cds_len: usize) {
+        let nbins = self.nbins;
+        if let Some(ct) = self
+            .genes
+            .entry(gene.clone())
+            .or_insert_with(|| BinnedProfile::new(nbins, 0))
+            .get_scaled_mut(pos, cds_len)
+        {
+            *ct += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gene_body_bins() {
+        let gene_a = Rc::new("GeneA".to_string());
+        let gene_b = Rc::new("GeneB".to_string());
+
+        let mut profile = GeneBodyProfile::new(2);
+        for _ in 0..6 {
+            profile.tally(&gene_a, 10, 300);
+        }
+        for _ in 0..2 {
+            profile.tally(&gene_a, 200, 300);
+        }
+        profile.tally(&gene_b, 50, 60);
+        profile.tally(&gene_b, 60, 60);
+        assert_eq!(profile.genes(), 2);
+
+        let totals = profile.totals();
+        assert_eq!(totals.get(0), Some(&6));
+        assert_eq!(totals.get(1), Some(&3));
+
+        // GeneA contributes 1.5 and 0.5, GeneB contributes 0.0 and 2.0
+        let normalized = profile.normalized();
+        assert_eq!(normalized.get(0), Some(&0.75));
+        assert_eq!(normalized.get(1), Some(&1.25));
+
+        assert_eq!(
+            profile.table(),
+            "bin\tstart\tend\tcount\tnormalized\n\
+             0\t0.0000\t0.5000\t6\t0.7500\n\
+             1\t0.5000\t1.0000\t3\t1.2500\n"
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::hash::Hash;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use failure;
+
+use bio_types::annot::loc::Loc;
+use bio_types::annot::pos::Pos;
+use bio_types::strand::*;
+
+use genome::*;
+use transcript::*;
+
+/// Set of in-frame codon motifs, such as a codon or a dicodon, used
+/// as metacodon anchors. Each motif spans one or more whole codons,
+/// and `N` in a motif matches any nucleotide.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodonMotifs {
+    motifs: Vec<Vec<u8>>,
+}
+
+impl CodonMotifs {
+    /// Returns true when the sequence begins with one of the motifs.
+    pub fn matches(&self, seq: &[u8]) -> bool {
+        self.motifs.iter().any(|motif| {
+            seq.len() >= motif.len()
+                && motif
+                    .iter()
+                    .zip(seq.iter())
+                    .all(|(&m, &nt)| m == b'N' || m == nt)
+        })
+    }
+
+    /// Returns the genomic position of the first nucleotide of every
+    /// in-frame occurrence of a motif within a coding sequence. Only
+    /// the longest-CDS transcript of each coding gene is considered,
+    /// and transcripts whose sequence is not available are skipped.
+    pub fn positions<R>(&self, trxome: &Transcriptome<R>, genome: &Genome) -> Vec<Pos<R, ReqStrand>>
+    where
+        R: Deref<Target = String> + Clone + Hash + Eq,
+    {
+        let mut positions = Vec::new();
+
+        for trx in trxome.longest_cds_transcripts() {
+            let cds = match trx.cds_range() {
+                Some(ref cds) => cds.clone(),
+                None => continue,
+            };
+            let trx_seq = match genome.spliced_seq(trx.loc()) {
+                Some(trx_seq) => trx_seq,
+                None => continue,
+            };
+
+            let mut codon = cds.start + trx.cds_phase();
+            while codon + 3 <= cds.end {
+                if self.matches(&trx_seq[codon..cds.end]) {
+                    if let Some(pos) =
+                        trx.loc()
+                            .pos_outof(&Pos::new((), codon as isize, ReqStrand::Forward))
+                    {
+                        positions.push(pos);
+                    }
+                }
+                codon += 3;
+            }
+        }
+
+        positions
+    }
+}
+
+impl FromStr for CodonMotifs {
+    type Err = failure::Error;
+
+    /// Parses a comma-separated list of motifs, e.g., `CCNCCN` for
+    /// all Pro-Pro dicodons.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut motifs = Vec::new();
+
+        for motif_str in s.split(',') {
+            let motif: Vec<u8> = motif_str
+                .trim()
+                .to_ascii_uppercase()
+                .bytes()
+                .map(|nt| if nt == b'U' { b'T' } else { nt })
+                .collect();
+            if motif.is_empty() || motif.len() % 3 != 0 {
+                bail!(
+                    "Codon motif \"{}\" is not a whole number of codons",
+                    motif_str
+                );
+            }
+            if let Some(&nt) = motif.iter().find(|nt| !b"ACGTN".contains(nt)) {
+                bail!(
+                    "Codon motif \"{}\" contains bad nucleotide \'{}\'",
+                    motif_str,
+                    nt as char
+                );
+            }
+            motifs.push(motif);
+        }
+
+        Ok(CodonMotifs { motifs: motifs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use bio::io::{bed, fasta};
+    use bio_types::annot::refids::RefIDSet;
+
+    fn transcriptome_from_str(bedstr: &str) -> Transcriptome<Rc<String>> {
+        let mut refids = RefIDSet::new();
+        Transcriptome::new_from_bed(bed::Reader::new(bedstr.as_bytes()).records(), &mut refids)
+            .expect("Transcriptome from string")
+    }
+
+    #[test]
+    fn parse_motifs() {
+        let motifs: CodonMotifs = "ccncCN, AUG".parse().unwrap();
+        assert!(motifs.matches(b"CCACCG"));
+        assert!(motifs.matches(b"ATGAAA"));
+        assert!(!motifs.matches(b"CCACAG"));
+        assert!(!motifs.matches(b"CCA"));
+
+        assert!("CCNC".parse::<CodonMotifs>().is_err());
+        assert!("CCX".parse::<CodonMotifs>().is_err());
+        assert!("ATG,".parse::<CodonMotifs>().is_err());
+    }
+
+    #[test]
+    fn motif_positions() {
+        // Forward CDS at 4..19 reads ATG CCC CCG CCA TAA, reverse CDS
+        // on the complement of 20..35 reads ATG CCT CCT AAA TAG
+        let fastastr = ">chr01\n\
+                        GGGGATGCCCCCGCCATAAGCTATTTAGGAGGCATCCCCC\n";
+        let genome =
+            Genome::new_from_records(fasta::Reader::new(fastastr.as_bytes()).records()).unwrap();
+
+        let bedstr = "chr01\t0\t22\tFwd\t0\t+\t4\t19\t0\t1\t22,\t0,\n\
+                      chr01\t20\t40\tRev\t0\t-\t20\t35\t0\t1\t20,\t0,\n";
+        let trxome = transcriptome_from_str(bedstr);
+
+        let motifs: CodonMotifs = "CCNCCN".parse().unwrap();
+        let expected: Vec<Pos<Rc<String>, ReqStrand>> =
+            ["chr01:7(+)", "chr01:10(+)", "chr01:31(-)"]
+                .iter()
+                .map(|pos_str| pos_str.parse().unwrap())
+                .collect();
+        assert_eq!(motifs.positions(&trxome, &genome), expected);
+    }
+}
@@ -0,0 +1,565 @@
+use std::cmp::min;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use failure;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use bio_types::annot::loc::Loc;
+use bio_types::annot::pos::Pos;
+use bio_types::annot::refids::RefIDSet;
+use bio_types::annot::spliced::Spliced;
+use bio_types::strand::ReqStrand;
+use rust_htslib::bam;
+use rust_htslib::bam::Read as BamRead;
+
+use bam_utils::*;
+use cli_utils::*;
+use codon_assign::*;
+use corrupt::*;
+use genome::*;
+use metagene::*;
+use normalization::*;
+use output::*;
+use timing::*;
+use transcript::*;
+
+mod anchors;
+mod binned;
+mod metacodon;
+mod normalize;
+mod per_gene;
+mod runoff;
+mod start_context;
+
+pub use metagene_count::anchors::*;
+use metagene_count::binned::*;
+pub use metagene_count::metacodon::*;
+pub use metagene_count::normalize::*;
+use metagene_count::per_gene::*;
+use metagene_count::runoff::*;
+pub use metagene_count::start_context::*;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CLI {
+    #[serde(skip_serializing)]
+    pub input: String,
+    pub output: String,
+    pub anchors: Option<String>,
+    pub bed: Option<String>,
+    pub feature: String,
+    pub codons: Option<String>,
+    pub fasta: Option<String>,
+    pub asites: Option<String>,
+    pub flanking: String,
+    pub lengths: String,
+    pub length_bins: Option<String>,
+    pub count_multi: bool,
+    pub sparse: bool,
+    #[serde(flatten)]
+    pub read_filter: ReadFilterCLI,
+    pub deletions: String,
+    pub max_indel: Option<String>,
+    pub bins: Option<String>,
+    pub runoff: Option<String>,
+    pub runoff_step: String,
+    pub per_gene: bool,
+    pub clean_genes: bool,
+    pub start_context: bool,
+    pub normalize: Vec<String>,
+    pub bootstrap: Option<String>,
+    pub bootstrap_seed: String,
+    #[serde(flatten)]
+    pub library_size: LibrarySizeCLI,
+    #[serde(flatten)]
+    pub output_config: OutputCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+pub struct Config {
+    input: String,
+    output: PathBuf,
+    mode: Mode,
+    offsets: Option<SiteOffsets>,
+    flanking: Range<isize>,
+    len_bins: LenBins,
+    count_multi: bool,
+    sparse: bool,
+    read_filter: ReadFilter,
+    indels: IndelPolicy,
+    per_gene: bool,
+    start_contexts: Option<StartContexts>,
+    normalizations: Vec<Normalization>,
+    library_size: LibrarySize,
+    bootstrap: Option<usize>,
+    bootstrap_seed: u64,
+    output_config: OutputConfig,
+    corrupt: CorruptInputs,
+}
+
+/// Footprints are tallied either by position relative to anchors, in
+/// scaled bins across each CDS, or in windows of codons from the start
+/// of each CDS.
+enum Mode {
+    Anchored(Anchors),
+    Binned(Transcriptome<Rc<String>>, usize),
+    Runoff(Transcriptome<Rc<String>>, usize, usize),
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        let read_trxome = |bed: &String| -> Result<Transcriptome<Rc<String>>, failure::Error> {
+            let mut refids = RefIDSet::new();
+            let trxome = Transcriptome::new_from_file(bed, &mut refids)?;
+            Ok(if cli.clean_genes {
+                trxome.clean_genes()
+            } else {
+                trxome
+            })
+        };
+        if cli.clean_genes && cli.bed.is_none() {
+            bail!("Restricting to clean genes requires an annotation file");
+        }
+        if cli.start_context
+            && (cli.bed.is_none()
+                || cli.codons.is_some()
+                || cli.bins.is_some()
+                || cli.runoff.is_some())
+        {
+            bail!("Start codon contexts require start codon anchors from an annotation file");
+        }
+
+        let mut start_contexts = None;
+
+        if cli.runoff.is_some() && (cli.bins.is_some() || cli.codons.is_some()) {
+            bail!("Run-off profiles cannot be combined with bins or codon anchors");
+        }
+
+        let mode = match (&cli.anchors, &cli.bed, &cli.bins) {
+            (None, Some(bed), None) if cli.runoff.is_some() => {
+                let window: usize = cli.runoff.as_ref().unwrap().parse()?;
+                let step: usize = cli.runoff_step.parse()?;
+                if window == 0 || step == 0 {
+                    bail!("Run-off window and step must be positive");
+                }
+                Mode::Runoff(read_trxome(bed)?, window, step)
+            }
+            (_, None, _) if cli.runoff.is_some() => {
+                bail!("Run-off profiles require an annotation file")
+            }
+            (Some(anchors), None, None) => Mode::Anchored(Anchors::new_from_bed(anchors)?),
+            (None, Some(bed), None) => match cli.codons {
+                Some(ref codons) => {
+                    let motifs: CodonMotifs = codons.parse()?;
+                    let genome = match cli.fasta {
+                        Some(ref fasta) => Genome::new_from_fasta(fasta)?,
+                        None => bail!("Metacodon profiles require a genome sequence"),
+                    };
+                    Mode::Anchored(Anchors::new_from_codons(
+                        &read_trxome(bed)?,
+                        &genome,
+                        &motifs,
+                    ))
+                }
+                None => {
+                    let trxome = read_trxome(bed)?;
+                    let feature: Feature = cli.feature.parse()?;
+                    let anchors = Anchors::new_from_transcriptome(&trxome, feature);
+                    if cli.start_context {
+                        if feature != Feature::Start {
+                            bail!("Start codon contexts require start codon anchors");
+                        }
+                        let genome = match cli.fasta {
+                            Some(ref fasta) => Genome::new_from_fasta(fasta)?,
+                            None => bail!("Start codon contexts require a genome sequence"),
+                        };
+                        start_contexts = Some(StartContexts::new(&trxome, &genome, &anchors));
+                    }
+                    Mode::Anchored(anchors)
+                }
+            },
+            (None, Some(bed), Some(bins)) => {
+                let nbins: usize = bins.parse()?;
+                if nbins == 0 {
+                    bail!("Number of bins must be positive");
+                }
+                Mode::Binned(read_trxome(bed)?, nbins)
+            }
+            (_, None, Some(_)) => bail!("Binned profiles require an annotation file"),
+            _ => bail!("Specify either an anchor BED file or an annotation file"),
+        };
+
+        let normalizations = cli
+            .normalize
+            .iter()
+            .map(|norm| norm.parse())
+            .collect::<Result<Vec<Normalization>, failure::Error>>()?;
+        let is_anchored = match mode {
+            Mode::Anchored(..) => true,
+            _ => false,
+        };
+        if !is_anchored
+            && normalizations
+                .iter()
+                .any(|&norm| norm != Normalization::Raw)
+        {
+            bail!(
+                "Binned and run-off profiles are always reported with raw and mean normalization"
+            );
+        }
+
+        Ok(Config {
+            input: cli.input.to_string(),
+            output: Path::new(&cli.output).to_path_buf(),
+            mode: mode,
+            offsets: match cli.asites {
+                Some(ref asites) => Some(SiteOffsets::new_from_file(asites)?),
+                None => None,
+            },
+            flanking: parse_pair(&cli.flanking)?,
+            len_bins: match cli.length_bins {
+                Some(ref length_bins) => length_bins.parse()?,
+                None => {
+                    let lengths: Range<usize> = parse_pair(&cli.lengths)?;
+                    LenBins::new(lengths.start, lengths.end)
+                }
+            },
+            count_multi: cli.count_multi,
+            sparse: cli.sparse,
+            read_filter: ReadFilter::new_from_cli(&cli.read_filter)?,
+            indels: IndelPolicy {
+                deletions: cli.deletions.parse()?,
+                max_indel: match cli.max_indel {
+                    Some(ref max_indel) => Some(max_indel.parse()?),
+                    None => None,
+                },
+            },
+            per_gene: cli.per_gene
+                || cli.start_context
+                || normalizations.contains(&Normalization::GeneMean),
+            start_contexts: start_contexts,
+            normalizations: normalizations,
+            library_size: LibrarySize::new_from_cli(&cli.library_size)?,
+            bootstrap: match cli.bootstrap {
+                Some(ref bootstrap) => Some(bootstrap.parse()?),
+                None => None,
+            },
+            bootstrap_seed: cli.bootstrap_seed.parse()?,
+            output_config: OutputConfig::new_from_cli(&cli.output_config)?,
+            corrupt: cli.corrupt.clone(),
+        })
+    }
+
+    fn output_filename(&self, suffix: &str) -> PathBuf {
+        output_filename(&self.output, suffix)
+    }
+}
+
+/// Tallies of alignments considered for the metagene.
+#[derive(Debug, Clone, Default)]
+struct MetageneStats {
+    total: usize,
+    filter: ReadFilterStats,
+    mapped: usize,
+    no_hit: usize,
+    multi_hit: usize,
+    too_short: usize,
+    too_long: usize,
+    indels: IndelStats,
+    no_offset: usize,
+    no_anchor: usize,
+    no_cds: usize,
+    multi_cds: usize,
+    counted: usize,
+    tallies: usize,
+}
+
+impl MetageneStats {
+    fn table(&self) -> String {
+        let rows = [
+            ("Total", self.total),
+            ("NoHit", self.no_hit),
+            ("MultiHit", self.multi_hit),
+            ("TooShort", self.too_short),
+            ("TooLong", self.too_long),
+            ("NoOffset", self.no_offset),
+            ("NoAnchor", self.no_anchor),
+            ("NoCds", self.no_cds),
+            ("MultiCds", self.multi_cds),
+            ("Counted", self.counted),
+            ("Tallies", self.tallies),
+        ];
+        let mut table: String = rows
+            .iter()
+            .map(|(name, n)| format!("{}\t{}\n", name, n))
+            .collect();
+        table += &self.indels.table();
+        table
+    }
+}
+
+pub fn run_metagene(config: Config) -> Result<(), failure::Error> {
+    let mut input = open_bam(&config.input, &config.corrupt)?;
+
+    let tids = {
+        let mut refids: RefIDSet<Rc<String>> = RefIDSet::new();
+        Tids::new(&mut refids, input.header())
+    };
+
+    // Open (empty) stats output file early to detect errors before processing data.
+    config
+        .output_config
+        .create(config.output_filename("_metagene_stats.txt"))?;
+
+    let mut stats = MetageneStats::default();
+
+    match config.mode {
+        Mode::Anchored(ref anchors) => {
+            info!("{} anchors", anchors.len());
+            let (metagene, gene_metagene) = timed("classification", || {
+                anchored_metagene(&config, anchors, &tids, &mut input, &mut stats)
+            })?;
+            let _timer = StageTimer::new("output");
+            config.output_config.write(
+                config.output_filename("_metagene.txt"),
+                len_metagene_table(&metagene),
+            )?;
+            let library = LibraryCounts::new(stats.mapped as f64, stats.counted as f64);
+            let rpm_scale = config.library_size.rpm_scale(&library);
+            for norm in config.normalizations.iter() {
+                if *norm != Normalization::Raw {
+                    config.output_config.write(
+                        config.output_filename(norm.suffix()),
+                        norm.table(&metagene, rpm_scale, gene_metagene.as_ref())?,
+                    )?;
+                }
+            }
+            if let (Some(contexts), Some(gene_metagene)) =
+                (config.start_contexts.as_ref(), gene_metagene.as_ref())
+            {
+                info!("{} start codon contexts", contexts.len());
+                config.output_config.write(
+                    config.output_filename("_start_context.txt"),
+                    contexts.table(gene_metagene),
+                )?;
+            }
+            if let Some(gene_metagene) = gene_metagene {
+                info!("{} anchors with footprints", gene_metagene.genes());
+                let mut rng = StdRng::seed_from_u64(config.bootstrap_seed);
+                config.output_config.write(
+                    config.output_filename("_metagene_genes.txt"),
+                    gene_metagene.table(config.bootstrap, &mut rng),
+                )?;
+            }
+        }
+        Mode::Binned(ref trxome, nbins) => {
+            info!("{}", trxome.summary());
+            tids.warn_missing(trxome);
+            let mut profile = GeneBodyProfile::new(nbins);
+            timed("classification", || {
+                cds_profile(&config, trxome, &mut profile, &tids, &mut input, &mut stats)
+            })?;
+            let _timer = StageTimer::new("output");
+            config
+                .output_config
+                .write(config.output_filename("_binned.txt"), profile.table())?;
+        }
+        Mode::Runoff(ref trxome, window, step) => {
+            info!("{}", trxome.summary());
+            tids.warn_missing(trxome);
+            let mut profile = RunoffProfile::new(window, step);
+            timed("classification", || {
+                cds_profile(&config, trxome, &mut profile, &tids, &mut input, &mut stats)
+            })?;
+            let _timer = StageTimer::new("output");
+            config
+                .output_config
+                .write(config.output_filename("_runoff.txt"), profile.table())?;
+        }
+    }
+
+    let _timer = StageTimer::new("output");
+    config
+        .output_config
+        .write(config.output_filename("_metagene_stats.txt"), stats.table())?;
+    config.output_config.write(
+        config.output_filename("_filter_stats.txt"),
+        stats.filter.table(),
+    )?;
+
+    info!("Tallied {} of {} alignments", stats.counted, stats.total);
+
+    Ok(())
+}
+
+/// Tallies footprints by position and length around anchors, along
+/// with the profile around each anchor individually when per-gene
+/// summaries are requested.
+fn anchored_metagene(
+    config: &Config,
+    anchors: &Anchors,
+    tids: &Tids<Rc<String>>,
+    input: &mut bam::Reader,
+    stats: &mut MetageneStats,
+) -> Result<(Metagene<LenProfile<usize>>, Option<GeneMetagene>), failure::Error> {
+    let flanking_len =
+        (config.flanking.end - min(config.flanking.end, config.flanking.start)) as usize;
+    let len_profile = LenProfile::new_binned(&config.len_bins, 0);
+    let mut metagene = if config.sparse {
+        Metagene::new_sparse(config.flanking.start, flanking_len, len_profile)
+    } else {
+        Metagene::new(config.flanking.start, flanking_len, len_profile)
+    };
+    let mut gene_metagene = if config.per_gene {
+        Some(GeneMetagene::new(config.flanking.start, flanking_len))
+    } else {
+        None
+    };
+
+    for recres in spliced_records(input, tids, &config.corrupt).deletions(config.indels.deletions) {
+        let (rec, fp) = recres?;
+        let (pos, fp_len) = match footprint_pos(config, &rec, fp, stats) {
+            Some(pos_len) => pos_len,
+            None => continue,
+        };
+
+        let anchor_offsets = anchors.anchor_offsets(&pos, &config.flanking);
+        if anchor_offsets.is_empty() {
+            stats.no_anchor += 1;
+            continue;
+        }
+
+        stats.counted += 1;
+        for (anchor, offset) in anchor_offsets {
+            if let Some(len_profile) = metagene.get_mut(offset) {
+                *len_profile.get_mut(fp_len) += 1;
+                stats.tallies += 1;
+            }
+            if let Some(ref mut gene_metagene) = gene_metagene {
+                gene_metagene.tally(anchor, offset);
+            }
+        }
+    }
+
+    Ok((metagene, gene_metagene))
+}
+
+/// Tallies footprints into `profile` by their position within the CDS
+/// of the representative, longest-CDS transcript of each gene.
+/// Footprints lying in the CDS of more than one gene are not counted.
+fn cds_profile<P: CdsProfile>(
+    config: &Config,
+    trxome: &Transcriptome<Rc<String>>,
+    profile: &mut P,
+    tids: &Tids<Rc<String>>,
+    input: &mut bam::Reader,
+    stats: &mut MetageneStats,
+) -> Result<(), failure::Error> {
+    let rep_trxnames: HashSet<Rc<String>> = trxome
+        .longest_cds_transcripts()
+        .iter()
+        .map(|trx| trx.trxname_ref().clone())
+        .collect();
+
+    for recres in spliced_records(input, tids, &config.corrupt).deletions(config.indels.deletions) {
+        let (rec, fp) = recres?;
+        let (pos, _fp_len) = match footprint_pos(config, &rec, fp, stats) {
+            Some(pos_len) => pos_len,
+            None => continue,
+        };
+
+        let cds_hits: Vec<(Rc<String>, usize, usize)> = TrxPos::transcriptome_pos(trxome, &pos)
+            .filter(|trxpos| rep_trxnames.contains(trxpos.transcript().trxname_ref()))
+            .filter_map(|trxpos| {
+                let cds = trxpos.transcript().cds_range().as_ref()?;
+                if trxpos.pos() >= cds.start && trxpos.pos() < cds.end {
+                    Some((
+                        trxpos.transcript().gene_ref().clone(),
+                        trxpos.pos() - cds.start,
+                        cds.end - cds.start,
+                    ))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        match cds_hits.len() {
+            0 => stats.no_cds += 1,
+            1 => {
+                let (ref gene, cds_pos, cds_len) = cds_hits[0];
+                profile.tally(gene, cds_pos, cds_len);
+                stats.counted += 1;
+                stats.tallies += 1;
+            }
+            _ => stats.multi_cds += 1,
+        }
+    }
+
+    info!("{} genes with footprints", profile.genes());
+
+    Ok(())
+}
+
+/// Returns the tallied position of a footprint alignment `fp` from
+/// record `rec`, either its 5' end or its A site, along with its
+/// length, or `None` when the alignment is not tallied.
+fn footprint_pos(
+    config: &Config,
+    rec: &bam::Record,
+    fp: Option<Spliced<Rc<String>, ReqStrand>>,
+    stats: &mut MetageneStats,
+) -> Option<(Pos<Rc<String>, ReqStrand>, usize)> {
+    stats.total += 1;
+
+    if !stats.filter.tally(&config.read_filter, rec) {
+        return None;
+    }
+    if !rec.is_unmapped() {
+        stats.mapped += 1;
+    }
+
+    if !(is_single_hit(rec) || (config.count_multi && is_first_hit(rec))) {
+        stats.multi_hit += 1;
+        return None;
+    }
+
+    if !stats.indels.tally(&config.indels, &rec.cigar()) {
+        return None;
+    }
+
+    let fp = match fp {
+        Some(fp) => fp,
+        None => {
+            stats.no_hit += 1;
+            return None;
+        }
+    };
+
+    let fp_len = fp.exon_total_length();
+    if fp_len < config.len_bins.minlen() {
+        stats.too_short += 1;
+        return None;
+    } else if fp_len > config.len_bins.maxlen() {
+        stats.too_long += 1;
+        return None;
+    }
+
+    let pos = match config.offsets {
+        Some(ref offsets) => match offsets.a_site(fp.clone(), None) {
+            Some(pos) => pos,
+            None => {
+                stats.no_offset += 1;
+                return None;
+            }
+        },
+        None => fp.first_pos(),
+    };
+
+    Some((pos, fp_len))
+}
@@ -0,0 +1,136 @@
+use std::str::FromStr;
+
+use failure;
+
+use metagene::*;
+
+use metagene_count::per_gene::*;
+
+/// Normalization applied to metagene counts in an output table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// Raw footprint counts
+    Raw,
+    /// Counts per million footprints tallied
+    Rpm,
+    /// Fraction of all counts in the metagene
+    Fraction,
+    /// Mean across genes of the profile of each gene, normalized to
+    /// an average of 1 per position
+    GeneMean,
+}
+
+impl FromStr for Normalization {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Normalization::Raw),
+            "rpm" => Ok(Normalization::Rpm),
+            "fraction" => Ok(Normalization::Fraction),
+            "gene-mean" => Ok(Normalization::GeneMean),
+            _ => Err(format_err!("Unknown normalization \"{}\"", s)),
+        }
+    }
+}
+
+impl Normalization {
+    /// Returns the filename suffix for the output table.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Normalization::Raw => "_metagene.txt",
+            Normalization::Rpm => "_metagene_rpm.txt",
+            Normalization::Fraction => "_metagene_fraction.txt",
+            Normalization::GeneMean => "_metagene_gene_mean.txt",
+        }
+    }
+
+    /// Returns the metagene table with this normalization applied.
+    /// RPM and fraction tables have the same layout as the raw table,
+    /// while the gene-mean table gives the mean normalized density
+    /// at each position.
+    ///
+    /// # Arguments
+    ///
+    /// * `metagene` holds counts by position and length
+    /// * `rpm_scale` converts counts into reads per million, from the
+    ///   library size of the sample
+    /// * `gene_metagene` holds per-gene profiles, which are needed
+    /// for gene-mean normalization
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned for gene-mean normalization
+    /// without per-gene profiles.
+    pub fn table(
+        &self,
+        metagene: &Metagene<LenProfile<usize>>,
+        rpm_scale: f64,
+        gene_metagene: Option<&GeneMetagene>,
+    ) -> Result<String, failure::Error> {
+        match self {
+            Normalization::Raw => Ok(len_metagene_table(metagene)),
+            Normalization::Rpm => Ok(scaled_len_metagene_table(metagene, rpm_scale)),
+            Normalization::Fraction => {
+                let total: usize = metagene
+                    .iter()
+                    .map(|len_profile| len_profile.iter().sum::<usize>())
+                    .sum();
+                Ok(scaled_len_metagene_table(
+                    metagene,
+                    1.0 / total.max(1) as f64,
+                ))
+            }
+            Normalization::GeneMean => {
+                let gene_metagene = gene_metagene.ok_or_else(|| {
+                    format_err!("Gene-mean normalization requires per-gene profiles")
+                })?;
+                let mut table = "pos\tgenes\tgene_mean\n".to_string();
+                for (pos, mean) in gene_metagene.pos_means() {
+                    table += &format!("{}\t{}\t{:.4}\n", pos, gene_metagene.genes(), mean);
+                }
+                Ok(table)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized_tables() {
+        let mut metagene = Metagene::new(0, 2, LenProfile::new(28, 28, 0));
+        *metagene.get_mut(0).unwrap().get_mut(28) += 1;
+        *metagene.get_mut(1).unwrap().get_mut(28) += 3;
+
+        assert_eq!(
+            Normalization::Fraction.table(&metagene, 1.0, None).unwrap(),
+            "pos\tttl\t<28\t28\t≥29\n\
+             0\t0.250000\t0.000000\t0.250000\t0.000000\n\
+             1\t0.750000\t0.000000\t0.750000\t0.000000\n"
+        );
+        assert!(Normalization::Rpm
+            .table(&metagene, 5e5, None)
+            .unwrap()
+            .contains("1\t1500000.000000\t0.000000\t1500000.000000\t0.000000\n"));
+        assert!(Normalization::GeneMean.table(&metagene, 1.0, None).is_err());
+
+        let mut gene_metagene = GeneMetagene::new(0, 2);
+        gene_metagene.tally(0, 1);
+        gene_metagene.tally(1, 0);
+        assert_eq!(
+            Normalization::GeneMean
+                .table(&metagene, 1.0, Some(&gene_metagene))
+                .unwrap(),
+            "pos\tgenes\tgene_mean\n0\t2\t1.0000\n1\t2\t1.0000\n"
+        );
+
+        assert_eq!(
+            "gene-mean".parse::<Normalization>().unwrap(),
+            Normalization::GeneMean
+        );
+        assert!("tpm".parse::<Normalization>().is_err());
+    }
+}
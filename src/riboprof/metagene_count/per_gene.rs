@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use rand::Rng;
+
+use bootstrap::*;
+
+/// Footprint profiles around each anchor individually, kept alongside
+/// the pooled metagene in order to summarize the variation across
+/// genes. Profiles are stored only for anchors with footprints, but
+/// each profile spans the full metagene window.
+pub struct GeneMetagene {
+    start: isize,
+    len: usize,
+    genes: HashMap<usize, Vec<usize>>,
+}
+
+/// Summary of normalized footprint density at one metagene position
+/// across genes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneSummary {
+    /// Number of genes with footprints at the position
+    pub nonzero: usize,
+    /// Mean normalized density across genes
+    pub mean: f64,
+    /// Median normalized density across genes
+    pub median: f64,
+    /// Bootstrap confidence interval for the mean, when computed
+    pub interval: Option<(f64, f64)>,
+}
+
+impl GeneMetagene {
+    /// Creates an empty per-gene metagene covering `len` positions
+    /// beginning at offset `start` relative to the anchor.
+    pub fn new(start: isize, len: usize) -> Self {
+        GeneMetagene {
+            start: start,
+            len: len,
+            genes: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of genes with at least one footprint.
+    pub fn genes(&self) -> usize {
+        self.genes.len()
+    }
+
+    /// Returns the number of positions in each profile.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the total footprints tallied around an anchor.
+    pub fn anchor_total(&self, anchor: usize) -> usize {
+        self.genes
+            .get(&anchor)
+            .map_or(0, |profile| profile.iter().sum())
+    }
+
+    /// Tallies a footprint at an offset relative to an anchor,
+    /// returning `false` when the offset lies outside the window.
+    pub fn tally(&mut self, anchor: usize, offset: isize) -> bool {
+        if offset < self.start || offset >= self.start + self.len as isize {
+            return false;
+        }
+
+        let len = self.len;
+        let profile = self.genes.entry(anchor).or_insert_with(|| vec![0; len]);
+        profile[(offset - self.start) as usize] += 1;
+        true
+    }
+
+    /// Returns the profile of each gene with footprints, normalized to
+    /// an average of 1 per position so that every gene contributes
+    /// equally. Genes are ordered by anchor number.
+    pub fn normalized(&self) -> Vec<Vec<f64>> {
+        let mut anchors: Vec<&usize> = self.genes.keys().collect();
+        anchors.sort();
+
+        anchors
+            .into_iter()
+            .map(|anchor| {
+                let profile = &self.genes[anchor];
+                let gene_mean = profile.iter().sum::<usize>() as f64 / self.len as f64;
+                profile.iter().map(|&ct| ct as f64 / gene_mean).collect()
+            })
+            .collect()
+    }
+
+    /// Returns the mean normalized density across genes at each
+    /// position.
+    pub fn pos_means(&self) -> Vec<(isize, f64)> {
+        let profiles = self.normalized();
+        (0..self.len)
+            .map(|i| {
+                let total: f64 = profiles.iter().map(|profile| profile[i]).sum();
+                (
+                    self.start + i as isize,
+                    total / profiles.len().max(1) as f64,
+                )
+            })
+            .collect()
+    }
+
+    /// Summarizes the normalized density at each position across
+    /// genes. When `replicates` is given, confidence intervals for
+    /// the mean are estimated by resampling genes with replacement.
+    pub fn summary<R: Rng>(&self, replicates: Option<usize>, rng: &mut R) -> Vec<GeneSummary> {
+        let profiles = self.normalized();
+        let ngenes = profiles.len();
+
+        let intervals: Option<Vec<(f64, f64)>> = match replicates {
+            Some(replicates) if ngenes > 0 && replicates > 0 => {
+                let mut estimates = vec![Vec::with_capacity(replicates); self.len];
+                for _ in 0..replicates {
+                    let mut totals = vec![0.0; self.len];
+                    for _ in 0..ngenes {
+                        let profile = &profiles[rng.gen_range(0, ngenes)];
+                        for (total, density) in totals.iter_mut().zip(profile.iter()) {
+                            *total += density;
+                        }
+                    }
+                    for (pos_estimates, total) in estimates.iter_mut().zip(totals.into_iter()) {
+                        pos_estimates.push(total / ngenes as f64);
+                    }
+                }
+                Some(
+                    estimates
+                        .iter_mut()
+                        .map(|pos_estimates| confidence_interval(pos_estimates))
+                        .collect(),
+                )
+            }
+            _ => None,
+        };
+
+        (0..self.len)
+            .map(|i| {
+                let mut densities: Vec<f64> = profiles.iter().map(|profile| profile[i]).collect();
+                let nonzero = densities.iter().filter(|&&density| density > 0.0).count();
+                let (mean, median) = if ngenes > 0 {
+                    (
+                        densities.iter().sum::<f64>() / ngenes as f64,
+                        median(&mut densities),
+                    )
+                } else {
+                    (0.0, 0.0)
+                };
+                GeneSummary {
+                    nonzero: nonzero,
+                    mean: mean,
+                    median: median,
+                    interval: intervals.as_ref().map(|intervals| intervals[i]),
+                }
+            })
+            .collect()
+    }
+
+    /// Returns a table with one row for each position, giving the
+    /// number of genes with footprints at the position along with the
+    /// mean and median normalized density across genes. Confidence
+    /// interval columns are included when `replicates` is given.
+    pub fn table<R: Rng>(&self, replicates: Option<usize>, rng: &mut R) -> String {
+        let mut table = "offset\tgenes\tnonzero\tmean\tmedian".to_string();
+        if replicates.is_some() {
+            table += "\tmean_lo\tmean_hi";
+        }
+        table += "\n";
+
+        for (i, summary) in self.summary(replicates, rng).into_iter().enumerate() {
+            table += &format!(
+                "{}\t{}\t{}\t{:.4}\t{:.4}",
+                self.start + i as isize,
+                self.genes(),
+                summary.nonzero,
+                summary.mean,
+                summary.median
+            );
+            if replicates.is_some() {
+                table += &match summary.interval {
+                    Some((lo, hi)) => format!("\t{:.4}\t{:.4}", lo, hi),
+                    None => "\tNA\tNA".to_string(),
+                };
+            }
+            table += "\n";
+        }
+
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn gene_summary() {
+        let mut metagene = GeneMetagene::new(-1, 2);
+        assert!(metagene.tally(0, -1));
+        assert!(metagene.tally(0, 0));
+        assert!(metagene.tally(0, 0));
+        assert!(metagene.tally(0, 0));
+        assert!(metagene.tally(3, 0));
+        assert!(!metagene.tally(3, 1));
+        assert!(!metagene.tally(3, -2));
+        assert_eq!(metagene.genes(), 2);
+        assert_eq!(metagene.anchor_total(0), 4);
+        assert_eq!(metagene.anchor_total(1), 0);
+
+        // Gene 0 contributes 0.5 and 1.5, gene 3 contributes 0.0 and 2.0
+        assert_eq!(metagene.normalized(), vec![vec![0.5, 1.5], vec![0.0, 2.0]]);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let summary = metagene.summary(None, &mut rng);
+        assert_eq!(
+            summary[0],
+            GeneSummary {
+                nonzero: 1,
+                mean: 0.25,
+                median: 0.25,
+                interval: None,
+            }
+        );
+        assert_eq!(summary[1].nonzero, 2);
+        assert_eq!(summary[1].mean, 1.75);
+
+        let summary = metagene.summary(Some(50), &mut rng);
+        let (lo, hi) = summary[0].interval.unwrap();
+        assert!(lo >= 0.0 && lo <= 0.25);
+        assert!(hi >= 0.25 && hi <= 0.5);
+
+        assert_eq!(
+            metagene.table(None, &mut rng),
+            "offset\tgenes\tnonzero\tmean\tmedian\n\
+             -1\t2\t1\t0.2500\t0.2500\n\
+             0\t2\t2\t1.7500\t1.7500\n"
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use metagene_count::binned::*;
+
+/// Footprint density in sliding windows of codons measured from the
+/// start codon, averaged across genes, for run-off time courses after
+/// harringtonine or lactimidomycin treatment. Each gene is normalized
+/// to its own mean density per codon across its CDS, and only genes
+/// whose CDS covers an entire window contribute to that window.
+pub struct RunoffProfile {
+    window: usize,
+    step: usize,
+    genes: HashMap<Rc<String>, Vec<usize>>,
+}
+
+impl RunoffProfile {
+    /// Creates an empty profile with windows of `window` codons whose
+    /// starts are spaced `step` codons apart.
+    pub fn new(window: usize, step: usize) -> Self {
+        RunoffProfile {
+            window: window,
+            step: step,
+            genes: HashMap::new(),
+        }
+    }
+
+    /// Returns a table with one row for each window, giving its start
+    /// and end in codons from the start codon, the number of genes
+    /// whose CDS covers the window, the total count in the window
+    /// across these genes, and the average normalized density.
+    /// Windows run until no CDS covers them.
+    pub fn table(&self) -> String {
+        let mut table = "start\tend\tgenes\tcount\tnormalized\n".to_string();
+
+        let mut start = 0;
+        loop {
+            let end = start + self.window;
+
+            let mut ngenes = 0;
+            let mut count = 0;
+            let mut density = 0.0;
+            for codons in self.genes.values() {
+                if codons.len() < end {
+                    continue;
+                }
+                let gene_total: usize = codons.iter().sum();
+                if gene_total == 0 {
+                    continue;
+                }
+                let gene_mean = gene_total as f64 / codons.len() as f64;
+                let window_count: usize = codons[start..end].iter().sum();
+
+                ngenes += 1;
+                count += window_count;
+                density += window_count as f64 / (self.window as f64 * gene_mean);
+            }
+
+            if ngenes == 0 {
+                break;
+            }
+
+            table += &format!(
+                "{}\t{}\t{}\t{}\t{:.4}\n",
+                start,
+                end,
+                ngenes,
+                count,
+                density / ngenes as f64
+            );
+            start += self.step;
+        }
+
+        table
+    }
+}
+
+impl CdsProfile for RunoffProfile {
+    fn genes(&self) -> usize {
+        self.genes.len()
+    }
+
+    /// Tallies a footprint in the codon containing it. Footprints in a
+    /// final, partial codon are not counted.
+    fn tally(&mut self, gene: &Rc<String>, pos: usize, cds_len: usize) {
+        let ncodons = cds_len / 3;
+        let codon = pos / 3;
+        if codon < ncodons {
+            self.genes
+                .entry(gene.clone())
+                .or_insert_with(|| vec![0; ncodons])[codon] += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runoff_windows() {
+        let gene_a = Rc::new("GeneA".to_string());
+        let gene_b = Rc::new("GeneB".to_string());
+
+        // GeneA has 6 codons and GeneB has 3 codons plus a partial codon
+        let mut profile = RunoffProfile::new(2, 1);
+        for _ in 0..4 {
+            profile.tally(&gene_a, 1, 18);
+        }
+        profile.tally(&gene_a, 9, 18);
+        profile.tally(&gene_a, 17, 18);
+        profile.tally(&gene_b, 7, 10);
+        profile.tally(&gene_b, 9, 10);
+        assert_eq!(profile.genes(), 2);
+
+        // GeneA has a mean of 1 per codon, and GeneB has 1/3 per codon
+        assert_eq!(
+            profile.table(),
+            "start\tend\tgenes\tcount\tnormalized\n\
+             0\t2\t2\t4\t1.0000\n\
+             1\t3\t2\t1\t0.7500\n\
+             2\t4\t1\t1\t0.5000\n\
+             3\t5\t1\t1\t0.5000\n\
+             4\t6\t1\t1\t0.5000\n"
+        );
+
+        let mut stepped = RunoffProfile::new(3, 3);
+        stepped.tally(&gene_a, 0, 18);
+        assert_eq!(
+            stepped.table(),
+            "start\tend\tgenes\tcount\tnormalized\n\
+             0\t3\t1\t1\t2.0000\n\
+             3\t6\t1\t0\t0.0000\n"
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use std::fmt;
+use std::hash::Hash;
+use std::ops::Deref;
+
+use bio_types::annot::pos::Pos;
+use bio_types::strand::*;
+
+use genome::*;
+use metagene_count::anchors::*;
+use metagene_count::per_gene::*;
+use transcript::*;
+
+/// Nucleotides upstream of the start codon included in its context.
+pub const CONTEXT_UPSTREAM: usize = 6;
+
+/// Nucleotides of the context beginning at the first nucleotide of
+/// the start codon, i.e., the start codon itself and the +4 position.
+pub const CONTEXT_DOWNSTREAM: usize = 4;
+
+/// Strength of a start codon context, judged from the two key
+/// positions of the Kozak consensus: a purine at -3 and a G at +4,
+/// numbering the A of the AUG as +1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KozakStrength {
+    /// Both key positions match the consensus
+    Strong,
+    /// One of the two key positions matches the consensus
+    Adequate,
+    /// Neither key position matches the consensus
+    Weak,
+}
+
+impl KozakStrength {
+    /// Classifies a start codon context running from -6 to +4.
+    pub fn new_from_context(context: &[u8]) -> Self {
+        let purine_m3 = match context[CONTEXT_UPSTREAM - 3] {
+            b'A' | b'G' => true,
+            _ => false,
+        };
+        let g_p4 = context[CONTEXT_UPSTREAM + 3] == b'G';
+        match (purine_m3, g_p4) {
+            (true, true) => KozakStrength::Strong,
+            (true, false) | (false, true) => KozakStrength::Adequate,
+            (false, false) => KozakStrength::Weak,
+        }
+    }
+}
+
+impl fmt::Display for KozakStrength {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KozakStrength::Strong => write!(f, "strong"),
+            KozakStrength::Adequate => write!(f, "adequate"),
+            KozakStrength::Weak => write!(f, "weak"),
+        }
+    }
+}
+
+/// Sequence context of the annotated start codon of one gene, along
+/// with the number of its start codon anchor in the metagene.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StartContext {
+    gene: String,
+    trxname: String,
+    anchor: usize,
+    context: Vec<u8>,
+}
+
+impl StartContext {
+    pub fn gene(&self) -> &str {
+        &self.gene
+    }
+
+    pub fn trxname(&self) -> &str {
+        &self.trxname
+    }
+
+    /// Returns the context from -6 to +4, reading 5' to 3' along the
+    /// transcript. Positions beyond the 5' end of the transcript are
+    /// given as `N`.
+    pub fn context(&self) -> &[u8] {
+        &self.context
+    }
+
+    pub fn start_codon(&self) -> &[u8] {
+        &self.context[CONTEXT_UPSTREAM..(CONTEXT_UPSTREAM + 3)]
+    }
+
+    pub fn strength(&self) -> KozakStrength {
+        KozakStrength::new_from_context(&self.context)
+    }
+}
+
+/// Start codon contexts for the longest-CDS transcript of each coding
+/// gene, in order by gene name, for comparing Kozak strength against
+/// footprint density around the start codon.
+pub struct StartContexts {
+    contexts: Vec<StartContext>,
+}
+
+impl StartContexts {
+    /// Collects the start codon context of each gene having a start
+    /// codon anchor. Genes whose transcript sequence is not available
+    /// are skipped.
+    pub fn new<R>(trxome: &Transcriptome<R>, genome: &Genome, anchors: &Anchors) -> Self
+    where
+        R: Deref<Target = String> + Clone + Hash + Eq,
+    {
+        let mut contexts = Vec::new();
+
+        for trx in trxome.longest_cds_transcripts() {
+            let cds_start = match trx.cds_range() {
+                Some(ref cds) => cds.start,
+                None => continue,
+            };
+            let anchor = match trx
+                .loc()
+                .pos_outof(&Pos::new((), cds_start as isize, ReqStrand::Forward))
+                .and_then(|pos| anchors.anchor_number(&pos))
+            {
+                Some(anchor) => anchor,
+                None => continue,
+            };
+            let trx_seq = match genome.spliced_seq(trx.loc()) {
+                Some(trx_seq) => trx_seq,
+                None => continue,
+            };
+
+            let context: Vec<u8> = (0..(CONTEXT_UPSTREAM + CONTEXT_DOWNSTREAM))
+                .map(|i| {
+                    (cds_start + i)
+                        .checked_sub(CONTEXT_UPSTREAM)
+                        .and_then(|trx_pos| trx_seq.get(trx_pos))
+                        .map_or(b'N', |&nt| nt)
+                })
+                .collect();
+
+            contexts.push(StartContext {
+                gene: trx.gene().to_owned(),
+                trxname: trx.trxname().to_owned(),
+                anchor: anchor,
+                context: context,
+            });
+        }
+
+        StartContexts { contexts: contexts }
+    }
+
+    pub fn len(&self) -> usize {
+        self.contexts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contexts.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &StartContext> {
+        self.contexts.iter()
+    }
+
+    /// Returns a table with one row for each gene giving its start
+    /// codon context, the nucleotides at -3 and +4, its Kozak
+    /// strength, and the footprints around its start codon, both as
+    /// a count and as a density per nucleotide of the metagene window.
+    pub fn table(&self, gene_metagene: &GeneMetagene) -> String {
+        let mut table =
+            "gene\ttranscript\tcontext\tcodon\tnt_m3\tnt_p4\tkozak\tcount\tdensity\n".to_string();
+
+        for context in self.contexts.iter() {
+            let footprints = gene_metagene.anchor_total(context.anchor);
+            table += &format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\n",
+                context.gene,
+                context.trxname,
+                String::from_utf8_lossy(&context.context),
+                String::from_utf8_lossy(context.start_codon()),
+                context.context[CONTEXT_UPSTREAM - 3] as char,
+                context.context[CONTEXT_UPSTREAM + 3] as char,
+                context.strength(),
+                footprints,
+                footprints as f64 / gene_metagene.len() as f64
+            );
+        }
+
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use bio::io::{bed, fasta};
+    use bio_types::annot::refids::RefIDSet;
+
+    fn transcriptome_from_str(bedstr: &str) -> Transcriptome<Rc<String>> {
+        let mut refids = RefIDSet::new();
+        Transcriptome::new_from_bed(bed::Reader::new(bedstr.as_bytes()).records(), &mut refids)
+            .expect("Transcriptome from string")
+    }
+
+    #[test]
+    fn kozak_strength() {
+        assert_eq!(
+            KozakStrength::new_from_context(b"GCCACCATGG"),
+            KozakStrength::Strong
+        );
+        assert_eq!(
+            KozakStrength::new_from_context(b"GCCGCCATGA"),
+            KozakStrength::Adequate
+        );
+        assert_eq!(
+            KozakStrength::new_from_context(b"GCCTCCATGG"),
+            KozakStrength::Adequate
+        );
+        assert_eq!(
+            KozakStrength::new_from_context(b"NNNNNNATGC"),
+            KozakStrength::Weak
+        );
+    }
+
+    #[test]
+    fn start_contexts() {
+        // Forward CDS at 8..20 follows GCCACC and has G at +4; reverse
+        // CDS on the complement of 24..36 follows a 3-nt 5' UTR, ATC,
+        // and has A at +4
+        let fastastr = ">chr01\n\
+                        TTGCCACCATGGCCCCGTAAGGGGCTATTTTTTCATGATT\n";
+        let genome =
+            Genome::new_from_records(fasta::Reader::new(fastastr.as_bytes()).records()).unwrap();
+
+        let bedstr = "chr01\t0\t22\tFwd\t0\t+\t8\t20\t0\t1\t22,\t0,\n\
+                      chr01\t22\t39\tRev\t0\t-\t24\t36\t0\t1\t17,\t0,\n";
+        let trxome = transcriptome_from_str(bedstr);
+        let anchors = Anchors::new_from_transcriptome(&trxome, Feature::Start);
+
+        let contexts = StartContexts::new(&trxome, &genome, &anchors);
+        assert_eq!(contexts.len(), 2);
+        let fwd = contexts.iter().next().unwrap();
+        assert_eq!(fwd.gene(), "Fwd");
+        assert_eq!(fwd.context(), b"GCCACCATGG");
+        assert_eq!(fwd.strength(), KozakStrength::Strong);
+        let rev = contexts.iter().nth(1).unwrap();
+        assert_eq!(rev.context(), b"NNNATCATGA");
+        assert_eq!(rev.start_codon(), b"ATG");
+        assert_eq!(rev.strength(), KozakStrength::Weak);
+
+        let mut gene_metagene = GeneMetagene::new(-5, 10);
+        gene_metagene.tally(fwd.anchor, -2);
+        gene_metagene.tally(fwd.anchor, 3);
+        assert_eq!(
+            contexts.table(&gene_metagene),
+            "gene\ttranscript\tcontext\tcodon\tnt_m3\tnt_p4\tkozak\tcount\tdensity\n\
+             Fwd\tFwd\tGCCACCATGG\tATG\tA\tG\tstrong\t2\t0.2000\n\
+             Rev\tRev\tNNNATCATGA\tATG\tN\tA\tweak\t0\t0.0000\n"
+        );
+    }
+}
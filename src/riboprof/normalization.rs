@@ -0,0 +1,347 @@
+use std::fmt;
+use std::str::FromStr;
+
+use clap::{Arg, ArgMatches};
+use failure;
+
+/// Units for normalized counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountUnits {
+    Raw,
+    /// Reads per million in the library
+    Rpm,
+    /// Reads per kilobase of feature per million in the library
+    Rpkm,
+}
+
+impl CountUnits {
+    /// Returns the filename suffix for normalized output tables,
+    /// which is empty for raw counts.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            CountUnits::Raw => "",
+            CountUnits::Rpm => "_rpm",
+            CountUnits::Rpkm => "_rpkm",
+        }
+    }
+}
+
+impl fmt::Display for CountUnits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            CountUnits::Raw => "raw",
+            CountUnits::Rpm => "rpm",
+            CountUnits::Rpkm => "rpkm",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for CountUnits {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(CountUnits::Raw),
+            "rpm" => Ok(CountUnits::Rpm),
+            "rpkm" => Ok(CountUnits::Rpkm),
+            _ => bail!(
+                "Unknown normalization \"{}\", expecting raw, rpm, or rpkm",
+                s
+            ),
+        }
+    }
+}
+
+/// Reads counted towards the size of a library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LibrarySource {
+    /// Every aligned read passing the read filters
+    Mapped,
+    /// Reads tallied in the output, e.g., assigned to a gene
+    Classified,
+}
+
+impl FromStr for LibrarySource {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mapped" => Ok(LibrarySource::Mapped),
+            "classified" => Ok(LibrarySource::Classified),
+            _ => bail!(
+                "Unknown library size \"{}\", expecting mapped or classified",
+                s
+            ),
+        }
+    }
+}
+
+/// Number of reads mapped and classified in one sample. Counts are
+/// fractional when multi-mapping reads are split between genes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LibraryCounts {
+    pub mapped: f64,
+    pub classified: f64,
+}
+
+impl LibraryCounts {
+    pub fn new(mapped: f64, classified: f64) -> Self {
+        LibraryCounts {
+            mapped: mapped,
+            classified: classified,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LibrarySizeCLI {
+    pub library_size: String,
+    pub spike_in: Option<String>,
+}
+
+impl Default for LibrarySizeCLI {
+    fn default() -> Self {
+        LibrarySizeCLI {
+            library_size: "classified".to_string(),
+            spike_in: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NormalizeCLI {
+    pub normalize: String,
+    #[serde(flatten)]
+    pub library_size: LibrarySizeCLI,
+}
+
+impl Default for NormalizeCLI {
+    fn default() -> Self {
+        NormalizeCLI {
+            normalize: CountUnits::Raw.to_string(),
+            library_size: LibrarySizeCLI::default(),
+        }
+    }
+}
+
+/// Returns the arguments choosing the library size for per-million
+/// normalization, for tools with their own choice of units.
+pub fn library_size_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("library-size")
+            .long("library-size")
+            .value_name("READS")
+            .help("Reads counted in the library size for per-million normalization")
+            .takes_value(true)
+            .possible_values(&["mapped", "classified"])
+            .default_value("classified"),
+        Arg::with_name("spike-in")
+            .long("spike-in")
+            .value_name("FACTOR")
+            .help("Spike-in size factor dividing per-million normalized counts")
+            .takes_value(true),
+    ]
+}
+
+/// Collects the values of the `library_size_args`.
+pub fn library_size_cli(matches: &ArgMatches) -> LibrarySizeCLI {
+    LibrarySizeCLI {
+        library_size: matches
+            .value_of("library-size")
+            .unwrap_or("classified")
+            .to_string(),
+        spike_in: matches.value_of("spike-in").map(str::to_string),
+    }
+}
+
+/// Returns the `--normalize` argument choosing count units along with
+/// the `library_size_args`.
+pub fn normalize_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    let mut args = vec![Arg::with_name("normalize")
+        .long("normalize")
+        .value_name("UNITS")
+        .help("Normalization of counts (raw, rpm, or rpkm)")
+        .takes_value(true)
+        .possible_values(&["raw", "rpm", "rpkm"])
+        .default_value("raw")];
+    args.extend(library_size_args());
+    args
+}
+
+/// Collects the values of the `normalize_args`.
+pub fn normalize_cli(matches: &ArgMatches) -> NormalizeCLI {
+    NormalizeCLI {
+        normalize: matches.value_of("normalize").unwrap_or("raw").to_string(),
+        library_size: library_size_cli(matches),
+    }
+}
+
+/// Library size and spike-in factor for per-million normalization.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LibrarySize {
+    source: LibrarySource,
+    spike_in: f64,
+}
+
+impl LibrarySize {
+    pub fn new(source: LibrarySource) -> Self {
+        LibrarySize {
+            source: source,
+            spike_in: 1.0,
+        }
+    }
+
+    pub fn new_from_cli(cli: &LibrarySizeCLI) -> Result<Self, failure::Error> {
+        let spike_in: f64 = match cli.spike_in {
+            Some(ref spike_in) => spike_in.parse()?,
+            None => 1.0,
+        };
+        if !(spike_in > 0.0) {
+            bail!("Spike-in factor must be positive");
+        }
+        Ok(LibrarySize::new(cli.library_size.parse()?).spike_in(spike_in))
+    }
+
+    pub fn spike_in(self, spike_in: f64) -> Self {
+        LibrarySize {
+            spike_in: spike_in,
+            ..self
+        }
+    }
+
+    /// Returns the number of reads in the library.
+    pub fn reads(&self, counts: &LibraryCounts) -> f64 {
+        match self.source {
+            LibrarySource::Mapped => counts.mapped,
+            LibrarySource::Classified => counts.classified,
+        }
+    }
+
+    /// Returns the factor converting raw counts into reads per
+    /// million. An empty library has a factor of 1.
+    pub fn rpm_scale(&self, counts: &LibraryCounts) -> f64 {
+        let reads = self.reads(counts);
+        if reads > 0.0 {
+            1.0e6 / (reads * self.spike_in)
+        } else {
+            1.0 / self.spike_in
+        }
+    }
+}
+
+/// Normalization of counts, shared by tools writing coverage tracks,
+/// metagene tables, and gene counts, so that outputs from different
+/// samples are comparable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NormalizeConfig {
+    units: CountUnits,
+    library_size: LibrarySize,
+}
+
+impl Default for NormalizeConfig {
+    fn default() -> Self {
+        NormalizeConfig::new()
+    }
+}
+
+impl NormalizeConfig {
+    /// Creates a configuration leaving counts raw.
+    pub fn new() -> Self {
+        NormalizeConfig {
+            units: CountUnits::Raw,
+            library_size: LibrarySize::new(LibrarySource::Classified),
+        }
+    }
+
+    pub fn new_from_cli(cli: &NormalizeCLI) -> Result<Self, failure::Error> {
+        Ok(NormalizeConfig::new()
+            .units(cli.normalize.parse()?)
+            .library_size(LibrarySize::new_from_cli(&cli.library_size)?))
+    }
+
+    pub fn units(self, units: CountUnits) -> Self {
+        NormalizeConfig {
+            units: units,
+            ..self
+        }
+    }
+
+    pub fn library_size(self, library_size: LibrarySize) -> Self {
+        NormalizeConfig {
+            library_size: library_size,
+            ..self
+        }
+    }
+
+    pub fn get_units(&self) -> CountUnits {
+        self.units
+    }
+
+    pub fn get_library_size(&self) -> &LibrarySize {
+        &self.library_size
+    }
+
+    pub fn is_raw(&self) -> bool {
+        self.units == CountUnits::Raw
+    }
+
+    /// Returns the factor converting raw counts on a feature of
+    /// `length` nt into normalized counts. The length is used only
+    /// for RPKM and a feature of length 0 has a factor of 0.
+    pub fn scale(&self, counts: &LibraryCounts, length: usize) -> f64 {
+        match self.units {
+            CountUnits::Raw => 1.0,
+            CountUnits::Rpm => self.library_size.rpm_scale(counts),
+            CountUnits::Rpkm if length == 0 => 0.0,
+            CountUnits::Rpkm => self.library_size.rpm_scale(counts) * 1.0e3 / length as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_scale() {
+        let counts = LibraryCounts::new(4.0e6, 2.0e6);
+
+        let rpm = NormalizeConfig::new().units(CountUnits::Rpm);
+        assert_eq!(rpm.scale(&counts, 500), 0.5);
+
+        let mapped = LibrarySize::new(LibrarySource::Mapped);
+        let rpkm = NormalizeConfig::new()
+            .units(CountUnits::Rpkm)
+            .library_size(mapped.spike_in(0.5));
+        assert_eq!(rpkm.scale(&counts, 500), 1.0);
+        assert_eq!(rpkm.scale(&counts, 0), 0.0);
+
+        assert_eq!(NormalizeConfig::new().scale(&counts, 500), 1.0);
+        assert_eq!(
+            rpm.scale(&LibraryCounts::default(), 500),
+            1.0,
+            "empty library"
+        );
+
+        let cli = NormalizeCLI {
+            normalize: "rpkm".to_string(),
+            library_size: LibrarySizeCLI {
+                library_size: "mapped".to_string(),
+                spike_in: Some("0.5".to_string()),
+            },
+        };
+        assert_eq!(NormalizeConfig::new_from_cli(&cli).unwrap(), rpkm);
+
+        let bad_spike_in = NormalizeCLI {
+            library_size: LibrarySizeCLI {
+                spike_in: Some("0".to_string()),
+                ..LibrarySizeCLI::default()
+            },
+            ..NormalizeCLI::default()
+        };
+        assert!(NormalizeConfig::new_from_cli(&bad_spike_in).is_err());
+    }
+}
@@ -0,0 +1,306 @@
+use std::fmt;
+use std::ops::Range;
+
+use failure;
+
+use bio_types::annot::loc::Loc;
+use bio_types::annot::spliced::Spliced;
+use bio_types::strand::ReqStrand;
+
+use transcript::*;
+
+/// Stop codons of the standard genetic code.
+pub const STOP_CODONS: [&[u8]; 3] = [b"TAA", b"TAG", b"TGA"];
+
+/// Parses a comma-separated list of start codons, such as
+/// `ATG,CTG,GTG`, accepting either DNA or RNA bases.
+pub fn parse_start_codons(codons_str: &str) -> Result<Vec<Vec<u8>>, failure::Error> {
+    let mut start_codons = Vec::new();
+    for codon in codons_str.split(',') {
+        let codon = codon.trim().to_ascii_uppercase().replace('U', "T");
+        if codon.len() != 3 || !codon.bytes().all(|nt| b"ACGT".contains(&nt)) {
+            bail!("Bad start codon \"{}\"", codon);
+        }
+        start_codons.push(codon.into_bytes());
+    }
+    Ok(start_codons)
+}
+
+/// Position of a candidate ORF relative to the annotated coding
+/// sequence of its transcript.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OrfClass {
+    /// Annotated CDS, ending at the annotated stop codon
+    Annotated,
+    /// Upstream ORF ending within the 5' UTR
+    Uorf,
+    /// Upstream ORF overlapping the start of the annotated CDS
+    OverlapUorf,
+    /// Out-of-frame ORF lying within the annotated CDS
+    Internal,
+    /// Downstream ORF starting in the 3' UTR
+    Dorf,
+    /// ORF on a transcript with no annotated CDS
+    Noncoding,
+}
+
+impl fmt::Display for OrfClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            OrfClass::Annotated => "annotated",
+            OrfClass::Uorf => "uORF",
+            OrfClass::OverlapUorf => "uoORF",
+            OrfClass::Internal => "internal",
+            OrfClass::Dorf => "dORF",
+            OrfClass::Noncoding => "ncORF",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Candidate open reading frame on a transcript.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrfCandidate {
+    /// Transcript coordinates, including the stop codon
+    range: Range<usize>,
+    start_codon: Vec<u8>,
+    class: OrfClass,
+}
+
+impl OrfCandidate {
+    /// Returns the ORF location in transcript coordinates, from the
+    /// first base of the start codon through the last base of the
+    /// stop codon.
+    pub fn range(&self) -> &Range<usize> {
+        &self.range
+    }
+
+    /// Returns the start codon sequence.
+    pub fn start_codon(&self) -> &[u8] {
+        &self.start_codon
+    }
+
+    pub fn class(&self) -> OrfClass {
+        self.class
+    }
+
+    /// Returns the number of codons, including the stop codon.
+    pub fn codons(&self) -> usize {
+        self.range.len() / 3
+    }
+
+    /// Returns a name for the ORF that is unique within the
+    /// transcriptome, based on the transcript name and the position of
+    /// the ORF on the transcript.
+    pub fn name<R>(&self, trx: &Transcript<R>) -> String
+    where
+        R: ::std::ops::Deref<Target = String>,
+    {
+        format!(
+            "{}_{}_{}-{}",
+            trx.trxname(),
+            self.class,
+            self.range.start,
+            self.range.end
+        )
+    }
+}
+
+/// Finds open reading frames in a transcript sequence. Each ORF begins
+/// with one of `start_codons` and ends with an in-frame stop codon; ORFs
+/// without a stop codon are not reported. Only the most upstream start
+/// codon is used for each stop codon, and ORFs shorter than
+/// `min_codons`, including the stop codon, are skipped.
+///
+/// Returned ranges include the stop codon and are sorted by start.
+pub fn find_orfs(seq: &[u8], start_codons: &[Vec<u8>], min_codons: usize) -> Vec<Range<usize>> {
+    let mut orfs = Vec::new();
+
+    for frame in 0..3 {
+        let mut open: Option<usize> = None;
+        let mut pos = frame;
+        while pos + 3 <= seq.len() {
+            let codon = &seq[pos..(pos + 3)];
+            if STOP_CODONS.contains(&codon) {
+                if let Some(start) = open.take() {
+                    if (pos + 3 - start) / 3 >= min_codons {
+                        orfs.push(start..(pos + 3));
+                    }
+                }
+            } else if open.is_none() && start_codons.iter().any(|start| start.as_slice() == codon) {
+                open = Some(pos);
+            }
+            pos += 3;
+        }
+    }
+
+    orfs.sort_by_key(|orf| (orf.start, orf.end));
+    orfs
+}
+
+/// Returns the class of an ORF given the annotated CDS of its
+/// transcript, if any. Both ranges are in transcript coordinates and
+/// include the stop codon.
+pub fn classify_orf(orf: &Range<usize>, cds: &Option<Range<usize>>) -> OrfClass {
+    match *cds {
+        None => OrfClass::Noncoding,
+        Some(ref cds) if orf.end == cds.end => OrfClass::Annotated,
+        Some(ref cds) if orf.start < cds.start && orf.end <= cds.start => OrfClass::Uorf,
+        Some(ref cds) if orf.start < cds.start => OrfClass::OverlapUorf,
+        Some(ref cds) if orf.start >= cds.end => OrfClass::Dorf,
+        Some(_) => OrfClass::Internal,
+    }
+}
+
+/// Finds and classifies the candidate ORFs on a transcript.
+///
+/// # Arguments
+///
+/// * `trx` is the transcript
+/// * `seq` is the transcript sequence, 5' to 3'
+/// * `start_codons` lists the allowed start codons, typically `ATG`
+///   and possibly near-cognate codons such as `CTG`
+/// * `min_codons` is the minimum ORF length, including the stop
+///   codon
+/// * `classes` restricts the classes of ORF reported
+pub fn transcript_orfs<R>(
+    trx: &Transcript<R>,
+    seq: &[u8],
+    start_codons: &[Vec<u8>],
+    min_codons: usize,
+    classes: &[OrfClass],
+) -> Vec<OrfCandidate> {
+    find_orfs(seq, start_codons, min_codons)
+        .into_iter()
+        .filter_map(|range| {
+            let class = classify_orf(&range, trx.cds_range());
+            if classes.contains(&class) {
+                Some(OrfCandidate {
+                    start_codon: seq[range.start..(range.start + 3)].to_vec(),
+                    range: range,
+                    class: class,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Returns the genomic blocks spanned by a range of transcript
+/// coordinates, as `(start, length)` pairs sorted by genomic start.
+pub fn range_blocks<R>(loc: &Spliced<R, ReqStrand>, range: &Range<usize>) -> Vec<(isize, usize)> {
+    let mut exons = loc.exon_contigs();
+    exons.sort_by_key(|exon| exon.start());
+    if loc.strand() == ReqStrand::Reverse {
+        exons.reverse();
+    }
+
+    let mut blocks = Vec::new();
+    let mut trx_offset = 0;
+    for exon in exons {
+        let exon_len = exon.length();
+        let ov_start = range.start.max(trx_offset);
+        let ov_end = range.end.min(trx_offset + exon_len);
+        if ov_start < ov_end {
+            let block_len = ov_end - ov_start;
+            let into_exon = (ov_start - trx_offset) as isize;
+            let block_start = match loc.strand() {
+                ReqStrand::Forward => exon.start() + into_exon,
+                ReqStrand::Reverse => {
+                    exon.start() + exon_len as isize - into_exon - block_len as isize
+                }
+            };
+            blocks.push((block_start, block_len));
+        }
+        trx_offset += exon_len;
+    }
+
+    blocks.sort();
+    blocks
+}
+
+/// Formats a range of transcript coordinates as a BED12 line, with the
+/// entire range as the thick region.
+pub fn bed12_line<R>(
+    loc: &Spliced<R, ReqStrand>,
+    range: &Range<usize>,
+    name: &str,
+    score: usize,
+) -> Option<String>
+where
+    R: ::std::ops::Deref<Target = String>,
+{
+    let blocks = range_blocks(loc, range);
+    let start = blocks.first()?.0;
+    let end = blocks
+        .last()
+        .map(|&(bstart, blen)| bstart + blen as isize)?;
+
+    let sizes: String = blocks.iter().map(|&(_, len)| format!("{},", len)).collect();
+    let starts: String = blocks
+        .iter()
+        .map(|&(bstart, _)| format!("{},", bstart - start))
+        .collect();
+    let strand = match loc.strand() {
+        ReqStrand::Forward => "+",
+        ReqStrand::Reverse => "-",
+    };
+
+    Some(format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t0\t{}\t{}\t{}",
+        loc.refid().as_str(),
+        start,
+        end,
+        name,
+        score.min(1000),
+        strand,
+        start,
+        end,
+        blocks.len(),
+        sizes,
+        starts
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    #[test]
+    fn orfs_in_sequence() {
+        let atg = vec![b"ATG".to_vec()];
+        let seq = b"CATGAAACCCTAGGATGTTTGGGCCCTGACATGAAA";
+        assert_eq!(find_orfs(seq, &atg, 1), vec![1..13, 14..29]);
+        assert_eq!(find_orfs(seq, &atg, 5), vec![14..29]);
+
+        let near = parse_start_codons("ATG, cug").unwrap();
+        assert_eq!(near, vec![b"ATG".to_vec(), b"CTG".to_vec()]);
+        assert!(parse_start_codons("ATGC").is_err());
+        assert_eq!(find_orfs(b"CTGATGTAA", &near, 1), vec![0..9]);
+
+        let cds = Some(14..29);
+        assert_eq!(classify_orf(&(14..29), &cds), OrfClass::Annotated);
+        assert_eq!(classify_orf(&(1..13), &cds), OrfClass::Uorf);
+        assert_eq!(classify_orf(&(1..19), &cds), OrfClass::OverlapUorf);
+        assert_eq!(classify_orf(&(15..24), &cds), OrfClass::Internal);
+        assert_eq!(classify_orf(&(30..42), &cds), OrfClass::Dorf);
+        assert_eq!(classify_orf(&(1..13), &None), OrfClass::Noncoding);
+    }
+
+    #[test]
+    fn orf_blocks() {
+        let fwd: Spliced<Rc<String>, ReqStrand> = "chr1:100-109;200-219(+)".parse().unwrap();
+        assert_eq!(range_blocks(&fwd, &(5..15)), vec![(105, 5), (200, 5)]);
+        assert_eq!(
+            bed12_line(&fwd, &(5..15), "orf", 10).unwrap(),
+            "chr1\t105\t205\torf\t10\t+\t105\t205\t0\t2\t5,5,\t0,95,"
+        );
+
+        let rev: Spliced<Rc<String>, ReqStrand> = "chr1:100-109;200-219(-)".parse().unwrap();
+        assert_eq!(range_blocks(&rev, &(5..15)), vec![(205, 10)]);
+        assert_eq!(range_blocks(&rev, &(18..24)), vec![(106, 4), (200, 2)]);
+    }
+}
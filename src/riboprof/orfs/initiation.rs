@@ -0,0 +1,232 @@
+use std::fmt;
+use std::ops::Range;
+
+/// Number of codons on either side of a candidate initiation site
+/// used to estimate the local background.
+pub const BACKGROUND_CODONS: usize = 10;
+
+/// Returns the codons differing from `codon` at exactly one position,
+/// e.g., the near-cognate start codons CTG, GTG, and ACG for ATG.
+///
+/// ```
+/// # use riboprof::orfs::near_cognate_codons;
+/// let near = near_cognate_codons(b"ATG");
+/// assert_eq!(near.len(), 9);
+/// assert!(near.contains(&b"CTG".to_vec()));
+/// assert!(!near.contains(&b"ATG".to_vec()));
+/// ```
+pub fn near_cognate_codons(codon: &[u8]) -> Vec<Vec<u8>> {
+    let mut codons = Vec::new();
+    for i in 0..codon.len() {
+        for nt in b"ACGT".iter() {
+            if *nt != codon[i] {
+                let mut near = codon.to_vec();
+                near[i] = *nt;
+                codons.push(near);
+            }
+        }
+    }
+    codons
+}
+
+/// Returns the total count over the codon starting at `pos`, treating
+/// positions beyond the end of `counts` as empty.
+pub fn codon_total(counts: &[usize], pos: usize) -> usize {
+    (pos..pos + 3).filter_map(|i| counts.get(i)).sum()
+}
+
+/// P-site counts on the annotated start codon of a transcript
+/// compared with the rest of its CDS.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StartEnrichment {
+    start: usize,
+    body: usize,
+    body_codons: usize,
+}
+
+impl StartEnrichment {
+    /// Tallies P sites on the start codon and on the remaining codons
+    /// of the CDS, `cds`, from per-position counts on the transcript.
+    pub fn new(counts: &[usize], cds: &Range<usize>) -> Self {
+        let body = (cds.start + 3).min(cds.end)..cds.end;
+        StartEnrichment {
+            start: codon_total(counts, cds.start),
+            body: body.clone().filter_map(|pos| counts.get(pos)).sum(),
+            body_codons: (body.end - body.start) / 3,
+        }
+    }
+
+    /// Returns the P-site count on the start codon.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Returns the P-site count on the CDS after the start codon.
+    pub fn body(&self) -> usize {
+        self.body
+    }
+
+    /// Returns the mean P-site count per codon on the CDS after the
+    /// start codon.
+    pub fn body_density(&self) -> f64 {
+        self.body as f64 / self.body_codons as f64
+    }
+
+    /// Returns the ratio of the start codon count to the mean count per
+    /// codon in the CDS body, which is infinite with no CDS body
+    /// counts and `NaN` with no counts at all.
+    pub fn enrichment(&self) -> f64 {
+        self.start as f64 / self.body_density()
+    }
+
+    /// Adds the counts from another transcript, giving an aggregate
+    /// enrichment across many genes.
+    pub fn add(&mut self, other: &StartEnrichment) {
+        self.start += other.start;
+        self.body += other.body;
+        self.body_codons += other.body_codons;
+    }
+}
+
+/// Location of a candidate initiation site relative to the annotated
+/// CDS of its transcript.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SiteClass {
+    Annotated,
+    Utr5,
+    Cds,
+    Utr3,
+}
+
+impl SiteClass {
+    pub fn new(pos: usize, cds: &Range<usize>) -> Self {
+        if pos == cds.start {
+            SiteClass::Annotated
+        } else if pos < cds.start {
+            SiteClass::Utr5
+        } else if pos < cds.end {
+            SiteClass::Cds
+        } else {
+            SiteClass::Utr3
+        }
+    }
+}
+
+impl fmt::Display for SiteClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SiteClass::Annotated => "annotated",
+            SiteClass::Utr5 => "utr5",
+            SiteClass::Cds => "cds",
+            SiteClass::Utr3 => "utr3",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Candidate initiation site, a start or near-cognate codon with a
+/// peak of P sites.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InitiationSite {
+    pub pos: usize,
+    pub codon: Vec<u8>,
+    pub count: usize,
+    pub background: f64,
+}
+
+impl InitiationSite {
+    /// Returns the ratio of the P-site count on the site to the mean
+    /// count per codon in the flanking background.
+    pub fn enrichment(&self) -> f64 {
+        self.count as f64 / self.background
+    }
+}
+
+/// Finds candidate initiation sites on a transcript. A candidate is
+/// an occurrence of one of `codons` in `seq` with at least
+/// `min_reads` P sites, no fewer than the codons shifted one base in
+/// either direction, and at least `min_enrichment` times the mean per
+/// codon over `BACKGROUND_CODONS` in-frame codons on either side.
+/// The background is floored at one footprint per codon, so an
+/// isolated peak needs `min_enrichment` footprints.
+pub fn initiation_sites(
+    seq: &[u8],
+    counts: &[usize],
+    codons: &[Vec<u8>],
+    min_reads: usize,
+    min_enrichment: f64,
+) -> Vec<InitiationSite> {
+    let mut sites = Vec::new();
+
+    for pos in 0..seq.len().saturating_sub(2) {
+        let codon = &seq[pos..pos + 3];
+        if !codons.iter().any(|c| c.as_slice() == codon) {
+            continue;
+        }
+
+        let count = codon_total(counts, pos);
+        if count < min_reads
+            || (pos > 0 && codon_total(counts, pos - 1) > count)
+            || codon_total(counts, pos + 1) > count
+        {
+            continue;
+        }
+
+        let flank = (1..=BACKGROUND_CODONS)
+            .flat_map(|i| vec![pos.checked_sub(3 * i), Some(pos + 3 * i)])
+            .filter_map(|flank_pos| flank_pos.filter(|&p| p + 3 <= seq.len()))
+            .collect::<Vec<usize>>();
+        let flank_total: usize = flank.iter().map(|&p| codon_total(counts, p)).sum();
+        let background = (flank_total as f64 / flank.len().max(1) as f64).max(1.0);
+
+        if count as f64 >= min_enrichment * background {
+            sites.push(InitiationSite {
+                pos: pos,
+                codon: codon.to_vec(),
+                count: count,
+                background: background,
+            });
+        }
+    }
+
+    sites
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_sites() {
+        let seq = b"CTGCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCATGAAAAAAAAAAAAAAATAACCC";
+        let cds = 35..56;
+        let mut counts = vec![0; seq.len()];
+        counts[1] = 12;
+        counts[35] = 40;
+        counts[36] = 2;
+        counts[41] = 1;
+        counts[47] = 3;
+
+        let enrich = StartEnrichment::new(&counts, &cds);
+        assert_eq!(enrich.start(), 42);
+        assert_eq!(enrich.body(), 4);
+        assert!((enrich.enrichment() - 42.0 / (4.0 / 6.0)).abs() < 1e-9);
+
+        let mut total = enrich;
+        total.add(&enrich);
+        assert_eq!(total.start(), 84);
+        assert!((total.enrichment() - enrich.enrichment()).abs() < 1e-9);
+
+        let mut codons = near_cognate_codons(b"ATG");
+        codons.push(b"ATG".to_vec());
+        let sites = initiation_sites(seq, &counts, &codons, 10, 5.0);
+        assert_eq!(sites.len(), 2);
+        assert_eq!((sites[0].pos, sites[0].codon.as_slice()), (0, &b"CTG"[..]));
+        assert_eq!(sites[0].count, 12);
+        assert_eq!(SiteClass::new(sites[0].pos, &cds), SiteClass::Utr5);
+        assert_eq!((sites[1].pos, sites[1].count), (35, 42));
+        assert_eq!(SiteClass::new(sites[1].pos, &cds), SiteClass::Annotated);
+
+        assert!(initiation_sites(seq, &counts, &codons, 10, 50.0).is_empty());
+    }
+}
@@ -0,0 +1,564 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use failure;
+
+use bio_types::annot::loc::Loc;
+use bio_types::annot::refids::RefIDSet;
+use rust_htslib::bam;
+use rust_htslib::bam::Read as BamRead;
+
+use bam_utils::*;
+use cli_utils::*;
+use codon_assign::*;
+use corrupt::*;
+use genome::*;
+use output::*;
+use timing::*;
+use transcript::*;
+
+mod candidates;
+mod initiation;
+mod periodicity;
+mod spectrum;
+
+pub use orfs::candidates::*;
+pub use orfs::initiation::*;
+pub use orfs::periodicity::*;
+pub use orfs::spectrum::*;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CLI {
+    #[serde(skip_serializing)]
+    pub input: String,
+    pub output: String,
+    pub bed: String,
+    pub genome: String,
+    pub asites: String,
+    pub start_codons: String,
+    pub classes: Option<String>,
+    pub min_codons: String,
+    pub min_reads: String,
+    pub max_pvalue: String,
+    pub periodicity: String,
+    pub count_multi: bool,
+    pub initiation: bool,
+    pub min_enrichment: String,
+    pub io_threads: String,
+    #[serde(flatten)]
+    pub read_filter: ReadFilterCLI,
+    #[serde(flatten)]
+    pub output_config: OutputCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+pub struct Config {
+    input: String,
+    output: PathBuf,
+    trxome: Transcriptome<Rc<String>>,
+    genome: Genome,
+    offsets: SiteOffsets,
+    start_codons: Vec<Vec<u8>>,
+    classes: Vec<OrfClass>,
+    min_codons: usize,
+    min_reads: usize,
+    max_pvalue: f64,
+    periodicity: PeriodicityMethod,
+    count_multi: bool,
+    initiation: bool,
+    min_enrichment: f64,
+    io_threads: usize,
+    read_filter: ReadFilter,
+    output_config: OutputConfig,
+    corrupt: CorruptInputs,
+}
+
+impl FromStr for OrfClass {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "annotated" => Ok(OrfClass::Annotated),
+            "uORF" | "uorf" => Ok(OrfClass::Uorf),
+            "uoORF" | "uoorf" => Ok(OrfClass::OverlapUorf),
+            "internal" => Ok(OrfClass::Internal),
+            "dORF" | "dorf" => Ok(OrfClass::Dorf),
+            "ncORF" | "ncorf" => Ok(OrfClass::Noncoding),
+            _ => bail!("Unknown ORF class \"{}\"", s),
+        }
+    }
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        let mut refids = RefIDSet::new();
+        let trxome = Transcriptome::new_from_file(&cli.bed, &mut refids)?;
+
+        let classes = match cli.classes {
+            Some(ref classes) => classes
+                .split(',')
+                .map(|class| class.trim().parse())
+                .collect::<Result<Vec<OrfClass>, failure::Error>>()?,
+            None => vec![
+                OrfClass::Annotated,
+                OrfClass::Uorf,
+                OrfClass::OverlapUorf,
+                OrfClass::Internal,
+                OrfClass::Dorf,
+                OrfClass::Noncoding,
+            ],
+        };
+
+        Ok(Config {
+            input: cli.input.to_string(),
+            output: Path::new(&cli.output).to_path_buf(),
+            trxome: trxome,
+            genome: Genome::new_from_fasta(&cli.genome)?,
+            offsets: SiteOffsets::new_from_file(&cli.asites)?,
+            start_codons: parse_start_codons(&cli.start_codons)?,
+            classes: classes,
+            min_codons: cli.min_codons.parse()?,
+            min_reads: cli.min_reads.parse()?,
+            max_pvalue: cli.max_pvalue.parse()?,
+            periodicity: cli.periodicity.parse()?,
+            count_multi: cli.count_multi,
+            initiation: cli.initiation,
+            min_enrichment: cli.min_enrichment.parse()?,
+            io_threads: cli.io_threads.parse()?,
+            read_filter: ReadFilter::new_from_cli(&cli.read_filter)?
+                .exclude_flags(NON_PRIMARY_FLAGS),
+            output_config: OutputConfig::new_from_cli(&cli.output_config)?,
+            corrupt: cli.corrupt.clone(),
+        })
+    }
+
+    fn output_filename(&self, suffix: &str) -> PathBuf {
+        output_filename(&self.output, suffix)
+    }
+}
+
+pub fn run_orfs(config: Config) -> Result<(), failure::Error> {
+    if config.initiation {
+        return run_initiation(&config);
+    }
+
+    // Open (empty) output files early to detect errors before processing data.
+    config
+        .output_config
+        .create(config.output_filename("_orfs.txt"))?;
+    config
+        .output_config
+        .create(config.output_filename("_orfs.bed"))?;
+    config
+        .output_config
+        .create(config.output_filename("_periodicity.txt"))?;
+
+    let mut trx_orfs: Vec<(&Transcript<Rc<String>>, Vec<OrfCandidate>)> = Vec::new();
+    let mut no_seq = 0;
+    for trx in config.trxome.transcripts() {
+        let seq = match config.genome.spliced_seq(trx.loc()) {
+            Some(seq) => seq,
+            None => {
+                no_seq += 1;
+                continue;
+            }
+        };
+        let orfs = transcript_orfs(
+            trx,
+            &seq,
+            &config.start_codons,
+            config.min_codons,
+            &config.classes,
+        );
+        if !orfs.is_empty() {
+            trx_orfs.push((trx, orfs));
+        }
+    }
+    trx_orfs.sort_by(|(trx_a, _), (trx_b, _)| trx_a.trxname().cmp(trx_b.trxname()));
+
+    if no_seq > 0 {
+        warn!("No genome sequence for {} transcripts", no_seq);
+    }
+    info!(
+        "Testing {} candidate ORFs on {} transcripts",
+        trx_orfs.iter().map(|(_, orfs)| orfs.len()).sum::<usize>(),
+        trx_orfs.len()
+    );
+
+    // Every transcript is tallied, not just those with candidate ORFs,
+    // for the per-transcript periodicity table.
+    let mut trx_counts: HashMap<Rc<String>, Vec<usize>> = config
+        .trxome
+        .transcripts()
+        .map(|trx| {
+            (
+                trx.trxname_ref().clone(),
+                vec![0; trx.loc().exon_total_length()],
+            )
+        })
+        .collect();
+
+    let mut input = open_bam(&config.input, &config.corrupt)?;
+    set_reader_threads(&mut input, config.io_threads)?;
+
+    let filter_stats = tally_asites(
+        &mut input,
+        &config.trxome,
+        &config.offsets,
+        &config.read_filter,
+        &config.corrupt,
+        config.count_multi,
+        &mut trx_counts,
+    )?;
+
+    let _timer = StageTimer::new("output");
+    let mut table_out = config
+        .output_config
+        .create(config.output_filename("_orfs.txt"))?;
+    let mut bed_out = config
+        .output_config
+        .create(config.output_filename("_orfs.bed"))?;
+    write!(
+        table_out,
+        "orf\ttranscript\tgene\tclass\tstart_codon\tstart\tend\tcodons\t\
+         frame0\tframe1\tframe2\tpvalue\tcalled\n"
+    )?;
+
+    let mut n_called = 0;
+    for (trx, orfs) in trx_orfs.iter() {
+        let counts = &trx_counts[trx.trxname_ref()];
+        for orf in orfs.iter() {
+            let signal = FrameSignal::new(counts, orf.range());
+            let pvalue = config.periodicity.pvalue(counts, orf.range());
+            let called = signal.total() >= config.min_reads && pvalue <= config.max_pvalue;
+            let name = orf.name(trx);
+
+            writeln!(
+                table_out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.3e}\t{}",
+                name,
+                trx.trxname(),
+                trx.gene(),
+                orf.class(),
+                String::from_utf8_lossy(orf.start_codon()),
+                orf.range().start,
+                orf.range().end,
+                orf.codons(),
+                signal.frames().get(0_isize),
+                signal.frames().get(1_isize),
+                signal.frames().get(2_isize),
+                pvalue,
+                called
+            )?;
+
+            if called {
+                if let Some(line) = bed12_line(trx.loc(), orf.range(), &name, phred_score(pvalue)) {
+                    writeln!(bed_out, "{}", line)?;
+                }
+                n_called += 1;
+            }
+        }
+    }
+
+    table_out.finish()?;
+    bed_out.finish()?;
+
+    config.output_config.write(
+        config.output_filename("_periodicity.txt"),
+        transcript_periodicity_table(&config.trxome, &trx_counts),
+    )?;
+    config.output_config.write(
+        config.output_filename("_filter_stats.txt"),
+        filter_stats.table(),
+    )?;
+
+    info!("Called {} periodic ORFs", n_called);
+
+    Ok(())
+}
+
+/// Returns a table of 3-nt periodicity scores for every transcript in
+/// `trxome`, whether or not it has candidate ORFs, from the A sites
+/// on its CDS or, for a non-coding transcript, along its whole length.
+fn transcript_periodicity_table(
+    trxome: &Transcriptome<Rc<String>>,
+    trx_counts: &HashMap<Rc<String>, Vec<usize>>,
+) -> String {
+    let mut table = "transcript\tgene\tregion\tlength\tasites\t\
+                     fft_power\tfft_pvalue\tmultitaper_f\tmultitaper_pvalue\n"
+        .to_string();
+
+    let peak_fields = |peak: Option<SpectralPeak>| {
+        peak.map_or("NA\tNA".to_string(), |peak| {
+            format!("{:.3}\t{:.3e}", peak.statistic, peak.pvalue)
+        })
+    };
+
+    let mut trxs: Vec<&Transcript<Rc<String>>> = trxome.transcripts().collect();
+    trxs.sort_by(|trx_a, trx_b| trx_a.trxname().cmp(trx_b.trxname()));
+
+    for trx in trxs {
+        let counts = &trx_counts[trx.trxname_ref()];
+        let (region, range) = match trx.cds_range() {
+            Some(cds) => (
+                "cds",
+                cds.start.min(counts.len())..cds.end.min(counts.len()),
+            ),
+            None => ("transcript", 0..counts.len()),
+        };
+        let window = &counts[range];
+        table += &format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            trx.trxname(),
+            trx.gene(),
+            region,
+            window.len(),
+            window.iter().sum::<usize>(),
+            peak_fields(fft_periodicity(window)),
+            peak_fields(multitaper_periodicity(window))
+        );
+    }
+
+    table
+}
+
+/// Initiation mode, for harringtonine or lactimidomycin libraries
+/// whose footprints pile up on start codons. The P sites of footprints
+/// are tallied on the longest coding transcript of each gene, giving
+/// the enrichment of P sites on the annotated start codon relative to
+/// the CDS body, per gene and across all genes, along with candidate
+/// initiation sites at start codons and near-cognates of AUG.
+fn run_initiation(config: &Config) -> Result<(), failure::Error> {
+    // Open (empty) output files early to detect errors before processing data.
+    config
+        .output_config
+        .create(config.output_filename("_initiation_genes.txt"))?;
+    config
+        .output_config
+        .create(config.output_filename("_initiation_sites.txt"))?;
+
+    let mut codons = config.start_codons.clone();
+    for near in near_cognate_codons(b"ATG") {
+        if !codons.contains(&near) {
+            codons.push(near);
+        }
+    }
+
+    let mut trx_seqs: Vec<(&Transcript<Rc<String>>, Vec<u8>)> = Vec::new();
+    let mut no_seq = 0;
+    for trx in config.trxome.longest_cds_transcripts() {
+        match config.genome.spliced_seq(trx.loc()) {
+            Some(seq) => trx_seqs.push((trx, seq)),
+            None => no_seq += 1,
+        }
+    }
+    if no_seq > 0 {
+        warn!("No genome sequence for {} transcripts", no_seq);
+    }
+
+    let mut trx_counts: HashMap<Rc<String>, Vec<usize>> = trx_seqs
+        .iter()
+        .map(|(trx, seq)| (trx.trxname_ref().clone(), vec![0; seq.len()]))
+        .collect();
+
+    let mut input = open_bam(&config.input, &config.corrupt)?;
+    set_reader_threads(&mut input, config.io_threads)?;
+
+    let filter_stats = tally_sites(
+        &mut input,
+        &config.trxome,
+        &config.offsets,
+        Site::P,
+        &config.read_filter,
+        &config.corrupt,
+        config.count_multi,
+        &mut trx_counts,
+    )?;
+
+    let _timer = StageTimer::new("output");
+    let mut genes_out = config
+        .output_config
+        .create(config.output_filename("_initiation_genes.txt"))?;
+    let mut sites_out = config
+        .output_config
+        .create(config.output_filename("_initiation_sites.txt"))?;
+    write!(
+        genes_out,
+        "gene\ttranscript\tstart_psites\tcds_psites\tcds_density\tenrichment\n"
+    )?;
+    write!(
+        sites_out,
+        "gene\ttranscript\tposition\tvs_cds_start\tcodon\tclass\tpsites\tbackground\tenrichment\n"
+    )?;
+
+    let mut total = StartEnrichment::default();
+    let mut n_sites = 0;
+    let mut n_non_aug = 0;
+    for (trx, seq) in trx_seqs.iter() {
+        let cds = match trx.cds_range() {
+            Some(cds) => cds,
+            None => continue,
+        };
+        let counts = &trx_counts[trx.trxname_ref()];
+
+        let enrich = StartEnrichment::new(counts, cds);
+        total.add(&enrich);
+        writeln!(
+            genes_out,
+            "{}\t{}\t{}\t{}\t{:.3}\t{:.3}",
+            trx.gene(),
+            trx.trxname(),
+            enrich.start(),
+            enrich.body(),
+            enrich.body_density(),
+            enrich.enrichment()
+        )?;
+
+        for site in initiation_sites(
+            seq,
+            counts,
+            &codons,
+            config.min_reads,
+            config.min_enrichment,
+        ) {
+            n_sites += 1;
+            if site.codon != b"ATG" {
+                n_non_aug += 1;
+            }
+            writeln!(
+                sites_out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.3}\t{:.3}",
+                trx.gene(),
+                trx.trxname(),
+                site.pos,
+                site.pos as isize - cds.start as isize,
+                String::from_utf8_lossy(&site.codon),
+                SiteClass::new(site.pos, cds),
+                site.count,
+                site.background,
+                site.enrichment()
+            )?;
+        }
+    }
+
+    genes_out.finish()?;
+    sites_out.finish()?;
+
+    config.output_config.write(
+        config.output_filename("_initiation_summary.txt"),
+        format!(
+            "genes\t{}\nstart_psites\t{}\ncds_psites\t{}\nstart_enrichment\t{:.3}\n\
+             initiation_sites\t{}\nnon_aug_sites\t{}\n",
+            trx_seqs.len(),
+            total.start(),
+            total.body(),
+            total.enrichment(),
+            n_sites,
+            n_non_aug
+        ),
+    )?;
+    config.output_config.write(
+        config.output_filename("_filter_stats.txt"),
+        filter_stats.table(),
+    )?;
+
+    info!(
+        "Start codon P-site enrichment {:.2} across {} genes, {} candidate initiation sites ({} non-AUG)",
+        total.enrichment(),
+        trx_seqs.len(),
+        n_sites,
+        n_non_aug
+    );
+
+    Ok(())
+}
+
+/// Tallies the A site of each footprint at each position of the
+/// compatible transcripts. Only transcripts with an entry in
+/// `trx_counts`, holding one count per transcript position, are
+/// tallied, and a footprint is counted on every compatible isoform.
+///
+/// # Arguments
+///
+/// * `input` is the BAM file of footprint alignments
+/// * `read_filter` selects the alignments tallied
+/// * `count_multi` tallies the first alignment of multi-mapping reads
+///
+/// Returns the read filter statistics for the alignments.
+pub fn tally_asites<B: BamRead>(
+    input: &mut B,
+    trxome: &Transcriptome<Rc<String>>,
+    offsets: &SiteOffsets,
+    read_filter: &ReadFilter,
+    corrupt: &CorruptInputs,
+    count_multi: bool,
+    trx_counts: &mut HashMap<Rc<String>, Vec<usize>>,
+) -> Result<ReadFilterStats, failure::Error> {
+    tally_sites(
+        input,
+        trxome,
+        offsets,
+        Site::A,
+        read_filter,
+        corrupt,
+        count_multi,
+        trx_counts,
+    )
+}
+
+/// Tallies one ribosomal site of each footprint, as for
+/// `tally_asites`, e.g., the P site of initiating ribosomes.
+pub fn tally_sites<B: BamRead>(
+    input: &mut B,
+    trxome: &Transcriptome<Rc<String>>,
+    offsets: &SiteOffsets,
+    site: Site,
+    read_filter: &ReadFilter,
+    corrupt: &CorruptInputs,
+    count_multi: bool,
+    trx_counts: &mut HashMap<Rc<String>, Vec<usize>>,
+) -> Result<ReadFilterStats, failure::Error> {
+    let tids = {
+        let mut refids: RefIDSet<Rc<String>> = RefIDSet::new();
+        Tids::new(&mut refids, input.header())
+    };
+    tids.warn_missing(trxome);
+
+    let _timer = StageTimer::new("classification");
+    let mut filter_stats = ReadFilterStats::default();
+
+    for recres in spliced_records(input, &tids, corrupt) {
+        let (rec, fp) = recres?;
+
+        if !filter_stats.tally(read_filter, &rec)
+            || !(is_single_hit(&rec) || (count_multi && is_first_hit(&rec)))
+        {
+            continue;
+        }
+
+        let fp = match fp {
+            Some(fp) => fp,
+            None => continue,
+        };
+
+        for trx in trxome
+            .find_at_loc(&fp)
+            .filter(|trx| trx.loc().strand() == fp.strand())
+        {
+            if let Some(counts) = trx_counts.get_mut(trx.trxname_ref()) {
+                if let Some(site_pos) = offsets.site_positions(trx, &fp, site) {
+                    if let Some(ct) = counts.get_mut(site_pos.trx_pos().pos()) {
+                        *ct += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(filter_stats)
+}
@@ -0,0 +1,148 @@
+use std::ops::Range;
+
+use metagene::*;
+
+/// A-site counts in each reading frame of an ORF, used to test for the
+/// 3-nt periodicity expected of translating ribosomes.
+#[derive(Debug, Clone)]
+pub struct FrameSignal {
+    frames: Frame<usize>,
+}
+
+impl FrameSignal {
+    /// Tallies per-position A-site counts on a transcript within an
+    /// ORF, assigning each position to its frame relative to the ORF
+    /// start. Positions beyond the end of `counts` are treated as
+    /// empty.
+    pub fn new(counts: &[usize], orf: &Range<usize>) -> Self {
+        let mut frames = Frame::new_with_default();
+        for pos in orf.clone() {
+            if let Some(ct) = counts.get(pos) {
+                *frames.get_mut((pos - orf.start) as isize) += *ct;
+            }
+        }
+        FrameSignal { frames: frames }
+    }
+
+    pub fn frames(&self) -> &Frame<usize> {
+        &self.frames
+    }
+
+    /// Returns the total A-site count in the ORF.
+    pub fn total(&self) -> usize {
+        self.frames.iter().sum()
+    }
+
+    /// Returns the A-site count in the reading frame of the ORF.
+    pub fn in_frame(&self) -> usize {
+        *self.frames.get(0_isize)
+    }
+
+    /// Returns the fraction of A sites in the reading frame of the
+    /// ORF, or `NaN` when there are none.
+    pub fn in_frame_fraction(&self) -> f64 {
+        self.in_frame() as f64 / self.total() as f64
+    }
+
+    /// Returns the P-value for periodic signal in the reading frame of
+    /// the ORF. The null hypothesis is that A sites fall in each frame
+    /// with equal probability, and the one-sided binomial test asks
+    /// whether the ORF frame is enriched. Signal is never called
+    /// periodic, and the P-value is 1, unless the ORF frame has more A
+    /// sites than either other frame.
+    pub fn pvalue(&self) -> f64 {
+        let off_frame = (*self.frames.get(1_isize)).max(*self.frames.get(2_isize));
+        if self.in_frame() <= off_frame {
+            1.0
+        } else {
+            binomial_upper_tail(self.in_frame(), self.total(), 1.0 / 3.0)
+        }
+    }
+
+    /// Returns a Phred-scaled periodicity score, -10 log<sub>10</sub>
+    /// of the P-value, capped at 1000 for use as a BED score.
+    pub fn score(&self) -> usize {
+        phred_score(self.pvalue())
+    }
+}
+
+/// Returns a Phred-scaled score for a P-value, -10 log<sub>10</sub>
+/// of the P-value, capped at 1000 for use as a BED score.
+pub fn phred_score(pvalue: f64) -> usize {
+    let score = -10.0 * pvalue.log10();
+    if score.is_finite() {
+        score.round().max(0.0).min(1000.0) as usize
+    } else {
+        1000
+    }
+}
+
+/// Returns the probability that a binomial random variable with `n`
+/// trials and success probability `p` is at least `k`.
+///
+/// ```
+/// # use riboprof::orfs::binomial_upper_tail;
+/// assert!((binomial_upper_tail(2, 2, 0.5) - 0.25).abs() < 1e-12);
+/// assert!((binomial_upper_tail(1, 2, 0.5) - 0.75).abs() < 1e-12);
+/// ```
+pub fn binomial_upper_tail(k: usize, n: usize, p: f64) -> f64 {
+    if k == 0 {
+        return 1.0;
+    } else if k > n {
+        return 0.0;
+    }
+
+    // Log probabilities are accumulated from P(X = 0) using the ratio
+    // of successive terms, then summed relative to the largest term.
+    let ln_odds = (p / (1.0 - p)).ln();
+    let ln_term =
+        |ln_prev: f64, i: usize| ln_prev + ((n - i) as f64 / (i + 1) as f64).ln() + ln_odds;
+
+    let mut ln_pmf = n as f64 * (1.0 - p).ln();
+    for i in 0..k {
+        ln_pmf = ln_term(ln_pmf, i);
+    }
+
+    let mut ln_tail = Vec::with_capacity(n - k + 1);
+    for i in k..(n + 1) {
+        ln_tail.push(ln_pmf);
+        if i < n {
+            ln_pmf = ln_term(ln_pmf, i);
+        }
+    }
+
+    let ln_max = ln_tail
+        .iter()
+        .cloned()
+        .fold(::std::f64::NEG_INFINITY, f64::max);
+    let sum: f64 = ln_tail.iter().map(|ln| (ln - ln_max).exp()).sum();
+    (ln_max + sum.ln()).exp().min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_signal() {
+        //               0  1  2  3  4  5  6  7  8  9
+        let counts = vec![5, 9, 1, 0, 6, 2, 1, 8, 0, 1];
+        let signal = FrameSignal::new(&counts, &(1..10));
+        assert_eq!(
+            signal.frames().iter().cloned().collect::<Vec<_>>(),
+            vec![23, 3, 2]
+        );
+        assert_eq!(signal.total(), 28);
+        assert!((signal.pvalue() - 1.530082e-7).abs() < 1e-12);
+        assert_eq!(signal.score(), 68);
+
+        let off_frame = FrameSignal::new(&counts, &(0..9));
+        assert_eq!(off_frame.in_frame(), 6);
+        assert_eq!(off_frame.pvalue(), 1.0);
+        assert_eq!(off_frame.score(), 0);
+
+        assert!((binomial_upper_tail(3, 10, 0.2) - 0.3222004).abs() < 1e-6);
+        assert_eq!(binomial_upper_tail(0, 10, 0.2), 1.0);
+        assert_eq!(binomial_upper_tail(11, 10, 0.2), 0.0);
+    }
+}
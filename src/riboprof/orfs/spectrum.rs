@@ -0,0 +1,243 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use failure;
+
+use orfs::periodicity::*;
+
+/// Number of sine tapers in the multitaper spectrum estimate.
+pub const TAPERS: usize = 5;
+
+/// Test for 3-nt periodicity of A-site counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeriodicityMethod {
+    /// Binomial test for enrichment in the ORF reading frame
+    Frames,
+    /// Periodogram power at a period of 3 nt against white noise
+    Fft,
+    /// Multitaper harmonic F-test for a period of 3 nt
+    Multitaper,
+}
+
+impl PeriodicityMethod {
+    /// Returns the P-value for 3-nt periodicity of the counts within
+    /// `orf`, or 1 when the counts do not allow a test. As with the
+    /// binomial test of `FrameSignal`, the P-value is also 1 unless
+    /// the periodic signal peaks in the reading frame of the ORF, so
+    /// that an ORF in another frame of a translated CDS is not called.
+    pub fn pvalue(&self, counts: &[usize], orf: &Range<usize>) -> f64 {
+        let window = &counts[orf.start.min(counts.len())..orf.end.min(counts.len())];
+        let peak = match self {
+            PeriodicityMethod::Frames => return FrameSignal::new(counts, orf).pvalue(),
+            PeriodicityMethod::Fft => fft_periodicity(window),
+            PeriodicityMethod::Multitaper => multitaper_periodicity(window),
+        };
+        peak.filter(|peak| peak.frame == 0)
+            .map_or(1.0, |peak| peak.pvalue)
+    }
+}
+
+impl fmt::Display for PeriodicityMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            PeriodicityMethod::Frames => "frames",
+            PeriodicityMethod::Fft => "fft",
+            PeriodicityMethod::Multitaper => "multitaper",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for PeriodicityMethod {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "frames" => Ok(PeriodicityMethod::Frames),
+            "fft" => Ok(PeriodicityMethod::Fft),
+            "multitaper" => Ok(PeriodicityMethod::Multitaper),
+            _ => bail!(
+                "Unknown periodicity method \"{}\", expecting frames, fft, or multitaper",
+                s
+            ),
+        }
+    }
+}
+
+/// Strength of the 3-nt periodic component of a count vector, as a
+/// test statistic and its P-value, along with the frame, from 0 to 2
+/// relative to the first count, where the component peaks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpectralPeak {
+    pub statistic: f64,
+    pub pvalue: f64,
+    pub frame: usize,
+}
+
+/// Returns the frame, from 0 to 2, where the sinusoid with period 3
+/// and the transform `(re, im)` from `period3_transform` peaks.
+fn peak_frame(re: f64, im: f64) -> usize {
+    let phase = im.atan2(re);
+    let height = |n: usize| (2.0 * PI * n as f64 / 3.0 + phase).cos();
+    (0..3)
+        .max_by(|&a, &b| height(a).partial_cmp(&height(b)).unwrap())
+        .unwrap()
+}
+
+/// Sum of `x[n] exp(-2 pi i n / 3)`, weighted by `taper`, as real and
+/// imaginary parts.
+fn period3_transform<F>(x: &[f64], taper: F) -> (f64, f64)
+where
+    F: Fn(usize) -> f64,
+{
+    x.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, xn)| {
+        let phase = 2.0 * PI * n as f64 / 3.0;
+        let w = taper(n) * xn;
+        (re + w * phase.cos(), im - w * phase.sin())
+    })
+}
+
+fn centered(counts: &[usize]) -> Vec<f64> {
+    let mean = counts.iter().sum::<usize>() as f64 / counts.len() as f64;
+    counts.iter().map(|&ct| ct as f64 - mean).collect()
+}
+
+/// Tests the periodogram of the counts at a period of 3 nt. The
+/// statistic is the periodogram power relative to the mean power at
+/// all other frequencies, found from the total variance, which is
+/// exponentially distributed for white noise. `None` is returned for
+/// fewer than 3 positions or constant counts.
+///
+/// ```
+/// # use riboprof::orfs::fft_periodicity;
+/// let counts: Vec<usize> = [10, 1, 1].iter().cycle().take(60).cloned().collect();
+/// assert!(fft_periodicity(&counts).unwrap().pvalue < 1e-10);
+/// ```
+pub fn fft_periodicity(counts: &[usize]) -> Option<SpectralPeak> {
+    let len = counts.len();
+    if len < 3 {
+        return None;
+    }
+    let x = centered(counts);
+
+    let noise = x.iter().map(|xn| xn * xn).sum::<f64>() * len as f64 / (len - 1) as f64;
+    if noise <= 0.0 {
+        return None;
+    }
+
+    let (re, im) = period3_transform(&x, |_| 1.0);
+    let ratio = (re * re + im * im) / noise;
+    Some(SpectralPeak {
+        statistic: ratio,
+        pvalue: (-ratio).exp(),
+        frame: peak_frame(re, im),
+    })
+}
+
+/// Tests for a sinusoid with a period of 3 nt by Thomson's harmonic
+/// F-test on a multitaper spectrum estimate, using `TAPERS` sine
+/// tapers. The statistic follows an F distribution with 2 and
+/// 2(`TAPERS` - 1) degrees of freedom under the null hypothesis of a
+/// smooth spectrum. `None` is returned for fewer than 3 positions per
+/// taper or constant counts.
+pub fn multitaper_periodicity(counts: &[usize]) -> Option<SpectralPeak> {
+    let len = counts.len();
+    if len < 3 * TAPERS {
+        return None;
+    }
+    let x = centered(counts);
+    if x.iter().all(|&xn| xn == 0.0) {
+        return None;
+    }
+
+    let norm = (2.0 / (len + 1) as f64).sqrt();
+    let tapers: Vec<(f64, (f64, f64))> = (1..TAPERS + 1)
+        .map(|k| {
+            let taper = |n: usize| norm * (PI * (k * (n + 1)) as f64 / (len + 1) as f64).sin();
+            let dc: f64 = (0..len).map(&taper).sum();
+            (dc, period3_transform(&x, &taper))
+        })
+        .collect();
+
+    let dc_power: f64 = tapers.iter().map(|(dc, _)| dc * dc).sum();
+    let mu = tapers
+        .iter()
+        .fold((0.0, 0.0), |(re, im), (dc, (j_re, j_im))| {
+            (re + dc * j_re / dc_power, im + dc * j_im / dc_power)
+        });
+    let residual: f64 = tapers
+        .iter()
+        .map(|(dc, (j_re, j_im))| (j_re - mu.0 * dc).powi(2) + (j_im - mu.1 * dc).powi(2))
+        .sum();
+
+    let df = (TAPERS - 1) as f64;
+    let fstat = df * (mu.0 * mu.0 + mu.1 * mu.1) * dc_power / residual;
+    let pvalue = if fstat.is_finite() {
+        (1.0 + fstat / df).powf(-df)
+    } else {
+        0.0
+    };
+    Some(SpectralPeak {
+        statistic: fstat,
+        pvalue: pvalue,
+        frame: peak_frame(mu.0, mu.1),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn periodicity_spectrum() {
+        let periodic: Vec<usize> = [10, 1, 1].iter().cycle().take(60).cloned().collect();
+        let alternating: Vec<usize> = [5, 0].iter().cycle().take(60).cloned().collect();
+
+        let fft = fft_periodicity(&periodic).unwrap();
+        assert!((fft.statistic - 29.5).abs() < 1e-9);
+        assert!(fft_periodicity(&alternating).unwrap().pvalue > 0.99);
+        assert_eq!(fft_periodicity(&[3, 3, 3, 3]), None);
+
+        assert!(multitaper_periodicity(&periodic).unwrap().pvalue < 1e-15);
+        let mt = multitaper_periodicity(&alternating).unwrap();
+        assert!((mt.statistic - 0.88146).abs() < 1e-4);
+        assert!(mt.pvalue > 0.4);
+        assert_eq!(multitaper_periodicity(&periodic[0..12]), None);
+
+        let orf = 3..63;
+        let mut counts = vec![0; 3];
+        counts.extend(periodic.iter());
+        assert!(PeriodicityMethod::Multitaper.pvalue(&counts, &orf) < 1e-15);
+        assert_eq!(PeriodicityMethod::Fft.pvalue(&counts, &(0..2)), 1.0);
+        assert_eq!(
+            "multitaper".parse::<PeriodicityMethod>().unwrap(),
+            PeriodicityMethod::Multitaper
+        );
+    }
+
+    #[test]
+    fn periodicity_frame() {
+        let shifted: Vec<usize> = [1, 10, 1].iter().cycle().take(61).cloned().collect();
+
+        assert_eq!(fft_periodicity(&shifted[0..60]).unwrap().frame, 1);
+        assert_eq!(fft_periodicity(&shifted[1..61]).unwrap().frame, 0);
+        assert_eq!(fft_periodicity(&shifted[2..32]).unwrap().frame, 2);
+        assert_eq!(multitaper_periodicity(&shifted[0..60]).unwrap().frame, 1);
+        assert_eq!(multitaper_periodicity(&shifted[1..61]).unwrap().frame, 0);
+
+        // Strongly periodic, but in frame +1 of the ORF
+        let off_frame = 0..60;
+        for method in [
+            PeriodicityMethod::Frames,
+            PeriodicityMethod::Fft,
+            PeriodicityMethod::Multitaper,
+        ]
+        .iter()
+        {
+            assert_eq!(method.pvalue(&shifted, &off_frame), 1.0);
+            assert!(method.pvalue(&shifted, &(1..61)) < 1e-10);
+        }
+    }
+}
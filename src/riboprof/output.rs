@@ -0,0 +1,645 @@
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::os::raw::{c_char, c_void};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use bzip2::read::MultiBzDecoder;
+use clap::{Arg, ArgMatches};
+use failure;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Crc;
+
+use rust_htslib::htslib;
+
+/// Default size of the buffer on each output file, in bytes.
+pub const DEFAULT_BUFFER_SIZE: usize = 1 << 16;
+
+/// Compression of text output files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    /// Standard gzip, readable by `zcat`
+    Gzip,
+    /// Blocked gzip, readable by `zcat` and indexable by `tabix`
+    Bgzf,
+}
+
+impl Compression {
+    /// Returns the suffix added to the names of compressed files.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip | Compression::Bgzf => ".gz",
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Bgzf => "bgzf",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Compression {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "bgzf" | "bgzip" => Ok(Compression::Bgzf),
+            _ => bail!(
+                "Unknown compression \"{}\", expecting none, gzip, or bgzf",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct OutputCLI {
+    pub compress: String,
+    pub buffer_size: String,
+}
+
+impl Default for OutputCLI {
+    fn default() -> Self {
+        OutputCLI {
+            compress: Compression::None.to_string(),
+            buffer_size: DEFAULT_BUFFER_SIZE.to_string(),
+        }
+    }
+}
+
+/// Returns the arguments controlling text output files, shared by
+/// tools writing large tables.
+pub fn output_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("compress")
+            .long("compress")
+            .value_name("COMPRESSION")
+            .help("Compression of text output files, which gain a .gz suffix")
+            .takes_value(true)
+            .possible_values(&["none", "gzip", "bgzf"])
+            .default_value("none"),
+        Arg::with_name("buffer-size")
+            .long("buffer-size")
+            .value_name("BYTES")
+            .help("Buffer size for each output file")
+            .takes_value(true)
+            .default_value("65536"),
+    ]
+}
+
+/// Collects the values of the `output_args`.
+pub fn output_cli(matches: &ArgMatches) -> OutputCLI {
+    OutputCLI {
+        compress: matches.value_of("compress").unwrap_or("none").to_string(),
+        buffer_size: matches
+            .value_of("buffer-size")
+            .map_or_else(|| DEFAULT_BUFFER_SIZE.to_string(), str::to_string),
+    }
+}
+
+/// Compression and buffering for text output files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputConfig {
+    compression: Compression,
+    buffer_size: usize,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig::new()
+    }
+}
+
+impl OutputConfig {
+    /// Creates a configuration writing uncompressed files with the
+    /// default buffer size.
+    pub fn new() -> Self {
+        OutputConfig {
+            compression: Compression::None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+        }
+    }
+
+    pub fn new_from_cli(cli: &OutputCLI) -> Result<Self, failure::Error> {
+        let buffer_size: usize = cli.buffer_size.parse()?;
+        if buffer_size == 0 {
+            bail!("Output buffer size must be positive");
+        }
+        Ok(OutputConfig::new()
+            .compression(cli.compress.parse()?)
+            .buffer_size(buffer_size))
+    }
+
+    pub fn compression(self, compression: Compression) -> Self {
+        OutputConfig {
+            compression: compression,
+            ..self
+        }
+    }
+
+    pub fn buffer_size(self, buffer_size: usize) -> Self {
+        OutputConfig {
+            buffer_size: buffer_size,
+            ..self
+        }
+    }
+
+    pub fn get_compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Returns the name of the file written for `path`, with the
+    /// suffix of the compression format added.
+    ///
+    /// ```
+    /// # use riboprof::output::*;
+    /// # use std::path::PathBuf;
+    /// let output = OutputConfig::new().compression(Compression::Bgzf);
+    /// assert_eq!(output.path("out_genes.txt"), PathBuf::from("out_genes.txt.gz"));
+    /// ```
+    pub fn path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let mut name = path.as_ref().as_os_str().to_os_string();
+        name.push(self.compression.suffix());
+        PathBuf::from(name)
+    }
+
+    /// Creates an output file for `path`, named as by `path()`.
+    pub fn create<P: AsRef<Path>>(&self, path: P) -> Result<OutputWriter, failure::Error> {
+        let path = self.path(path);
+        let encoder = match self.compression {
+            Compression::None => Encoder::Plain(fs::File::create(&path)?),
+            Compression::Gzip => Encoder::Gzip(GzEncoder::new(
+                fs::File::create(&path)?,
+                ::flate2::Compression::default(),
+            )),
+            Compression::Bgzf => Encoder::Bgzf(BgzfWriter::create(&path)?),
+        };
+        Ok(OutputWriter {
+            inner: BufWriter::with_capacity(self.buffer_size, encoder),
+        })
+    }
+
+    /// Writes `contents` as the entire output file for `path`, in the
+    /// manner of `fs::write()`.
+    pub fn write<P, C>(&self, path: P, contents: C) -> Result<(), failure::Error>
+    where
+        P: AsRef<Path>,
+        C: AsRef<[u8]>,
+    {
+        let mut out = self.create(path)?;
+        out.write_all(contents.as_ref())?;
+        out.finish()?;
+        Ok(())
+    }
+}
+
+/// Buffered, possibly compressed, output file created by
+/// `OutputConfig::create()`. Dropping the writer finishes the file
+/// but discards any error; `finish()` reports errors.
+pub struct OutputWriter {
+    inner: BufWriter<Encoder>,
+}
+
+impl OutputWriter {
+    /// Flushes buffered output and writes the end of the compressed
+    /// stream.
+    pub fn finish(self) -> io::Result<()> {
+        self.inner.into_inner().map_err(io::Error::from)?.finish()
+    }
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+enum Encoder {
+    Plain(fs::File),
+    Gzip(GzEncoder<fs::File>),
+    Bgzf(BgzfWriter),
+}
+
+impl Encoder {
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(mut file) => file.flush(),
+            Encoder::Gzip(gz) => gz.finish().and_then(|mut file| file.flush()),
+            Encoder::Bgzf(bgzf) => bgzf.close(),
+        }
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Plain(file) => file.write(buf),
+            Encoder::Gzip(gz) => gz.write(buf),
+            Encoder::Bgzf(bgzf) => bgzf.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(file) => file.flush(),
+            Encoder::Gzip(gz) => gz.flush(),
+            Encoder::Bgzf(bgzf) => bgzf.flush(),
+        }
+    }
+}
+
+/// BGZF file written through htslib.
+struct BgzfWriter {
+    inner: *mut htslib::BGZF,
+}
+
+impl BgzfWriter {
+    fn create(path: &Path) -> io::Result<Self> {
+        let cpath = CString::new(path.to_string_lossy().as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let inner = unsafe { htslib::bgzf_open(cpath.as_ptr(), b"w\0".as_ptr() as *const c_char) };
+        if inner.is_null() {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Unable to create BGZF file {}", path.display()),
+            ))
+        } else {
+            Ok(BgzfWriter { inner: inner })
+        }
+    }
+
+    fn close(mut self) -> io::Result<()> {
+        let inner = self.inner;
+        self.inner = ptr::null_mut();
+        if unsafe { htslib::bgzf_close(inner) } < 0 {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Error closing BGZF file",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Write for BgzfWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written =
+            unsafe { htslib::bgzf_write(self.inner, buf.as_ptr() as *const c_void, buf.len()) };
+        if written < 0 {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Error writing BGZF file",
+            ))
+        } else {
+            Ok(written as usize)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if unsafe { htslib::bgzf_flush(self.inner) } < 0 {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Error flushing BGZF file",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Drop for BgzfWriter {
+    fn drop(&mut self) {
+        if !self.inner.is_null() {
+            unsafe {
+                htslib::bgzf_close(self.inner);
+            }
+        }
+    }
+}
+
+/// Writer that gzip compresses its output, or passes it through
+/// unchanged, as chosen at run time. Clones share one stream, so the
+/// compressed stream can be finished after the writer has been moved
+/// into a buffered writer, as with a `Checksum`.
+pub struct MaybeGzWriter<W: Write> {
+    stream: Rc<RefCell<MaybeGz<W>>>,
+}
+
+enum MaybeGz<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+}
+
+impl<W: Write> MaybeGzWriter<W> {
+    pub fn new(inner: W, gzip: bool) -> Self {
+        let stream = if gzip {
+            MaybeGz::Gzip(GzEncoder::new(inner, ::flate2::Compression::default()))
+        } else {
+            MaybeGz::Plain(inner)
+        };
+        MaybeGzWriter {
+            stream: Rc::new(RefCell::new(stream)),
+        }
+    }
+
+    /// Writes the end of the compressed stream and flushes the inner
+    /// writer. Any buffered writer wrapping a clone must be flushed
+    /// first, and nothing more may be written afterwards.
+    pub fn finish(&self) -> io::Result<()> {
+        match *self.stream.borrow_mut() {
+            MaybeGz::Plain(ref mut inner) => inner.flush(),
+            MaybeGz::Gzip(ref mut gz) => {
+                gz.try_finish()?;
+                gz.get_mut().flush()
+            }
+        }
+    }
+}
+
+impl<W: Write> Clone for MaybeGzWriter<W> {
+    fn clone(&self) -> Self {
+        MaybeGzWriter {
+            stream: self.stream.clone(),
+        }
+    }
+}
+
+impl<W: Write> Write for MaybeGzWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self.stream.borrow_mut() {
+            MaybeGz::Plain(ref mut inner) => inner.write(buf),
+            MaybeGz::Gzip(ref mut gz) => gz.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self.stream.borrow_mut() {
+            MaybeGz::Plain(ref mut inner) => inner.flush(),
+            MaybeGz::Gzip(ref mut gz) => gz.flush(),
+        }
+    }
+}
+
+/// Running CRC32 checksum and length of the data written through a
+/// `ChecksumWriter`. Clones share one tally, so the checksum can be
+/// read after the writer has been moved into a buffered writer.
+#[derive(Clone)]
+pub struct Checksum {
+    tally: Rc<RefCell<(Crc, u64)>>,
+}
+
+impl Checksum {
+    pub fn new() -> Self {
+        Checksum {
+            tally: Rc::new(RefCell::new((Crc::new(), 0))),
+        }
+    }
+
+    /// Returns the checksum of `data`.
+    pub fn new_from_bytes(data: &[u8]) -> Self {
+        let checksum = Checksum::new();
+        checksum.update(data);
+        checksum
+    }
+
+    /// Returns the checksum of the contents of the file at `path`.
+    pub fn new_from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let checksum = Checksum::new();
+        io::copy(&mut fs::File::open(path)?, &mut checksum.writer(io::sink()))?;
+        Ok(checksum)
+    }
+
+    /// Returns a writer that passes data through to `inner` and adds
+    /// it to this checksum.
+    pub fn writer<W: Write>(&self, inner: W) -> ChecksumWriter<W> {
+        ChecksumWriter {
+            inner: inner,
+            checksum: self.clone(),
+        }
+    }
+
+    fn update(&self, data: &[u8]) {
+        let mut tally = self.tally.borrow_mut();
+        tally.0.update(data);
+        tally.1 += data.len() as u64;
+    }
+
+    /// Returns the CRC32 checksum of the data written so far.
+    pub fn crc32(&self) -> u32 {
+        self.tally.borrow().0.sum()
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn bytes(&self) -> u64 {
+        self.tally.borrow().1
+    }
+}
+
+/// Writer that adds everything written through it to a `Checksum`.
+pub struct ChecksumWriter<W> {
+    inner: W,
+    checksum: Checksum,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.checksum.update(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Compression of an input file, detected from its first bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputCompression {
+    None,
+    /// Gzip or BGZF, possibly with several concatenated members
+    Gzip,
+    Bzip2,
+}
+
+impl InputCompression {
+    /// Detects the compression of a file beginning with `magic`.
+    pub fn new_from_magic(magic: &[u8]) -> Self {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            InputCompression::Gzip
+        } else if magic.starts_with(b"BZh") {
+            InputCompression::Bzip2
+        } else {
+            InputCompression::None
+        }
+    }
+}
+
+/// Returns a reader that decompresses `inner` when it is gzip, BGZF,
+/// or bzip2 compressed, judging by its first bytes rather than a file
+/// suffix so that compressed standard input is also detected.
+pub fn decompressed<R: Read + 'static>(inner: R) -> io::Result<Box<Read>> {
+    let mut buffered = BufReader::with_capacity(DEFAULT_BUFFER_SIZE, inner);
+    let compression = InputCompression::new_from_magic(buffered.fill_buf()?);
+    Ok(match compression {
+        InputCompression::None => Box::new(buffered),
+        InputCompression::Gzip => Box::new(MultiGzDecoder::new(buffered)),
+        InputCompression::Bzip2 => Box::new(MultiBzDecoder::new(buffered)),
+    })
+}
+
+/// Opens an input file, or standard input when `path` is `-`, and
+/// decompresses it as needed.
+pub fn open_input<P: AsRef<Path>>(path: P) -> io::Result<Box<Read>> {
+    if path.as_ref() == Path::new("-") {
+        decompressed(io::stdin())
+    } else {
+        decompressed(fs::File::open(path)?)
+    }
+}
+
+/// Reads a text file written by an `OutputWriter`, decompressing it
+/// when it is gzip or BGZF compressed.
+pub fn read_text<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut text = String::new();
+        MultiGzDecoder::new(bytes.as_slice()).read_to_string(&mut text)?;
+        Ok(text)
+    } else {
+        String::from_utf8(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn checksums() {
+        let checksum = Checksum::new();
+        {
+            let mut writer = checksum.writer(Vec::new());
+            writer.write_all(b"The quick brown fox ").unwrap();
+            writer.write_all(b"jumps over the lazy dog").unwrap();
+        }
+        assert_eq!(checksum.bytes(), 43);
+        assert_eq!(checksum.crc32(), 0x414fa339);
+        assert_eq!(
+            Checksum::new_from_bytes(b"The quick brown fox jumps over the lazy dog").crc32(),
+            0x414fa339
+        );
+    }
+
+    #[test]
+    fn compressed_outputs() {
+        let dir = env::temp_dir().join(format!("riboprof-output-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let table = "gene\tcount\nYAL001C\t17\n".repeat(1000);
+
+        for compression in [Compression::None, Compression::Gzip, Compression::Bgzf].iter() {
+            let output = OutputConfig::new()
+                .compression(*compression)
+                .buffer_size(100);
+            let path = dir.join(format!("{}_genes.txt", compression));
+            output.write(&path, &table).unwrap();
+
+            let written = output.path(&path);
+            assert_eq!(written.exists(), true);
+            assert_eq!(read_text(&written).unwrap(), table);
+            if *compression != Compression::None {
+                assert!(fs::metadata(&written).unwrap().len() < table.len() as u64);
+            }
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!("zip".parse::<Compression>().is_err());
+    }
+
+    #[test]
+    fn compressed_inputs() {
+        let fastq = "@read\nACGT\n+\nIIII\n".repeat(100);
+        let read_all = |bytes: Vec<u8>| {
+            let mut text = String::new();
+            decompressed(io::Cursor::new(bytes))
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        };
+
+        assert_eq!(read_all(fastq.clone().into_bytes()), fastq);
+
+        let mut gz = GzEncoder::new(Vec::new(), ::flate2::Compression::default());
+        gz.write_all(fastq.as_bytes()).unwrap();
+        let mut gz = gz.finish().unwrap();
+        assert_eq!(
+            InputCompression::new_from_magic(&gz),
+            InputCompression::Gzip
+        );
+        let member = gz.clone();
+        gz.extend(member);
+        assert_eq!(read_all(gz), fastq.repeat(2));
+
+        let mut bz = ::bzip2::write::BzEncoder::new(Vec::new(), ::bzip2::Compression::Default);
+        bz.write_all(fastq.as_bytes()).unwrap();
+        let bz = bz.finish().unwrap();
+        assert_eq!(
+            InputCompression::new_from_magic(&bz),
+            InputCompression::Bzip2
+        );
+        assert_eq!(read_all(bz), fastq);
+
+        assert_eq!(read_all(Vec::new()), "");
+    }
+
+    #[test]
+    fn maybe_gz_writer() {
+        for &gzip in [false, true].iter() {
+            let checksum = Checksum::new();
+            let stream = MaybeGzWriter::new(checksum.writer(Vec::new()), gzip);
+            {
+                let mut buffered = BufWriter::new(stream.clone());
+                buffered.write_all(b"@read\nACGT\n+\nIIII\n").unwrap();
+            }
+            stream.finish().unwrap();
+
+            let written = match Rc::try_unwrap(stream.stream).ok().unwrap().into_inner() {
+                MaybeGz::Plain(inner) => inner.inner,
+                MaybeGz::Gzip(gz) => gz.finish().unwrap().inner,
+            };
+            assert_eq!(checksum.bytes(), written.len() as u64);
+            let mut text = String::new();
+            decompressed(io::Cursor::new(written))
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            assert_eq!(text, "@read\nACGT\n+\nIIII\n");
+        }
+    }
+}
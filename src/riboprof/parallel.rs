@@ -0,0 +1,185 @@
+use clap::Arg;
+use failure;
+use rayon;
+use rayon::prelude::*;
+
+/// Number of items, e.g., reads or alignment groups, in each batch
+/// handed to a worker thread.
+pub const DEFAULT_BATCH_SIZE: usize = 4096;
+
+/// Returns the `--threads` argument shared by tools that process
+/// records in parallel with `Parallel`.
+pub fn threads_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("threads")
+        .long("threads")
+        .value_name("N")
+        .help("Worker threads for processing records")
+        .takes_value(true)
+        .default_value("1")
+}
+
+/// Batched parallel processing of a stream of records.
+///
+/// Records are read sequentially and collected into batches, each
+/// batch is processed by a `map` function on a pool of worker threads,
+/// and the results are handed back, in the original order, to a `sink`
+/// on the calling thread. The sink typically writes output records and
+/// merges the statistics from each batch, so output is identical
+/// regardless of the number of threads.
+pub struct Parallel {
+    pool: Option<rayon::ThreadPool>,
+    threads: usize,
+    batch_size: usize,
+}
+
+impl Parallel {
+    /// Creates a parallel processor with `threads` worker threads. With
+    /// 0 or 1 threads, batches are processed on the calling thread.
+    pub fn new(threads: usize) -> Result<Self, failure::Error> {
+        let pool = if threads > 1 {
+            Some(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Parallel {
+            pool: pool,
+            threads: threads.max(1),
+            batch_size: DEFAULT_BATCH_SIZE,
+        })
+    }
+
+    /// Sets the number of records in each batch.
+    pub fn batch_size(self, batch_size: usize) -> Self {
+        Parallel {
+            batch_size: batch_size.max(1),
+            ..self
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Processes every record from `records` in batches.
+    ///
+    /// # Arguments
+    ///
+    /// * `records` yields records, or errors that stop processing
+    /// * `map` processes one batch of records on a worker thread
+    /// * `sink` receives the result of each batch, in order
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned for the first error from
+    /// `records`, `map`, or `sink`.
+    pub fn map_batches<I, T, E, U, M, S>(
+        &self,
+        records: I,
+        map: M,
+        mut sink: S,
+    ) -> Result<(), failure::Error>
+    where
+        I: IntoIterator<Item = Result<T, E>>,
+        E: Into<failure::Error>,
+        T: Send,
+        U: Send,
+        M: Fn(Vec<T>) -> Result<U, failure::Error> + Sync,
+        S: FnMut(U) -> Result<(), failure::Error>,
+    {
+        let mut records = records.into_iter();
+
+        loop {
+            let batches = self.read_batches(&mut records)?;
+            if batches.is_empty() {
+                return Ok(());
+            }
+
+            let results: Vec<Result<U, failure::Error>> = match self.pool {
+                Some(ref pool) => pool.install(|| batches.into_par_iter().map(&map).collect()),
+                None => batches.into_iter().map(&map).collect(),
+            };
+
+            for result in results {
+                sink(result?)?;
+            }
+        }
+    }
+
+    /// Reads enough batches to keep every worker thread busy, stopping
+    /// early at the end of `records`.
+    fn read_batches<I, T, E>(&self, records: &mut I) -> Result<Vec<Vec<T>>, failure::Error>
+    where
+        I: Iterator<Item = Result<T, E>>,
+        E: Into<failure::Error>,
+    {
+        let mut batches = Vec::new();
+
+        while batches.len() < 2 * self.threads {
+            let mut batch = Vec::with_capacity(self.batch_size);
+            while batch.len() < self.batch_size {
+                match records.next() {
+                    Some(record) => batch.push(record.map_err(|err| err.into())?),
+                    None => break,
+                }
+            }
+
+            if batch.is_empty() {
+                break;
+            }
+            let is_full = batch.len() == self.batch_size;
+            batches.push(batch);
+            if !is_full {
+                break;
+            }
+        }
+
+        Ok(batches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch_sums(threads: usize) -> (Vec<usize>, usize) {
+        let records = (0..100).map(Ok::<usize, failure::Error>);
+        let mut sums = Vec::new();
+        let mut total = 0;
+        Parallel::new(threads)
+            .unwrap()
+            .batch_size(8)
+            .map_batches(
+                records,
+                |batch| Ok(batch.iter().sum::<usize>()),
+                |sum| {
+                    sums.push(sum);
+                    total += sum;
+                    Ok(())
+                },
+            )
+            .unwrap();
+        (sums, total)
+    }
+
+    #[test]
+    fn ordered_batches() {
+        let (sums, total) = batch_sums(1);
+        assert_eq!(sums.len(), 13);
+        assert_eq!(sums[0], (0..8).sum());
+        assert_eq!(sums[12], 96 + 97 + 98 + 99);
+        assert_eq!(total, 4950);
+        assert_eq!(batch_sums(4), (sums, total));
+
+        let records = vec![Ok(1), Err(failure::err_msg("Bad record")), Ok(2)];
+        let res =
+            Parallel::new(2)
+                .unwrap()
+                .map_batches(records, |batch| Ok(batch.len()), |_| Ok(()));
+        assert!(res.is_err());
+    }
+}
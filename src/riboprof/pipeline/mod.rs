@@ -0,0 +1,225 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use failure;
+
+use bam_suppress_duplicates;
+use codon_count;
+use corrupt::*;
+use fastx_split;
+use fp_framing;
+use timing::*;
+
+mod project;
+mod state;
+
+pub use pipeline::project::*;
+pub use pipeline::state::*;
+
+pub struct CLI {
+    pub project: String,
+    pub dry_run: bool,
+    pub restart: bool,
+    pub corrupt: CorruptInputs,
+}
+
+pub struct Config {
+    project: Project,
+    dry_run: bool,
+    restart: bool,
+    corrupt: CorruptInputs,
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        Ok(Config {
+            project: Project::new_from_file(&cli.project)?,
+            dry_run: cli.dry_run,
+            restart: cli.restart,
+            corrupt: cli.corrupt.clone(),
+        })
+    }
+
+    fn step_dir(&self, step: Step) -> PathBuf {
+        self.project.output_dir().join(step.to_string())
+    }
+
+    fn split_fastq(&self, sample: &str) -> PathBuf {
+        self.step_dir(Step::Split).join(format!("{}.fastq", sample))
+    }
+
+    fn aligned_bam(&self, sample: &str) -> PathBuf {
+        self.step_dir(Step::Align).join(format!("{}.bam", sample))
+    }
+
+    fn dedup_bam(&self, sample: &str) -> PathBuf {
+        self.step_dir(Step::Dedup).join(format!("{}.bam", sample))
+    }
+
+    fn framing_base(&self, sample: &str) -> PathBuf {
+        self.step_dir(Step::Framing).join(sample)
+    }
+
+    fn estimated_asites(&self, sample: &str) -> PathBuf {
+        self.step_dir(Step::Framing)
+            .join(format!("{}_asites.txt", sample))
+    }
+}
+
+/// Runs every pipeline step that has not already completed, in order,
+/// for each sample. Progress is saved after each step, so a failed or
+/// interrupted run resumes where it stopped.
+pub fn run_pipeline(config: Config) -> Result<(), failure::Error> {
+    let output_dir = config.project.output_dir();
+    fs::DirBuilder::new().recursive(true).create(&output_dir)?;
+
+    let mut state = PipelineState::load(output_dir.join("pipeline_state.json"))?;
+    if config.restart && !config.dry_run {
+        state.clear()?;
+    }
+
+    for &step in Step::ALL.iter() {
+        let samples: Vec<Option<&str>> = if step.is_project_wide() {
+            vec![None]
+        } else {
+            config
+                .project
+                .samples
+                .iter()
+                .map(|sample| Some(sample.name.as_str()))
+                .collect()
+        };
+
+        for sample in samples {
+            let label = match sample {
+                Some(sample) => format!("{} {}", step, sample),
+                None => step.to_string(),
+            };
+
+            if state.is_done(step, sample) && !config.restart {
+                info!("Skipping {} (already complete)", label);
+                continue;
+            } else if config.dry_run {
+                info!("Would run {}", label);
+                continue;
+            }
+
+            info!("Running {}", label);
+            fs::DirBuilder::new()
+                .recursive(true)
+                .create(config.step_dir(step))?;
+            run_step(&config, step, sample)
+                .map_err(|err| format_err!("Step {} failed: {}", label, err))?;
+            state.mark_done(step, sample)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn run_step(config: &Config, step: Step, sample: Option<&str>) -> Result<(), failure::Error> {
+    match (step, sample) {
+        (Step::Split, _) => run_split(config),
+        (Step::Align, Some(sample)) => run_align(config, sample),
+        (Step::Dedup, Some(sample)) => run_dedup(config, sample),
+        (Step::Framing, Some(sample)) => run_framing(config, sample),
+        (Step::CodonCount, Some(sample)) => run_codon_count(config, sample),
+        (_, None) => bail!("No sample for per-sample step"),
+    }
+}
+
+fn run_split(config: &Config) -> Result<(), failure::Error> {
+    let split_dir = config.step_dir(Step::Split);
+    let sample_sheet = config.project.output_dir().join("samples.csv");
+    fs::write(&sample_sheet, config.project.sample_sheet())?;
+
+    let cli = fastx_split::CLI {
+        fastx_inputs: config.project.fastq.clone(),
+        output_dir: split_dir.to_string_lossy().into_owned(),
+        min_insert: config.project.linkers.min_insert,
+        max_insert: config.project.linkers.max_insert,
+        prefix: config.project.linkers.prefix.clone(),
+        suffix: config.project.linkers.suffix.clone(),
+        sample_sheet: sample_sheet.to_string_lossy().into_owned(),
+        threads: config.project.threads.to_string(),
+        corrupt: config.corrupt.clone(),
+        ..Default::default()
+    };
+    fastx_split::fastx_split(fastx_split::Config::new(&cli)?)
+}
+
+fn run_align(config: &Config, sample: &str) -> Result<(), failure::Error> {
+    let bam = config.aligned_bam(sample);
+    let command = config
+        .project
+        .align_command(sample, &config.split_fastq(sample), &bam);
+    debug!("Alignment command: {}", command);
+
+    let _timer = StageTimer::new("alignment");
+    let status = Command::new("sh").arg("-c").arg(&command).status()?;
+    if !status.success() {
+        bail!("Alignment command exited with {}", status);
+    }
+    if !bam.exists() {
+        bail!("Alignment command did not create {:?}", bam);
+    }
+    Ok(())
+}
+
+fn run_dedup(config: &Config, sample: &str) -> Result<(), failure::Error> {
+    let dedup_dir = config.step_dir(Step::Dedup);
+    let cli = bam_suppress_duplicates::CLI {
+        bam_input: config.aligned_bam(sample).to_string_lossy().into_owned(),
+        bam_output: config.dedup_bam(sample).to_string_lossy().into_owned(),
+        stats: Some(path_string(
+            &dedup_dir.join(format!("{}_dedup_stats.txt", sample)),
+        )),
+        stats_json: Some(path_string(
+            &dedup_dir.join(format!("{}_dedup_stats.json", sample)),
+        )),
+        allow_untagged: true,
+        threads: config.project.threads.to_string(),
+        io_threads: config.project.threads.to_string(),
+        corrupt: config.corrupt.clone(),
+        ..Default::default()
+    };
+    bam_suppress_duplicates::bam_suppress_duplicates(bam_suppress_duplicates::Config::new(&cli)?)
+}
+
+fn run_framing(config: &Config, sample: &str) -> Result<(), failure::Error> {
+    let cli = fp_framing::CLI {
+        input: path_string(&config.dedup_bam(sample)),
+        output: path_string(&config.framing_base(sample)),
+        bed: vec![config.project.annotation.bed.clone()],
+        lengths: config.project.annotation.lengths.clone(),
+        write_asites: Some(path_string(&config.estimated_asites(sample))),
+        threads: config.project.threads.to_string(),
+        io_threads: config.project.threads.to_string(),
+        corrupt: config.corrupt.clone(),
+        ..Default::default()
+    };
+    fp_framing::run_fp_framing(fp_framing::Config::new(&cli)?)
+}
+
+fn run_codon_count(config: &Config, sample: &str) -> Result<(), failure::Error> {
+    let asites = match config.project.annotation.asites {
+        Some(ref asites) => asites.clone(),
+        None => path_string(&config.estimated_asites(sample)),
+    };
+
+    let cli = codon_count::CLI {
+        inputs: vec![path_string(&config.dedup_bam(sample))],
+        output: path_string(&config.step_dir(Step::CodonCount).join(sample)),
+        bed: vec![config.project.annotation.bed.clone()],
+        asites: asites,
+        lengths: config.project.annotation.lengths.clone(),
+        corrupt: config.corrupt.clone(),
+        ..Default::default()
+    };
+    codon_count::run_codon_count(codon_count::Config::new(&cli)?)
+}
+
+fn path_string(path: &PathBuf) -> String {
+    path.to_string_lossy().into_owned()
+}
@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use failure;
+use toml;
+
+/// Project description for `riboprof run`, read from a TOML file.
+///
+/// ```toml
+/// output_dir = "work"
+/// fastq = ["lane1.fastq"]
+///
+/// [linkers]
+/// suffix = "NNNNNNCACTCGGGCACCAAGGAC"
+///
+/// [annotation]
+/// bed = "genes.bed"
+///
+/// [align]
+/// command = "bowtie2 -p {threads} -x genome -U {fastq} | samtools view -b -o {bam} -"
+///
+/// [[sample]]
+/// name = "wt"
+/// index = "ACGT"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Project {
+    pub output_dir: String,
+    /// Raw sequencing reads, split by sample index
+    pub fastq: Vec<String>,
+    #[serde(default)]
+    pub threads: usize,
+    #[serde(default)]
+    pub linkers: Linkers,
+    pub annotation: Annotation,
+    pub align: Align,
+    #[serde(rename = "sample")]
+    pub samples: Vec<ProjectSample>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Linkers {
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub suffix: String,
+    #[serde(default)]
+    pub min_insert: usize,
+    #[serde(default)]
+    pub max_insert: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Annotation {
+    pub bed: String,
+    /// A site offsets used for codon counting; when absent, offsets
+    /// estimated by fp-framing are used for each sample.
+    pub asites: Option<String>,
+    #[serde(default = "Annotation::default_lengths")]
+    pub lengths: String,
+}
+
+impl Annotation {
+    fn default_lengths() -> String {
+        "26,34".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Align {
+    /// Shell command template aligning one sample, with `{sample}`,
+    /// `{fastq}`, `{bam}`, and `{threads}` placeholders.
+    pub command: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProjectSample {
+    pub name: String,
+    pub index: String,
+}
+
+impl Project {
+    /// Reads and validates a project file.
+    pub fn new_from_file<P: AsRef<Path>>(path: P) -> Result<Self, failure::Error> {
+        Self::new_from_toml(&::std::fs::read_to_string(path)?)
+    }
+
+    /// Parses and validates a project description.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when the TOML is malformed, when
+    /// there are no samples or sample names are repeated, or when the
+    /// alignment command lacks a `{fastq}` or `{bam}` placeholder.
+    pub fn new_from_toml(project_toml: &str) -> Result<Self, failure::Error> {
+        let project: Project = toml::from_str(project_toml)?;
+
+        if project.samples.is_empty() {
+            bail!("No samples in project");
+        }
+        let mut names = HashSet::new();
+        for sample in project.samples.iter() {
+            if sample.name.is_empty() || sample.name.contains('/') {
+                bail!("Bad sample name \"{}\"", sample.name);
+            }
+            if !names.insert(&sample.name) {
+                bail!("Duplicate sample name \"{}\"", sample.name);
+            }
+        }
+        for placeholder in ["{fastq}", "{bam}"].iter() {
+            if !project.align.command.contains(placeholder) {
+                bail!("Alignment command lacks {} placeholder", placeholder);
+            }
+        }
+
+        Ok(project)
+    }
+
+    pub fn output_dir(&self) -> PathBuf {
+        PathBuf::from(&self.output_dir)
+    }
+
+    /// Returns the sample sheet text for fastx-split.
+    pub fn sample_sheet(&self) -> String {
+        self.samples
+            .iter()
+            .map(|sample| format!("{},{}\n", sample.name, sample.index))
+            .collect()
+    }
+
+    /// Returns the alignment command for one sample.
+    pub fn align_command(&self, sample: &str, fastq: &Path, bam: &Path) -> String {
+        self.align
+            .command
+            .replace("{sample}", sample)
+            .replace("{fastq}", &fastq.to_string_lossy())
+            .replace("{bam}", &bam.to_string_lossy())
+            .replace("{threads}", &self.threads.max(1).to_string())
+    }
+}
+
+/// Steps of the pipeline, in order of execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Step {
+    /// Split raw reads by sample index and trim linkers (all samples)
+    Split,
+    /// Align reads with the external alignment command
+    Align,
+    /// Suppress PCR duplicates
+    Dedup,
+    /// Footprint framing and A site offset estimation
+    Framing,
+    /// Codon-level footprint counts
+    CodonCount,
+}
+
+impl Step {
+    pub const ALL: [Step; 5] = [
+        Step::Split,
+        Step::Align,
+        Step::Dedup,
+        Step::Framing,
+        Step::CodonCount,
+    ];
+
+    /// Returns `true` for steps run once for the whole project, rather
+    /// than once per sample.
+    pub fn is_project_wide(&self) -> bool {
+        *self == Step::Split
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Step::Split => "split",
+            Step::Align => "align",
+            Step::Dedup => "dedup",
+            Step::Framing => "framing",
+            Step::CodonCount => "codon-count",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Step {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Step::ALL
+            .iter()
+            .find(|step| step.to_string() == s)
+            .cloned()
+            .ok_or_else(|| format_err!("Unknown pipeline step \"{}\"", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROJECT: &str = "output_dir = \"work\"\n\
+                           fastq = [\"lane1.fastq\"]\n\
+                           threads = 4\n\
+                           [linkers]\n\
+                           suffix = \"NNNNNNCACTCGGGCACCAAGGAC\"\n\
+                           [annotation]\n\
+                           bed = \"genes.bed\"\n\
+                           [align]\n\
+                           command = \"align -p {threads} {fastq} > {bam}\"\n\
+                           [[sample]]\n\
+                           name = \"wt\"\n\
+                           index = \"ACGT\"\n\
+                           [[sample]]\n\
+                           name = \"ko\"\n\
+                           index = \"TGCA\"\n";
+
+    #[test]
+    fn parse_project() {
+        let project = Project::new_from_toml(PROJECT).unwrap();
+        assert_eq!(project.samples.len(), 2);
+        assert_eq!(project.linkers.prefix, "");
+        assert_eq!(project.annotation.lengths, "26,34");
+        assert!(project.annotation.asites.is_none());
+        assert_eq!(project.sample_sheet(), "wt,ACGT\nko,TGCA\n");
+        assert_eq!(
+            project.align_command("wt", Path::new("split/wt.fastq"), Path::new("align/wt.bam")),
+            "align -p 4 split/wt.fastq > align/wt.bam"
+        );
+
+        let dup = PROJECT.replace("name = \"ko\"", "name = \"wt\"");
+        assert!(Project::new_from_toml(&dup).is_err());
+        let no_bam = PROJECT.replace("> {bam}", "");
+        assert!(Project::new_from_toml(&no_bam).is_err());
+
+        assert_eq!("codon-count".parse::<Step>().unwrap(), Step::CodonCount);
+        assert!("count".parse::<Step>().is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use failure;
+use serde_json;
+
+use pipeline::project::Step;
+
+/// Completed pipeline steps, saved after every step so that an
+/// interrupted run resumes after the last completed step.
+#[derive(Debug, Clone)]
+pub struct PipelineState {
+    path: PathBuf,
+    completed: BTreeSet<String>,
+}
+
+impl PipelineState {
+    /// Loads the pipeline state from `path`, starting with no
+    /// completed steps when the file does not exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, failure::Error> {
+        let path = path.as_ref().to_path_buf();
+        let completed = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)?,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(PipelineState {
+            path: path,
+            completed: completed,
+        })
+    }
+
+    fn key(step: Step, sample: Option<&str>) -> String {
+        match sample {
+            Some(sample) => format!("{}:{}", step, sample),
+            None => step.to_string(),
+        }
+    }
+
+    /// Returns `true` when a step has completed, either for one sample
+    /// or, when `sample` is `None`, for the whole project.
+    pub fn is_done(&self, step: Step, sample: Option<&str>) -> bool {
+        self.completed.contains(&Self::key(step, sample))
+    }
+
+    /// Records a completed step and saves the state. The state file is
+    /// replaced atomically, so an interruption never leaves it
+    /// truncated.
+    pub fn mark_done(&mut self, step: Step, sample: Option<&str>) -> Result<(), failure::Error> {
+        self.completed.insert(Self::key(step, sample));
+        self.save()
+    }
+
+    /// Forgets every completed step and saves the state.
+    pub fn clear(&mut self) -> Result<(), failure::Error> {
+        self.completed.clear();
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), failure::Error> {
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&self.completed)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn resume_state() {
+        let path = env::temp_dir().join(format!("riboprof_state_test_{}.json", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut state = PipelineState::load(&path).unwrap();
+        assert!(!state.is_done(Step::Split, None));
+        state.mark_done(Step::Split, None).unwrap();
+        state.mark_done(Step::Align, Some("wt")).unwrap();
+
+        let reloaded = PipelineState::load(&path).unwrap();
+        assert!(reloaded.is_done(Step::Split, None));
+        assert!(reloaded.is_done(Step::Align, Some("wt")));
+        assert!(!reloaded.is_done(Step::Align, Some("ko")));
+        assert!(!reloaded.is_done(Step::Dedup, Some("wt")));
+
+        state.clear().unwrap();
+        assert!(!PipelineState::load(&path)
+            .unwrap()
+            .is_done(Step::Split, None));
+        fs::remove_file(&path).unwrap();
+    }
+}
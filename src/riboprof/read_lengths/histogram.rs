@@ -0,0 +1,106 @@
+use std::fmt::Display;
+
+use metagene::*;
+
+/// Read length histograms for several classes of reads, such as the
+/// annotated region containing each read.
+pub struct LengthHistogram<C> {
+    classes: Vec<C>,
+    profiles: Vec<LenProfile<usize>>,
+}
+
+impl<C: Clone + Eq + Display> LengthHistogram<C> {
+    /// Creates empty histograms for each class, tallying lengths from
+    /// `minlen` to `maxlen` individually.
+    pub fn new(classes: &[C], minlen: usize, maxlen: usize) -> Self {
+        LengthHistogram {
+            classes: classes.to_vec(),
+            profiles: vec![LenProfile::new(minlen, maxlen, 0); classes.len()],
+        }
+    }
+
+    /// Tallies a read of length `len` in class `class`.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `class` is not one of the classes of the
+    /// histogram.
+    pub fn tally(&mut self, class: &C, len: usize) {
+        let idx = self
+            .classes
+            .iter()
+            .position(|c| c == class)
+            .expect("Unknown read class");
+        *self.profiles[idx].get_mut(len) += 1;
+    }
+
+    /// Returns the total number of reads tallied.
+    pub fn total(&self) -> usize {
+        self.profiles
+            .iter()
+            .map(|profile| profile.iter().sum::<usize>())
+            .sum()
+    }
+
+    /// Returns a table with one row for each length, giving the count
+    /// of reads in each class followed by the total over all classes.
+    /// Classes with no reads are omitted. The first and last rows
+    /// tally all reads shorter and longer than the length range.
+    pub fn table(&self) -> String {
+        let used: Vec<usize> = (0..self.classes.len())
+            .filter(|&idx| self.profiles[idx].iter().any(|&ct| ct > 0))
+            .collect();
+
+        let mut table = "length".to_string();
+        for &idx in used.iter() {
+            table += &format!("\t{}", self.classes[idx]);
+        }
+        table += "\ttotal\n";
+
+        let mut profile_iters: Vec<_> = used.iter().map(|&idx| self.profiles[idx].iter()).collect();
+        let labels = self
+            .profiles
+            .first()
+            .into_iter()
+            .flat_map(|profile| profile.named_iter().map(|(label, _)| label));
+        for label in labels {
+            table += &label;
+            let mut total = 0;
+            for profile_iter in profile_iters.iter_mut() {
+                let ct = profile_iter.next().cloned().unwrap_or(0);
+                table += &format!("\t{}", ct);
+                total += ct;
+            }
+            table += &format!("\t{}\n", total);
+        }
+
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_table() {
+        let mut hist = LengthHistogram::new(&["CDS", "UTR", "ncRNA"], 28, 30);
+        hist.tally(&"CDS", 28);
+        hist.tally(&"CDS", 28);
+        hist.tally(&"CDS", 30);
+        hist.tally(&"ncRNA", 29);
+        hist.tally(&"ncRNA", 35);
+        hist.tally(&"CDS", 12);
+        assert_eq!(hist.total(), 6);
+
+        assert_eq!(
+            hist.table(),
+            "length\tCDS\tncRNA\ttotal\n\
+             <28\t1\t0\t1\n\
+             28\t2\t0\t2\n\
+             29\t0\t1\t1\n\
+             30\t1\t0\t1\n\
+             ≥31\t0\t1\t1\n"
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use failure;
+
+use bio::io::fastq;
+use bio_types::annot::loc::Loc;
+use bio_types::annot::refids::RefIDSet;
+use rust_htslib::bam;
+use rust_htslib::bam::Read as BamRead;
+
+use bam_utils::*;
+use cli_utils::*;
+use corrupt::*;
+use output::*;
+use timing::*;
+use transcript::*;
+
+mod histogram;
+mod region;
+
+pub use read_lengths::histogram::*;
+pub use read_lengths::region::*;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CLI {
+    #[serde(skip_serializing)]
+    pub input: String,
+    pub output: String,
+    pub format: Option<String>,
+    pub bed: Option<String>,
+    pub lengths: String,
+    pub io_threads: String,
+    #[serde(flatten)]
+    pub read_filter: ReadFilterCLI,
+    #[serde(flatten)]
+    pub output_config: OutputCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+pub struct Config {
+    input: String,
+    output: PathBuf,
+    format: InputFormat,
+    trxome: Option<Transcriptome<Rc<String>>>,
+    lengths: Range<usize>,
+    io_threads: usize,
+    read_filter: ReadFilter,
+    output_config: OutputConfig,
+    corrupt: CorruptInputs,
+}
+
+/// Input file formats for read length histograms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Fastq,
+    Bam,
+}
+
+impl InputFormat {
+    /// Guesses the format of a file from its name, treating `.bam`
+    /// files as BAM and everything else as FASTQ.
+    pub fn from_filename(name: &str) -> Self {
+        if name.to_lowercase().ends_with(".bam") {
+            InputFormat::Bam
+        } else {
+            InputFormat::Fastq
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fastq" => Ok(InputFormat::Fastq),
+            "bam" => Ok(InputFormat::Bam),
+            _ => bail!("Unknown input format \"{}\"", s),
+        }
+    }
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        let format = match cli.format {
+            Some(ref format) => format.parse()?,
+            None => InputFormat::from_filename(&cli.input),
+        };
+
+        let trxome = match cli.bed {
+            Some(ref bed) => {
+                if format != InputFormat::Bam {
+                    bail!("Classifying reads by annotated region requires BAM input");
+                }
+                let mut refids = RefIDSet::new();
+                Some(Transcriptome::new_from_file(bed, &mut refids)?)
+            }
+            None => None,
+        };
+
+        Ok(Config {
+            input: cli.input.to_string(),
+            output: Path::new(&cli.output).to_path_buf(),
+            format: format,
+            trxome: trxome,
+            lengths: parse_pair(&cli.lengths)?,
+            io_threads: cli.io_threads.parse()?,
+            read_filter: ReadFilter::new_from_cli(&cli.read_filter)?
+                .exclude_flags(NON_PRIMARY_FLAGS),
+            output_config: OutputConfig::new_from_cli(&cli.output_config)?,
+            corrupt: cli.corrupt.clone(),
+        })
+    }
+
+    fn output_filename(&self, suffix: &str) -> PathBuf {
+        output_filename(&self.output, suffix)
+    }
+}
+
+pub fn run_read_lengths(config: Config) -> Result<(), failure::Error> {
+    // Open (empty) output file early to detect errors before processing data.
+    config
+        .output_config
+        .create(config.output_filename("_lengths.txt"))?;
+
+    let table = match config.format {
+        InputFormat::Fastq => fastq_lengths(&config)?,
+        InputFormat::Bam => bam_lengths(&config)?,
+    };
+
+    let _timer = StageTimer::new("output");
+    config
+        .output_config
+        .write(config.output_filename("_lengths.txt"), table)?;
+
+    Ok(())
+}
+
+/// Returns the length histogram table for all reads in a FASTQ file.
+fn fastq_lengths(config: &Config) -> Result<String, failure::Error> {
+    let input_reader = open_input(&config.input)?;
+
+    let _timer = StageTimer::new("classification");
+    let mut hist = LengthHistogram::new(&["all"], config.lengths.start, config.lengths.end);
+
+    for fqres in timed_iter(
+        "FASTQ read",
+        config
+            .corrupt
+            .fastq_records(fastq::Reader::new(input_reader)),
+    ) {
+        let fq = fqres?;
+        hist.tally(&"all", fq.seq().len());
+    }
+
+    info!("Tallied {} reads", hist.total());
+
+    Ok(hist.table())
+}
+
+/// Returns the length histogram table for reads in a BAM file,
+/// stratified by annotated region when an annotation is given.
+fn bam_lengths(config: &Config) -> Result<String, failure::Error> {
+    let mut input = open_bam(&config.input, &config.corrupt)?;
+    set_reader_threads(&mut input, config.io_threads)?;
+
+    let tids = {
+        let mut refids: RefIDSet<Rc<String>> = RefIDSet::new();
+        Tids::new(&mut refids, input.header())
+    };
+    if let Some(ref trxome) = config.trxome {
+        tids.warn_missing(trxome);
+    }
+
+    let _timer = StageTimer::new("classification");
+    let mut hist = LengthHistogram::new(&Region::ALL, config.lengths.start, config.lengths.end);
+    let mut filter_stats = ReadFilterStats::default();
+
+    for recres in spliced_records(&mut input, &tids, &config.corrupt) {
+        let (rec, fp) = recres?;
+
+        if !filter_stats.tally(&config.read_filter, &rec) {
+            continue;
+        }
+
+        let region = match fp {
+            Some(ref fp) if !rec.is_unmapped() => match config.trxome {
+                Some(ref trxome) => Region::classify(trxome, &fp.first_pos()),
+                None => Region::Aligned,
+            },
+            _ => Region::Unaligned,
+        };
+        hist.tally(&region, rec.seq().len());
+    }
+
+    config.output_config.write(
+        config.output_filename("_filter_stats.txt"),
+        filter_stats.table(),
+    )?;
+
+    info!("Tallied {} reads", hist.total());
+
+    Ok(hist.table())
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+use std::hash::Hash;
+
+use bio_types::annot::pos::Pos;
+use bio_types::strand::ReqStrand;
+
+use transcript::*;
+
+/// Annotated region containing a read, used to stratify read length
+/// distributions. Regions are ordered by priority, so that a read in
+/// the CDS of one transcript and the UTR of another is counted as
+/// CDS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Region {
+    Cds,
+    Utr5,
+    Utr3,
+    Noncoding,
+    NoGene,
+    /// Aligned read, when no annotation is available
+    Aligned,
+    Unaligned,
+}
+
+impl Region {
+    /// Every region, in priority order.
+    pub const ALL: [Region; 7] = [
+        Region::Cds,
+        Region::Utr5,
+        Region::Utr3,
+        Region::Noncoding,
+        Region::NoGene,
+        Region::Aligned,
+        Region::Unaligned,
+    ];
+
+    /// Returns the region containing a genomic position, typically
+    /// the 5' end of a read. Only transcripts on the same strand are
+    /// considered.
+    pub fn classify<R: Eq + Hash>(trxome: &Transcriptome<R>, pos: &Pos<R, ReqStrand>) -> Self {
+        TrxPos::transcriptome_pos(trxome, pos)
+            .map(|trxpos| match *trxpos.transcript().cds_range() {
+                Some(ref cds) if trxpos.pos() < cds.start => Region::Utr5,
+                Some(ref cds) if trxpos.pos() >= cds.end => Region::Utr3,
+                Some(_) => Region::Cds,
+                None => Region::Noncoding,
+            })
+            .min()
+            .unwrap_or(Region::NoGene)
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Region::Cds => "CDS",
+            Region::Utr5 => "UTR5",
+            Region::Utr3 => "UTR3",
+            Region::Noncoding => "ncRNA",
+            Region::NoGene => "NoGene",
+            Region::Aligned => "Aligned",
+            Region::Unaligned => "Unaligned",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use test_fixtures::coding_noncoding_trxome;
+
+    fn classify(pos_str: &str) -> Region {
+        let pos: Pos<Rc<String>, ReqStrand> = pos_str.parse().unwrap();
+        Region::classify(&coding_noncoding_trxome(), &pos)
+    }
+
+    #[test]
+    fn classify_forward() {
+        assert_eq!(classify("chr01:120(+)"), Region::Utr5);
+        assert_eq!(classify("chr01:200(+)"), Region::Cds);
+        assert_eq!(classify("chr01:320(+)"), Region::Cds);
+        assert_eq!(classify("chr01:380(+)"), Region::Utr3);
+        assert_eq!(classify("chr01:450(+)"), Region::Noncoding);
+        assert_eq!(classify("chr01:200(-)"), Region::NoGene);
+    }
+
+    #[test]
+    fn classify_reverse() {
+        assert_eq!(classify("chr01:880(-)"), Region::Utr5);
+        assert_eq!(classify("chr01:620(-)"), Region::Utr3);
+        assert_eq!(classify("chr01:700(-)"), Region::Cds);
+    }
+}
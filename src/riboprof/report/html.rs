@@ -0,0 +1,98 @@
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; color: #222; }\n\
+                     h1 { border-bottom: 2px solid #1b6ca8; }\n\
+                     h2 { margin-top: 2em; border-bottom: 1px solid #ccc; }\n\
+                     table { border-collapse: collapse; margin: 1em 0; }\n\
+                     th, td { border: 1px solid #ccc; padding: 2px 8px; text-align: right; }\n\
+                     th:first-child, td:first-child { text-align: left; }\n\
+                     th { background: #eef3f8; }\n\
+                     .plot { display: inline-block; margin: 0.5em; vertical-align: top; }\n";
+
+/// Self-contained HTML document assembled from headings, tables, and
+/// inline SVG plots.
+pub struct HtmlReport {
+    title: String,
+    body: String,
+}
+
+impl HtmlReport {
+    pub fn new(title: &str) -> Self {
+        HtmlReport {
+            title: title.to_string(),
+            body: String::new(),
+        }
+    }
+
+    /// Starts a new top-level section.
+    pub fn section(&mut self, title: &str) {
+        self.body += &format!("<h2>{}</h2>\n", escape(title));
+    }
+
+    /// Adds a subheading within the current section.
+    pub fn subsection(&mut self, title: &str) {
+        self.body += &format!("<h3>{}</h3>\n", escape(title));
+    }
+
+    /// Adds a paragraph of text.
+    pub fn paragraph(&mut self, text: &str) {
+        self.body += &format!("<p>{}</p>\n", escape(text));
+    }
+
+    /// Adds a table with a header row. Cells are escaped.
+    pub fn table<S: AsRef<str>>(&mut self, header: &[&str], rows: &[Vec<S>]) {
+        self.body += "<table>\n<tr>";
+        for col in header.iter() {
+            self.body += &format!("<th>{}</th>", escape(col));
+        }
+        self.body += "</tr>\n";
+        for row in rows.iter() {
+            self.body += "<tr>";
+            for cell in row.iter() {
+                self.body += &format!("<td>{}</td>", escape(cell.as_ref()));
+            }
+            self.body += "</tr>\n";
+        }
+        self.body += "</table>\n";
+    }
+
+    /// Adds a plot rendered as inline SVG, with a caption.
+    pub fn plot(&mut self, caption: &str, svg: &str) {
+        self.body += &format!(
+            "<div class=\"plot\"><div>{}</div>\n{}</div>\n",
+            escape(caption),
+            svg
+        );
+    }
+
+    /// Returns the complete HTML document.
+    pub fn finish(&self) -> String {
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>{0}</title>\n<style>\n{1}</style>\n</head>\n<body>\n\
+             <h1>{0}</h1>\n{2}</body>\n</html>\n",
+            escape(&self.title),
+            STYLE,
+            self.body
+        )
+    }
+}
+
+/// Escapes text for inclusion in HTML content or attribute values.
+///
+/// ```
+/// # use riboprof::report::escape;
+/// assert_eq!(escape("a<b & \"c\""), "a&lt;b &amp; &quot;c&quot;");
+/// ```
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
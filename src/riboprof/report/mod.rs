@@ -0,0 +1,286 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use failure;
+
+use bam_suppress_duplicates;
+use fastx_split::SplitFates;
+use fp_framing::FramingStats;
+use metagene::*;
+use output::*;
+use timing::*;
+
+mod html;
+mod svg;
+
+pub use report::html::*;
+pub use report::svg::*;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CLI {
+    pub output: String,
+    pub title: String,
+    #[serde(rename = "split")]
+    pub split_dirs: Vec<String>,
+    pub dedup: Vec<String>,
+    pub framing: Vec<String>,
+}
+
+pub struct Config {
+    output: PathBuf,
+    title: String,
+    split_dirs: Vec<PathBuf>,
+    dedup: Vec<PathBuf>,
+    framing: Vec<PathBuf>,
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        if cli.split_dirs.is_empty() && cli.dedup.is_empty() && cli.framing.is_empty() {
+            bail!("No statistics to report");
+        }
+
+        Ok(Config {
+            output: Path::new(&cli.output).to_path_buf(),
+            title: cli.title.to_string(),
+            split_dirs: cli.split_dirs.iter().map(PathBuf::from).collect(),
+            dedup: cli.dedup.iter().map(PathBuf::from).collect(),
+            framing: cli.framing.iter().map(PathBuf::from).collect(),
+        })
+    }
+}
+
+/// Suffix of the JSON statistics written by `fp-framing`.
+pub const FRAMING_SUFFIX: &str = "_framing_stats.json";
+
+/// Suffixes stripped from duplicate statistics filenames, in order, to
+/// find the sample name.
+pub const DEDUP_SUFFIXES: [&str; 4] = [".json", "_dedup_stats", "_stats", "_dedup"];
+
+pub fn run_report(config: Config) -> Result<(), failure::Error> {
+    let mut report = HtmlReport::new(&config.title);
+
+    if !config.split_dirs.is_empty() {
+        report.section("Sample splitting");
+        for split_dir in config.split_dirs.iter() {
+            split_section(&mut report, split_dir)?;
+        }
+    }
+
+    if !config.dedup.is_empty() {
+        report.section("Duplicate suppression");
+        dedup_section(&mut report, &config.dedup)?;
+    }
+
+    if !config.framing.is_empty() {
+        report.section("Footprint framing");
+        framing_section(&mut report, &config.framing)?;
+    }
+
+    timed("output", || fs::write(&config.output, report.finish()))?;
+
+    Ok(())
+}
+
+/// Returns the sample name for a statistics file, the filename with
+/// any of `suffixes` removed in turn.
+pub fn sample_name(path: &Path, suffixes: &[&str]) -> String {
+    let mut name = path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    for suffix in suffixes.iter() {
+        if name.ends_with(suffix) && name.len() > suffix.len() {
+            let len = name.len() - suffix.len();
+            name.truncate(len);
+        }
+    }
+    name
+}
+
+fn split_section(report: &mut HtmlReport, split_dir: &Path) -> Result<(), failure::Error> {
+    let fates: SplitFates = read_text(split_dir.join("fates.txt"))?.parse()?;
+    let total = fates.total() as f64;
+
+    let mut samples: Vec<(&str, &str, u64)> = fates
+        .samples()
+        .iter()
+        .map(|sample| (sample.name.as_str(), sample.index.as_str(), sample.reads))
+        .collect();
+    samples.push(("short", "N/A", fates.short()));
+    if let Some(long) = fates.long() {
+        samples.push(("long", "N/A", long));
+    }
+    if let Some(lowqual) = fates.lowqual() {
+        samples.push(("lowqual", "N/A", lowqual));
+    }
+
+    report.subsection(&split_dir.to_string_lossy());
+    let rows: Vec<Vec<String>> = samples
+        .iter()
+        .map(|&(name, index, reads)| {
+            vec![
+                name.to_string(),
+                index.to_string(),
+                reads.to_string(),
+                percent(reads as f64, total),
+            ]
+        })
+        .collect();
+    report.table(&["Sample", "Index", "Reads", "Fraction"], &rows);
+
+    let labels: Vec<String> = samples
+        .iter()
+        .map(|&(name, _, _)| name.to_string())
+        .collect();
+    let reads: Vec<f64> = samples.iter().map(|&(_, _, reads)| reads as f64).collect();
+    report.plot("Reads per sample", &bar_chart(&labels, &reads, "Reads"));
+
+    Ok(())
+}
+
+fn dedup_section(report: &mut HtmlReport, files: &[PathBuf]) -> Result<(), failure::Error> {
+    let mut rows = Vec::new();
+    let mut labels = Vec::new();
+    let mut unique = Vec::new();
+
+    for file in files.iter() {
+        let stats = bam_suppress_duplicates::Stats::from_json(&read_text(file)?)?;
+        let name = sample_name(file, &DEDUP_SUFFIXES);
+        let unique_fract = stats.unique_reads() as f64 / stats.total_reads() as f64;
+
+        rows.push(vec![
+            name.to_string(),
+            stats.total_reads().to_string(),
+            stats.unique_reads().to_string(),
+            stats.dupl_reads().to_string(),
+            percent(stats.unique_reads() as f64, stats.total_reads() as f64),
+            stats.untagged_reads().to_string(),
+        ]);
+        labels.push(name);
+        unique.push(100.0 * unique_fract);
+    }
+
+    report.table(
+        &[
+            "Sample",
+            "Tagged reads",
+            "Unique",
+            "Duplicates",
+            "Unique fraction",
+            "Untagged reads",
+        ],
+        &rows,
+    );
+    report.plot("Unique reads (%)", &bar_chart(&labels, &unique, "% unique"));
+
+    Ok(())
+}
+
+fn framing_section(report: &mut HtmlReport, files: &[PathBuf]) -> Result<(), failure::Error> {
+    let mut samples = Vec::new();
+    for file in files.iter() {
+        let stats = FramingStats::from_json(&read_text(file)?)?;
+        samples.push((sample_name(file, &[FRAMING_SUFFIX]), stats));
+    }
+
+    let rows: Vec<Vec<String>> = samples
+        .iter()
+        .map(|(name, stats)| {
+            let align = stats.align_stats();
+            let total = align.total() as f64;
+            vec![
+                name.to_string(),
+                align.total().to_string(),
+                align.unmapped().to_string(),
+                align.multi_hit().to_string(),
+                align.short().to_string(),
+                align.long().to_string(),
+                align.good_total().to_string(),
+                percent(align.good_total() as f64, total),
+            ]
+        })
+        .collect();
+    report.table(
+        &[
+            "Sample",
+            "Alignments",
+            "No hit",
+            "Multi hit",
+            "Too short",
+            "Too long",
+            "Good",
+            "Good fraction",
+        ],
+        &rows,
+    );
+
+    for (name, stats) in samples.iter() {
+        report.subsection(name);
+
+        let frame_length = stats.frame_length();
+        let labels: Vec<String> = frame_length.named_iter().map(|(label, _)| label).collect();
+        let frames: Vec<(&str, Vec<f64>)> = ["Frame 0", "Frame 1", "Frame 2"]
+            .iter()
+            .enumerate()
+            .map(|(fr, frame_name)| {
+                (
+                    *frame_name,
+                    frame_length
+                        .iter()
+                        .map(|frame| *frame.get(fr as isize) as f64)
+                        .collect(),
+                )
+            })
+            .collect();
+        report.plot(
+            "Footprint length and frame",
+            &stacked_bar_chart(&labels, &frames, "Footprints"),
+        );
+
+        report.plot(
+            "Footprints around start codons",
+            &metagene_chart(stats.around_start()),
+        );
+        report.plot(
+            "Footprints around stop codons",
+            &metagene_chart(stats.around_end()),
+        );
+    }
+
+    Ok(())
+}
+
+fn metagene_chart(metagene: &Metagene<LenProfile<usize>>) -> String {
+    let (xs, totals): (Vec<isize>, Vec<f64>) = metagene
+        .pos_iter()
+        .map(|(pos, profile)| (pos, profile.iter().sum::<usize>() as f64))
+        .unzip();
+    line_chart(&xs, &[("", totals)], "Footprints")
+}
+
+fn percent(part: f64, total: f64) -> String {
+    if total > 0.0 {
+        format!("{:.2}%", 100.0 * part / total)
+    } else {
+        "N/A".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_inputs() {
+        assert_eq!(
+            sample_name(Path::new("out/wt_framing_stats.json"), &[FRAMING_SUFFIX]),
+            "wt"
+        );
+        assert_eq!(
+            sample_name(Path::new("wt_dedup_stats.json"), &DEDUP_SUFFIXES),
+            "wt"
+        );
+        assert_eq!(sample_name(Path::new("wt.json"), &DEDUP_SUFFIXES), "wt");
+    }
+}
@@ -0,0 +1,228 @@
+use report::html::escape;
+
+const WIDTH: f64 = 640.0;
+const HEIGHT: f64 = 240.0;
+const MARGIN_LEFT: f64 = 56.0;
+const MARGIN_RIGHT: f64 = 16.0;
+const MARGIN_TOP: f64 = 16.0;
+const MARGIN_BOTTOM: f64 = 48.0;
+
+/// Fill colors for successive series, e.g., reading frames 0, 1, and 2.
+pub const SERIES_COLORS: [&str; 4] = ["#1b6ca8", "#e07b39", "#7f7f7f", "#4caf50"];
+
+/// Plot area and y-axis scaling shared by every chart.
+struct Axes {
+    ymax: f64,
+}
+
+impl Axes {
+    fn new(ymax: f64) -> Self {
+        Axes {
+            ymax: if ymax > 0.0 && ymax.is_finite() {
+                ymax
+            } else {
+                1.0
+            },
+        }
+    }
+
+    fn plot_width(&self) -> f64 {
+        WIDTH - MARGIN_LEFT - MARGIN_RIGHT
+    }
+
+    fn plot_height(&self) -> f64 {
+        HEIGHT - MARGIN_TOP - MARGIN_BOTTOM
+    }
+
+    fn y(&self, value: f64) -> f64 {
+        MARGIN_TOP + self.plot_height() * (1.0 - value / self.ymax)
+    }
+
+    fn open(&self, ylabel: &str) -> String {
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             font-family=\"sans-serif\" font-size=\"10\">\n",
+            WIDTH, HEIGHT
+        );
+        svg += &format!(
+            "<line x1=\"{0}\" y1=\"{1}\" x2=\"{0}\" y2=\"{2}\" stroke=\"black\"/>\n",
+            MARGIN_LEFT,
+            MARGIN_TOP,
+            HEIGHT - MARGIN_BOTTOM
+        );
+        svg += &format!(
+            "<line x1=\"{0}\" y1=\"{1}\" x2=\"{2}\" y2=\"{1}\" stroke=\"black\"/>\n",
+            MARGIN_LEFT,
+            HEIGHT - MARGIN_BOTTOM,
+            WIDTH - MARGIN_RIGHT
+        );
+        for tick in 0..5 {
+            let value = self.ymax * tick as f64 / 4.0;
+            svg += &format!(
+                "<text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>\n",
+                MARGIN_LEFT - 4.0,
+                self.y(value) + 3.0,
+                tick_label(value)
+            );
+        }
+        svg += &format!(
+            "<text transform=\"translate(12,{:.1}) rotate(-90)\" text-anchor=\"middle\">{}</text>\n",
+            MARGIN_TOP + self.plot_height() / 2.0,
+            escape(ylabel)
+        );
+        svg
+    }
+}
+
+fn tick_label(value: f64) -> String {
+    if value >= 1e6 {
+        format!("{:.1}M", value / 1e6)
+    } else if value >= 1e3 {
+        format!("{:.1}k", value / 1e3)
+    } else if value.fract() == 0.0 {
+        format!("{}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+/// Renders a bar chart with one bar per label.
+pub fn bar_chart(labels: &[String], values: &[f64], ylabel: &str) -> String {
+    stacked_bar_chart(labels, &[("", values.to_vec())], ylabel)
+}
+
+/// Renders a stacked bar chart with one bar per label and one segment
+/// per series. Each series is a name, shown in a legend unless it is
+/// empty, and one value per label.
+pub fn stacked_bar_chart(labels: &[String], series: &[(&str, Vec<f64>)], ylabel: &str) -> String {
+    let totals: Vec<f64> = (0..labels.len())
+        .map(|i| {
+            series
+                .iter()
+                .map(|(_, vals)| vals.get(i).cloned().unwrap_or(0.0))
+                .sum()
+        })
+        .collect();
+    let axes = Axes::new(totals.iter().cloned().fold(0.0, f64::max));
+    let mut svg = axes.open(ylabel);
+
+    let slot = axes.plot_width() / labels.len().max(1) as f64;
+    let bar_width = (slot * 0.8).max(1.0);
+    for (i, label) in labels.iter().enumerate() {
+        let x = MARGIN_LEFT + slot * i as f64 + (slot - bar_width) / 2.0;
+        let mut base = 0.0;
+        for (j, (_, vals)) in series.iter().enumerate() {
+            let value = vals.get(i).cloned().unwrap_or(0.0);
+            svg += &format!(
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"/>\n",
+                x,
+                axes.y(base + value),
+                bar_width,
+                axes.y(base) - axes.y(base + value),
+                SERIES_COLORS[j % SERIES_COLORS.len()]
+            );
+            base += value;
+        }
+        svg += &format!(
+            "<text transform=\"translate({:.1},{:.1}) rotate(-45)\" text-anchor=\"end\">{}</text>\n",
+            x + bar_width / 2.0,
+            HEIGHT - MARGIN_BOTTOM + 12.0,
+            escape(label)
+        );
+    }
+
+    svg += &legend(series.iter().map(|(name, _)| *name));
+    svg += "</svg>\n";
+    svg
+}
+
+/// Renders a line chart with one line per series over shared x
+/// positions, such as a metagene profile.
+pub fn line_chart(xs: &[isize], series: &[(&str, Vec<f64>)], ylabel: &str) -> String {
+    let ymax = series
+        .iter()
+        .flat_map(|(_, vals)| vals.iter().cloned())
+        .fold(0.0, f64::max);
+    let axes = Axes::new(ymax);
+    let mut svg = axes.open(ylabel);
+
+    let (xmin, xmax) = match (xs.iter().min(), xs.iter().max()) {
+        (Some(&xmin), Some(&xmax)) if xmax > xmin => (xmin, xmax),
+        _ => {
+            svg += "</svg>\n";
+            return svg;
+        }
+    };
+    let x_of =
+        |x: isize| MARGIN_LEFT + axes.plot_width() * (x - xmin) as f64 / (xmax - xmin) as f64;
+
+    for (j, (_, vals)) in series.iter().enumerate() {
+        let points: Vec<String> = xs
+            .iter()
+            .zip(vals.iter())
+            .map(|(&x, &y)| format!("{:.1},{:.1}", x_of(x), axes.y(y)))
+            .collect();
+        svg += &format!(
+            "<polyline fill=\"none\" stroke=\"{}\" points=\"{}\"/>\n",
+            SERIES_COLORS[j % SERIES_COLORS.len()],
+            points.join(" ")
+        );
+    }
+
+    for &x in [xmin, 0, xmax].iter() {
+        if x >= xmin && x <= xmax {
+            svg += &format!(
+                "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>\n",
+                x_of(x),
+                HEIGHT - MARGIN_BOTTOM + 12.0,
+                x
+            );
+        }
+    }
+
+    svg += &legend(series.iter().map(|(name, _)| *name));
+    svg += "</svg>\n";
+    svg
+}
+
+fn legend<'a, I: Iterator<Item = &'a str>>(names: I) -> String {
+    let mut svg = String::new();
+    for (j, name) in names.enumerate().filter(|(_, name)| !name.is_empty()) {
+        let y = MARGIN_TOP + 12.0 * j as f64;
+        svg += &format!(
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"8\" height=\"8\" fill=\"{}\"/>\n",
+            WIDTH - MARGIN_RIGHT - 80.0,
+            y,
+            SERIES_COLORS[j % SERIES_COLORS.len()]
+        );
+        svg += &format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>\n",
+            WIDTH - MARGIN_RIGHT - 68.0,
+            y + 8.0,
+            escape(name)
+        );
+    }
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bar_heights() {
+        let labels = vec!["a".to_string(), "b<".to_string()];
+        let svg = bar_chart(&labels, &[2.0, 4.0], "Reads");
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(svg.contains("b&lt;"));
+
+        let full = format!("height=\"{:.1}\"", HEIGHT - MARGIN_TOP - MARGIN_BOTTOM);
+        let half = format!(
+            "height=\"{:.1}\"",
+            (HEIGHT - MARGIN_TOP - MARGIN_BOTTOM) / 2.0
+        );
+        assert!(svg.contains(&full));
+        assert!(svg.contains(&half));
+    }
+}
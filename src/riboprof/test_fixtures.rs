@@ -0,0 +1,25 @@
+//! Annotation fixtures shared by unit tests in several modules.
+
+use std::rc::Rc;
+
+use bio::io::bed;
+use bio_types::annot::refids::RefIDSet;
+
+use transcript::Transcriptome;
+
+/// Forward coding gene with a CDS at 150..350, a forward non-coding
+/// gene overlapping its 3' end, and a reverse coding gene with a CDS
+/// at 650..850.
+pub const CODING_NONCODING_BED: &str = "chr01\t100\t400\tCoding\t0\t+\t150\t350\t0\t1\t300,\t0,\n\
+                                        chr01\t300\t500\tNoncoding\t0\t+\t300\t300\t0\t1\t200,\t0,\n\
+                                        chr01\t600\t900\tRevCoding\t0\t-\t650\t850\t0\t1\t300,\t0,\n";
+
+/// Returns the transcriptome of the `CODING_NONCODING_BED` genes.
+pub fn coding_noncoding_trxome() -> Transcriptome<Rc<String>> {
+    let mut refids = RefIDSet::new();
+    Transcriptome::new_from_bed(
+        bed::Reader::new(CODING_NONCODING_BED.as_bytes()).records(),
+        &mut refids,
+    )
+    .expect("Transcriptome from string")
+}
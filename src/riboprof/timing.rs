@@ -0,0 +1,237 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Whether stage timing was requested with `--timing`.
+static TIMING: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static STAGES: RefCell<StageTimes> = RefCell::new(StageTimes::new());
+}
+
+/// Starts recording the wall time of stages timed by `StageTimer`.
+pub fn enable_timing() {
+    TIMING.store(true, Ordering::Relaxed);
+}
+
+pub fn timing_enabled() -> bool {
+    TIMING.load(Ordering::Relaxed)
+}
+
+/// Wall time spent in one named stage of a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageTime {
+    pub stage: &'static str,
+    pub calls: usize,
+    pub elapsed: Duration,
+}
+
+/// Wall time spent in each named stage of a run. Stages may be nested,
+/// e.g., reading BAM records within classification, and the time of a
+/// nested stage is excluded from the stage enclosing it, so that the
+/// stage times add up to no more than the total.
+#[derive(Debug, Clone, Default)]
+pub struct StageTimes {
+    stages: Vec<StageTime>,
+    nested: Vec<Duration>,
+}
+
+impl StageTimes {
+    pub fn new() -> Self {
+        StageTimes::default()
+    }
+
+    /// Returns the stages in the order they were first finished.
+    pub fn stages(&self) -> &[StageTime] {
+        &self.stages
+    }
+
+    /// Starts a stage within any stages already started.
+    pub fn start(&mut self) {
+        self.nested.push(Duration::from_secs(0));
+    }
+
+    /// Finishes the most recently started stage, which took `elapsed`
+    /// in total including any stages nested within it.
+    pub fn finish(&mut self, stage: &'static str, elapsed: Duration) {
+        let nested = self.nested.pop().unwrap_or_else(|| Duration::from_secs(0));
+        if let Some(outer) = self.nested.last_mut() {
+            *outer += elapsed;
+        }
+
+        let own = elapsed
+            .checked_sub(nested)
+            .unwrap_or_else(|| Duration::from_secs(0));
+        match self.stages.iter_mut().find(|st| st.stage == stage) {
+            Some(st) => {
+                st.calls += 1;
+                st.elapsed += own;
+            }
+            None => self.stages.push(StageTime {
+                stage: stage,
+                calls: 1,
+                elapsed: own,
+            }),
+        }
+    }
+
+    /// Returns a table of the time in each stage, with the number of
+    /// times it ran and its fraction of the `total` run time, followed
+    /// by the time outside of any stage.
+    pub fn table(&self, total: Duration) -> String {
+        let total_secs = seconds(total);
+        let mut table = "stage\tcalls\tsecs\tfract\n".to_string();
+
+        let mut staged = Duration::from_secs(0);
+        for st in self.stages.iter() {
+            staged += st.elapsed;
+            table += &format!(
+                "{}\t{}\t{:.3}\t{:.4}\n",
+                st.stage,
+                st.calls,
+                seconds(st.elapsed),
+                seconds(st.elapsed) / total_secs
+            );
+        }
+
+        let other = seconds(
+            total
+                .checked_sub(staged)
+                .unwrap_or_else(|| Duration::from_secs(0)),
+        );
+        table += &format!("other\t\t{:.3}\t{:.4}\n", other, other / total_secs);
+        table += &format!("total\t\t{:.3}\t{:.4}\n", total_secs, 1.0);
+
+        table
+    }
+}
+
+/// Records the wall time from its creation until it is dropped, or
+/// finished explicitly, as time spent in a named stage. Nothing is
+/// recorded unless timing was enabled by `enable_timing()`.
+pub struct StageTimer {
+    stage: &'static str,
+    start: Option<Instant>,
+}
+
+impl StageTimer {
+    pub fn new(stage: &'static str) -> Self {
+        let start = if timing_enabled() {
+            STAGES.with(|stages| stages.borrow_mut().start());
+            Some(Instant::now())
+        } else {
+            None
+        };
+
+        StageTimer {
+            stage: stage,
+            start: start,
+        }
+    }
+
+    /// Finishes the stage before the timer goes out of scope.
+    pub fn finish(self) {}
+}
+
+impl Drop for StageTimer {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            let elapsed = start.elapsed();
+            STAGES.with(|stages| stages.borrow_mut().finish(self.stage, elapsed));
+        }
+    }
+}
+
+/// Runs `f` as a named stage.
+pub fn timed<T, F>(stage: &'static str, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let _timer = StageTimer::new(stage);
+    f()
+}
+
+/// Iterator adapter recording the time spent producing each item as a
+/// named stage, created by `timed_iter`.
+pub struct TimedIter<I> {
+    stage: &'static str,
+    iter: I,
+}
+
+/// Returns an iterator recording the time spent in `iter` as `stage`,
+/// e.g., reading records from a file.
+pub fn timed_iter<I: Iterator>(stage: &'static str, iter: I) -> TimedIter<I> {
+    TimedIter {
+        stage: stage,
+        iter: iter,
+    }
+}
+
+impl<I: Iterator> Iterator for TimedIter<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let _timer = StageTimer::new(self.stage);
+        self.iter.next()
+    }
+}
+
+/// Returns the table of stage times recorded on this thread, or `None`
+/// when timing was not enabled.
+pub fn timing_table(total: Duration) -> Option<String> {
+    if timing_enabled() {
+        Some(STAGES.with(|stages| stages.borrow().table(total)))
+    } else {
+        None
+    }
+}
+
+fn seconds(elapsed: Duration) -> f64 {
+    elapsed.as_secs() as f64 + 1e-9 * elapsed.subsec_nanos() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_stages() {
+        let ms = Duration::from_millis;
+
+        let mut times = StageTimes::new();
+        times.start();
+        times.start();
+        times.finish("BAM read", ms(100));
+        times.start();
+        times.finish("BAM read", ms(200));
+        times.finish("classification", ms(1000));
+        times.start();
+        times.finish("output", ms(500));
+
+        assert_eq!(
+            times.stages(),
+            &[
+                StageTime {
+                    stage: "BAM read",
+                    calls: 2,
+                    elapsed: ms(300),
+                },
+                StageTime {
+                    stage: "classification",
+                    calls: 1,
+                    elapsed: ms(700),
+                },
+                StageTime {
+                    stage: "output",
+                    calls: 1,
+                    elapsed: ms(500),
+                },
+            ]
+        );
+
+        let table = times.table(ms(2000));
+        assert!(table.starts_with("stage\tcalls\tsecs\tfract\nBAM read\t2\t0.300\t0.1500\n"));
+        assert!(table.contains("other\t\t0.500\t0.2500\n"));
+        assert!(table.ends_with("total\t\t2.000\t1.0000\n"));
+    }
+}
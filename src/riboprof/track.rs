@@ -0,0 +1,457 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::ops::Deref;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+use failure;
+
+use bio_types::annot::loc::Loc;
+use bio_types::annot::pos::Pos;
+use bio_types::annot::spliced::Spliced;
+use bio_types::strand::*;
+
+use output::*;
+
+/// Largest run of empty positions written as zeros within a single
+/// fixed-step wiggle block, rather than starting a new block.
+const WIGGLE_MAX_GAP: usize = 100;
+
+/// Genome coordinate track formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackFormat {
+    BedGraph,
+    Wiggle,
+    /// Binary bigWig, converted from bedGraph by the external
+    /// `bedGraphToBigWig` program
+    BigWig,
+}
+
+impl TrackFormat {
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            TrackFormat::BedGraph => "bedgraph",
+            TrackFormat::Wiggle => "wig",
+            TrackFormat::BigWig => "bw",
+        }
+    }
+}
+
+impl FromStr for TrackFormat {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bedgraph" => Ok(TrackFormat::BedGraph),
+            "wig" | "wiggle" => Ok(TrackFormat::Wiggle),
+            "bigwig" | "bw" => Ok(TrackFormat::BigWig),
+            _ => Err(format_err!("Unknown track format \"{}\"", s)),
+        }
+    }
+}
+
+/// Strand-specific counts at single-nucleotide genomic positions,
+/// for output as bedGraph or wiggle tracks.
+pub struct GenomeTrack {
+    fwd: HashMap<String, BTreeMap<usize, usize>>,
+    rev: HashMap<String, BTreeMap<usize, usize>>,
+    total: usize,
+}
+
+impl GenomeTrack {
+    pub fn new() -> Self {
+        GenomeTrack {
+            fwd: HashMap::new(),
+            rev: HashMap::new(),
+            total: 0,
+        }
+    }
+
+    /// Returns the total number of reads tallied.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Tallies one count at a genomic position. Positions before the
+    /// start of the reference sequence are ignored.
+    pub fn tally<R>(&mut self, pos: &Pos<R, ReqStrand>)
+    where
+        R: Deref<Target = String>,
+    {
+        if pos.pos() < 0 {
+            return;
+        }
+
+        let strand_counts = match pos.strand() {
+            ReqStrand::Forward => &mut self.fwd,
+            ReqStrand::Reverse => &mut self.rev,
+        };
+
+        *strand_counts
+            .entry(pos.refid().deref().to_owned())
+            .or_insert_with(BTreeMap::new)
+            .entry(pos.pos() as usize)
+            .or_insert(0) += 1;
+        self.total += 1;
+    }
+
+    /// Tallies the coverage of one read, adding one count at every
+    /// position in the exons of its alignment. Positions before the
+    /// start of the reference sequence are ignored.
+    pub fn tally_spliced<R>(&mut self, loc: &Spliced<R, ReqStrand>)
+    where
+        R: Deref<Target = String> + Clone,
+    {
+        let strand_counts = match loc.strand() {
+            ReqStrand::Forward => &mut self.fwd,
+            ReqStrand::Reverse => &mut self.rev,
+        };
+        let counts = strand_counts
+            .entry(loc.refid().deref().to_owned())
+            .or_insert_with(BTreeMap::new);
+
+        for exon in loc.exon_contigs() {
+            for pos in exon.start()..(exon.start() + exon.length() as isize) {
+                if pos >= 0 {
+                    *counts.entry(pos as usize).or_insert(0) += 1;
+                }
+            }
+        }
+        self.total += 1;
+    }
+
+    /// Returns the counts at positions in one genomic reading frame,
+    /// i.e., with a position modulo 3 equal to `frame`. The counts in
+    /// any one open reading frame fall in a single genomic frame on
+    /// either strand, so the three frame tracks separate overlapping
+    /// ORFs and show frameshifts. The total is unchanged, so that
+    /// reads per million are scaled to all reads tallied.
+    pub fn frame_track(&self, frame: usize) -> GenomeTrack {
+        type Counts = HashMap<String, BTreeMap<usize, usize>>;
+        let in_frame = |counts: &Counts| -> Counts {
+            counts
+                .iter()
+                .map(|(chrom, chrom_counts)| {
+                    let frame_counts = chrom_counts
+                        .iter()
+                        .filter(|(pos, _)| *pos % 3 == frame)
+                        .map(|(pos, count)| (*pos, *count))
+                        .collect();
+                    (chrom.to_owned(), frame_counts)
+                })
+                .collect()
+        };
+
+        GenomeTrack {
+            fwd: in_frame(&self.fwd),
+            rev: in_frame(&self.rev),
+            total: self.total,
+        }
+    }
+
+    fn strand_counts(&self, strand: ReqStrand) -> Vec<(&String, &BTreeMap<usize, usize>)> {
+        let counts = match strand {
+            ReqStrand::Forward => &self.fwd,
+            ReqStrand::Reverse => &self.rev,
+        };
+        let mut chrom_counts: Vec<(&String, &BTreeMap<usize, usize>)> = counts.iter().collect();
+        chrom_counts.sort_by(|(c1, _), (c2, _)| c1.cmp(c2));
+        chrom_counts
+    }
+
+    /// Writes a bedGraph track for one strand. Adjacent positions
+    /// with the same count are merged into one interval.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` is the output destination
+    /// * `strand` selects the strand written
+    /// * `scale` multiplies each count
+    /// * `name` is the track name
+    pub fn write_bedgraph<W: Write>(
+        &self,
+        out: &mut W,
+        strand: ReqStrand,
+        scale: f64,
+        name: &str,
+    ) -> io::Result<()> {
+        writeln!(out, "track type=bedGraph name=\"{}\"", name)?;
+        self.write_bedgraph_intervals(out, strand, scale)
+    }
+
+    fn write_bedgraph_intervals<W: Write>(
+        &self,
+        out: &mut W,
+        strand: ReqStrand,
+        scale: f64,
+    ) -> io::Result<()> {
+        for (chrom, counts) in self.strand_counts(strand) {
+            let mut run: Option<(usize, usize, usize)> = None;
+
+            for (&pos, &ct) in counts.iter() {
+                run = match run {
+                    Some((start, end, run_ct)) if end == pos && run_ct == ct => {
+                        Some((start, end + 1, run_ct))
+                    }
+                    Some((start, end, run_ct)) => {
+                        writeln!(
+                            out,
+                            "{}\t{}\t{}\t{}",
+                            chrom,
+                            start,
+                            end,
+                            run_ct as f64 * scale
+                        )?;
+                        Some((pos, pos + 1, ct))
+                    }
+                    None => Some((pos, pos + 1, ct)),
+                };
+            }
+
+            if let Some((start, end, run_ct)) = run {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}",
+                    chrom,
+                    start,
+                    end,
+                    run_ct as f64 * scale
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes a fixed-step wiggle track for one strand. Short gaps
+    /// between covered positions are filled with zeros and longer
+    /// gaps start a new block.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` is the output destination
+    /// * `strand` selects the strand written
+    /// * `scale` multiplies each count
+    /// * `name` is the track name
+    pub fn write_wiggle<W: Write>(
+        &self,
+        out: &mut W,
+        strand: ReqStrand,
+        scale: f64,
+        name: &str,
+    ) -> io::Result<()> {
+        writeln!(out, "track type=wiggle_0 name=\"{}\"", name)?;
+
+        for (chrom, counts) in self.strand_counts(strand) {
+            let mut next: Option<usize> = None;
+
+            for (&pos, &ct) in counts.iter() {
+                match next {
+                    Some(next_pos) if pos - next_pos <= WIGGLE_MAX_GAP => {
+                        for _ in next_pos..pos {
+                            writeln!(out, "0")?;
+                        }
+                    }
+                    _ => writeln!(out, "fixedStep chrom={} start={} step=1", chrom, pos + 1)?,
+                }
+                writeln!(out, "{}", ct as f64 * scale)?;
+                next = Some(pos + 1);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes a track for one strand to a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` is the output filename, which gains a suffix when
+    ///   `output` compresses a text track
+    /// * `output` sets the compression of bedGraph and wiggle tracks,
+    ///   while bigWig tracks are compressed already
+    /// * `format` is the track format
+    /// * `strand` selects the strand written
+    /// * `scale` multiplies each count
+    /// * `name` is the track name
+    /// * `chrom_sizes` gives the length of each reference sequence,
+    ///   which is needed only for bigWig output
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when the file cannot be written
+    /// or, for bigWig output, when `bedGraphToBigWig` cannot be run
+    /// or fails.
+    pub fn write_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+        output: &OutputConfig,
+        format: TrackFormat,
+        strand: ReqStrand,
+        scale: f64,
+        name: &str,
+        chrom_sizes: &[(String, u32)],
+    ) -> Result<(), failure::Error> {
+        let path = path.as_ref();
+
+        match format {
+            TrackFormat::BedGraph => {
+                let mut out = output.create(path)?;
+                self.write_bedgraph(&mut out, strand, scale, name)?;
+                out.finish()?;
+            }
+            TrackFormat::Wiggle => {
+                let mut out = output.create(path)?;
+                self.write_wiggle(&mut out, strand, scale, name)?;
+                out.finish()?;
+            }
+            TrackFormat::BigWig => {
+                // bedGraphToBigWig reads an untitled, sorted bedGraph
+                // and a table of chromosome sizes.
+                let bedgraph_path = path.with_extension("tmp.bedgraph");
+                let sizes_path = path.with_extension("chrom.sizes");
+                {
+                    let mut out = io::BufWriter::new(fs::File::create(&bedgraph_path)?);
+                    self.write_bedgraph_intervals(&mut out, strand, scale)?;
+                    let mut sizes = io::BufWriter::new(fs::File::create(&sizes_path)?);
+                    for (chrom, len) in chrom_sizes.iter() {
+                        writeln!(sizes, "{}\t{}", chrom, len)?;
+                    }
+                }
+
+                let status = Command::new("bedGraphToBigWig")
+                    .arg(&bedgraph_path)
+                    .arg(&sizes_path)
+                    .arg(path)
+                    .status()
+                    .map_err(|err| format_err!("Unable to run bedGraphToBigWig: {}", err))?;
+                fs::remove_file(&bedgraph_path)?;
+                fs::remove_file(&sizes_path)?;
+                if !status.success() {
+                    bail!("bedGraphToBigWig failed writing {}", path.display());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    fn track_from_strs(posstrs: &[&str]) -> GenomeTrack {
+        let mut track = GenomeTrack::new();
+        for posstr in posstrs {
+            let pos: Pos<Rc<String>, ReqStrand> = posstr.parse().expect("Parsing position");
+            track.tally(&pos);
+        }
+        track
+    }
+
+    #[test]
+    fn bedgraph_runs() {
+        let track = track_from_strs(&[
+            "chr1:100(+)",
+            "chr1:101(+)",
+            "chr1:101(+)",
+            "chr1:102(+)",
+            "chr1:103(+)",
+            "chr1:200(-)",
+        ]);
+        assert_eq!(track.total(), 6);
+
+        let mut fwd = Vec::new();
+        track
+            .write_bedgraph(&mut fwd, ReqStrand::Forward, 1.0, "test")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(fwd).unwrap(),
+            "track type=bedGraph name=\"test\"\n\
+             chr1\t100\t101\t1\n\
+             chr1\t101\t102\t2\n\
+             chr1\t102\t104\t1\n"
+        );
+
+        let mut rev = Vec::new();
+        track
+            .write_bedgraph(&mut rev, ReqStrand::Reverse, 0.5, "test")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(rev).unwrap(),
+            "track type=bedGraph name=\"test\"\nchr1\t200\t201\t0.5\n"
+        );
+    }
+
+    #[test]
+    fn coverage() {
+        let mut track = GenomeTrack::new();
+        let fp: Spliced<Rc<String>, ReqStrand> = "chr1:100-103;200-202(+)".parse().unwrap();
+        track.tally_spliced(&fp);
+        let fp: Spliced<Rc<String>, ReqStrand> = "chr1:101-105(+)".parse().unwrap();
+        track.tally_spliced(&fp);
+        assert_eq!(track.total(), 2);
+
+        let mut fwd = Vec::new();
+        track
+            .write_bedgraph(&mut fwd, ReqStrand::Forward, 1.0, "test")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(fwd).unwrap(),
+            "track type=bedGraph name=\"test\"\n\
+             chr1\t100\t101\t1\n\
+             chr1\t101\t103\t2\n\
+             chr1\t103\t105\t1\n\
+             chr1\t200\t202\t1\n"
+        );
+    }
+
+    #[test]
+    fn frame_tracks() {
+        let track = track_from_strs(&["chr1:100(+)", "chr1:103(+)", "chr1:104(+)", "chr1:200(-)"]);
+
+        let frame1 = track.frame_track(1);
+        assert_eq!(frame1.total(), 4);
+        let mut fwd = Vec::new();
+        frame1
+            .write_bedgraph(&mut fwd, ReqStrand::Forward, 1.0, "test")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(fwd).unwrap(),
+            "track type=bedGraph name=\"test\"\n\
+             chr1\t100\t101\t1\n\
+             chr1\t103\t104\t1\n"
+        );
+
+        let mut rev = Vec::new();
+        track
+            .frame_track(2)
+            .write_bedgraph(&mut rev, ReqStrand::Reverse, 1.0, "test")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(rev).unwrap(),
+            "track type=bedGraph name=\"test\"\nchr1\t200\t201\t1\n"
+        );
+    }
+
+    #[test]
+    fn wiggle_blocks() {
+        let track = track_from_strs(&["chr1:100(+)", "chr1:102(+)", "chr1:1000(+)"]);
+
+        let mut fwd = Vec::new();
+        track
+            .write_wiggle(&mut fwd, ReqStrand::Forward, 1.0, "test")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(fwd).unwrap(),
+            "track type=wiggle_0 name=\"test\"\n\
+             fixedStep chrom=chr1 start=101 step=1\n1\n0\n1\n\
+             fixedStep chrom=chr1 start=1001 step=1\n1\n"
+        );
+    }
+}
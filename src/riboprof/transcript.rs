@@ -6,21 +6,27 @@ use std::hash::Hash;
 use std::io;
 use std::num::ParseIntError;
 use std::ops::{Deref, Range};
+use std::path::Path;
+use std::sync::Arc;
 
-use failure;
+use csv;
 
 use bio::data_structures::annot_map::AnnotMap;
-use bio::io::bed;
+use bio::io::{bed, gff};
 use bio_types::annot::loc::Loc;
 use bio_types::annot::pos::*;
 use bio_types::annot::refids::RefIDSet;
 use bio_types::annot::spliced::*;
 use bio_types::strand::*;
 
+use timing::*;
+
 /// Annotation of a transcript as a `Spliced` `annot` location.
 ///
 /// The transcript is associated with a gene (one gene may have
-/// multiple transcripts) and has an optional coding sequence.
+/// multiple transcripts) and has an optional coding sequence. The
+/// coding sequence may begin mid-codon, as indicated by its phase,
+/// when the annotation is incomplete at the 5' end.
 ///
 /// Parameterized over the data type used for identifiers (e.g.,
 /// `String`, `Rc<String>`, or `Arc<String>`).
@@ -30,6 +36,7 @@ pub struct Transcript<R> {
     trxname: R,
     loc: Spliced<R, ReqStrand>,
     cds: Option<Range<usize>>,
+    cds_phase: usize,
 }
 
 impl<R> Transcript<R> {
@@ -44,6 +51,13 @@ impl<R> Transcript<R> {
         &self.cds
     }
 
+    /// Returns the phase of the coding sequence, the number of bases
+    /// at the start of the CDS that precede the first complete
+    /// codon. This is 0 unless the annotation begins mid-codon.
+    pub fn cds_phase(&self) -> usize {
+        self.cds_phase
+    }
+
     pub fn is_coding(&self) -> bool {
         self.cds.is_some()
     }
@@ -52,6 +66,20 @@ impl<R> Transcript<R> {
         self.cds.is_none()
     }
 
+    /// Returns the 5' UTR in transcript coordinates, which may be
+    /// empty, or `None` for a non-coding transcript.
+    pub fn utr5_range(&self) -> Option<Range<usize>> {
+        self.cds.as_ref().map(|cds| 0..cds.start)
+    }
+
+    /// Returns the 3' UTR in transcript coordinates, which may be
+    /// empty, or `None` for a non-coding transcript.
+    pub fn utr3_range(&self) -> Option<Range<usize>> {
+        self.cds
+            .as_ref()
+            .map(|cds| cds.end..self.loc.exon_total_length())
+    }
+
     /// Returns a reference to the gene name.
     pub fn gene_ref(&self) -> &R {
         &self.gene
@@ -156,7 +184,32 @@ where
         loc: Spliced<R, ReqStrand>,
         cds: Option<Range<usize>>,
     ) -> Result<Self, TrxError> {
-        if cds.as_ref().map_or(false, |rng| rng.end <= rng.start) {
+        Self::new_with_phase(gene, trxname, loc, cds, 0)
+    }
+
+    /// Returns a new `Transcript` whose coding sequence begins
+    /// mid-codon.
+    ///
+    /// # Arguments
+    ///
+    /// * `gene`, `trxname`, `loc`, and `cds` are as in `new()`
+    /// * `cds_phase` is the number of bases (0, 1, or 2) at the start
+    /// of the CDS before the first complete codon
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned under the same conditions as
+    /// `new()`, or when `cds_phase` is 3 or more.
+    pub fn new_with_phase(
+        gene: R,
+        trxname: R,
+        loc: Spliced<R, ReqStrand>,
+        cds: Option<Range<usize>>,
+        cds_phase: usize,
+    ) -> Result<Self, TrxError> {
+        if cds_phase > 2 {
+            Err(TrxError::Cds(format!("Invalid CDS phase {}", cds_phase)))
+        } else if cds.as_ref().map_or(false, |rng| rng.end <= rng.start) {
             Err(TrxError::Cds(format!("Invalid CDS range {:?}", cds)))
         } else if cds.as_ref().map_or(false, |rng| rng.end > loc.length()) {
             Err(TrxError::Cds(format!(
@@ -169,6 +222,7 @@ where
                 trxname: trxname,
                 loc: loc,
                 cds: cds,
+                cds_phase: cds_phase,
             })
         }
     }
@@ -181,11 +235,18 @@ where
     /// Construct a transcirpt from a 12-column BED annotation.
     ///
     /// The gene and transcript name are both taken from the BED name
-    /// entry, which is required. The overall transcript annotation is
-    /// determined from the BED location and strand information along
-    /// with the exon "blocks" location in columns 10 through 12. The
-    /// CDS is determined by the "thickStart" and "thickEnd"
-    /// entries; if these are equal, then the CDS is `None.`
+    /// entry. The overall transcript annotation is determined from the
+    /// BED location and strand information along with the exon
+    /// "blocks" location in columns 10 through 12. The CDS is
+    /// determined by the "thickStart" and "thickEnd" entries; if these
+    /// are equal, then the CDS is `None.`
+    ///
+    /// Records without block columns, e.g., BED6, are single-exon
+    /// transcripts spanning the whole interval, and records without
+    /// thick columns have no CDS. A 3-column BED interval has no name
+    /// or strand; it is named by its location, e.g., `chr01:100-250`,
+    /// and lies on the forward strand, as does any interval whose
+    /// strand is `.`.
     ///
     /// ```
     /// # use std::error::Error;
@@ -225,15 +286,17 @@ where
     pub fn from_bed12(record: &bed::Record, refids: &mut RefIDSet<R>) -> Result<Self, TrxError> {
         let loc = Self::loc_from_bed(record, refids)?;
         let cds = Self::cds_from_bed(record, &loc)?;
-        let name = record
-            .name()
-            .ok_or_else(|| TrxError::bed(record, "No name"))?;
+        let name = match record.name() {
+            Some(name) => name.to_string(),
+            None => format!("{}:{}-{}", record.chrom(), record.start(), record.end()),
+        };
 
         Ok(Transcript {
-            gene: refids.intern(name),
-            trxname: refids.intern(name),
+            gene: refids.intern(&name),
+            trxname: refids.intern(&name),
             loc: loc,
             cds: cds,
+            cds_phase: 0,
         })
     }
 
@@ -248,9 +311,36 @@ where
         record: &bed::Record,
         refids: &mut RefIDSet<R>,
     ) -> Result<Spliced<R, ReqStrand>, TrxError> {
-        let block_count = record
-            .aux(Self::BLOCK_COUNT_COL)
-            .ok_or_else(|| TrxError::bed(record, "No splicing blocks"))?
+        let strand = match record.aux(Self::STRAND_COL) {
+            None | Some(".") => Ok(ReqStrand::Forward),
+            Some("+") => Ok(ReqStrand::Forward),
+            Some("-") => Ok(ReqStrand::Reverse),
+            Some(_) => Err(TrxError::bed(record, "Bad strand")),
+        }?;
+
+        if record.end() <= record.start() {
+            return Err(TrxError::bed(record, "Empty interval"));
+        }
+        let start =
+            annot_pos(record.start()).ok_or_else(|| TrxError::bed(record, "Start too large"))?;
+        let end = annot_pos(record.end()).ok_or_else(|| TrxError::bed(record, "End too large"))?;
+
+        let block_count = match record.aux(Self::BLOCK_COUNT_COL) {
+            Some(block_count) => block_count,
+            None => {
+                let length = (end - start) as usize;
+                return Spliced::with_lengths_starts(
+                    refids.intern(record.chrom()),
+                    start,
+                    &[length],
+                    &[0],
+                    strand,
+                ).map_err(|err| {
+                    TrxError::BedSplicing(format!("Splicing error on record {:?}", record), err)
+                });
+            }
+        };
+        let block_count = block_count
             .parse::<usize>()
             .map_err(|err| TrxError::bed_parse(record, "Bad block count", err))?;
 
@@ -282,18 +372,9 @@ where
             ));
         }
 
-        let strand = match record
-            .aux(Self::STRAND_COL)
-            .ok_or_else(|| TrxError::bed(record, "No strand"))?
-        {
-            "+" => Ok(ReqStrand::Forward),
-            "-" => Ok(ReqStrand::Reverse),
-            _ => Err(TrxError::bed(record, "Bad strand")),
-        }?;
-
         Spliced::with_lengths_starts(
             refids.intern(record.chrom()),
-            record.start() as isize,
+            start,
             &block_sizes,
             &block_starts,
             strand,
@@ -323,14 +404,30 @@ where
         if thick_start >= thick_end {
             return Ok(None);
         }
+        if annot_pos(thick_end as u64).is_none() {
+            return Err(TrxError::bed(record, "thickEnd too large"));
+        }
 
+        Self::cds_from_thick(loc, thick_start, thick_end)
+            .map(Some)
+            .map_err(|msg| TrxError::bed(record, msg))
+    }
+
+    /// Converts a genomic CDS extent, given as a half-open interval
+    /// `thick_start..thick_end` on the reference sequence, into a
+    /// range of transcript coordinates.
+    fn cds_from_thick(
+        loc: &Spliced<R, ReqStrand>,
+        thick_start: usize,
+        thick_end: usize,
+    ) -> Result<Range<usize>, &'static str> {
         // Left-most position within the location
         let left_pos =
             loc.pos_into(&Pos::new(
                 loc.refid().clone(),
                 thick_start as isize,
                 loc.strand(),
-            )).ok_or_else(|| TrxError::bed(record, "thickStart not in annot"))?
+            )).ok_or("thickStart not in annot")?
                 .pos();
 
         // Right-most position _within_ the location
@@ -349,7 +446,7 @@ where
                 loc.refid().clone(),
                 thick_end as isize - 1,
                 loc.strand(),
-            )).ok_or_else(|| TrxError::bed(record, "thickEnd-1 not in annot"))?
+            )).ok_or("thickEnd-1 not in annot")?
                 .pos()
         };
 
@@ -358,10 +455,187 @@ where
 
         assert!(last >= start);
 
-        Ok(Some(Range {
+        Ok(Range {
             start: start,
             end: last + 1,
-        }))
+        })
+    }
+
+    /// Construct a transcript from the exon and CDS features of a
+    /// GTF annotation, collected into a `GtfTranscript`.
+    ///
+    /// The CDS runs from the first to the last base of the `CDS`
+    /// features, extended to include any `stop_codon` features, in
+    /// order to match the BED convention. The CDS phase is taken from
+    /// the `frame` column of the 5'-most `CDS` feature.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when the features are
+    /// inconsistent, e.g., on different strands or outside of the
+    /// annotated exons.
+    fn from_gtf(
+        trxname: &str,
+        gtf_trx: GtfTranscript,
+        refids: &mut RefIDSet<R>,
+    ) -> Result<Self, TrxError> {
+        let strand = gtf_trx
+            .strand
+            .ok_or_else(|| TrxError::Gtf(format!("No strand for transcript {}", trxname)))?;
+
+        let mut exons = if gtf_trx.exons.is_empty() {
+            gtf_trx.cds.iter().map(|&(start, end, _)| (start, end)).collect()
+        } else {
+            gtf_trx.exons
+        };
+        exons.sort();
+
+        let trx_start = exons
+            .first()
+            .ok_or_else(|| TrxError::Gtf(format!("No exons for transcript {}", trxname)))?
+            .0;
+        let lengths: Vec<usize> = exons.iter().map(|&(start, end)| end - start).collect();
+        let starts: Vec<usize> = exons.iter().map(|&(start, _)| start - trx_start).collect();
+
+        let loc = Spliced::with_lengths_starts(
+            refids.intern(&gtf_trx.chrom),
+            trx_start as isize,
+            &lengths,
+            &starts,
+            strand,
+        ).map_err(|err| {
+            TrxError::GtfSplicing(format!("Splicing error on transcript {}", trxname), err)
+        })?;
+
+        let cds_phase = match strand {
+            ReqStrand::Forward => gtf_trx.cds.iter().min_by_key(|&&(start, _, _)| start),
+            ReqStrand::Reverse => gtf_trx.cds.iter().max_by_key(|&&(_, end, _)| end),
+        }.map_or(0, |&(_, _, phase)| phase);
+
+        let thick_start = gtf_trx
+            .cds
+            .iter()
+            .map(|&(start, _, _)| start)
+            .chain(gtf_trx.stop_codons.iter().map(|&(start, _)| start))
+            .min();
+        let thick_end = gtf_trx
+            .cds
+            .iter()
+            .map(|&(_, end, _)| end)
+            .chain(gtf_trx.stop_codons.iter().map(|&(_, end)| end))
+            .max();
+
+        let cds = match (thick_start, thick_end) {
+            (Some(thick_start), Some(thick_end)) if !gtf_trx.cds.is_empty() => Some(
+                Self::cds_from_thick(&loc, thick_start, thick_end)
+                    .map_err(|msg| TrxError::Gtf(format!("{} on transcript {}", msg, trxname)))?,
+            ),
+            _ => None,
+        };
+
+        if cds_phase > 2 {
+            return Err(TrxError::Cds(format!(
+                "Invalid CDS phase {} on transcript {}",
+                cds_phase, trxname
+            )));
+        }
+
+        Ok(Transcript {
+            gene: refids.intern(&gtf_trx.gene),
+            trxname: refids.intern(trxname),
+            loc: loc,
+            cds: cds,
+            cds_phase: cds_phase,
+        })
+    }
+}
+
+/// Returns the transcripts named by the `Parent` attribute of a GFF3
+/// feature, which may list several, separated by commas.
+fn gff3_parents(record: &gff::Record) -> Vec<String> {
+    record
+        .attributes()
+        .get_vec("Parent")
+        .map_or_else(Vec::new, |parents| {
+            parents
+                .iter()
+                .flat_map(|parent| parent.split(','))
+                .map(str::to_string)
+                .collect()
+        })
+}
+
+/// Exon and CDS features for one transcript, accumulated while
+/// reading a GTF file. Positions are converted to 0-based, half-open
+/// intervals.
+#[derive(Debug, Clone)]
+struct GtfTranscript {
+    gene: String,
+    chrom: String,
+    strand: Option<ReqStrand>,
+    exons: Vec<(usize, usize)>,
+    cds: Vec<(usize, usize, usize)>,
+    stop_codons: Vec<(usize, usize)>,
+}
+
+impl GtfTranscript {
+    fn new(gene: &str, chrom: &str) -> Self {
+        GtfTranscript {
+            gene: gene.to_string(),
+            chrom: chrom.to_string(),
+            strand: None,
+            exons: Vec::new(),
+            cds: Vec::new(),
+            stop_codons: Vec::new(),
+        }
+    }
+
+    /// Adds a GTF feature to the transcript. Features other than
+    /// `exon`, `CDS`, and `stop_codon` are ignored.
+    fn add_record(&mut self, trxname: &str, record: &gff::Record) -> Result<(), TrxError> {
+        if record.seqname() != self.chrom {
+            return Err(TrxError::Gtf(format!(
+                "Transcript {} on multiple chromosomes",
+                trxname
+            )));
+        }
+
+        let strand = match record.strand() {
+            Some(Strand::Forward) => ReqStrand::Forward,
+            Some(Strand::Reverse) => ReqStrand::Reverse,
+            _ => return Err(TrxError::Gtf(format!("No strand for transcript {}", trxname))),
+        };
+        if self.strand.map_or(false, |s| s != strand) {
+            return Err(TrxError::Gtf(format!(
+                "Transcript {} on multiple strands",
+                trxname
+            )));
+        }
+        self.strand = Some(strand);
+
+        let start = annot_pos(*record.start())
+            .and_then(|start| (start as usize).checked_sub(1))
+            .ok_or_else(|| TrxError::Gtf(format!("Bad start on transcript {}", trxname)))?;
+        let end = annot_pos(*record.end())
+            .ok_or_else(|| TrxError::Gtf(format!("Bad end on transcript {}", trxname)))?
+            as usize;
+
+        match record.feature_type() {
+            "exon" => self.exons.push((start, end)),
+            "CDS" => {
+                let phase = match record.frame() {
+                    "." | "" => 0,
+                    frame_str => frame_str.parse::<usize>().map_err(|err| {
+                        TrxError::GtfParse(format!("Bad frame on transcript {}", trxname), err)
+                    })?,
+                };
+                self.cds.push((start, end, phase));
+            }
+            "stop_codon" => self.stop_codons.push((start, end)),
+            _ => (),
+        }
+
+        Ok(())
     }
 }
 
@@ -485,9 +759,13 @@ impl<'a, R: 'a> TrxPos<'a, R> {
             .map(|cds| self.pos as isize - cds.end as isize)
     }
 
+    /// Returns the reading frame (0, 1, or 2) of the position within
+    /// the CDS, accounting for the CDS phase, or `None` for a
+    /// non-coding transcript.
     pub fn cds_frame(&self) -> Option<usize> {
+        let phase = self.transcript.cds_phase() as isize;
         self.offset_from_cds_start()
-            .map(|off| ((off % 3) + 3) as usize % 3)
+            .map(|off| (((off - phase) % 3) + 3) as usize % 3)
     }
 }
 
@@ -521,14 +799,72 @@ impl<'a, R: Eq + Hash> TrxPos<'a, R> {
     }
 }
 
+/// Transcripts annotated on one reference sequence (chromosome),
+/// indexed by location.
+///
+/// Each `RefTranscripts` is independent of the others, so that
+/// queries on different reference sequences can be handled by
+/// separate worker threads. Transcripts are shared with the
+/// `Transcriptome` by reference counting rather than copied.
+pub struct RefTranscripts<R>
+where
+    R: Eq + Hash,
+{
+    refid: R,
+    trx_by_location: AnnotMap<R, Arc<Transcript<R>>>,
+    n_transcripts: usize,
+}
+
+impl<R: Eq + Hash> RefTranscripts<R> {
+    /// Returns the reference sequence name shared by all transcripts.
+    pub fn refid(&self) -> &R {
+        &self.refid
+    }
+
+    /// Returns the number of transcripts on the reference sequence.
+    pub fn len(&self) -> usize {
+        self.n_transcripts
+    }
+
+    /// Returns `true` when there are no transcripts.
+    pub fn is_empty(&self) -> bool {
+        self.n_transcripts == 0
+    }
+
+    /// Returns the transcripts overlapping a location. Locations on
+    /// any other reference sequence never overlap.
+    pub fn find_at_loc<'a: 'c, 'b: 'c, 'c, L: Loc<RefID = R>>(
+        &'a self,
+        loc: &'b L,
+    ) -> impl Iterator<Item = &'c Transcript<R>> {
+        self.trx_by_location.find(loc).map(|ent| &**ent.data())
+    }
+}
+
+impl<R: Eq + Hash + Clone> RefTranscripts<R> {
+    fn new(refid: R) -> Self {
+        RefTranscripts {
+            refid: refid,
+            trx_by_location: AnnotMap::new(),
+            n_transcripts: 0,
+        }
+    }
+
+    fn insert(&mut self, transcript: Arc<Transcript<R>>) {
+        let loc = transcript.loc().clone();
+        self.trx_by_location.insert_at(transcript, &loc);
+        self.n_transcripts += 1;
+    }
+}
+
 pub struct Transcriptome<R>
 where
     R: Eq + Hash,
 {
     gene_to_trxnames: HashMap<R, Vec<R>>,
     trxname_to_gene: HashMap<R, R>,
-    trxname_to_transcript: HashMap<R, Transcript<R>>,
-    trxname_by_location: AnnotMap<R, R>,
+    trxname_to_transcript: HashMap<R, Arc<Transcript<R>>>,
+    ref_transcripts: HashMap<R, RefTranscripts<R>>,
 }
 
 impl<R: Eq + Hash> Transcriptome<R> {
@@ -537,7 +873,7 @@ impl<R: Eq + Hash> Transcriptome<R> {
             gene_to_trxnames: HashMap::new(),
             trxname_to_gene: HashMap::new(),
             trxname_to_transcript: HashMap::new(),
-            trxname_by_location: AnnotMap::new(),
+            ref_transcripts: HashMap::new(),
         }
     }
 
@@ -545,15 +881,55 @@ impl<R: Eq + Hash> Transcriptome<R> {
         self.trxname_to_transcript.keys()
     }
 
+    /// Returns every transcript, in no particular order.
+    pub fn transcripts(&self) -> impl Iterator<Item = &Transcript<R>> {
+        self.trxname_to_transcript.values().map(Arc::as_ref)
+    }
+
     pub fn find_at_loc<'a: 'c, 'b: 'c, 'c, L: Loc<RefID = R>>(
         &'a self,
         loc: &'b L,
     ) -> impl Iterator<Item = &'c Transcript<R>> {
-        self.trxname_by_location.find(loc).map(move |ent| {
-            self.trxname_to_transcript
-                .get(ent.data())
-                .expect("transcript missing from map")
-        })
+        self.ref_transcripts
+            .get(loc.refid())
+            .into_iter()
+            .flat_map(move |ref_trxs| ref_trxs.find_at_loc(loc))
+    }
+
+    /// Returns the transcripts on `strand` overlapping a location,
+    /// grouped by gene. Tools assign footprints to genes through this
+    /// grouping so that they classify footprints consistently.
+    pub fn genes_at_loc<'a, L: Loc<RefID = R>>(
+        &'a self,
+        loc: &'a L,
+        strand: ReqStrand,
+    ) -> Vec<(&'a R, Vec<&'a Transcript<R>>)> {
+        Transcript::group_by_gene(
+            self.find_at_loc(loc)
+                .filter(|trx| trx.loc().strand() == strand),
+        )
+    }
+
+    /// Returns the location index for one reference sequence, or
+    /// `None` when no transcripts are annotated on it.
+    pub fn ref_transcripts(&self, refid: &R) -> Option<&RefTranscripts<R>> {
+        self.ref_transcripts.get(refid)
+    }
+
+    /// Returns the per-reference location indices. Each can be
+    /// handed to a separate worker thread to process the reads
+    /// aligned to that reference sequence.
+    pub fn ref_transcripts_iter(&self) -> impl Iterator<Item = &RefTranscripts<R>> {
+        self.ref_transcripts.values()
+    }
+
+    /// Consumes the transcriptome and returns its per-reference
+    /// location indices, e.g., to move them into worker threads.
+    pub fn into_ref_transcripts(self) -> Vec<RefTranscripts<R>> {
+        self.ref_transcripts
+            .into_iter()
+            .map(|(_refid, ref_trxs)| ref_trxs)
+            .collect()
     }
 }
 
@@ -562,6 +938,12 @@ where
     R: Deref<Target = String> + From<String> + Clone + Hash + Eq,
 {
     pub fn insert(&mut self, transcript: Transcript<R>) -> Result<R, TrxError> {
+        self.insert_shared(Arc::new(transcript))
+    }
+
+    /// Inserts a transcript that may already belong to another
+    /// transcriptome, without copying it.
+    fn insert_shared(&mut self, transcript: Arc<Transcript<R>>) -> Result<R, TrxError> {
         if self.trxname_to_transcript.contains_key(&transcript.trxname) {
             return Err(TrxError::TrxExists(transcript.trxname.to_string()));
         }
@@ -574,8 +956,10 @@ where
             .entry(transcript.gene.clone())
             .or_insert(vec![])
             .push(trxname.clone());
-        self.trxname_by_location
-            .insert_at(trxname.clone(), &transcript.loc);
+        self.ref_transcripts
+            .entry(transcript.loc.refid().clone())
+            .or_insert_with(|| RefTranscripts::new(transcript.loc.refid().clone()))
+            .insert(Arc::clone(&transcript));
 
         self.trxname_to_transcript
             .insert(trxname.clone(), transcript);
@@ -583,6 +967,32 @@ where
         Ok(trxname)
     }
 
+    /// Returns the transcriptome restricted to "clean" genes, those
+    /// with a single annotated isoform whose transcript does not
+    /// overlap a transcript of any other gene on either strand. Reads
+    /// on clean genes have only one possible transcript coordinate,
+    /// as is standard practice for metagene and framing statistics.
+    pub fn clean_genes(&self) -> Transcriptome<R> {
+        let mut clean = Self::new();
+
+        for trx in self.trxname_to_transcript.values() {
+            let single_isoform = self
+                .gene_to_trxnames
+                .get(&trx.gene)
+                .map_or(false, |trxnames| trxnames.len() == 1);
+            let isolated = self
+                .find_at_loc(trx.loc())
+                .all(|other| other.gene == trx.gene);
+            if single_isoform && isolated {
+                clean
+                    .insert_shared(Arc::clone(trx))
+                    .expect("Duplicate transcript in clean genes");
+            }
+        }
+
+        clean
+    }
+
     pub fn new_from_bed<B: io::Read>(
         records: bed::Records<B>,
         refids: &mut RefIDSet<R>,
@@ -590,26 +1000,544 @@ where
         let mut trxome = Self::new();
 
         for recres in records {
-            let rec = recres.map_err(|err| TrxError::BedRead(err.into()))?;
+            let rec = recres.map_err(TrxError::BedRead)?;
             let transcript = Transcript::from_bed12(&rec, refids)?;
             trxome.insert(transcript)?;
         }
 
         Ok(trxome)
     }
+
+    /// Constructs a transcriptome from GTF records. Transcripts are
+    /// assembled from `exon`, `CDS`, and `stop_codon` features,
+    /// grouped by their `transcript_id` attribute and assigned to the
+    /// gene given by their `gene_id` attribute. The phase of a
+    /// partial CDS is taken from the `frame` column.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when a record cannot be read, when
+    /// a feature lacks a `gene_id` or `transcript_id`, or when the
+    /// features of a transcript are inconsistent.
+    pub fn new_from_gtf<B: io::Read>(
+        records: gff::Records<B>,
+        refids: &mut RefIDSet<R>,
+    ) -> Result<Transcriptome<R>, TrxError> {
+        let mut trxnames = Vec::new();
+        let mut gtf_trxs: HashMap<String, GtfTranscript> = HashMap::new();
+
+        for recres in records {
+            let rec = recres.map_err(TrxError::GtfRead)?;
+
+            match rec.feature_type() {
+                "exon" | "CDS" | "stop_codon" => (),
+                _ => continue,
+            };
+
+            let trxname = rec
+                .attributes()
+                .get("transcript_id")
+                .ok_or_else(|| TrxError::Gtf(format!("No transcript_id in {:?}", rec)))?
+                .to_string();
+            let gene = rec
+                .attributes()
+                .get("gene_id")
+                .ok_or_else(|| TrxError::Gtf(format!("No gene_id in {:?}", rec)))?
+                .to_string();
+
+            if !gtf_trxs.contains_key(&trxname) {
+                trxnames.push(trxname.clone());
+                gtf_trxs.insert(trxname.clone(), GtfTranscript::new(&gene, rec.seqname()));
+            }
+
+            gtf_trxs
+                .get_mut(&trxname)
+                .expect("GTF transcript missing from map")
+                .add_record(&trxname, &rec)?;
+        }
+
+        let mut trxome = Self::new();
+
+        for trxname in trxnames {
+            let gtf_trx = gtf_trxs
+                .remove(&trxname)
+                .expect("GTF transcript missing from map");
+            let transcript = Transcript::from_gtf(&trxname, gtf_trx, refids)?;
+            trxome.insert(transcript)?;
+        }
+
+        Ok(trxome)
+    }
+
+    /// Constructs a transcriptome from GFF3 records. Transcripts are
+    /// assembled from `exon`, `CDS`, and `stop_codon` features as for
+    /// `new_from_gtf()`, but grouped by their `Parent` attribute,
+    /// which gives the `ID` of the transcript. A feature shared by
+    /// several transcripts lists each of them as a `Parent`. Each
+    /// transcript is assigned to the gene given by its own `Parent`,
+    /// or to a gene of its own name when it has none.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when a record cannot be read, when
+    /// a feature lacks a `Parent`, or when the features of a
+    /// transcript are inconsistent.
+    pub fn new_from_gff3<B: io::Read>(
+        records: gff::Records<B>,
+        refids: &mut RefIDSet<R>,
+    ) -> Result<Transcriptome<R>, TrxError> {
+        let mut trxnames = Vec::new();
+        let mut gff_trxs: HashMap<String, GtfTranscript> = HashMap::new();
+        let mut trx_genes: HashMap<String, String> = HashMap::new();
+
+        for recres in records {
+            let rec = recres.map_err(TrxError::GtfRead)?;
+            let parents = gff3_parents(&rec);
+
+            match rec.feature_type() {
+                "exon" | "CDS" | "stop_codon" => (),
+                _ => {
+                    if let (Some(id), Some(gene)) = (rec.attributes().get("ID"), parents.first()) {
+                        trx_genes.insert(id.to_string(), gene.to_string());
+                    }
+                    continue;
+                }
+            };
+
+            if parents.is_empty() {
+                return Err(TrxError::Gtf(format!("No Parent in {:?}", rec)));
+            }
+
+            for trxname in parents {
+                if !gff_trxs.contains_key(&trxname) {
+                    trxnames.push(trxname.clone());
+                    gff_trxs.insert(trxname.clone(), GtfTranscript::new(&trxname, rec.seqname()));
+                }
+
+                gff_trxs
+                    .get_mut(&trxname)
+                    .expect("GFF3 transcript missing from map")
+                    .add_record(&trxname, &rec)?;
+            }
+        }
+
+        let mut trxome = Self::new();
+
+        for trxname in trxnames {
+            let mut gff_trx = gff_trxs
+                .remove(&trxname)
+                .expect("GFF3 transcript missing from map");
+            if let Some(gene) = trx_genes.get(&trxname) {
+                gff_trx.gene = gene.clone();
+            }
+            let transcript = Transcript::from_gtf(&trxname, gff_trx, refids)?;
+            trxome.insert(transcript)?;
+        }
+
+        Ok(trxome)
+    }
+
+    /// Reads a transcriptome from an annotation file. Files with a
+    /// `.gtf` extension are read as GTF, those with a `.gff` or `.gff3`
+    /// extension as GFF3, and all others as BED.
+    ///
+    /// # Errors
+    ///
+    /// `TrxError::Open` is returned when the file cannot be opened,
+    /// and other variants when `new_from_bed()`, `new_from_gtf()`, or
+    /// `new_from_gff3()` fails.
+    pub fn new_from_file<P: AsRef<Path>>(
+        path: P,
+        refids: &mut RefIDSet<R>,
+    ) -> Result<Transcriptome<R>, TrxError> {
+        let _timer = StageTimer::new("annotation load");
+        let path = path.as_ref();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gtf") => {
+                let reader = gff::Reader::from_file(path, gff::GffType::GTF2)
+                    .map_err(|err| TrxError::open(path, err))?;
+                Self::new_from_gtf(reader.records(), refids)
+            }
+            Some("gff") | Some("gff3") => {
+                let reader = gff::Reader::from_file(path, gff::GffType::GFF3)
+                    .map_err(|err| TrxError::open(path, err))?;
+                Self::new_from_gff3(reader.records(), refids)
+            }
+            _ => {
+                let reader =
+                    bed::Reader::from_file(path).map_err(|err| TrxError::open(path, err))?;
+                Self::new_from_bed(reader.records(), refids)
+            }
+        }
+    }
+
+    /// Reads a transcriptome from several annotation files, e.g., a
+    /// genome annotation along with a BED file of reporter constructs
+    /// or spike-ins, in order of precedence. A transcript whose name
+    /// appears in an earlier file is skipped with a warning.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when any file cannot be read, as
+    /// by `new_from_file()`.
+    pub fn new_from_files<P: AsRef<Path>>(
+        paths: &[P],
+        refids: &mut RefIDSet<R>,
+    ) -> Result<Transcriptome<R>, TrxError> {
+        let mut trxome = Self::new();
+
+        for path in paths.iter() {
+            let skipped = trxome.merge(Self::new_from_file(path, refids)?);
+            if skipped > 0 {
+                warn!(
+                    "Skipped {} transcripts in {} already annotated in earlier files",
+                    skipped,
+                    path.as_ref().display()
+                );
+            }
+        }
+
+        Ok(trxome)
+    }
+
+    /// Adds the transcripts of `other`, which has lower precedence, and
+    /// returns the number skipped because a transcript of the same
+    /// name is already present.
+    pub fn merge(&mut self, other: Transcriptome<R>) -> usize {
+        let mut skipped = 0;
+
+        for (trxname, transcript) in other.trxname_to_transcript.into_iter() {
+            if self.trxname_to_transcript.contains_key(&trxname) {
+                skipped += 1;
+            } else {
+                self.insert_shared(transcript)
+                    .expect("Duplicate transcript in merge");
+            }
+        }
+
+        skipped
+    }
 }
 
+impl<R> Transcriptome<R>
+where
+    R: Deref<Target = String> + Hash + Eq,
+{
+    /// Returns the transcripts annotated for a gene, or an empty
+    /// `Vec` for an unknown gene.
+    pub fn gene_transcripts(&self, gene: &R) -> Vec<&Transcript<R>> {
+        self.gene_to_trxnames.get(gene).map_or_else(Vec::new, |trxnames| {
+            trxnames
+                .iter()
+                .filter_map(|trxname| self.trxname_to_transcript.get(trxname).map(Arc::as_ref))
+                .collect()
+        })
+    }
+
+    /// Returns one representative coding transcript for each gene
+    /// having at least one coding transcript, namely the transcript
+    /// with the longest CDS (breaking ties by transcript name). The
+    /// transcripts are sorted by gene name.
+    pub fn longest_cds_transcripts(&self) -> Vec<&Transcript<R>> {
+        fn cds_len<R>(trx: &Transcript<R>) -> usize {
+            trx.cds_range().as_ref().map_or(0, |cds| cds.end - cds.start)
+        }
+
+        let mut reps: Vec<&Transcript<R>> = self
+            .gene_to_trxnames
+            .keys()
+            .filter_map(|gene| {
+                self.gene_transcripts(gene)
+                    .into_iter()
+                    .filter(|trx| trx.is_coding())
+                    .max_by(|trx1, trx2| {
+                        cds_len(trx1)
+                            .cmp(&cds_len(trx2))
+                            .then_with(|| trx2.trxname().cmp(trx1.trxname()))
+                    })
+            })
+            .collect();
+        reps.sort_by(|trx1, trx2| trx1.gene().cmp(trx2.gene()));
+        reps
+    }
+}
+
+impl<R> Transcriptome<R>
+where
+    R: Deref<Target = String> + Clone + Hash + Eq,
+{
+    /// Returns all pairs of distinct genes whose exons overlap on the
+    /// same strand. The overlap length for a pair of genes is the
+    /// largest exonic overlap between any transcript of one gene and
+    /// any transcript of the other. Pairs are reported once, with the
+    /// genes in lexical order, and are sorted by gene name.
+    pub fn overlapping_genes(&self) -> Vec<GeneOverlap<R>> {
+        let mut overlaps: HashMap<(R, R), GeneOverlap<R>> = HashMap::new();
+
+        for trx in self.trxname_to_transcript.values() {
+            for other in self.find_at_loc(trx.loc()) {
+                if other.loc().strand() != trx.loc().strand() || trx.gene() >= other.gene() {
+                    continue;
+                }
+
+                let overlap = exon_overlap(trx.loc(), other.loc());
+                if overlap == 0 {
+                    continue;
+                }
+
+                let key = (trx.gene_ref().clone(), other.gene_ref().clone());
+                let entry = overlaps.entry(key).or_insert_with(|| GeneOverlap {
+                    gene_a: trx.gene_ref().clone(),
+                    gene_b: other.gene_ref().clone(),
+                    strand: trx.loc().strand(),
+                    overlap: 0,
+                });
+                entry.overlap = max(entry.overlap, overlap);
+            }
+        }
+
+        let mut overlaps: Vec<GeneOverlap<R>> = overlaps.into_iter().map(|(_, ov)| ov).collect();
+        overlaps.sort_by(|ov1, ov2| {
+            (ov1.gene_a(), ov1.gene_b()).cmp(&(ov2.gene_a(), ov2.gene_b()))
+        });
+        overlaps
+    }
+}
+
+impl<R: Eq + Hash> Transcriptome<R> {
+    /// Returns summary statistics for the transcriptome, useful for
+    /// confirming that an annotation was loaded correctly.
+    pub fn summary(&self) -> TranscriptomeSummary {
+        let mut cds_lengths: Vec<usize> = self
+            .trxname_to_transcript
+            .values()
+            .filter_map(|trx| trx.cds_range().as_ref().map(|cds| cds.end - cds.start))
+            .collect();
+        cds_lengths.sort();
+
+        let mut isoform_counts = Vec::new();
+        for trxnames in self.gene_to_trxnames.values() {
+            while isoform_counts.len() <= trxnames.len() {
+                isoform_counts.push(0);
+            }
+            isoform_counts[trxnames.len()] += 1;
+        }
+
+        TranscriptomeSummary {
+            n_genes: self.gene_to_trxnames.len(),
+            n_transcripts: self.trxname_to_transcript.len(),
+            n_refids: self.ref_transcripts.len(),
+            exon_length: self
+                .trxname_to_transcript
+                .values()
+                .map(|trx| trx.loc().exon_total_length())
+                .sum(),
+            cds_not_codons: cds_lengths.iter().filter(|&&len| len % 3 != 0).count(),
+            cds_lengths: cds_lengths,
+            isoform_counts: isoform_counts,
+        }
+    }
+}
+
+/// Summary statistics describing a `Transcriptome`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptomeSummary {
+    n_genes: usize,
+    n_transcripts: usize,
+    n_refids: usize,
+    exon_length: usize,
+    cds_lengths: Vec<usize>,
+    cds_not_codons: usize,
+    isoform_counts: Vec<usize>,
+}
+
+impl TranscriptomeSummary {
+    /// Returns the number of genes.
+    pub fn genes(&self) -> usize {
+        self.n_genes
+    }
+
+    /// Returns the number of transcripts.
+    pub fn transcripts(&self) -> usize {
+        self.n_transcripts
+    }
+
+    /// Returns the number of transcripts with a CDS.
+    pub fn coding_transcripts(&self) -> usize {
+        self.cds_lengths.len()
+    }
+
+    /// Returns the number of reference sequences with transcripts.
+    pub fn refids(&self) -> usize {
+        self.n_refids
+    }
+
+    /// Returns the total exonic length summed over all transcripts.
+    pub fn exon_length(&self) -> usize {
+        self.exon_length
+    }
+
+    /// Returns the number of coding sequences whose length is not a
+    /// multiple of 3.
+    pub fn cds_not_codons(&self) -> usize {
+        self.cds_not_codons
+    }
+
+    /// Returns the CDS length at quantile `q` (between 0 and 1), or
+    /// `None` when there are no coding transcripts.
+    pub fn cds_length_quantile(&self, q: f64) -> Option<usize> {
+        if self.cds_lengths.is_empty() {
+            None
+        } else {
+            let idx = (q * (self.cds_lengths.len() - 1) as f64).round() as usize;
+            self.cds_lengths.get(idx).map(|len| *len)
+        }
+    }
+
+    /// Returns the number of genes having `n` isoforms.
+    pub fn genes_with_isoforms(&self, n: usize) -> usize {
+        self.isoform_counts.get(n).map_or(0, |ct| *ct)
+    }
+
+    /// Returns the largest number of isoforms for any gene.
+    pub fn max_isoforms(&self) -> usize {
+        self.isoform_counts.len().saturating_sub(1)
+    }
+}
+
+impl fmt::Display for TranscriptomeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Annotation: {} genes, {} transcripts ({} coding) on {} reference sequences\n",
+            self.genes(),
+            self.transcripts(),
+            self.coding_transcripts(),
+            self.refids()
+        )?;
+        write!(f, "  Total exonic length: {}\n", self.exon_length())?;
+
+        let quantile_str = |q| {
+            self.cds_length_quantile(q)
+                .map_or_else(|| "N/A".to_string(), |len| len.to_string())
+        };
+        write!(
+            f,
+            "  CDS length: min {}, 25% {}, median {}, 75% {}, max {}\n",
+            quantile_str(0.0),
+            quantile_str(0.25),
+            quantile_str(0.5),
+            quantile_str(0.75),
+            quantile_str(1.0)
+        )?;
+        if self.cds_not_codons() > 0 {
+            write!(
+                f,
+                "  CDS length not a multiple of 3: {}\n",
+                self.cds_not_codons()
+            )?;
+        }
+
+        write!(f, "  Isoforms per gene:")?;
+        for n in 1..(self.max_isoforms() + 1) {
+            let ct = self.genes_with_isoforms(n);
+            if ct > 0 {
+                write!(f, " {}: {}", n, ct)?;
+            }
+        }
+        write!(f, "\n")
+    }
+}
+
+/// A pair of genes with overlapping exons on the same strand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneOverlap<R> {
+    gene_a: R,
+    gene_b: R,
+    strand: ReqStrand,
+    overlap: usize,
+}
+
+impl<R> GeneOverlap<R> {
+    /// Returns the shared strand of the overlapping genes.
+    pub fn strand(&self) -> ReqStrand {
+        self.strand
+    }
+
+    /// Returns the length of the exonic overlap.
+    pub fn overlap(&self) -> usize {
+        self.overlap
+    }
+}
+
+impl<R: Deref<Target = String>> GeneOverlap<R> {
+    /// Returns the lexically first gene name.
+    pub fn gene_a(&self) -> &str {
+        &self.gene_a
+    }
+
+    /// Returns the lexically second gene name.
+    pub fn gene_b(&self) -> &str {
+        &self.gene_b
+    }
+}
+
+/// Returns the total length of the positions shared by the exons of
+/// two spliced locations, ignoring strand.
+pub fn exon_overlap<R: Clone + Eq>(a: &Spliced<R, ReqStrand>, b: &Spliced<R, ReqStrand>) -> usize {
+    if a.refid() != b.refid() {
+        return 0;
+    }
+
+    let mut overlap = 0;
+    for ca in a.exon_contigs() {
+        for cb in b.exon_contigs() {
+            let start = max(ca.start(), cb.start());
+            let end = min(
+                ca.start() + ca.length() as isize,
+                cb.start() + cb.length() as isize,
+            );
+            if end > start {
+                overlap += (end - start) as usize;
+            }
+        }
+    }
+    overlap
+}
+
+/// Converts a coordinate read from an annotation file into a position,
+/// or returns `None` when it is too large to represent, rather than
+/// wrapping around to a negative position.
+pub fn annot_pos(coord: u64) -> Option<isize> {
+    if coord <= isize::max_value() as u64 {
+        Some(coord as isize)
+    } else {
+        None
+    }
+}
+
+/// Errors reading transcript annotations and constructing
+/// transcripts.
 #[derive(Debug)]
 pub enum TrxError {
+    Open(String, io::Error),
     Bed(String),
     BedParse(String, ParseIntError),
-    BedRead(failure::Error),
+    BedRead(csv::Error),
     BedSplicing(String, SplicingError),
     Cds(String),
+    Gtf(String),
+    GtfParse(String, ParseIntError),
+    GtfRead(csv::Error),
+    GtfSplicing(String, SplicingError),
     TrxExists(String),
 }
 
 impl TrxError {
+    fn open(path: &Path, err: io::Error) -> TrxError {
+        TrxError::Open(path.to_string_lossy().into_owned(), err)
+    }
+
     fn bed(record: &bed::Record, message: &str) -> TrxError {
         TrxError::Bed(format!("{} converting BED record {:?}", message, record))
     }
@@ -627,6 +1555,7 @@ impl Error for TrxError {}
 impl fmt::Display for TrxError {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
+            TrxError::Open(path, err) => write!(f, "Opening annotation file {}: {}", path, err),
             TrxError::Bed(msg) => write!(f, "BED record to transcript: {}", msg),
             TrxError::BedParse(msg, err) => write!(
                 f,
@@ -640,6 +1569,18 @@ impl fmt::Display for TrxError {
                 msg, err
             ),
             TrxError::Cds(msg) => write!(f, "CDS on transcript: {}", msg),
+            TrxError::Gtf(msg) => write!(f, "GTF records to transcript: {}", msg),
+            TrxError::GtfParse(msg, err) => write!(
+                f,
+                "GTF records to transcript: {}: parsing error {}",
+                msg, err
+            ),
+            TrxError::GtfRead(err) => write!(f, "Reading GTF records: {}", err),
+            TrxError::GtfSplicing(msg, err) => write!(
+                f,
+                "GTF records to transcript: {}: splicing error {}",
+                msg, err
+            ),
             TrxError::TrxExists(trx) => write!(f, "Transcript already exists: {}", trx),
         }
     }
@@ -686,6 +1627,27 @@ mod tests {
         assert_eq!(trx.cds_range(), &Some(0..315));
     }
 
+    #[test]
+    fn large_positions() {
+        // Beyond the 2^31 positions addressable in BAM records, as on
+        // wheat chromosomes
+        if cfg!(target_pointer_width = "64") {
+            let recstr = "chr3B\t3000000000\t3000000400\tBigGene\t0\t+\t3000000010\t3000000400\t0\t2\t100,200,\t0,200,\n";
+            let trx = transcript_from_str(recstr);
+            assert_eq!(
+                trx.loc().to_string(),
+                "chr3B:3000000000-3000000100;3000000200-3000000400(+)"
+            );
+            assert_eq!(trx.cds_range(), &Some(10..300));
+            assert_eq!(annot_pos(1 << 40), Some((1u64 << 40) as isize));
+        }
+
+        assert!(no_transcript_from_str(
+            "chr01\t9300000000000000000\t9300000000000000315\tHuge\t0\t+\n"
+        ));
+        assert_eq!(annot_pos(u64::max_value()), None);
+    }
+
     #[test]
     fn gene_1exon_rev() {
         let recstr = "chr01	1806	2169	YAL068C	0	-	1806	2169	0	1	363,	0,\n";
@@ -695,6 +1657,29 @@ mod tests {
         assert_eq!(trx.cds_range(), &Some(0..363));
     }
 
+    #[test]
+    fn simple_intervals() {
+        let trx = transcript_from_str("chr01\t334\t649\tsnR18\t0\t-\n");
+        assert_eq!(trx.gene(), "snR18");
+        assert_eq!(trx.loc().to_string(), "chr01:334-649(-)");
+        assert_eq!(trx.cds_range(), &None);
+
+        let trx = transcript_from_str("chr01\t100\t250\n");
+        assert_eq!(trx.gene(), "chr01:100-250");
+        assert_eq!(trx.loc().to_string(), "chr01:100-250(+)");
+        assert_eq!(trx.cds_range(), &None);
+
+        let trx = transcript_from_str("chr01\t334\t649\tYAL069W\t0\t+\t334\t649\n");
+        assert_eq!(trx.cds_range(), &Some(0..315));
+
+        let trx = transcript_from_str("chr01\t334\t649\tsnR18\t0\t.\n");
+        assert_eq!(trx.loc().to_string(), "chr01:334-649(+)");
+        assert_eq!(trx.cds_range(), &None);
+
+        assert!(no_transcript_from_str("chr01\t334\t649\tsnR18\t0\t*\n"));
+        assert!(no_transcript_from_str("chr01\t334\t334\tsnR18\t0\t+\n"));
+    }
+
     #[test]
     fn gene_1exon_fwd_cds() {
         let recstr = "chr01	33364	34785	YAL061W	0	+	33447	34701	0	1	1421,	0,\n";
@@ -763,6 +1748,107 @@ mod tests {
         assert_eq!(trx.cds_range(), &Some(89..503));
     }
 
+    fn gtf_transcriptome_from_str(gtfstr: &str) -> Transcriptome<Rc<String>> {
+        let mut refids = RefIDSet::new();
+        Transcriptome::new_from_gtf(
+            gff::Reader::new(gtfstr.as_bytes(), gff::GffType::GTF2).records(),
+            &mut refids,
+        ).expect("Transcriptome from GTF string")
+    }
+
+    #[test]
+    fn gene_2exon_fwd_gtf() {
+        let gtfs = "\
+chr01	test	exon	87262	87387	.	+	.	gene_id \"YAL030W\"; transcript_id \"YAL030W_mRNA\";
+chr01	test	exon	87501	87822	.	+	.	gene_id \"YAL030W\"; transcript_id \"YAL030W_mRNA\";
+chr01	test	CDS	87286	87387	.	+	0	gene_id \"YAL030W\"; transcript_id \"YAL030W_mRNA\";
+chr01	test	CDS	87501	87749	.	+	2	gene_id \"YAL030W\"; transcript_id \"YAL030W_mRNA\";
+chr01	test	stop_codon	87750	87752	.	+	0	gene_id \"YAL030W\"; transcript_id \"YAL030W_mRNA\";
+";
+        let tome = gtf_transcriptome_from_str(gtfs);
+        let trx = tome
+            .find_at_loc(&"chr01:87300(+)".parse::<Pos<Rc<String>, ReqStrand>>().unwrap())
+            .next()
+            .expect("No transcript");
+        assert_eq!(trx.gene(), "YAL030W");
+        assert_eq!(trx.trxname(), "YAL030W_mRNA");
+        assert_eq!(trx.loc().to_string(), "chr01:87261-87387;87500-87822(+)");
+        assert_eq!(trx.cds_range(), &Some(24..378));
+        assert_eq!(trx.cds_phase(), 0);
+    }
+
+    #[test]
+    fn gene_2isoform_gff3() {
+        let gffs = "\
+chr01\ttest\tgene\t87262\t87822\t.\t+\t.\tID=YAL030W;Name=SNC1
+chr01\ttest\tmRNA\t87262\t87822\t.\t+\t.\tID=YAL030W_mRNA;Parent=YAL030W
+chr01\ttest\tmRNA\t87262\t87387\t.\t+\t.\tID=YAL030W_short;Parent=YAL030W
+chr01\ttest\texon\t87262\t87387\t.\t+\t.\tParent=YAL030W_mRNA,YAL030W_short
+chr01\ttest\texon\t87501\t87822\t.\t+\t.\tParent=YAL030W_mRNA
+chr01\ttest\tCDS\t87286\t87387\t.\t+\t0\tID=YAL030W_cds;Parent=YAL030W_mRNA
+chr01\ttest\tCDS\t87501\t87752\t.\t+\t2\tID=YAL030W_cds;Parent=YAL030W_mRNA
+";
+        let mut refids = RefIDSet::new();
+        let tome: Transcriptome<Rc<String>> = Transcriptome::new_from_gff3(
+            gff::Reader::new(gffs.as_bytes(), gff::GffType::GFF3).records(),
+            &mut refids,
+        ).expect("Transcriptome from GFF3 string");
+
+        assert_eq!(
+            transcripts_at_pos(&tome, "chr01:87300(+)"),
+            vec!["YAL030W_mRNA", "YAL030W_short"]
+        );
+        let trx = tome
+            .find_at_loc(&"chr01:87600(+)".parse::<Pos<Rc<String>, ReqStrand>>().unwrap())
+            .next()
+            .expect("No transcript");
+        assert_eq!(trx.gene(), "YAL030W");
+        assert_eq!(trx.trxname(), "YAL030W_mRNA");
+        assert_eq!(trx.loc().to_string(), "chr01:87261-87387;87500-87822(+)");
+        assert_eq!(trx.cds_range(), &Some(24..378));
+    }
+
+    #[test]
+    fn no_parent_gff3() {
+        let gffs = "chr01\ttest\texon\t87262\t87387\t.\t+\t.\tID=exon1\n";
+        let mut refids: RefIDSet<Rc<String>> = RefIDSet::new();
+        assert!(Transcriptome::new_from_gff3(
+            gff::Reader::new(gffs.as_bytes(), gff::GffType::GFF3).records(),
+            &mut refids,
+        ).is_err());
+    }
+
+    #[test]
+    fn gene_partial_cds_gtf() {
+        let gtfs = "\
+chr05	test	exon	1001	1300	.	+	.	gene_id \"FWD\"; transcript_id \"FWD.1\";
+chr05	test	CDS	1001	1200	.	+	1	gene_id \"FWD\"; transcript_id \"FWD.1\";
+chr05	test	exon	2001	2300	.	-	.	gene_id \"REV\"; transcript_id \"REV.1\";
+chr05	test	CDS	2051	2300	.	-	2	gene_id \"REV\"; transcript_id \"REV.1\";
+";
+        let tome = gtf_transcriptome_from_str(gtfs);
+
+        let fwd = tome
+            .find_at_loc(&"chr05:1100(+)".parse::<Pos<Rc<String>, ReqStrand>>().unwrap())
+            .next()
+            .expect("No forward transcript");
+        assert_eq!(fwd.cds_range(), &Some(0..200));
+        assert_eq!(fwd.cds_phase(), 1);
+        assert_eq!(TrxPos::new(fwd, 0).cds_frame(), Some(2));
+        assert_eq!(TrxPos::new(fwd, 1).cds_frame(), Some(0));
+        assert_eq!(TrxPos::new(fwd, 2).cds_frame(), Some(1));
+        assert_eq!(TrxPos::new(fwd, 4).cds_frame(), Some(0));
+
+        let rev = tome
+            .find_at_loc(&"chr05:2100(-)".parse::<Pos<Rc<String>, ReqStrand>>().unwrap())
+            .next()
+            .expect("No reverse transcript");
+        assert_eq!(rev.cds_range(), &Some(0..250));
+        assert_eq!(rev.cds_phase(), 2);
+        assert_eq!(TrxPos::new(rev, 0).cds_frame(), Some(1));
+        assert_eq!(TrxPos::new(rev, 2).cds_frame(), Some(0));
+    }
+
     fn transcriptome_from_str(bedstr: &str) -> Transcriptome<Rc<String>> {
         let mut refids = RefIDSet::new();
         Transcriptome::new_from_bed(bed::Reader::new(bedstr.as_bytes()).records(), &mut refids)
@@ -820,6 +1906,147 @@ chr03	500	1500	EEE	0	+	600	1200	0	2	250,450	0,550
         assert_eq!(transcripts_at_pos(&tome, "chr03:1450(+)"), vec!["EEE"]);
     }
 
+    #[test]
+    fn transcriptome_ref_transcripts() {
+        let beds = "\
+chr01	1000	2000	AAA	0	+	1200	1800	0	1	1000,	0,
+chr01	1900	2100	BBB	0	+	1950	2050	0	1	200,	0,
+chr02	1500	2500	CCC	0	+	1600	2400	0	1	1000,	0,
+";
+        let tome = transcriptome_from_str(&beds);
+
+        let mut refids: Vec<(String, usize)> = tome
+            .ref_transcripts_iter()
+            .map(|ref_trxs| (ref_trxs.refid().to_string(), ref_trxs.len()))
+            .collect();
+        refids.sort();
+        assert_eq!(
+            refids,
+            vec![("chr01".to_string(), 2), ("chr02".to_string(), 1)]
+        );
+
+        let chr01 = tome
+            .ref_transcripts(&Rc::new("chr01".to_string()))
+            .expect("No chr01 transcripts");
+        let pos: Pos<Rc<String>, ReqStrand> = "chr01:1950(+)".parse().unwrap();
+        let mut trxs: Vec<&str> = chr01.find_at_loc(&pos).map(|trx| trx.trxname()).collect();
+        trxs.sort();
+        assert_eq!(trxs, vec!["AAA", "BBB"]);
+
+        let pos: Pos<Rc<String>, ReqStrand> = "chr02:1950(+)".parse().unwrap();
+        assert_eq!(chr01.find_at_loc(&pos).count(), 0);
+        assert!(tome.ref_transcripts(&Rc::new("chr03".to_string())).is_none());
+
+        let ccc = tome
+            .transcripts()
+            .find(|trx| trx.trxname() == "CCC")
+            .expect("No CCC transcript");
+        let pos: Pos<Rc<String>, ReqStrand> = "chr02:1950(+)".parse().unwrap();
+        let found = tome.find_at_loc(&pos).next().expect("No transcript at chr02:1950");
+        assert!(::std::ptr::eq(ccc, found));
+    }
+
+    #[test]
+    fn transcriptome_overlapping_genes() {
+        let beds = "\
+chr01	1000	2000	AAA	0	+	1200	1800	0	1	1000,	0,
+chr01	1900	2100	BBB	0	+	1950	2050	0	1	200,	0,
+chr01	1500	2500	CCC	0	-	1600	2400	0	1	1000,	0,
+chr03	500	1500	EEE	0	+	600	1200	0	2	250,450	0,550
+chr03	800	1000	FFF	0	+	800	1000	0	1	200,	0,
+chr03	1000	1100	GGG	0	+	1000	1100	0	1	100,	0,
+";
+        let tome = transcriptome_from_str(&beds);
+        let overlaps: Vec<(String, String, usize)> = tome
+            .overlapping_genes()
+            .iter()
+            .map(|ov| (ov.gene_a().to_string(), ov.gene_b().to_string(), ov.overlap()))
+            .collect();
+        assert_eq!(
+            overlaps,
+            vec![
+                ("AAA".to_string(), "BBB".to_string(), 100),
+                ("EEE".to_string(), "GGG".to_string(), 50),
+            ]
+        );
+    }
+
+    #[test]
+    fn transcriptome_clean_genes() {
+        let beds = "\
+chr01	1000	2000	AAA	0	+	1200	1800	0	1	1000,	0,
+chr01	1900	2100	BBB	0	-	1950	2050	0	1	200,	0,
+chr02	1500	2500	CCC	0	+	1600	2400	0	1	1000,	0,
+chr03	500	1500	EEE	0	+	600	1200	0	2	250,450	0,550
+";
+        let tome = transcriptome_from_str(&beds);
+        let mut clean: Vec<String> = tome
+            .clean_genes()
+            .transcripts()
+            .map(|trx| trx.gene().to_string())
+            .collect();
+        clean.sort();
+        assert_eq!(clean, vec!["CCC", "EEE"]);
+
+        let gtfs = "\
+chr01	test	exon	1001	2000	.	+	.	gene_id \"AAA\"; transcript_id \"AAA.1\";
+chr01	test	exon	1001	1800	.	+	.	gene_id \"AAA\"; transcript_id \"AAA.2\";
+chr01	test	exon	5001	6000	.	+	.	gene_id \"BBB\"; transcript_id \"BBB.1\";
+";
+        let tome = gtf_transcriptome_from_str(gtfs);
+        let clean: Vec<String> = tome
+            .clean_genes()
+            .transcripts()
+            .map(|trx| trx.trxname().to_string())
+            .collect();
+        assert_eq!(clean, vec!["BBB.1"]);
+    }
+
+    #[test]
+    fn transcriptome_merge() {
+        let genome = "\
+chr01	1000	2000	AAA	0	+	1200	1800	0	1	1000,	0,
+chr01	3000	4000	BBB	0	+	3200	3800	0	1	1000,	0,
+";
+        let reporter = "\
+chr01	1000	1500	AAA	0	-	1100	1400	0	1	500,	0,
+reporter	0	900	nLuc	0	+	100	616	0	1	900,	0,
+";
+        let mut tome = transcriptome_from_str(&genome);
+        assert_eq!(tome.merge(transcriptome_from_str(&reporter)), 1);
+
+        let mut names: Vec<String> = tome
+            .transcripts()
+            .map(|trx| trx.trxname().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["AAA", "BBB", "nLuc"]);
+        let aaa = tome.transcripts().find(|trx| trx.trxname() == "AAA").unwrap();
+        assert_eq!(aaa.loc().to_string(), "chr01:1000-2000(+)");
+    }
+
+    #[test]
+    fn transcriptome_summary() {
+        let beds = "\
+chr01	1000	2000	AAA	0	+	1200	1800	0	1	1000,	0,
+chr01	1900	2100	BBB	0	+	1950	2050	0	1	200,	0,
+chr02	1500	2500	CCC	0	+	1600	2400	0	1	1000,	0,
+chr03	500	1500	EEE	0	+	600	1200	0	2	250,450	0,550
+chr03	500	1500	FFF	0	+	500	500	0	1	1000,	0,
+";
+        let summary = transcriptome_from_str(&beds).summary();
+        assert_eq!(summary.genes(), 5);
+        assert_eq!(summary.transcripts(), 5);
+        assert_eq!(summary.coding_transcripts(), 4);
+        assert_eq!(summary.refids(), 3);
+        assert_eq!(summary.exon_length(), 3900);
+        assert_eq!(summary.cds_length_quantile(0.0), Some(100));
+        assert_eq!(summary.cds_length_quantile(1.0), Some(800));
+        assert_eq!(summary.cds_not_codons(), 2);
+        assert_eq!(summary.genes_with_isoforms(1), 5);
+        assert_eq!(summary.max_isoforms(), 1);
+    }
+
     fn make_spliced(s: &str) -> Spliced<String, ReqStrand> {
         s.parse().expect("Parsing spliced")
     }
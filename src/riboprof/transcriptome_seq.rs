@@ -0,0 +1,248 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use failure;
+
+use bio_types::annot::refids::RefIDSet;
+
+use cli_utils::*;
+use genome::*;
+use timing::*;
+use transcript::*;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CLI {
+    pub output: String,
+    pub bed: String,
+    pub genome: String,
+    pub regions: String,
+    pub header: String,
+    pub line_width: String,
+}
+
+pub struct Config {
+    output: PathBuf,
+    trxome: Transcriptome<Rc<String>>,
+    genome: Genome,
+    regions: Vec<SeqRegion>,
+    header: String,
+    line_width: usize,
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        let mut refids = RefIDSet::new();
+
+        let regions = cli
+            .regions
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<Vec<SeqRegion>, failure::Error>>()?;
+
+        Ok(Config {
+            output: Path::new(&cli.output).to_path_buf(),
+            trxome: Transcriptome::new_from_file(&cli.bed, &mut refids)?,
+            genome: Genome::new_from_fasta(&cli.genome)?,
+            regions: regions,
+            header: cli.header.to_string(),
+            line_width: cli.line_width.parse()?,
+        })
+    }
+}
+
+/// Region of a transcript written as a separate FastA file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeqRegion {
+    Transcript,
+    Cds,
+    Utr5,
+    Utr3,
+}
+
+impl SeqRegion {
+    /// Returns the region in transcript coordinates, or `None` when
+    /// the transcript lacks the region, e.g., the CDS of a non-coding
+    /// transcript.
+    pub fn range<R>(&self, trx: &Transcript<R>) -> Option<Range<usize>> {
+        match self {
+            SeqRegion::Transcript => Some(0..trx.loc().exon_total_length()),
+            SeqRegion::Cds => trx.cds_range().clone(),
+            SeqRegion::Utr5 => trx.utr5_range(),
+            SeqRegion::Utr3 => trx.utr3_range(),
+        }
+    }
+}
+
+impl fmt::Display for SeqRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SeqRegion::Transcript => "transcript",
+            SeqRegion::Cds => "cds",
+            SeqRegion::Utr5 => "utr5",
+            SeqRegion::Utr3 => "utr3",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for SeqRegion {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "transcript" => Ok(SeqRegion::Transcript),
+            "cds" => Ok(SeqRegion::Cds),
+            "utr5" => Ok(SeqRegion::Utr5),
+            "utr3" => Ok(SeqRegion::Utr3),
+            _ => bail!(
+                "Unknown region \"{}\", expecting transcript, cds, utr5, or utr3",
+                s
+            ),
+        }
+    }
+}
+
+/// Formats a FastA header line from a template, replacing `{trx}`,
+/// `{gene}`, `{loc}`, `{region}`, and `{length}` with the transcript
+/// name, gene name, genomic location, region, and sequence length.
+///
+/// ```
+/// # use riboprof::transcriptome_seq::*;
+/// let template = "{trx} gene={gene} {region} len={length}";
+/// let header = format_header(template, "YAL001C-A", "TFC3", "chrI:1-9(-)", SeqRegion::Cds, 9);
+/// assert_eq!(header, "YAL001C-A gene=TFC3 cds len=9");
+/// ```
+pub fn format_header(
+    template: &str,
+    trxname: &str,
+    gene: &str,
+    loc: &str,
+    region: SeqRegion,
+    length: usize,
+) -> String {
+    template
+        .replace("{trx}", trxname)
+        .replace("{gene}", gene)
+        .replace("{loc}", loc)
+        .replace("{region}", &region.to_string())
+        .replace("{length}", &length.to_string())
+}
+
+/// Writes one FastA record, wrapping the sequence every `line_width`
+/// bases, or on a single line when `line_width` is 0.
+pub fn write_fasta<W: Write>(
+    out: &mut W,
+    header: &str,
+    seq: &[u8],
+    line_width: usize,
+) -> Result<(), io::Error> {
+    writeln!(out, ">{}", header)?;
+    if line_width == 0 {
+        out.write_all(seq)?;
+        out.write_all(b"\n")?;
+    } else {
+        for line in seq.chunks(line_width) {
+            out.write_all(line)?;
+            out.write_all(b"\n")?;
+        }
+    }
+    Ok(())
+}
+
+pub fn run_transcriptome_seq(config: Config) -> Result<(), failure::Error> {
+    info!("{}", config.trxome.summary());
+
+    let _timer = StageTimer::new("output");
+    let mut outputs = Vec::new();
+    for region in config.regions.iter() {
+        let path = output_filename(&config.output, &format!("_{}.fa", region));
+        outputs.push((*region, BufWriter::new(fs::File::create(&path)?)));
+    }
+
+    let mut trxs: Vec<&Transcript<Rc<String>>> = config.trxome.transcripts().collect();
+    trxs.sort_by(|a, b| a.trxname().cmp(b.trxname()));
+
+    let mut no_seq = 0;
+    for trx in trxs {
+        let trx_seq = match config.genome.spliced_seq(trx.loc()) {
+            Some(seq) => seq,
+            None => {
+                no_seq += 1;
+                continue;
+            }
+        };
+        let loc = trx.loc().to_string();
+
+        for (region, out) in outputs.iter_mut() {
+            let seq = match region.range(trx) {
+                Some(ref range) if !range.is_empty() => &trx_seq[range.clone()],
+                _ => continue,
+            };
+            let header = format_header(
+                &config.header,
+                trx.trxname(),
+                trx.gene(),
+                &loc,
+                *region,
+                seq.len(),
+            );
+            write_fasta(out, &header, seq, config.line_width)?;
+        }
+    }
+
+    if no_seq > 0 {
+        warn!(
+            "No genome sequence for {} transcripts, which were skipped",
+            no_seq
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bio::io::{bed, fasta};
+
+    fn transcriptome_from_str(bedstr: &str) -> Transcriptome<Rc<String>> {
+        let mut refids = RefIDSet::new();
+        Transcriptome::new_from_bed(bed::Reader::new(bedstr.as_bytes()).records(), &mut refids)
+            .expect("Transcriptome from string")
+    }
+
+    #[test]
+    fn region_seqs() {
+        let bedstr = "chr01\t2\t22\tCoding\t0\t-\t4\t19\t0\t2\t8,6,\t0,14,\n";
+        let trxome = transcriptome_from_str(bedstr);
+        let trx = trxome.transcripts().next().unwrap();
+
+        let fastastr = ">chr01\nAACCCGGGTTACGTACGTAAAAAAAA\n";
+        let genome =
+            Genome::new_from_records(fasta::Reader::new(fastastr.as_bytes()).records()).unwrap();
+        let trx_seq = genome.spliced_seq(trx.loc()).unwrap();
+        assert_eq!(trx_seq, b"TTTTACAACCCGGG".to_vec());
+
+        let region_seq = |region: SeqRegion| {
+            region
+                .range(trx)
+                .map(|range| trx_seq[range].to_vec())
+                .unwrap_or_default()
+        };
+        assert_eq!(region_seq(SeqRegion::Transcript), trx_seq);
+        assert_eq!(region_seq(SeqRegion::Utr5), b"TTT".to_vec());
+        assert_eq!(region_seq(SeqRegion::Cds), b"TACAACCCG".to_vec());
+        assert_eq!(region_seq(SeqRegion::Utr3), b"GG".to_vec());
+
+        let mut out = Vec::new();
+        write_fasta(&mut out, "Coding cds", b"TACCCGG", 3).unwrap();
+        assert_eq!(out, b">Coding cds\nTAC\nCCG\nG\n".to_vec());
+    }
+}
@@ -0,0 +1,733 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::{self, FromStr};
+
+use clap::{Arg, ArgMatches};
+use failure;
+
+use rust_htslib::bam;
+
+/// Default delimiter between the read name and the UMI appended by
+/// `fastx-split`.
+pub const UMI_DELIMITER: u8 = b'#';
+
+/// BAM and SAM tag holding the raw UMI sequence.
+pub const UMI_TAG: &[u8; 2] = b"RX";
+
+/// BAM and SAM tag holding the sample barcode sequence.
+pub const BARCODE_TAG: &[u8; 2] = b"BC";
+
+/// Where the UMI of a read is recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UmiSource {
+    /// `RX` tag when present, and otherwise the read name suffix
+    Auto,
+    /// Read name suffix following the UMI delimiter, by default `#`
+    Name,
+    /// `RX` tag, or, for `fastx-split` output, an `RX:Z:` comment
+    /// that aligners can copy into the BAM record
+    Tag,
+}
+
+impl fmt::Display for UmiSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            UmiSource::Auto => "auto",
+            UmiSource::Name => "name",
+            UmiSource::Tag => "tag",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for UmiSource {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(UmiSource::Auto),
+            "name" => Ok(UmiSource::Name),
+            "tag" => Ok(UmiSource::Tag),
+            _ => bail!("Unknown UMI source \"{}\", expecting auto, name, or tag", s),
+        }
+    }
+}
+
+/// Returns the UMI suffix of a read name, following the last `#`.
+pub fn name_umi(qname: &[u8]) -> Option<&[u8]> {
+    delimited_umi(qname, UMI_DELIMITER)
+}
+
+/// Returns the UMI suffix of a read name, following the last
+/// `delimiter`. The UMI itself never contains the delimiter, but the
+/// read name before it may, e.g., when the sample barcode precedes
+/// the UMI as in `read_ACGT_TTAGC`.
+pub fn delimited_umi(qname: &[u8], delimiter: u8) -> Option<&[u8]> {
+    qname
+        .iter()
+        .rposition(|&ch| ch == delimiter)
+        .map(|delim_pos| &qname[(delim_pos + 1)..])
+}
+
+/// Returns the UMI of a record under the default conventions, taken
+/// from its `RX` tag when present and otherwise from the suffix of
+/// the read name following a `#`, as written by `fastx-split`.
+pub fn read_umi(rec: &bam::Record) -> Option<&[u8]> {
+    UmiSource::Auto.record_umi(rec)
+}
+
+impl UmiSource {
+    /// Returns the raw UMI of a record, without any correction.
+    pub fn record_umi<'a>(&self, rec: &'a bam::Record) -> Option<&'a [u8]> {
+        self.record_umi_delimited(rec, UMI_DELIMITER)
+    }
+
+    /// Returns the raw UMI of a record, finding a read name UMI after
+    /// `delimiter`.
+    pub fn record_umi_delimited<'a>(
+        &self,
+        rec: &'a bam::Record,
+        delimiter: u8,
+    ) -> Option<&'a [u8]> {
+        let tag_umi = || match rec.aux(UMI_TAG) {
+            Some(bam::record::Aux::String(rx)) => Some(rx),
+            _ => None,
+        };
+        match self {
+            UmiSource::Auto => tag_umi().or_else(|| delimited_umi(rec.qname(), delimiter)),
+            UmiSource::Name => delimited_umi(rec.qname(), delimiter),
+            UmiSource::Tag => tag_umi(),
+        }
+    }
+}
+
+/// Number of records inspected by default when detecting where UMIs
+/// are recorded.
+pub const UMI_PROBE_RECORDS: usize = 10000;
+
+/// Tally of where UMIs are recorded in the first records of a BAM
+/// file, used to choose the UMI source automatically.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UmiProbe {
+    records: usize,
+    tagged: usize,
+    named: usize,
+}
+
+impl UmiProbe {
+    pub fn new() -> Self {
+        UmiProbe::default()
+    }
+
+    /// Tallies the UMIs in up to `limit` records read from `records`.
+    pub fn new_from_records<I, E>(records: I, limit: usize) -> Result<Self, E>
+    where
+        I: IntoIterator<Item = Result<bam::Record, E>>,
+    {
+        let mut probe = UmiProbe::new();
+        for recres in records.into_iter().take(limit) {
+            probe.tally(&recres?);
+        }
+        Ok(probe)
+    }
+
+    pub fn tally(&mut self, rec: &bam::Record) {
+        self.records += 1;
+        if UmiSource::Tag.record_umi(rec).is_some() {
+            self.tagged += 1;
+        }
+        if UmiSource::Name.record_umi(rec).is_some() {
+            self.named += 1;
+        }
+    }
+
+    pub fn records(&self) -> usize {
+        self.records
+    }
+    pub fn tagged(&self) -> usize {
+        self.tagged
+    }
+    pub fn named(&self) -> usize {
+        self.named
+    }
+
+    /// Returns the source holding UMIs for the most records, preferring
+    /// the `RX` tag in a tie, or `None` when no record has a UMI.
+    pub fn source(&self) -> Option<UmiSource> {
+        if self.tagged == 0 && self.named == 0 {
+            None
+        } else if self.tagged >= self.named {
+            Some(UmiSource::Tag)
+        } else {
+            Some(UmiSource::Name)
+        }
+    }
+}
+
+impl fmt::Display for UmiProbe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} records have an RX tag UMI and {} have a read name UMI",
+            self.tagged, self.records, self.named
+        )
+    }
+}
+
+/// Set of known UMI sequences, e.g., for UMIs drawn from a fixed pool
+/// rather than synthesized as random bases.
+#[derive(Debug, Clone, Default)]
+pub struct UmiWhitelist {
+    umis: HashSet<Vec<u8>>,
+}
+
+impl UmiWhitelist {
+    pub fn new<I: IntoIterator<Item = Vec<u8>>>(umis: I) -> Self {
+        UmiWhitelist {
+            umis: umis.into_iter().collect(),
+        }
+    }
+
+    /// Reads a whitelist with one UMI in the first column of each
+    /// line, skipping blank lines and `#` comments.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when the file cannot be read or
+    /// the UMIs have different lengths.
+    pub fn new_from_file<P: AsRef<Path>>(path: P) -> Result<Self, failure::Error> {
+        let text = fs::read_to_string(path.as_ref())?;
+        let umis: Vec<Vec<u8>> = text
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_whitespace().next())
+            .map(|umi| umi.to_ascii_uppercase().into_bytes())
+            .collect();
+
+        if let Some(first) = umis.first() {
+            if let Some(umi) = umis.iter().find(|umi| umi.len() != first.len()) {
+                bail!(
+                    "UMI whitelist {:?} has UMIs of different lengths, e.g., {}",
+                    path.as_ref(),
+                    String::from_utf8_lossy(umi)
+                );
+            }
+        }
+
+        Ok(Self::new(umis))
+    }
+
+    pub fn len(&self) -> usize {
+        self.umis.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.umis.is_empty()
+    }
+
+    pub fn contains(&self, umi: &[u8]) -> bool {
+        self.umis.contains(umi)
+    }
+
+    /// Returns the whitelisted UMI that `umi` matches, allowing up to
+    /// `max_mismatches` substitutions, or `None` when no UMI, or more
+    /// than one equally close UMI, matches.
+    pub fn correct(&self, umi: &[u8], max_mismatches: usize) -> Option<&[u8]> {
+        if let Some(exact) = self.umis.get(umi) {
+            return Some(exact.as_slice());
+        }
+
+        let mut best: Option<(usize, &[u8])> = None;
+        let mut tied = false;
+        for known in self.umis.iter().filter(|known| known.len() == umi.len()) {
+            let mismatches = known.iter().zip(umi.iter()).filter(|(a, b)| a != b).count();
+            if mismatches > max_mismatches {
+                continue;
+            }
+            match best {
+                Some((best_mm, _)) if mismatches > best_mm => (),
+                Some((best_mm, _)) if mismatches == best_mm => tied = true,
+                _ => {
+                    best = Some((mismatches, known.as_slice()));
+                    tied = false;
+                }
+            }
+        }
+
+        if tied {
+            None
+        } else {
+            best.map(|(_, known)| known)
+        }
+    }
+}
+
+/// Corrections for UMIs with sequencing errors, found by clustering
+/// the UMIs seen at one site or in one sample with the directional
+/// method of UMI-tools. A UMI one substitution away from a UMI with
+/// `n` reads joins its cluster when it has no more than `(n + 1) / 2`
+/// reads, and clusters grow through chains of such UMIs starting from
+/// the most abundant.
+#[derive(Debug, Clone, Default)]
+pub struct UmiClusters {
+    representatives: HashMap<Vec<u8>, Vec<u8>>,
+    clusters: usize,
+}
+
+impl UmiClusters {
+    /// Clusters the UMIs in `counts`, which gives the number of reads
+    /// with each UMI. Ties in abundance are broken by UMI sequence, so
+    /// that clusters are the same on every run.
+    pub fn new_directional(counts: &HashMap<Vec<u8>, usize>) -> Self {
+        let mut umis: Vec<(&Vec<u8>, usize)> =
+            counts.iter().map(|(umi, &count)| (umi, count)).collect();
+        umis.sort_by(|(umi0, count0), (umi1, count1)| {
+            count1.cmp(count0).then_with(|| umi0.cmp(umi1))
+        });
+
+        let mut assigned: HashSet<Vec<u8>> = HashSet::new();
+        let mut clusters = UmiClusters::default();
+        for (umi, _) in umis {
+            if !assigned.insert(umi.to_vec()) {
+                continue;
+            }
+            clusters.clusters += 1;
+
+            let mut queue = vec![umi.to_vec()];
+            while let Some(parent) = queue.pop() {
+                let parent_count = counts[&parent];
+                for neighbor in substitutions(&parent) {
+                    match counts.get(&neighbor) {
+                        Some(&count) if parent_count + 1 >= 2 * count => {
+                            if assigned.insert(neighbor.clone()) {
+                                clusters
+                                    .representatives
+                                    .insert(neighbor.clone(), umi.to_vec());
+                                queue.push(neighbor);
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        clusters
+    }
+
+    /// Returns the UMI at the center of the cluster holding `umi`,
+    /// which is `umi` itself when it was not merged into a cluster.
+    pub fn correct<'a>(&'a self, umi: &'a [u8]) -> &'a [u8] {
+        self.representatives.get(umi).map_or(umi, Vec::as_slice)
+    }
+
+    /// Returns the number of clusters.
+    pub fn clusters(&self) -> usize {
+        self.clusters
+    }
+
+    /// Returns the number of UMIs merged into the cluster of another
+    /// UMI.
+    pub fn merged(&self) -> usize {
+        self.representatives.len()
+    }
+}
+
+/// Returns every sequence differing from `umi` by one substitution,
+/// including substitutions of `N`.
+fn substitutions(umi: &[u8]) -> Vec<Vec<u8>> {
+    let mut variants = Vec::with_capacity(umi.len() * 4);
+    for pos in 0..umi.len() {
+        for &nt in b"ACGTN".iter().filter(|&&nt| nt != umi[pos]) {
+            let mut variant = umi.to_vec();
+            variant[pos] = nt;
+            variants.push(variant);
+        }
+    }
+    variants
+}
+
+/// Command-line arguments for a `UmiConfig`, shared by all tools that
+/// handle UMIs.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UmiCLI {
+    pub umi_source: Option<String>,
+    pub umi_whitelist: Option<String>,
+    pub umi_mismatches: Option<String>,
+    pub umi_delimiter: Option<String>,
+}
+
+/// Returns the command-line arguments for a `UmiConfig`.
+pub fn umi_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("umi-source")
+            .long("umi-source")
+            .value_name("SOURCE")
+            .help("Where UMIs are recorded: RX tag or read name suffix (auto), name, or tag")
+            .takes_value(true)
+            .possible_values(&["auto", "name", "tag"]),
+        Arg::with_name("umi-whitelist")
+            .long("umi-whitelist")
+            .value_name("UMIS.TXT")
+            .help("File of known UMIs, one per line, for correcting UMI errors")
+            .takes_value(true),
+        Arg::with_name("umi-mismatches")
+            .long("umi-mismatches")
+            .value_name("N")
+            .help("Correct UMIs with up to N mismatches to a unique whitelisted UMI (default 1)")
+            .takes_value(true),
+        Arg::with_name("umi-delimiter")
+            .long("umi-delimiter")
+            .value_name("CHAR")
+            .help("Character between the read name and the UMI, e.g., _ for umi_tools (default #)")
+            .takes_value(true),
+    ]
+}
+
+/// Collects the arguments from `umi_args` after parsing.
+pub fn umi_cli(matches: &ArgMatches) -> UmiCLI {
+    UmiCLI {
+        umi_source: matches.value_of("umi-source").map(str::to_string),
+        umi_whitelist: matches.value_of("umi-whitelist").map(str::to_string),
+        umi_mismatches: matches.value_of("umi-mismatches").map(str::to_string),
+        umi_delimiter: matches.value_of("umi-delimiter").map(str::to_string),
+    }
+}
+
+/// UMI conventions shared by `fastx-split`, which writes UMIs,
+/// `bam-suppress-duplicates`, and the counting tools, which read
+/// them. A new configuration finds UMIs in the `RX` tag or the read
+/// name after a `#` and accepts every UMI, and options are added by
+/// chaining the builder methods.
+///
+/// ```
+/// # use riboprof::umi::*;
+/// let whitelist = UmiWhitelist::new(vec![b"ACGT".to_vec(), b"TTTT".to_vec()]);
+/// let umis = UmiConfig::new().whitelist(whitelist).max_mismatches(1);
+/// assert_eq!(umis.correct(b"ACGA"), Some(b"ACGT".to_vec()));
+/// assert_eq!(umis.correct(b"AAAA"), None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct UmiConfig {
+    source: UmiSource,
+    whitelist: Option<UmiWhitelist>,
+    max_mismatches: usize,
+    delimiter: u8,
+    name_barcode: bool,
+}
+
+impl Default for UmiConfig {
+    fn default() -> Self {
+        UmiConfig {
+            source: UmiSource::Auto,
+            whitelist: None,
+            max_mismatches: 1,
+            delimiter: UMI_DELIMITER,
+            name_barcode: false,
+        }
+    }
+}
+
+/// Returns a string-valued SAM field, `TG:Z:value`, for a FastQ
+/// comment that aligners can copy into the BAM record.
+fn sam_comment(tag: &[u8; 2], value: &str) -> Result<String, failure::Error> {
+    Ok(format!("{}:Z:{}", str::from_utf8(tag)?, value))
+}
+
+/// Parses a UMI delimiter, which must be a single ASCII punctuation
+/// character so that it cannot be mistaken for part of the UMI.
+fn parse_delimiter(delimiter: &str) -> Result<u8, failure::Error> {
+    match delimiter.as_bytes() {
+        &[ch] if ch.is_ascii_punctuation() => Ok(ch),
+        _ => bail!(
+            "UMI delimiter \"{}\" must be one punctuation character, e.g., # or _",
+            delimiter
+        ),
+    }
+}
+
+impl UmiConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a configuration from command-line arguments, reading the
+    /// whitelist file if one is given.
+    pub fn new_from_cli(cli: &UmiCLI) -> Result<Self, failure::Error> {
+        let mut config = UmiConfig::new();
+        if let Some(ref source) = cli.umi_source {
+            config = config.source(source.parse()?);
+        }
+        if let Some(ref whitelist) = cli.umi_whitelist {
+            let whitelist = UmiWhitelist::new_from_file(whitelist)?;
+            info!("Read {} whitelisted UMIs", whitelist.len());
+            config = config.whitelist(whitelist);
+        }
+        if let Some(ref mismatches) = cli.umi_mismatches {
+            config = config.max_mismatches(mismatches.parse()?);
+        }
+        if let Some(ref delimiter) = cli.umi_delimiter {
+            config = config.delimiter(parse_delimiter(delimiter)?);
+        }
+        Ok(config)
+    }
+
+    pub fn source(self, source: UmiSource) -> Self {
+        UmiConfig {
+            source: source,
+            ..self
+        }
+    }
+
+    pub fn whitelist(self, whitelist: UmiWhitelist) -> Self {
+        UmiConfig {
+            whitelist: Some(whitelist),
+            ..self
+        }
+    }
+
+    pub fn max_mismatches(self, max_mismatches: usize) -> Self {
+        UmiConfig {
+            max_mismatches: max_mismatches,
+            ..self
+        }
+    }
+
+    pub fn delimiter(self, delimiter: u8) -> Self {
+        UmiConfig {
+            delimiter: delimiter,
+            ..self
+        }
+    }
+
+    /// Records the sample barcode along with the UMI in `fastx-split`
+    /// output, before the UMI in the read name or as a `BC:Z:` comment.
+    pub fn name_barcode(self, name_barcode: bool) -> Self {
+        UmiConfig {
+            name_barcode: name_barcode,
+            ..self
+        }
+    }
+
+    pub fn get_source(&self) -> UmiSource {
+        self.source
+    }
+
+    pub fn get_whitelist(&self) -> Option<&UmiWhitelist> {
+        self.whitelist.as_ref()
+    }
+
+    pub fn get_delimiter(&self) -> u8 {
+        self.delimiter
+    }
+
+    pub fn get_name_barcode(&self) -> bool {
+        self.name_barcode
+    }
+
+    /// Returns the UMI after correction against the whitelist, if
+    /// any, or `None` for a UMI that cannot be corrected. Every UMI is
+    /// accepted as-is without a whitelist.
+    pub fn correct(&self, umi: &[u8]) -> Option<Vec<u8>> {
+        match self.whitelist {
+            Some(ref whitelist) => whitelist
+                .correct(umi, self.max_mismatches)
+                .map(|known| known.to_vec()),
+            None => Some(umi.to_vec()),
+        }
+    }
+
+    /// Returns the corrected UMI of a record, or `None` when it has
+    /// no UMI or its UMI cannot be corrected. Records with no UMI are
+    /// never treated as duplicates.
+    pub fn record_umi(&self, rec: &bam::Record) -> Option<Vec<u8>> {
+        self.source
+            .record_umi_delimited(rec, self.delimiter)
+            .and_then(|umi| self.correct(umi))
+    }
+
+    /// Returns `true` when two records have the same corrected UMI.
+    pub fn same_umi(&self, r0: &bam::Record, r1: &bam::Record) -> bool {
+        match (self.record_umi(r0), self.record_umi(r1)) {
+            (Some(umi0), Some(umi1)) => umi0 == umi1,
+            _ => false,
+        }
+    }
+
+    /// Returns the read name and description for a FastQ record
+    /// carrying `umi`: the UMI follows the delimiter in the name, or,
+    /// with the `Tag` source, is an `RX:Z:` comment in the
+    /// description. When set by `name_barcode()`, the sample
+    /// `barcode` precedes the UMI in the name, as for `umi_tools`, or
+    /// is a `BC:Z:` comment.
+    ///
+    /// # Errors
+    ///
+    /// An error variant is returned when the UMI or barcode is not
+    /// valid UTF-8.
+    pub fn fastq_id(
+        &self,
+        id: &str,
+        desc: Option<&str>,
+        barcode: &[u8],
+        umi: &[u8],
+    ) -> Result<(String, Option<String>), failure::Error> {
+        let umi = str::from_utf8(umi)?;
+        let barcode = if self.name_barcode {
+            Some(str::from_utf8(barcode)?)
+        } else {
+            None
+        };
+        match self.source {
+            UmiSource::Tag => {
+                let mut comments = vec![sam_comment(UMI_TAG, umi)?];
+                if let Some(barcode) = barcode {
+                    comments.push(sam_comment(BARCODE_TAG, barcode)?);
+                }
+                let comments = comments.join(" ");
+                let desc = match desc {
+                    Some(desc) => format!("{} {}", desc, comments),
+                    None => comments,
+                };
+                Ok((id.to_string(), Some(desc)))
+            }
+            UmiSource::Auto | UmiSource::Name => {
+                let delimiter = self.delimiter as char;
+                let name = match barcode {
+                    Some(barcode) => format!("{}{}{}{}{}", id, delimiter, barcode, delimiter, umi),
+                    None => format!("{}{}{}", id, delimiter, umi),
+                };
+                Ok((name, desc.map(str::to_string)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_htslib::bam::record::{Aux, Cigar, CigarString};
+
+    #[test]
+    fn umi_conventions() {
+        let record = |qname: &[u8]| {
+            let mut rec = bam::Record::new();
+            rec.set(
+                qname,
+                &CigarString(vec![Cigar::Match(4)]),
+                b"ACGT",
+                &[30; 4],
+            );
+            rec
+        };
+
+        let named = record(b"read1#ACGT");
+        let mut tagged = record(b"read2#TTTT");
+        tagged.push_aux(UMI_TAG, &Aux::String(b"ACGA")).unwrap();
+        let untagged = record(b"read3");
+
+        assert_eq!(read_umi(&named), Some(&b"ACGT"[..]));
+        assert_eq!(read_umi(&tagged), Some(&b"ACGA"[..]));
+        assert_eq!(read_umi(&untagged), None);
+        assert_eq!(UmiSource::Name.record_umi(&tagged), Some(&b"TTTT"[..]));
+        assert_eq!(UmiSource::Tag.record_umi(&named), None);
+
+        let mut probe = UmiProbe::new();
+        assert_eq!(probe.source(), None);
+        probe.tally(&untagged);
+        assert_eq!(probe.source(), None);
+        probe.tally(&named);
+        assert_eq!(probe.source(), Some(UmiSource::Name));
+        probe.tally(&tagged);
+        assert_eq!((probe.records(), probe.tagged(), probe.named()), (3, 1, 2));
+        assert_eq!(probe.source(), Some(UmiSource::Name));
+        let records = vec![Ok(tagged.clone()), Ok(untagged.clone()), Err(())];
+        assert_eq!(
+            UmiProbe::new_from_records(records.clone(), 2).map(|probe| probe.source()),
+            Ok(Some(UmiSource::Tag))
+        );
+        assert!(UmiProbe::new_from_records(records, 3).is_err());
+
+        let umis = UmiConfig::new();
+        assert!(!umis.same_umi(&named, &tagged));
+        assert!(!umis.same_umi(&untagged, &untagged));
+
+        let whitelist = UmiWhitelist::new(vec![b"ACGT".to_vec(), b"ACTT".to_vec()]);
+        let corrected = UmiConfig::new().whitelist(whitelist.clone());
+        assert!(corrected.same_umi(&named, &tagged));
+        assert_eq!(corrected.record_umi(&untagged), None);
+        assert_eq!(whitelist.correct(b"ACAT", 1), None);
+        assert_eq!(whitelist.correct(b"ACTT", 0), Some(&b"ACTT"[..]));
+        assert_eq!(whitelist.correct(b"AGGA", 1), None);
+        assert_eq!(whitelist.correct(b"AGGA", 2), Some(&b"ACGT"[..]));
+
+        assert_eq!(
+            umis.fastq_id("read1", None, b"TTGA", b"ACGT").unwrap(),
+            ("read1#ACGT".to_string(), None)
+        );
+        assert_eq!(
+            umis.clone()
+                .source(UmiSource::Tag)
+                .fastq_id("read1", Some("desc"), b"TTGA", b"ACGT")
+                .unwrap(),
+            ("read1".to_string(), Some("desc RX:Z:ACGT".to_string()))
+        );
+        assert_eq!(
+            umis.clone()
+                .source(UmiSource::Tag)
+                .name_barcode(true)
+                .fastq_id("read1", None, b"TTGA", b"ACGT")
+                .unwrap(),
+            ("read1".to_string(), Some("RX:Z:ACGT BC:Z:TTGA".to_string()))
+        );
+
+        let umi_tools = UmiConfig::new()
+            .delimiter(parse_delimiter("_").unwrap())
+            .name_barcode(true);
+        assert_eq!(
+            umi_tools
+                .fastq_id("read_1", None, b"TTGA", b"ACGT")
+                .unwrap(),
+            ("read_1_TTGA_ACGT".to_string(), None)
+        );
+        assert_eq!(
+            umi_tools.record_umi(&record(b"read_1_TTGA_ACGT")),
+            Some(b"ACGT".to_vec())
+        );
+        assert_eq!(umi_tools.record_umi(&named), None);
+        assert_eq!(name_umi(b"read#0#ACGT"), Some(&b"ACGT"[..]));
+        assert!(parse_delimiter("A").is_err());
+        assert!(parse_delimiter("__").is_err());
+    }
+
+    #[test]
+    fn directional_clusters() {
+        let counts: HashMap<Vec<u8>, usize> = vec![
+            (b"AAAA".to_vec(), 100),
+            (b"AAAT".to_vec(), 10),
+            (b"AATT".to_vec(), 4),
+            (b"CCCC".to_vec(), 50),
+            (b"CCCA".to_vec(), 30),
+            (b"CCAA".to_vec(), 15),
+            (b"GGGG".to_vec(), 3),
+            (b"GGGN".to_vec(), 2),
+        ]
+        .into_iter()
+        .collect();
+
+        let clusters = UmiClusters::new_directional(&counts);
+        // AATT joins through AAAT, and CCAA through CCCA, which has
+        // too many reads to join CCCC
+        assert_eq!(clusters.correct(b"AAAT"), b"AAAA");
+        assert_eq!(clusters.correct(b"AATT"), b"AAAA");
+        assert_eq!(clusters.correct(b"CCCA"), b"CCCA");
+        assert_eq!(clusters.correct(b"CCAA"), b"CCCA");
+        assert_eq!(clusters.correct(b"GGGN"), b"GGGG");
+        assert_eq!(clusters.correct(b"TTTT"), b"TTTT");
+        assert_eq!((clusters.clusters(), clusters.merged()), (4, 4));
+    }
+}
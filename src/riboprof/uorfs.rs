@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use failure;
+
+use bio_types::annot::refids::RefIDSet;
+use rust_htslib::bam;
+
+use bam_utils::*;
+use cli_utils::*;
+use codon_assign::*;
+use corrupt::*;
+use genome::*;
+use orfs::*;
+use output::*;
+use timing::*;
+use transcript::*;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CLI {
+    #[serde(skip_serializing)]
+    pub input: String,
+    pub output: String,
+    pub bed: String,
+    pub genome: String,
+    pub asites: String,
+    pub start_codons: String,
+    pub min_codons: String,
+    pub count_multi: bool,
+    pub io_threads: String,
+    #[serde(flatten)]
+    pub read_filter: ReadFilterCLI,
+    #[serde(flatten)]
+    pub output_config: OutputCLI,
+    #[serde(skip)]
+    pub corrupt: CorruptInputs,
+}
+
+pub struct Config {
+    input: String,
+    output: PathBuf,
+    trxome: Transcriptome<Rc<String>>,
+    genome: Genome,
+    offsets: SiteOffsets,
+    start_codons: Vec<Vec<u8>>,
+    min_codons: usize,
+    count_multi: bool,
+    io_threads: usize,
+    read_filter: ReadFilter,
+    output_config: OutputConfig,
+    corrupt: CorruptInputs,
+}
+
+impl Config {
+    pub fn new(cli: &CLI) -> Result<Self, failure::Error> {
+        let mut refids = RefIDSet::new();
+
+        Ok(Config {
+            input: cli.input.to_string(),
+            output: Path::new(&cli.output).to_path_buf(),
+            trxome: Transcriptome::new_from_file(&cli.bed, &mut refids)?,
+            genome: Genome::new_from_fasta(&cli.genome)?,
+            offsets: SiteOffsets::new_from_file(&cli.asites)?,
+            start_codons: parse_start_codons(&cli.start_codons)?,
+            min_codons: cli.min_codons.parse()?,
+            count_multi: cli.count_multi,
+            io_threads: cli.io_threads.parse()?,
+            read_filter: ReadFilter::new_from_cli(&cli.read_filter)?
+                .exclude_flags(NON_PRIMARY_FLAGS),
+            output_config: OutputConfig::new_from_cli(&cli.output_config)?,
+            corrupt: cli.corrupt.clone(),
+        })
+    }
+
+    fn output_filename(&self, suffix: &str) -> PathBuf {
+        output_filename(&self.output, suffix)
+    }
+}
+
+/// Footprint occupancy on a region of a transcript.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Occupancy {
+    asites: usize,
+    codons: usize,
+}
+
+impl Occupancy {
+    /// Tallies the A sites within a region of a transcript, from
+    /// per-position counts.
+    pub fn new(counts: &[usize], range: &Range<usize>) -> Self {
+        Occupancy {
+            asites: counts
+                .get(range.clone())
+                .map_or(0, |region| region.iter().sum()),
+            codons: range.len() / 3,
+        }
+    }
+
+    pub fn asites(&self) -> usize {
+        self.asites
+    }
+
+    /// Returns the A-site density, in footprints per codon.
+    pub fn density(&self) -> f64 {
+        self.asites as f64 / self.codons as f64
+    }
+
+    /// Returns the ratio of the A-site density in this region to the
+    /// density in another, typically the main ORF.
+    pub fn relative_density(&self, main: &Occupancy) -> f64 {
+        self.density() / main.density()
+    }
+}
+
+/// Summary of footprint occupancy across the 5' UTR and uORFs of one
+/// transcript.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UtrUsage {
+    utr5: Occupancy,
+    uorf_asites: usize,
+    cds: Occupancy,
+}
+
+impl UtrUsage {
+    /// Summarizes footprint occupancy on the 5' UTR and CDS of a
+    /// transcript, along with the A sites falling within any of its
+    /// uORFs, from per-position counts.
+    pub fn new(
+        counts: &[usize],
+        utr5: &Range<usize>,
+        cds: &Range<usize>,
+        uorfs: &[OrfCandidate],
+    ) -> Self {
+        let uorf_asites = counts
+            .iter()
+            .enumerate()
+            .filter(|&(pos, _)| {
+                uorfs
+                    .iter()
+                    .any(|uorf| uorf.range().start <= pos && pos < uorf.range().end)
+            })
+            .map(|(_, ct)| ct)
+            .sum();
+
+        UtrUsage {
+            utr5: Occupancy::new(counts, utr5),
+            uorf_asites: uorf_asites,
+            cds: Occupancy::new(counts, cds),
+        }
+    }
+
+    /// Returns the table header for `row()`.
+    pub fn header() -> &'static str {
+        "utr5_length\tutr5_asites\tuorf_asites\tcds_asites\tutr5_cds_ratio\tuorf_cds_ratio"
+    }
+
+    /// Returns the tab-delimited row of values for the transcript.
+    pub fn row(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{:.4}\t{:.4}",
+            self.utr5.codons * 3,
+            self.utr5.asites,
+            self.uorf_asites,
+            self.cds.asites,
+            self.utr5.asites as f64 / self.cds.asites as f64,
+            self.uorf_asites as f64 / self.cds.asites as f64
+        )
+    }
+}
+
+pub fn run_uorfs(config: Config) -> Result<(), failure::Error> {
+    // Open (empty) output files early to detect errors before processing data.
+    config
+        .output_config
+        .create(config.output_filename("_uorfs.txt"))?;
+    config
+        .output_config
+        .create(config.output_filename("_uorf_genes.txt"))?;
+
+    let uorf_classes = [OrfClass::Uorf, OrfClass::OverlapUorf];
+
+    let mut trx_uorfs: Vec<(&Transcript<Rc<String>>, Vec<OrfCandidate>)> = Vec::new();
+    let mut no_seq = 0;
+    for trx in config.trxome.longest_cds_transcripts() {
+        match config.genome.spliced_seq(trx.loc()) {
+            Some(seq) => {
+                let uorfs = transcript_orfs(
+                    trx,
+                    &seq,
+                    &config.start_codons,
+                    config.min_codons,
+                    &uorf_classes,
+                );
+                trx_uorfs.push((trx, uorfs));
+            }
+            None => no_seq += 1,
+        }
+    }
+
+    if no_seq > 0 {
+        warn!("No genome sequence for {} transcripts", no_seq);
+    }
+    info!(
+        "Found {} uORFs on {} genes",
+        trx_uorfs
+            .iter()
+            .map(|(_, uorfs)| uorfs.len())
+            .sum::<usize>(),
+        trx_uorfs
+            .iter()
+            .filter(|(_, uorfs)| !uorfs.is_empty())
+            .count()
+    );
+
+    let mut trx_counts: HashMap<Rc<String>, Vec<usize>> = trx_uorfs
+        .iter()
+        .map(|(trx, _)| {
+            (
+                trx.trxname_ref().clone(),
+                vec![0; trx.loc().exon_total_length()],
+            )
+        })
+        .collect();
+
+    let mut input = open_bam(&config.input, &config.corrupt)?;
+    set_reader_threads(&mut input, config.io_threads)?;
+
+    let filter_stats = tally_asites(
+        &mut input,
+        &config.trxome,
+        &config.offsets,
+        &config.read_filter,
+        &config.corrupt,
+        config.count_multi,
+        &mut trx_counts,
+    )?;
+
+    let _timer = StageTimer::new("output");
+    let mut uorf_out = config
+        .output_config
+        .create(config.output_filename("_uorfs.txt"))?;
+    let mut gene_out = config
+        .output_config
+        .create(config.output_filename("_uorf_genes.txt"))?;
+    writeln!(
+        uorf_out,
+        "gene\ttranscript\tuorf\tclass\tstart_codon\tstart\tend\tcodons\t\
+         asites\tin_frame\tdensity\tcds_density\trelative_density"
+    )?;
+    writeln!(gene_out, "gene\ttranscript\tuorfs\t{}", UtrUsage::header())?;
+
+    for (trx, uorfs) in trx_uorfs.iter() {
+        let counts = &trx_counts[trx.trxname_ref()];
+        let (utr5, cds) = match (trx.utr5_range(), trx.cds_range()) {
+            (Some(utr5), Some(cds)) => (utr5, cds.clone()),
+            _ => continue,
+        };
+        let main = Occupancy::new(counts, &cds);
+
+        for uorf in uorfs.iter() {
+            let occupancy = Occupancy::new(counts, uorf.range());
+            let signal = FrameSignal::new(counts, uorf.range());
+            writeln!(
+                uorf_out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{:.4}",
+                trx.gene(),
+                trx.trxname(),
+                uorf.name(trx),
+                uorf.class(),
+                String::from_utf8_lossy(uorf.start_codon()),
+                uorf.range().start,
+                uorf.range().end,
+                uorf.codons(),
+                occupancy.asites(),
+                signal.in_frame(),
+                occupancy.density(),
+                main.density(),
+                occupancy.relative_density(&main)
+            )?;
+        }
+
+        let usage = UtrUsage::new(counts, &utr5, &cds, uorfs);
+        writeln!(
+            gene_out,
+            "{}\t{}\t{}\t{}",
+            trx.gene(),
+            trx.trxname(),
+            uorfs.len(),
+            usage.row()
+        )?;
+    }
+
+    uorf_out.finish()?;
+    gene_out.finish()?;
+
+    config.output_config.write(
+        config.output_filename("_filter_stats.txt"),
+        filter_stats.table(),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use bio::io::bed;
+
+    fn transcriptome_from_str(bedstr: &str) -> Transcriptome<Rc<String>> {
+        let mut refids = RefIDSet::new();
+        Transcriptome::new_from_bed(bed::Reader::new(bedstr.as_bytes()).records(), &mut refids)
+            .expect("Transcriptome from string")
+    }
+
+    #[test]
+    fn utr_usage() {
+        // 5' UTR of 15 nt with an ATG uORF at 3..12, CDS at 15..30.
+        let bedstr = "chr01\t100\t130\tGene\t0\t+\t115\t130\t0\t1\t30,\t0,\n";
+        let trxome = transcriptome_from_str(bedstr);
+        let trx = trxome.transcripts().next().unwrap();
+        let seq = b"CCCATGAAATAGCCCATGCCCGGGAAATAA";
+
+        let uorfs = transcript_orfs(trx, seq, &[b"ATG".to_vec()], 2, &[OrfClass::Uorf]);
+        assert_eq!(uorfs.len(), 1);
+        assert_eq!(uorfs[0].range(), &(3..12));
+
+        let mut counts = vec![0; 30];
+        counts[1] = 1;
+        counts[3] = 4;
+        counts[6] = 2;
+        counts[18] = 3;
+        counts[24] = 7;
+
+        let main = Occupancy::new(&counts, &(15..30));
+        assert_eq!(main.asites(), 10);
+        assert_eq!(main.density(), 2.0);
+        let uorf = Occupancy::new(&counts, uorfs[0].range());
+        assert_eq!(uorf.asites(), 6);
+        assert_eq!(uorf.relative_density(&main), 1.0);
+
+        let usage = UtrUsage::new(&counts, &trx.utr5_range().unwrap(), &(15..30), &uorfs);
+        assert_eq!(usage.row(), "15\t7\t6\t10\t0.7000\t0.6000");
+    }
+}
@@ -0,0 +1,298 @@
+extern crate clap;
+extern crate failure;
+
+extern crate riboprof;
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use riboprof::bam_suppress_duplicates;
+use riboprof::bench;
+use riboprof::check;
+use riboprof::fp_framing;
+use riboprof::gene_overlaps;
+use riboprof::logging::*;
+use riboprof::pipeline;
+
+fn main() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("riboprof", start.elapsed(), &result);
+    match result {
+        Err(e) => {
+            io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
+            process::exit(1);
+        }
+        _ => (),
+    };
+}
+
+fn wrapper() -> Result<(), failure::Error> {
+    let matches = App::new("riboprof")
+        .version("0.1.0")
+        .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .about("Ribosome profiling annotation and analysis utilities")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .args(&logging_args())
+        .subcommand(
+            SubCommand::with_name("overlaps")
+                .about("Reports pairs of genes with overlapping exons on the same strand")
+                .arg(
+                    Arg::with_name("bed")
+                        .short("b")
+                        .long("bed")
+                        .value_name("BED")
+                        .help("BED-, GTF-, or GFF3-format annotation filename")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("OVERLAPS.TXT")
+                        .help("Output filename (\"-\" for standard output)")
+                        .takes_value(true)
+                        .default_value("-"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("merge-framing")
+                .about("Merges framing statistics from several fp-framing runs")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("OUTBASE")
+                        .help("Base filename for output files")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("frame-tables")
+                        .long("frame-tables")
+                        .help("Also write start and end tables by position, length, and frame"),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .value_name("FRAMING_STATS.JSON")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("merge-dedup")
+                .about("Merges duplicate statistics from several bam-suppress-duplicates runs")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("STATS.TXT")
+                        .help("Output file with merged duplicate statistics")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .value_name("STATS.JSON")
+                        .help("Output file with merged duplicate statistics as JSON")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .value_name("STATS.JSON")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Checks read counts between stages of a pipeline run")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("CHECK.TXT")
+                        .help("Output filename (\"-\" for standard output)")
+                        .takes_value(true)
+                        .default_value("-"),
+                )
+                .arg(
+                    Arg::with_name("fates")
+                        .long("fates")
+                        .value_name("FATES.TXT")
+                        .help("Sample fates from fastx-split")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("dedup")
+                        .long("dedup")
+                        .value_name("STATS.JSON")
+                        .help("Duplicate statistics from bam-suppress-duplicates")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("framing")
+                        .long("framing")
+                        .value_name("FRAMING_STATS.JSON")
+                        .help("Framing statistics from fp-framing")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("codon-stats")
+                        .long("codon-stats")
+                        .value_name("CODON_STATS.TXT")
+                        .help("Codon assignment statistics from bam-codon-count")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Measures the throughput of each tool on synthetic data")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("BENCH.TXT")
+                        .help("Output filename (\"-\" for standard output)")
+                        .takes_value(true)
+                        .default_value("-"),
+                )
+                .arg(
+                    Arg::with_name("reads")
+                        .long("reads")
+                        .value_name("N")
+                        .help("Number of synthetic reads and alignments")
+                        .takes_value(true)
+                        .default_value("1000000"),
+                )
+                .arg(
+                    Arg::with_name("genes")
+                        .long("genes")
+                        .value_name("N")
+                        .help("Number of synthetic genes")
+                        .takes_value(true)
+                        .default_value("1000"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .value_name("SEED")
+                        .help("Random seed for the synthetic data")
+                        .takes_value(true)
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::with_name("threads")
+                        .long("threads")
+                        .value_name("N")
+                        .help("Number of threads for each tool")
+                        .takes_value(true)
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::with_name("keep")
+                        .long("keep")
+                        .value_name("DIR")
+                        .help("Write synthetic data and outputs in DIR and keep them")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Runs the split, align, dedup, framing, and codon count pipeline")
+                .arg(
+                    Arg::with_name("dry-run")
+                        .short("n")
+                        .long("dry-run")
+                        .help("List the steps that would be run without running them"),
+                )
+                .arg(
+                    Arg::with_name("restart")
+                        .long("restart")
+                        .help("Run every step again, ignoring steps completed previously"),
+                )
+                .arg(
+                    Arg::with_name("project")
+                        .value_name("PROJECT.TOML")
+                        .help("Project description")
+                        .required(true),
+                ),
+        )
+        .get_matches();
+    let corrupt = init_logging(&matches)?;
+
+    match matches.subcommand() {
+        ("overlaps", Some(overlaps_matches)) => {
+            let cli = overlaps_cli(overlaps_matches)?;
+            let config = gene_overlaps::Config::new(&cli)?;
+            gene_overlaps::run_gene_overlaps(config)
+        }
+        ("merge-framing", Some(merge_matches)) => fp_framing::run_merge_framing(
+            &merge_matches.values_of_lossy("input").unwrap(),
+            merge_matches.value_of("output").unwrap(),
+            merge_matches.is_present("frame-tables"),
+        ),
+        ("merge-dedup", Some(merge_matches)) => bam_suppress_duplicates::run_merge_stats(
+            &merge_matches.values_of_lossy("input").unwrap(),
+            merge_matches.value_of("output").unwrap(),
+            merge_matches.value_of("json"),
+        ),
+        ("check", Some(check_matches)) => {
+            let values = |name: &str| check_matches.values_of_lossy(name).unwrap_or_default();
+            let cli = check::CLI {
+                output: check_matches.value_of("output").unwrap().to_string(),
+                fates: values("fates"),
+                dedup: values("dedup"),
+                framing: values("framing"),
+                codon_stats: values("codon-stats"),
+            };
+            let config = check::Config::new(&cli)?;
+            check::run_check(config)
+        }
+        ("bench", Some(bench_matches)) => {
+            let value = |name: &str| bench_matches.value_of(name).unwrap().to_string();
+            let cli = bench::CLI {
+                reads: value("reads"),
+                genes: value("genes"),
+                seed: value("seed"),
+                threads: value("threads"),
+                output: value("output"),
+                keep: bench_matches.value_of("keep").map(str::to_string),
+            };
+            let config = bench::Config::new(&cli)?;
+            bench::run_bench(config)
+        }
+        ("run", Some(run_matches)) => {
+            let cli = pipeline::CLI {
+                project: run_matches.value_of("project").unwrap().to_string(),
+                dry_run: run_matches.is_present("dry-run"),
+                restart: run_matches.is_present("restart"),
+                corrupt: corrupt,
+            };
+            let config = pipeline::Config::new(&cli)?;
+            pipeline::run_pipeline(config)
+        }
+        (cmd, _) => Err(failure::err_msg(format!("Unknown command \"{}\"", cmd))),
+    }
+}
+
+fn overlaps_cli(matches: &ArgMatches) -> Result<gene_overlaps::CLI, failure::Error> {
+    Ok(gene_overlaps::CLI {
+        bed: matches.value_of("bed").unwrap().to_string(),
+        output: matches.value_of("output").unwrap().to_string(),
+    })
+}
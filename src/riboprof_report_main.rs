@@ -0,0 +1,111 @@
+extern crate clap;
+extern crate failure;
+
+extern crate riboprof;
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg};
+
+use riboprof::config_file::*;
+use riboprof::logging::*;
+use riboprof::report::*;
+
+fn main() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("riboprof-report", start.elapsed(), &result);
+    match result {
+        Err(e) => {
+            io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
+            process::exit(1);
+        }
+        _ => (),
+    };
+}
+
+fn wrapper() -> Result<(), failure::Error> {
+    let cli = get_cli()?;
+    let config = Config::new(&cli)?;
+    run_report(config)
+}
+
+fn get_cli() -> Result<CLI, failure::Error> {
+    let matches = App::new("riboprof-report")
+        .version("0.1.0")
+        .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .about("Aggregates QC statistics from several tools into one HTML report")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("REPORT.HTML")
+                .help("Output HTML report filename")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("title")
+                .short("t")
+                .long("title")
+                .value_name("TITLE")
+                .help("Report title")
+                .takes_value(true)
+                .default_value("Ribosome profiling QC"),
+        )
+        .arg(
+            Arg::with_name("split")
+                .long("split")
+                .value_name("SPLIT_DIR")
+                .help("fastx-split output directory, containing fates.txt")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("dedup")
+                .long("dedup")
+                .value_name("STATS.JSON")
+                .help("bam-suppress-duplicates JSON statistics, named by sample")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("framing")
+                .long("framing")
+                .value_name("SAMPLE_FRAMING_STATS.JSON")
+                .help("fp-framing JSON statistics, named by sample")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    init_logging(&matches)?;
+
+    let values = |name: &str| -> Vec<String> {
+        matches
+            .values_of(name)
+            .map_or_else(Vec::new, |vals| vals.map(|s| s.to_string()).collect())
+    };
+
+    let cli = CLI {
+        output: matches.value_of("output").unwrap().to_string(),
+        title: matches.value_of("title").unwrap().to_string(),
+        split_dirs: values("split"),
+        dedup: values("dedup"),
+        framing: values("framing"),
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
+}
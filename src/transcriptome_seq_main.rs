@@ -0,0 +1,112 @@
+extern crate clap;
+extern crate failure;
+
+extern crate riboprof;
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg};
+
+use riboprof::config_file::*;
+use riboprof::logging::*;
+use riboprof::transcriptome_seq::*;
+
+fn main() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("transcriptome-seq", start.elapsed(), &result);
+    match result {
+        Err(e) => {
+            io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
+            process::exit(1);
+        }
+        _ => (),
+    };
+}
+
+fn wrapper() -> Result<(), failure::Error> {
+    let cli = get_cli()?;
+    let config = Config::new(&cli)?;
+    run_transcriptome_seq(config)
+}
+
+fn get_cli() -> Result<CLI, failure::Error> {
+    let matches = App::new("transcriptome-seq")
+        .version("0.1.0")
+        .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .about("Writes transcript, CDS, and UTR sequences in FastA format")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTBASE")
+                .help("Base filename for output files")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("bed")
+                .short("b")
+                .long("bed")
+                .value_name("BED")
+                .help("BED-, GTF-, or GFF3-format annotation filename")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("genome")
+                .short("g")
+                .long("genome")
+                .value_name("GENOME.FA")
+                .help("FastA-format genome sequence filename")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("regions")
+                .long("regions")
+                .value_name("REGION,...")
+                .help("Regions to write, each to its own file, from transcript, cds, utr5, and utr3")
+                .takes_value(true)
+                .default_value("transcript,cds,utr5,utr3"),
+        )
+        .arg(
+            Arg::with_name("header")
+                .long("header")
+                .value_name("TEMPLATE")
+                .help("FastA header template, with {trx}, {gene}, {loc}, {region}, and {length} replaced")
+                .takes_value(true)
+                .default_value("{trx} gene={gene}"),
+        )
+        .arg(
+            Arg::with_name("line-width")
+                .long("line-width")
+                .value_name("N")
+                .help("Wrap sequence lines at N bases, or 0 for no wrapping")
+                .takes_value(true)
+                .default_value("60"),
+        )
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    init_logging(&matches)?;
+
+    let cli = CLI {
+        output: matches.value_of("output").unwrap().to_string(),
+        bed: matches.value_of("bed").unwrap().to_string(),
+        genome: matches.value_of("genome").unwrap().to_string(),
+        regions: matches.value_of("regions").unwrap().to_string(),
+        header: matches.value_of("header").unwrap().to_string(),
+        line_width: matches.value_of("line-width").unwrap().to_string(),
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
+}
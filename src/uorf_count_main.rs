@@ -0,0 +1,143 @@
+extern crate clap;
+extern crate failure;
+
+extern crate riboprof;
+
+use std::io;
+use std::io::Write;
+use std::process;
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg};
+
+use riboprof::cli_utils::*;
+use riboprof::config_file::*;
+use riboprof::logging::*;
+use riboprof::output::*;
+use riboprof::uorfs::*;
+
+fn main() {
+    let start = Instant::now();
+    let result = wrapper();
+    log_summary("uorf-count", start.elapsed(), &result);
+    match result {
+        Err(e) => {
+            io::stderr().write(format!("{}\n", e).as_bytes()).unwrap();
+            process::exit(1);
+        }
+        _ => (),
+    };
+}
+
+fn wrapper() -> Result<(), failure::Error> {
+    let cli = get_cli()?;
+    let config = Config::new(&cli)?;
+    run_uorfs(config)
+}
+
+fn get_cli() -> Result<CLI, failure::Error> {
+    let matches = App::new("uorf-count")
+        .version("0.1.0")
+        .author("Nick Ingolia <ingolia@berkeley.edu>")
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .about("Quantifies footprint occupancy on upstream ORFs relative to the main ORF")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTBASE")
+                .help("Base filename for output files")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("bed")
+                .short("b")
+                .long("bed")
+                .value_name("BED")
+                .help("BED-, GTF-, or GFF3-format annotation filename")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("genome")
+                .short("g")
+                .long("genome")
+                .value_name("GENOME.FA")
+                .help("FastA genome sequence")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("asites")
+                .short("a")
+                .long("asites")
+                .value_name("ASITES.TXT")
+                .help("Tab-delimited file of A site offsets")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("start-codons")
+                .short("s")
+                .long("start-codons")
+                .value_name("CODONS")
+                .help("Comma-separated start codons (e.g., ATG,CTG,GTG)")
+                .takes_value(true)
+                .default_value("ATG,CTG,GTG,TTG,ACG"),
+        )
+        .arg(
+            Arg::with_name("min-codons")
+                .long("min-codons")
+                .value_name("N")
+                .help("Minimum ORF length in codons, including the stop codon")
+                .takes_value(true)
+                .default_value("2"),
+        )
+        .arg(
+            Arg::with_name("count-multi")
+                .short("m")
+                .long("count-multi")
+                .help("Tally the first alignment of multi-mapping reads"),
+        )
+        .arg(
+            Arg::with_name("io-threads")
+                .long("io-threads")
+                .value_name("N")
+                .help("Additional threads for BAM compression and decompression")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .value_name("INPUT.BAM")
+                .required(true),
+        )
+        .args(&read_filter_args())
+        .args(&output_args())
+        .args(&config_file_args())
+        .args(&logging_args())
+        .get_matches_from(args_with_config()?);
+    let corrupt = init_logging(&matches)?;
+
+    let cli = CLI {
+        read_filter: read_filter_cli(&matches),
+        output_config: output_cli(&matches),
+        output: matches.value_of("output").unwrap().to_string(),
+        bed: matches.value_of("bed").unwrap().to_string(),
+        genome: matches.value_of("genome").unwrap().to_string(),
+        asites: matches.value_of("asites").unwrap().to_string(),
+        start_codons: matches.value_of("start-codons").unwrap().to_string(),
+        min_codons: matches.value_of("min-codons").unwrap().to_string(),
+        count_multi: matches.is_present("count-multi"),
+        io_threads: matches.value_of("io-threads").unwrap().to_string(),
+        input: matches.value_of("input").unwrap().to_string(),
+        corrupt: corrupt,
+    };
+
+    if print_config(&matches, &cli)? {
+        process::exit(0);
+    }
+
+    Ok(cli)
+}